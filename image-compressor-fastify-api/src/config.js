@@ -1,3 +1,5 @@
+const { checkPathTemplate } = require('./utils');
+
 const env = process.env.NODE_ENV || 'development';
 
 const config = {
@@ -19,11 +21,43 @@ const config = {
     timeout: parseInt(process.env.RUST_SERVICE_TIMEOUT || '30000', 10) // 30 seconds
   },
 
+  storage: {
+    // supabase, gcs, azure or filesystem
+    backend: process.env.STORAGE_BACKEND || 'supabase',
+    uploadPath: process.env.STORAGE_UPLOAD_PATH || process.env.SUPABASE_UPLOAD_PATH || 'compressed',
+    // Where uploads are stored, e.g. `{tenant}/{date}/{hash}.{ext}`
+    pathTemplate: process.env.STORAGE_PATH_TEMPLATE || '{uploadPath}/{filename}',
+    // Lifetime of the pre-signed URLs in JSON responses, 0 to leave them out
+    signedUrlTtl: parseInt(process.env.STORAGE_SIGNED_URL_TTL || '3600', 10) // 1 hour
+  },
+
   supabase: {
     url: process.env.SUPABASE_URL,
     key: process.env.SUPABASE_KEY,
-    bucket: process.env.SUPABASE_BUCKET || 'images',
-    uploadPath: process.env.SUPABASE_UPLOAD_PATH || 'compressed'
+    bucket: process.env.SUPABASE_BUCKET || 'images'
+  },
+
+  gcs: {
+    bucket: process.env.GCS_BUCKET,
+    projectId: process.env.GCS_PROJECT_ID,
+    keyFilename: process.env.GCS_KEY_FILE,
+    // Base of the returned URLs, e.g. a CDN in front of the bucket
+    publicUrl: process.env.GCS_PUBLIC_URL
+  },
+
+  azure: {
+    connectionString: process.env.AZURE_STORAGE_CONNECTION_STRING,
+    container: process.env.AZURE_STORAGE_CONTAINER || 'images',
+    // Base of the returned URLs, e.g. a CDN in front of the container
+    publicUrl: process.env.AZURE_PUBLIC_URL
+  },
+
+  filesystem: {
+    root: process.env.FS_STORAGE_ROOT,
+    // Base of the returned URLs, when the directory is served over HTTP
+    publicUrl: process.env.FS_PUBLIC_URL,
+    // Writes leaving less free space than this are refused
+    minFreeBytes: parseInt(process.env.FS_MIN_FREE_BYTES || '104857600', 10) // 100MB
   },
 
   upload: {
@@ -34,6 +68,31 @@ const config = {
     defaultQuality: parseInt(process.env.DEFAULT_QUALITY || '80', 10)
   },
 
+  // Contact sheets of uploaded or stored image sets
+  contactSheet: {
+    // Most images one sheet request may include
+    maxImages: parseInt(process.env.CONTACT_SHEET_MAX_IMAGES || '500', 10)
+  },
+
+  // Periodic re-compression of images already in storage
+  sweep: {
+    enabled: process.env.SWEEP_ENABLED === 'true',
+    intervalMs: parseInt(process.env.SWEEP_INTERVAL_MS || '86400000', 10), // 24 hours
+    prefix: process.env.SWEEP_PREFIX || process.env.STORAGE_UPLOAD_PATH || process.env.SUPABASE_UPLOAD_PATH || 'compressed',
+    // Originals are copied here before being replaced
+    backupPrefix: process.env.SWEEP_BACKUP_PREFIX || 'sweep-backup',
+    // Savings reports are uploaded here as JSON
+    reportPrefix: process.env.SWEEP_REPORT_PREFIX || 'sweep-reports',
+    // Images at least this large are re-compressed
+    minSize: parseInt(process.env.SWEEP_MIN_SIZE || '524288', 10), // 512KB
+    // JPEGs encoded above this quality are re-compressed, whatever their size
+    maxQuality: parseInt(process.env.SWEEP_MAX_QUALITY || '90', 10),
+    // Quality the images are re-compressed at
+    quality: parseInt(process.env.SWEEP_QUALITY || process.env.DEFAULT_QUALITY || '80', 10),
+    // Results saving less than this are discarded, keeping the original
+    minSavingsPercent: parseFloat(process.env.SWEEP_MIN_SAVINGS_PERCENT || '10')
+  },
+
   security: {
     rateLimit: {
       max: parseInt(process.env.RATE_LIMIT_MAX || '100', 10), // requests
@@ -48,16 +107,37 @@ const config = {
   }
 };
 
-// Validate required configuration
-const requiredConfig = {
-  'SUPABASE_URL': config.supabase.url,
-  'SUPABASE_KEY': config.supabase.key
+// Validate required configuration of the storage backend in use
+const requiredConfigByBackend = {
+  supabase: {
+    'SUPABASE_URL': config.supabase.url,
+    'SUPABASE_KEY': config.supabase.key
+  },
+  gcs: {
+    'GCS_BUCKET': config.gcs.bucket
+  },
+  azure: {
+    'AZURE_STORAGE_CONNECTION_STRING': config.azure.connectionString
+  },
+  filesystem: {
+    'FS_STORAGE_ROOT': config.filesystem.root
+  }
 };
 
+const requiredConfig = requiredConfigByBackend[config.storage.backend];
+if (!requiredConfig) {
+  throw new Error(`Unknown STORAGE_BACKEND: ${config.storage.backend} (expected supabase, gcs, azure or filesystem)`);
+}
+
 Object.entries(requiredConfig).forEach(([key, value]) => {
   if (!value) {
     throw new Error(`Missing required environment variable: ${key}`);
   }
 });
 
-module.exports = config; 
\ No newline at end of file
+const templateError = checkPathTemplate(config.storage.pathTemplate);
+if (templateError) {
+  throw new Error(`Invalid STORAGE_PATH_TEMPLATE: ${templateError}`);
+}
+
+module.exports = config;
@@ -1,4 +1,12 @@
-const { validateFileUpload, generateUniqueFilename, createErrorResponse } = require('../utils');
+const {
+  validateFileUpload,
+  sanitizeFilename,
+  withOutputExtension,
+  contentDisposition,
+  parseFocalPoint,
+  createZip,
+  createErrorResponse
+} = require('../utils');
 
 /**
  * Register compression routes
@@ -6,7 +14,7 @@ const { validateFileUpload, generateUniqueFilename, createErrorResponse } = requ
  * @param {Object} opts - Route options
  */
 async function routes(fastify, opts) {
-  const { config, compressionService, supabaseService } = fastify;
+  const { config, compressionService, storageService } = fastify;
 
   // Schema for the compression endpoint
   const schema = {
@@ -18,7 +26,14 @@ async function routes(fastify, opts) {
           key: { type: 'string' },
           size: { type: 'number' },
           originalSize: { type: 'number' },
-          compressionRatio: { type: 'number' }
+          compressionRatio: { type: 'number' },
+          focalPoint: {
+            type: 'object',
+            properties: { x: { type: 'number' }, y: { type: 'number' } }
+          },
+          placeholder: { type: 'string' },
+          signedUrl: { type: 'string' },
+          signedUrlExpiresAt: { type: 'string' }
         }
       }
     }
@@ -45,30 +60,97 @@ async function routes(fastify, opts) {
     // Convert the file stream to a buffer before processing
     const imageBuffer = await data.toBuffer();
     const originalSize = imageBuffer.length;
-    const filename = generateUniqueFilename(data.filename);
+    const originalFilename = sanitizeFilename(data.filename);
+    // Resize and crop settings sent as multipart fields
+    const field = (name) => data.fields?.[name]?.value;
+    const gravity = field('gravity');
 
     try {
       // Compress image using the buffer
-      const compressedBuffer = await compressionService.compressImage(
+      const { buffer: compressedBuffer, contentType, placeholder } = await compressionService.compressImage(
         imageBuffer,
         {
           quality: request.body?.quality,
-          format: request.body?.format
+          format: request.body?.format,
+          filename: originalFilename,
+          width: field('width'),
+          height: field('height'),
+          fit: field('fit'),
+          aspect: field('aspect'),
+          gravity,
+          background: field('background'),
+          filter: field('filter'),
+          colorSpace: field('colorSpace'),
+          paletteColors: field('paletteColors'),
+          dither: field('dither'),
+          borderWidth: field('borderWidth'),
+          borderColor: field('borderColor'),
+          cornerRadius: field('cornerRadius'),
+          placeholder: field('placeholder'),
+          watermark: field('watermark'),
+          forceEncode: field('forceEncode'),
+          maxBytes: field('maxBytes'),
+          minQuality: field('minQuality'),
+          minWidth: field('minWidth'),
+          minHeight: field('minHeight'),
+          effort: field('effort')
         }
       );
 
-      // Upload to Supabase
-      const uploadResult = await supabaseService.uploadFile(
+      // The output keeps the original name, with the extension of its format
+      const outputFilename = withOutputExtension(originalFilename, contentType);
+
+      // Upload to the configured storage backend
+      const uploadResult = await storageService.uploadFile(
         compressedBuffer,
-        filename,
-        data.mimetype
+        outputFilename,
+        contentType,
+        { tenant: request.headers['x-tenant-id'] }
       );
 
-      reply.header('Content-Type', 'image/jpeg');
+      // Keep the subject of the asset for later crops
+      const focalPoint = parseFocalPoint(gravity);
+      if (focalPoint) {
+        await storageService.putFocalPoint(uploadResult.key, focalPoint);
+      }
+
+      // Clients asking for JSON get where the result is stored, and fetch
+      // it from there
+      if (/\bapplication\/json\b/.test(request.headers.accept || '')) {
+        const response = {
+          url: uploadResult.url,
+          key: uploadResult.key,
+          size: compressedBuffer.length,
+          originalSize,
+          compressionRatio: Number((originalSize / compressedBuffer.length).toFixed(2)),
+          ...(focalPoint && { focalPoint }),
+          ...(placeholder && { placeholder })
+        };
+        const ttl = config.storage.signedUrlTtl;
+        const signedUrl = ttl > 0 ? await storageService.signedUrl(uploadResult.key, ttl) : null;
+        if (signedUrl) {
+          response.signedUrl = signedUrl;
+          response.signedUrlExpiresAt = new Date(Date.now() + ttl * 1000).toISOString();
+        }
+        return response;
+      }
+
+      reply.header('Content-Type', contentType);
+      reply.header('Content-Disposition', contentDisposition(outputFilename));
+      reply.header('X-Original-Filename', encodeURIComponent(originalFilename));
+      if (placeholder) reply.header('X-Placeholder-Data', placeholder);
       return reply.send(compressedBuffer);
     } catch (error) {
       request.log.error(error);
       
+      if (error.code === 'STORAGE_FULL') {
+        return reply.code(507).send(createErrorResponse(
+          'Storage full',
+          error.message,
+          'STORAGE_FULL'
+        ));
+      }
+
       if (error.message.includes('Compression failed')) {
         return reply.code(500).send(createErrorResponse(
           'Compression failed',
@@ -85,6 +167,88 @@ async function routes(fastify, opts) {
     }
   });
 
+  // Contact sheet of uploaded files (multipart, any number of file parts)
+  // and/or every image under a storage prefix (the `prefix` field, or a
+  // JSON body). `page`, `columns`, `rows` and `thumbSize` pick the page and
+  // layout; the page comes back as a JPEG
+  fastify.post('/contact-sheet', async (request, reply) => {
+    const files = [];
+    let fields = {};
+    if (request.isMultipart()) {
+      for await (const part of request.parts()) {
+        if (part.type === 'file') {
+          const buffer = await part.toBuffer();
+          files.push({ name: sanitizeFilename(part.filename), buffer });
+        } else {
+          fields[part.fieldname] = part.value;
+        }
+      }
+    } else {
+      fields = request.body || {};
+    }
+
+    try {
+      if (fields.prefix) {
+        const { allowedMimeTypes } = config.upload;
+        const listed = (await storageService.listFilesRecursive(fields.prefix))
+          .filter((file) => allowedMimeTypes.includes(file.metadata?.mimetype));
+        if (files.length + listed.length > config.contactSheet.maxImages) {
+          return reply.code(400).send(createErrorResponse(
+            'Too many images',
+            `A contact sheet holds at most ${config.contactSheet.maxImages} images`,
+            'VALIDATION_ERROR'
+          ));
+        }
+        for (const file of listed) {
+          files.push({ name: file.path, buffer: await storageService.downloadFile(file.path) });
+        }
+      }
+
+      if (files.length === 0) {
+        return reply.code(400).send(createErrorResponse(
+          'No images',
+          'Upload files or give a storage prefix that holds images',
+          'VALIDATION_ERROR'
+        ));
+      }
+      if (files.length > config.contactSheet.maxImages) {
+        return reply.code(400).send(createErrorResponse(
+          'Too many images',
+          `A contact sheet holds at most ${config.contactSheet.maxImages} images`,
+          'VALIDATION_ERROR'
+        ));
+      }
+
+      const sheet = await compressionService.contactSheet(createZip(files), {
+        page: fields.page,
+        columns: fields.columns,
+        rows: fields.rows,
+        thumbSize: fields.thumbSize
+      });
+      reply.header('Content-Type', sheet.contentType);
+      reply.header('X-Page-Count', sheet.pageCount);
+      reply.header('X-Image-Count', sheet.imageCount);
+      return reply.send(sheet.buffer);
+    } catch (error) {
+      request.log.error(error);
+
+      // Bad layouts and pages past the end are the caller's to fix
+      if (error.status === 400 || error.status === 404) {
+        return reply.code(error.status).send(createErrorResponse(
+          'Invalid contact sheet request',
+          error.message,
+          'VALIDATION_ERROR'
+        ));
+      }
+
+      return reply.code(500).send(createErrorResponse(
+        'Contact sheet failed',
+        error.message,
+        'CONTACT_SHEET_ERROR'
+      ));
+    }
+  });
+
   // Health check endpoint
   fastify.get('/health', async (request, reply) => {
     try {
@@ -18,13 +18,8 @@ const fastify = require('fastify')({
 const config = require('./config');
 const { apiKeyAuth } = require('./middleware');
 const CompressionService = require('./services/compression');
-const SupabaseService = require('./services/supabase');
-
-// Validate required configuration
-if (!config.supabase.url || !config.supabase.key) {
-  fastify.log.error('Missing required Supabase configuration');
-  process.exit(1);
-}
+const { createStorageBackend } = require('./services/storage');
+const SweepService = require('./services/sweep');
 
 // Register plugins
 async function registerPlugins() {
@@ -44,12 +39,20 @@ async function registerPlugins() {
 // Initialize services
 function initializeServices() {
   const compressionService = new CompressionService(config);
-  const supabaseService = new SupabaseService(config);
+  const storageService = createStorageBackend(config);
 
   // Make services available in Fastify instance
   fastify.decorate('config', config);
   fastify.decorate('compressionService', compressionService);
-  fastify.decorate('supabaseService', supabaseService);
+  fastify.decorate('storageService', storageService);
+
+  if (config.sweep.enabled) {
+    fastify.decorate('sweepService', new SweepService(config, {
+      compressionService,
+      storageService,
+      log: fastify.log
+    }));
+  }
 }
 
 // Register routes
@@ -67,6 +70,10 @@ async function registerRoutes() {
 async function closeGracefully(signal) {
   fastify.log.info(`Received signal to terminate: ${signal}`);
 
+  if (fastify.sweepService) {
+    await fastify.sweepService.stop();
+  }
+
   await fastify.close();
   process.exit(0);
 }
@@ -91,6 +98,11 @@ async function start() {
     });
 
     fastify.log.info(`Server listening on ${address}`);
+
+    if (fastify.sweepService) {
+      fastify.sweepService.start();
+      fastify.log.info(`Storage sweep of '${config.sweep.prefix}' scheduled every ${config.sweep.intervalMs}ms`);
+    }
   } catch (err) {
     fastify.log.error(err);
     process.exit(1);
@@ -8,8 +8,20 @@ class CompressionService {
   /**
    * Compress an image using the Rust service
    * @param {Buffer|ReadableStream} imageData - Image data to compress
-   * @param {Object} options - Compression options
-   * @returns {Promise<Buffer>} Compressed image data
+   * @param {Object} options - Compression options; `filename` is the
+   *   sanitized original name, passed on so the service can name the output.
+   *   `width`, `height`, `fit`, `aspect`, `gravity` and `background` resize,
+   *   crop and pad it; `filter` colours it and `colorSpace` converts it;
+   *   `paletteColors` and `dither` ask for a palette PNG instead of a JPEG;
+   *   `borderWidth`, `borderColor` and `cornerRadius` frame it.
+   *   `watermark` (a 32-bit number) hides an invisible, traceable mark in it.
+   *   `forceEncode` returns the encoded output even when it is not smaller.
+   *   `maxBytes` caps its size, lowering the quality and dimensions no
+   *   further than `minQuality`, `minWidth` and `minHeight`.
+   *   `effort` (`fast`, `balanced` or `max`) trades encode time for size.
+   *   `placeholder` (`true` or a width) also asks for a blurred preview
+   * @returns {Promise<Object>} Compressed image `{ buffer, contentType }`,
+   *   plus `placeholder`, a `data:` URI, when one was asked for
    */
   async compressImage(imageData, options = {}) {
     const controller = new AbortController();
@@ -22,7 +34,29 @@ class CompressionService {
         headers: {
           'Content-Type': 'application/octet-stream',
           ...(options.quality && { 'X-Compression-Quality': options.quality.toString() }),
-          ...(options.format && { 'X-Output-Format': options.format })
+          ...(options.format && { 'X-Output-Format': options.format }),
+          ...(options.filename && { 'X-Filename': encodeURIComponent(options.filename) }),
+          ...(options.width && { 'X-Width': options.width.toString() }),
+          ...(options.height && { 'X-Height': options.height.toString() }),
+          ...(options.fit && { 'X-Fit': options.fit }),
+          ...(options.aspect && { 'X-Aspect': options.aspect }),
+          ...(options.gravity && { 'X-Gravity': options.gravity }),
+          ...(options.background && { 'X-Background': options.background }),
+          ...(options.filter && { 'X-Filter': options.filter }),
+          ...(options.colorSpace && { 'X-Color-Space': options.colorSpace }),
+          ...(options.paletteColors && { 'X-Palette-Colors': options.paletteColors.toString() }),
+          ...(options.dither && { 'X-Dither': options.dither }),
+          ...(options.borderWidth && { 'X-Border-Width': options.borderWidth.toString() }),
+          ...(options.borderColor && { 'X-Border-Color': options.borderColor }),
+          ...(options.cornerRadius && { 'X-Corner-Radius': options.cornerRadius.toString() }),
+          ...(options.placeholder && { 'X-Placeholder': options.placeholder.toString() }),
+          ...(options.watermark && { 'X-Watermark': options.watermark.toString() }),
+          ...(options.forceEncode === 'true' && { 'X-Force-Encode': 'true' }),
+          ...(options.maxBytes && { 'X-Max-Bytes': options.maxBytes.toString() }),
+          ...(options.minQuality && { 'X-Min-Quality': options.minQuality.toString() }),
+          ...(options.minWidth && { 'X-Min-Width': options.minWidth.toString() }),
+          ...(options.minHeight && { 'X-Min-Height': options.minHeight.toString() }),
+          ...(options.effort && { 'X-Effort': options.effort })
         },
         signal: controller.signal
       });
@@ -32,7 +66,56 @@ class CompressionService {
         throw new Error(`Compression failed: ${error}`);
       }
 
-      return await response.arrayBuffer().then(Buffer.from);
+      const buffer = await response.arrayBuffer().then(Buffer.from);
+      return {
+        buffer,
+        contentType: response.headers.get('content-type') || 'image/jpeg',
+        placeholder: response.headers.get('x-placeholder-data') || undefined
+      };
+    } finally {
+      clearTimeout(timeoutId);
+    }
+  }
+
+  /**
+   * Render a page of a contact sheet from a ZIP of images
+   * @param {Buffer} archive - ZIP of the images, named as they are labelled
+   * @param {Object} options - `page` (from 1), and `columns`, `rows` and
+   *   `thumbSize` to override the service's layout
+   * @returns {Promise<Object>} The page as `{ buffer, contentType }`, with
+   *   the `pageCount` and `imageCount` of the whole sheet. Failures carry
+   *   the service's HTTP `status`
+   */
+  async contactSheet(archive, options = {}) {
+    const controller = new AbortController();
+    const timeoutId = setTimeout(() => controller.abort(), this.timeout);
+
+    try {
+      const response = await fetch(`${this.serviceUrl}/contact-sheet`, {
+        method: 'POST',
+        body: archive,
+        headers: {
+          'Content-Type': 'application/zip',
+          ...(options.page && { 'X-Page': options.page.toString() }),
+          ...(options.columns && { 'X-Columns': options.columns.toString() }),
+          ...(options.rows && { 'X-Rows': options.rows.toString() }),
+          ...(options.thumbSize && { 'X-Thumb-Size': options.thumbSize.toString() })
+        },
+        signal: controller.signal
+      });
+
+      if (!response.ok) {
+        const error = new Error(`Contact sheet failed: ${await response.text()}`);
+        error.status = response.status;
+        throw error;
+      }
+
+      return {
+        buffer: await response.arrayBuffer().then(Buffer.from),
+        contentType: response.headers.get('content-type') || 'image/jpeg',
+        pageCount: parseInt(response.headers.get('x-page-count'), 10),
+        imageCount: parseInt(response.headers.get('x-image-count'), 10)
+      };
     } finally {
       clearTimeout(timeoutId);
     }
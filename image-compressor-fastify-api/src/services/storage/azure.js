@@ -0,0 +1,95 @@
+const { BlobSASPermissions, BlobServiceClient } = require('@azure/storage-blob');
+const { StorageBackend, directoryPrefix } = require('./index');
+
+/**
+ * Azure Blob Storage, reached with a storage account connection string.
+ */
+class AzureStorage extends StorageBackend {
+  constructor(config) {
+    super(config);
+    const { connectionString, container } = config.azure;
+    this.container = BlobServiceClient
+      .fromConnectionString(connectionString)
+      .getContainerClient(container);
+    this.publicUrl = config.azure.publicUrl ? config.azure.publicUrl.replace(/\/+$/, '') : null;
+  }
+
+  async putFile(filePath, buffer, contentType) {
+    const blob = this.container.getBlockBlobClient(filePath);
+    try {
+      await blob.uploadData(buffer, { blobHTTPHeaders: { blobContentType: contentType } });
+    } catch (error) {
+      throw new Error(`Azure upload failed: ${error.message}`);
+    }
+
+    return {
+      key: filePath,
+      url: this.publicUrl ? `${this.publicUrl}/${encodeURI(filePath)}` : blob.url,
+      size: buffer.length,
+      contentType
+    };
+  }
+
+  /**
+   * Signing needs the account key, so the connection string must carry one
+   */
+  async signedUrl(filePath, expiresIn) {
+    try {
+      return await this.container.getBlockBlobClient(filePath).generateSasUrl({
+        permissions: BlobSASPermissions.parse('r'),
+        expiresOn: new Date(Date.now() + expiresIn * 1000)
+      });
+    } catch (error) {
+      throw new Error(`Azure URL signing failed: ${error.message}`);
+    }
+  }
+
+  async downloadFile(filePath) {
+    try {
+      return await this.container.getBlockBlobClient(filePath).downloadToBuffer();
+    } catch (error) {
+      throw new Error(`Azure download failed: ${error.message}`);
+    }
+  }
+
+  async deleteFile(filePath) {
+    try {
+      await this.container.getBlockBlobClient(filePath).delete();
+    } catch (error) {
+      throw new Error(`Azure delete failed: ${error.message}`);
+    }
+  }
+
+  async listFilesRecursive(prefix = '') {
+    const files = [];
+    try {
+      for await (const blob of this.container.listBlobsFlat({ prefix: directoryPrefix(prefix) })) {
+        files.push(describe(blob.name, blob.properties));
+      }
+    } catch (error) {
+      throw new Error(`Azure list failed: ${error.message}`);
+    }
+    return files;
+  }
+
+  async getFileMetadata(filePath) {
+    try {
+      const properties = await this.container.getBlockBlobClient(filePath).getProperties();
+      return describe(filePath, properties);
+    } catch (error) {
+      if (error.statusCode === 404) return undefined;
+      throw new Error(`Azure metadata fetch failed: ${error.message}`);
+    }
+  }
+}
+
+function describe(filePath, properties) {
+  return {
+    name: filePath.split('/').pop(),
+    path: filePath,
+    size: properties.contentLength,
+    metadata: { mimetype: properties.contentType, size: properties.contentLength }
+  };
+}
+
+module.exports = AzureStorage;
@@ -0,0 +1,140 @@
+const crypto = require('crypto');
+const fs = require('fs/promises');
+const path = require('path');
+const { StorageBackend } = require('./index');
+
+// Content types of the stored files, by extension
+const CONTENT_TYPES = {
+  '.jpg': 'image/jpeg',
+  '.jpeg': 'image/jpeg',
+  '.png': 'image/png',
+  '.webp': 'image/webp',
+  '.json': 'application/json'
+};
+
+/**
+ * A directory on a local or mounted disk.
+ *
+ * Files are written to a temporary file next to their destination and
+ * renamed into place, so readers never see a partial file. Writes that
+ * would leave less than `minFreeBytes` free on the disk are refused.
+ */
+class FilesystemStorage extends StorageBackend {
+  constructor(config) {
+    super(config);
+    this.root = path.resolve(config.filesystem.root);
+    this.publicUrl = config.filesystem.publicUrl
+      ? config.filesystem.publicUrl.replace(/\/+$/, '')
+      : null;
+    this.minFreeBytes = config.filesystem.minFreeBytes;
+  }
+
+  async putFile(filePath, buffer, contentType) {
+    const destination = this.resolve(filePath);
+    await fs.mkdir(path.dirname(destination), { recursive: true });
+    await this.checkFreeSpace(destination, buffer.length);
+
+    const temporary = `${destination}.${crypto.randomBytes(6).toString('hex')}.tmp`;
+    try {
+      await fs.writeFile(temporary, buffer);
+      await fs.rename(temporary, destination);
+    } catch (error) {
+      await fs.rm(temporary, { force: true });
+      throw new Error(`Filesystem upload failed: ${error.message}`);
+    }
+
+    return {
+      key: filePath,
+      url: this.publicUrl
+        ? `${this.publicUrl}/${encodeURI(filePath)}`
+        : `file://${encodeURI(destination)}`,
+      size: buffer.length,
+      contentType
+    };
+  }
+
+  async downloadFile(filePath) {
+    try {
+      return await fs.readFile(this.resolve(filePath));
+    } catch (error) {
+      throw new Error(`Filesystem download failed: ${error.message}`);
+    }
+  }
+
+  async deleteFile(filePath) {
+    try {
+      await fs.unlink(this.resolve(filePath));
+    } catch (error) {
+      throw new Error(`Filesystem delete failed: ${error.message}`);
+    }
+  }
+
+  async listFilesRecursive(prefix = '') {
+    const directory = this.resolve(prefix);
+    let entries;
+    try {
+      entries = await fs.readdir(directory, { recursive: true, withFileTypes: true });
+    } catch (error) {
+      if (error.code === 'ENOENT') return [];
+      throw new Error(`Filesystem list failed: ${error.message}`);
+    }
+
+    const files = [];
+    for (const entry of entries) {
+      // Leftovers of interrupted writes are not files yet
+      if (!entry.isFile() || entry.name.endsWith('.tmp')) continue;
+      const relative = path.relative(this.root, path.join(entry.parentPath ?? entry.path, entry.name));
+      const file = await this.getFileMetadata(relative.split(path.sep).join('/'));
+      if (file) files.push(file);
+    }
+    return files;
+  }
+
+  async getFileMetadata(filePath) {
+    try {
+      const stats = await fs.stat(this.resolve(filePath));
+      const mimetype = CONTENT_TYPES[path.extname(filePath).toLowerCase()] || 'application/octet-stream';
+      return {
+        name: path.basename(filePath),
+        path: filePath,
+        size: stats.size,
+        metadata: { mimetype, size: stats.size }
+      };
+    } catch (error) {
+      if (error.code === 'ENOENT') return undefined;
+      throw new Error(`Filesystem metadata fetch failed: ${error.message}`);
+    }
+  }
+
+  /**
+   * The absolute path of a file, which must stay under the root
+   * @param {string} filePath - Path relative to the root
+   * @returns {string} The absolute path
+   */
+  resolve(filePath) {
+    const resolved = path.resolve(this.root, filePath);
+    if (resolved !== this.root && !resolved.startsWith(`${this.root}${path.sep}`)) {
+      throw new Error(`Path escapes the storage root: ${filePath}`);
+    }
+    return resolved;
+  }
+
+  /**
+   * Refuse a write of `size` bytes that would leave too little free space
+   * @param {string} destination - Where the file goes
+   * @param {number} size - Bytes about to be written
+   */
+  async checkFreeSpace(destination, size) {
+    const stats = await fs.statfs(path.dirname(destination));
+    const free = stats.bavail * stats.bsize;
+    if (free - size < this.minFreeBytes) {
+      const error = new Error(
+        `Not enough free space for ${size} bytes: ${free} bytes free, ${this.minFreeBytes} kept in reserve`
+      );
+      error.code = 'STORAGE_FULL';
+      throw error;
+    }
+  }
+}
+
+module.exports = FilesystemStorage;
@@ -0,0 +1,96 @@
+const { Storage } = require('@google-cloud/storage');
+const { StorageBackend, directoryPrefix } = require('./index');
+
+/**
+ * Google Cloud Storage. Credentials come from `GCS_KEY_FILE`, or from the
+ * environment (`GOOGLE_APPLICATION_CREDENTIALS`, workload identity).
+ */
+class GcsStorage extends StorageBackend {
+  constructor(config) {
+    super(config);
+    const { projectId, keyFilename, bucket } = config.gcs;
+    this.client = new Storage({ projectId, keyFilename });
+    this.bucket = this.client.bucket(bucket);
+    this.publicUrl = (config.gcs.publicUrl || `https://storage.googleapis.com/${bucket}`)
+      .replace(/\/+$/, '');
+  }
+
+  async putFile(filePath, buffer, contentType) {
+    try {
+      await this.bucket.file(filePath).save(buffer, { contentType, resumable: false });
+    } catch (error) {
+      throw new Error(`GCS upload failed: ${error.message}`);
+    }
+
+    return {
+      key: filePath,
+      url: `${this.publicUrl}/${encodeURI(filePath)}`,
+      size: buffer.length,
+      contentType
+    };
+  }
+
+  async signedUrl(filePath, expiresIn) {
+    try {
+      const [url] = await this.bucket.file(filePath).getSignedUrl({
+        version: 'v4',
+        action: 'read',
+        expires: Date.now() + expiresIn * 1000
+      });
+      return url;
+    } catch (error) {
+      throw new Error(`GCS URL signing failed: ${error.message}`);
+    }
+  }
+
+  async downloadFile(filePath) {
+    try {
+      const [contents] = await this.bucket.file(filePath).download();
+      return contents;
+    } catch (error) {
+      throw new Error(`GCS download failed: ${error.message}`);
+    }
+  }
+
+  async deleteFile(filePath) {
+    try {
+      await this.bucket.file(filePath).delete();
+    } catch (error) {
+      throw new Error(`GCS delete failed: ${error.message}`);
+    }
+  }
+
+  async listFilesRecursive(prefix = '') {
+    try {
+      // Listings are flat, and paginated by the client
+      const [files] = await this.bucket.getFiles({ prefix: directoryPrefix(prefix) });
+      return files
+        .filter((file) => !file.name.endsWith('/'))
+        .map((file) => describe(file.name, file.metadata));
+    } catch (error) {
+      throw new Error(`GCS list failed: ${error.message}`);
+    }
+  }
+
+  async getFileMetadata(filePath) {
+    try {
+      const [metadata] = await this.bucket.file(filePath).getMetadata();
+      return describe(filePath, metadata);
+    } catch (error) {
+      if (error.code === 404) return undefined;
+      throw new Error(`GCS metadata fetch failed: ${error.message}`);
+    }
+  }
+}
+
+function describe(filePath, metadata) {
+  const size = Number(metadata.size);
+  return {
+    name: filePath.split('/').pop(),
+    path: filePath,
+    size,
+    metadata: { mimetype: metadata.contentType, size }
+  };
+}
+
+module.exports = GcsStorage;
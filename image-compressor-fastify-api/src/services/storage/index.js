@@ -0,0 +1,158 @@
+const { renderPathTemplate } = require('../../utils');
+
+/**
+ * Where compressed images are kept.
+ *
+ * Every backend writes, reads, deletes and lists files by their path in a
+ * bucket or container, and describes listed files the same way:
+ * `{ name, path, size, metadata: { mimetype, size } }`.
+ */
+class StorageBackend {
+  constructor(config) {
+    this.config = config;
+    this.uploadPath = config.storage.uploadPath;
+    this.pathTemplate = config.storage.pathTemplate;
+  }
+
+  /**
+   * Upload a compressed image at the path `storage.pathTemplate` gives it
+   * @param {Buffer} buffer - File buffer
+   * @param {string} filename - File name, with the extension of its format
+   * @param {string} contentType - File content type
+   * @param {Object} [options]
+   * @param {string} [options.tenant] - Who uploaded the file
+   * @returns {Promise<Object>} Upload result
+   */
+  async uploadFile(buffer, filename, contentType, { tenant } = {}) {
+    const filePath = renderPathTemplate(this.pathTemplate, {
+      uploadPath: this.uploadPath,
+      tenant,
+      buffer,
+      filename
+    });
+    return this.putFile(filePath, buffer, contentType);
+  }
+
+  /**
+   * Remember the focal point of a stored image, so later crops of the same
+   * asset keep the same subject. Kept next to the image as `<path>.focal.json`
+   * @param {string} filePath - Path to the image
+   * @param {Object} focalPoint - `{ x, y }`, both between 0 and 1
+   * @returns {Promise<void>}
+   */
+  async putFocalPoint(filePath, focalPoint) {
+    await this.putFile(
+      focalPointPath(filePath),
+      Buffer.from(JSON.stringify(focalPoint)),
+      'application/json'
+    );
+  }
+
+  /**
+   * Write a file at an exact path, replacing any existing one
+   * @param {string} filePath - Path to the file
+   * @param {Buffer} buffer - File buffer
+   * @param {string} contentType - File content type
+   * @returns {Promise<Object>} `{ key, url, size, contentType }`
+   */
+  async putFile(filePath, buffer, contentType) {
+    throw new Error(`${this.constructor.name} does not implement putFile`);
+  }
+
+  /**
+   * Download a file
+   * @param {string} filePath - Path to the file
+   * @returns {Promise<Buffer>} File contents
+   */
+  async downloadFile(filePath) {
+    throw new Error(`${this.constructor.name} does not implement downloadFile`);
+  }
+
+  /**
+   * Delete a file
+   * @param {string} filePath - Path to the file
+   * @returns {Promise<void>}
+   */
+  async deleteFile(filePath) {
+    throw new Error(`${this.constructor.name} does not implement deleteFile`);
+  }
+
+  /**
+   * List every file below a directory, descending into subdirectories
+   * @param {string} prefix - Directory prefix
+   * @returns {Promise<Array>} Files, with `path` set to their full path
+   */
+  async listFilesRecursive(prefix = '') {
+    throw new Error(`${this.constructor.name} does not implement listFilesRecursive`);
+  }
+
+  /**
+   * A URL that lets anyone holding it download a file until it expires
+   * @param {string} filePath - Path to the file
+   * @param {number} expiresIn - Seconds the URL stays valid
+   * @returns {Promise<string|null>} The URL, or null if the backend cannot sign URLs
+   */
+  async signedUrl(filePath, expiresIn) {
+    return null;
+  }
+
+  /**
+   * Get file metadata
+   * @param {string} filePath - Path to the file
+   * @returns {Promise<Object|undefined>} The file's listing entry
+   */
+  async getFileMetadata(filePath) {
+    throw new Error(`${this.constructor.name} does not implement getFileMetadata`);
+  }
+}
+
+/**
+ * Create the backend selected by `storage.backend`. SDKs are loaded only
+ * for the backend in use.
+ * @param {Object} config - Application config
+ * @returns {StorageBackend} The storage backend
+ */
+function createStorageBackend(config) {
+  switch (config.storage.backend) {
+    case 'supabase':
+      return new (require('./supabase'))(config);
+    case 'gcs':
+      return new (require('./gcs'))(config);
+    case 'azure':
+      return new (require('./azure'))(config);
+    case 'filesystem':
+      return new (require('./filesystem'))(config);
+    default:
+      throw new Error(`Unknown storage backend: ${config.storage.backend}`);
+  }
+}
+
+/**
+ * Where the focal point of an image is stored
+ * @param {string} filePath - Path to the image
+ * @returns {string} Path of its focal point file
+ */
+function focalPointPath(filePath) {
+  return `${filePath}.focal.json`;
+}
+
+/**
+ * Join a directory prefix and a name the way listings report paths
+ * @param {string} prefix - Directory prefix, possibly empty
+ * @param {string} name - Entry name
+ * @returns {string} The full path
+ */
+function joinPath(prefix, name) {
+  return prefix ? `${prefix}/${name}` : name;
+}
+
+/**
+ * The prefix that lists the contents of a directory, with a trailing slash
+ * @param {string} prefix - Directory prefix, possibly empty
+ * @returns {string} The listing prefix
+ */
+function directoryPrefix(prefix) {
+  return prefix ? `${prefix.replace(/\/+$/, '')}/` : '';
+}
+
+module.exports = { StorageBackend, createStorageBackend, joinPath, directoryPrefix, focalPointPath };
@@ -1,23 +1,21 @@
 const { createClient } = require('@supabase/supabase-js');
+const { StorageBackend, joinPath } = require('./index');
 
-class SupabaseService {
+class SupabaseStorage extends StorageBackend {
   constructor(config) {
-    this.config = config;
+    super(config);
     this.client = createClient(config.supabase.url, config.supabase.key);
     this.bucket = config.supabase.bucket;
-    this.uploadPath = config.supabase.uploadPath;
   }
 
   /**
-   * Upload file to Supabase Storage
+   * Write a file at an exact path in the bucket, replacing any existing one
+   * @param {string} filePath - Path to the file in the bucket
    * @param {Buffer} buffer - File buffer
-   * @param {string} filename - File name
    * @param {string} contentType - File content type
    * @returns {Promise<Object>} Upload result
    */
-  async uploadFile(buffer, filename, contentType) {
-    const filePath = `${this.uploadPath}/${filename}`;
-
+  async putFile(filePath, buffer, contentType) {
     const { data, error } = await this.client.storage
       .from(this.bucket)
       .upload(filePath, buffer, {
@@ -42,6 +40,35 @@ class SupabaseService {
     };
   }
 
+  async signedUrl(filePath, expiresIn) {
+    const { data, error } = await this.client.storage
+      .from(this.bucket)
+      .createSignedUrl(filePath, expiresIn);
+
+    if (error) {
+      throw new Error(`Supabase URL signing failed: ${error.message}`);
+    }
+
+    return data.signedUrl;
+  }
+
+  /**
+   * Download a file from Supabase Storage
+   * @param {string} filePath - Path to the file in the bucket
+   * @returns {Promise<Buffer>} File contents
+   */
+  async downloadFile(filePath) {
+    const { data, error } = await this.client.storage
+      .from(this.bucket)
+      .download(filePath);
+
+    if (error) {
+      throw new Error(`Supabase download failed: ${error.message}`);
+    }
+
+    return Buffer.from(await data.arrayBuffer());
+  }
+
   /**
    * Delete file from Supabase Storage
    * @param {string} filePath - Path to the file in the bucket
@@ -74,6 +101,40 @@ class SupabaseService {
     return data;
   }
 
+  /**
+   * List every file below a directory, descending into subdirectories
+   * @param {string} prefix - Directory prefix
+   * @returns {Promise<Array>} Files, with `path` set to their full path
+   */
+  async listFilesRecursive(prefix = '') {
+    const pageSize = 100;
+    const files = [];
+
+    for (let offset = 0; ; offset += pageSize) {
+      const { data, error } = await this.client.storage
+        .from(this.bucket)
+        .list(prefix, { limit: pageSize, offset });
+
+      if (error) {
+        throw new Error(`Supabase list failed: ${error.message}`);
+      }
+
+      for (const entry of data) {
+        const entryPath = joinPath(prefix, entry.name);
+        // Folders have no id
+        if (entry.id === null) {
+          files.push(...await this.listFilesRecursive(entryPath));
+        } else {
+          files.push({ ...entry, path: entryPath, size: entry.metadata?.size });
+        }
+      }
+
+      if (data.length < pageSize) {
+        return files;
+      }
+    }
+  }
+
   /**
    * Get file metadata
    * @param {string} filePath - Path to the file in the bucket
@@ -96,4 +157,4 @@ class SupabaseService {
   }
 }
 
-module.exports = SupabaseService; 
\ No newline at end of file
+module.exports = SupabaseStorage;
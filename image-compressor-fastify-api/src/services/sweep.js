@@ -0,0 +1,172 @@
+const { estimateJpegQuality, formatBytes } = require('../utils');
+
+/**
+ * Periodically re-compresses images already in storage.
+ *
+ * Every run lists the configured prefix, and re-compresses in place each
+ * JPEG that is larger than `minSize` or was encoded above `maxQuality`.
+ * The original is copied under `backupPrefix` first, and results saving
+ * less than `minSavingsPercent` are discarded. Each run uploads a savings
+ * report under `reportPrefix`.
+ *
+ * Only JPEGs are swept: the Rust service writes stills as JPEG, and
+ * replacing a file in place must keep its format.
+ */
+class SweepService {
+  constructor(config, { compressionService, storageService, log }) {
+    this.config = config.sweep;
+    this.compressionService = compressionService;
+    this.storageService = storageService;
+    this.log = log;
+    this.timer = null;
+    this.running = null;
+    this.lastReport = null;
+  }
+
+  /**
+   * Run a sweep now and then every `intervalMs`
+   */
+  start() {
+    const tick = () => this.run().catch((error) => this.log.error(error, 'Storage sweep failed'));
+    tick();
+    this.timer = setInterval(tick, this.config.intervalMs);
+    this.timer.unref();
+  }
+
+  /**
+   * Stop scheduling sweeps and wait for a running one to finish
+   * @returns {Promise<void>}
+   */
+  async stop() {
+    clearInterval(this.timer);
+    this.timer = null;
+    await this.running;
+  }
+
+  /**
+   * Sweep storage once. A call while a sweep runs joins that sweep.
+   * @returns {Promise<Object>} The savings report
+   */
+  async run() {
+    if (!this.running) {
+      this.running = this.sweep().finally(() => {
+        this.running = null;
+      });
+    }
+    return this.running;
+  }
+
+  async sweep() {
+    const { prefix, backupPrefix, reportPrefix } = this.config;
+    const report = {
+      startedAt: new Date().toISOString(),
+      finishedAt: null,
+      prefix,
+      scanned: 0,
+      recompressed: 0,
+      skipped: 0,
+      failed: 0,
+      bytesBefore: 0,
+      bytesAfter: 0,
+      bytesSaved: 0,
+      files: []
+    };
+
+    const files = await this.storageService.listFilesRecursive(prefix);
+    for (const file of files) {
+      // Never sweep the sweep's own backups and reports, or focal points
+      if (isUnder(file.path, backupPrefix) || isUnder(file.path, reportPrefix)) continue;
+      if (file.path.endsWith('.focal.json')) continue;
+      report.scanned++;
+
+      try {
+        const result = await this.sweepFile(file);
+        report.files.push(result);
+        if (result.action === 'recompressed') {
+          report.recompressed++;
+          report.bytesBefore += result.sizeBefore;
+          report.bytesAfter += result.sizeAfter;
+        } else {
+          report.skipped++;
+        }
+      } catch (error) {
+        this.log.warn({ err: error, path: file.path }, 'Storage sweep failed for a file');
+        report.failed++;
+        report.files.push({ path: file.path, action: 'failed', error: error.message });
+      }
+    }
+
+    report.bytesSaved = report.bytesBefore - report.bytesAfter;
+    report.finishedAt = new Date().toISOString();
+
+    const reportPath = `${reportPrefix}/sweep-${report.startedAt.replace(/[:.]/g, '-')}.json`;
+    await this.storageService.putFile(
+      reportPath,
+      Buffer.from(JSON.stringify(report, null, 2)),
+      'application/json'
+    );
+    this.log.info({
+      report: reportPath,
+      scanned: report.scanned,
+      recompressed: report.recompressed,
+      failed: report.failed,
+      bytesSaved: report.bytesSaved
+    }, `Storage sweep saved ${formatBytes(Math.max(report.bytesSaved, 0))}`);
+
+    this.lastReport = report;
+    return report;
+  }
+
+  /**
+   * Re-compress one file if it goes over a threshold
+   * @param {Object} file - A listing entry with its full `path`
+   * @returns {Promise<Object>} The file's entry in the report
+   */
+  async sweepFile(file) {
+    const { path: filePath, metadata = {} } = file;
+    if (metadata.mimetype && metadata.mimetype !== 'image/jpeg') {
+      return { path: filePath, action: 'skipped', reason: 'not a JPEG' };
+    }
+
+    const original = await this.storageService.downloadFile(filePath);
+    const quality = estimateJpegQuality(original);
+    if (quality === null) {
+      return { path: filePath, action: 'skipped', reason: 'not a JPEG' };
+    }
+    if (original.length < this.config.minSize && quality <= this.config.maxQuality) {
+      return { path: filePath, action: 'skipped', reason: 'under thresholds', size: original.length, quality };
+    }
+
+    const { buffer, contentType } = await this.compressionService.compressImage(original, {
+      quality: this.config.quality
+    });
+    const savedPercent = ((original.length - buffer.length) / original.length) * 100;
+    if (contentType !== 'image/jpeg' || savedPercent < this.config.minSavingsPercent) {
+      return {
+        path: filePath,
+        action: 'skipped',
+        reason: 'not enough savings',
+        size: original.length,
+        quality
+      };
+    }
+
+    await this.storageService.putFile(`${this.config.backupPrefix}/${filePath}`, original, 'image/jpeg');
+    await this.storageService.putFile(filePath, buffer, contentType);
+
+    return {
+      path: filePath,
+      action: 'recompressed',
+      backup: `${this.config.backupPrefix}/${filePath}`,
+      quality,
+      sizeBefore: original.length,
+      sizeAfter: buffer.length
+    };
+  }
+}
+
+function isUnder(filePath, prefix) {
+  return filePath === prefix || filePath.startsWith(`${prefix}/`);
+}
+
+module.exports = SweepService;
@@ -1,6 +1,62 @@
 const crypto = require('crypto');
 const path = require('path');
 
+// Extensions of the formats the Rust service produces
+const OUTPUT_EXTENSIONS = {
+  'image/jpeg': '.jpg',
+  'image/webp': '.webp',
+  'image/png': '.png'
+};
+
+// Longest filename kept, in characters
+const MAX_FILENAME_LENGTH = 200;
+
+/**
+ * Make a client-supplied filename safe to store and send in headers:
+ * drop any directory part, control and reserved characters, leading dots
+ * and surrounding whitespace, and cap the length
+ * @param {string} originalFilename - The filename from the upload
+ * @param {string} fallback - Name used when nothing usable is left
+ * @returns {string} The sanitized filename
+ */
+function sanitizeFilename(originalFilename, fallback = 'image') {
+  const base = String(originalFilename || '').split(/[/\\]/).pop();
+  const cleaned = base
+    .replace(/[\u0000-\u001f\u007f"*:<>?|]/g, '')
+    .trim()
+    .replace(/^\.+/, '')
+    .trim()
+    .slice(0, MAX_FILENAME_LENGTH);
+  return cleaned || fallback;
+}
+
+/**
+ * Replace the extension of a filename with the one of the output format
+ * @param {string} filename - A sanitized filename
+ * @param {string} contentType - MIME type of the output
+ * @returns {string} The filename with the output extension
+ */
+function withOutputExtension(filename, contentType) {
+  const ext = OUTPUT_EXTENSIONS[contentType];
+  if (!ext) return filename;
+  const name = path.basename(filename, path.extname(filename)) || filename;
+  return `${name}${ext}`;
+}
+
+/**
+ * Build a Content-Disposition value, with the exact name RFC 5987 encoded
+ * @param {string} filename - A sanitized filename
+ * @param {string} type - `inline` or `attachment`
+ * @returns {string} The header value
+ */
+function contentDisposition(filename, type = 'inline') {
+  const ascii = filename.replace(/[^\x20-\x7e]|["\\]/g, '_');
+  if (ascii === filename) {
+    return `${type}; filename="${filename}"`;
+  }
+  return `${type}; filename="${ascii}"; filename*=UTF-8''${encodeURIComponent(filename)}`;
+}
+
 /**
  * Generate a unique filename with timestamp and random hash
  * @param {string} originalFilename - The original filename
@@ -14,6 +70,60 @@ function generateUniqueFilename(originalFilename) {
   return `${name}-${timestamp}-${hash}${ext}`;
 }
 
+// Placeholders of storage path templates
+const PATH_TEMPLATE_FIELDS = ['uploadPath', 'tenant', 'date', 'hash', 'name', 'ext', 'filename'];
+
+/**
+ * Check that a storage path template only uses known placeholders
+ * @param {string} template - e.g. `{tenant}/{date}/{hash}.{ext}`
+ * @returns {string|null} An error message, or null when valid
+ */
+function checkPathTemplate(template) {
+  for (const [, field] of template.matchAll(/\{([^}]*)\}/g)) {
+    if (!PATH_TEMPLATE_FIELDS.includes(field)) {
+      return `unknown placeholder {${field}} (expected ${PATH_TEMPLATE_FIELDS.map((f) => `{${f}}`).join(', ')})`;
+    }
+  }
+  return null;
+}
+
+/**
+ * Render the storage path of an uploaded file
+ * @param {string} template - A template accepted by checkPathTemplate
+ * @param {Object} file - `{ uploadPath, tenant, buffer, filename }`
+ * @returns {string} The path, with empty segments dropped
+ */
+function renderPathTemplate(template, { uploadPath, tenant, buffer, filename }) {
+  const ext = path.extname(filename);
+  const values = {
+    uploadPath,
+    tenant: sanitizeFilename(tenant, 'default'),
+    date: new Date().toISOString().slice(0, 10),
+    hash: crypto.createHash('sha256').update(buffer).digest('hex').slice(0, 16),
+    name: path.basename(filename, ext),
+    ext: ext.slice(1),
+    filename: generateUniqueFilename(filename)
+  };
+  return template
+    .replace(/\{([^}]*)\}/g, (_, field) => values[field])
+    .split('/')
+    .filter(Boolean)
+    .join('/');
+}
+
+/**
+ * Parse a focal point gravity, `x,y` with both between 0 and 1
+ * @param {string} gravity - The requested gravity
+ * @returns {Object|null} `{ x, y }`, or null for directions and invalid values
+ */
+function parseFocalPoint(gravity) {
+  const parts = String(gravity || '').split(',');
+  if (parts.length !== 2) return null;
+  const [x, y] = parts.map((part) => Number(part.trim()));
+  const valid = (v) => Number.isFinite(v) && v >= 0 && v <= 1;
+  return valid(x) && valid(y) ? { x, y } : null;
+}
+
 /**
  * Validate file upload
  * @param {Object} file - The file object from fastify-multipart
@@ -55,6 +165,132 @@ function formatBytes(bytes) {
   return `${parseFloat((bytes / Math.pow(k, i)).toFixed(2))} ${sizes[i]}`;
 }
 
+// IJG standard luminance quantization table, which libjpeg scales by quality
+const STANDARD_LUMA_TABLE = [
+  16, 11, 10, 16, 24, 40, 51, 61,
+  12, 12, 14, 19, 26, 58, 60, 55,
+  14, 13, 16, 24, 40, 57, 69, 56,
+  14, 17, 22, 29, 51, 87, 80, 62,
+  18, 22, 37, 56, 68, 109, 103, 77,
+  24, 35, 55, 64, 81, 104, 113, 92,
+  49, 64, 78, 87, 103, 121, 120, 101,
+  72, 92, 95, 98, 112, 100, 103, 99
+];
+const STANDARD_LUMA_SUM = STANDARD_LUMA_TABLE.reduce((sum, q) => sum + q, 0);
+
+/**
+ * Estimate the quality a JPEG was encoded at, from its luminance
+ * quantization table, on the libjpeg 1-100 scale
+ * @param {Buffer} buffer - JPEG data
+ * @returns {number|null} Estimated quality, or null if not a JPEG
+ */
+function estimateJpegQuality(buffer) {
+  if (buffer.length < 4 || buffer[0] !== 0xff || buffer[1] !== 0xd8) {
+    return null;
+  }
+
+  let offset = 2;
+  while (offset + 4 <= buffer.length && buffer[offset] === 0xff) {
+    const marker = buffer[offset + 1];
+    const length = buffer.readUInt16BE(offset + 2);
+    const end = offset + 2 + length;
+    // Start of scan: no tables after this
+    if (marker === 0xda || end > buffer.length) break;
+
+    if (marker === 0xdb) {
+      let table = offset + 4;
+      while (table < end) {
+        const precision = buffer[table] >> 4;
+        const id = buffer[table] & 0x0f;
+        const size = precision ? 128 : 64;
+        if (id === 0 && table + 1 + size <= end) {
+          let sum = 0;
+          for (let i = 0; i < 64; i++) {
+            sum += precision ? buffer.readUInt16BE(table + 1 + i * 2) : buffer[table + 1 + i];
+          }
+          // Inverse of libjpeg's quality scaling
+          const scale = (sum * 100) / STANDARD_LUMA_SUM;
+          const quality = scale <= 100 ? (200 - scale) / 2 : 5000 / scale;
+          return Math.max(1, Math.min(100, Math.round(quality)));
+        }
+        table += 1 + size;
+      }
+    }
+    offset = end;
+  }
+  return null;
+}
+
+// CRC-32 (IEEE) lookup table for ZIP entries
+const CRC32_TABLE = Array.from({ length: 256 }, (_, n) => {
+  let c = n;
+  for (let k = 0; k < 8; k++) {
+    c = c & 1 ? 0xedb88320 ^ (c >>> 1) : c >>> 1;
+  }
+  return c >>> 0;
+});
+
+/**
+ * CRC-32 checksum of a buffer, as ZIP archives use it
+ * @param {Buffer} buffer - Data to checksum
+ * @returns {number} The checksum
+ */
+function crc32(buffer) {
+  let crc = 0xffffffff;
+  for (const byte of buffer) {
+    crc = CRC32_TABLE[(crc ^ byte) & 0xff] ^ (crc >>> 8);
+  }
+  return (crc ^ 0xffffffff) >>> 0;
+}
+
+/**
+ * Pack files into a ZIP archive, stored without compression (images are
+ * compressed already)
+ * @param {Array<{name: string, buffer: Buffer}>} files - Entries, in order
+ * @returns {Buffer} The archive
+ */
+function createZip(files) {
+  const locals = [];
+  const directory = [];
+  let offset = 0;
+  for (const { name, buffer } of files) {
+    const nameBytes = Buffer.from(name, 'utf8');
+    // Version needed, flags (UTF-8 name), method (stored), time, date
+    // (1980-01-01), CRC, sizes, name and extra field lengths
+    const common = Buffer.alloc(26);
+    common.writeUInt16LE(10, 0);
+    common.writeUInt16LE(0x0800, 2);
+    common.writeUInt16LE(0, 4);
+    common.writeUInt16LE(0, 6);
+    common.writeUInt16LE(0x21, 8);
+    common.writeUInt32LE(crc32(buffer), 10);
+    common.writeUInt32LE(buffer.length, 14);
+    common.writeUInt32LE(buffer.length, 18);
+    common.writeUInt16LE(nameBytes.length, 22);
+    common.writeUInt16LE(0, 24);
+
+    const local = Buffer.concat([Buffer.from('PK\x03\x04', 'latin1'), common, nameBytes, buffer]);
+    const central = Buffer.alloc(46 + nameBytes.length);
+    central.write('PK\x01\x02', 0, 'latin1');
+    central.writeUInt16LE(10, 4);
+    common.copy(central, 6);
+    central.writeUInt32LE(offset, 42);
+    nameBytes.copy(central, 46);
+    locals.push(local);
+    directory.push(central);
+    offset += local.length;
+  }
+
+  const centralDirectory = Buffer.concat(directory);
+  const end = Buffer.alloc(22);
+  end.write('PK\x05\x06', 0, 'latin1');
+  end.writeUInt16LE(files.length, 8);
+  end.writeUInt16LE(files.length, 10);
+  end.writeUInt32LE(centralDirectory.length, 12);
+  end.writeUInt32LE(offset, 16);
+  return Buffer.concat([...locals, centralDirectory, end]);
+}
+
 /**
  * Create error response object
  * @param {string} message - Error message
@@ -100,9 +336,18 @@ function parseQuality(quality, defaultQuality) {
 }
 
 module.exports = {
+  sanitizeFilename,
+  withOutputExtension,
+  contentDisposition,
   generateUniqueFilename,
+  checkPathTemplate,
+  renderPathTemplate,
+  parseFocalPoint,
   validateFileUpload,
   formatBytes,
+  estimateJpegQuality,
+  crc32,
+  createZip,
   createErrorResponse,
   createSuccessResponse,
   parseQuality
@@ -0,0 +1,92 @@
+// image-compressor-rust-service/client/src/error.rs
+
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+/// Errors returned by [`Client`](crate::Client).
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The base URL could not be parsed or joined with an endpoint path.
+    #[error("invalid URL: {0}")]
+    InvalidUrl(String),
+    /// The request could not be sent or the response could not be read.
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    /// Reading the upload from disk failed.
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// A successful response could not be understood.
+    #[error("invalid response: {0}")]
+    InvalidResponse(String),
+    /// The service answered with an error status.
+    #[error("{status} {code}: {message}")]
+    Api {
+        status: StatusCode,
+        /// The service's machine-readable error code, e.g. `unprocessable_image`.
+        code: String,
+        message: String,
+        /// The `X-Request-Id` of the failed request, for correlation with
+        /// service logs.
+        request_id: Option<String>,
+    },
+}
+
+impl Error {
+    /// Whether retrying the same request might succeed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Http(e) => e.is_connect() || e.is_timeout(),
+            Self::Api { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::InvalidUrl(_) | Self::Io(_) | Self::InvalidResponse(_) => false,
+        }
+    }
+
+    /// The HTTP status, if the service answered at all.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Http(e) => e.status(),
+            Self::Api { status, .. } => Some(*status),
+            Self::InvalidUrl(_) | Self::Io(_) | Self::InvalidResponse(_) => None,
+        }
+    }
+
+    /// Builds an [`Error::Api`] from an error response body. Bodies that are
+    /// not the service's JSON error shape (e.g. from a proxy) are kept as the
+    /// message verbatim.
+    pub(crate) fn from_response(
+        status: StatusCode,
+        header_request_id: Option<String>,
+        body: &[u8],
+    ) -> Self {
+        #[derive(Deserialize)]
+        struct Envelope {
+            error: Body,
+        }
+
+        #[derive(Deserialize)]
+        struct Body {
+            code: String,
+            message: String,
+            request_id: Option<String>,
+        }
+
+        match serde_json::from_slice::<Envelope>(body) {
+            Ok(Envelope { error }) => Self::Api {
+                status,
+                code: error.code,
+                message: error.message,
+                request_id: error.request_id.or(header_request_id),
+            },
+            Err(_) => Self::Api {
+                status,
+                code: "http_error".to_string(),
+                message: String::from_utf8_lossy(body).trim().to_string(),
+                request_id: header_request_id,
+            },
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,502 @@
+// image-compressor-rust-service/client/src/lib.rs
+
+//! Async client for the image compression service.
+//!
+//! ```no_run
+//! use image_compressor_client::{Client, CompressOptions, Fit};
+//!
+//! # async fn run() -> image_compressor_client::Result<()> {
+//! let client = Client::new("http://image-compressor:8000")?;
+//! let options = CompressOptions::new().quality(75).width(1024).fit(Fit::Contain);
+//! let image = client.compress_file("photo.png", &options).await?;
+//! std::fs::write("photo.jpg", &image.data)?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod error;
+mod options;
+mod retry;
+
+pub use error::{Error, Result};
+pub use options::{
+    Animation, ColorSpace, CompressOptions, CornerRadius, Dither, Effort, Filter, Fit, Gravity,
+    Priority, QualityScale, ALLOW_UPSCALE_HEADER, ANIMATION_HEADER, ASPECT_HEADER,
+    BACKGROUND_HEADER, BORDER_COLOR_HEADER, BORDER_WIDTH_HEADER, COLOR_SPACE_HEADER,
+    CORNER_RADIUS_HEADER, DETERMINISTIC_HEADER, DITHER_HEADER, DOWNLOAD_HEADER, EFFORT_HEADER,
+    FILENAME_HEADER, FILENAME_TEMPLATE_HEADER, FILTER_HEADER, FIT_HEADER, FORCE_ENCODE_HEADER,
+    GRAVITY_HEADER, HEIGHT_HEADER, MAX_BYTES_HEADER, METADATA_COPYRIGHT_HEADER,
+    METADATA_EXIF_HEADER, METADATA_XMP_HEADER, MIN_HEIGHT_HEADER, MIN_QUALITY_HEADER,
+    MIN_WIDTH_HEADER, ONLY_IF_LARGER_HEADER, PALETTE_COLORS_HEADER, PLACEHOLDER_HEADER,
+    PRIORITY_HEADER, QUALITY_HEADER, QUALITY_SCALE_HEADER, ROTATE_HEADER,
+    SKIP_IF_SMALLER_THAN_HEADER, WATERMARK_HEADER, WIDTH_HEADER,
+};
+pub use retry::RetryPolicy;
+
+use base64::Engine;
+use bytes::Bytes;
+use futures_core::Stream;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, USER_AGENT};
+use reqwest::{Body, Response, Url};
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+use tokio_util::io::ReaderStream;
+
+/// Header used by the service for request correlation.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Header the service sets when it returned the input uncompressed.
+pub const SKIPPED_HEADER: &str = "X-Compression-Skipped";
+
+/// Header saying whether the service resized the image.
+pub const SCALING_HEADER: &str = "X-Scaling";
+
+/// Header carrying the requested placeholder as a `data:` URI.
+pub const PLACEHOLDER_DATA_HEADER: &str = "X-Placeholder-Data";
+
+/// A compressed image returned by the service.
+#[derive(Debug, Clone)]
+pub struct CompressedImage {
+    pub data: Bytes,
+    /// The output MIME type, e.g. `image/jpeg` or `image/webp`.
+    pub content_type: String,
+    /// The `X-Request-Id` the service assigned to the request.
+    pub request_id: Option<String>,
+    /// Why the input came back uncompressed (`small-enough` or
+    /// `not-smaller`), if it did.
+    pub skipped: Option<String>,
+    /// Whether the image was resized: `none`, `down` or `up`.
+    pub scaling: Option<String>,
+    /// The blurred preview asked for with
+    /// [`CompressOptions::placeholder`], as a `data:image/jpeg;base64,...`
+    /// URI.
+    pub placeholder: Option<String>,
+}
+
+/// Difference metrics and heatmap returned by [`Client::diff`].
+#[derive(Debug, Clone)]
+pub struct ImageDiff {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels whose largest channel delta exceeds the threshold.
+    pub differing_pixels: u64,
+    /// `differing_pixels` as a fraction of all pixels.
+    pub mismatch_ratio: f64,
+    /// The largest channel delta, from 0 to 255.
+    pub max_delta: u8,
+    /// The mean channel delta, from 0 (identical) to 1.
+    pub mean_delta: f64,
+    /// Peak signal-to-noise ratio in dB; `None` for identical images.
+    pub psnr: Option<f64>,
+    /// PNG heatmap of the differences.
+    pub heatmap: Bytes,
+}
+
+/// Exposure statistics returned by [`Client::histogram`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExposureStats {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels counted: those that are not fully transparent.
+    pub pixels: u64,
+    pub histogram: Histogram,
+    /// Mean luminance, from 0 (black) to 1 (white).
+    pub mean_luminance: f64,
+    pub clipping: Clipping,
+}
+
+/// Per-channel histograms with 256 bins each.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Histogram {
+    pub red: Vec<u64>,
+    pub green: Vec<u64>,
+    pub blue: Vec<u64>,
+    pub luminance: Vec<u64>,
+}
+
+/// Percentages of pixels with a channel at 0 or 255.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Clipping {
+    pub shadows_percent: f64,
+    pub highlights_percent: f64,
+}
+
+/// What [`Client::detect_watermark`] found in an image.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatermarkDetection {
+    pub found: bool,
+    /// The payload passed as [`CompressOptions::watermark`], if found.
+    pub payload: Option<u32>,
+    /// The share of blocks agreeing with the bits read: about 0.5 for
+    /// unmarked images, close to 1 for intact marks.
+    pub confidence: f64,
+}
+
+/// The `site.webmanifest` [`Client::favicon`] and [`Client::icons`] add
+/// to their bundles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebManifest {
+    pub name: String,
+    /// Theme and splash background colour, as RGBA; alpha is ignored.
+    pub theme_color: Option<[u8; 4]>,
+}
+
+#[derive(Deserialize)]
+struct DiffResponse {
+    width: u32,
+    height: u32,
+    differing_pixels: u64,
+    mismatch_ratio: f64,
+    max_delta: u8,
+    mean_delta: f64,
+    psnr: Option<f64>,
+    diff: String,
+}
+
+/// Builder for a [`Client`].
+#[derive(Debug)]
+pub struct ClientBuilder {
+    base_url: String,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    user_agent: String,
+    http: Option<reqwest::Client>,
+}
+
+impl ClientBuilder {
+    /// Total timeout for a single attempt, including upload and download.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// Uses an existing `reqwest` client, e.g. to share its connection pool.
+    /// [`timeout`](Self::timeout) is then applied per request instead.
+    pub fn http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = Some(http);
+        self
+    }
+
+    pub fn build(self) -> Result<Client> {
+        let mut base_url = Url::parse(&self.base_url)
+            .map_err(|e| Error::InvalidUrl(format!("{}: {}", self.base_url, e)))?;
+        // Make `join` append to the path instead of replacing its last segment.
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+
+        let http = match self.http {
+            Some(http) => http,
+            None => reqwest::Client::builder().build()?,
+        };
+        Ok(Client {
+            http,
+            base_url,
+            timeout: self.timeout,
+            retry: self.retry,
+            user_agent: self.user_agent,
+        })
+    }
+}
+
+/// Client for the compression service.
+///
+/// Cheap to clone; clones share the underlying connection pool.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: Url,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    user_agent: String,
+}
+
+impl Client {
+    /// Creates a client with the default retry policy and no timeout.
+    pub fn new(base_url: impl Into<String>) -> Result<Self> {
+        Self::builder(base_url).build()
+    }
+
+    pub fn builder(base_url: impl Into<String>) -> ClientBuilder {
+        ClientBuilder {
+            base_url: base_url.into(),
+            timeout: None,
+            retry: RetryPolicy::default(),
+            user_agent: concat!("image-compressor-client/", env!("CARGO_PKG_VERSION")).to_string(),
+            http: None,
+        }
+    }
+
+    /// Compresses an in-memory image. Retried according to the retry policy.
+    pub async fn compress(
+        &self,
+        image: impl Into<Bytes>,
+        options: &CompressOptions,
+    ) -> Result<CompressedImage> {
+        let image = image.into();
+        self.with_retries(|| async {
+            let mut headers = options.to_headers();
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(image.len()));
+            self.post_compress(headers, Body::from(image.clone())).await
+        })
+        .await
+    }
+
+    /// Streams an image from disk without reading it into memory first.
+    /// Retried according to the retry policy; the file is reopened for
+    /// every attempt.
+    pub async fn compress_file(
+        &self,
+        path: impl AsRef<Path>,
+        options: &CompressOptions,
+    ) -> Result<CompressedImage> {
+        let path = path.as_ref();
+        self.with_retries(|| async {
+            let file = tokio::fs::File::open(path).await?;
+            let len = file.metadata().await?.len();
+            let mut headers = options.to_headers();
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+            let body = Body::wrap_stream(ReaderStream::new(file));
+            self.post_compress(headers, body).await
+        })
+        .await
+    }
+
+    /// Streams an image from an arbitrary byte stream.
+    ///
+    /// The stream can only be consumed once, so this call is never retried.
+    pub async fn compress_stream<S, E>(
+        &self,
+        stream: S,
+        options: &CompressOptions,
+    ) -> Result<CompressedImage>
+    where
+        S: Stream<Item = std::result::Result<Bytes, E>> + Send + Sync + 'static,
+        E: Into<Box<dyn std::error::Error + Send + Sync>> + 'static,
+    {
+        self.post_compress(options.to_headers(), Body::wrap_stream(stream))
+            .await
+    }
+
+    /// Compares `candidate` with `baseline` for visual regression tests.
+    /// Pixels whose channels all differ by at most `threshold` count as
+    /// equal. Retried according to the retry policy.
+    pub async fn diff(
+        &self,
+        baseline: &[u8],
+        candidate: &[u8],
+        threshold: u8,
+    ) -> Result<ImageDiff> {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let body = serde_json::json!({
+            "baseline": engine.encode(baseline),
+            "candidate": engine.encode(candidate),
+            "threshold": threshold,
+        })
+        .to_string();
+        let response: DiffResponse = self
+            .with_retries(|| async {
+                let request = self
+                    .http
+                    .post(self.url("diff")?)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(body.clone());
+                let body = self.send(request).await?.bytes().await?;
+                serde_json::from_slice(&body).map_err(|e| Error::InvalidResponse(e.to_string()))
+            })
+            .await?;
+
+        let heatmap = response
+            .diff
+            .strip_prefix("data:image/png;base64,")
+            .and_then(|data| engine.decode(data).ok())
+            .ok_or_else(|| Error::InvalidResponse("diff is not a PNG data URI".to_string()))?;
+        Ok(ImageDiff {
+            width: response.width,
+            height: response.height,
+            differing_pixels: response.differing_pixels,
+            mismatch_ratio: response.mismatch_ratio,
+            max_delta: response.max_delta,
+            mean_delta: response.mean_delta,
+            psnr: response.psnr,
+            heatmap: heatmap.into(),
+        })
+    }
+
+    /// Computes histograms, mean luminance and clipping of an image on the
+    /// service. Retried according to the retry policy.
+    pub async fn histogram(&self, image: impl Into<Bytes>) -> Result<ExposureStats> {
+        let image = image.into();
+        self.with_retries(|| async {
+            let request = self
+                .http
+                .post(self.url("analyze/histogram")?)
+                .body(image.clone());
+            let body = self.send(request).await?.bytes().await?;
+            serde_json::from_slice(&body).map_err(|e| Error::InvalidResponse(e.to_string()))
+        })
+        .await
+    }
+
+    /// Reads the invisible watermark in an image, if it carries one under
+    /// the service's secret. Retried according to the retry policy.
+    pub async fn detect_watermark(&self, image: impl Into<Bytes>) -> Result<WatermarkDetection> {
+        let image = image.into();
+        self.with_retries(|| async {
+            let request = self
+                .http
+                .post(self.url("watermark/detect")?)
+                .body(image.clone());
+            let body = self.send(request).await?.bytes().await?;
+            serde_json::from_slice(&body).map_err(|e| Error::InvalidResponse(e.to_string()))
+        })
+        .await
+    }
+
+    /// Renders a favicon bundle from one image: a ZIP of `favicon.ico`,
+    /// the standard PNG icon sizes and, with `manifest`, a
+    /// `site.webmanifest`. Retried according to the retry policy.
+    pub async fn favicon(
+        &self,
+        image: impl Into<Bytes>,
+        manifest: Option<&WebManifest>,
+    ) -> Result<Bytes> {
+        self.icon_bundle("favicon", image.into(), manifest).await
+    }
+
+    /// Renders the icon set `set` (such as `pwa` or `apple`, or one from the
+    /// service's config) from one image, as a ZIP like [`Client::favicon`].
+    pub async fn icons(
+        &self,
+        set: &str,
+        image: impl Into<Bytes>,
+        manifest: Option<&WebManifest>,
+    ) -> Result<Bytes> {
+        self.icon_bundle(&format!("icons/{}", set), image.into(), manifest)
+            .await
+    }
+
+    async fn icon_bundle(
+        &self,
+        path: &str,
+        image: Bytes,
+        manifest: Option<&WebManifest>,
+    ) -> Result<Bytes> {
+        let mut headers = HeaderMap::new();
+        if let Some(manifest) = manifest {
+            if let Ok(name) = HeaderValue::from_str(&manifest.name) {
+                headers.insert("X-Manifest-Name", name);
+            }
+            if let Some(value) = manifest
+                .theme_color
+                .and_then(|rgba| HeaderValue::from_str(&options::hex_color(rgba)).ok())
+            {
+                headers.insert("X-Theme-Color", value);
+            }
+        }
+        self.with_retries(|| async {
+            let request = self
+                .http
+                .post(self.url(path)?)
+                .headers(headers.clone())
+                .body(image.clone());
+            Ok(self.send(request).await?.bytes().await?)
+        })
+        .await
+    }
+
+    /// Checks that the service is up.
+    pub async fn health(&self) -> Result<()> {
+        self.with_retries(|| async {
+            let request = self.http.get(self.url("health")?);
+            self.send(request).await.map(drop)
+        })
+        .await
+    }
+
+    async fn post_compress(&self, headers: HeaderMap, body: Body) -> Result<CompressedImage> {
+        let request = self
+            .http
+            .post(self.url("compress")?)
+            .headers(headers)
+            .body(body);
+        let response = self.send(request).await?;
+
+        let request_id = header_string(&response, REQUEST_ID_HEADER);
+        let content_type = header_string(&response, CONTENT_TYPE.as_str())
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        let skipped = header_string(&response, SKIPPED_HEADER);
+        let scaling = header_string(&response, SCALING_HEADER);
+        let placeholder = header_string(&response, PLACEHOLDER_DATA_HEADER);
+        let data = response.bytes().await?;
+
+        Ok(CompressedImage {
+            data,
+            content_type,
+            request_id,
+            skipped,
+            scaling,
+            placeholder,
+        })
+    }
+
+    /// Sends a request and turns error statuses into [`Error::Api`].
+    async fn send(&self, mut request: reqwest::RequestBuilder) -> Result<Response> {
+        request = request.header(USER_AGENT, &self.user_agent);
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let request_id = header_string(&response, REQUEST_ID_HEADER);
+        let body = response.bytes().await.unwrap_or_default();
+        Err(Error::from_response(status, request_id, &body))
+    }
+
+    async fn with_retries<T, F, Fut>(&self, mut attempt: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let mut retry = 0;
+        loop {
+            match attempt().await {
+                Err(e) if e.is_retryable() && retry < self.retry.max_retries => {
+                    retry += 1;
+                    tokio::time::sleep(self.retry.backoff(retry)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn url(&self, path: &str) -> Result<Url> {
+        self.base_url
+            .join(path)
+            .map_err(|e| Error::InvalidUrl(format!("{}{}: {}", self.base_url, path, e)))
+    }
+}
+
+fn header_string(response: &Response, name: &str) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
@@ -0,0 +1,747 @@
+// image-compressor-rust-service/client/src/options.rs
+
+use base64::Engine;
+use reqwest::header::{HeaderMap, HeaderValue};
+
+/// Header carrying the encoder quality.
+pub const QUALITY_HEADER: &str = "X-Compression-Quality";
+/// Header selecting whether the quality is perceptual or encoder-native.
+pub const QUALITY_SCALE_HEADER: &str = "X-Quality-Scale";
+/// Header selecting how animated inputs are handled.
+pub const ANIMATION_HEADER: &str = "X-Animation";
+/// Header carrying the requested output width.
+pub const WIDTH_HEADER: &str = "X-Width";
+/// Header carrying the requested output height.
+pub const HEIGHT_HEADER: &str = "X-Height";
+/// Header selecting the resize fit.
+pub const FIT_HEADER: &str = "X-Fit";
+/// Header choosing which part of the image survives crops.
+pub const GRAVITY_HEADER: &str = "X-Gravity";
+/// Header carrying the colour `pad` fills the box with.
+pub const BACKGROUND_HEADER: &str = "X-Background";
+/// Header selecting a colour filter.
+pub const FILTER_HEADER: &str = "X-Filter";
+/// Header selecting the output colour space.
+pub const COLOR_SPACE_HEADER: &str = "X-Color-Space";
+/// Header asking for a palette PNG with at most this many colours.
+pub const PALETTE_COLORS_HEADER: &str = "X-Palette-Colors";
+/// Header selecting how colours between palette entries are dithered.
+pub const DITHER_HEADER: &str = "X-Dither";
+/// Header asking for a blurred placeholder of the given width, or `true`.
+pub const PLACEHOLDER_HEADER: &str = "X-Placeholder";
+/// Header carrying the border width in pixels.
+pub const BORDER_WIDTH_HEADER: &str = "X-Border-Width";
+/// Header carrying the border colour.
+pub const BORDER_COLOR_HEADER: &str = "X-Border-Color";
+/// Header carrying the corner radius.
+pub const CORNER_RADIUS_HEADER: &str = "X-Corner-Radius";
+/// Header carrying the exact aspect ratio to crop to.
+pub const ASPECT_HEADER: &str = "X-Aspect";
+/// Header carrying the clockwise rotation in degrees.
+pub const ROTATE_HEADER: &str = "X-Rotate";
+/// Header allowing the output to be larger than the input.
+pub const ALLOW_UPSCALE_HEADER: &str = "X-Allow-Upscale";
+/// Header carrying the EXIF copyright notice to write.
+pub const METADATA_COPYRIGHT_HEADER: &str = "X-Metadata-Copyright";
+/// Header carrying EXIF text fields to write.
+pub const METADATA_EXIF_HEADER: &str = "X-Metadata-Exif";
+/// Header carrying a base64-encoded XMP packet to embed.
+pub const METADATA_XMP_HEADER: &str = "X-Metadata-Xmp";
+/// Header requesting reproducible, byte-identical output.
+pub const DETERMINISTIC_HEADER: &str = "X-Deterministic";
+/// Header choosing the scheduling class of the request.
+pub const PRIORITY_HEADER: &str = "X-Priority";
+/// Header carrying the original filename, percent-encoded.
+pub const FILENAME_HEADER: &str = "X-Filename";
+/// Header asking for the output as an attachment.
+pub const DOWNLOAD_HEADER: &str = "X-Download";
+/// Header carrying the output filename template.
+pub const FILENAME_TEMPLATE_HEADER: &str = "X-Filename-Template";
+/// Header carrying the size below which inputs are returned uncompressed.
+pub const SKIP_IF_SMALLER_THAN_HEADER: &str = "X-Skip-If-Smaller-Than";
+/// Header asking for the input back when compressing does not shrink it.
+pub const ONLY_IF_LARGER_HEADER: &str = "X-Only-If-Larger";
+/// Header asking for the encoded output even when it is not smaller.
+pub const FORCE_ENCODE_HEADER: &str = "X-Force-Encode";
+/// Header carrying the payload of an invisible watermark.
+pub const WATERMARK_HEADER: &str = "X-Watermark";
+/// Header carrying the largest output size, in bytes.
+pub const MAX_BYTES_HEADER: &str = "X-Max-Bytes";
+/// Header carrying the lowest quality a byte budget may lower to.
+pub const MIN_QUALITY_HEADER: &str = "X-Min-Quality";
+/// Header carrying the narrowest a byte budget may scale the output to.
+pub const MIN_WIDTH_HEADER: &str = "X-Min-Width";
+/// Header carrying the shortest a byte budget may scale the output to.
+pub const MIN_HEIGHT_HEADER: &str = "X-Min-Height";
+/// Header carrying how hard the encoder works for a smaller output.
+pub const EFFORT_HEADER: &str = "X-Effort";
+
+/// How the service interprets [`CompressOptions::quality`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QualityScale {
+    /// Comparable across output formats (the service default).
+    #[default]
+    Perceptual,
+    /// Passed to the encoder as is.
+    Native,
+}
+
+impl QualityScale {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Perceptual => "perceptual",
+            Self::Native => "native",
+        }
+    }
+}
+
+/// The colour space the service converts the output to, tagging it with
+/// the matching ICC profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    /// The wide gamut of Apple displays.
+    DisplayP3,
+    /// sRGB primaries with a linear transfer curve, for compositing.
+    LinearSrgb,
+}
+
+impl ColorSpace {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Srgb => "srgb",
+            Self::DisplayP3 => "display-p3",
+            Self::LinearSrgb => "linear-srgb",
+        }
+    }
+}
+
+/// How a palette output approximates colours it has no entry for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dither {
+    /// Nearest entry only; flat areas band.
+    None,
+    /// Error diffusion, best for photos.
+    #[default]
+    FloydSteinberg,
+    /// A fixed Bayer pattern, which compresses better and does not shimmer
+    /// between similar images.
+    Ordered,
+}
+
+impl Dither {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::FloydSteinberg => "floyd-steinberg",
+            Self::Ordered => "ordered",
+        }
+    }
+}
+
+/// How hard the encoder works for a smaller output. Only animated WebP
+/// and palette PNG outputs are affected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Effort {
+    Fast,
+    /// The service default.
+    #[default]
+    Balanced,
+    Max,
+}
+
+impl Effort {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Fast => "fast",
+            Self::Balanced => "balanced",
+            Self::Max => "max",
+        }
+    }
+}
+
+/// Scheduling class of a request while the service is busy. The service
+/// caps it at what the caller's API key is allowed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::High => "high",
+            Self::Normal => "normal",
+            Self::Low => "low",
+        }
+    }
+}
+
+/// What the service does with animated inputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Animation {
+    /// Keep only the first frame (the service default).
+    #[default]
+    FirstFrame,
+    /// Keep every frame and return an animated WebP.
+    Animate,
+}
+
+impl Animation {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::FirstFrame => "first-frame",
+            Self::Animate => "animate",
+        }
+    }
+}
+
+/// How the image is fitted into the requested width and height.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fit {
+    /// Fit inside the box, preserving the aspect ratio (the service default).
+    #[default]
+    Contain,
+    /// Cover the box, preserving the aspect ratio, and crop the overflow.
+    Cover,
+    /// Stretch to exactly the requested box.
+    Fill,
+    /// Fit inside the box and pad to exactly the box with the background.
+    Pad,
+}
+
+impl Fit {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Contain => "contain",
+            Self::Cover => "cover",
+            Self::Fill => "fill",
+            Self::Pad => "pad",
+        }
+    }
+}
+
+/// A colour filter. Colours are RGBA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// Maps luma onto a gradient from the first colour to the second.
+    Duotone([u8; 4], [u8; 4]),
+    /// Multiplies by a colour; its alpha is the strength.
+    Tint([u8; 4]),
+    /// Sepia toning, in percent.
+    Sepia(u8),
+}
+
+impl Filter {
+    fn to_header(self) -> String {
+        match self {
+            Self::Duotone(shadow, highlight) => {
+                format!("duotone:{},{}", hex_color(shadow), hex_color(highlight))
+            }
+            Self::Tint(color) => format!("tint:{}", hex_color(color)),
+            Self::Sepia(percent) => format!("sepia:{}", f64::from(percent.min(100)) / 100.0),
+        }
+    }
+}
+
+/// How round the output's corners are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CornerRadius {
+    Pixels(u32),
+    /// A percentage of the shorter side, up to 50; `50` turns a square
+    /// into a circle.
+    Percent(u8),
+}
+
+impl CornerRadius {
+    fn to_header(self) -> String {
+        match self {
+            Self::Pixels(px) => px.to_string(),
+            Self::Percent(percent) => format!("{}%", percent),
+        }
+    }
+}
+
+/// Which part of the image survives `cover` and aspect-ratio crops.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Gravity {
+    /// The service default.
+    #[default]
+    Center,
+    North,
+    South,
+    East,
+    West,
+    NorthEast,
+    NorthWest,
+    SouthEast,
+    SouthWest,
+    /// A point to keep, in ten-thousandths of the width and height from the
+    /// top left corner; see [`Gravity::focal`].
+    Focal {
+        x: u16,
+        y: u16,
+    },
+}
+
+impl Gravity {
+    /// A focal point from fractions of the width and height, clamped to 0-1.
+    pub fn focal(x: f64, y: f64) -> Self {
+        let basis = |v: f64| (v.clamp(0.0, 1.0) * 10_000.0).round() as u16;
+        Self::Focal {
+            x: basis(x),
+            y: basis(y),
+        }
+    }
+
+    fn to_header(self) -> String {
+        match self {
+            Self::Center => "center".to_string(),
+            Self::North => "north".to_string(),
+            Self::South => "south".to_string(),
+            Self::East => "east".to_string(),
+            Self::West => "west".to_string(),
+            Self::NorthEast => "north-east".to_string(),
+            Self::NorthWest => "north-west".to_string(),
+            Self::SouthEast => "south-east".to_string(),
+            Self::SouthWest => "south-west".to_string(),
+            Self::Focal { x, y } => {
+                format!("{},{}", f64::from(x) / 10_000.0, f64::from(y) / 10_000.0)
+            }
+        }
+    }
+}
+
+/// Options for a single `/compress` call.
+///
+/// Every field is optional; anything left unset is omitted from the request
+/// and the service default applies.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompressOptions {
+    /// Encoder quality, from 1 to 100. The service clamps values outside
+    /// that range.
+    pub quality: Option<u8>,
+    pub quality_scale: Option<QualityScale>,
+    pub animation: Option<Animation>,
+    /// Requested output width in pixels.
+    pub width: Option<u32>,
+    /// Requested output height in pixels.
+    pub height: Option<u32>,
+    pub fit: Option<Fit>,
+    pub gravity: Option<Gravity>,
+    /// The RGBA colour [`Fit::Pad`] fills the box with. The service pads
+    /// with white for JPEG output and transparency otherwise.
+    pub background: Option<[u8; 4]>,
+    pub filter: Option<Filter>,
+    /// Without it, the service passes pixels through untagged.
+    pub color_space: Option<ColorSpace>,
+    /// Return a PNG with a palette of at most this many colours (2 to 256)
+    /// instead of a JPEG.
+    pub palette: Option<(u16, Dither)>,
+    /// Also return a tiny blurred preview this many pixels wide (at most
+    /// 64); see [`CompressedImage::placeholder`](crate::CompressedImage::placeholder).
+    pub placeholder: Option<u32>,
+    /// A border of this many pixels, drawn inside the edges, and its RGBA
+    /// colour.
+    pub border: Option<(u32, [u8; 4])>,
+    /// Round the corners. Outside them, JPEG output shows the background
+    /// (white by default).
+    pub corner_radius: Option<CornerRadius>,
+    /// Crop to this aspect ratio, as `(width, height)`, e.g. `(16, 9)`.
+    /// With `width` or `height` set alone, the other side follows it.
+    pub aspect: Option<(u32, u32)>,
+    /// Rotate clockwise by this many degrees, `90`, `180` or `270`, before
+    /// resizing. JPEGs that are only rotated or cropped come back without
+    /// generation loss where the service can manage it.
+    pub rotate: Option<u16>,
+    /// Let `width` and `height` enlarge images smaller than them. The
+    /// service does not upscale by default.
+    pub allow_upscale: bool,
+    /// EXIF copyright notice (ASCII).
+    pub copyright: Option<String>,
+    /// EXIF text fields as `(name, value)` pairs, e.g. `("Artist", "Jane
+    /// Doe")`. Names and values must be ASCII and must not contain `;` or
+    /// `=`.
+    pub exif: Vec<(String, String)>,
+    /// XMP packet to embed.
+    pub xmp: Option<Vec<u8>>,
+    /// Ask for output that is byte-identical for identical input and
+    /// options, e.g. for content-addressed storage.
+    pub deterministic: bool,
+    pub priority: Option<Priority>,
+    /// The input's original filename, used for the output filename.
+    pub filename: Option<String>,
+    /// Ask for `Content-Disposition: attachment`.
+    pub download: bool,
+    /// Output filename template, e.g. `{name}-{width}x{height}.{ext}`.
+    pub filename_template: Option<String>,
+    /// Inputs smaller than this many bytes that need no resizing are
+    /// returned as they are.
+    pub skip_if_smaller_than: Option<u64>,
+    /// Return the input as it is when the compressed output is not smaller.
+    pub only_if_larger: bool,
+    /// Return the encoded output even when it is not smaller than an input
+    /// the service would otherwise pass through.
+    pub force_encode: bool,
+    /// Hide this payload in the output as an invisible watermark. Needs a
+    /// watermark secret on the service; read back with
+    /// [`Client::detect_watermark`](crate::Client::detect_watermark).
+    pub watermark: Option<u32>,
+    /// Keep the output within this many bytes, lowering the quality and,
+    /// when that is not enough, the dimensions. JPEG stills only.
+    pub max_bytes: Option<u64>,
+    /// The lowest quality [`max_bytes`](Self::max_bytes) may lower to.
+    pub min_quality: Option<u8>,
+    /// The smallest `(width, height)` [`max_bytes`](Self::max_bytes) may
+    /// scale the output down to.
+    pub min_dimensions: Option<(u32, u32)>,
+    /// Encoder effort; the service's configured default when unset.
+    pub effort: Option<Effort>,
+}
+
+impl CompressOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn quality(mut self, quality: u8) -> Self {
+        self.quality = Some(quality);
+        self
+    }
+
+    pub fn quality_scale(mut self, scale: QualityScale) -> Self {
+        self.quality_scale = Some(scale);
+        self
+    }
+
+    pub fn animation(mut self, animation: Animation) -> Self {
+        self.animation = Some(animation);
+        self
+    }
+
+    pub fn width(mut self, width: u32) -> Self {
+        self.width = Some(width);
+        self
+    }
+
+    pub fn height(mut self, height: u32) -> Self {
+        self.height = Some(height);
+        self
+    }
+
+    pub fn fit(mut self, fit: Fit) -> Self {
+        self.fit = Some(fit);
+        self
+    }
+
+    pub fn gravity(mut self, gravity: Gravity) -> Self {
+        self.gravity = Some(gravity);
+        self
+    }
+
+    pub fn background(mut self, rgba: [u8; 4]) -> Self {
+        self.background = Some(rgba);
+        self
+    }
+
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    pub fn color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = Some(color_space);
+        self
+    }
+
+    pub fn palette(mut self, colors: u16, dither: Dither) -> Self {
+        self.palette = Some((colors, dither));
+        self
+    }
+
+    pub fn placeholder(mut self, width: u32) -> Self {
+        self.placeholder = Some(width);
+        self
+    }
+
+    pub fn border(mut self, width: u32, rgba: [u8; 4]) -> Self {
+        self.border = Some((width, rgba));
+        self
+    }
+
+    pub fn corner_radius(mut self, radius: CornerRadius) -> Self {
+        self.corner_radius = Some(radius);
+        self
+    }
+
+    pub fn aspect(mut self, width: u32, height: u32) -> Self {
+        self.aspect = Some((width, height));
+        self
+    }
+
+    pub fn rotate(mut self, degrees: u16) -> Self {
+        self.rotate = Some(degrees);
+        self
+    }
+
+    pub fn allow_upscale(mut self, allow_upscale: bool) -> Self {
+        self.allow_upscale = allow_upscale;
+        self
+    }
+
+    pub fn copyright(mut self, copyright: impl Into<String>) -> Self {
+        self.copyright = Some(copyright.into());
+        self
+    }
+
+    /// Adds an EXIF text field.
+    pub fn exif(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.exif.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn xmp(mut self, xmp: impl Into<Vec<u8>>) -> Self {
+        self.xmp = Some(xmp.into());
+        self
+    }
+
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn filename(mut self, filename: impl Into<String>) -> Self {
+        self.filename = Some(filename.into());
+        self
+    }
+
+    pub fn download(mut self, download: bool) -> Self {
+        self.download = download;
+        self
+    }
+
+    pub fn filename_template(mut self, template: impl Into<String>) -> Self {
+        self.filename_template = Some(template.into());
+        self
+    }
+
+    pub fn skip_if_smaller_than(mut self, bytes: u64) -> Self {
+        self.skip_if_smaller_than = Some(bytes);
+        self
+    }
+
+    pub fn only_if_larger(mut self, only_if_larger: bool) -> Self {
+        self.only_if_larger = only_if_larger;
+        self
+    }
+
+    pub fn force_encode(mut self, force_encode: bool) -> Self {
+        self.force_encode = force_encode;
+        self
+    }
+
+    pub fn watermark(mut self, payload: u32) -> Self {
+        self.watermark = Some(payload);
+        self
+    }
+
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn min_quality(mut self, quality: u8) -> Self {
+        self.min_quality = Some(quality);
+        self
+    }
+
+    pub fn min_dimensions(mut self, width: u32, height: u32) -> Self {
+        self.min_dimensions = Some((width, height));
+        self
+    }
+
+    pub fn effort(mut self, effort: Effort) -> Self {
+        self.effort = Some(effort);
+        self
+    }
+
+    /// Renders the options as request headers. Metadata values that cannot
+    /// be sent in a header are left out.
+    pub fn to_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(quality) = self.quality {
+            headers.insert(QUALITY_HEADER, HeaderValue::from(u16::from(quality)));
+        }
+        if let Some(scale) = self.quality_scale {
+            headers.insert(
+                QUALITY_SCALE_HEADER,
+                HeaderValue::from_static(scale.as_str()),
+            );
+        }
+        if let Some(animation) = self.animation {
+            headers.insert(
+                ANIMATION_HEADER,
+                HeaderValue::from_static(animation.as_str()),
+            );
+        }
+        if let Some(width) = self.width {
+            headers.insert(WIDTH_HEADER, HeaderValue::from(width));
+        }
+        if let Some(height) = self.height {
+            headers.insert(HEIGHT_HEADER, HeaderValue::from(height));
+        }
+        if let Some(fit) = self.fit {
+            headers.insert(FIT_HEADER, HeaderValue::from_static(fit.as_str()));
+        }
+        if let Some(value) = self
+            .gravity
+            .and_then(|g| HeaderValue::from_str(&g.to_header()).ok())
+        {
+            headers.insert(GRAVITY_HEADER, value);
+        }
+        if let Some(value) = self
+            .background
+            .and_then(|rgba| HeaderValue::from_str(&hex_color(rgba)).ok())
+        {
+            headers.insert(BACKGROUND_HEADER, value);
+        }
+        if let Some(value) = self
+            .filter
+            .and_then(|f| HeaderValue::from_str(&f.to_header()).ok())
+        {
+            headers.insert(FILTER_HEADER, value);
+        }
+        if let Some(color_space) = self.color_space {
+            headers.insert(
+                COLOR_SPACE_HEADER,
+                HeaderValue::from_static(color_space.as_str()),
+            );
+        }
+        if let Some((colors, dither)) = self.palette {
+            headers.insert(PALETTE_COLORS_HEADER, HeaderValue::from(colors));
+            headers.insert(DITHER_HEADER, HeaderValue::from_static(dither.as_str()));
+        }
+        if let Some(width) = self.placeholder {
+            headers.insert(PLACEHOLDER_HEADER, HeaderValue::from(width));
+        }
+        if let Some((width, rgba)) = self.border {
+            headers.insert(BORDER_WIDTH_HEADER, HeaderValue::from(width));
+            if let Ok(value) = HeaderValue::from_str(&hex_color(rgba)) {
+                headers.insert(BORDER_COLOR_HEADER, value);
+            }
+        }
+        if let Some(value) = self
+            .corner_radius
+            .and_then(|r| HeaderValue::from_str(&r.to_header()).ok())
+        {
+            headers.insert(CORNER_RADIUS_HEADER, value);
+        }
+        if let Some((width, height)) = self.aspect {
+            if let Ok(value) = HeaderValue::from_str(&format!("{}:{}", width, height)) {
+                headers.insert(ASPECT_HEADER, value);
+            }
+        }
+        if let Some(degrees) = self.rotate {
+            headers.insert(ROTATE_HEADER, HeaderValue::from(degrees));
+        }
+        if self.allow_upscale {
+            headers.insert(ALLOW_UPSCALE_HEADER, HeaderValue::from_static("true"));
+        }
+        if let Some(value) = self
+            .copyright
+            .as_deref()
+            .and_then(|c| HeaderValue::from_str(c).ok())
+        {
+            headers.insert(METADATA_COPYRIGHT_HEADER, value);
+        }
+        if !self.exif.is_empty() {
+            let pairs = self
+                .exif
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join(";");
+            if let Ok(value) = HeaderValue::from_str(&pairs) {
+                headers.insert(METADATA_EXIF_HEADER, value);
+            }
+        }
+        if let Some(xmp) = &self.xmp {
+            let encoded = base64::engine::general_purpose::STANDARD.encode(xmp);
+            if let Ok(value) = HeaderValue::from_str(&encoded) {
+                headers.insert(METADATA_XMP_HEADER, value);
+            }
+        }
+        if let Some(priority) = self.priority {
+            headers.insert(PRIORITY_HEADER, HeaderValue::from_static(priority.as_str()));
+        }
+        if self.deterministic {
+            headers.insert(DETERMINISTIC_HEADER, HeaderValue::from_static("true"));
+        }
+        if let Some(value) = self
+            .filename
+            .as_deref()
+            .and_then(|f| HeaderValue::from_str(&percent_encode(f)).ok())
+        {
+            headers.insert(FILENAME_HEADER, value);
+        }
+        if self.download {
+            headers.insert(DOWNLOAD_HEADER, HeaderValue::from_static("true"));
+        }
+        if let Some(value) = self
+            .filename_template
+            .as_deref()
+            .and_then(|t| HeaderValue::from_str(&percent_encode(t)).ok())
+        {
+            headers.insert(FILENAME_TEMPLATE_HEADER, value);
+        }
+        if let Some(bytes) = self.skip_if_smaller_than {
+            headers.insert(SKIP_IF_SMALLER_THAN_HEADER, HeaderValue::from(bytes));
+        }
+        if self.only_if_larger {
+            headers.insert(ONLY_IF_LARGER_HEADER, HeaderValue::from_static("true"));
+        }
+        if self.force_encode {
+            headers.insert(FORCE_ENCODE_HEADER, HeaderValue::from_static("true"));
+        }
+        if let Some(payload) = self.watermark {
+            headers.insert(WATERMARK_HEADER, HeaderValue::from(payload));
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            headers.insert(MAX_BYTES_HEADER, HeaderValue::from(max_bytes));
+        }
+        if let Some(quality) = self.min_quality {
+            headers.insert(MIN_QUALITY_HEADER, HeaderValue::from(u16::from(quality)));
+        }
+        if let Some((width, height)) = self.min_dimensions {
+            headers.insert(MIN_WIDTH_HEADER, HeaderValue::from(width));
+            headers.insert(MIN_HEIGHT_HEADER, HeaderValue::from(height));
+        }
+        if let Some(effort) = self.effort {
+            headers.insert(EFFORT_HEADER, HeaderValue::from_static(effort.as_str()));
+        }
+        headers
+    }
+}
+
+/// Escapes `%`, control and non-ASCII bytes, as the filename headers expect.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b' '..=b'~' if b != b'%' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+pub(crate) fn hex_color([r, g, b, a]: [u8; 4]) -> String {
+    format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+}
@@ -0,0 +1,58 @@
+// image-compressor-rust-service/client/src/retry.rs
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// When and how often failed requests are retried.
+///
+/// Connection errors, timeouts, `429` and `5xx` responses are retried with
+/// exponential backoff; every other failure is returned immediately.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt. `0` disables retrying.
+    pub max_retries: u32,
+    /// Delay before the first retry. Doubles on every further retry.
+    pub initial_backoff: Duration,
+    /// Upper bound for a single delay.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// The delay before retry number `retry` (starting at 1), with up to 25%
+    /// jitter so that many clients failing together do not retry in lockstep.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(31);
+        let base = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+        let jitter = base.mul_f64(jitter_fraction() * 0.25);
+        base.saturating_sub(jitter)
+    }
+}
+
+/// A cheap value in `[0, 1)`; good enough to spread retries out.
+fn jitter_fraction() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    f64::from(nanos % 1000) / 1000.0
+}
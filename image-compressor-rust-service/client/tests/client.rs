@@ -0,0 +1,206 @@
+// image-compressor-rust-service/client/tests/client.rs
+
+//! End-to-end tests against the real service router on a loopback port.
+
+use axum::{http::StatusCode, routing::get, Router};
+use image_compressor_client::{Client, CompressOptions, Error, Fit, RetryPolicy, WebManifest};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::server::{self, AppState};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("../tests/fixtures")
+        .join(name)
+}
+
+/// Serves `router` on an ephemeral port and returns its base URL.
+async fn serve(router: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+async fn service() -> String {
+    let state = AppState::new(
+        Config::default(),
+        PrometheusBuilder::new().build_recorder().handle(),
+    );
+    serve(server::router(state)).await
+}
+
+#[tokio::test]
+async fn compresses_file_with_typed_options() {
+    let client = Client::new(service().await).unwrap();
+    let options = CompressOptions::new()
+        .quality(70)
+        .width(64)
+        .fit(Fit::Contain);
+
+    let image = client
+        .compress_file(fixture_path("landscape.jpg"), &options)
+        .await
+        .unwrap();
+
+    assert_eq!(image.content_type, "image/jpeg");
+    assert!(image.request_id.is_some());
+    assert_eq!(image.scaling.as_deref(), Some("down"));
+    let decoded = image::load_from_memory(&image.data).unwrap();
+    assert_eq!(decoded.width(), 64);
+}
+
+#[tokio::test]
+async fn surfaces_structured_api_errors() {
+    let client = Client::new(service().await).unwrap();
+
+    let err = client
+        .compress(&b"not an image"[..], &CompressOptions::new())
+        .await
+        .unwrap_err();
+
+    match err {
+        Error::Api {
+            status,
+            code,
+            request_id,
+            ..
+        } => {
+            assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            assert_eq!(code, "unsupported_media_type");
+            assert!(request_id.is_some());
+        }
+        other => panic!("expected an API error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn retries_server_errors_with_backoff() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let flaky = Router::new().route(
+        "/health",
+        get(move || {
+            let counter = counter.clone();
+            async move {
+                if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                    StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    StatusCode::OK
+                }
+            }
+        }),
+    );
+    let retry = RetryPolicy {
+        max_retries: 3,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(5),
+    };
+    let client = Client::builder(serve(flaky).await)
+        .retry_policy(retry)
+        .build()
+        .unwrap();
+
+    client.health().await.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn small_inputs_come_back_untouched() {
+    let client = Client::new(service().await).unwrap();
+    let input = std::fs::read(fixture_path("landscape.jpg")).unwrap();
+
+    let image = client
+        .compress(
+            input.clone(),
+            &CompressOptions::new().skip_if_smaller_than(1 << 20),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(image.skipped.as_deref(), Some("small-enough"));
+    assert_eq!(image.data, input);
+}
+
+#[tokio::test]
+async fn diffs_images() {
+    let client = Client::new(service().await).unwrap();
+    let baseline = std::fs::read(fixture_path("landscape.jpg")).unwrap();
+    let compressed = client
+        .compress(baseline.clone(), &CompressOptions::new().quality(20))
+        .await
+        .unwrap();
+
+    let same = client.diff(&baseline, &baseline, 0).await.unwrap();
+    assert_eq!(same.differing_pixels, 0);
+    assert_eq!(same.psnr, None);
+
+    let diff = client.diff(&baseline, &compressed.data, 0).await.unwrap();
+    assert_eq!((diff.width, diff.height), (160, 96));
+    assert!(diff.differing_pixels > 0);
+    assert!(diff.psnr.is_some_and(|psnr| psnr > 20.0));
+    assert!(diff.heatmap.starts_with(b"\x89PNG"));
+}
+
+#[tokio::test]
+async fn analyzes_histograms() {
+    let client = Client::new(service().await).unwrap();
+    let image = std::fs::read(fixture_path("landscape.jpg")).unwrap();
+    let stats = client.histogram(image).await.unwrap();
+    assert_eq!(stats.pixels, 160 * 96);
+    assert_eq!(stats.histogram.luminance.len(), 256);
+    assert!((0.0..=1.0).contains(&stats.mean_luminance));
+}
+
+#[tokio::test]
+async fn renders_favicon_bundles() {
+    let client = Client::new(service().await).unwrap();
+    let image = std::fs::read(fixture_path("landscape.jpg")).unwrap();
+    let manifest = WebManifest {
+        name: "Storage".to_string(),
+        theme_color: Some([26, 26, 64, 255]),
+    };
+    let archive = client.favicon(image, Some(&manifest)).await.unwrap();
+    assert!(archive.starts_with(b"PK\x03\x04"));
+    let manifest_at = archive
+        .windows(16)
+        .position(|w| w == b"site.webmanifest")
+        .unwrap();
+    assert!(archive[manifest_at..].windows(7).any(|w| w == b"#1a1a40"));
+}
+
+#[tokio::test]
+async fn renders_icon_sets() {
+    let client = Client::new(service().await).unwrap();
+    let image = std::fs::read(fixture_path("landscape.jpg")).unwrap();
+    let archive = client.icons("pwa", image.clone(), None).await.unwrap();
+    assert!(archive
+        .windows(25)
+        .any(|w| w == b"maskable-icon-512x512.png"));
+    assert!(matches!(
+        client.icons("nope", image, None).await,
+        Err(Error::Api { code, .. }) if code == "unknown_icon_set"
+    ));
+}
+
+#[tokio::test]
+async fn watermarks_round_trip() {
+    let mut config = Config::default();
+    config.watermark.secret = Some("review-copies".to_string());
+    let state = AppState::new(config, PrometheusBuilder::new().build_recorder().handle());
+    let client = Client::new(serve(server::router(state)).await).unwrap();
+    let image = std::fs::read(fixture_path("landscape.jpg")).unwrap();
+
+    let marked = client
+        .compress(image.clone(), &CompressOptions::new().watermark(0xBEEF))
+        .await
+        .unwrap();
+    let detection = client.detect_watermark(marked.data).await.unwrap();
+    assert!(detection.found);
+    assert_eq!(detection.payload, Some(0xBEEF));
+    assert!(!client.detect_watermark(image).await.unwrap().found);
+}
@@ -0,0 +1,25 @@
+// image-compressor-rust-service/fuzz/fuzz_targets/compress.rs
+
+#![no_main]
+
+use image_compressor_rust_service::{compress_image, AnimationMode, CompressionOptions};
+use libfuzzer_sys::fuzz_target;
+
+// The first byte picks the quality and animation mode, the rest is the image.
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, input)) = data.split_first() else {
+        return;
+    };
+    let options = CompressionOptions {
+        quality: selector % 100 + 1,
+        quality_explicit: true,
+        animation: if selector & 0x80 == 0 {
+            AnimationMode::FirstFrame
+        } else {
+            AnimationMode::Animate
+        },
+        ..CompressionOptions::default()
+    };
+    // Errors are expected for malformed inputs; only panics and crashes matter.
+    let _ = compress_image(input, &options);
+});
@@ -0,0 +1,50 @@
+// image-compressor-rust-service/fuzz/fuzz_targets/options.rs
+
+#![no_main]
+
+use axum::body::Bytes;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::server::options::{Options, QUERY_OPTIONS};
+use image_compressor_rust_service::CompressionOptions;
+use libfuzzer_sys::fuzz_target;
+
+// The input is split on newlines into values, assigned round-robin to every
+// option of `QUERY_OPTIONS`: first as its header, then as its query
+// parameter. Empty values leave the option unset.
+fuzz_target!(|data: &[u8]| {
+    let mut headers = HeaderMap::new();
+    let mut query = String::new();
+    for (i, value) in data.split(|&b| b == b'\n').enumerate() {
+        let (parameter, header) = QUERY_OPTIONS[i % QUERY_OPTIONS.len()];
+        if value.is_empty() {
+            continue;
+        }
+        if i / QUERY_OPTIONS.len() % 2 == 0 {
+            if let Ok(value) = HeaderValue::from_bytes(value) {
+                headers.append(HeaderName::from_bytes(header.as_bytes()).unwrap(), value);
+            }
+        } else {
+            query.push_str(&format!("&{}=", parameter));
+            for byte in value {
+                query.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+
+    let mut options = CompressionOptions::default();
+    let _ = options.read_headers(&headers);
+    assert!((1..=100).contains(&options.quality));
+
+    let uri: Uri = format!("/compress?{}", query).parse().unwrap();
+    for lenient_options in [false, true] {
+        let config = Config {
+            lenient_options,
+            ..Config::default()
+        };
+        match Options::resolve(&config, &uri, headers.clone(), Bytes::new()) {
+            Ok(resolved) => assert!((1..=100).contains(&resolved.options.quality)),
+            Err(rejection) => assert_eq!(rejection.error.status, StatusCode::BAD_REQUEST),
+        }
+    }
+});
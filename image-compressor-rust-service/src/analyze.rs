@@ -0,0 +1,115 @@
+// image-compressor-rust-service/src/analyze.rs
+
+//! Image statistics for client-side decisions, such as auto-exposure,
+//! without sending pixels back.
+
+use crate::decode::probe_dimensions;
+use crate::formats::{InputFormat, SniffedFormat};
+use crate::quality;
+use anyhow::Result;
+use image::DynamicImage;
+use serde::Serialize;
+
+/// Per-channel histograms with 256 bins each. Luminance uses the Rec. 709
+/// weights. Fully transparent pixels are not counted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Histogram {
+    pub red: Vec<u64>,
+    pub green: Vec<u64>,
+    pub blue: Vec<u64>,
+    pub luminance: Vec<u64>,
+}
+
+/// Share of counted pixels, in percent, with a channel at either extreme.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Clipping {
+    /// Pixels with at least one channel at 0.
+    pub shadows_percent: f64,
+    /// Pixels with at least one channel at 255.
+    pub highlights_percent: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExposureStats {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels counted: those that are not fully transparent.
+    pub pixels: u64,
+    pub histogram: Histogram,
+    /// Mean luminance, from 0 (black) to 1 (white).
+    pub mean_luminance: f64,
+    pub clipping: Clipping,
+}
+
+/// Computes histograms, mean luminance and clipping over every pixel.
+pub fn exposure(image: &DynamicImage) -> ExposureStats {
+    let mut histogram = Histogram {
+        red: vec![0; 256],
+        green: vec![0; 256],
+        blue: vec![0; 256],
+        luminance: vec![0; 256],
+    };
+    let (mut pixels, mut shadows, mut highlights) = (0u64, 0u64, 0u64);
+    let mut luminance_sum = 0.0;
+    for pixel in image.to_rgba8().pixels() {
+        let [r, g, b, a] = pixel.0;
+        if a == 0 {
+            continue;
+        }
+        pixels += 1;
+        histogram.red[usize::from(r)] += 1;
+        histogram.green[usize::from(g)] += 1;
+        histogram.blue[usize::from(b)] += 1;
+        let luminance = 0.2126 * f64::from(r) + 0.7152 * f64::from(g) + 0.0722 * f64::from(b);
+        histogram.luminance[luminance.round() as usize] += 1;
+        luminance_sum += luminance;
+        shadows += u64::from(r == 0 || g == 0 || b == 0);
+        highlights += u64::from(r == 255 || g == 255 || b == 255);
+    }
+
+    let percent = |count: u64| count as f64 * 100.0 / pixels.max(1) as f64;
+    ExposureStats {
+        width: image.width(),
+        height: image.height(),
+        pixels,
+        histogram,
+        mean_luminance: luminance_sum / 255.0 / pixels.max(1) as f64,
+        clipping: Clipping {
+            shadows_percent: percent(shadows),
+            highlights_percent: percent(highlights),
+        },
+    }
+}
+
+/// What an input is, read from its headers without decoding it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Inspection {
+    pub format: InputFormat,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: usize,
+    /// The quality a JPEG input was saved at, estimated from its
+    /// quantization tables (see [`quality::estimate_jpeg_quality`]).
+    pub jpeg_quality: Option<u8>,
+    /// Whether the declared `Content-Type` names the detected format, or
+    /// `None` when no image type was declared (see [`SniffedFormat`]).
+    pub content_type_matches: Option<bool>,
+}
+
+/// Reads the format, dimensions and, for JPEGs, estimated quality of an
+/// input in a format the service decodes, and whether `content_type`, the
+/// type it was declared as, matches it.
+pub fn inspect(data: &[u8], format: InputFormat, content_type: Option<&str>) -> Result<Inspection> {
+    let (width, height) = probe_dimensions(data)?;
+    Ok(Inspection {
+        format,
+        width,
+        height,
+        bytes: data.len(),
+        jpeg_quality: match format {
+            InputFormat::Jpeg => quality::estimate_jpeg_quality(data),
+            _ => None,
+        },
+        content_type_matches: SniffedFormat::of(data, content_type).matches_content_type,
+    })
+}
@@ -0,0 +1,154 @@
+// image-compressor-rust-service/src/animation.rs
+
+//! Trimming animated outputs: frame limits, sampling, duration caps and
+//! poster frames.
+//!
+//! Encoding an animated WebP costs about as much per frame as a still, so
+//! requests can keep fewer frames, and the server caps how many an output
+//! keeps and how long it plays whatever the request asks.
+
+use image::{Delay, Frame};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Server-side caps on animated outputs (`[animation]` in the config file).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnimationConfig {
+    /// Most frames an animated output keeps, `0` for no cap.
+    pub max_frames: u32,
+    /// Longest an animated output plays, in milliseconds, `0` for no cap.
+    pub max_duration_ms: u64,
+}
+
+impl Default for AnimationConfig {
+    fn default() -> Self {
+        Self {
+            max_frames: 1000,
+            max_duration_ms: 300_000,
+        }
+    }
+}
+
+/// Which frames of an animation a request keeps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FrameSelection {
+    /// Keep at most this many frames.
+    pub max_frames: Option<u32>,
+    /// Keep every `step`-th frame, starting with the first.
+    pub step: Option<u32>,
+    /// Stop the animation after this many milliseconds.
+    pub max_duration_ms: Option<u64>,
+    /// Encode only this frame, counted from 0, as a still image. Past the
+    /// last frame, the last one is used.
+    pub poster: Option<u32>,
+}
+
+impl FrameSelection {
+    /// The frame cap of the request and the server, whichever is lower.
+    fn max_frames(&self, config: &AnimationConfig) -> Option<usize> {
+        let server = Some(config.max_frames).filter(|&max| max > 0);
+        self.max_frames
+            .into_iter()
+            .chain(server)
+            .min()
+            .map(|max| max as usize)
+    }
+
+    /// The duration cap of the request and the server, whichever is lower.
+    fn max_duration(&self, config: &AnimationConfig) -> Option<Duration> {
+        let server = Some(config.max_duration_ms).filter(|&max| max > 0);
+        self.max_duration_ms
+            .into_iter()
+            .chain(server)
+            .min()
+            .map(Duration::from_millis)
+    }
+
+    /// The frames [`select`] or [`poster`] can use for this selection, so
+    /// that decoding stops once the caps are reached.
+    pub fn limit(&self, config: &AnimationConfig) -> FrameLimit {
+        if let Some(index) = self.poster {
+            return FrameLimit {
+                frames: (index as usize).saturating_add(1),
+                duration: None,
+            };
+        }
+        let step = self.step.unwrap_or(1).max(1) as usize;
+        FrameLimit {
+            frames: match self.max_frames(config) {
+                Some(max) => max.max(1).saturating_mul(step),
+                None => usize::MAX,
+            },
+            duration: self.max_duration(config),
+        }
+    }
+}
+
+/// How far into an animation decoding has to go: at most `frames` frames,
+/// and none that would start once the ones before play for `duration`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLimit {
+    /// Most frames to decode.
+    pub frames: usize,
+    /// How long the decoded frames may play, if capped.
+    pub duration: Option<Duration>,
+}
+
+impl FrameLimit {
+    /// Every frame of the animation.
+    pub const NONE: Self = Self {
+        frames: usize::MAX,
+        duration: None,
+    };
+}
+
+/// The frames of an animation `selection` keeps, within the `config` caps.
+///
+/// Every `step`-th frame stands for the frames dropped after it and lasts
+/// as long as all of them, so the animation keeps its pace. Frames that
+/// would start after the duration cap are dropped and the last one is
+/// shortened to end on it. The first frame is always kept.
+pub fn select(
+    frames: Vec<Frame>,
+    selection: &FrameSelection,
+    config: &AnimationConfig,
+) -> Vec<Frame> {
+    let step = selection.step.unwrap_or(1).max(1) as usize;
+    let max_frames = selection.max_frames(config).unwrap_or(usize::MAX).max(1);
+    let max_duration = selection.max_duration(config);
+
+    let mut selected = Vec::new();
+    let mut elapsed = Duration::ZERO;
+    let mut frames = frames.into_iter();
+    while let Some(frame) = frames.next() {
+        if selected.len() == max_frames || max_duration.is_some_and(|max| elapsed >= max) {
+            break;
+        }
+        let mut duration = Duration::from(frame.delay());
+        for _ in 1..step {
+            match frames.next() {
+                Some(dropped) => duration += Duration::from(dropped.delay()),
+                None => break,
+            }
+        }
+        if let Some(max) = max_duration {
+            duration = duration.min(max.saturating_sub(elapsed));
+        }
+        elapsed += duration;
+        let (left, top) = (frame.left(), frame.top());
+        let delay = Delay::from_saturating_duration(duration);
+        selected.push(Frame::from_parts(frame.into_buffer(), left, top, delay));
+    }
+    selected
+}
+
+/// The poster frame `selection` asks for, if any.
+pub fn poster(mut frames: Vec<Frame>, selection: &FrameSelection) -> Option<Frame> {
+    let index = selection.poster? as usize;
+    if index < frames.len() {
+        Some(frames.swap_remove(index))
+    } else {
+        frames.pop()
+    }
+}
@@ -0,0 +1,219 @@
+// image-compressor-rust-service/src/archive.rs
+
+//! Minimal ZIP archives: writing stored archives for bundles such as
+//! [`crate::favicon`], and reading the stored or deflated archives clients
+//! upload image sets in.
+//!
+//! Only what those need is supported: no ZIP64, encryption or multi-disk
+//! archives. Entries are found through the central directory, so data
+//! trailing or preceding the archive is ignored.
+
+use anyhow::{bail, Context, Result};
+use flate2::read::DeflateDecoder;
+use std::io::Read;
+
+/// The "end of central directory" record, without its comment.
+const EOCD_LEN: usize = 22;
+
+/// A file in an archive being read.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    /// Uncompressed size, as the archive claims it.
+    pub size: u64,
+    method: u16,
+    crc: u32,
+    compressed_size: u64,
+    header_offset: usize,
+}
+
+/// A ZIP archive read from memory. Directory entries are left out.
+#[derive(Debug)]
+pub struct Archive<'a> {
+    data: &'a [u8],
+    entries: Vec<Entry>,
+}
+
+impl<'a> Archive<'a> {
+    /// Reads the central directory of `data`.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let reader = Reader(data);
+        // The record ends the file, followed by a comment of up to 64 KiB.
+        let earliest = data.len().saturating_sub(EOCD_LEN + usize::from(u16::MAX));
+        let eocd = (earliest..=data.len().saturating_sub(EOCD_LEN))
+            .rev()
+            .find(|&at| data[at..].starts_with(b"PK\x05\x06"))
+            .context("Not a ZIP archive.")?;
+        let count = reader.u16(eocd + 10)?;
+        let mut at = reader.u32(eocd + 16)? as usize;
+        if count == u16::MAX || at == u32::MAX as usize {
+            bail!("ZIP64 archives are not supported.");
+        }
+
+        let mut entries = Vec::with_capacity(usize::from(count));
+        for _ in 0..count {
+            if !data.get(at..).is_some_and(|d| d.starts_with(b"PK\x01\x02")) {
+                bail!("The ZIP central directory is damaged.");
+            }
+            let flags = reader.u16(at + 8)?;
+            if flags & 1 != 0 {
+                bail!("Encrypted ZIP entries are not supported.");
+            }
+            let name_len = usize::from(reader.u16(at + 28)?);
+            let extra_len = usize::from(reader.u16(at + 30)?);
+            let comment_len = usize::from(reader.u16(at + 32)?);
+            let name = data
+                .get(at + 46..at + 46 + name_len)
+                .context("The ZIP central directory is damaged.")?;
+            // Bit 11 marks UTF-8 names; others are usually ASCII too.
+            let name = String::from_utf8_lossy(name).into_owned();
+            let entry = Entry {
+                size: u64::from(reader.u32(at + 24)?),
+                method: reader.u16(at + 10)?,
+                crc: reader.u32(at + 16)?,
+                compressed_size: u64::from(reader.u32(at + 20)?),
+                header_offset: reader.u32(at + 42)? as usize,
+                name,
+            };
+            if entry.size == u64::from(u32::MAX) || entry.compressed_size == u64::from(u32::MAX) {
+                bail!("ZIP64 entries are not supported.");
+            }
+            if !entry.name.ends_with('/') {
+                entries.push(entry);
+            }
+            at += 46 + name_len + extra_len + comment_len;
+        }
+        Ok(Self { data, entries })
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The contents of `entry`, refusing entries larger than `max_size`
+    /// however small their compressed data is.
+    pub fn read(&self, entry: &Entry, max_size: u64) -> Result<Vec<u8>> {
+        if entry.size > max_size {
+            bail!(
+                "'{}' is {} bytes, over the limit of {}.",
+                entry.name,
+                entry.size,
+                max_size
+            );
+        }
+        let reader = Reader(self.data);
+        let at = entry.header_offset;
+        if !self
+            .data
+            .get(at..)
+            .is_some_and(|d| d.starts_with(b"PK\x03\x04"))
+        {
+            bail!("The ZIP entry '{}' is damaged.", entry.name);
+        }
+        let start = at + 30 + usize::from(reader.u16(at + 26)?) + usize::from(reader.u16(at + 28)?);
+        let compressed = self
+            .data
+            .get(start..start + entry.compressed_size as usize)
+            .with_context(|| format!("The ZIP entry '{}' is truncated.", entry.name))?;
+        let data = match entry.method {
+            0 => compressed.to_vec(),
+            8 => {
+                let mut data = Vec::with_capacity(entry.size as usize);
+                // Read one byte past the claimed size to catch entries
+                // that inflate to more than they say.
+                DeflateDecoder::new(compressed)
+                    .take(entry.size + 1)
+                    .read_to_end(&mut data)
+                    .with_context(|| format!("Failed to inflate '{}'.", entry.name))?;
+                data
+            }
+            method => bail!(
+                "'{}' uses ZIP compression method {}; only stored and deflated entries are supported.",
+                entry.name,
+                method
+            ),
+        };
+        if data.len() as u64 != entry.size || crc32fast::hash(&data) != entry.crc {
+            bail!("The ZIP entry '{}' is corrupt.", entry.name);
+        }
+        Ok(data)
+    }
+}
+
+/// Little-endian reads that fail instead of panicking past the end.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn u16(&self, at: usize) -> Result<u16> {
+        let bytes = self
+            .0
+            .get(at..at + 2)
+            .context("The ZIP archive is truncated.")?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&self, at: usize) -> Result<u32> {
+        let bytes = self
+            .0
+            .get(at..at + 4)
+            .context("The ZIP archive is truncated.")?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+/// A ZIP archive of `files`, stored without compression.
+pub fn write_zip(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    // 1980-01-01 00:00, the earliest time MS-DOS dates can hold.
+    const DOS_TIME: u16 = 0;
+    const DOS_DATE: u16 = (1 << 5) | 1;
+
+    let mut out = Vec::new();
+    let mut directory = Vec::new();
+    for (name, data) in files {
+        let offset = out.len() as u32;
+        let crc = crc32fast::hash(data);
+        let size = data.len() as u32;
+        // Fields shared by the local header and the central directory:
+        // version needed, flags, method (stored), time, date, CRC and sizes.
+        let mut common = Vec::with_capacity(26);
+        common.extend_from_slice(&10u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&DOS_TIME.to_le_bytes());
+        common.extend_from_slice(&DOS_DATE.to_le_bytes());
+        common.extend_from_slice(&crc.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        // No extra field.
+        common.extend_from_slice(&0u16.to_le_bytes());
+
+        out.extend_from_slice(b"PK\x03\x04");
+        out.extend_from_slice(&common);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(data);
+
+        directory.extend_from_slice(b"PK\x01\x02");
+        // Made by version 1.0.
+        directory.extend_from_slice(&10u16.to_le_bytes());
+        directory.extend_from_slice(&common);
+        // Comment length, disk number, internal and external attributes.
+        directory.extend_from_slice(&[0; 10]);
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let directory_offset = out.len() as u32;
+    let count = files.len() as u16;
+    out.extend_from_slice(&directory);
+    out.extend_from_slice(b"PK\x05\x06");
+    // This disk and the disk the directory starts on.
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&count.to_le_bytes());
+    out.extend_from_slice(&count.to_le_bytes());
+    out.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    out.extend_from_slice(&directory_offset.to_le_bytes());
+    // No archive comment.
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out
+}
@@ -0,0 +1,263 @@
+// image-compressor-rust-service/src/budget.rs
+
+//! Fitting stills into a byte budget (`X-Max-Bytes`).
+//!
+//! Lowering the quality alone cannot reach every budget: long before the
+//! file is small enough, blocking ruins the image, and a smaller image at a
+//! decent quality looks better. [`fit`] walks down a ladder of scales, from
+//! the requested output size towards the caller's minimum dimensions, and
+//! at each one bisects the highest quality, not below the caller's floor,
+//! that fits. Every fitting candidate is decoded, scaled back up and
+//! compared with the full-size image, and the one with the highest PSNR is
+//! kept.
+//!
+//! Down the ladder, the score rises while quality is won back and falls
+//! once resolution is being lost, so the walk stops at the first rung that
+//! scores lower than the one above it, or as soon as a rung fits at the
+//! requested quality.
+//!
+//! With more than one thread, consecutive rungs are searched at the same
+//! time, one per thread, and their results walked in order afterwards. The
+//! rungs past the one the walk stops at are wasted work, but the chosen
+//! candidate is the same as on one thread.
+
+use crate::options::ByteBudget;
+use crate::pool;
+use crate::{decode_image, diff};
+use anyhow::{bail, Result};
+use image::imageops::FilterType;
+use image::DynamicImage;
+use std::ops::RangeInclusive;
+
+/// Each rung of the ladder is this fraction of the size of the one above.
+const SCALE_STEP: f64 = 0.8;
+
+/// Rungs tried at most, the full size included; the last is about a tenth
+/// of the full width and height.
+const MAX_RUNGS: i32 = 11;
+
+/// The candidate [`fit`] chose.
+#[derive(Debug, Clone)]
+pub struct Fitted {
+    /// The image at the chosen size.
+    pub image: DynamicImage,
+    pub quality: u8,
+    /// The encoded image, at most the budget's `max_bytes` long.
+    pub data: Vec<u8>,
+}
+
+/// What searching one rung found.
+struct Trial {
+    /// The best candidate of the rung, if any fits, and its score.
+    fitted: Option<(f64, Fitted)>,
+    /// The size of the smallest encode tried, for the error message.
+    smallest: usize,
+}
+
+/// Encodes `image` with `encode` at the highest quality in `qualities`,
+/// scaling it down if that is not enough, so that the output fits in the
+/// budget. `encode` should produce the final bytes, metadata included, so
+/// that they are what is counted. Up to `threads` rungs are searched at
+/// once.
+///
+/// Fails when even the smallest allowed size at the lowest quality does
+/// not fit, which `/compress` answers with a `422`.
+pub fn fit(
+    image: DynamicImage,
+    budget: &ByteBudget,
+    qualities: RangeInclusive<u8>,
+    threads: usize,
+    encode: impl Fn(&DynamicImage, u8) -> Result<Vec<u8>> + Sync,
+) -> Result<Fitted> {
+    let (floor, ceiling) = (*qualities.start(), *qualities.end());
+    // Most budgets are met at the requested size and quality; that is tried
+    // on its own before any thread is spent on the ladder.
+    let data = encode(&image, ceiling)?;
+    if data.len() as u64 <= budget.max_bytes {
+        return Ok(Fitted {
+            image,
+            quality: ceiling,
+            data,
+        });
+    }
+    let mut smallest = data.len();
+    pool::give(data);
+
+    let rungs = ladder(&image, budget, floor, ceiling);
+    let mut best: Option<(f64, Fitted)> = None;
+    for batch in rungs.chunks(threads.max(1)) {
+        let trials: Vec<Result<Trial>> = match batch {
+            [(size, qualities)] => vec![trial(&image, *size, qualities, budget, &encode)],
+            _ => std::thread::scope(|scope| {
+                let workers: Vec<_> = batch
+                    .iter()
+                    .map(|(size, qualities)| {
+                        let (image, encode) = (&image, &encode);
+                        scope.spawn(move || trial(image, *size, qualities, budget, encode))
+                    })
+                    .collect();
+                workers
+                    .into_iter()
+                    .map(|worker| {
+                        worker
+                            .join()
+                            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                    })
+                    .collect()
+            }),
+        };
+        for trial in trials {
+            let trial = trial?;
+            smallest = smallest.min(trial.smallest);
+            let Some((score, fitted)) = trial.fitted else {
+                continue;
+            };
+            if let Some((best_score, best)) = best.take() {
+                if score <= best_score {
+                    recycle(fitted);
+                    return Ok(best);
+                }
+                recycle(best);
+            }
+            if fitted.quality == ceiling {
+                return Ok(fitted);
+            }
+            best = Some((score, fitted));
+        }
+    }
+
+    match best {
+        Some((_, fitted)) => Ok(fitted),
+        None => bail!(
+            "The output does not fit in {} bytes at quality {} or above and the allowed dimensions; the smallest candidate was {} bytes.",
+            budget.max_bytes,
+            floor,
+            smallest
+        ),
+    }
+}
+
+/// The sizes to search, largest first, with the qualities to search each
+/// at. The full size is only searched below `ceiling`, which did not fit.
+fn ladder(
+    image: &DynamicImage,
+    budget: &ByteBudget,
+    floor: u8,
+    ceiling: u8,
+) -> Vec<((u32, u32), RangeInclusive<u8>)> {
+    let (width, height) = (image.width(), image.height());
+    let mut rungs = Vec::new();
+    if floor < ceiling {
+        rungs.push(((width, height), floor..=ceiling - 1));
+    }
+    for rung in 1..MAX_RUNGS {
+        let scale = SCALE_STEP.powi(rung);
+        let size = (scaled(width, scale), scaled(height, scale));
+        if too_small(size.0, budget.min_width) || too_small(size.1, budget.min_height) {
+            break;
+        }
+        rungs.push((size, floor..=ceiling));
+    }
+    rungs
+}
+
+/// Searches one rung: `reference` scaled to `size`, at `qualities`.
+fn trial(
+    reference: &DynamicImage,
+    size: (u32, u32),
+    qualities: &RangeInclusive<u8>,
+    budget: &ByteBudget,
+    encode: &impl Fn(&DynamicImage, u8) -> Result<Vec<u8>>,
+) -> Result<Trial> {
+    let image = if size == (reference.width(), reference.height()) {
+        reference.clone()
+    } else {
+        reference.resize_exact(size.0, size.1, FilterType::Lanczos3)
+    };
+    let mut smallest = usize::MAX;
+    let fitted = match highest_fitting(&image, budget.max_bytes, qualities, encode, &mut smallest)?
+    {
+        Some((quality, data)) => Some((
+            score(reference, &data)?,
+            Fitted {
+                image,
+                quality,
+                data,
+            },
+        )),
+        None => None,
+    };
+    Ok(Trial { fitted, smallest })
+}
+
+/// The highest quality at which `image` fits in `max_bytes`, and its bytes.
+/// Sizes grow with quality, so the range is bisected after trying its top.
+fn highest_fitting(
+    image: &DynamicImage,
+    max_bytes: u64,
+    qualities: &RangeInclusive<u8>,
+    encode: &impl Fn(&DynamicImage, u8) -> Result<Vec<u8>>,
+    smallest: &mut usize,
+) -> Result<Option<(u8, Vec<u8>)>> {
+    let mut attempt = |quality: u8| -> Result<Option<Vec<u8>>> {
+        let data = encode(image, quality)?;
+        *smallest = (*smallest).min(data.len());
+        if data.len() as u64 > max_bytes {
+            pool::give(data);
+            return Ok(None);
+        }
+        Ok(Some(data))
+    };
+
+    let (floor, ceiling) = (*qualities.start(), *qualities.end());
+    if let Some(data) = attempt(ceiling)? {
+        return Ok(Some((ceiling, data)));
+    }
+    if floor >= ceiling {
+        return Ok(None);
+    }
+    let Some(mut fitting) = attempt(floor)? else {
+        return Ok(None);
+    };
+    // `low` always fits and `high` never does.
+    let (mut low, mut high) = (floor, ceiling);
+    while high - low > 1 {
+        let middle = low + (high - low) / 2;
+        match attempt(middle)? {
+            Some(data) => {
+                low = middle;
+                pool::give(std::mem::replace(&mut fitting, data));
+            }
+            None => high = middle,
+        }
+    }
+    Ok(Some((low, fitting)))
+}
+
+/// Returns the buffers of a candidate that lost to another to the pool.
+fn recycle(fitted: Fitted) {
+    pool::give(fitted.data);
+    pool::give(fitted.image.into_bytes());
+}
+
+/// PSNR of a candidate, scaled back up to the size of `reference`.
+fn score(reference: &DynamicImage, data: &[u8]) -> Result<f64> {
+    let decoded = decode_image(data)?;
+    let (width, height) = (reference.width(), reference.height());
+    let decoded = if (decoded.width(), decoded.height()) == (width, height) {
+        decoded
+    } else {
+        decoded.resize_exact(width, height, FilterType::CatmullRom)
+    };
+    let (report, _) = diff::compare(reference, &decoded, 0)?;
+    pool::give(decoded.into_bytes());
+    Ok(report.psnr.unwrap_or(f64::INFINITY))
+}
+
+fn scaled(side: u32, scale: f64) -> u32 {
+    ((f64::from(side) * scale).round() as u32).max(1)
+}
+
+fn too_small(side: u32, min: Option<u32>) -> bool {
+    side < min.unwrap_or(1)
+}
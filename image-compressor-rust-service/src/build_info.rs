@@ -0,0 +1,23 @@
+// image-compressor-rust-service/src/build_info.rs
+
+//! Metadata captured by `build.rs` at compile time.
+
+/// The crate version from `Cargo.toml`.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Short hash of the commit the binary was built from, or `unknown`.
+pub const GIT_COMMIT: &str = env!("BUILD_GIT_COMMIT");
+
+/// UTC build time in RFC 3339 format (`SOURCE_DATE_EPOCH` if set).
+pub const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+
+/// Comma-separated list of enabled cargo features.
+const FEATURES: &str = env!("BUILD_FEATURES");
+
+/// The cargo features the binary was built with.
+pub fn features() -> Vec<&'static str> {
+    FEATURES.split(',').filter(|f| !f.is_empty()).collect()
+}
+
+/// The codecs compiled into the binary.
+pub const CODECS: &[&str] = &["jpeg", "png", "apng", "webp", "lcms2"];
@@ -0,0 +1,167 @@
+// image-compressor-rust-service/src/color.rs
+
+//! Conversion to an output colour space.
+//!
+//! Pixels are converted with lcms2 from the input's embedded ICC profile,
+//! or from sRGB when the input has none (or one that does not describe
+//! RGB, such as the CMYK profile [`crate::decode`] already applied). The
+//! output is then tagged with the profile of the target space, so viewers
+//! decode the gamma and gamut the pixels were written in.
+
+use crate::options::ColorSpace;
+use crate::simd;
+use anyhow::{anyhow, Context, Result};
+use image::{DynamicImage, Frame, RgbaImage};
+use lcms2::{
+    CIExyY, CIExyYTRIPLE, ColorSpaceSignature, Flags, Intent, PixelFormat, Profile, ToneCurve,
+    Transform,
+};
+
+/// D65, the white point of sRGB and Display P3.
+const D65: CIExyY = CIExyY {
+    x: 0.3127,
+    y: 0.3290,
+    Y: 1.0,
+};
+
+/// The ICC profile of `space`.
+pub fn profile(space: ColorSpace) -> Result<Profile> {
+    let primaries = |[r, g, b]: [(f64, f64); 3]| {
+        let xy = |(x, y)| CIExyY { x, y, Y: 1.0 };
+        CIExyYTRIPLE {
+            Red: xy(r),
+            Green: xy(g),
+            Blue: xy(b),
+        }
+    };
+    let srgb_primaries = primaries([(0.64, 0.33), (0.30, 0.60), (0.15, 0.06)]);
+    let profile = match space {
+        ColorSpace::Srgb => Ok(Profile::new_srgb()),
+        ColorSpace::DisplayP3 => {
+            // The piecewise sRGB curve, as ICC parametric type 4.
+            let curve = ToneCurve::new_parametric(
+                4,
+                &[2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045],
+            )?;
+            let p3_primaries = primaries([(0.680, 0.320), (0.265, 0.690), (0.150, 0.060)]);
+            Profile::new_rgb(&D65, &p3_primaries, &[&curve, &curve, &curve])
+        }
+        ColorSpace::LinearSrgb => {
+            let curve = ToneCurve::new(1.0);
+            Profile::new_rgb(&D65, &srgb_primaries, &[&curve, &curve, &curve])
+        }
+    };
+    profile.map_err(|e| anyhow!("Failed to build the {:?} ICC profile: {}", space, e))
+}
+
+/// The serialized ICC profile of `space`, for embedding in outputs.
+pub fn icc(space: ColorSpace) -> Result<Vec<u8>> {
+    let mut icc = profile(space)?
+        .icc()
+        .map_err(|e| anyhow!("Failed to serialize the {:?} ICC profile: {}", space, e))?;
+    // lcms2 stamps profiles with their creation time; a fixed date keeps
+    // deterministic outputs byte-identical.
+    if let Some(created) = icc.get_mut(24..36) {
+        for (field, value) in created.chunks_exact_mut(2).zip([2024u16, 1, 1, 0, 0, 0]) {
+            field.copy_from_slice(&value.to_be_bytes());
+        }
+    }
+    Ok(icc)
+}
+
+/// How far apart, in 8-bit levels, the channels of a pixel may be for it
+/// to still count as grey. Decoded JPEGs carry a little chroma noise.
+const GRAY_TOLERANCE: u8 = 2;
+
+/// Whether every pixel of `image` is grey, within [`GRAY_TOLERANCE`].
+/// Fully transparent pixels are not looked at.
+pub fn is_grayscale(image: &DynamicImage) -> bool {
+    match image {
+        _ if !image.color().has_color() => true,
+        DynamicImage::ImageRgb8(rgb) => simd::is_gray(rgb, 3, GRAY_TOLERANCE),
+        DynamicImage::ImageRgba8(rgba) => simd::is_gray(rgba, 4, GRAY_TOLERANCE),
+        other => simd::is_gray(&other.to_rgba8(), 4, GRAY_TOLERANCE),
+    }
+}
+
+/// `image` with a single grey channel, plus its alpha if it has one.
+pub fn to_grayscale(image: DynamicImage) -> DynamicImage {
+    match image {
+        _ if !image.color().has_color() => image,
+        _ if image.color().has_alpha() => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+        _ => DynamicImage::ImageLuma8(image.to_luma8()),
+    }
+}
+
+/// Converts the pixels of `image` from the colour space described by
+/// `source_icc` (sRGB when `None`) to `target`. The result is 8-bit RGB,
+/// or RGBA when the input has alpha, which is kept as it is.
+pub fn convert(
+    image: DynamicImage,
+    source_icc: Option<&[u8]>,
+    target: ColorSpace,
+) -> Result<DynamicImage> {
+    let source = source_profile(source_icc);
+    if source.is_none() && target == ColorSpace::Srgb {
+        return Ok(image);
+    }
+    let source = source.unwrap_or_else(Profile::new_srgb);
+    let target = profile(target)?;
+    if image.color().has_alpha() {
+        let mut rgba = image.into_rgba8();
+        transform(&source, &target, PixelFormat::RGBA_8)?.transform_in_place(&mut rgba);
+        Ok(DynamicImage::ImageRgba8(rgba))
+    } else {
+        let mut rgb = image.into_rgb8();
+        transform(&source, &target, PixelFormat::RGB_8)?.transform_in_place(&mut rgb);
+        Ok(DynamicImage::ImageRgb8(rgb))
+    }
+}
+
+/// Converts animation frames like [`convert`], with one transform for all
+/// of them.
+pub fn convert_frames(
+    frames: Vec<Frame>,
+    source_icc: Option<&[u8]>,
+    target: ColorSpace,
+) -> Result<Vec<Frame>> {
+    let source = source_profile(source_icc);
+    if source.is_none() && target == ColorSpace::Srgb {
+        return Ok(frames);
+    }
+    let source = source.unwrap_or_else(Profile::new_srgb);
+    let transform = transform(&source, &profile(target)?, PixelFormat::RGBA_8)?;
+    Ok(frames
+        .into_iter()
+        .map(|frame| {
+            let (left, top, delay) = (frame.left(), frame.top(), frame.delay());
+            let mut buffer: RgbaImage = frame.into_buffer();
+            transform.transform_in_place(&mut buffer);
+            Frame::from_parts(buffer, left, top, delay)
+        })
+        .collect())
+}
+
+/// The embedded profile, if it parses and describes RGB.
+fn source_profile(icc: Option<&[u8]>) -> Option<Profile> {
+    let profile = match Profile::new_icc(icc?) {
+        Ok(profile) => profile,
+        Err(e) => {
+            tracing::warn!("Ignoring unusable embedded ICC profile: {}", e);
+            return None;
+        }
+    };
+    (profile.color_space() == ColorSpaceSignature::RgbData).then_some(profile)
+}
+
+fn transform(source: &Profile, target: &Profile, format: PixelFormat) -> Result<Transform<u8, u8>> {
+    Transform::new_flags(
+        source,
+        format,
+        target,
+        format,
+        Intent::Perceptual,
+        Flags::COPY_ALPHA,
+    )
+    .context("The embedded ICC profile cannot be converted to the output colour space.")
+}
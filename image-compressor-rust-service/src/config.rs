@@ -0,0 +1,741 @@
+// image-compressor-rust-service/src/config.rs
+
+use crate::animation::AnimationConfig;
+use crate::cpu::CpuConfig;
+use crate::encode::EncoderConfig;
+use crate::favicon::IconSet;
+use crate::filename::FilenameConfig;
+use crate::formats::InputFormat;
+use crate::logging::LoggingConfig;
+use crate::metadata::MetadataConfig;
+use crate::ocr::OcrConfig;
+use crate::options::Effort;
+use crate::pool::PoolConfig;
+use crate::preset::Preset;
+use crate::provenance::ProvenanceConfig;
+use crate::quality::QualityConfig;
+use crate::sandbox::SandboxConfig;
+use crate::server::admin::AdminConfig;
+use crate::server::audit::AuditConfig;
+use crate::server::concurrency::ConcurrencyConfig;
+use crate::server::cors::CorsConfig;
+use crate::server::error_reporting::ErrorReportingConfig;
+use crate::server::hooks::{HookConfig, HooksConfig};
+use crate::server::idempotency::IdempotencyConfig;
+use crate::server::limits::BodyLimitConfig;
+use crate::server::listen::HttpConfig;
+use crate::server::metrics_endpoint::{BasicAuth, MetricsBackend, MetricsConfig};
+use crate::server::peers::PeersConfig;
+use crate::server::routing::RoutingConfig;
+use crate::server::scan::{ScanConfig, ScanProtocol};
+use crate::server::selftest::SelftestConfig;
+use crate::server::slow_log::SlowLogConfig;
+use crate::server::tus::{TusConfig, UploadStore};
+use crate::simd::SimdMode;
+use crate::social::SocialConfig;
+use crate::transform::MaxDimensions;
+use crate::watermark::WatermarkConfig;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+/// Environment variable pointing at an optional TOML configuration file.
+pub const CONFIG_PATH_ENV: &str = "CONFIG_PATH";
+
+/// Service configuration.
+///
+/// Values are taken, in increasing order of precedence, from the built-in
+/// defaults, the TOML file named by `CONFIG_PATH`, and individual
+/// environment variables (see [`Config::apply_env`]).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address the HTTP server binds to.
+    pub bind_addr: SocketAddr,
+    /// Listen on this Unix socket instead of `bind_addr`. Both are ignored
+    /// when the process is started through systemd socket activation.
+    pub unix_socket: Option<PathBuf>,
+    /// HTTP/2, keep-alive and connection limits.
+    pub http: HttpConfig,
+    /// Maximum accepted request body size, in bytes, unless overridden in
+    /// `body_limits`.
+    pub max_body_bytes: usize,
+    /// Per-route and per-API-key body size limits.
+    pub body_limits: BodyLimitConfig,
+    /// Input formats accepted by `/compress`, detected by magic bytes.
+    /// Anything else is rejected with `415 Unsupported Media Type`.
+    pub allowed_input_formats: Vec<InputFormat>,
+    /// Virus scanning of inputs before decoding.
+    pub virus_scan: ScanConfig,
+    /// Per-output-format default quality and clamp range, and what to do
+    /// with requests above the quality of their JPEG input.
+    pub quality: QualityConfig,
+    /// Maximum output width in pixels. Larger results are scaled down.
+    pub max_output_width: u32,
+    /// Maximum output height in pixels. Larger results are scaled down.
+    pub max_output_height: u32,
+    /// Caps on the frames and duration of animated outputs.
+    pub animation: AnimationConfig,
+    /// Named option sets, on top of (or replacing) the built-in `web` and
+    /// `thumbnail`.
+    pub presets: BTreeMap<String, Preset>,
+    /// Named icon bundles for `/icons`, on top of (or replacing) the
+    /// built-in `favicon`, `pwa` and `apple`.
+    pub icon_sets: BTreeMap<String, IconSet>,
+    /// How `Content-Disposition` filenames are built.
+    pub filename: FilenameConfig,
+    /// Which EXIF metadata is copied to outputs, per API key.
+    pub metadata: MetadataConfig,
+    /// Produce reproducible output for every request, as if each sent
+    /// `X-Deterministic: true`.
+    pub deterministic: bool,
+    /// Encoder effort of requests that do not send `X-Effort`.
+    pub effort: Effort,
+    /// Per-format encoder settings beyond quality and effort.
+    pub encoders: EncoderConfig,
+    /// Signed C2PA manifests in outputs.
+    pub provenance: ProvenanceConfig,
+    /// Threads shared by concurrent compressions and large images.
+    pub cpu: CpuConfig,
+    /// Reuse of decode and output buffers across requests.
+    pub buffer_pool: PoolConfig,
+    /// Per-endpoint limits on concurrent and queued requests.
+    pub concurrency: ConcurrencyConfig,
+    /// Out-of-process decoding of untrusted inputs.
+    pub sandbox: SandboxConfig,
+    /// Run a test encode per allowed format at startup before reporting ready.
+    pub warm_up: bool,
+    /// Compress JSON and text responses with gzip or brotli when the client
+    /// accepts it. Image responses are never re-compressed.
+    pub compress_responses: bool,
+    /// Return the input when compressing it did not make it smaller and it
+    /// can stand in for the output (see [`crate::server::skip`]).
+    pub pass_through_larger: bool,
+    /// Encode identical concurrent `/compress` requests (same body and
+    /// options) once and share the result.
+    pub coalesce: bool,
+    /// Other replicas to share `/compress` work with.
+    pub peers: PeersConfig,
+    /// Router mode: `/compress` proxied to workers by content hash.
+    pub routing: RoutingConfig,
+    /// Serve the manual testing page on `/ui`.
+    pub ui: bool,
+    /// Ignore request options that are invalid, out of range or
+    /// contradictory, as older versions did, instead of answering `400`.
+    pub lenient_options: bool,
+    /// Report per-stage timings of `/compress` in a `Server-Timing` header.
+    /// They are always logged.
+    pub server_timing: bool,
+    /// Log levels, sampling of request logs, and redaction.
+    pub logging: LoggingConfig,
+    /// Warnings for slow requests and large images.
+    pub slow_log: SlowLogConfig,
+    /// Compliance record of every `/compress` request.
+    pub audit: AuditConfig,
+    /// Webhooks called before and after each `/compress` request.
+    pub hooks: HooksConfig,
+    /// Resumable uploads over the tus protocol on `/uploads`.
+    pub uploads: TusConfig,
+    /// Replay of `/compress` results for retried requests.
+    pub idempotency: IdempotencyConfig,
+    /// Cross-origin access for browser clients.
+    pub cors: CorsConfig,
+    /// The authenticated `/selftest` endpoint.
+    pub selftest: SelftestConfig,
+    /// The authenticated `/admin` endpoints.
+    pub admin: AdminConfig,
+    /// Where `/metrics` is served, and who may read it.
+    pub metrics: MetricsConfig,
+    /// Where failed requests and panics are reported.
+    pub error_reporting: ErrorReportingConfig,
+    /// Text extraction for presets that ask for it, by an external command
+    /// such as `tesseract`, installed separately.
+    pub ocr: OcrConfig,
+    /// Font and templates for `/social-card`.
+    pub social: SocialConfig,
+    /// Invisible watermarks for `X-Watermark` and `/watermark/detect`.
+    pub watermark: WatermarkConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind_addr: SocketAddr::from(([0, 0, 0, 0], 8000)),
+            unix_socket: None,
+            http: HttpConfig::default(),
+            max_body_bytes: 10 * 1024 * 1024, // 10 MB
+            body_limits: BodyLimitConfig::default(),
+            allowed_input_formats: InputFormat::ALL.to_vec(),
+            virus_scan: ScanConfig::default(),
+            quality: QualityConfig::default(),
+            max_output_width: 8192,
+            max_output_height: 8192,
+            animation: AnimationConfig::default(),
+            presets: BTreeMap::new(),
+            icon_sets: BTreeMap::new(),
+            filename: FilenameConfig::default(),
+            metadata: MetadataConfig::default(),
+            deterministic: false,
+            effort: Effort::default(),
+            encoders: EncoderConfig::default(),
+            provenance: ProvenanceConfig::default(),
+            cpu: CpuConfig::default(),
+            buffer_pool: PoolConfig::default(),
+            concurrency: ConcurrencyConfig::default(),
+            sandbox: SandboxConfig::default(),
+            warm_up: true,
+            compress_responses: true,
+            pass_through_larger: true,
+            coalesce: true,
+            peers: PeersConfig::default(),
+            routing: RoutingConfig::default(),
+            ui: true,
+            lenient_options: false,
+            server_timing: false,
+            logging: LoggingConfig::default(),
+            slow_log: SlowLogConfig::default(),
+            audit: AuditConfig::default(),
+            hooks: HooksConfig::default(),
+            uploads: TusConfig::default(),
+            idempotency: IdempotencyConfig::default(),
+            cors: CorsConfig::default(),
+            selftest: SelftestConfig::default(),
+            admin: AdminConfig::default(),
+            metrics: MetricsConfig::default(),
+            error_reporting: ErrorReportingConfig::default(),
+            ocr: OcrConfig::default(),
+            social: SocialConfig::default(),
+            watermark: WatermarkConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The configured output dimension cap.
+    pub fn max_dimensions(&self) -> MaxDimensions {
+        MaxDimensions {
+            width: self.max_output_width,
+            height: self.max_output_height,
+        }
+    }
+
+    /// The preset `name`, from the config or built in.
+    pub fn preset(&self, name: &str) -> Option<Preset> {
+        self.presets
+            .get(name)
+            .cloned()
+            .or_else(|| Preset::builtin(name))
+    }
+
+    /// The icon set `name`, from the config or built in.
+    pub fn icon_set(&self, name: &str) -> Option<IconSet> {
+        self.icon_sets
+            .get(name)
+            .cloned()
+            .or_else(|| IconSet::builtin(name))
+    }
+
+    /// Loads the configuration from `CONFIG_PATH` (if set) and the environment.
+    pub fn load() -> Result<Self> {
+        let mut config = match std::env::var_os(CONFIG_PATH_ENV) {
+            Some(path) => Self::from_file(Path::new(&path))?,
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks settings that cannot be validated while deserializing.
+    pub fn validate(&self) -> Result<()> {
+        self.cors
+            .layer()
+            .map(drop)
+            .context("Invalid cors settings")?;
+        self.metrics
+            .recorder()
+            .map(drop)
+            .context("Invalid metrics settings")?;
+        self.quality
+            .validate()
+            .context("Invalid quality settings")?;
+        self.encoders
+            .validate()
+            .context("Invalid encoders settings")?;
+        for (name, preset) in &self.presets {
+            preset
+                .validate()
+                .with_context(|| format!("Invalid preset '{}'", name))?;
+            if preset.ocr == Some(true) && !self.ocr.enabled {
+                bail!("Invalid preset '{}': ocr needs [ocr] to be enabled", name);
+            }
+        }
+        for (name, set) in &self.icon_sets {
+            set.validate()
+                .with_context(|| format!("Invalid icon set '{}'", name))?;
+        }
+        self.provenance
+            .validate()
+            .context("Invalid provenance settings")?;
+        self.concurrency
+            .validate()
+            .context("Invalid concurrency settings")?;
+        self.buffer_pool
+            .validate()
+            .context("Invalid buffer_pool settings")?;
+        self.logging
+            .validate()
+            .context("Invalid logging settings")?;
+        self.audit.validate().context("Invalid audit settings")?;
+        self.hooks.validate().context("Invalid hooks settings")?;
+        self.uploads
+            .validate()
+            .context("Invalid uploads settings")?;
+        self.error_reporting
+            .validate()
+            .context("Invalid error_reporting settings")?;
+        self.ocr.validate().context("Invalid ocr settings")?;
+        self.social.validate().context("Invalid social settings")?;
+        self.watermark
+            .validate()
+            .context("Invalid watermark settings")?;
+        self.peers.validate().context("Invalid peers settings")?;
+        self.routing
+            .validate()
+            .context("Invalid routing settings")?;
+        Ok(())
+    }
+
+    /// Settings that are valid but probably not what was meant: limits for
+    /// routes the service does not have or that cannot take effect, and
+    /// presets asking for more than requests are allowed.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let mut paths: Vec<_> = self.body_limits.routes.keys().collect();
+        paths.sort();
+        for path in paths {
+            if !crate::server::is_route(path) {
+                warnings.push(format!("body_limits: no route serves '{}'", path));
+            }
+        }
+        let mut routes: Vec<_> = self.concurrency.routes.iter().collect();
+        routes.sort_by_key(|(route, _)| *route);
+        for (route, limit) in routes {
+            if !crate::server::ROUTES.contains(&route.as_str()) {
+                warnings.push(format!("concurrency: there is no route '{}'", route));
+            } else if self.cpu.budget > 0 && limit.max_in_flight > self.cpu.budget {
+                warnings.push(format!(
+                    "concurrency: max_in_flight of '{}' is above the CPU budget of {} threads",
+                    route, self.cpu.budget
+                ));
+            }
+        }
+        if self.cpu.budget > 0 && self.cpu.max_threads_per_image > self.cpu.budget {
+            warnings.push(format!(
+                "cpu: max_threads_per_image is above the budget of {} threads",
+                self.cpu.budget
+            ));
+        }
+        for (name, preset) in &self.presets {
+            warnings.extend(self.preset_warnings(name, preset));
+        }
+        warnings
+    }
+
+    /// The [`warnings`](Self::warnings) about the preset `name`.
+    pub fn preset_warnings(&self, name: &str, preset: &Preset) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if Preset::builtin(name).is_some() {
+            warnings.push(format!("preset '{}' replaces the built-in one", name));
+        }
+        if let Some(quality) = preset.quality {
+            for (format, range) in [("jpeg", self.quality.jpeg), ("webp", self.quality.webp)] {
+                if !(range.min..=range.max).contains(&quality) {
+                    warnings.push(format!(
+                        "preset '{}': quality {} is clamped to {}-{} for {}",
+                        name, quality, range.min, range.max, format
+                    ));
+                }
+            }
+        }
+        let conflicts = preset.options().conflicts(self.max_dimensions());
+        warnings.extend(
+            conflicts.into_iter().map(|conflict| {
+                format!("preset '{}': {} {}", name, conflict.field, conflict.message)
+            }),
+        );
+        warnings
+    }
+
+    /// Parses a TOML configuration file. Missing keys keep their defaults.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    /// Overrides settings from environment variables:
+    ///
+    /// * `BIND_ADDR` - e.g. `0.0.0.0:8000`.
+    /// * `UNIX_SOCKET` - path of a Unix socket to listen on instead.
+    /// * `HTTP2` - `false` to serve HTTP/1.1 only.
+    /// * `MAX_CONNECTIONS` - maximum open connections (`0` for unlimited).
+    /// * `MAX_BODY_BYTES` - maximum request body size in bytes.
+    /// * `ALLOWED_INPUT_FORMATS` - comma-separated list, e.g. `jpeg,png`.
+    /// * `VIRUS_SCAN` - `true` to scan inputs before decoding.
+    /// * `VIRUS_SCAN_PROTOCOL` - `clamav` or `icap`.
+    /// * `VIRUS_SCAN_ADDR` - `host:port` of the scanner.
+    /// * `MAX_OUTPUT_WIDTH` / `MAX_OUTPUT_HEIGHT` - output dimension cap in pixels.
+    /// * `MAX_ANIMATION_FRAMES` / `MAX_ANIMATION_DURATION_MS` - caps on
+    ///   animated outputs, `0` for none.
+    /// * `KEEP_EXIF` - `false` to strip all EXIF metadata from outputs.
+    /// * `DETERMINISTIC` - `true` for reproducible output on every request.
+    /// * `EFFORT` - `fast`, `balanced` or `max`, the default encoder effort.
+    /// * `JPEG_QUANT_TABLES` - `standard` or `flat`.
+    /// * `JPEG_BACKEND` - `image` or `builtin`, the JPEG encoder.
+    /// * `JPEG_CANARY` - another JPEG encoder for a share of the outputs, as
+    ///   `backend:percent`, e.g. `builtin:5`.
+    /// * `WEBP_METHOD` / `WEBP_SEGMENTS` / `WEBP_SNS_STRENGTH` /
+    ///   `WEBP_FILTER_STRENGTH` / `WEBP_FILTER_SHARPNESS` / `WEBP_PASSES` -
+    ///   libwebp settings of animated outputs.
+    /// * `CPU_BUDGET` - threads shared by all compressions, `0` for one per core.
+    /// * `MAX_THREADS_PER_IMAGE` - most threads a single large image may use.
+    /// * `BACKGROUND_THREADS` / `BACKGROUND_NICE` - size and nice value of
+    ///   the background job pool.
+    /// * `SIMD` - `auto`, or `scalar` to keep pixel loops off vector extensions.
+    /// * `BUFFER_POOL` - `false` to stop reusing buffers across requests.
+    /// * `BUFFER_POOL_MAX_BYTES` - most bytes of buffers kept for reuse.
+    /// * `SANDBOX_DECODE` - `true` to decode in a sandboxed subprocess.
+    /// * `WARM_UP` - `false` to skip the startup warm-up.
+    /// * `COMPRESS_RESPONSES` - `false` to disable gzip/br for JSON responses.
+    /// * `PASS_THROUGH_LARGER` - `false` to always return the compressed output,
+    ///   even when it is larger than the input.
+    /// * `COALESCE` - `false` to encode every identical concurrent request.
+    /// * `PEERS` - comma-separated base URLs of the replicas to share work with.
+    /// * `PEER_FORWARD_MIN_PIXELS` - inputs at least this large are forwarded
+    ///   to a less loaded peer, `0` to forward by load only.
+    /// * `ROUTE_WORKERS` - comma-separated base URLs of workers; when set,
+    ///   `/compress` is proxied to them by content hash instead of served.
+    /// * `UI` - `false` to not serve the manual testing page on `/ui`.
+    /// * `LENIENT_OPTIONS` - `true` to ignore invalid request options instead
+    ///   of rejecting the request.
+    /// * `SERVER_TIMING` - `true` to send a `Server-Timing` header from `/compress`.
+    /// * `LOG_LEVEL` - `error`, `warn`, `info`, `debug` or `trace`.
+    /// * `LOG_MODULES` - per-module levels, as
+    ///   `tower_http=debug,image_compressor_rust_service::server::peers=warn`.
+    /// * `TRACE_SAMPLE_RATE` - share of requests logged in full, from `0` to `1`.
+    /// * `LOG_REDACT_URLS` / `LOG_REDACT_TENANTS` - `true` to keep URLs or
+    ///   tenants out of the logs.
+    /// * `SLOW_REQUEST_MS` / `LARGE_IMAGE_PIXELS` - thresholds for the slow
+    ///   request and large image warnings, `0` to disable.
+    /// * `AUDIT_LOG_PATH` - file receiving one JSON line per `/compress` request.
+    /// * `AUDIT_WEBHOOK_URL` - URL each audit record is POSTed to.
+    /// * `BEFORE_HOOK_URL` / `AFTER_HOOK_URL` - URLs of the hooks called
+    ///   before and after each `/compress` request.
+    /// * `BEFORE_HOOK_FAIL_OPEN` - `true` to go on when the before hook fails.
+    /// * `TUS_UPLOADS` - `true` to accept resumable uploads on `/uploads`.
+    /// * `TUS_STORE` - `disk` or `redis`.
+    /// * `TUS_DIR` / `TUS_REDIS_URL` - where the store keeps uploads.
+    /// * `TUS_EXPIRE_SECS` / `TUS_MAX_STORAGE_BYTES` - how long uploads are
+    ///   kept and how much the disk store holds, `0` for no cap.
+    /// * `IDEMPOTENCY_TTL_SECS` - how long results are kept for `Idempotency-Key` replay.
+    /// * `IDEMPOTENCY_MAX_BYTES` - most bytes of results kept for replay.
+    /// * `CORS_ALLOWED_ORIGINS` - comma-separated list of origins, or `*`.
+    /// * `SELFTEST_TOKEN` - bearer token that enables `/selftest`.
+    /// * `ADMIN_TOKEN` - bearer token that enables the `/admin` endpoints.
+    /// * `WATERMARK_SECRET` - key that enables invisible watermarks.
+    /// * `METRICS_BIND_ADDR` - separate address serving only `/metrics`.
+    /// * `METRICS_USERNAME` / `METRICS_PASSWORD` - basic auth for `/metrics`;
+    ///   the username defaults to `metrics`.
+    /// * `METRICS_BACKEND` - `prometheus` or `statsd`.
+    /// * `STATSD_ADDR` - `host:port` of the StatsD or Datadog agent.
+    /// * `METRICS_PREFIX` - prefix for every metric name.
+    /// * `METRICS_GLOBAL_LABELS` - labels for every metric, as
+    ///   `service=imgsvc,region=eu-west-1`.
+    /// * `SENTRY_DSN` / `SENTRY_ENVIRONMENT` - where and under which
+    ///   environment errors are reported.
+    pub fn apply_env(&mut self) -> Result<()> {
+        if let Some(value) = env_var("BIND_ADDR") {
+            self.bind_addr = value.parse().context("Invalid BIND_ADDR")?;
+        }
+        if let Some(value) = env_var("UNIX_SOCKET") {
+            self.unix_socket = Some(PathBuf::from(value));
+        }
+        if let Some(value) = env_var("HTTP2") {
+            self.http.http2 = value.parse().context("Invalid HTTP2")?;
+        }
+        if let Some(value) = env_var("MAX_CONNECTIONS") {
+            self.http.max_connections = value.parse().context("Invalid MAX_CONNECTIONS")?;
+        }
+        if let Some(value) = env_var("MAX_BODY_BYTES") {
+            self.max_body_bytes = value.parse().context("Invalid MAX_BODY_BYTES")?;
+        }
+        if let Some(value) = env_var("ALLOWED_INPUT_FORMATS") {
+            self.allowed_input_formats = split_list(&value)
+                .map(str::parse)
+                .collect::<Result<_, String>>()
+                .map_err(anyhow::Error::msg)
+                .context("Invalid ALLOWED_INPUT_FORMATS")?;
+        }
+        if let Some(value) = env_var("VIRUS_SCAN") {
+            self.virus_scan.enabled = value.parse().context("Invalid VIRUS_SCAN")?;
+        }
+        if let Some(value) = env_var("VIRUS_SCAN_PROTOCOL") {
+            self.virus_scan.protocol = match value.trim().to_ascii_lowercase().as_str() {
+                "clamav" | "clamd" => ScanProtocol::Clamav,
+                "icap" => ScanProtocol::Icap,
+                other => bail!("Invalid VIRUS_SCAN_PROTOCOL '{}'", other),
+            };
+        }
+        if let Some(value) = env_var("VIRUS_SCAN_ADDR") {
+            self.virus_scan.addr = value;
+        }
+        if let Some(value) = env_var("MAX_OUTPUT_WIDTH") {
+            self.max_output_width = value.parse().context("Invalid MAX_OUTPUT_WIDTH")?;
+        }
+        if let Some(value) = env_var("MAX_OUTPUT_HEIGHT") {
+            self.max_output_height = value.parse().context("Invalid MAX_OUTPUT_HEIGHT")?;
+        }
+        if let Some(value) = env_var("MAX_ANIMATION_FRAMES") {
+            self.animation.max_frames = value.parse().context("Invalid MAX_ANIMATION_FRAMES")?;
+        }
+        if let Some(value) = env_var("MAX_ANIMATION_DURATION_MS") {
+            self.animation.max_duration_ms =
+                value.parse().context("Invalid MAX_ANIMATION_DURATION_MS")?;
+        }
+        if let Some(value) = env_var("KEEP_EXIF") {
+            self.metadata.policy.keep_exif = value.parse().context("Invalid KEEP_EXIF")?;
+        }
+        if let Some(value) = env_var("DETERMINISTIC") {
+            self.deterministic = value.parse().context("Invalid DETERMINISTIC")?;
+        }
+        if let Some(value) = env_var("EFFORT") {
+            self.effort = match Effort::parse(&value) {
+                Some(effort) => effort,
+                None => bail!("Invalid EFFORT '{}'", value),
+            };
+        }
+        if let Some(value) = env_var("JPEG_QUANT_TABLES") {
+            self.encoders.jpeg.tables = value.parse().context("Invalid JPEG_QUANT_TABLES")?;
+        }
+        if let Some(value) = env_var("JPEG_BACKEND") {
+            self.encoders.jpeg.backend = value.parse().context("Invalid JPEG_BACKEND")?;
+        }
+        if let Some(value) = env_var("JPEG_CANARY") {
+            self.encoders.jpeg.canary = Some(value.parse().context("Invalid JPEG_CANARY")?);
+        }
+        let webp = &mut self.encoders.webp;
+        for (name, setting) in [
+            ("WEBP_METHOD", &mut webp.method),
+            ("WEBP_SEGMENTS", &mut webp.segments),
+            ("WEBP_SNS_STRENGTH", &mut webp.sns_strength),
+            ("WEBP_FILTER_STRENGTH", &mut webp.filter_strength),
+            ("WEBP_FILTER_SHARPNESS", &mut webp.filter_sharpness),
+            ("WEBP_PASSES", &mut webp.passes),
+        ] {
+            if let Some(value) = env_var(name) {
+                *setting = Some(value.parse().with_context(|| format!("Invalid {}", name))?);
+            }
+        }
+        if let Some(value) = env_var("CPU_BUDGET") {
+            self.cpu.budget = value.parse().context("Invalid CPU_BUDGET")?;
+        }
+        if let Some(value) = env_var("MAX_THREADS_PER_IMAGE") {
+            self.cpu.max_threads_per_image =
+                value.parse().context("Invalid MAX_THREADS_PER_IMAGE")?;
+        }
+        if let Some(value) = env_var("BACKGROUND_THREADS") {
+            self.cpu.background.threads = value.parse().context("Invalid BACKGROUND_THREADS")?;
+        }
+        if let Some(value) = env_var("BACKGROUND_NICE") {
+            self.cpu.background.nice = value.parse().context("Invalid BACKGROUND_NICE")?;
+        }
+        if let Some(value) = env_var("BUFFER_POOL") {
+            self.buffer_pool.enabled = value.parse().context("Invalid BUFFER_POOL")?;
+        }
+        if let Some(value) = env_var("BUFFER_POOL_MAX_BYTES") {
+            self.buffer_pool.max_bytes = value.parse().context("Invalid BUFFER_POOL_MAX_BYTES")?;
+        }
+        if let Some(value) = env_var("SIMD") {
+            self.cpu.simd = match value.trim().to_ascii_lowercase().as_str() {
+                "auto" => SimdMode::Auto,
+                "scalar" => SimdMode::Scalar,
+                other => bail!("Invalid SIMD '{}'", other),
+            };
+        }
+        if let Some(value) = env_var("SANDBOX_DECODE") {
+            self.sandbox.enabled = value.parse().context("Invalid SANDBOX_DECODE")?;
+        }
+        if let Some(value) = env_var("WARM_UP") {
+            self.warm_up = value.parse().context("Invalid WARM_UP")?;
+        }
+        if let Some(value) = env_var("COMPRESS_RESPONSES") {
+            self.compress_responses = value.parse().context("Invalid COMPRESS_RESPONSES")?;
+        }
+        if let Some(value) = env_var("PASS_THROUGH_LARGER") {
+            self.pass_through_larger = value.parse().context("Invalid PASS_THROUGH_LARGER")?;
+        }
+        if let Some(value) = env_var("COALESCE") {
+            self.coalesce = value.parse().context("Invalid COALESCE")?;
+        }
+        if let Some(value) = env_var("PEERS") {
+            self.peers.urls = split_list(&value).map(str::to_string).collect();
+        }
+        if let Some(value) = env_var("PEER_FORWARD_MIN_PIXELS") {
+            self.peers.forward_min_pixels =
+                value.parse().context("Invalid PEER_FORWARD_MIN_PIXELS")?;
+        }
+        if let Some(value) = env_var("ROUTE_WORKERS") {
+            self.routing.workers = split_list(&value).map(str::to_string).collect();
+        }
+        if let Some(value) = env_var("UI") {
+            self.ui = value.parse().context("Invalid UI")?;
+        }
+        if let Some(value) = env_var("LENIENT_OPTIONS") {
+            self.lenient_options = value.parse().context("Invalid LENIENT_OPTIONS")?;
+        }
+        if let Some(value) = env_var("SERVER_TIMING") {
+            self.server_timing = value.parse().context("Invalid SERVER_TIMING")?;
+        }
+        if let Some(value) = env_var("LOG_LEVEL") {
+            self.logging.level = value;
+        }
+        if let Some(value) = env_var("LOG_MODULES") {
+            self.logging.modules = split_list(&value)
+                .map(|pair| {
+                    pair.split_once('=')
+                        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+                        .with_context(|| format!("Invalid LOG_MODULES entry '{}'", pair))
+                })
+                .collect::<Result<_>>()?;
+        }
+        if let Some(value) = env_var("TRACE_SAMPLE_RATE") {
+            self.logging.trace_sample_rate = value.parse().context("Invalid TRACE_SAMPLE_RATE")?;
+        }
+        if let Some(value) = env_var("LOG_REDACT_URLS") {
+            self.logging.redact.urls = value.parse().context("Invalid LOG_REDACT_URLS")?;
+        }
+        if let Some(value) = env_var("LOG_REDACT_TENANTS") {
+            self.logging.redact.tenants = value.parse().context("Invalid LOG_REDACT_TENANTS")?;
+        }
+        if let Some(value) = env_var("SLOW_REQUEST_MS") {
+            self.slow_log.slow_request_ms = value.parse().context("Invalid SLOW_REQUEST_MS")?;
+        }
+        if let Some(value) = env_var("LARGE_IMAGE_PIXELS") {
+            self.slow_log.large_image_pixels =
+                value.parse().context("Invalid LARGE_IMAGE_PIXELS")?;
+        }
+        if let Some(value) = env_var("AUDIT_LOG_PATH") {
+            self.audit.path = Some(PathBuf::from(value));
+        }
+        if let Some(value) = env_var("AUDIT_WEBHOOK_URL") {
+            self.audit.webhook_url = Some(value);
+        }
+        if let Some(url) = env_var("BEFORE_HOOK_URL") {
+            self.hooks
+                .before
+                .get_or_insert_with(HookConfig::default)
+                .url = url;
+        }
+        if let Some(url) = env_var("AFTER_HOOK_URL") {
+            self.hooks.after.get_or_insert_with(HookConfig::default).url = url;
+        }
+        if let Some(value) = env_var("BEFORE_HOOK_FAIL_OPEN") {
+            self.hooks
+                .before
+                .get_or_insert_with(HookConfig::default)
+                .fail_open = value.parse().context("Invalid BEFORE_HOOK_FAIL_OPEN")?;
+        }
+        if let Some(value) = env_var("TUS_UPLOADS") {
+            self.uploads.enabled = value.parse().context("Invalid TUS_UPLOADS")?;
+        }
+        if let Some(value) = env_var("TUS_STORE") {
+            self.uploads.store = match value.trim().to_ascii_lowercase().as_str() {
+                "disk" => UploadStore::Disk,
+                "redis" => UploadStore::Redis,
+                other => bail!("Invalid TUS_STORE '{}'", other),
+            };
+        }
+        if let Some(value) = env_var("TUS_DIR") {
+            self.uploads.dir = PathBuf::from(value);
+        }
+        if let Some(value) = env_var("TUS_REDIS_URL") {
+            self.uploads.redis_url = value;
+        }
+        if let Some(value) = env_var("TUS_EXPIRE_SECS") {
+            self.uploads.expire_secs = value.parse().context("Invalid TUS_EXPIRE_SECS")?;
+        }
+        if let Some(value) = env_var("TUS_MAX_STORAGE_BYTES") {
+            self.uploads.max_storage_bytes =
+                value.parse().context("Invalid TUS_MAX_STORAGE_BYTES")?;
+        }
+        if let Some(value) = env_var("IDEMPOTENCY_TTL_SECS") {
+            self.idempotency.ttl_secs = value.parse().context("Invalid IDEMPOTENCY_TTL_SECS")?;
+        }
+        if let Some(value) = env_var("IDEMPOTENCY_MAX_BYTES") {
+            self.idempotency.max_bytes = value.parse().context("Invalid IDEMPOTENCY_MAX_BYTES")?;
+        }
+        if let Some(value) = env_var("CORS_ALLOWED_ORIGINS") {
+            self.cors.allowed_origins = split_list(&value).map(str::to_string).collect();
+        }
+        if let Some(value) = env_var("METRICS_BIND_ADDR") {
+            self.metrics.bind_addr = Some(value.parse().context("Invalid METRICS_BIND_ADDR")?);
+        }
+        if let Some(value) = env_var("METRICS_BACKEND") {
+            self.metrics.backend = match value.trim().to_ascii_lowercase().as_str() {
+                "prometheus" => MetricsBackend::Prometheus,
+                "statsd" | "dogstatsd" => MetricsBackend::Statsd,
+                other => bail!("Invalid METRICS_BACKEND '{}'", other),
+            };
+        }
+        if let Some(value) = env_var("STATSD_ADDR") {
+            self.metrics.statsd.addr = value;
+        }
+        if let Some(value) = env_var("METRICS_PREFIX") {
+            self.metrics.prefix = Some(value);
+        }
+        if let Some(value) = env_var("METRICS_GLOBAL_LABELS") {
+            self.metrics.global_labels = split_list(&value)
+                .map(|pair| {
+                    pair.split_once('=')
+                        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+                        .with_context(|| format!("Invalid METRICS_GLOBAL_LABELS entry '{}'", pair))
+                })
+                .collect::<Result<_>>()?;
+        }
+        if let Some(password) = env_var("METRICS_PASSWORD") {
+            self.metrics.basic_auth = Some(BasicAuth {
+                username: env_var("METRICS_USERNAME").unwrap_or_else(|| "metrics".to_string()),
+                password,
+            });
+        }
+        if let Some(value) = env_var("SENTRY_DSN") {
+            self.error_reporting.sentry_dsn = Some(value);
+        }
+        if let Some(value) = env_var("SENTRY_ENVIRONMENT") {
+            self.error_reporting.environment = Some(value);
+        }
+        if let Some(value) = env_var("SELFTEST_TOKEN") {
+            self.selftest.token = Some(value);
+        }
+        if let Some(value) = env_var("ADMIN_TOKEN") {
+            self.admin.token = Some(value);
+        }
+        if let Some(value) = env_var("WATERMARK_SECRET") {
+            self.watermark.secret = Some(value);
+        }
+        Ok(())
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.trim().is_empty())
+}
+
+/// Splits a comma-separated environment value, skipping empty items.
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|s| !s.is_empty())
+}
@@ -0,0 +1,123 @@
+// image-compressor-rust-service/src/contact_sheet.rs
+
+//! Contact sheets: pages of thumbnails with their file names underneath,
+//! for reviewing a batch of images at a glance.
+//!
+//! A [`Layout`] fixes the grid; sets larger than one page are split across
+//! pages, of which only the last may be shorter. Files that cannot be
+//! decoded still get their cell, tinted red, so nothing in a batch goes
+//! missing from the review unnoticed.
+
+use crate::options::Color;
+use crate::text::Font;
+use anyhow::{bail, Result};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+/// Largest columns and rows a page may have.
+pub const MAX_GRID: u32 = 20;
+
+/// Largest thumbnail edge, in pixels.
+pub const MAX_THUMB_SIZE: u32 = 512;
+
+/// Space between and around cells, in pixels.
+const GAP: u32 = 16;
+
+/// File name text size, in pixels.
+const LABEL_SIZE: f32 = 14.0;
+
+const PAGE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const CELL: Rgba<u8> = Rgba([232, 232, 232, 255]);
+const BROKEN: Rgba<u8> = Rgba([248, 215, 218, 255]);
+const LABEL: Color = Color([51, 51, 51, 255]);
+
+/// The grid of every page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub columns: u32,
+    pub rows: u32,
+    /// Thumbnails fit in a square this size.
+    pub thumb_size: u32,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            columns: 6,
+            rows: 5,
+            thumb_size: 200,
+        }
+    }
+}
+
+/// One cell: a file name and its image, or `None` if it could not be read.
+#[derive(Debug, Clone)]
+pub struct Tile {
+    pub name: String,
+    pub image: Option<DynamicImage>,
+}
+
+impl Layout {
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=MAX_GRID).contains(&self.columns) || !(1..=MAX_GRID).contains(&self.rows) {
+            bail!("columns and rows must be between 1 and {}", MAX_GRID);
+        }
+        if !(16..=MAX_THUMB_SIZE).contains(&self.thumb_size) {
+            bail!("thumbnail size must be between 16 and {}", MAX_THUMB_SIZE);
+        }
+        Ok(())
+    }
+
+    pub fn per_page(&self) -> usize {
+        (self.columns * self.rows) as usize
+    }
+
+    /// How many pages `count` images take; an empty set still gets one.
+    pub fn pages(&self, count: usize) -> usize {
+        count.div_ceil(self.per_page()).max(1)
+    }
+
+    /// Renders one page of at most [`Layout::per_page`] tiles. The page
+    /// is only as tall as the rows it fills.
+    pub fn render(&self, font: &Font, tiles: &[Tile]) -> RgbaImage {
+        let thumb = self.thumb_size;
+        let label_height = font.line_height(LABEL_SIZE).ceil() as u32 + GAP / 4;
+        let rows = (tiles.len() as u32)
+            .div_ceil(self.columns)
+            .clamp(1, self.rows);
+        let width = self.columns * thumb + (self.columns + 1) * GAP;
+        let height = rows * (thumb + label_height) + (rows + 1) * GAP;
+        let mut page = RgbaImage::from_pixel(width, height, PAGE);
+
+        for (i, tile) in tiles.iter().take(self.per_page()).enumerate() {
+            let (column, row) = (i as u32 % self.columns, i as u32 / self.columns);
+            let x = GAP + column * (thumb + GAP);
+            let y = GAP + row * (thumb + label_height + GAP);
+            let background = if tile.image.is_some() { CELL } else { BROKEN };
+            let cell = RgbaImage::from_pixel(thumb, thumb, background);
+            imageops::overlay(&mut page, &cell, i64::from(x), i64::from(y));
+            if let Some(image) = &tile.image {
+                let scale = (f64::from(thumb) / f64::from(image.width()))
+                    .min(f64::from(thumb) / f64::from(image.height()))
+                    .min(1.0);
+                let size = (
+                    ((f64::from(image.width()) * scale).round() as u32).max(1),
+                    ((f64::from(image.height()) * scale).round() as u32).max(1),
+                );
+                let small =
+                    imageops::resize(&image.to_rgba8(), size.0, size.1, FilterType::Triangle);
+                imageops::overlay(
+                    &mut page,
+                    &small,
+                    i64::from(x + (thumb - size.0) / 2),
+                    i64::from(y + (thumb - size.1) / 2),
+                );
+            }
+            if let Some(label) = font.wrap(&tile.name, LABEL_SIZE, thumb as f32, 1).first() {
+                let baseline = (y + thumb) as f32 + GAP as f32 / 4.0 + font.ascent(LABEL_SIZE);
+                font.draw(&mut page, label, (x as f32, baseline), LABEL_SIZE, LABEL);
+            }
+        }
+        page
+    }
+}
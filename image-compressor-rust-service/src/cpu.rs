@@ -0,0 +1,484 @@
+// image-compressor-rust-service/src/cpu.rs
+
+//! CPU budget shared by every compression.
+//!
+//! Each compression holds one thread of the budget while it runs, so at most
+//! `budget` compressions run at once and the rest wait. A large image may
+//! then borrow extra threads, up to `max_threads_per_image`, but only ones
+//! that are free at that moment: it never waits for them, so a single huge
+//! image cannot starve concurrent requests. Waiting requests are served by
+//! [`Priority`], with weights that keep low priorities from starving.
+//!
+//! Background work (see [`BackgroundPool`]) runs outside this budget, on a
+//! few dedicated threads with a lower scheduling priority, so it only takes
+//! CPU time interactive requests leave unused.
+
+use crate::simd::SimdMode;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use tracing::warn;
+
+/// Settings for the CPU budget.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CpuConfig {
+    /// Threads available to compression, shared by concurrent requests and
+    /// the extra threads of large images. `0` means one per CPU core.
+    pub budget: usize,
+    /// Most threads a single image may use.
+    pub max_threads_per_image: usize,
+    /// Images (all frames together) with fewer pixels than this always run
+    /// on one thread; splitting them costs more than it saves.
+    pub parallel_min_pixels: u64,
+    /// Priority classes of waiting requests.
+    pub priorities: PriorityConfig,
+    /// Pool for background jobs.
+    pub background: BackgroundConfig,
+    /// Whether pixel loops may use the CPU's vector extensions.
+    pub simd: SimdMode,
+}
+
+impl Default for CpuConfig {
+    fn default() -> Self {
+        Self {
+            budget: 0,
+            max_threads_per_image: 4,
+            parallel_min_pixels: 4_000_000,
+            priorities: PriorityConfig::default(),
+            background: BackgroundConfig::default(),
+            simd: SimdMode::default(),
+        }
+    }
+}
+
+/// Settings for the background job pool.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackgroundConfig {
+    /// Worker threads; jobs beyond that wait in a queue.
+    pub threads: usize,
+    /// Nice value of the worker threads, from `-20` (highest priority) to
+    /// `19` (lowest). Only applied on Linux.
+    pub nice: i32,
+}
+
+impl Default for BackgroundConfig {
+    fn default() -> Self {
+        Self {
+            threads: 1,
+            nice: 10,
+        }
+    }
+}
+
+impl CpuConfig {
+    /// The budget in threads, with `0` resolved to the number of cores.
+    pub fn budget(&self) -> usize {
+        match self.budget {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+    }
+}
+
+/// Scheduling class of a request, from the `X-Priority` header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub const ALL: [Self; 3] = [Self::High, Self::Normal, Self::Low];
+
+    /// Parses the value of the `X-Priority` header.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "high" => Some(Self::High),
+            "normal" => Some(Self::Normal),
+            "low" => Some(Self::Low),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::High => "high",
+            Self::Normal => "normal",
+            Self::Low => "low",
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Self::High => 0,
+            Self::Normal => 1,
+            Self::Low => 2,
+        }
+    }
+}
+
+/// Which priorities callers get, and how queued requests share threads.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PriorityConfig {
+    /// Highest priority a caller may ask for, and the priority of requests
+    /// that do not ask, unless overridden in `api_keys`.
+    pub max: Priority,
+    /// The same by `X-Api-Key` value, e.g. `premium-key = "high"`.
+    pub api_keys: HashMap<String, Priority>,
+    /// Share of freed threads each class gets while requests are queued.
+    pub weights: PriorityWeights,
+}
+
+impl Default for PriorityConfig {
+    fn default() -> Self {
+        Self {
+            max: Priority::Normal,
+            api_keys: HashMap::new(),
+            weights: PriorityWeights::default(),
+        }
+    }
+}
+
+impl PriorityConfig {
+    /// The priority for a caller with `api_key` asking for `requested`:
+    /// what it asked for, up to its maximum.
+    pub fn priority_for(&self, api_key: Option<&str>, requested: Option<Priority>) -> Priority {
+        let max = api_key
+            .and_then(|key| self.api_keys.get(key))
+            .copied()
+            .unwrap_or(self.max);
+        requested.map_or(max, |requested| requested.min(max))
+    }
+}
+
+/// Dispatch weights per priority class. With the defaults, while all three
+/// classes are queued, six in ten freed threads go to `high`, three to
+/// `normal` and one to `low`, so no class waits forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PriorityWeights {
+    pub high: u32,
+    pub normal: u32,
+    pub low: u32,
+}
+
+impl Default for PriorityWeights {
+    fn default() -> Self {
+        Self {
+            high: 6,
+            normal: 3,
+            low: 1,
+        }
+    }
+}
+
+impl PriorityWeights {
+    fn get(&self, priority: Priority) -> i64 {
+        i64::from(match priority {
+            Priority::High => self.high,
+            Priority::Normal => self.normal,
+            Priority::Low => self.low,
+        })
+        .max(1)
+    }
+}
+
+/// The shared pool of CPU threads.
+///
+/// Requests wait in one queue per [`Priority`]. Whenever a thread frees up
+/// while requests are queued, a smooth weighted round-robin over the
+/// non-empty queues picks who gets it.
+#[derive(Debug, Clone)]
+pub struct CpuBudget {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: Mutex<Dispatch>,
+    weights: PriorityWeights,
+    max_threads_per_image: usize,
+    parallel_min_pixels: u64,
+}
+
+#[derive(Debug)]
+struct Dispatch {
+    /// Threads nobody holds. Only non-zero while every queue is empty.
+    free: usize,
+    queues: [VecDeque<oneshot::Sender<()>>; 3],
+    /// Running round-robin credit per class.
+    credit: [i64; 3],
+}
+
+impl CpuBudget {
+    pub fn new(config: &CpuConfig) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::new(Dispatch {
+                    free: config.budget(),
+                    queues: Default::default(),
+                    credit: [0; 3],
+                }),
+                weights: config.priorities.weights,
+                max_threads_per_image: config.max_threads_per_image.max(1),
+                parallel_min_pixels: config.parallel_min_pixels,
+            }),
+        }
+    }
+
+    /// Waits for a thread to run one compression on.
+    pub async fn acquire(&self, priority: Priority) -> Threads {
+        let receiver = {
+            let mut state = self.inner.state.lock().unwrap();
+            if state.free > 0 {
+                state.free -= 1;
+                return self.threads(1);
+            }
+            let (sender, receiver) = oneshot::channel();
+            state.queues[priority.index()].push_back(sender);
+            receiver
+        };
+        metrics::increment_gauge!("compress_queued", 1.0, "priority" => priority.name());
+        let mut waiter = Waiter {
+            budget: self,
+            receiver,
+            granted: false,
+        };
+        // The senders live in the queues until they are used, and `self`
+        // keeps the queues alive.
+        let _ = (&mut waiter.receiver).await;
+        waiter.granted = true;
+        metrics::decrement_gauge!("compress_queued", 1.0, "priority" => priority.name());
+        self.threads(1)
+    }
+
+    /// Threads not held by any compression right now.
+    pub fn available(&self) -> usize {
+        self.inner.state.lock().unwrap().free
+    }
+
+    /// Requests waiting for a thread, all priorities together.
+    pub fn queued(&self) -> usize {
+        let state = self.inner.state.lock().unwrap();
+        state.queues.iter().map(VecDeque::len).sum()
+    }
+
+    fn threads(&self, count: usize) -> Threads {
+        Threads {
+            budget: Some(self.clone()),
+            count,
+        }
+    }
+
+    /// Takes up to `wanted` threads if they are free, without waiting.
+    fn try_take(&self, wanted: usize) -> usize {
+        let mut state = self.inner.state.lock().unwrap();
+        let taken = wanted.min(state.free);
+        state.free -= taken;
+        taken
+    }
+
+    /// Hands `count` threads to queued requests, or frees them.
+    fn release(&self, count: usize) {
+        let mut state = self.inner.state.lock().unwrap();
+        for _ in 0..count {
+            loop {
+                let Some(next) = self.next_queue(&mut state) else {
+                    state.free += 1;
+                    break;
+                };
+                let sender = state.queues[next].pop_front().expect("queue is not empty");
+                // A waiter that gave up has closed its receiver; try the next.
+                if sender.send(()).is_ok() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Smooth weighted round-robin over the non-empty queues.
+    fn next_queue(&self, state: &mut Dispatch) -> Option<usize> {
+        let active: Vec<usize> = Priority::ALL
+            .iter()
+            .map(|p| p.index())
+            .filter(|&i| !state.queues[i].is_empty())
+            .collect();
+        let total: i64 = active
+            .iter()
+            .map(|&i| self.inner.weights.get(Priority::ALL[i]))
+            .sum();
+        for &i in &active {
+            state.credit[i] += self.inner.weights.get(Priority::ALL[i]);
+        }
+        let chosen = *active
+            .iter()
+            .max_by_key(|&&i| (state.credit[i], -(i as i64)))?;
+        state.credit[chosen] -= total;
+        Some(chosen)
+    }
+}
+
+/// A queued [`CpuBudget::acquire`]. Returns a thread that was handed over
+/// just as the caller gave up.
+struct Waiter<'a> {
+    budget: &'a CpuBudget,
+    receiver: oneshot::Receiver<()>,
+    granted: bool,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if !self.granted {
+            self.receiver.close();
+            if self.receiver.try_recv().is_ok() {
+                self.budget.release(1);
+            }
+        }
+    }
+}
+
+/// The threads held by one compression, returned to the budget on drop.
+#[derive(Debug)]
+pub struct Threads {
+    budget: Option<CpuBudget>,
+    count: usize,
+}
+
+impl Threads {
+    /// A single thread outside any budget, for library callers that manage
+    /// their own concurrency.
+    pub fn single() -> Self {
+        Self {
+            budget: None,
+            count: 1,
+        }
+    }
+
+    /// Number of threads held.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Borrows as many free threads as an image of `pixels` is allowed, up
+    /// to the per-image maximum, and returns the new count. Never waits, and
+    /// never takes threads queued requests are waiting for.
+    pub fn reserve_for(&mut self, pixels: u64) -> usize {
+        match &self.budget {
+            Some(budget) if pixels >= budget.inner.parallel_min_pixels => self.reserve(),
+            _ => self.count,
+        }
+    }
+
+    /// Borrows free threads up to the per-image maximum, whatever the size
+    /// of the image, for work made of independent jobs each worth a thread,
+    /// such as the trial encodes of a byte budget. Returns the new count.
+    /// Never waits.
+    pub fn reserve(&mut self) -> usize {
+        let Some(budget) = &self.budget else {
+            return self.count;
+        };
+        let wanted = budget
+            .inner
+            .max_threads_per_image
+            .saturating_sub(self.count);
+        self.count += budget.try_take(wanted);
+        self.count
+    }
+}
+
+impl Drop for Threads {
+    fn drop(&mut self) {
+        if let Some(budget) = &self.budget {
+            budget.release(self.count);
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A small pool of low-priority threads for work nobody is waiting on
+/// interactively, such as the startup warm-up. It is separate from the
+/// [`CpuBudget`], so queued background jobs never delay a request.
+#[derive(Debug, Clone)]
+pub struct BackgroundPool {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl BackgroundPool {
+    /// Starts the worker threads.
+    pub fn new(config: &BackgroundConfig) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        for index in 0..config.threads.max(1) {
+            let queue = queue.clone();
+            let nice = config.nice;
+            std::thread::Builder::new()
+                .name(format!("background-{}", index))
+                .spawn(move || {
+                    if let Err(e) = set_nice(nice) {
+                        warn!("Background worker keeps its default priority: {:#}", e);
+                    }
+                    // The lock is only held while waiting for the next job.
+                    while let Ok(job) = { queue.lock().unwrap().recv() } {
+                        metrics::decrement_gauge!("background_jobs_queued", 1.0);
+                        // A panicking job fails on its own; the worker lives on.
+                        let _ = std::panic::catch_unwind(AssertUnwindSafe(job));
+                    }
+                })
+                .expect("failed to spawn a background worker");
+        }
+        Self { jobs }
+    }
+
+    /// Queues `job` and returns without waiting for it.
+    pub fn spawn(&self, job: impl FnOnce() + Send + 'static) {
+        metrics::increment_counter!("background_jobs_total");
+        metrics::increment_gauge!("background_jobs_queued", 1.0);
+        // The workers only stop once every sender is gone.
+        let _ = self.jobs.send(Box::new(job));
+    }
+
+    /// Queues `job` and waits for its result.
+    pub async fn run<T: Send + 'static>(
+        &self,
+        job: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T> {
+        let (result, receiver) = oneshot::channel();
+        self.spawn(move || {
+            let _ = result.send(job());
+        });
+        receiver
+            .await
+            .map_err(|_| anyhow!("The background job panicked"))
+    }
+}
+
+/// Sets the nice value of the calling thread.
+#[cfg(target_os = "linux")]
+fn set_nice(nice: i32) -> Result<()> {
+    // SAFETY: `gettid` has no preconditions; on Linux `setpriority` with a
+    // thread ID changes only that thread.
+    let result = unsafe {
+        let tid = libc::gettid();
+        libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, nice)
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error()).context("setpriority failed");
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_nice(_nice: i32) -> Result<()> {
+    anyhow::bail!("thread priorities are only supported on Linux")
+}
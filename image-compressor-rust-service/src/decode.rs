@@ -0,0 +1,492 @@
+// image-compressor-rust-service/src/decode.rs
+
+use crate::animation::FrameLimit;
+use crate::pool;
+use anyhow::{anyhow, Context, Result};
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::error::{ImageError, UnsupportedErrorKind};
+use image::io::Limits;
+use image::{
+    AnimationDecoder, ColorType, DynamicImage, Frame, GrayAlphaImage, GrayImage, ImageBuffer,
+    ImageDecoder, ImageFormat, ImageResult, Luma, RgbImage, RgbaImage,
+};
+use jpeg_decoder::PixelFormat;
+use lcms2::{Intent, Profile, Transform};
+use std::fmt;
+use std::io::{self, Cursor};
+use std::time::Duration;
+
+/// JPEG files always start with the SOI marker followed by another marker.
+const JPEG_MAGIC: [u8; 3] = [0xFF, 0xD8, 0xFF];
+/// PNG (and APNG) signature.
+const PNG_MAGIC: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Why an input could not be decoded.
+///
+/// Decoding failures carry one as the context of the decoder's own error,
+/// so callers can tell them apart with `downcast_ref::<DecodeError>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The data ends before the image does, e.g. after an interrupted upload.
+    Truncated,
+    /// The data is not in a container format the service decodes.
+    UnsupportedContainer,
+    /// The container is supported, but the image uses a codec feature the
+    /// decoder lacks, such as arithmetic-coded or lossless JPEG.
+    UnsupportedFeature,
+    /// The data is malformed.
+    Corrupt,
+    /// The image would take more memory to decode than the service allows.
+    TooLarge,
+}
+
+impl DecodeError {
+    pub const ALL: [DecodeError; 5] = [
+        DecodeError::Truncated,
+        DecodeError::UnsupportedContainer,
+        DecodeError::UnsupportedFeature,
+        DecodeError::Corrupt,
+        DecodeError::TooLarge,
+    ];
+
+    /// The snake_case name used in API error details.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Truncated => "truncated",
+            Self::UnsupportedContainer => "unsupported_container",
+            Self::UnsupportedFeature => "unsupported_feature",
+            Self::Corrupt => "corrupt",
+            Self::TooLarge => "too_large",
+        }
+    }
+
+    /// Classifies a failure of `jpeg-decoder`.
+    fn of_jpeg(error: &jpeg_decoder::Error) -> Self {
+        match error {
+            jpeg_decoder::Error::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                Self::Truncated
+            }
+            jpeg_decoder::Error::Unsupported(_) => Self::UnsupportedFeature,
+            _ => Self::Corrupt,
+        }
+    }
+
+    /// Classifies a failure of the `image` decoders.
+    fn of_image(error: &ImageError) -> Self {
+        match error {
+            ImageError::IoError(e) if e.kind() == io::ErrorKind::UnexpectedEof => Self::Truncated,
+            ImageError::Unsupported(e) => match e.kind() {
+                UnsupportedErrorKind::Format(_) => Self::UnsupportedContainer,
+                _ => Self::UnsupportedFeature,
+            },
+            ImageError::Limits(_) => Self::TooLarge,
+            _ => Self::Corrupt,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Truncated => "Failed to decode input image. The data is truncated.",
+            Self::UnsupportedContainer => {
+                "Failed to decode input image. The format is not supported."
+            }
+            Self::UnsupportedFeature => {
+                "Failed to decode input image. It uses a codec feature that is not supported."
+            }
+            Self::Corrupt => "Failed to decode input image. The data is corrupted.",
+            Self::TooLarge => "Failed to decode input image. It is too large to decode.",
+        })
+    }
+}
+
+/// Wraps a failure of the `image` decoders with its [`DecodeError`].
+fn image_error(error: ImageError) -> anyhow::Error {
+    let kind = DecodeError::of_image(&error);
+    anyhow::Error::new(error).context(kind)
+}
+
+/// Decodes an image from memory into a `DynamicImage`.
+///
+/// JPEG inputs are decoded with `jpeg-decoder` directly so that CMYK/YCCK
+/// files (common in print workflows) can be converted to RGB correctly.
+/// PNG and WebP inputs are decoded into a buffer from the [`pool`] when they
+/// have 8-bit samples. Every other format is handed to
+/// `image::load_from_memory`, which detects the format automatically.
+/// Interlaced (Adam7) PNGs are de-interlaced by the `png` crate, and for APNG
+/// files this returns the default image.
+///
+/// # Arguments
+///
+/// * `input_bytes` - A byte slice `&[u8]` containing the raw data of the input image.
+///
+/// # Returns
+///
+/// * `Result<DynamicImage>` - The decoded image, or an `anyhow::Error` with a
+///   [`DecodeError`] if the data could not be decoded.
+///
+pub fn decode_image(input_bytes: &[u8]) -> Result<DynamicImage> {
+    if input_bytes.starts_with(&JPEG_MAGIC) {
+        return decode_jpeg(input_bytes);
+    }
+    if input_bytes.starts_with(&PNG_MAGIC) {
+        let decoder = PngDecoder::with_limits(Cursor::new(input_bytes), Limits::default())
+            .map_err(image_error)?;
+        return decode_pooled(decoder).map_err(image_error);
+    }
+    if image::guess_format(input_bytes).ok() == Some(ImageFormat::WebP) {
+        let decoder = WebPDecoder::new(Cursor::new(input_bytes)).map_err(image_error)?;
+        return decode_pooled(decoder).map_err(image_error);
+    }
+
+    image::load_from_memory(input_bytes).map_err(image_error)
+}
+
+/// Decodes an image with 8-bit samples into a buffer from the [`pool`],
+/// within the allocation limit `image::load_from_memory` applies. Other
+/// images are decoded as `image` does.
+fn decode_pooled<'a>(mut decoder: impl ImageDecoder<'a>) -> ImageResult<DynamicImage> {
+    let total = decoder.total_bytes();
+    let mut limits = Limits::default();
+    limits.reserve(total)?;
+    decoder.set_limits(limits)?;
+
+    let (width, height) = decoder.dimensions();
+    let color = decoder.color_type();
+    if !matches!(
+        color,
+        ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8
+    ) {
+        return DynamicImage::from_decoder(decoder);
+    }
+    // Within the limit, so it fits in memory.
+    let mut pixels = pool::take(total as usize);
+    pixels.resize(total as usize, 0);
+    decoder.read_image(&mut pixels)?;
+    let image = match color {
+        ColorType::L8 => GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        ColorType::La8 => {
+            GrayAlphaImage::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8)
+        }
+        ColorType::Rgb8 => RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        _ => RgbaImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+    };
+    // `total_bytes` is the size of exactly such a buffer.
+    Ok(image.expect("decoded buffer matches the image dimensions"))
+}
+
+/// Decodes an image like [`decode_image`], but recovers what it can of a
+/// truncated or corrupt JPEG or PNG instead of failing.
+///
+/// A JPEG is ended with an EOI marker where its data stops, and the decoder
+/// fills the missing blocks in grey. If it is corrupt, it is cut back to the
+/// longest prefix that decodes, found by bisection, which costs a decode per
+/// halving. A non-interlaced PNG keeps the rows read before the break,
+/// ignoring checksums, and the rest is transparent black.
+///
+/// # Returns
+///
+/// * `Result<(DynamicImage, bool)>` - The image and whether it was recovered
+///   rather than decoded whole, or the error of [`decode_image`] if nothing
+///   could be.
+///
+pub fn salvage_image(input_bytes: &[u8]) -> Result<(DynamicImage, bool)> {
+    let error = match decode_image(input_bytes) {
+        Ok(image) => return Ok((image, false)),
+        Err(e) => e,
+    };
+    let salvageable = matches!(
+        error.downcast_ref::<DecodeError>(),
+        Some(DecodeError::Truncated | DecodeError::Corrupt)
+    );
+    let salvaged = if !salvageable {
+        None
+    } else if input_bytes.starts_with(&JPEG_MAGIC) {
+        salvage_jpeg(input_bytes)
+    } else if input_bytes.starts_with(&PNG_MAGIC) {
+        salvage_png(input_bytes)
+    } else {
+        None
+    };
+    match salvaged {
+        Some(image) => Ok((image, true)),
+        None => Err(error),
+    }
+}
+
+/// The longest prefix of a broken JPEG that decodes once ended with EOI.
+fn salvage_jpeg(input_bytes: &[u8]) -> Option<DynamicImage> {
+    // Every prefix shares the header, so one too large to decode is not
+    // bisected at all.
+    let mut decoder = jpeg_decoder::Decoder::new(input_bytes);
+    decoder.read_info().ok()?;
+    check_jpeg_size(&decoder.info()?).ok()?;
+
+    let ended = |len: usize| {
+        let mut data = input_bytes[..len].to_vec();
+        data.extend_from_slice(&[0xFF, 0xD9]);
+        decode_jpeg(&data).ok()
+    };
+    if let Some(image) = ended(input_bytes.len()) {
+        return Some(image);
+    }
+    // `high` never decodes; the best image so far came from `low`.
+    let (mut low, mut high) = (0, input_bytes.len());
+    let mut best = None;
+    while low + 1 < high {
+        let middle = low + (high - low) / 2;
+        match ended(middle) {
+            Some(image) => {
+                best = Some(image);
+                low = middle;
+            }
+            None => high = middle,
+        }
+    }
+    best
+}
+
+/// The rows of a broken, non-interlaced PNG read before it breaks.
+fn salvage_png(input_bytes: &[u8]) -> Option<DynamicImage> {
+    let mut decoder = png::Decoder::new(Cursor::new(input_bytes));
+    decoder.ignore_checksums(true);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().ok()?;
+    if reader.info().interlaced {
+        return None;
+    }
+    let (width, height) = reader.info().size();
+    let line = reader.output_line_size(width);
+    // Expanded to 8-bit samples above.
+    check_size(width, height, reader.output_color_type().0.samples() as u64).ok()?;
+    let mut pixels = vec![0; line * height as usize];
+    let mut rows = 0;
+    while let Ok(Some(row)) = reader.next_row() {
+        pixels[rows * line..][..line].copy_from_slice(row.data());
+        rows += 1;
+    }
+    if rows == 0 {
+        return None;
+    }
+    match reader.output_color_type().0 {
+        png::ColorType::Grayscale => {
+            GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8)
+        }
+        png::ColorType::GrayscaleAlpha => {
+            GrayAlphaImage::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8)
+        }
+        png::ColorType::Rgb => {
+            RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8)
+        }
+        png::ColorType::Rgba => {
+            RgbaImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8)
+        }
+        // Expanded to RGB or RGBA above.
+        png::ColorType::Indexed => None,
+    }
+}
+
+/// Reads the width and height of an input from its header, without
+/// decoding any pixels.
+pub fn probe_dimensions(input_bytes: &[u8]) -> Result<(u32, u32)> {
+    image::io::Reader::new(std::io::Cursor::new(input_bytes))
+        .with_guessed_format()?
+        .into_dimensions()
+        .context("Failed to read the input image dimensions.")
+}
+
+/// Decodes the frames of an animated input, up to `limit`.
+///
+/// Frames are fully composited RGBA canvases (APNG blend and dispose operations
+/// already applied), each carrying its own delay. Decoding stops at the limit,
+/// and an animation whose frames up to it would take more than the allocation
+/// limit together is rejected before any frame is decoded, going by the frame
+/// count in its `acTL` chunk.
+///
+/// # Returns
+///
+/// * `Result<Option<Vec<Frame>>>` - `None` when the input is not animated, so the
+///   caller can fall back to [`decode_image`].
+///
+pub fn decode_frames(input_bytes: &[u8], limit: FrameLimit) -> Result<Option<Vec<Frame>>> {
+    if !input_bytes.starts_with(&PNG_MAGIC) {
+        return Ok(None);
+    }
+
+    let decoder = PngDecoder::with_limits(Cursor::new(input_bytes), Limits::default())
+        .map_err(image_error)?;
+    if !decoder.is_apng() {
+        return Ok(None);
+    }
+    let count = (apng_frame_count(input_bytes)? as usize).min(limit.frames);
+    let (width, height) = decoder.dimensions();
+    check_size(width, height, 4 * count as u64)?;
+
+    // Frames past the announced count would not have been checked.
+    let mut decoded = decoder.apng().into_frames().take(count);
+    let mut frames = Vec::new();
+    let mut elapsed = Duration::ZERO;
+    while limit.duration.is_none_or(|max| elapsed < max) {
+        let Some(frame) = decoded.next() else {
+            break;
+        };
+        let frame = frame
+            .map_err(image_error)
+            .context("Failed to decode APNG animation frames.")?;
+        elapsed += Duration::from(frame.delay());
+        frames.push(frame);
+    }
+    Ok(Some(frames))
+}
+
+/// The number of frames the `acTL` chunk of an APNG announces.
+fn apng_frame_count(input_bytes: &[u8]) -> Result<u32> {
+    let reader = png::Decoder::new(input_bytes)
+        .read_info()
+        .map_err(|e| anyhow!(e))
+        .context(DecodeError::Corrupt)?;
+    Ok(reader
+        .info()
+        .animation_control()
+        .map_or(1, |control| control.num_frames))
+}
+
+/// Decodes a JPEG, converting CMYK data to RGB.
+///
+/// The header is read first, and a JPEG that would decode to more than the
+/// allocation limit `image::load_from_memory` applies is rejected before
+/// any pixel is: `jpeg-decoder` itself allocates whatever the header asks
+/// for, and a failed allocation aborts the process.
+fn decode_jpeg(input_bytes: &[u8]) -> Result<DynamicImage> {
+    let mut decoder = jpeg_decoder::Decoder::new(input_bytes);
+    decoder.read_info().map_err(jpeg_error)?;
+    let info = decoder
+        .info()
+        .ok_or_else(|| anyhow!("JPEG decoder returned no image information."))
+        .context(DecodeError::Corrupt)?;
+    check_jpeg_size(&info)?;
+    let pixels = decoder.decode().map_err(jpeg_error)?;
+    let (width, height) = (u32::from(info.width), u32::from(info.height));
+
+    let image = match info.pixel_format {
+        PixelFormat::L8 => GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        PixelFormat::L16 => {
+            // `jpeg-decoder` emits 16-bit samples in big-endian byte order.
+            let samples = pixels
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            pool::give(pixels);
+            ImageBuffer::<Luma<u16>, _>::from_raw(width, height, samples)
+                .map(DynamicImage::ImageLuma16)
+        }
+        PixelFormat::RGB24 => {
+            RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8)
+        }
+        PixelFormat::CMYK32 => {
+            let mut cmyk = pixels;
+            // `jpeg-decoder` assumes the Adobe convention of inverted CMYK samples and
+            // flips them back. Files without an Adobe APP14 segment store plain CMYK,
+            // so undo that flip or the output comes out as a colour negative.
+            if !has_adobe_marker(input_bytes) {
+                cmyk.iter_mut().for_each(|v| *v = 255 - *v);
+            }
+            let rgb = match decoder.icc_profile() {
+                Some(icc) => cmyk_to_rgb_icc(&cmyk, &icc).unwrap_or_else(|e| {
+                    tracing::warn!("Ignoring unusable embedded CMYK ICC profile: {:#}", e);
+                    cmyk_to_rgb_naive(&cmyk)
+                }),
+                None => cmyk_to_rgb_naive(&cmyk),
+            };
+            pool::give(cmyk);
+            RgbImage::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8)
+        }
+    };
+
+    image
+        .ok_or_else(|| anyhow!("JPEG pixel buffer does not match the declared dimensions."))
+        .context(DecodeError::Corrupt)
+}
+
+/// Wraps a failure of `jpeg-decoder` with its [`DecodeError`].
+fn jpeg_error(error: jpeg_decoder::Error) -> anyhow::Error {
+    let kind = DecodeError::of_jpeg(&error);
+    anyhow::Error::new(error).context(kind)
+}
+
+/// Fails when a JPEG with the header `info` would decode to more than the
+/// allocation limit.
+fn check_jpeg_size(info: &jpeg_decoder::ImageInfo) -> Result<()> {
+    check_size(
+        u32::from(info.width),
+        u32::from(info.height),
+        info.pixel_format.pixel_bytes() as u64,
+    )
+}
+
+/// Fails when `width` x `height` pixels of `bytes_per_pixel` bytes are more
+/// than the default [`Limits`] let a decoder allocate.
+pub(crate) fn check_size(width: u32, height: u32, bytes_per_pixel: u64) -> Result<()> {
+    let bytes = (u64::from(width) * u64::from(height)).saturating_mul(bytes_per_pixel);
+    Limits::default().reserve(bytes).map_err(image_error)
+}
+
+/// Converts CMYK samples (0 = no ink) to sRGB through the embedded ICC profile.
+fn cmyk_to_rgb_icc(cmyk: &[u8], icc: &[u8]) -> Result<Vec<u8>> {
+    let input_profile = Profile::new_icc(icc).context("Failed to parse embedded ICC profile.")?;
+    let output_profile = Profile::new_srgb();
+    let transform = Transform::<u8, u8>::new(
+        &input_profile,
+        lcms2::PixelFormat::CMYK_8,
+        &output_profile,
+        lcms2::PixelFormat::RGB_8,
+        Intent::Perceptual,
+    )
+    .context("Embedded ICC profile cannot be used for a CMYK to sRGB transform.")?;
+
+    let mut rgb = pool::take(cmyk.len() / 4 * 3);
+    rgb.resize(cmyk.len() / 4 * 3, 0);
+    transform.transform_pixels(cmyk, &mut rgb);
+    Ok(rgb)
+}
+
+/// Converts CMYK samples (0 = no ink) to RGB with the uncalibrated formula.
+fn cmyk_to_rgb_naive(cmyk: &[u8]) -> Vec<u8> {
+    let mut rgb = pool::take(cmyk.len() / 4 * 3);
+    for pixel in cmyk.chunks_exact(4) {
+        let k = 255 - u16::from(pixel[3]);
+        for &ink in &pixel[..3] {
+            rgb.push(((255 - u16::from(ink)) * k / 255) as u8);
+        }
+    }
+    rgb
+}
+
+/// Scans the JPEG header segments for an Adobe APP14 marker.
+fn has_adobe_marker(data: &[u8]) -> bool {
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return false;
+        }
+        let marker = data[pos + 1];
+        // Fill bytes may precede a marker.
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        // Start of scan: no more header segments follow.
+        if marker == 0xDA {
+            return false;
+        }
+        let length = usize::from(u16::from_be_bytes([data[pos + 2], data[pos + 3]]));
+        let segment = data.get(pos + 4..pos + 2 + length).unwrap_or(&[]);
+        if marker == 0xEE && segment.starts_with(b"Adobe") {
+            return true;
+        }
+        pos += 2 + length;
+    }
+    false
+}
@@ -0,0 +1,139 @@
+// image-compressor-rust-service/src/diff.rs
+
+//! Pixel comparison of two equally sized images, for visual regression
+//! tests: difference metrics and a heatmap of where the images differ.
+
+use anyhow::{ensure, Result};
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde::Serialize;
+
+/// Difference metrics of a comparison. Deltas are per channel, RGBA.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiffReport {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels whose largest channel delta exceeds the threshold.
+    pub differing_pixels: u64,
+    /// `differing_pixels` as a fraction of all pixels.
+    pub mismatch_ratio: f64,
+    /// The largest channel delta, from 0 to 255.
+    pub max_delta: u8,
+    /// The mean channel delta, from 0 (identical) to 1.
+    pub mean_delta: f64,
+    /// Peak signal-to-noise ratio of the colour channels in dB; `None` for
+    /// identical images.
+    pub psnr: Option<f64>,
+}
+
+/// Compares `candidate` with `baseline`.
+///
+/// Pixels whose largest channel delta is at most `threshold` count as
+/// equal. The heatmap shows equal pixels as a faded grayscale copy of the
+/// baseline and differing ones from yellow (slightly off) to red (fully
+/// different).
+pub fn compare(
+    baseline: &DynamicImage,
+    candidate: &DynamicImage,
+    threshold: u8,
+) -> Result<(DiffReport, RgbaImage)> {
+    let (width, height) = (baseline.width(), baseline.height());
+    ensure!(
+        (candidate.width(), candidate.height()) == (width, height),
+        "images differ in size: {}x{} and {}x{}",
+        width,
+        height,
+        candidate.width(),
+        candidate.height()
+    );
+    let (baseline, candidate) = (baseline.to_rgba8(), candidate.to_rgba8());
+
+    let mut heatmap = RgbaImage::new(width, height);
+    let (mut differing_pixels, mut max_delta) = (0u64, 0u8);
+    let (mut delta_sum, mut squared_sum) = (0u64, 0u64);
+    for ((b, c), out) in baseline
+        .pixels()
+        .zip(candidate.pixels())
+        .zip(heatmap.pixels_mut())
+    {
+        let deltas: Vec<u8> = b.0.iter().zip(c.0).map(|(&b, c)| b.abs_diff(c)).collect();
+        let delta = deltas.iter().copied().max().unwrap_or(0);
+        delta_sum += deltas.iter().map(|&d| u64::from(d)).sum::<u64>();
+        squared_sum += deltas[..3]
+            .iter()
+            .map(|&d| u64::from(d) * u64::from(d))
+            .sum::<u64>();
+        max_delta = max_delta.max(delta);
+
+        *out = if delta > threshold {
+            differing_pixels += 1;
+            Rgba([255, 255 - delta, 0, 255])
+        } else {
+            let [r, g, b, _] = b.0;
+            let luma = 0.2126 * f64::from(r) + 0.7152 * f64::from(g) + 0.0722 * f64::from(b);
+            let faded = (255.0 - (255.0 - luma) * 0.25).round() as u8;
+            Rgba([faded, faded, faded, 255])
+        };
+    }
+
+    let pixels = u64::from(width) * u64::from(height);
+    let mse = squared_sum as f64 / (pixels * 3).max(1) as f64;
+    let report = DiffReport {
+        width,
+        height,
+        differing_pixels,
+        mismatch_ratio: differing_pixels as f64 / pixels.max(1) as f64,
+        max_delta,
+        mean_delta: delta_sum as f64 / ((pixels * 4).max(1) as f64 * 255.0),
+        psnr: (mse > 0.0).then(|| 10.0 * (255.0 * 255.0 / mse).log10()),
+    };
+    Ok((report, heatmap))
+}
+
+/// Side of the windows [`ssim`] compares, and the distance between them.
+const SSIM_WINDOW: u32 = 8;
+const SSIM_STRIDE: u32 = 4;
+
+/// Structural similarity of the luma of two equally sized images, from 1
+/// (identical) down; the mean over 8x8 windows, every 4 pixels. Images
+/// smaller than a window are compared as one.
+pub fn ssim(baseline: &DynamicImage, candidate: &DynamicImage) -> Result<f64> {
+    let (width, height) = (baseline.width(), baseline.height());
+    ensure!(
+        (candidate.width(), candidate.height()) == (width, height),
+        "images differ in size: {}x{} and {}x{}",
+        width,
+        height,
+        candidate.width(),
+        candidate.height()
+    );
+    let (a, b) = (baseline.to_luma8(), candidate.to_luma8());
+    let (c1, c2) = ((0.01f64 * 255.0).powi(2), (0.03f64 * 255.0).powi(2));
+    let (window_width, window_height) = (SSIM_WINDOW.min(width), SSIM_WINDOW.min(height));
+
+    let (mut total, mut windows) = (0.0, 0u64);
+    for top in (0..=height - window_height).step_by(SSIM_STRIDE as usize) {
+        for left in (0..=width - window_width).step_by(SSIM_STRIDE as usize) {
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) =
+                (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in top..top + window_height {
+                for x in left..left + window_width {
+                    let (pa, pb) = (f64::from(a[(x, y)].0[0]), f64::from(b[(x, y)].0[0]));
+                    sum_a += pa;
+                    sum_b += pb;
+                    sum_aa += pa * pa;
+                    sum_bb += pb * pb;
+                    sum_ab += pa * pb;
+                }
+            }
+            let n = f64::from(window_width * window_height);
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + c1) * (2.0 * covariance + c2))
+                / ((mean_a * mean_a + mean_b * mean_b + c1) * (var_a + var_b + c2));
+            windows += 1;
+        }
+    }
+    Ok(total / windows.max(1) as f64)
+}
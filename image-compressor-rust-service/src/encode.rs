@@ -0,0 +1,392 @@
+// image-compressor-rust-service/src/encode.rs
+
+use crate::jpeg::{self, CustomTables, QuantTables};
+use crate::options::Effort;
+use crate::palette::Indexed;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use image::{DynamicImage, Frame, ImageOutputFormat};
+use serde::Deserialize;
+use std::fmt;
+use std::io::Cursor;
+use std::str::FromStr;
+use std::time::Instant;
+use webp::{AnimEncoder, AnimFrame, WebPConfig};
+
+/// Advanced encoder settings, per output format (`[encoders]` in the config
+/// file). Requests only choose the quality and effort; these trade density
+/// against CPU for every output of the instance.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncoderConfig {
+    pub jpeg: JpegTuning,
+    pub webp: WebpTuning,
+}
+
+impl EncoderConfig {
+    pub fn validate(&self) -> Result<()> {
+        self.jpeg.validate().context("encoders.jpeg")?;
+        self.webp.validate().context("encoders.webp")
+    }
+}
+
+/// JPEG encoder settings.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JpegTuning {
+    /// Quantization tables, scaled by the quality. Outputs with other than
+    /// the standard tables are encoded by [`crate::jpeg`].
+    pub tables: QuantTables,
+    /// The encoder of outputs with the standard tables.
+    pub backend: JpegBackend,
+    /// A share of outputs encoded by another backend, to compare it with
+    /// `backend` before switching over.
+    pub canary: Option<Canary>,
+}
+
+/// A backend trialled on a share of the outputs. Which inputs take it
+/// follows from a hash of their bytes, so the same input is always encoded
+/// the same way, and deterministic requests never take it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Canary {
+    pub backend: JpegBackend,
+    /// Share of the inputs, from 0 to 100.
+    pub percent: u8,
+}
+
+/// An implementation of the JPEG encoder. The encoder, and the
+/// `jpeg_encode_seconds`, `jpeg_output_bytes` and
+/// `jpeg_encode_failures_total` metrics, are labelled with its name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JpegBackend {
+    /// The image crate's encoder.
+    #[default]
+    Image,
+    /// The in-tree encoder of [`crate::jpeg`].
+    Builtin,
+}
+
+impl JpegBackend {
+    pub const ALL: [JpegBackend; 2] = [JpegBackend::Image, JpegBackend::Builtin];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Image => "image",
+            Self::Builtin => "builtin",
+        }
+    }
+}
+
+impl fmt::Display for JpegBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for JpegBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match Self::ALL
+            .into_iter()
+            .find(|backend| backend.name().eq_ignore_ascii_case(value.trim()))
+        {
+            Some(backend) => Ok(backend),
+            None => bail!(
+                "unknown JPEG backend '{}' (expected image or builtin)",
+                value
+            ),
+        }
+    }
+}
+
+impl FromStr for Canary {
+    type Err = anyhow::Error;
+
+    /// Parses `backend:percent`, e.g. `builtin:5`.
+    fn from_str(value: &str) -> Result<Self> {
+        let Some((backend, percent)) = value.split_once(':') else {
+            bail!("expected backend:percent, e.g. builtin:5, not '{}'", value);
+        };
+        Ok(Self {
+            backend: backend.parse()?,
+            percent: percent.trim().parse().context("invalid percent")?,
+        })
+    }
+}
+
+impl JpegTuning {
+    fn validate(&self) -> Result<()> {
+        if let Some(canary) = self.canary {
+            ensure!(
+                canary.percent <= 100,
+                "canary percent must be 0-100, not {}",
+                canary.percent
+            );
+            ensure!(
+                canary.backend != self.backend,
+                "the canary backend is already the backend"
+            );
+        }
+        Ok(())
+    }
+
+    /// The backend that encodes `input`: the canary for its share of the
+    /// inputs, unless the output must be deterministic.
+    pub fn backend_for(&self, input: &[u8], deterministic: bool) -> JpegBackend {
+        match self.canary {
+            Some(canary)
+                if !deterministic && crc32fast::hash(input) % 100 < u32::from(canary.percent) =>
+            {
+                canary.backend
+            }
+            _ => self.backend,
+        }
+    }
+}
+
+/// libwebp settings of animated WebP outputs. Unset values keep libwebp's
+/// defaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebpTuning {
+    /// Compression method, 0 (fastest) to 6 (densest), in place of the one
+    /// the effort picks.
+    pub method: Option<u8>,
+    /// Number of segments with their own quantizer and filter, 1-4.
+    pub segments: Option<u8>,
+    /// Spatial noise shaping, 0-100: how much bits move from flat areas to
+    /// busy ones.
+    pub sns_strength: Option<u8>,
+    /// Deblocking filter strength, 0 (off) to 100.
+    pub filter_strength: Option<u8>,
+    /// Deblocking filter sharpness, 0 (sharpest) to 7.
+    pub filter_sharpness: Option<u8>,
+    /// Analysis passes, 1-10.
+    pub passes: Option<u8>,
+}
+
+impl WebpTuning {
+    fn validate(&self) -> Result<()> {
+        let ranges = [
+            ("method", self.method, 0..=6),
+            ("segments", self.segments, 1..=4),
+            ("sns_strength", self.sns_strength, 0..=100),
+            ("filter_strength", self.filter_strength, 0..=100),
+            ("filter_sharpness", self.filter_sharpness, 0..=7),
+            ("passes", self.passes, 1..=10),
+        ];
+        for (name, value, range) in ranges {
+            if let Some(value) = value {
+                ensure!(
+                    range.contains(&value),
+                    "{} must be {}-{}, not {}",
+                    name,
+                    range.start(),
+                    range.end(),
+                    value
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&self, config: &mut WebPConfig) {
+        let settings = [
+            (&mut config.method, self.method),
+            (&mut config.segments, self.segments),
+            (&mut config.sns_strength, self.sns_strength),
+            (&mut config.filter_strength, self.filter_strength),
+            (&mut config.filter_sharpness, self.filter_sharpness),
+            (&mut config.pass, self.passes),
+        ];
+        for (setting, value) in settings {
+            if let Some(value) = value {
+                *setting = i32::from(value);
+            }
+        }
+    }
+}
+
+/// Encodes a still image to JPEG with the given quality (1-100).
+pub fn encode_jpeg(image: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    // `Cursor` allows us to treat the `Vec<u8>` buffer as a writable stream.
+    let mut writer = Cursor::new(&mut buffer);
+    image
+        .write_to(&mut writer, ImageOutputFormat::Jpeg(quality))
+        .context("Failed to encode image to JPEG format.")?;
+    Ok(buffer)
+}
+
+/// Encodes a still image to JPEG like [`encode_jpeg`], with the `custom`
+/// tables of a preset if it has some, or else those of `tuning`. Standard
+/// tables are encoded by `backend`, others by the in-tree encoder, which
+/// is the only one that takes them.
+pub fn encode_jpeg_with(
+    image: &DynamicImage,
+    quality: u8,
+    tuning: &JpegTuning,
+    backend: JpegBackend,
+    custom: Option<&CustomTables>,
+) -> Result<Vec<u8>> {
+    let (backend, tables) = match (custom, tuning.tables) {
+        (Some(custom), _) => (JpegBackend::Builtin, custom.scaled(quality)),
+        (None, tables) if tables != QuantTables::Standard => {
+            (JpegBackend::Builtin, tables.scaled(quality))
+        }
+        (None, tables) => (backend, tables.scaled(quality)),
+    };
+    let start = Instant::now();
+    let encoded = match backend {
+        JpegBackend::Image => encode_jpeg(image, quality),
+        JpegBackend::Builtin => {
+            jpeg::encode(image, &tables).context("Failed to encode image to JPEG format.")
+        }
+    };
+    match &encoded {
+        Ok(data) => {
+            let encoder = backend.name();
+            metrics::histogram!("jpeg_encode_seconds", start.elapsed().as_secs_f64(), "encoder" => encoder);
+            metrics::histogram!("jpeg_output_bytes", data.len() as f64, "encoder" => encoder);
+        }
+        Err(_) => {
+            metrics::increment_counter!("jpeg_encode_failures_total", "encoder" => backend.name())
+        }
+    }
+    encoded
+}
+
+/// Encodes a quantized image as an indexed PNG, packing pixels into 1, 2
+/// or 4 bits when the palette is small enough. Palette alpha goes into a
+/// `tRNS` chunk, which is left out for opaque palettes. [`Effort::Fast`]
+/// deflates with the fastest zlib level instead of the best.
+///
+/// Images using more than 16 entries, all of them opaque grey, are written
+/// as 8-bit greyscale instead, which needs no `PLTE` chunk. Fewer entries
+/// pack tighter as indices.
+pub fn encode_png8(image: &Indexed, effort: Effort) -> Result<Vec<u8>> {
+    let width = image.width as usize;
+    let mut used = [false; 256];
+    for &index in &image.indices {
+        used[usize::from(index)] = true;
+    }
+    let mut entries = image.palette.iter().zip(used).filter(|&(_, used)| used);
+    let gray = used.iter().filter(|&&used| used).count() > 16
+        && entries.all(|(&[r, g, b, a], _)| r == g && g == b && a == 255);
+    let (color, depth, data) = if gray {
+        let levels = image
+            .indices
+            .iter()
+            .map(|&index| image.palette[usize::from(index)][0])
+            .collect();
+        (png::ColorType::Grayscale, png::BitDepth::Eight, levels)
+    } else {
+        let (depth, bits) = match image.palette.len() {
+            0..=2 => (png::BitDepth::One, 1),
+            3..=4 => (png::BitDepth::Two, 2),
+            5..=16 => (png::BitDepth::Four, 4),
+            _ => (png::BitDepth::Eight, 8),
+        };
+        let mut data = Vec::with_capacity((width * bits).div_ceil(8) * image.height as usize);
+        for row in image.indices.chunks(width.max(1)) {
+            let mut byte = 0u8;
+            for (x, &index) in row.iter().enumerate() {
+                let shift = 8 - bits - (x * bits) % 8;
+                byte |= index << shift;
+                if shift == 0 {
+                    data.push(byte);
+                    byte = 0;
+                }
+            }
+            if !(width * bits).is_multiple_of(8) {
+                data.push(byte);
+            }
+        }
+        (png::ColorType::Indexed, depth, data)
+    };
+
+    let mut buffer = Vec::new();
+    let mut encoder = png::Encoder::new(&mut buffer, image.width, image.height);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    encoder.set_compression(match effort {
+        Effort::Fast => png::Compression::Fast,
+        Effort::Balanced | Effort::Max => png::Compression::Best,
+    });
+    // Filters predict smooth gradients, which indices are not.
+    encoder.set_filter(png::FilterType::NoFilter);
+    if !gray {
+        encoder.set_palette(
+            image
+                .palette
+                .iter()
+                .flat_map(|c| [c[0], c[1], c[2]])
+                .collect::<Vec<u8>>(),
+        );
+        if image.palette.iter().any(|c| c[3] < 255) {
+            encoder.set_trns(image.palette.iter().map(|c| c[3]).collect::<Vec<u8>>());
+        }
+    }
+    let mut writer = encoder
+        .write_header()
+        .context("Failed to write the PNG header.")?;
+    writer
+        .write_image_data(&data)
+        .context("Failed to encode image to PNG format.")?;
+    writer.finish().context("Failed to finish the PNG.")?;
+    Ok(buffer)
+}
+
+/// Encodes composited animation frames to a lossy animated WebP.
+///
+/// Every frame is expected to cover the full canvas, as produced by
+/// [`crate::decode::decode_frames`]. The animation loops forever. With more
+/// than one thread, libwebp runs its analysis and encoding in parallel; the
+/// output is the same. The effort picks libwebp's method: 1, 4 (its
+/// default) or 6, the slowest and densest, unless `tuning` sets one.
+pub fn encode_animated_webp(
+    frames: &[Frame],
+    quality: u8,
+    threads: usize,
+    effort: Effort,
+    tuning: &WebpTuning,
+) -> Result<Vec<u8>> {
+    let first = frames
+        .first()
+        .ok_or_else(|| anyhow!("Cannot encode an animation without frames."))?;
+    let (width, height) = first.buffer().dimensions();
+
+    let mut config =
+        WebPConfig::new().map_err(|_| anyhow!("Failed to initialize the WebP encoder."))?;
+    config.quality = f32::from(quality);
+    config.thread_level = i32::from(threads > 1);
+    config.method = match effort {
+        Effort::Fast => 1,
+        Effort::Balanced => 4,
+        Effort::Max => 6,
+    };
+    tuning.apply(&mut config);
+
+    let mut encoder = AnimEncoder::new(width, height, &config);
+    encoder.set_loop_count(0);
+
+    // Each frame is added with the timestamp at which it starts.
+    let mut timestamp_ms = 0i32;
+    for frame in frames {
+        encoder.add_frame(AnimFrame::from_rgba(
+            frame.buffer().as_raw(),
+            width,
+            height,
+            timestamp_ms,
+        ));
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        timestamp_ms = timestamp_ms.saturating_add((numer / denom.max(1)) as i32);
+    }
+
+    let webp = encoder
+        .try_encode()
+        .map_err(|e| anyhow!("Failed to encode animated WebP: {:?}", e))?;
+    Ok(webp.to_vec())
+}
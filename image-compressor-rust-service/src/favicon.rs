@@ -0,0 +1,302 @@
+// image-compressor-rust-service/src/favicon.rs
+
+//! Favicon and app icon bundles: every icon a site or PWA links to,
+//! rendered from one source image and packed into a ZIP.
+//!
+//! Which files a bundle holds is an [`IconSet`]: the built-in `favicon`,
+//! `pwa` and `apple` sets, or ones from the config. Non-square sources are
+//! centred on a square rather than cropped, since logos rarely survive
+//! losing their edges. Entries are stored uncompressed (the PNGs and the
+//! ICO are compressed already) with a fixed timestamp, so the same source
+//! always gives the same archive.
+
+use crate::archive;
+use crate::options::Color;
+use anyhow::{bail, Context, Result};
+use image::codecs::ico::{IcoEncoder, IcoFrame};
+use image::imageops::{self, FilterType};
+use image::{ColorType, DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashSet;
+use std::io::Cursor;
+
+/// Names of the built-in icon sets.
+pub const BUILTIN: [&str; 3] = ["favicon", "pwa", "apple"];
+
+/// Largest icon a set may ask for.
+pub const MAX_ICON_SIZE: u32 = 1024;
+
+/// Largest frame an ICO directory can describe.
+const MAX_ICO_SIZE: u32 = 256;
+
+/// The files of a bundle.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IconSet {
+    #[serde(default)]
+    pub icons: Vec<Icon>,
+    /// Sizes packed into `favicon.ico`; none leaves it out.
+    #[serde(default)]
+    pub ico_sizes: Vec<u32>,
+}
+
+/// One PNG icon, rendered at each of its sizes.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Icon {
+    /// File name in the archive, with `{size}` replaced by the size.
+    pub name: String,
+    pub sizes: Vec<u32>,
+    /// Space left around the image on each side, in percent of the icon.
+    /// Maskable icons need 10 to keep their content in the safe zone.
+    #[serde(default)]
+    pub padding: u8,
+    /// Fills the square behind the image; transparent without it.
+    #[serde(default)]
+    pub background: Option<Color>,
+    /// Lists the icon in `site.webmanifest` with this purpose.
+    #[serde(default)]
+    pub purpose: Option<Purpose>,
+}
+
+/// How a manifest icon may be displayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Purpose {
+    /// As it is.
+    Any,
+    /// Cropped to whatever shape the platform uses.
+    Maskable,
+}
+
+impl Purpose {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Any => "any",
+            Self::Maskable => "maskable",
+        }
+    }
+}
+
+impl Icon {
+    fn new(name: &str, sizes: &[u32]) -> Self {
+        Self {
+            name: name.to_string(),
+            sizes: sizes.to_vec(),
+            padding: 0,
+            background: None,
+            purpose: None,
+        }
+    }
+
+    /// The file name for `size`.
+    pub fn file_name(&self, size: u32) -> String {
+        self.name.replace("{size}", &size.to_string())
+    }
+}
+
+impl IconSet {
+    pub fn builtin(name: &str) -> Option<Self> {
+        // iOS shows transparency as black, so Apple icons get a white square.
+        let apple = |name: &str, sizes: &[u32]| Icon {
+            background: Some(Color::WHITE),
+            ..Icon::new(name, sizes)
+        };
+        match name {
+            // What browsers look for, plus Android's two app icons.
+            "favicon" => Some(Self {
+                icons: vec![
+                    Icon::new("favicon-{size}x{size}.png", &[16, 32]),
+                    Icon::new("apple-touch-icon.png", &[180]),
+                    Icon {
+                        purpose: Some(Purpose::Any),
+                        ..Icon::new("android-chrome-{size}x{size}.png", &[192, 512])
+                    },
+                ],
+                ico_sizes: vec![16, 32, 48],
+            }),
+            // Every size PWA installers and app stores ask for, with
+            // maskable variants padded into the safe zone.
+            "pwa" => Some(Self {
+                icons: vec![
+                    Icon {
+                        purpose: Some(Purpose::Any),
+                        ..Icon::new(
+                            "icon-{size}x{size}.png",
+                            &[48, 72, 96, 128, 144, 152, 192, 384, 512],
+                        )
+                    },
+                    Icon {
+                        padding: 10,
+                        background: Some(Color::WHITE),
+                        purpose: Some(Purpose::Maskable),
+                        ..Icon::new("maskable-icon-{size}x{size}.png", &[192, 512])
+                    },
+                    apple("apple-touch-icon.png", &[180]),
+                ],
+                ico_sizes: vec![16, 32, 48],
+            }),
+            // iPhone, iPad and iPad Pro home screens.
+            "apple" => Some(Self {
+                icons: vec![
+                    apple("apple-touch-icon-{size}x{size}.png", &[120, 152, 167, 180]),
+                    apple("apple-touch-icon.png", &[180]),
+                ],
+                ico_sizes: Vec::new(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.icons.is_empty() && self.ico_sizes.is_empty() {
+            bail!("the set has no icons");
+        }
+        if let Some(size) = self
+            .ico_sizes
+            .iter()
+            .find(|&&size| !(1..=MAX_ICO_SIZE).contains(&size))
+        {
+            bail!(
+                "ICO sizes must be between 1 and {}, got {}",
+                MAX_ICO_SIZE,
+                size
+            );
+        }
+        let mut names = HashSet::new();
+        if !self.ico_sizes.is_empty() {
+            names.insert("favicon.ico".to_string());
+        }
+        names.insert("site.webmanifest".to_string());
+        for icon in &self.icons {
+            if icon.sizes.is_empty() {
+                bail!("icon '{}' has no sizes", icon.name);
+            }
+            if icon.padding >= 50 {
+                bail!("icon '{}' padding must be below 50%", icon.name);
+            }
+            for &size in &icon.sizes {
+                if !(1..=MAX_ICON_SIZE).contains(&size) {
+                    bail!(
+                        "icon sizes must be between 1 and {}, got {}",
+                        MAX_ICON_SIZE,
+                        size
+                    );
+                }
+                let name = icon.file_name(size);
+                if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+                    bail!("'{}' is not a plain file name", name);
+                }
+                if !names.insert(name.clone()) {
+                    bail!("'{}' appears twice", name);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What goes into `site.webmanifest`, when one is asked for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub name: String,
+    /// Also used as the background colour of the splash screen.
+    pub theme_color: Option<Color>,
+}
+
+/// Renders the icons of `set` from `image`, plus a manifest if given, as a
+/// ZIP.
+pub fn bundle(image: &DynamicImage, set: &IconSet, manifest: Option<&Manifest>) -> Result<Vec<u8>> {
+    let source = image.to_rgba8();
+    let mut files = Vec::new();
+
+    if !set.ico_sizes.is_empty() {
+        let frames = set
+            .ico_sizes
+            .iter()
+            .map(|&size| {
+                let icon = square(&source, size, 0, None);
+                IcoFrame::as_png(icon.as_raw(), size, size, ColorType::Rgba8)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to encode the ICO frames.")?;
+        let mut ico = Vec::new();
+        IcoEncoder::new(&mut ico)
+            .encode_images(&frames)
+            .context("Failed to encode favicon.ico.")?;
+        files.push(("favicon.ico".to_string(), ico));
+    }
+
+    for icon in &set.icons {
+        for &size in &icon.sizes {
+            let name = icon.file_name(size);
+            let mut png = Vec::new();
+            DynamicImage::ImageRgba8(square(&source, size, icon.padding, icon.background))
+                .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+                .with_context(|| format!("Failed to encode {}.", name))?;
+            files.push((name, png));
+        }
+    }
+
+    if let Some(manifest) = manifest {
+        files.push((
+            "site.webmanifest".to_string(),
+            webmanifest(set, manifest).into_bytes(),
+        ));
+    }
+    Ok(archive::write_zip(&files))
+}
+
+/// `source` scaled to fit a `size`x`size` square less `padding` percent on
+/// each side, centred on `background` (transparency by default).
+fn square(source: &RgbaImage, size: u32, padding: u8, background: Option<Color>) -> RgbaImage {
+    let inner = (size - size * u32::from(padding) * 2 / 100).max(1);
+    let (width, height) = source.dimensions();
+    let scale = f64::from(inner) / f64::from(width.max(height));
+    let scaled_width = ((f64::from(width) * scale).round() as u32).clamp(1, inner);
+    let scaled_height = ((f64::from(height) * scale).round() as u32).clamp(1, inner);
+    let scaled = imageops::resize(source, scaled_width, scaled_height, FilterType::Lanczos3);
+    if scaled.dimensions() == (size, size) && background.is_none() {
+        return scaled;
+    }
+    let fill = background.unwrap_or(Color::TRANSPARENT);
+    let mut canvas = RgbaImage::from_pixel(size, size, Rgba(fill.0));
+    imageops::overlay(
+        &mut canvas,
+        &scaled,
+        i64::from((size - scaled_width) / 2),
+        i64::from((size - scaled_height) / 2),
+    );
+    canvas
+}
+
+fn webmanifest(set: &IconSet, manifest: &Manifest) -> String {
+    let icons: Vec<_> = set
+        .icons
+        .iter()
+        .filter_map(|icon| Some((icon, icon.purpose?)))
+        .flat_map(|(icon, purpose)| {
+            icon.sizes.iter().map(move |&size| {
+                json!({
+                    "src": format!("/{}", icon.file_name(size)),
+                    "sizes": format!("{0}x{0}", size),
+                    "type": "image/png",
+                    "purpose": purpose.as_str(),
+                })
+            })
+        })
+        .collect();
+    let mut value = json!({
+        "name": manifest.name,
+        "short_name": manifest.name,
+        "icons": icons,
+        "display": "standalone",
+    });
+    if let Some(Color([r, g, b, _])) = manifest.theme_color {
+        let hex = format!("#{:02x}{:02x}{:02x}", r, g, b);
+        value["theme_color"] = json!(hex);
+        value["background_color"] = json!(hex);
+    }
+    serde_json::to_string_pretty(&value).expect("the manifest is plain JSON")
+}
@@ -0,0 +1,213 @@
+// image-compressor-rust-service/src/filename.rs
+
+//! Output filenames.
+//!
+//! A filename is rendered from a template such as `{name}-{width}x{height}.{ext}`
+//! and sent in a `Content-Disposition` header. Names supplied by callers are
+//! sanitized first, so they can never carry a path or break out of the
+//! header.
+//!
+//! Watch mode renders output paths the same way, from templates that may
+//! also use `/` and the capture date and camera of the photo, e.g.
+//! `{yyyy}/{mm}/{name}.{ext}`.
+
+use crate::formats::OutputFormat;
+use crate::metadata::CaptureDate;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Longest filename produced, in bytes.
+pub const MAX_LEN: usize = 200;
+
+/// Settings for output filenames.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilenameConfig {
+    /// Template used when the request has none. Placeholders: `{name}` (the
+    /// original name without its extension), `{width}`, `{height}`,
+    /// `{format}` (e.g. `jpeg`) and `{ext}` (e.g. `jpg`).
+    pub template: String,
+    /// `{name}` when the request does not send a usable original name.
+    pub fallback_name: String,
+}
+
+impl Default for FilenameConfig {
+    fn default() -> Self {
+        Self {
+            template: "{name}.{ext}".to_string(),
+            fallback_name: "image".to_string(),
+        }
+    }
+}
+
+/// The values a template is rendered with.
+#[derive(Debug, Clone, Copy)]
+pub struct FilenameVars<'a> {
+    /// The caller's original filename, unsanitized.
+    pub original: Option<&'a str>,
+    pub width: u32,
+    pub height: u32,
+    pub format: OutputFormat,
+}
+
+impl FilenameConfig {
+    /// Renders `template` (or the configured one) and sanitizes the result.
+    pub fn render(&self, template: Option<&str>, vars: &FilenameVars<'_>) -> String {
+        let name = vars
+            .original
+            .and_then(sanitize)
+            .map(|name| stem(&name).to_string())
+            .filter(|stem| !stem.is_empty())
+            .unwrap_or_else(|| self.fallback_name.clone());
+        // `{name}` goes last so placeholders in the caller's name stay literal.
+        let rendered = template
+            .unwrap_or(&self.template)
+            .replace("{width}", &vars.width.to_string())
+            .replace("{height}", &vars.height.to_string())
+            .replace("{format}", vars.format.name())
+            .replace("{ext}", vars.format.extension())
+            .replace("{name}", &name);
+        sanitize(&rendered)
+            .unwrap_or_else(|| format!("{}.{}", self.fallback_name, vars.format.extension()))
+    }
+}
+
+/// The values a path template is rendered with.
+#[derive(Debug, Clone, Copy)]
+pub struct PathVars<'a> {
+    /// The input's filename without its extension.
+    pub name: &'a str,
+    pub width: u32,
+    pub height: u32,
+    pub format: OutputFormat,
+    /// From EXIF; `{yyyy}`, `{mm}` and `{dd}` are `unknown` without it.
+    pub date: Option<CaptureDate>,
+    /// From EXIF; `{camera}` is `unknown` without it.
+    pub camera: Option<&'a str>,
+}
+
+/// Renders a path template such as `{yyyy}/{mm}/{camera}/{name}.{ext}`
+/// into a relative path. Every segment is sanitized and empty ones, `.`
+/// and `..` are dropped, so the result never leaves the output directory.
+/// Returns `None` if no filename is left.
+pub fn render_path(template: &str, vars: &PathVars<'_>) -> Option<PathBuf> {
+    const UNKNOWN: &str = "unknown";
+    let date = |f: fn(&CaptureDate) -> String| vars.date.as_ref().map_or(UNKNOWN.to_string(), f);
+    let (year, month, day) = (
+        date(|d| format!("{:04}", d.year)),
+        date(|d| format!("{:02}", d.month)),
+        date(|d| format!("{:02}", d.day)),
+    );
+    let camera = vars.camera.unwrap_or(UNKNOWN).replace(['/', '\\'], "-");
+    let segments: Vec<String> = template
+        .split(['/', '\\'])
+        .filter_map(|segment| {
+            // `{name}` goes last so placeholders in the input's name stay
+            // literal.
+            let rendered = segment
+                .replace("{yyyy}", &year)
+                .replace("{mm}", &month)
+                .replace("{dd}", &day)
+                .replace("{width}", &vars.width.to_string())
+                .replace("{height}", &vars.height.to_string())
+                .replace("{format}", vars.format.name())
+                .replace("{ext}", vars.format.extension())
+                .replace("{camera}", &camera)
+                .replace("{name}", vars.name);
+            sanitize(&rendered)
+        })
+        .collect();
+    if template.ends_with(['/', '\\']) || segments.is_empty() {
+        return None;
+    }
+    Some(segments.iter().collect())
+}
+
+/// Makes `name` safe to use as a filename: drops any directory part,
+/// control characters and characters reserved on common file systems,
+/// trims leading dots and surrounding whitespace, and caps the length.
+/// Returns `None` if nothing usable is left.
+pub fn sanitize(name: &str) -> Option<String> {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = base
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '"' | '*' | ':' | '<' | '>' | '?' | '|'))
+        .collect();
+    let mut cleaned = cleaned.trim().trim_start_matches('.').trim().to_string();
+    if cleaned.len() > MAX_LEN {
+        let mut end = MAX_LEN;
+        while !cleaned.is_char_boundary(end) {
+            end -= 1;
+        }
+        cleaned.truncate(end);
+    }
+    Some(cleaned).filter(|c| !c.is_empty())
+}
+
+/// `name` without its last extension.
+pub fn stem(name: &str) -> &str {
+    match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => name,
+    }
+}
+
+/// A `Content-Disposition` value for `filename`, with an ASCII fallback
+/// and the exact name in RFC 5987 encoding.
+pub fn content_disposition(attachment: bool, filename: &str) -> String {
+    let kind = if attachment { "attachment" } else { "inline" };
+    let ascii: String = filename
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    if ascii == filename {
+        return format!("{}; filename=\"{}\"", kind, filename);
+    }
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        kind,
+        ascii,
+        percent_encode(filename)
+    )
+}
+
+/// Escapes everything but unreserved characters as `%XX`, for names sent
+/// in ASCII-only headers.
+pub fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Decodes `%XX` escapes, for names sent in ASCII-only headers.
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,72 @@
+// image-compressor-rust-service/src/filter.rs
+
+//! Colour filters applied after resizing: duotone, tint and sepia.
+//!
+//! Filters change colour only; alpha is kept as it is. Luma uses the
+//! Rec. 709 weights, as `image` does.
+
+use crate::options::{Color, Filter};
+use crate::simd;
+use image::DynamicImage;
+
+/// Applies `filter` to every pixel. Grayscale inputs come out as RGB, so
+/// the filter's colours survive.
+pub fn apply(image: DynamicImage, filter: Filter) -> DynamicImage {
+    let map = |[r, g, b]: [f64; 3]| match filter {
+        Filter::Duotone { shadow, highlight } => {
+            let luma = luma([r, g, b]) / 255.0;
+            let [sr, sg, sb] = rgb(shadow);
+            let [hr, hg, hb] = rgb(highlight);
+            [mix(sr, hr, luma), mix(sg, hg, luma), mix(sb, hb, luma)]
+        }
+        // Multiplies by the colour, its alpha being the strength.
+        Filter::Tint(color) => {
+            let strength = f64::from(color.0[3]) / 255.0;
+            let [tr, tg, tb] = rgb(color);
+            [
+                mix(r, r * tr / 255.0, strength),
+                mix(g, g * tg / 255.0, strength),
+                mix(b, b * tb / 255.0, strength),
+            ]
+        }
+        Filter::Sepia { amount } => {
+            let amount = f64::from(amount) / 100.0;
+            let sepia = [
+                0.393 * r + 0.769 * g + 0.189 * b,
+                0.349 * r + 0.686 * g + 0.168 * b,
+                0.272 * r + 0.534 * g + 0.131 * b,
+            ];
+            [
+                mix(r, sepia[0], amount),
+                mix(g, sepia[1], amount),
+                mix(b, sepia[2], amount),
+            ]
+        }
+    };
+    let filter_pixel = |pixel: [u8; 3]| {
+        map(pixel.map(f64::from)).map(|value| value.round().clamp(0.0, 255.0) as u8)
+    };
+
+    if image.color().has_alpha() {
+        let mut image = image.into_rgba8();
+        simd::map_rgb(&mut image, 4, filter_pixel);
+        DynamicImage::ImageRgba8(image)
+    } else {
+        let mut image = image.into_rgb8();
+        simd::map_rgb(&mut image, 3, filter_pixel);
+        DynamicImage::ImageRgb8(image)
+    }
+}
+
+fn luma([r, g, b]: [f64; 3]) -> f64 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+fn rgb(color: Color) -> [f64; 3] {
+    let [r, g, b, _] = color.0;
+    [f64::from(r), f64::from(g), f64::from(b)]
+}
+
+fn mix(from: f64, to: f64, t: f64) -> f64 {
+    from + (to - from) * t
+}
@@ -0,0 +1,227 @@
+// image-compressor-rust-service/src/formats.rs
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// What a format supports, as handled by this service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Capabilities {
+    /// Transparency is preserved.
+    pub alpha: bool,
+    /// Multiple frames are decoded or encoded.
+    pub animation: bool,
+    /// Lossless data is handled without loss.
+    pub lossless: bool,
+}
+
+/// Image formats the service can decode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputFormat {
+    #[serde(alias = "jpg")]
+    Jpeg,
+    /// PNG, including interlaced PNG and APNG.
+    Png,
+    Webp,
+}
+
+impl InputFormat {
+    /// Every input format compiled into the service.
+    pub const ALL: [InputFormat; 3] = [InputFormat::Jpeg, InputFormat::Png, InputFormat::Webp];
+
+    /// Detects the format from the leading magic bytes of the data.
+    ///
+    /// The declared `Content-Type` is never consulted: only the signature
+    /// decides which decoder may run.
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(Self::Jpeg)
+        } else if data.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+            Some(Self::Png)
+        } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+            Some(Self::Webp)
+        } else {
+            None
+        }
+    }
+
+    /// The format an `image/*` MIME type names, e.g. from a `Content-Type`
+    /// header. Parameters and case are ignored.
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        let essence = mime_type.split(';').next()?.trim().to_ascii_lowercase();
+        match essence.strip_prefix("image/")? {
+            "pjpeg" => Some(Self::Jpeg),
+            subtype => subtype.parse().ok(),
+        }
+    }
+
+    /// The lowercase name used in configuration and error messages.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpeg",
+            Self::Png => "png",
+            Self::Webp => "webp",
+        }
+    }
+
+    /// The canonical MIME type of the format.
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Webp => "image/webp",
+        }
+    }
+
+    /// What the decoder supports. Only APNG is decoded as an animation;
+    /// animated WebP inputs yield their first frame.
+    pub fn capabilities(self) -> Capabilities {
+        match self {
+            Self::Jpeg => Capabilities {
+                alpha: false,
+                animation: false,
+                lossless: false,
+            },
+            Self::Png => Capabilities {
+                alpha: true,
+                animation: true,
+                lossless: true,
+            },
+            Self::Webp => Capabilities {
+                alpha: true,
+                animation: false,
+                lossless: true,
+            },
+        }
+    }
+}
+
+/// The format detected in an input, next to the `Content-Type` it was
+/// sent with, as reported in `X-Input-Format` and by `/inspect`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SniffedFormat {
+    /// The format its magic bytes identify, if any.
+    pub detected: Option<InputFormat>,
+    /// Whether the declared `Content-Type` names the detected format.
+    /// `None` when no `image/*` type was declared, e.g. for
+    /// `application/octet-stream` or JSON bodies.
+    pub matches_content_type: Option<bool>,
+}
+
+impl SniffedFormat {
+    pub fn of(data: &[u8], content_type: Option<&str>) -> Self {
+        let detected = InputFormat::sniff(data);
+        let declared = content_type
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().to_ascii_lowercase())
+            .filter(|v| v.starts_with("image/"));
+        Self {
+            detected,
+            matches_content_type: declared
+                .map(|v| detected.is_some() && InputFormat::from_mime_type(&v) == detected),
+        }
+    }
+
+    /// The `X-Input-Format` value: the detected format or `unknown`,
+    /// followed by `; matches-content-type=true` or `false` when an image
+    /// type was declared.
+    pub fn header_value(&self) -> String {
+        let name = self.detected.map_or("unknown", InputFormat::name);
+        match self.matches_content_type {
+            Some(matches) => format!("{}; matches-content-type={}", name, matches),
+            None => name.to_owned(),
+        }
+    }
+}
+
+/// Image formats the service encodes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Still images.
+    Jpeg,
+    /// Animated images, with [`AnimationMode::Animate`](crate::AnimationMode::Animate).
+    Webp,
+    /// Palette (PNG8) stills, with [`Palette`](crate::Palette) options.
+    Png,
+}
+
+impl OutputFormat {
+    /// Every output format compiled into the service.
+    pub const ALL: [OutputFormat; 3] = [OutputFormat::Jpeg, OutputFormat::Webp, OutputFormat::Png];
+
+    /// The lowercase name of the format.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpeg",
+            Self::Webp => "webp",
+            Self::Png => "png",
+        }
+    }
+
+    /// The MIME type sent as the response `Content-Type`.
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+            Self::Png => "image/png",
+        }
+    }
+
+    /// The usual file extension, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+            Self::Png => "png",
+        }
+    }
+
+    /// What the encoder produces. WebP output is always lossy; palette PNGs
+    /// lose only what quantization does.
+    pub fn capabilities(self) -> Capabilities {
+        match self {
+            Self::Jpeg => Capabilities {
+                alpha: false,
+                animation: false,
+                lossless: false,
+            },
+            Self::Webp => Capabilities {
+                alpha: true,
+                animation: true,
+                lossless: false,
+            },
+            Self::Png => Capabilities {
+                alpha: true,
+                animation: false,
+                lossless: false,
+            },
+        }
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "jpeg" | "jpg" => Ok(Self::Jpeg),
+            "png" | "apng" => Ok(Self::Png),
+            "webp" => Ok(Self::Webp),
+            other => Err(format!("unknown input format '{}'", other)),
+        }
+    }
+}
@@ -0,0 +1,974 @@
+// image-compressor-rust-service/src/jpeg.rs
+
+//! A baseline JPEG encoder with selectable quantization tables.
+//!
+//! The `image` crate's encoder only takes a quality, which scales the
+//! Annex K reference tables. Outputs with other tables go through this one
+//! instead. It writes what that encoder does, apart from the tables: JFIF,
+//! no chroma subsampling, the Annex K Huffman tables and one sequential
+//! scan, with a single component for greyscale images.
+//!
+//! Encoding is split in two: [`Coefficients`] holds the quantized DCT
+//! blocks of each component and [`Coefficients::write`] entropy codes them,
+//! so blocks that were never pixels can be written the same way.
+//! [`Coefficients::read`] goes the other way for baseline inputs, which is
+//! how [`crate::lossless`] rearranges their blocks without decoding them.
+
+use crate::quality::{scale_entry, CHROMA_TABLE, LUMA_TABLE, ZIGZAG};
+use crate::simd;
+use anyhow::{bail, Context, Result};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+/// The quantization tables JPEG outputs are encoded with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuantTables {
+    /// The Annex K reference tables, scaled by the quality as libjpeg does.
+    #[default]
+    Standard,
+    /// One value for every frequency, scaled the same way. Keeps the fine
+    /// detail of text, line art and sharp-edged graphics at the cost of
+    /// larger photographs.
+    Flat,
+}
+
+impl QuantTables {
+    pub const ALL: [QuantTables; 2] = [QuantTables::Standard, QuantTables::Flat];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Standard => "standard",
+            Self::Flat => "flat",
+        }
+    }
+
+    /// The luma and chroma tables at `quality` (1-100), in natural order.
+    pub fn scaled(self, quality: u8) -> [[u16; 64]; 2] {
+        let quality = quality.clamp(1, 100);
+        let (luma, chroma) = match self {
+            Self::Standard => (LUMA_TABLE, CHROMA_TABLE),
+            Self::Flat => ([16; 64], [16; 64]),
+        };
+        [luma, chroma].map(|table| table.map(|base| scale_entry(base, quality)))
+    }
+}
+
+impl fmt::Display for QuantTables {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for QuantTables {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match Self::ALL
+            .into_iter()
+            .find(|tables| tables.name().eq_ignore_ascii_case(value.trim()))
+        {
+            Some(tables) => Ok(tables),
+            None => bail!(
+                "unknown quantization tables '{}' (expected standard or flat)",
+                value
+            ),
+        }
+    }
+}
+
+/// Quantization tables given in full, for content the built-in ones suit
+/// poorly, such as medical or satellite imagery. Entries are 1-255, row by
+/// row from the DC coefficient, as they apply at quality 50: other
+/// qualities scale them as they do the standard tables.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomTables {
+    /// 64 luma entries.
+    pub luma: Vec<u16>,
+    /// 64 chroma entries, the luma ones when left out.
+    pub chroma: Option<Vec<u16>>,
+}
+
+impl CustomTables {
+    /// What is wrong with the tables, if anything.
+    pub fn problem(&self) -> Option<String> {
+        let tables = [Some(&self.luma), self.chroma.as_ref()];
+        for (name, table) in ["luma", "chroma"].into_iter().zip(tables) {
+            let Some(table) = table else { continue };
+            if table.len() != 64 {
+                return Some(format!("{} needs 64 entries, got {}", name, table.len()));
+            }
+            if let Some(entry) = table.iter().find(|&&q| !(1..=255).contains(&q)) {
+                return Some(format!("{} entries must be 1-255, got {}", name, entry));
+            }
+        }
+        None
+    }
+
+    /// The luma and chroma tables at `quality` (1-100), in natural order.
+    /// The tables must be valid (see [`CustomTables::problem`]).
+    pub fn scaled(&self, quality: u8) -> [[u16; 64]; 2] {
+        let quality = quality.clamp(1, 100);
+        let luma = &self.luma;
+        let chroma = self.chroma.as_ref().unwrap_or(luma);
+        [luma, chroma].map(|table| std::array::from_fn(|i| scale_entry(table[i], quality)))
+    }
+}
+
+/// Encodes `image` as a baseline JPEG quantized with `tables`, luma first
+/// and chroma second, in natural order. Entries must be 1-255. Alpha is
+/// dropped.
+pub fn encode(image: &DynamicImage, tables: &[[u16; 64]; 2]) -> Result<Vec<u8>> {
+    let (width, height) = (image.width(), image.height());
+    if width == 0 || height == 0 || width > 0xFFFF || height > 0xFFFF {
+        bail!(
+            "JPEG cannot hold a {}x{} image (1 to 65535 pixels a side).",
+            width,
+            height
+        );
+    }
+    if tables.iter().flatten().any(|&q| !(1..=255).contains(&q)) {
+        bail!("JPEG quantization table entries must be 1-255.");
+    }
+    let planes: Vec<(Vec<f32>, usize)> = if image.color().has_color() {
+        let [y, cb, cr] = simd::rgb_to_ycbcr(&image.to_rgb8());
+        vec![(y, 0), (cb, 1), (cr, 1)]
+    } else {
+        let luma = image.to_luma8().into_raw();
+        vec![(luma.into_iter().map(f32::from).collect(), 0)]
+    };
+
+    let (width, height) = (width as usize, height as usize);
+    let (blocks_wide, blocks_high) = (width.div_ceil(8), height.div_ceil(8));
+    let basis = dct_basis();
+    let components = planes
+        .iter()
+        .enumerate()
+        .map(|(index, (plane, table))| {
+            let mut blocks = Vec::with_capacity(blocks_wide * blocks_high);
+            for by in 0..blocks_high {
+                for bx in 0..blocks_wide {
+                    // Edge blocks repeat the last row and column.
+                    let mut samples = [0f32; 64];
+                    for (i, sample) in samples.iter_mut().enumerate() {
+                        let x = (bx * 8 + i % 8).min(width - 1);
+                        let y = (by * 8 + i / 8).min(height - 1);
+                        *sample = plane[y * width + x] - 128.0;
+                    }
+                    blocks.push(quantize(&fdct(&samples, &basis), &tables[*table]));
+                }
+            }
+            Component {
+                id: index as u8 + 1,
+                sampling: (1, 1),
+                table: *table as u8,
+                blocks_wide,
+                blocks_high,
+                blocks,
+            }
+        })
+        .collect();
+
+    let coefficients = Coefficients {
+        width: width as u16,
+        height: height as u16,
+        tables: if planes.len() == 1 {
+            vec![tables[0]]
+        } else {
+            tables.to_vec()
+        },
+        components,
+    };
+    Ok(coefficients.write())
+}
+
+/// `basis[u][x]` is the DCT-II basis function `u` at sample `x`, with the
+/// normalization of the JPEG forward DCT folded in.
+fn dct_basis() -> [[f32; 8]; 8] {
+    let mut basis = [[0f32; 8]; 8];
+    for (u, row) in basis.iter_mut().enumerate() {
+        let scale = if u == 0 { 0.5 / 2f32.sqrt() } else { 0.5 };
+        for (x, value) in row.iter_mut().enumerate() {
+            *value = scale * ((2 * x + 1) as f32 * u as f32 * PI / 16.0).cos();
+        }
+    }
+    basis
+}
+
+/// The forward DCT of a level-shifted block, rows then columns.
+fn fdct(samples: &[f32; 64], basis: &[[f32; 8]; 8]) -> [f32; 64] {
+    let mut rows = [0f32; 64];
+    for y in 0..8 {
+        for u in 0..8 {
+            rows[y * 8 + u] = (0..8).map(|x| samples[y * 8 + x] * basis[u][x]).sum();
+        }
+    }
+    let mut coefficients = [0f32; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            coefficients[v * 8 + u] = (0..8).map(|y| rows[y * 8 + u] * basis[v][y]).sum();
+        }
+    }
+    coefficients
+}
+
+fn quantize(coefficients: &[f32; 64], table: &[u16; 64]) -> [i16; 64] {
+    let mut block = [0i16; 64];
+    for ((value, &coefficient), &q) in block.iter_mut().zip(coefficients).zip(table) {
+        *value = (coefficient / f32::from(q)).round() as i16;
+    }
+    block
+}
+
+/// The quantized DCT blocks of a baseline JPEG.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Coefficients {
+    pub width: u16,
+    pub height: u16,
+    /// Quantization tables by id, in natural order.
+    pub tables: Vec<[u16; 64]>,
+    pub components: Vec<Component>,
+}
+
+/// One colour component: its blocks, row by row, in natural order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Component {
+    pub id: u8,
+    /// Horizontal and vertical sampling factors.
+    pub sampling: (u8, u8),
+    /// Id of its quantization table. The first component uses the luma
+    /// Huffman tables, the others the chroma ones.
+    pub table: u8,
+    /// The block grid, padded to whole MCUs when there is more than one
+    /// component.
+    pub blocks_wide: usize,
+    pub blocks_high: usize,
+    pub blocks: Vec<[i16; 64]>,
+}
+
+impl Coefficients {
+    /// Writes the blocks as a baseline JPEG: JFIF, the quantization and
+    /// Annex K Huffman tables, and one scan holding every component.
+    pub fn write(&self) -> Vec<u8> {
+        let mut out = vec![0xFF, 0xD8];
+        // JFIF 1.2, square pixels.
+        segment(&mut out, 0xE0, b"JFIF\0\x01\x02\0\0\x01\0\x01\0\0");
+        for (id, table) in self.tables.iter().enumerate() {
+            let mut payload = vec![id as u8];
+            payload.extend(ZIGZAG.iter().map(|&natural| table[natural] as u8));
+            segment(&mut out, 0xDB, &payload);
+        }
+
+        let mut frame = vec![8];
+        frame.extend(self.height.to_be_bytes());
+        frame.extend(self.width.to_be_bytes());
+        frame.push(self.components.len() as u8);
+        for component in &self.components {
+            let (h, v) = component.sampling;
+            frame.extend([component.id, h << 4 | v, component.table]);
+        }
+        segment(&mut out, 0xC0, &frame);
+
+        let chroma = self.components.len() > 1;
+        for (class, id, spec) in [
+            (0, 0, &LUMA_DC),
+            (1, 0, &LUMA_AC),
+            (0, 1, &CHROMA_DC),
+            (1, 1, &CHROMA_AC),
+        ] {
+            if id == 1 && !chroma {
+                continue;
+            }
+            let mut payload = vec![class << 4 | id];
+            payload.extend(spec.lengths);
+            payload.extend(spec.values);
+            segment(&mut out, 0xC4, &payload);
+        }
+
+        let mut scan = vec![self.components.len() as u8];
+        for (index, component) in self.components.iter().enumerate() {
+            let table = u8::from(index > 0);
+            scan.extend([component.id, table << 4 | table]);
+        }
+        scan.extend([0, 63, 0]);
+        segment(&mut out, 0xDA, &scan);
+
+        let codes = [
+            [LUMA_DC.codes(), LUMA_AC.codes()],
+            [CHROMA_DC.codes(), CHROMA_AC.codes()],
+        ];
+        let mut writer = BitWriter {
+            out,
+            bits: 0,
+            count: 0,
+        };
+        let mut predictions = vec![0i16; self.components.len()];
+        let units = |component: &Component| {
+            let (h, v) = component.sampling;
+            (usize::from(h), usize::from(v))
+        };
+        if let [component] = self.components.as_slice() {
+            // A single component is not interleaved: its blocks are coded
+            // in order, without MCU padding.
+            for block in &component.blocks {
+                writer.block(block, &mut predictions[0], &codes[0]);
+            }
+        } else {
+            let (h0, v0) = units(&self.components[0]);
+            let mcus_wide = self.components[0].blocks_wide / h0;
+            let mcus_high = self.components[0].blocks_high / v0;
+            for my in 0..mcus_high {
+                for mx in 0..mcus_wide {
+                    for (index, component) in self.components.iter().enumerate() {
+                        let (h, v) = units(component);
+                        for y in 0..v {
+                            for x in 0..h {
+                                let row = my * v + y;
+                                let column = mx * h + x;
+                                let block = &component.blocks[row * component.blocks_wide + column];
+                                let codes = &codes[usize::from(index > 0)];
+                                writer.block(block, &mut predictions[index], codes);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let mut out = writer.finish();
+        out.extend([0xFF, 0xD9]);
+        out
+    }
+}
+
+impl Coefficients {
+    /// Reads the quantized blocks of a baseline JPEG with one or three
+    /// components. Progressive, arithmetic-coded, 12-bit, CMYK and Adobe
+    /// RGB JPEGs are refused, as are truncated ones and coefficients the
+    /// Annex K Huffman tables cannot code again.
+    pub fn read(data: &[u8]) -> Result<Self> {
+        if !data.starts_with(&[0xFF, 0xD8]) {
+            bail!("Not a JPEG.");
+        }
+        let mut tables: [Option<[u16; 64]>; 4] = [None; 4];
+        let mut huffman: [[Option<HuffmanDecoder>; 4]; 2] = Default::default();
+        let mut frame: Option<Self> = None;
+        let mut scanned = Vec::new();
+        let mut restart_interval = 0;
+        let mut at = 2;
+        loop {
+            // Markers may be padded with fill bytes.
+            while data.get(at..at + 2) == Some(&[0xFF, 0xFF]) {
+                at += 1;
+            }
+            let Some(&[0xFF, marker]) = data.get(at..at + 2) else {
+                bail!("Truncated or corrupt JPEG.");
+            };
+            at += 2;
+            match marker {
+                0xD9 => break,
+                // Markers without a segment.
+                0x01 | 0xD0..=0xD7 => continue,
+                _ => {}
+            }
+            let length = data
+                .get(at..at + 2)
+                .map(|bytes| usize::from(u16::from_be_bytes([bytes[0], bytes[1]])))
+                .filter(|&length| length >= 2)
+                .context("Truncated or corrupt JPEG.")?;
+            let payload = data
+                .get(at + 2..at + length)
+                .context("Truncated JPEG segment.")?;
+            at += length;
+            match marker {
+                0xDB => read_quant_tables(payload, &mut tables)?,
+                0xC4 => read_huffman_tables(payload, &mut huffman)?,
+                0xC0 | 0xC1 => frame = Some(Self::read_frame(payload)?),
+                0xC2 | 0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                    bail!("Only baseline JPEGs are supported.")
+                }
+                0xDD => {
+                    let interval = payload.get(..2).context("Corrupt restart interval.")?;
+                    restart_interval = usize::from(u16::from_be_bytes([interval[0], interval[1]]));
+                }
+                // Adobe's transform flag 0 marks RGB samples, which a JFIF
+                // output would pass off as YCbCr.
+                0xEE if payload.starts_with(b"Adobe") && payload.get(11) == Some(&0) => {
+                    bail!("Adobe RGB JPEGs are not supported.")
+                }
+                0xDA => {
+                    let frame = frame.as_mut().context("JPEG scan before its frame.")?;
+                    let (end, components) =
+                        frame.read_scan(payload, data, at, &huffman, restart_interval)?;
+                    scanned.extend(components);
+                    at = end;
+                }
+                _ => {}
+            }
+        }
+
+        let mut coefficients = frame.context("JPEG without a frame.")?;
+        if (0..coefficients.components.len()).any(|index| !scanned.contains(&index)) {
+            bail!("Truncated JPEG: a component has no scan.");
+        }
+        // Renumber the tables in use from 0, as they are written.
+        let mut used: Vec<u8> = Vec::new();
+        for component in &mut coefficients.components {
+            let id = component.table;
+            let index = match used.iter().position(|&t| t == id) {
+                Some(index) => index,
+                None => {
+                    used.push(id);
+                    used.len() - 1
+                }
+            };
+            component.table = index as u8;
+        }
+        coefficients.tables = used
+            .into_iter()
+            .map(|id| {
+                tables[usize::from(id)].context("JPEG component without its quantization table.")
+            })
+            .collect::<Result<_>>()?;
+        if coefficients
+            .tables
+            .iter()
+            .flatten()
+            .any(|&q| !(1..=255).contains(&q))
+        {
+            bail!("JPEG quantization table entries must be 1-255.");
+        }
+        Ok(coefficients)
+    }
+
+    /// The frame header of a JPEG, with room for all of its blocks.
+    fn read_frame(payload: &[u8]) -> Result<Self> {
+        let [8, h1, h0, w1, w0, count, ref specs @ ..] = *payload else {
+            bail!("Only 8-bit JPEGs are supported.");
+        };
+        let (height, width) = (u16::from_be_bytes([h1, h0]), u16::from_be_bytes([w1, w0]));
+        if width == 0 || height == 0 {
+            bail!("JPEG without dimensions.");
+        }
+        if count != 1 && count != 3 {
+            bail!("Only greyscale and YCbCr JPEGs are supported.");
+        }
+        let specs = specs
+            .get(..usize::from(count) * 3)
+            .context("Truncated JPEG frame header.")?;
+        let mut components: Vec<Component> = specs
+            .chunks_exact(3)
+            .map(|spec| Component {
+                id: spec[0],
+                sampling: (spec[1] >> 4, spec[1] & 0x0F),
+                table: spec[2],
+                blocks_wide: 0,
+                blocks_high: 0,
+                blocks: Vec::new(),
+            })
+            .collect();
+        if components.iter().any(|c| {
+            !(1..=4).contains(&c.sampling.0) || !(1..=4).contains(&c.sampling.1) || c.table > 3
+        }) {
+            bail!("Corrupt JPEG frame header.");
+        }
+        if let [component] = components.as_mut_slice() {
+            // A lone component is never interleaved, so its sampling
+            // factors mean nothing.
+            component.sampling = (1, 1);
+        }
+        let mut coefficients = Self {
+            width,
+            height,
+            tables: Vec::new(),
+            components,
+        };
+        let (mcus_wide, mcus_high) = coefficients.mcus();
+        for component in &mut coefficients.components {
+            let (h, v) = component.sampling;
+            component.blocks_wide = mcus_wide * usize::from(h);
+            component.blocks_high = mcus_high * usize::from(v);
+            component.blocks = vec![[0; 64]; component.blocks_wide * component.blocks_high];
+        }
+        Ok(coefficients)
+    }
+
+    /// The size of an MCU in pixels: one block of the component sampled
+    /// least, or a single block for greyscale.
+    pub fn mcu_size(&self) -> (u32, u32) {
+        let max = |factor: fn(&Component) -> u8| {
+            let max = self.components.iter().map(factor).max().unwrap_or(1);
+            8 * u32::from(max)
+        };
+        (max(|c| c.sampling.0), max(|c| c.sampling.1))
+    }
+
+    /// How many MCUs wide and high the image is.
+    fn mcus(&self) -> (usize, usize) {
+        let (mcu_width, mcu_height) = self.mcu_size();
+        (
+            u32::from(self.width).div_ceil(mcu_width) as usize,
+            u32::from(self.height).div_ceil(mcu_height) as usize,
+        )
+    }
+
+    /// Decodes the scan whose entropy-coded data starts at `data[at]` into
+    /// the blocks of its components. Returns where the data ends and which
+    /// components the scan held.
+    fn read_scan(
+        &mut self,
+        payload: &[u8],
+        data: &[u8],
+        at: usize,
+        huffman: &[[Option<HuffmanDecoder>; 4]; 2],
+        restart_interval: usize,
+    ) -> Result<(usize, Vec<usize>)> {
+        let count = usize::from(*payload.first().context("Corrupt JPEG scan header.")?);
+        let specs = payload
+            .get(1..1 + count * 2)
+            .context("Corrupt JPEG scan header.")?;
+        if payload.get(1 + count * 2..) != Some(&[0, 63, 0]) {
+            bail!("Only baseline JPEGs are supported.");
+        }
+        let mut members = Vec::with_capacity(count);
+        for spec in specs.chunks_exact(2) {
+            let index = self
+                .components
+                .iter()
+                .position(|c| c.id == spec[0])
+                .context("JPEG scan of an unknown component.")?;
+            let table = |class: usize, id: u8| {
+                huffman[class]
+                    .get(usize::from(id))
+                    .and_then(Option::as_ref)
+                    .context("JPEG scan without its Huffman table.")
+            };
+            members.push((index, table(0, spec[1] >> 4)?, table(1, spec[1] & 0x0F)?));
+        }
+        // Every block takes at least two bits, which bounds the blocks a
+        // small input can claim to hold.
+        let blocks: usize = self.components.iter().map(|c| c.blocks.len()).sum();
+        if blocks > data.len().saturating_mul(4) {
+            bail!("Corrupt JPEG: more blocks than data.");
+        }
+
+        let mut reader = BitReader {
+            data,
+            at,
+            byte: 0,
+            left: 0,
+        };
+        let mut predictions = vec![0; members.len()];
+        let (mcus_wide, mcus_high, interleaved) = match members.as_slice() {
+            [(index, ..)] => {
+                // A single component is coded block by block, without the
+                // MCU padding.
+                let component = &self.components[*index];
+                let (mcu_width, mcu_height) = self.mcu_size();
+                let (h, v) = component.sampling;
+                let width = (u32::from(self.width) * u32::from(h)).div_ceil(mcu_width / 8);
+                let height = (u32::from(self.height) * u32::from(v)).div_ceil(mcu_height / 8);
+                (
+                    width.div_ceil(8) as usize,
+                    height.div_ceil(8) as usize,
+                    false,
+                )
+            }
+            _ => {
+                let (wide, high) = self.mcus();
+                (wide, high, true)
+            }
+        };
+        for mcu in 0..mcus_wide * mcus_high {
+            if restart_interval > 0 && mcu > 0 && mcu % restart_interval == 0 {
+                reader.restart()?;
+                predictions.fill(0);
+            }
+            let (mx, my) = (mcu % mcus_wide, mcu / mcus_wide);
+            for (member, &(index, dc, ac)) in members.iter().enumerate() {
+                let component = &mut self.components[index];
+                let (h, v) = if interleaved {
+                    let (h, v) = component.sampling;
+                    (usize::from(h), usize::from(v))
+                } else {
+                    (1, 1)
+                };
+                for y in 0..v {
+                    for x in 0..h {
+                        let row = my * v + y;
+                        let column = mx * h + x;
+                        let block = &mut component.blocks[row * component.blocks_wide + column];
+                        *block = reader.block(dc, ac, &mut predictions[member])?;
+                    }
+                }
+            }
+        }
+        let end = reader.at;
+        Ok((end, members.iter().map(|&(index, ..)| index).collect()))
+    }
+}
+
+/// Reads the tables of a DQT segment into `tables`, by id, in natural
+/// order.
+fn read_quant_tables(mut payload: &[u8], tables: &mut [Option<[u16; 64]>; 4]) -> Result<()> {
+    while let [spec, rest @ ..] = payload {
+        let (precision, id) = (spec >> 4, usize::from(spec & 0x0F));
+        let size = if precision == 0 { 64 } else { 128 };
+        let entries = rest
+            .get(..size)
+            .context("Truncated JPEG quantization table.")?;
+        let slot = tables
+            .get_mut(id)
+            .context("Corrupt JPEG quantization table.")?;
+        let mut table = [0; 64];
+        for (position, &natural) in ZIGZAG.iter().enumerate() {
+            table[natural] = if precision == 0 {
+                u16::from(entries[position])
+            } else {
+                u16::from_be_bytes([entries[position * 2], entries[position * 2 + 1]])
+            };
+        }
+        *slot = Some(table);
+        payload = &rest[size..];
+    }
+    Ok(())
+}
+
+/// Reads the tables of a DHT segment into `tables`, by class (DC, AC)
+/// and id.
+fn read_huffman_tables(
+    mut payload: &[u8],
+    tables: &mut [[Option<HuffmanDecoder>; 4]; 2],
+) -> Result<()> {
+    while let [spec, rest @ ..] = payload {
+        let (class, id) = (usize::from(spec >> 4), usize::from(spec & 0x0F));
+        let lengths: [u8; 16] = rest
+            .get(..16)
+            .and_then(|lengths| lengths.try_into().ok())
+            .context("Truncated JPEG Huffman table.")?;
+        let count = lengths.iter().map(|&n| usize::from(n)).sum::<usize>();
+        let values = rest
+            .get(16..16 + count)
+            .context("Truncated JPEG Huffman table.")?;
+        let slot = tables
+            .get_mut(class)
+            .and_then(|class| class.get_mut(id))
+            .context("Corrupt JPEG Huffman table.")?;
+        *slot = Some(HuffmanDecoder::new(&lengths, values));
+        payload = &rest[16 + count..];
+    }
+    Ok(())
+}
+
+/// A Huffman table read from a DHT segment, decoded as in Annex F.2.2.3.
+#[derive(Debug, Default)]
+struct HuffmanDecoder {
+    /// By code length: the first code, the last one (-1 for none) and the
+    /// index of the first one's symbol.
+    min_code: [i32; 17],
+    max_code: [i32; 17],
+    first_value: [usize; 17],
+    values: Vec<u8>,
+}
+
+impl HuffmanDecoder {
+    fn new(lengths: &[u8; 16], values: &[u8]) -> Self {
+        let mut decoder = Self {
+            values: values.to_vec(),
+            ..Self::default()
+        };
+        let (mut code, mut index) = (0i32, 0usize);
+        for (length, &count) in (1..=16).zip(lengths) {
+            decoder.first_value[length] = index;
+            decoder.min_code[length] = code;
+            code += i32::from(count);
+            index += usize::from(count);
+            decoder.max_code[length] = if count == 0 { -1 } else { code - 1 };
+            code <<= 1;
+        }
+        decoder
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u8> {
+        let mut code = 0i32;
+        for length in 1..=16 {
+            code = code << 1 | i32::from(reader.bit()?);
+            if code <= self.max_code[length] {
+                let index = self.first_value[length] + (code - self.min_code[length]) as usize;
+                return self
+                    .values
+                    .get(index)
+                    .copied()
+                    .context("Corrupt JPEG Huffman code.");
+            }
+        }
+        bail!("Corrupt JPEG Huffman code.")
+    }
+}
+
+/// Reads the entropy-coded data of a scan bit by bit.
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// The next byte to read.
+    at: usize,
+    byte: u8,
+    /// Bits of `byte` not read yet.
+    left: u8,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> Result<u16> {
+        if self.left == 0 {
+            let byte = *self.data.get(self.at).context("Truncated JPEG scan.")?;
+            if byte == 0xFF {
+                // A stuffed 0x00 follows 0xFF in the data; anything else is
+                // a marker, which ends it early.
+                if self.data.get(self.at + 1) != Some(&0) {
+                    bail!("Truncated JPEG scan.");
+                }
+                self.at += 1;
+            }
+            self.at += 1;
+            self.byte = byte;
+            self.left = 8;
+        }
+        self.left -= 1;
+        Ok(u16::from(self.byte >> self.left & 1))
+    }
+
+    /// The next `count` bits as a coefficient of that size category
+    /// (Annex F.2.2.1).
+    fn coefficient(&mut self, count: u8) -> Result<i16> {
+        if count == 0 {
+            return Ok(0);
+        }
+        let mut bits = 0i32;
+        for _ in 0..count {
+            bits = bits << 1 | i32::from(self.bit()?);
+        }
+        let value = if bits < 1 << (count - 1) {
+            bits - (1 << count) + 1
+        } else {
+            bits
+        };
+        Ok(value as i16)
+    }
+
+    /// Skips the restart marker ending an interval, and the bits before it.
+    fn restart(&mut self) -> Result<()> {
+        self.left = 0;
+        match self.data.get(self.at..self.at + 2) {
+            Some(&[0xFF, 0xD0..=0xD7]) => {
+                self.at += 2;
+                Ok(())
+            }
+            _ => bail!("Corrupt JPEG: missing restart marker."),
+        }
+    }
+
+    /// Decodes one block, the inverse of `BitWriter::block`.
+    fn block(
+        &mut self,
+        dc: &HuffmanDecoder,
+        ac: &HuffmanDecoder,
+        prediction: &mut i16,
+    ) -> Result<[i16; 64]> {
+        let mut block = [0i16; 64];
+        let size = dc.decode(self)?;
+        if size > 11 {
+            bail!("Corrupt JPEG DC coefficient.");
+        }
+        let value = i32::from(*prediction) + i32::from(self.coefficient(size)?);
+        // What 8-bit samples can produce, and the Annex K tables code.
+        if !(-1024..=1023).contains(&value) {
+            bail!("Corrupt JPEG DC coefficient.");
+        }
+        *prediction = value as i16;
+        block[0] = *prediction;
+
+        let mut position = 1;
+        while position < 64 {
+            let symbol = ac.decode(self)?;
+            let (run, size) = (usize::from(symbol >> 4), symbol & 0x0F);
+            if size == 0 {
+                if run != 15 {
+                    // EOB.
+                    break;
+                }
+                position += 16;
+                continue;
+            }
+            position += run;
+            if position > 63 || size > 10 {
+                bail!("Corrupt JPEG AC coefficient.");
+            }
+            block[ZIGZAG[position]] = self.coefficient(size)?;
+            position += 1;
+        }
+        if position > 64 {
+            bail!("Corrupt JPEG AC coefficient.");
+        }
+        Ok(block)
+    }
+}
+
+fn segment(out: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    out.extend([0xFF, marker]);
+    out.extend((payload.len() as u16 + 2).to_be_bytes());
+    out.extend(payload);
+}
+
+/// A Huffman table as stored in a DHT segment.
+pub(crate) struct HuffmanSpec {
+    /// How many codes there are of each length, 1 to 16 bits.
+    pub lengths: [u8; 16],
+    /// The symbols, shortest codes first.
+    pub values: &'static [u8],
+}
+
+impl HuffmanSpec {
+    /// `(length, code)` by symbol, assigned canonically (Annex C).
+    pub fn codes(&self) -> [(u8, u16); 256] {
+        let mut codes = [(0, 0); 256];
+        let mut code = 0u16;
+        let mut values = self.values.iter();
+        for (length, &count) in (1..=16u8).zip(&self.lengths) {
+            for _ in 0..count {
+                if let Some(&value) = values.next() {
+                    codes[usize::from(value)] = (length, code);
+                }
+                code += 1;
+            }
+            code <<= 1;
+        }
+        codes
+    }
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u16, length: u8) {
+        for shift in (0..length).rev() {
+            self.bits = self.bits << 1 | u32::from(value >> shift & 1);
+            self.count += 1;
+            if self.count == 8 {
+                let byte = self.bits as u8;
+                self.out.push(byte);
+                // 0xFF in entropy-coded data is followed by a stuffed 0x00.
+                if byte == 0xFF {
+                    self.out.push(0);
+                }
+                self.bits = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    /// Codes one block: its DC difference from the previous block of the
+    /// component, then the AC run lengths in zigzag order (Annex F.1.2).
+    fn block(&mut self, block: &[i16; 64], prediction: &mut i16, codes: &[[(u8, u16); 256]; 2]) {
+        let [dc, ac] = codes;
+        let difference = block[0].wrapping_sub(*prediction);
+        *prediction = block[0];
+        let (size, bits) = magnitude(difference);
+        self.put(dc[usize::from(size)].1, dc[usize::from(size)].0);
+        self.put(bits, size);
+
+        let mut run = 0;
+        for &natural in &ZIGZAG[1..] {
+            let value = block[natural];
+            if value == 0 {
+                run += 1;
+                continue;
+            }
+            while run > 15 {
+                // ZRL: sixteen zeros.
+                self.put(ac[0xF0].1, ac[0xF0].0);
+                run -= 16;
+            }
+            let (size, bits) = magnitude(value);
+            let symbol = usize::from(run << 4 | size);
+            self.put(ac[symbol].1, ac[symbol].0);
+            self.put(bits, size);
+            run = 0;
+        }
+        if run > 0 {
+            // EOB.
+            self.put(ac[0].1, ac[0].0);
+        }
+    }
+
+    /// Pads the last byte with ones.
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.put(0x7F, 8 - self.count);
+        }
+        self.out
+    }
+}
+
+/// The size category of a coefficient and its low bits, negative values
+/// stored as one less, as in Annex F.1.2.1.
+fn magnitude(value: i16) -> (u8, u16) {
+    let size = (16 - value.unsigned_abs().leading_zeros()) as u8;
+    let bits = if value < 0 { value - 1 } else { value } as u16;
+    (size, bits & ((1u32 << size) - 1) as u16)
+}
+
+/// Table K.3.
+pub(crate) const LUMA_DC: HuffmanSpec = HuffmanSpec {
+    lengths: [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0],
+    values: &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+};
+
+/// Table K.4.
+pub(crate) const CHROMA_DC: HuffmanSpec = HuffmanSpec {
+    lengths: [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0],
+    values: &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+};
+
+/// Table K.5.
+#[rustfmt::skip]
+pub(crate) const LUMA_AC: HuffmanSpec = HuffmanSpec {
+    lengths: [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7D],
+    values: &[
+        0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+        0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xA1, 0x08, 0x23, 0x42, 0xB1, 0xC1, 0x15, 0x52, 0xD1, 0xF0,
+        0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0A, 0x16, 0x17, 0x18, 0x19, 0x1A, 0x25, 0x26, 0x27, 0x28,
+        0x29, 0x2A, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+        0x4A, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+        0x6A, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+        0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9A, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7,
+        0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3, 0xC4, 0xC5,
+        0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA, 0xE1, 0xE2,
+        0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xEA, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8,
+        0xF9, 0xFA,
+    ],
+};
+
+/// Table K.6.
+#[rustfmt::skip]
+pub(crate) const CHROMA_AC: HuffmanSpec = HuffmanSpec {
+    lengths: [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77],
+    values: &[
+        0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+        0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xA1, 0xB1, 0xC1, 0x09, 0x23, 0x33, 0x52, 0xF0,
+        0x15, 0x62, 0x72, 0xD1, 0x0A, 0x16, 0x24, 0x34, 0xE1, 0x25, 0xF1, 0x17, 0x18, 0x19, 0x1A, 0x26,
+        0x27, 0x28, 0x29, 0x2A, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+        0x49, 0x4A, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+        0x69, 0x6A, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+        0x88, 0x89, 0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9A, 0xA2, 0xA3, 0xA4, 0xA5,
+        0xA6, 0xA7, 0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3,
+        0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA,
+        0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xEA, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8,
+        0xF9, 0xFA,
+    ],
+};
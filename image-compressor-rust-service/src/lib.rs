@@ -1,16 +1,498 @@
 // image-compressor-rust-service/src/lib.rs
 
-use anyhow::{Context, Result};
-use image::ImageOutputFormat;
-use std::io::Cursor;
-use metrics_exporter_prometheus::PrometheusHandle;
-use std::sync::Arc;
-use metrics;
+pub mod analyze;
+pub mod animation;
+pub mod archive;
+pub mod budget;
+pub mod build_info;
+pub mod color;
+pub mod config;
+pub mod contact_sheet;
+pub mod cpu;
+pub mod decode;
+pub mod diff;
+pub mod encode;
+pub mod favicon;
+pub mod filename;
+pub mod filter;
+pub mod formats;
+pub mod jpeg;
+pub mod logging;
+pub mod lossless;
+pub mod metadata;
+pub mod ocr;
+pub mod options;
+pub mod palette;
+pub mod placeholder;
+pub mod pool;
+pub mod preset;
+pub mod provenance;
+pub mod quality;
+pub mod sandbox;
+pub mod server;
+pub mod simd;
+pub mod social;
+pub mod text;
+pub mod timing;
+pub mod transform;
+pub mod tune;
+pub mod warmup;
+pub mod watch;
+pub mod watermark;
+
+use anyhow::{bail, Result};
+use config::Config;
+use cpu::Threads;
+use formats::{InputFormat, OutputFormat};
+use image::DynamicImage;
+use provenance::Transformation;
+use timing::Timings;
+
+pub use decode::{decode_frames, decode_image, DecodeError};
+pub use options::{
+    AnimationMode, AspectRatio, Border, ByteBudget, Color, ColorSpace, CompressionOptions,
+    CornerRadius, Dither, Effort, FieldError, Filter, Fit, Gravity, Palette, QualityScale,
+    Rotation,
+};
+
+/// Version of the deterministic output contract.
+///
+/// With [`CompressionOptions::deterministic`] (or `deterministic` in the
+/// [`Config`]), the same input and options produce the same bytes for as
+/// long as this number stays the same: encoder settings are fixed and
+/// nothing time- or randomness-dependent, such as a C2PA manifest, is
+/// written. It is bumped whenever a change (an encoder upgrade, a new
+/// quality curve) alters those bytes, so caches can key on it.
+///
+/// `tests/golden.rs` pins the SHA-256 of deterministic JPEG outputs. WebP
+/// outputs are reproducible on one CPU architecture, but libwebp's SIMD
+/// paths may differ between architectures.
+pub const DETERMINISTIC_OUTPUT_VERSION: u32 = 3;
+
+/// The encoded output of a compression request.
+#[derive(Debug, Clone)]
+pub struct CompressedImage {
+    /// The encoded image bytes.
+    pub data: Vec<u8>,
+    /// MIME type of `data`, suitable for a `Content-Type` header.
+    pub content_type: &'static str,
+    pub format: OutputFormat,
+    /// Width and height of the output in pixels.
+    pub dimensions: (u32, u32),
+    /// Width and height of the input in pixels.
+    pub source_dimensions: (u32, u32),
+    /// Pixels the input decoded to, all frames together. JPEGs transformed
+    /// without decoding count the pixels of their header's dimensions.
+    pub decoded_pixels: u64,
+    /// A tiny blurred preview as a `data:` URI, when the options ask for
+    /// one (see [`placeholder`]).
+    pub placeholder: Option<String>,
+    /// The text of the input, for options with [`CompressionOptions::ocr`]
+    /// (see [`ocr`]).
+    pub text: Option<String>,
+    /// Whether the input was truncated or corrupt and only what could be
+    /// recovered of it was compressed (see [`CompressionOptions::salvage`]).
+    pub salvaged: bool,
+    /// How long each pipeline stage took.
+    pub timings: Timings,
+}
+
+/// Compresses an image according to the given options.
+///
+/// Still images are decoded through [`decode_image`], resized as requested
+/// and re-encoded as JPEG (or a palette PNG with
+/// [`CompressionOptions::palette`]), keeping the EXIF metadata allowed by the
+/// [`metadata`] policy plus any metadata the request adds. Animated inputs
+/// (APNG) either keep only their first frame or, with
+/// [`AnimationMode::Animate`], are converted to an animated WebP.
+///
+/// This uses the default service [`Config`]; see [`compress_image_with`].
+///
+/// # Arguments
+///
+/// * `input_bytes` - A byte slice `&[u8]` containing the raw data of the input image.
+/// * `options` - The [`CompressionOptions`] parsed from the request.
+///
+/// # Returns
+///
+/// * `Result<CompressedImage>` - On success, the encoded bytes and their content type.
+///   On failure, an `anyhow::Error` detailing the cause of the failure.
+///
+pub fn compress_image(input_bytes: &[u8], options: &CompressionOptions) -> Result<CompressedImage> {
+    compress_image_with(input_bytes, options, &Config::default())
+}
+
+/// Compresses an image according to the given options and service configuration.
+///
+/// The configuration supplies server-side behaviour that callers cannot
+/// choose per request, such as decoding in the [`sandbox`].
+///
+/// This runs on a single thread; see [`compress_image_on`].
+pub fn compress_image_with(
+    input_bytes: &[u8],
+    options: &CompressionOptions,
+    config: &Config,
+) -> Result<CompressedImage> {
+    compress_image_on(input_bytes, options, config, &mut Threads::single())
+}
+
+/// Compresses an image like [`compress_image_with`], borrowing extra threads
+/// from the [`cpu`] budget for large animations: frames are resized in
+/// parallel and the WebP encoder runs multi-threaded. The JPEG encoder is
+/// single-threaded, so still images use one thread, except for the trial
+/// encodes of a [`budget`], which run side by side whatever the image size.
+/// The output does not depend on the number of threads.
+pub fn compress_image_on(
+    input_bytes: &[u8],
+    options: &CompressionOptions,
+    config: &Config,
+    threads: &mut Threads,
+) -> Result<CompressedImage> {
+    metrics::increment_counter!("compress_requests_total");
+
+    let sandbox = Some(&config.sandbox).filter(|s| s.enabled);
+    let metadata_policy = options.metadata.as_ref().unwrap_or(&config.metadata.policy);
+    let effort = options.effort.unwrap_or(config.effort);
+    if (options.deterministic || config.deterministic) && config.provenance.enabled {
+        bail!("Deterministic output is not available while C2PA signing is enabled: manifests carry unique IDs and timestamps.");
+    }
+    if options.watermark.is_some() && options.palette.is_some() {
+        bail!("Watermarks do not survive palette quantization; ask for a JPEG instead.");
+    }
+    if options.budget.is_some() && options.palette.is_some() {
+        bail!("Byte budgets apply to JPEG output; palette PNGs have no quality to lower.");
+    }
+    if options.budget.is_some() && options.watermark.is_some() {
+        bail!("Watermarks do not survive the downscaling a byte budget may need.");
+    }
+
+    // The profile to convert from, and the one to tag the output with.
+    let source_icc = options
+        .color_space
+        .and_then(|_| metadata::find_icc(input_bytes));
+    let output_icc = options.color_space.map(color::icc).transpose()?;
+    let tag_icc = |data: Vec<u8>| match &output_icc {
+        Some(icc) => metadata::embed_icc(&data, icc).unwrap_or(data),
+        None => data,
+    };
+
+    let mut timings = Timings::new();
+
+    // JPEG inputs that are only rotated or cropped keep their DCT blocks
+    // as they are.
+    if InputFormat::sniff(input_bytes) == Some(InputFormat::Jpeg) {
+        // Timed only when it does the transform stage's job.
+        let mut attempt = Timings::new();
+        let transformed = attempt.record("transform", || {
+            lossless::transform(input_bytes, options, config.max_dimensions())
+        });
+        if let Some(transformed) = transformed {
+            metrics::increment_counter!("compress_lossless_total");
+            timings.extend(attempt);
+            let data = timings.record("metadata", || {
+                metadata::apply(
+                    input_bytes,
+                    transformed.data,
+                    metadata_policy,
+                    &options.custom_metadata,
+                )
+            });
+            let quality = quality::estimate_jpeg_quality(input_bytes)
+                .unwrap_or_else(|| config.quality.resolve(OutputFormat::Jpeg, options));
+            let data = timings.record("sign", || {
+                config.provenance.sign(
+                    input_bytes,
+                    data,
+                    &Transformation {
+                        input_format: Some(InputFormat::Jpeg),
+                        output_format: OutputFormat::Jpeg,
+                        quality,
+                        source_dimensions: transformed.source_dimensions,
+                        rotation: options.rotate,
+                        output_dimensions: transformed.dimensions,
+                    },
+                )
+            })?;
+            return Ok(CompressedImage {
+                data,
+                content_type: OutputFormat::Jpeg.mime_type(),
+                format: OutputFormat::Jpeg,
+                dimensions: transformed.dimensions,
+                source_dimensions: transformed.source_dimensions,
+                decoded_pixels: u64::from(transformed.source_dimensions.0)
+                    * u64::from(transformed.source_dimensions.1),
+                placeholder: None,
+                text: None,
+                salvaged: false,
+                timings,
+            });
+        }
+    }
+
+    let mut poster = None;
+    if options.animation == AnimationMode::Animate || options.frames.poster.is_some() {
+        let limit = options.frames.limit(&config.animation);
+        let frames = timings.record("decode", || match sandbox {
+            Some(sandbox) => sandbox::decode_frames_sandboxed(input_bytes, limit, sandbox),
+            None => decode_frames(input_bytes, limit),
+        });
+        // A broken animation is salvaged as a still, below.
+        let frames = match frames {
+            Err(_) if options.salvage => None,
+            frames => frames?,
+        };
+        if options.frames.poster.is_some() {
+            poster = frames.and_then(|frames| animation::poster(frames, &options.frames));
+        } else if let Some(frames) = frames {
+            let frames = animation::select(frames, &options.frames, &config.animation);
+            if options.watermark.is_some() {
+                bail!("Watermarks can only be embedded in still images.");
+            }
+            if options.budget.is_some() {
+                bail!("Byte budgets apply to still images only.");
+            }
+            let source_dimensions = frame_dimensions(&frames);
+            let (width, height) = source_dimensions;
+            let decoded_pixels = u64::from(width) * u64::from(height) * frames.len() as u64;
+            let threads = threads.reserve_for(decoded_pixels);
+            metrics::histogram!("compress_threads", threads as f64);
+            let frames = timings.record("transform", || -> Result<_> {
+                let frames = match options.color_space {
+                    Some(space) => color::convert_frames(frames, source_icc.as_deref(), space)?,
+                    None => frames,
+                };
+                Ok(transform::resize_frames(
+                    frames,
+                    options,
+                    config.max_dimensions(),
+                    threads,
+                ))
+            })?;
+            let quality = config.quality.resolve(OutputFormat::Webp, options);
+            let data = timings.record("encode", || {
+                encode::encode_animated_webp(
+                    &frames,
+                    quality,
+                    threads,
+                    effort,
+                    &config.encoders.webp,
+                )
+            })?;
+            let placeholder = match (options.placeholder, frames.first()) {
+                (Some(width), Some(first)) => Some(timings.record("placeholder", || {
+                    let first = DynamicImage::ImageRgba8(first.buffer().clone());
+                    placeholder::generate(&first, width)
+                })?),
+                _ => None,
+            };
+            let data = timings.record("metadata", || {
+                tag_icc(metadata::apply(
+                    input_bytes,
+                    data,
+                    metadata_policy,
+                    &options.custom_metadata,
+                ))
+            });
+            let data = timings.record("sign", || {
+                config.provenance.sign(
+                    input_bytes,
+                    data,
+                    &Transformation {
+                        input_format: InputFormat::sniff(input_bytes),
+                        output_format: OutputFormat::Webp,
+                        quality,
+                        source_dimensions,
+                        rotation: options.rotate,
+                        output_dimensions: frame_dimensions(&frames),
+                    },
+                )
+            })?;
+            return Ok(CompressedImage {
+                data,
+                content_type: OutputFormat::Webp.mime_type(),
+                format: OutputFormat::Webp,
+                dimensions: frame_dimensions(&frames),
+                source_dimensions,
+                decoded_pixels,
+                placeholder,
+                text: None,
+                salvaged: false,
+                timings,
+            });
+        }
+    }
+
+    // Step 1: Decode the input image from memory.
+    // The format is detected automatically; CMYK JPEGs are converted to RGB.
+    // A poster frame was decoded with the other frames above.
+    let (dynamic_img, salvaged) = match poster {
+        Some(frame) => (DynamicImage::ImageRgba8(frame.into_buffer()), false),
+        None => timings.record("decode", || match (sandbox, options.salvage) {
+            (Some(sandbox), true) => sandbox::salvage_image_sandboxed(input_bytes, sandbox),
+            (None, true) => decode::salvage_image(input_bytes),
+            (Some(sandbox), false) => {
+                sandbox::decode_image_sandboxed(input_bytes, sandbox).map(|image| (image, false))
+            }
+            (None, false) => decode_image(input_bytes).map(|image| (image, false)),
+        })?,
+    };
+    if salvaged {
+        tracing::warn!("Salvaged a truncated or corrupt input.");
+        metrics::increment_counter!("compress_salvaged_total");
+    }
+    let text = (options.ocr && config.ocr.enabled)
+        .then(|| timings.record("ocr", || config.ocr.recognize(&dynamic_img)))
+        .transpose()?;
+
+    let format = match options.palette {
+        Some(_) => OutputFormat::Png,
+        None => OutputFormat::Jpeg,
+    };
+
+    // Step 2: Rotate, convert to the requested colour space and resize to
+    // the requested dimensions, within the configured cap.
+    let source_dimensions = (dynamic_img.width(), dynamic_img.height());
+    let dynamic_img = timings.record("transform", || -> Result<_> {
+        let rotated = transform::rotate(dynamic_img, options.rotate);
+        let converted = match options.color_space {
+            Some(space) => color::convert(rotated, source_icc.as_deref(), space)?,
+            None => rotated,
+        };
+        let resized = transform::resize(converted, options, config.max_dimensions());
+        let filtered = match options.filter {
+            Some(filter) => filter::apply(resized, filter),
+            None => resized,
+        };
+        let decorated = transform::decorate(filtered, options);
+        // JPEG has no alpha channel, so padding and rounded corners are
+        // flattened onto the background, or white.
+        Ok(
+            if format == OutputFormat::Jpeg && transform::adds_transparency(options) {
+                let matte = options.background.filter(|c| c.is_opaque());
+                transform::flatten(decorated, matte.unwrap_or(Color::WHITE))
+            } else {
+                decorated
+            },
+        )
+    })?;
+
+    let dynamic_img = match options.watermark {
+        Some(payload) => {
+            timings.record("watermark", || config.watermark.embed(dynamic_img, payload))?
+        }
+        None => dynamic_img,
+    };
+
+    // Grey content is encoded with a single channel, unless the output is
+    // tagged with an RGB profile.
+    let grayscale = options
+        .grayscale
+        .unwrap_or_else(|| options.color_space.is_none() && color::is_grayscale(&dynamic_img));
+    let dynamic_img = if grayscale {
+        color::to_grayscale(dynamic_img)
+    } else {
+        dynamic_img
+    };
+
+    // Step 4, run on every encoded output: copy over the EXIF metadata the
+    // policy allows, add the caller's own and tag the output with its
+    // colour space.
+    let add_metadata = |data: Vec<u8>| {
+        tag_icc(metadata::apply(
+            input_bytes,
+            data,
+            metadata_policy,
+            &options.custom_metadata,
+        ))
+    };
+
+    let tuning = &config.encoders.jpeg;
+    let backend = tuning.backend_for(input_bytes, options.deterministic || config.deterministic);
+    let encode_jpeg = |image: &DynamicImage, quality| {
+        let custom = options.quant_tables.as_ref();
+        encode::encode_jpeg_with(image, quality, tuning, backend, custom)
+    };
+
+    // Step 3: Encode the image to JPEG with the requested (or the configured
+    // default) quality, or quantize it to a palette PNG. With a byte budget,
+    // the quality and size are lowered until the output, metadata included,
+    // fits.
+    let quality = config.quality.resolve(format, options);
+    let (dynamic_img, quality, data) = match options.budget {
+        Some(budget) => {
+            let floor = budget.min_quality.max(config.quality.jpeg.min).min(quality);
+            let threads = threads.reserve();
+            metrics::histogram!("compress_threads", threads as f64);
+            let fitted = timings.record("optimize", || {
+                budget::fit(
+                    dynamic_img,
+                    &budget,
+                    floor..=quality,
+                    threads,
+                    |image, quality| encode_jpeg(image, quality).map(add_metadata),
+                )
+            })?;
+            (fitted.image, fitted.quality, fitted.data)
+        }
+        None => {
+            let data = timings.record("encode", || match options.palette {
+                Some(palette) => encode::encode_png8(
+                    &palette::quantize(&dynamic_img.to_rgba8(), palette),
+                    effort,
+                ),
+                None => encode_jpeg(&dynamic_img, quality),
+            })?;
+            let data = timings.record("metadata", || add_metadata(data));
+            (dynamic_img, quality, data)
+        }
+    };
+    let placeholder = options
+        .placeholder
+        .map(|width| timings.record("placeholder", || placeholder::generate(&dynamic_img, width)))
+        .transpose()?;
+
+    // Step 5: Sign the final bytes with a C2PA manifest, if configured.
+    let data = timings.record("sign", || {
+        config.provenance.sign(
+            input_bytes,
+            data,
+            &Transformation {
+                input_format: InputFormat::sniff(input_bytes),
+                output_format: format,
+                quality,
+                source_dimensions,
+                rotation: options.rotate,
+                output_dimensions: (dynamic_img.width(), dynamic_img.height()),
+            },
+        )
+    })?;
+    let dimensions = (dynamic_img.width(), dynamic_img.height());
+    // The pixels are done with; the next decode can have their buffer.
+    pool::give(dynamic_img.into_bytes());
+    Ok(CompressedImage {
+        data,
+        content_type: format.mime_type(),
+        format,
+        dimensions,
+        source_dimensions,
+        decoded_pixels: u64::from(source_dimensions.0) * u64::from(source_dimensions.1),
+        placeholder,
+        text,
+        salvaged,
+        timings,
+    })
+}
+
+fn frame_dimensions(frames: &[image::Frame]) -> (u32, u32) {
+    frames
+        .first()
+        .map_or((0, 0), |frame| frame.buffer().dimensions())
+}
 
 /// Compresses an image from a byte slice to JPEG format using the `image` crate.
 ///
-/// This simplified function relies entirely on the stable `image` crate for both
-/// decoding and encoding, removing the complexity of unstable dependencies.
+/// This is a shorthand for [`compress_image`] with default options and the
+/// given quality.
 ///
 /// # Arguments
 ///
@@ -23,24 +505,6 @@ use metrics;
 ///   On failure, returns an `anyhow::Error` detailing the cause of the failure.
 ///
 pub fn compress_image_bytes(input_bytes: &[u8], quality: u8) -> Result<Vec<u8>> {
-    metrics::increment_counter!("compress_requests_total");
-
-    // Step 1: Decode the input image from memory.
-    // The `image` crate automatically detects the format.
-    let dynamic_img = image::load_from_memory(input_bytes)
-        .context("Failed to decode input image. The format may be unsupported or the data is corrupted.")?;
-
-    // Step 2: Create a buffer to hold the compressed image data.
-    let mut buffer = Vec::new();
-    // `Cursor` allows us to treat the `Vec<u8>` buffer as a writable stream.
-    let mut writer = Cursor::new(&mut buffer);
-
-    // Step 3: Write the image to the buffer in JPEG format with the specified quality.
-    // The `image` crate handles the encoding internally.
-    dynamic_img
-        .write_to(&mut writer, ImageOutputFormat::Jpeg(quality))
-        .context("Failed to encode image to JPEG format.")?;
-
-    // The buffer is now filled with the compressed JPEG data.
-    Ok(buffer)
-}
\ No newline at end of file
+    let options = CompressionOptions::default().with_quality(quality);
+    compress_image(input_bytes, &options).map(|compressed| compressed.data)
+}
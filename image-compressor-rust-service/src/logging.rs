@@ -0,0 +1,252 @@
+// image-compressor-rust-service/src/logging.rs
+
+//! Log verbosity, sampling of request logs, and redaction.
+//!
+//! Logs are JSON lines on stdout, at `level` unless `modules` says
+//! otherwise for a module. With a `trace_sample_rate` below 1, only that
+//! share of requests keep their spans and their events below `WARN`; the
+//! choice is made from a hash of the request ID, so a request forwarded to
+//! a peer or worker is sampled the same way there. Warnings and errors are
+//! always logged.
+//!
+//! [`RedactConfig`] keeps URLs and tenants out of the logs, for the fields
+//! that carry them (see [`redaction`]).
+
+use anyhow::{bail, Context as _, Result};
+use axum::http::Uri;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::OnceLock;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Level, Subscriber};
+use tracing_subscriber::filter::{self, EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+/// Settings for the service's logs.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Most verbose level logged: `off`, `error`, `warn`, `info`, `debug`
+    /// or `trace`.
+    pub level: String,
+    /// Levels by module path, overriding `level`, e.g. `tower_http = "debug"`
+    /// or `"image_compressor_rust_service::server::peers" = "warn"`.
+    pub modules: BTreeMap<String, String>,
+    /// Share of requests, from `0` to `1`, whose spans and events below
+    /// `WARN` are logged.
+    pub trace_sample_rate: f64,
+    /// Sensitive fields left out of the logs.
+    pub redact: RedactConfig,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_owned(),
+            modules: BTreeMap::new(),
+            trace_sample_rate: 1.0,
+            redact: RedactConfig::default(),
+        }
+    }
+}
+
+/// Which sensitive fields are redacted in logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedactConfig {
+    /// Log request URIs without their query, and peer, worker and webhook
+    /// URLs as only their scheme and host.
+    pub urls: bool,
+    /// Log the tenant of a request, its `X-Api-Key`, as a short hash that
+    /// still tells tenants apart. On by default, since the key is a
+    /// credential.
+    pub tenants: bool,
+}
+
+impl Default for RedactConfig {
+    fn default() -> Self {
+        Self {
+            urls: false,
+            tenants: true,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// Checks the levels and the sample rate.
+    pub fn validate(&self) -> Result<()> {
+        parse_level(&self.level).context("Invalid level")?;
+        for (module, level) in &self.modules {
+            if module.is_empty() || module.contains([',', '=', '[', ' ']) {
+                bail!("invalid module path '{}'", module);
+            }
+            parse_level(level).with_context(|| format!("Invalid level of '{}'", module))?;
+        }
+        if !(0.0..=1.0).contains(&self.trace_sample_rate) {
+            bail!(
+                "trace_sample_rate must be between 0 and 1, got {}",
+                self.trace_sample_rate
+            );
+        }
+        Ok(())
+    }
+
+    /// The filter for `level` and `modules`, as `RUST_LOG` would spell it.
+    pub fn directives(&self) -> String {
+        std::iter::once(self.level.clone())
+            .chain(
+                self.modules
+                    .iter()
+                    .map(|(module, level)| format!("{}={}", module, level)),
+            )
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Whether the request `request_id` is logged in full: the same answer
+    /// for the same ID, for about `trace_sample_rate` of all IDs.
+    pub fn sampled(&self, request_id: &str) -> bool {
+        if self.trace_sample_rate >= 1.0 {
+            return true;
+        }
+        let digest = Sha256::digest(request_id.as_bytes());
+        let hash = u64::from_be_bytes(digest[..8].try_into().expect("8 bytes"));
+        (hash as f64 / u64::MAX as f64) < self.trace_sample_rate
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter> {
+    level
+        .parse()
+        .map_err(|_| anyhow::anyhow!("unknown level '{}'", level))
+}
+
+impl RedactConfig {
+    /// A request URI as logged: without its query when redacting URLs.
+    pub fn uri<'a>(&self, uri: &'a Uri) -> Cow<'a, str> {
+        if self.urls {
+            Cow::Borrowed(uri.path())
+        } else {
+            Cow::Owned(uri.to_string())
+        }
+    }
+
+    /// A URL as logged: only its scheme, host and port when redacting URLs.
+    pub fn url<'a>(&self, url: &'a str) -> Cow<'a, str> {
+        if !self.urls {
+            return Cow::Borrowed(url);
+        }
+        match reqwest::Url::parse(url) {
+            Ok(parsed) => match (parsed.host_str(), parsed.port()) {
+                (Some(host), Some(port)) => {
+                    Cow::Owned(format!("{}://{}:{}", parsed.scheme(), host, port))
+                }
+                (Some(host), None) => Cow::Owned(format!("{}://{}", parsed.scheme(), host)),
+                (None, _) => Cow::Borrowed("[redacted]"),
+            },
+            Err(_) => Cow::Borrowed("[redacted]"),
+        }
+    }
+
+    /// An outbound request failure as logged: without the URL it was sent
+    /// to when redacting URLs.
+    pub fn request_error(&self, error: reqwest::Error) -> reqwest::Error {
+        if self.urls {
+            error.without_url()
+        } else {
+            error
+        }
+    }
+
+    /// A tenant as logged: the first 12 hex digits of its SHA-256 when
+    /// redacting tenants.
+    pub fn tenant<'a>(&self, tenant: &'a str) -> Cow<'a, str> {
+        if !self.tenants {
+            return Cow::Borrowed(tenant);
+        }
+        let digest = Sha256::digest(tenant.as_bytes());
+        Cow::Owned(format!("{:x}", digest)[..12].to_owned())
+    }
+}
+
+static REDACTION: OnceLock<RedactConfig> = OnceLock::new();
+
+/// The redaction the logs are written with: that of [`init`], or the
+/// default before it has run.
+pub fn redaction() -> RedactConfig {
+    REDACTION.get().copied().unwrap_or_default()
+}
+
+/// Installs the service's logging as the global default.
+pub fn init(config: &LoggingConfig) {
+    let _ = REDACTION.set(config.redact);
+    subscriber(config, std::io::stdout).init();
+}
+
+/// The subscriber [`init`] installs, writing to `writer`. The config must
+/// have been validated.
+pub fn subscriber<W>(config: &LoggingConfig, writer: W) -> impl Subscriber + Send + Sync + 'static
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let filter = EnvFilter::builder()
+        .parse(config.directives())
+        .expect("logging settings are checked by Config::validate");
+    let output = tracing_subscriber::fmt::layer()
+        .with_target(false)
+        .json()
+        .with_writer(writer)
+        .with_filter(filter::dynamic_filter_fn(|metadata, cx| {
+            *metadata.level() <= Level::WARN
+                || !cx.lookup_current().is_some_and(|span| {
+                    span.scope()
+                        .any(|s| s.extensions().get::<Unsampled>().is_some())
+                })
+        }));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(Sampling)
+        .with(output)
+}
+
+/// Marks the span of a request left out by sampling.
+struct Unsampled;
+
+/// Marks spans created with `sampled = false`, which the log output then
+/// leaves out along with everything below `WARN` inside them.
+struct Sampling;
+
+impl<S> Layer<S> for Sampling
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut sampled = SampledField(None);
+        attrs.record(&mut sampled);
+        if sampled.0 == Some(false) {
+            if let Some(span) = ctx.span(id) {
+                span.extensions_mut().insert(Unsampled);
+            }
+        }
+    }
+}
+
+struct SampledField(Option<bool>);
+
+impl Visit for SampledField {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if field.name() == "sampled" {
+            self.0 = Some(value);
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+}
@@ -0,0 +1,231 @@
+// image-compressor-rust-service/src/lossless.rs
+
+//! Lossless rotation and cropping of JPEG inputs, as jpegtran does them.
+//!
+//! Rotating a JPEG by a multiple of 90° or cropping it on MCU boundaries
+//! only moves its DCT blocks around and flips the sign of some of their
+//! coefficients. Requests for a JPEG that do nothing else are answered
+//! without decoding it to pixels: its quantized blocks are read with
+//! [`Coefficients::read`], rearranged and written again with its own
+//! quantization tables, so the output has exactly the pixels of the input
+//! instead of losing some more to a decode and re-encode.
+//!
+//! Only baseline JPEGs qualify. A rotation needs the edges that end up at
+//! the top and left of the output to fall on whole MCUs (8 or 16 pixels,
+//! depending on the chroma subsampling), and a crop its top left corner;
+//! other requests, and an explicit quality, take the pixel path.
+
+use crate::jpeg::{Coefficients, Component};
+use crate::options::{AnimationMode, CompressionOptions, Rotation};
+use crate::transform::{self, MaxDimensions};
+
+/// A JPEG rotated or cropped without loss.
+#[derive(Debug, Clone)]
+pub struct Transformed {
+    /// The JPEG, without the input's metadata.
+    pub data: Vec<u8>,
+    pub source_dimensions: (u32, u32),
+    pub dimensions: (u32, u32),
+}
+
+/// Whether the options ask for nothing a lossless transform cannot do:
+/// no pixels drawn, filtered or converted, no other output format or
+/// quality, and nothing that needs the decoded image.
+pub fn applies(options: &CompressionOptions) -> bool {
+    !options.quality_explicit
+        && options.animation == AnimationMode::FirstFrame
+        && options.frames.poster.is_none()
+        && options.filter.is_none()
+        && options.palette.is_none()
+        && options.color_space.is_none()
+        && options.grayscale.is_none()
+        && options.placeholder.is_none()
+        && !options.ocr
+        && options.border.is_none()
+        && options.corner_radius.is_none()
+        && options.watermark.is_none()
+        && options.budget.is_none()
+        && options.quant_tables.is_none()
+}
+
+/// Rotates and crops the JPEG `input` as the options ask without decoding
+/// it, when the request [`applies`], does rotate or crop, and the input
+/// allows it.
+pub fn transform(
+    input: &[u8],
+    options: &CompressionOptions,
+    max: MaxDimensions,
+) -> Option<Transformed> {
+    if !applies(options) {
+        return None;
+    }
+    let source = crate::decode::probe_dimensions(input).ok()?;
+    let rotated = transform::rotated_dimensions(source, options);
+    let region = if transform::output_dimensions(rotated, options, max) == rotated {
+        None
+    } else {
+        Some(transform::crop_region(rotated, options, max)?)
+    };
+    if options.rotate.is_none() && region.is_none() {
+        return None;
+    }
+    // Up to four components of 16-bit coefficients per pixel. Larger
+    // inputs are left to the pixel path, which rejects them.
+    crate::decode::check_size(source.0, source.1, 8).ok()?;
+    let mut coefficients = Coefficients::read(input).ok()?;
+    if (
+        u32::from(coefficients.width),
+        u32::from(coefficients.height),
+    ) != source
+    {
+        return None;
+    }
+    if let Some(rotation) = options.rotate {
+        coefficients = rotate(coefficients, rotation)?;
+    }
+    if let Some(region) = region {
+        coefficients = crop(coefficients, region)?;
+    }
+    Some(Transformed {
+        data: coefficients.write(),
+        source_dimensions: source,
+        dimensions: (
+            u32::from(coefficients.width),
+            u32::from(coefficients.height),
+        ),
+    })
+}
+
+/// Rotates the blocks clockwise, if the edges that become the top and
+/// left of the image fall on whole MCUs.
+fn rotate(coefficients: Coefficients, rotation: Rotation) -> Option<Coefficients> {
+    let (mcu_width, mcu_height) = coefficients.mcu_size();
+    let whole_width = u32::from(coefficients.width) % mcu_width == 0;
+    let whole_height = u32::from(coefficients.height) % mcu_height == 0;
+    let turns = match rotation {
+        // The bottom edge becomes the left one, the right edge the top one.
+        Rotation::Quarter => whole_height,
+        Rotation::Half => whole_width && whole_height,
+        Rotation::ThreeQuarters => whole_width,
+    };
+    if !turns {
+        return None;
+    }
+
+    let transposes = rotation != Rotation::Half;
+    let components = coefficients
+        .components
+        .into_iter()
+        .map(|component| {
+            let (wide, high) = (component.blocks_wide, component.blocks_high);
+            let (blocks_wide, blocks_high) = if transposes {
+                (high, wide)
+            } else {
+                (wide, high)
+            };
+            let blocks = (0..blocks_high)
+                .flat_map(|y| (0..blocks_wide).map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    let (from_x, from_y) = match rotation {
+                        Rotation::Quarter => (y, high - 1 - x),
+                        Rotation::Half => (wide - 1 - x, high - 1 - y),
+                        Rotation::ThreeQuarters => (wide - 1 - y, x),
+                    };
+                    turn_block(&component.blocks[from_y * wide + from_x], rotation)
+                })
+                .collect();
+            let (h, v) = component.sampling;
+            Component {
+                sampling: if transposes { (v, h) } else { (h, v) },
+                blocks_wide,
+                blocks_high,
+                blocks,
+                ..component
+            }
+        })
+        .collect();
+    let (width, height) = if transposes {
+        (coefficients.height, coefficients.width)
+    } else {
+        (coefficients.width, coefficients.height)
+    };
+    let tables = if transposes {
+        coefficients.tables.iter().map(transpose).collect()
+    } else {
+        coefficients.tables
+    };
+    Some(Coefficients {
+        width,
+        height,
+        tables,
+        components,
+    })
+}
+
+/// Rotates one block clockwise. A block's coefficients are in natural
+/// order, `v * 8 + u` for the vertical frequency `v` and the horizontal
+/// one `u`: rotating transposes them, and mirroring negates the odd
+/// frequencies along the mirrored axis.
+fn turn_block(block: &[i16; 64], rotation: Rotation) -> [i16; 64] {
+    let sign = |odd: bool, value: i16| if odd { -value } else { value };
+    std::array::from_fn(|index| {
+        let (v, u) = (index / 8, index % 8);
+        match rotation {
+            // Transposed, then mirrored horizontally.
+            Rotation::Quarter => sign(u % 2 == 1, block[u * 8 + v]),
+            Rotation::Half => sign((u + v) % 2 == 1, block[index]),
+            // Transposed, then mirrored vertically.
+            Rotation::ThreeQuarters => sign(v % 2 == 1, block[u * 8 + v]),
+        }
+    })
+}
+
+fn transpose(table: &[u16; 64]) -> [u16; 64] {
+    std::array::from_fn(|index| table[index % 8 * 8 + index / 8])
+}
+
+/// Crops the blocks to `(x, y, width, height)`, if its top left corner
+/// falls on an MCU boundary. The right and bottom edges need not: the
+/// blocks they cut through are kept and the decoder drops what lies past
+/// the new dimensions.
+fn crop(
+    coefficients: Coefficients,
+    (x, y, width, height): (u32, u32, u32, u32),
+) -> Option<Coefficients> {
+    let (mcu_width, mcu_height) = coefficients.mcu_size();
+    if x % mcu_width != 0 || y % mcu_height != 0 {
+        return None;
+    }
+    let (left, top) = ((x / mcu_width) as usize, (y / mcu_height) as usize);
+    let (mcus_wide, mcus_high) = (
+        width.div_ceil(mcu_width) as usize,
+        height.div_ceil(mcu_height) as usize,
+    );
+    let components = coefficients
+        .components
+        .into_iter()
+        .map(|component| {
+            let (h, v) = component.sampling;
+            let (h, v) = (usize::from(h), usize::from(v));
+            let (blocks_wide, blocks_high) = (mcus_wide * h, mcus_high * v);
+            let blocks = (0..blocks_high)
+                .flat_map(|row| {
+                    let from = (top * v + row) * component.blocks_wide + left * h;
+                    component.blocks[from..from + blocks_wide].to_vec()
+                })
+                .collect();
+            Component {
+                blocks_wide,
+                blocks_high,
+                blocks,
+                ..component
+            }
+        })
+        .collect();
+    Some(Coefficients {
+        width: width as u16,
+        height: height as u16,
+        tables: coefficients.tables,
+        components,
+    })
+}
@@ -1,114 +1,209 @@
-use axum::{
-    body::Bytes,
-    http::{header, HeaderMap, StatusCode},
-    response::{IntoResponse, Response},
-    routing::{get, post},
-    Json, Router,
-    extract::DefaultBodyLimit,
-};
-use image_compressor_rust_service::compress_image_bytes;
-use serde_json::json;
-use std::net::SocketAddr;
-use std::time::Instant;
-use tower_http::trace::TraceLayer;
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::logging::{self, LoggingConfig};
+use image_compressor_rust_service::server::listen::{self, Listener};
+use image_compressor_rust_service::server::{self, AppState};
+use image_compressor_rust_service::simd::{self, CpuFeatures};
+use image_compressor_rust_service::watch::{self, WatchOptions, Watcher};
+use image_compressor_rust_service::{pool, sandbox, warmup};
+use std::time::Duration;
 use tracing::{error, info, warn};
-use metrics_exporter_prometheus::PrometheusHandle;
-use std::sync::Arc;
 
-#[tokio::main]
-async fn main() {
-    // Initialize tracing (structured logging)
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        .with_target(false)
-        .json()
-        .init();
-
-    info!("Initializing server...");
+fn main() {
+    // The sandboxed decoder re-executes this binary; handle that before the
+    // async runtime spawns any threads, so the worker stays single-threaded.
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some(sandbox::WORKER_ARG) {
+        sandbox::run_worker(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some(watch::COMMAND) {
+        run_watch(&args[2..]);
+    }
 
-    let builder = metrics_exporter_prometheus::PrometheusBuilder::new();
-    let handle = builder.install_recorder().unwrap();
-    let handle = Arc::new(handle);
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to build the Tokio runtime")
+        .block_on(serve());
+}
 
-    // Build our application router
-    let app = Router::new()
-        .route("/compress", post(compress_handler))
-        .route("/health", get(health_handler))
-        .route("/metrics", get({
-            let handle = handle.clone();
-            move || metrics_handler(handle.clone())
-        }))
-        .layer(TraceLayer::new_for_http())
-        .layer(DefaultBodyLimit::max(10 * 1024 * 1024)); // 10 MB
+/// Loads the configuration and initializes tracing (structured logging)
+/// with its `[logging]` settings, or the default ones if it is invalid, so
+/// that can be logged.
+fn load_config() -> anyhow::Result<Config> {
+    let config = Config::load();
+    let default = LoggingConfig::default();
+    logging::init(config.as_ref().map_or(&default, |config| &config.logging));
+    if let Ok(config) = &config {
+        simd::init(config.cpu.simd);
+        pool::init(&config.buffer_pool);
+    }
+    config
+}
 
-    // Run the server
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8000));
-    info!("Server listening on {}", addr);
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+/// Compresses the images dropped into a directory, until killed.
+fn run_watch(args: &[String]) -> ! {
+    let options = match WatchOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{:#}\n{}", e, WatchOptions::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let watcher = load_config().and_then(|config| Watcher::new(options, config));
+    match watcher {
+        Ok(watcher) => watcher.run(),
+        Err(e) => {
+            error!("Cannot start watch mode: {:#}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
-/// Handles image compression requests.
-///
-/// It expects the image data in the request body and an optional
-/// `X-Compression-Quality` header to specify the quality (1-100).
-async fn compress_handler(headers: HeaderMap, body: Bytes) -> Response {
-    let start_time = Instant::now();
+async fn serve() {
+    let config = load_config();
+    info!("Initializing server...");
+
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Invalid configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    };
     info!(
-        "Received compression request. Body size: {} bytes",
-        body.len()
+        "Allowed input formats: {:?}, sandboxed decoding: {}",
+        config.allowed_input_formats, config.sandbox.enabled
     );
-
-    if body.is_empty() {
-        warn!("Request body is empty.");
-        return (StatusCode::BAD_REQUEST, "Request body cannot be empty.").into_response();
+    let features = CpuFeatures::detect();
+    info!(
+        "CPU: {}, features: [{}], SIMD path: {}",
+        features.arch,
+        features.names().join(", "),
+        simd::path().name()
+    );
+    for warning in config.warnings() {
+        warn!("Configuration warning: {}", warning);
     }
 
-    // Extract quality from header, with a default of 80
-    let quality = headers
-        .get("X-Compression-Quality")
-        .and_then(|v| v.to_str().ok())
-        .and_then(|s| s.parse::<u8>().ok())
-        .filter(|&q| (1..=100).contains(&q))
-        .unwrap_or(80);
+    // Held until shutdown, which flushes pending reports.
+    let _reporter = match config.error_reporting.init() {
+        Ok(reporter) => reporter,
+        Err(e) => {
+            error!("Failed to set up error reporting: {:#}", e);
+            std::process::exit(1);
+        }
+    };
 
-    info!("Using compression quality: {}", quality);
+    let handle = match config.metrics.install() {
+        Ok(handle) => handle,
+        Err(e) => {
+            error!("Failed to set up metrics: {:#}", e);
+            std::process::exit(1);
+        }
+    };
 
-    match compress_image_bytes(&body, quality) {
-        Ok(compressed_data) => {
-            let duration = start_time.elapsed();
-            info!(
-                "Compression successful in {:.2?}. Original size: {}, Compressed size: {}",
-                duration,
-                body.len(),
-                compressed_data.len()
-            );
+    let listener = match Listener::bind(&config).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to open listening socket: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let http = config.http.clone();
+    let state = AppState::new(config, handle);
+    spawn_warm_up(state.clone());
+    spawn_upload_reaper(state.clone());
+    spawn_peer_poller(state.clone());
+    spawn_metrics_listener(state.clone()).await;
 
-            (
-                StatusCode::OK,
-                [(header::CONTENT_TYPE, "image/jpeg")],
-                compressed_data,
-            )
-                .into_response()
+    // Build our application router
+    let app = server::router(state);
+
+    // Run the server
+    if let Err(e) = listen::serve(listener, app, &http).await {
+        error!("Server error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Polls the load of the replicas work is shared with, if any.
+fn spawn_peer_poller(state: AppState) {
+    if !state.peers.enabled() {
+        return;
+    }
+    info!("Sharing work with {} peers.", state.config.peers.urls.len());
+    let period = state.config.peers.poll_interval();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            state.peers.poll().await;
         }
+    });
+}
+
+/// Serves `/metrics` on its own address, if one is configured.
+async fn spawn_metrics_listener(state: AppState) {
+    let Some(addr) = state
+        .config
+        .metrics
+        .bind_addr
+        .filter(|_| state.config.metrics.serves_endpoint())
+    else {
+        return;
+    };
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
         Err(e) => {
-            error!("Image compression failed: {:?}", e);
-            (
-                StatusCode::UNPROCESSABLE_ENTITY,
-                format!("Failed to compress image: {}", e),
-            )
-                .into_response()
+            error!("Failed to bind the metrics listener on {}: {}", addr, e);
+            std::process::exit(1);
         }
-    }
+    };
+    info!("Serving /metrics on {}", addr);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, server::metrics_endpoint::router(state)).await {
+            error!("Metrics listener failed: {}", e);
+        }
+    });
 }
 
-/// Provides a simple health check endpoint.
-async fn health_handler() -> impl IntoResponse {
-    info!("Health check requested.");
-    (StatusCode::OK, Json(json!({ "status": "ok" })))
+/// Warms up the codecs on the background pool so `/health` answers right
+/// away, then flips `/ready`. A failed warm-up leaves the service unready.
+fn spawn_warm_up(state: AppState) {
+    if !state.config.warm_up {
+        state.mark_ready();
+        return;
+    }
+    let background = state.background.clone();
+    background.spawn(move || match warmup::warm_up(&state.config) {
+        Ok(_) => {
+            info!("Warm-up complete; service is ready.");
+            state.mark_ready();
+        }
+        Err(e) => error!("Warm-up failed, service stays unready: {:#}", e),
+    });
 }
 
-async fn metrics_handler(handle: Arc<PrometheusHandle>) -> impl IntoResponse {
-    let body = handle.render();
-    (StatusCode::OK, [(header::CONTENT_TYPE, "text/plain")], body)
+/// Removes expired uploads and enforces the upload storage cap every
+/// `reap_interval_secs`.
+fn spawn_upload_reaper(state: AppState) {
+    if !state.config.uploads.enabled {
+        return;
+    }
+    let period = Duration::from_secs(state.config.uploads.reap_interval_secs);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match state.uploads.reap(0).await {
+                Ok(reaped) if reaped.expired + reaped.evicted > 0 => info!(
+                    "Removed {} expired and {} evicted uploads; {} bytes remain.",
+                    reaped.expired, reaped.evicted, reaped.bytes
+                ),
+                Ok(_) => {}
+                Err(e) => warn!("Failed to reap uploads: {:#}", e),
+            }
+        }
+    });
 }
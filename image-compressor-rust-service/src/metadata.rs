@@ -0,0 +1,906 @@
+// image-compressor-rust-service/src/metadata.rs
+
+//! Metadata written to outputs: EXIF carried over from the input, and
+//! fields the caller adds.
+//!
+//! The pipeline re-encodes pixels, so nothing from the input container
+//! survives on its own. [`apply`] reads the input's EXIF block (JPEG APP1,
+//! PNG `eXIf` or WebP `EXIF`), drops what the [`MetadataPolicy`] says to
+//! drop, merges in the request's [`CustomMetadata`] and writes the result,
+//! plus any XMP packet, into the output. By default orientation, copyright
+//! and camera settings are kept, while GPS position, serial numbers and the
+//! embedded thumbnail are removed.
+
+use crate::pool;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::debug;
+
+/// `Exif\0\0`, in front of the TIFF data in JPEG APP1 segments.
+const EXIF_PREFIX: &[u8] = b"Exif\0\0";
+
+pub const ORIENTATION: u16 = 0x0112;
+pub const COPYRIGHT: u16 = 0x8298;
+pub const MODEL: u16 = 0x0110;
+pub const DATE_TIME: u16 = 0x0132;
+pub const DATE_TIME_ORIGINAL: u16 = 0x9003;
+pub const EXIF_IFD: u16 = 0x8769;
+pub const GPS_IFD: u16 = 0x8825;
+pub const INTEROP_IFD: u16 = 0xA005;
+pub const CAMERA_OWNER_NAME: u16 = 0xA430;
+pub const BODY_SERIAL_NUMBER: u16 = 0xA431;
+pub const LENS_SERIAL_NUMBER: u16 = 0xA435;
+pub const MAKER_NOTE: u16 = 0x927C;
+const STRIP_OFFSETS: u16 = 0x0111;
+const STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TILE_OFFSETS: u16 = 0x0144;
+const TILE_BYTE_COUNTS: u16 = 0x0145;
+const THUMBNAIL_OFFSET: u16 = 0x0201;
+const THUMBNAIL_LENGTH: u16 = 0x0202;
+const PIXEL_X_DIMENSION: u16 = 0xA002;
+const PIXEL_Y_DIMENSION: u16 = 0xA003;
+
+/// Tags removed by [`MetadataPolicy::strip_serial_numbers`].
+const SERIAL_NUMBER_TAGS: [u16; 3] = [CAMERA_OWNER_NAME, BODY_SERIAL_NUMBER, LENS_SERIAL_NUMBER];
+
+/// Tags that are never copied: they point at data the output does not
+/// carry, describe the source encoding, or (maker notes) hold private
+/// offsets that cannot be relocated.
+const DROPPED_TAGS: [u16; 7] = [
+    STRIP_OFFSETS,
+    STRIP_BYTE_COUNTS,
+    TILE_OFFSETS,
+    TILE_BYTE_COUNTS,
+    PIXEL_X_DIMENSION,
+    PIXEL_Y_DIMENSION,
+    MAKER_NOTE,
+];
+
+const ASCII: u16 = 2;
+const LONG: u16 = 4;
+
+/// Which EXIF metadata is copied to the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetadataPolicy {
+    /// Copy EXIF metadata at all. `false` strips everything.
+    pub keep_exif: bool,
+    /// Remove the GPS block (position, altitude, timestamps).
+    pub strip_gps: bool,
+    /// Remove camera body and lens serial numbers and the camera owner name.
+    pub strip_serial_numbers: bool,
+    /// Remove the embedded preview thumbnail, which shows the original
+    /// rather than the processed image.
+    pub strip_thumbnail: bool,
+}
+
+impl Default for MetadataPolicy {
+    fn default() -> Self {
+        Self {
+            keep_exif: true,
+            strip_gps: true,
+            strip_serial_numbers: true,
+            strip_thumbnail: true,
+        }
+    }
+}
+
+/// The metadata policy, with overrides per tenant.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetadataConfig {
+    /// Policy for callers without an override.
+    pub policy: MetadataPolicy,
+    /// Policies by `X-Api-Key` value.
+    pub api_keys: HashMap<String, MetadataPolicy>,
+}
+
+impl MetadataConfig {
+    /// The policy for a caller with the given API key.
+    pub fn policy_for(&self, api_key: Option<&str>) -> &MetadataPolicy {
+        api_key
+            .and_then(|key| self.api_keys.get(key))
+            .unwrap_or(&self.policy)
+    }
+}
+
+/// Metadata the caller asks to add to the output.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CustomMetadata {
+    /// EXIF text fields, replacing carried-over values with the same tag.
+    pub exif: Vec<(u16, String)>,
+    /// An XMP packet, written as is.
+    pub xmp: Option<Vec<u8>>,
+}
+
+impl CustomMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.exif.is_empty() && self.xmp.is_none()
+    }
+
+    /// Parses `Name=value` pairs separated by `;`, as sent in
+    /// `X-Metadata-Exif`. Tags are given by name or number (see
+    /// [`text_tag`]); pairs with unknown tags or non-ASCII values are skipped.
+    pub fn parse_exif(value: &str) -> Vec<(u16, String)> {
+        value
+            .split(';')
+            .filter_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                let value = value.trim();
+                (value.is_ascii() && !value.is_empty())
+                    .then(|| Some((text_tag(name.trim())?, value.to_string())))
+                    .flatten()
+            })
+            .collect()
+    }
+}
+
+/// IFD0 text fields callers may set.
+const TEXT_TAGS: [(&str, u16); 7] = [
+    ("ImageDescription", 0x010E),
+    ("Make", 0x010F),
+    ("Model", MODEL),
+    ("Software", 0x0131),
+    ("DateTime", DATE_TIME),
+    ("Artist", 0x013B),
+    ("Copyright", COPYRIGHT),
+];
+
+/// Looks up a settable text tag by its EXIF name (case-insensitive) or its
+/// number, e.g. `Artist` or `0x013B`.
+pub fn text_tag(name: &str) -> Option<u16> {
+    let number = name
+        .strip_prefix("0x")
+        .or_else(|| name.strip_prefix("0X"))
+        .and_then(|hex| u16::from_str_radix(hex, 16).ok());
+    TEXT_TAGS
+        .iter()
+        .find(|(tag_name, tag)| Some(*tag) == number || tag_name.eq_ignore_ascii_case(name))
+        .map(|&(_, tag)| tag)
+}
+
+/// Writes metadata into the encoded `output`: the EXIF block of `input`,
+/// filtered by `policy`, merged with the caller's `custom` fields.
+///
+/// This is best effort: when the input has no (readable) EXIF block or the
+/// output container cannot hold a block, `output` is returned without it.
+pub fn apply(
+    input: &[u8],
+    output: Vec<u8>,
+    policy: &MetadataPolicy,
+    custom: &CustomMetadata,
+) -> Vec<u8> {
+    let mut exif = policy.keep_exif.then(|| Exif::from_image(input)).flatten();
+    if let Some(exif) = &mut exif {
+        exif.filter(policy);
+    }
+    if !custom.exif.is_empty() {
+        let exif = exif.get_or_insert_with(Exif::new);
+        for (tag, value) in &custom.exif {
+            exif.set_text(*tag, value);
+        }
+    }
+
+    let mut output = output;
+    if let Some(exif) = exif {
+        output = match embed(&output, &exif.to_tiff()) {
+            Some(with_exif) => {
+                pool::give(output);
+                with_exif
+            }
+            None => {
+                debug!("EXIF metadata does not fit the output container; dropped it.");
+                output
+            }
+        };
+    }
+    if let Some(xmp) = &custom.xmp {
+        output = match embed_xmp(&output, xmp) {
+            Some(with_xmp) => {
+                pool::give(output);
+                with_xmp
+            }
+            None => {
+                debug!("XMP metadata does not fit the output container; dropped it.");
+                output
+            }
+        };
+    }
+    output
+}
+
+/// Signature of XMP packets in JPEG APP1 segments.
+const XMP_JPEG_PREFIX: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// PNG iTXt keyword for XMP, followed by empty compression, language and
+/// translated-keyword fields.
+const XMP_PNG_PREFIX: &[u8] = b"XML:com.adobe.xmp\0\0\0\0\0";
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Signature of ICC profile chunks in JPEG APP2 segments.
+const ICC_JPEG_PREFIX: &[u8] = b"ICC_PROFILE\0";
+/// Largest profile chunk an APP2 segment holds, after its prefix and its
+/// sequence number and count.
+const ICC_JPEG_CHUNK: usize = u16::MAX as usize - 2 - ICC_JPEG_PREFIX.len() - 2;
+
+/// VP8X flag bits.
+const WEBP_FLAG_ICC: u8 = 0x20;
+const WEBP_FLAG_EXIF: u8 = 0x08;
+const WEBP_FLAG_XMP: u8 = 0x04;
+
+/// Marker and payload of each JPEG segment before the image data.
+fn jpeg_segments(jpeg: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut segments = Vec::new();
+    let mut at = 2;
+    while let Some(&[0xFF, marker]) = jpeg.get(at..at + 2) {
+        match marker {
+            // Fill byte before a marker.
+            0xFF => at += 1,
+            // Markers without a payload.
+            0x01 | 0xD0..=0xD8 => at += 2,
+            // Start of scan or end of image: no more metadata segments.
+            0xD9 | 0xDA => break,
+            _ => {
+                let Some(len) = jpeg.get(at + 2..at + 4) else {
+                    break;
+                };
+                let len = usize::from(u16::from_be_bytes([len[0], len[1]])).max(2);
+                let Some(payload) = jpeg.get(at + 4..at + 2 + len) else {
+                    break;
+                };
+                segments.push((marker, payload));
+                at += 2 + len;
+            }
+        }
+    }
+    segments
+}
+
+/// Type, data and end offset of each PNG chunk.
+fn png_chunks(png: &[u8]) -> Vec<(&[u8], &[u8], usize)> {
+    let mut chunks = Vec::new();
+    let mut at = PNG_MAGIC.len();
+    while let (Some(len), Some(kind)) = (png.get(at..at + 4), png.get(at + 4..at + 8)) {
+        let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+        let Some(data) = png.get(at + 8..(at + 8).saturating_add(len)) else {
+            break;
+        };
+        at += 12 + len;
+        chunks.push((kind, data, at));
+        if kind == b"IEND" {
+            break;
+        }
+    }
+    chunks
+}
+
+/// FourCC and data of each WebP chunk.
+fn webp_chunks(webp: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut chunks = Vec::new();
+    let mut at = 12;
+    while let (Some(kind), Some(len)) = (webp.get(at..at + 4), webp.get(at + 4..at + 8)) {
+        let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+        let Some(data) = webp.get(at + 8..(at + 8).saturating_add(len)) else {
+            break;
+        };
+        chunks.push((kind, data));
+        at += 8 + len + (len & 1);
+    }
+    chunks
+}
+
+fn is_jpeg(image: &[u8]) -> bool {
+    image.starts_with(&[0xFF, 0xD8])
+}
+
+fn is_webp(image: &[u8]) -> bool {
+    image.get(0..4) == Some(b"RIFF") && image.get(8..12) == Some(b"WEBP")
+}
+
+/// Returns the TIFF-structured EXIF block of a JPEG, PNG or WebP file.
+pub fn find_exif(image: &[u8]) -> Option<&[u8]> {
+    if is_jpeg(image) {
+        jpeg_segments(image)
+            .into_iter()
+            .filter(|(marker, _)| *marker == 0xE1)
+            .find_map(|(_, payload)| payload.strip_prefix(EXIF_PREFIX))
+    } else if image.starts_with(PNG_MAGIC) {
+        png_chunks(image)
+            .into_iter()
+            .find(|(kind, _, _)| *kind == b"eXIf")
+            .map(|(_, data, _)| data)
+    } else if is_webp(image) {
+        webp_chunks(image)
+            .into_iter()
+            .find(|(kind, _)| *kind == b"EXIF")
+            // Some writers keep the JPEG-style prefix.
+            .map(|(_, data)| data.strip_prefix(EXIF_PREFIX).unwrap_or(data))
+    } else {
+        None
+    }
+}
+
+/// Returns the XMP packet of a JPEG, PNG or WebP file.
+pub fn find_xmp(image: &[u8]) -> Option<&[u8]> {
+    if is_jpeg(image) {
+        jpeg_segments(image)
+            .into_iter()
+            .filter(|(marker, _)| *marker == 0xE1)
+            .find_map(|(_, payload)| payload.strip_prefix(XMP_JPEG_PREFIX))
+    } else if image.starts_with(PNG_MAGIC) {
+        png_chunks(image)
+            .into_iter()
+            .filter(|(kind, _, _)| *kind == b"iTXt")
+            .find_map(|(_, data, _)| data.strip_prefix(XMP_PNG_PREFIX))
+    } else if is_webp(image) {
+        webp_chunks(image)
+            .into_iter()
+            .find(|(kind, _)| *kind == b"XMP ")
+            .map(|(_, data)| data)
+    } else {
+        None
+    }
+}
+
+/// Returns the embedded ICC profile of a JPEG, PNG or WebP file.
+pub fn find_icc(image: &[u8]) -> Option<Vec<u8>> {
+    if is_jpeg(image) {
+        // Large profiles are split over several segments, numbered from 1.
+        let mut chunks: Vec<(u8, &[u8])> = jpeg_segments(image)
+            .into_iter()
+            .filter(|(marker, _)| *marker == 0xE2)
+            .filter_map(|(_, payload)| payload.strip_prefix(ICC_JPEG_PREFIX))
+            .filter_map(|chunk| Some((*chunk.first()?, chunk.get(2..)?)))
+            .collect();
+        chunks.sort_by_key(|(sequence, _)| *sequence);
+        let profile: Vec<u8> = chunks
+            .into_iter()
+            .flat_map(|(_, data)| data)
+            .copied()
+            .collect();
+        Some(profile).filter(|p| !p.is_empty())
+    } else if image.starts_with(PNG_MAGIC) {
+        // `iCCP` is zlib-compressed; let the PNG decoder inflate it.
+        let mut decoder = image::codecs::png::PngDecoder::new(std::io::Cursor::new(image)).ok()?;
+        image::ImageDecoder::icc_profile(&mut decoder)
+    } else if is_webp(image) {
+        webp_chunks(image)
+            .into_iter()
+            .find(|(kind, _)| *kind == b"ICCP")
+            .map(|(_, data)| data.to_vec())
+    } else {
+        None
+    }
+}
+
+/// Writes an ICC profile into an encoded JPEG or extended WebP. Returns
+/// `None` for other containers.
+pub fn embed_icc(image: &[u8], icc: &[u8]) -> Option<Vec<u8>> {
+    if is_jpeg(image) {
+        let chunks: Vec<&[u8]> = icc.chunks(ICC_JPEG_CHUNK).collect();
+        let count = u8::try_from(chunks.len()).ok()?;
+        // After APP0 and APP1, where readers expect the profile.
+        let at = 2 + jpeg_segments(image)
+            .into_iter()
+            .take_while(|(marker, _)| matches!(marker, 0xE0 | 0xE1))
+            .map(|(_, payload)| 4 + payload.len())
+            .sum::<usize>();
+        let mut out = Vec::with_capacity(image.len() + icc.len() + 18 * chunks.len());
+        out.extend_from_slice(image.get(..at)?);
+        for (index, chunk) in chunks.iter().enumerate() {
+            let len = (2 + ICC_JPEG_PREFIX.len() + 2 + chunk.len()) as u16;
+            out.extend_from_slice(&[0xFF, 0xE2]);
+            out.extend_from_slice(&len.to_be_bytes());
+            out.extend_from_slice(ICC_JPEG_PREFIX);
+            out.extend_from_slice(&[index as u8 + 1, count]);
+            out.extend_from_slice(chunk);
+        }
+        out.extend_from_slice(image.get(at..)?);
+        Some(out)
+    } else if is_webp(image) {
+        // `ICCP` must directly follow `VP8X`.
+        if image.get(12..16) != Some(b"VP8X") || image.len() < 30 {
+            return None;
+        }
+        let mut chunk = Vec::with_capacity(8 + icc.len() + 1);
+        chunk.extend_from_slice(b"ICCP");
+        chunk.extend_from_slice(&u32::try_from(icc.len()).ok()?.to_le_bytes());
+        chunk.extend_from_slice(icc);
+        if icc.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        let mut out = [&image[..30], &chunk, &image[30..]].concat();
+        out[20] |= WEBP_FLAG_ICC;
+        let riff_len = u32::try_from(out.len() - 8).ok()?;
+        out[4..8].copy_from_slice(&riff_len.to_le_bytes());
+        Some(out)
+    } else {
+        None
+    }
+}
+
+/// Writes a TIFF-structured EXIF block into an encoded JPEG, PNG or extended
+/// (`VP8X`) WebP. Returns `None` for other containers, or when the block is
+/// too large for a JPEG segment.
+pub fn embed(image: &[u8], tiff: &[u8]) -> Option<Vec<u8>> {
+    if is_jpeg(image) {
+        jpeg_insert_app1(image, EXIF_PREFIX, tiff)
+    } else if image.starts_with(PNG_MAGIC) {
+        png_insert_chunk(image, b"eXIf", tiff)
+    } else if is_webp(image) {
+        webp_append_chunk(image, b"EXIF", WEBP_FLAG_EXIF, tiff)
+    } else {
+        None
+    }
+}
+
+/// Writes an XMP packet into an encoded JPEG, PNG or extended WebP.
+pub fn embed_xmp(image: &[u8], xmp: &[u8]) -> Option<Vec<u8>> {
+    if is_jpeg(image) {
+        jpeg_insert_app1(image, XMP_JPEG_PREFIX, xmp)
+    } else if image.starts_with(PNG_MAGIC) {
+        png_insert_chunk(image, b"iTXt", &[XMP_PNG_PREFIX, xmp].concat())
+    } else if is_webp(image) {
+        webp_append_chunk(image, b"XMP ", WEBP_FLAG_XMP, xmp)
+    } else {
+        None
+    }
+}
+
+/// Adds an APP1 segment after the leading APP0 (JFIF) and APP1 segments, so
+/// EXIF stays first and XMP follows it.
+fn jpeg_insert_app1(jpeg: &[u8], prefix: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+    let len = u16::try_from(2 + prefix.len() + data.len()).ok()?;
+    let at = 2 + jpeg_segments(jpeg)
+        .into_iter()
+        .take_while(|(marker, _)| matches!(marker, 0xE0 | 0xE1))
+        .map(|(_, payload)| 4 + payload.len())
+        .sum::<usize>();
+    let (head, tail) = (jpeg.get(..at)?, jpeg.get(at..)?);
+
+    let mut out = Vec::with_capacity(jpeg.len() + 2 + usize::from(len));
+    out.extend_from_slice(head);
+    out.extend_from_slice(&[0xFF, 0xE1]);
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(prefix);
+    out.extend_from_slice(data);
+    out.extend_from_slice(tail);
+    Some(out)
+}
+
+/// Adds a chunk right after `IHDR`, ahead of the image data as `eXIf`
+/// requires.
+fn png_insert_chunk(png: &[u8], kind: &[u8; 4], data: &[u8]) -> Option<Vec<u8>> {
+    let (_, _, at) = png_chunks(png)
+        .into_iter()
+        .next()
+        .filter(|(kind, _, _)| *kind == b"IHDR")?;
+    let mut chunk = Vec::with_capacity(12 + data.len());
+    chunk.extend_from_slice(&u32::try_from(data.len()).ok()?.to_be_bytes());
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(&crc32fast::hash(&chunk[4..]).to_be_bytes());
+    Some([&png[..at], &chunk, &png[at..]].concat())
+}
+
+fn webp_append_chunk(webp: &[u8], kind: &[u8; 4], flag: u8, data: &[u8]) -> Option<Vec<u8>> {
+    // Simple (VP8/VP8L-only) files cannot carry metadata chunks.
+    if webp.get(12..16) != Some(b"VP8X") || webp.len() < 30 {
+        return None;
+    }
+    let mut out = webp.to_vec();
+    out[20] |= flag;
+    out.extend_from_slice(kind);
+    out.extend_from_slice(&u32::try_from(data.len()).ok()?.to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+    let riff_len = u32::try_from(out.len() - 8).ok()?;
+    out[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    Some(out)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    fn u16(self, data: &[u8], at: usize) -> Option<u16> {
+        let bytes = data.get(at..at.checked_add(2)?)?.try_into().ok()?;
+        Some(match self {
+            Self::Little => u16::from_le_bytes(bytes),
+            Self::Big => u16::from_be_bytes(bytes),
+        })
+    }
+
+    fn u32(self, data: &[u8], at: usize) -> Option<u32> {
+        let bytes = data.get(at..at.checked_add(4)?)?.try_into().ok()?;
+        Some(match self {
+            Self::Little => u32::from_le_bytes(bytes),
+            Self::Big => u32::from_be_bytes(bytes),
+        })
+    }
+
+    fn put_u16(self, out: &mut Vec<u8>, value: u16) {
+        out.extend_from_slice(&match self {
+            Self::Little => value.to_le_bytes(),
+            Self::Big => value.to_be_bytes(),
+        });
+    }
+
+    fn put_u32(self, out: &mut Vec<u8>, value: u32) {
+        out.extend_from_slice(&self.u32_bytes(value));
+    }
+
+    fn set_u32(self, out: &mut [u8], at: usize, value: u32) {
+        out[at..at + 4].copy_from_slice(&self.u32_bytes(value));
+    }
+
+    fn u32_bytes(self, value: u32) -> [u8; 4] {
+        match self {
+            Self::Little => value.to_le_bytes(),
+            Self::Big => value.to_be_bytes(),
+        }
+    }
+}
+
+/// Size in bytes of one value of a TIFF field type.
+fn type_size(kind: u16) -> Option<usize> {
+    match kind {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+/// A field whose value is kept as raw bytes in the block's byte order.
+#[derive(Debug, Clone)]
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    value: Vec<u8>,
+}
+
+/// What an offset field points at.
+#[derive(Debug, Clone)]
+enum Child {
+    Ifd(Ifd),
+    /// The JPEG thumbnail of IFD1.
+    Blob(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Default)]
+struct Ifd {
+    entries: Vec<Entry>,
+    children: Vec<(u16, Child)>,
+}
+
+/// Nesting below IFD0: the Exif IFD, then the interoperability IFD.
+const MAX_DEPTH: usize = 2;
+
+impl Ifd {
+    /// Parses the IFD at `offset`, returning it and the offset of the next
+    /// one. Fields that are malformed or point outside `tiff` are skipped.
+    fn parse(tiff: &[u8], order: ByteOrder, offset: usize, depth: usize) -> Option<(Self, u32)> {
+        let count = usize::from(order.u16(tiff, offset)?);
+        let mut ifd = Self::default();
+        let mut thumbnail = None;
+        let mut thumbnail_len = None;
+
+        for index in 0..count {
+            let at = offset + 2 + index * 12;
+            let tag = order.u16(tiff, at)?;
+            let kind = order.u16(tiff, at + 2)?;
+            let count = order.u32(tiff, at + 4)?;
+            let Some(size) = type_size(kind).and_then(|s| s.checked_mul(count as usize)) else {
+                continue;
+            };
+            let value = if size <= 4 {
+                tiff.get(at + 8..at + 8 + size)
+            } else {
+                let start = order.u32(tiff, at + 8)? as usize;
+                start.checked_add(size).and_then(|end| tiff.get(start..end))
+            };
+            let Some(value) = value else {
+                continue;
+            };
+            let pointer = || (size == 4).then(|| order.u32(value, 0)).flatten();
+
+            match tag {
+                EXIF_IFD | GPS_IFD | INTEROP_IFD => {
+                    let child = pointer()
+                        .filter(|_| depth < MAX_DEPTH)
+                        .and_then(|at| Self::parse(tiff, order, at as usize, depth + 1));
+                    if let Some((child, _)) = child {
+                        ifd.children.push((tag, Child::Ifd(child)));
+                    }
+                }
+                THUMBNAIL_OFFSET => thumbnail = pointer(),
+                THUMBNAIL_LENGTH => {
+                    thumbnail_len = pointer();
+                    ifd.entries.push(Entry {
+                        tag,
+                        kind,
+                        count,
+                        value: value.to_vec(),
+                    });
+                }
+                _ if DROPPED_TAGS.contains(&tag) => {}
+                _ => ifd.entries.push(Entry {
+                    tag,
+                    kind,
+                    count,
+                    value: value.to_vec(),
+                }),
+            }
+        }
+
+        if let (Some(start), Some(len)) = (thumbnail, thumbnail_len) {
+            let (start, len) = (start as usize, len as usize);
+            if let Some(blob) = start.checked_add(len).and_then(|end| tiff.get(start..end)) {
+                ifd.children
+                    .push((THUMBNAIL_OFFSET, Child::Blob(blob.to_vec())));
+            }
+        }
+
+        let next = order.u32(tiff, offset + 2 + count * 12).unwrap_or(0);
+        Some((ifd, next))
+    }
+
+    fn text(&self, tag: u16) -> Option<&str> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|e| e.tag == tag && e.kind == ASCII)?;
+        std::str::from_utf8(&entry.value)
+            .ok()
+            .map(|s| s.trim_end_matches('\0'))
+    }
+
+    fn child(&self, tag: u16) -> Option<&Ifd> {
+        self.children
+            .iter()
+            .find_map(|(child_tag, child)| match child {
+                Child::Ifd(ifd) if *child_tag == tag => Some(ifd),
+                _ => None,
+            })
+    }
+
+    fn has_tag(&self, tag: u16) -> bool {
+        self.entries.iter().any(|e| e.tag == tag)
+            || self.children.iter().any(|(child_tag, child)| {
+                *child_tag == tag || matches!(child, Child::Ifd(ifd) if ifd.has_tag(tag))
+            })
+    }
+
+    fn remove_tags(&mut self, tags: &[u16]) {
+        self.entries.retain(|e| !tags.contains(&e.tag));
+        self.children.retain(|(tag, _)| !tags.contains(tag));
+        for (_, child) in &mut self.children {
+            if let Child::Ifd(ifd) = child {
+                ifd.remove_tags(tags);
+            }
+        }
+    }
+
+    /// Appends the IFD, its out-of-line values and its children to `out`,
+    /// and returns the position of its next-IFD offset.
+    fn write(&self, out: &mut Vec<u8>, order: ByteOrder) -> usize {
+        let mut fields: Vec<(u16, u16, u32, Option<&[u8]>)> = self
+            .entries
+            .iter()
+            .map(|e| (e.tag, e.kind, e.count, Some(e.value.as_slice())))
+            .chain(self.children.iter().map(|(tag, _)| (*tag, LONG, 1, None)))
+            .collect();
+        // TIFF requires fields in ascending tag order.
+        fields.sort_by_key(|field| field.0);
+
+        let start = out.len();
+        let data_start = start + 2 + fields.len() * 12 + 4;
+        let mut data = Vec::new();
+        let mut pointers = Vec::new();
+
+        order.put_u16(out, fields.len() as u16);
+        for (tag, kind, count, value) in fields {
+            order.put_u16(out, tag);
+            order.put_u16(out, kind);
+            order.put_u32(out, count);
+            match value {
+                Some(value) if value.len() <= 4 => {
+                    out.extend_from_slice(value);
+                    out.resize(out.len() + 4 - value.len(), 0);
+                }
+                Some(value) => {
+                    order.put_u32(out, (data_start + data.len()) as u32);
+                    data.extend_from_slice(value);
+                    if data.len() % 2 == 1 {
+                        data.push(0);
+                    }
+                }
+                None => {
+                    pointers.push((tag, out.len()));
+                    order.put_u32(out, 0);
+                }
+            }
+        }
+        let next = out.len();
+        order.put_u32(out, 0);
+        out.extend_from_slice(&data);
+
+        for (tag, child) in &self.children {
+            if out.len() % 2 == 1 {
+                out.push(0);
+            }
+            let child_at = out.len() as u32;
+            if let Some(&(_, pointer)) = pointers.iter().find(|(t, _)| t == tag) {
+                order.set_u32(out, pointer, child_at);
+            }
+            match child {
+                Child::Ifd(ifd) => {
+                    ifd.write(out, order);
+                }
+                Child::Blob(blob) => out.extend_from_slice(blob),
+            }
+        }
+        next
+    }
+}
+
+/// The calendar date of an EXIF timestamp, in the camera's local time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureDate {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl CaptureDate {
+    /// Parses the date part of `YYYY:MM:DD HH:MM:SS`. Cameras without a
+    /// clock write zeros or blanks, which are rejected.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.get(..10)?.split(':');
+        let mut next = || parts.next()?.trim().parse::<u16>().ok();
+        let (year, month, day) = (next()?, next()?, next()?);
+        ((1..=9999).contains(&year) && (1..=12).contains(&month) && (1..=31).contains(&day))
+            .then_some(Self {
+                year,
+                month: month as u8,
+                day: day as u8,
+            })
+    }
+}
+
+/// A parsed EXIF block.
+#[derive(Debug, Clone)]
+pub struct Exif {
+    order: ByteOrder,
+    ifd0: Ifd,
+    /// IFD1, which describes the embedded thumbnail.
+    thumbnail: Option<Ifd>,
+}
+
+impl Exif {
+    /// Parses a TIFF-structured EXIF block.
+    pub fn parse(tiff: &[u8]) -> Option<Self> {
+        let order = match tiff.get(0..2)? {
+            b"II" => ByteOrder::Little,
+            b"MM" => ByteOrder::Big,
+            _ => return None,
+        };
+        if order.u16(tiff, 2)? != 42 {
+            return None;
+        }
+        let (ifd0, next) = Ifd::parse(tiff, order, order.u32(tiff, 4)? as usize, 0)?;
+        let thumbnail = (next != 0)
+            .then(|| Ifd::parse(tiff, order, next as usize, MAX_DEPTH))
+            .flatten()
+            .map(|(ifd, _)| ifd)
+            .filter(|ifd| ifd.has_tag(THUMBNAIL_OFFSET));
+        Some(Self {
+            order,
+            ifd0,
+            thumbnail,
+        })
+    }
+
+    /// Reads the EXIF block of a JPEG, PNG or WebP file.
+    pub fn from_image(image: &[u8]) -> Option<Self> {
+        find_exif(image).and_then(Self::parse)
+    }
+
+    /// An empty little-endian block.
+    pub fn new() -> Self {
+        Self {
+            order: ByteOrder::Little,
+            ifd0: Ifd::default(),
+            thumbnail: None,
+        }
+    }
+
+    /// The value of an IFD0 text field.
+    pub fn text(&self, tag: u16) -> Option<&str> {
+        self.ifd0.text(tag)
+    }
+
+    /// When the photo was taken: `DateTimeOriginal`, or `DateTime` for
+    /// files without one.
+    pub fn capture_date(&self) -> Option<CaptureDate> {
+        self.ifd0
+            .child(EXIF_IFD)
+            .and_then(|exif| exif.text(DATE_TIME_ORIGINAL))
+            .and_then(CaptureDate::parse)
+            .or_else(|| self.text(DATE_TIME).and_then(CaptureDate::parse))
+    }
+
+    /// The camera model, e.g. `Canon EOS R5`.
+    pub fn camera_model(&self) -> Option<&str> {
+        self.text(MODEL).map(str::trim).filter(|m| !m.is_empty())
+    }
+
+    /// Sets an IFD0 text field, replacing any existing value.
+    pub fn set_text(&mut self, tag: u16, value: &str) {
+        self.ifd0.remove_tags(&[tag]);
+        let mut value = value.replace('\0', "").into_bytes();
+        value.push(0);
+        self.ifd0.entries.push(Entry {
+            tag,
+            kind: ASCII,
+            count: value.len() as u32,
+            value,
+        });
+    }
+
+    /// Whether the block has a field (or sub-IFD) with this tag.
+    pub fn has_tag(&self, tag: u16) -> bool {
+        self.ifd0.has_tag(tag)
+    }
+
+    pub fn has_thumbnail(&self) -> bool {
+        self.thumbnail.is_some()
+    }
+
+    /// Removes what the policy asks to strip.
+    pub fn filter(&mut self, policy: &MetadataPolicy) {
+        if policy.strip_gps {
+            self.ifd0.remove_tags(&[GPS_IFD]);
+        }
+        if policy.strip_serial_numbers {
+            self.ifd0.remove_tags(&SERIAL_NUMBER_TAGS);
+        }
+        if policy.strip_thumbnail {
+            self.thumbnail = None;
+        }
+    }
+
+    /// Serializes the block, in its original byte order.
+    pub fn to_tiff(&self) -> Vec<u8> {
+        let order = self.order;
+        let mut out = match order {
+            ByteOrder::Little => b"II".to_vec(),
+            ByteOrder::Big => b"MM".to_vec(),
+        };
+        order.put_u16(&mut out, 42);
+        order.put_u32(&mut out, 8);
+        let next = self.ifd0.write(&mut out, order);
+        if let Some(thumbnail) = &self.thumbnail {
+            if out.len() % 2 == 1 {
+                out.push(0);
+            }
+            let at = out.len() as u32;
+            order.set_u32(&mut out, next, at);
+            thumbnail.write(&mut out, order);
+        }
+        out
+    }
+}
+
+impl Default for Exif {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,130 @@
+// image-compressor-rust-service/src/ocr.rs
+
+//! Text extraction from document images.
+//!
+//! Presets with `ocr = true` extract the text of the input along with
+//! compressing it; watch mode writes the text next to the output.
+//! Recognition is delegated to an external command, `tesseract` by default,
+//! which reads a PNG on stdin and writes UTF-8 text to stdout. The service
+//! links no OCR library: the command must be installed separately. The PNG
+//! is re-encoded from the decoded pixels, so the recognizer never parses the
+//! untrusted input itself.
+
+use anyhow::{bail, Context, Result};
+use image::{DynamicImage, ImageOutputFormat};
+use serde::Deserialize;
+use std::io::{Cursor, Read, Write};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// How often a running recognizer is polled for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Settings for the OCR stage.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OcrConfig {
+    /// Allow presets to ask for OCR.
+    pub enabled: bool,
+    /// The recognizer and its arguments, run as a subprocess for every
+    /// recognition. The default needs `tesseract` on the `PATH`.
+    pub command: Vec<String>,
+    /// Time allowed for one recognition, in milliseconds.
+    pub timeout_ms: u64,
+    /// Longer text is truncated to this many bytes, at a character boundary.
+    pub max_text_bytes: usize,
+}
+
+impl Default for OcrConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            command: ["tesseract", "stdin", "stdout"].map(String::from).to_vec(),
+            timeout_ms: 30_000,
+            max_text_bytes: 16 * 1024,
+        }
+    }
+}
+
+impl OcrConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.enabled && self.command.is_empty() {
+            bail!("command must not be empty");
+        }
+        if self.timeout_ms == 0 {
+            bail!("timeout_ms must be positive");
+        }
+        Ok(())
+    }
+
+    /// Extracts the text of `image`, trimmed of surrounding whitespace.
+    pub fn recognize(&self, image: &DynamicImage) -> Result<String> {
+        let mut png = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+            .context("Failed to encode the image for OCR.")?;
+        let output = self.run(&png)?;
+        let mut text = String::from_utf8_lossy(&output).trim().to_string();
+        if text.len() > self.max_text_bytes {
+            let mut end = self.max_text_bytes;
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+        }
+        Ok(text)
+    }
+
+    fn run(&self, input: &[u8]) -> Result<Vec<u8>> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .context("No OCR command is configured.")?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to spawn the OCR command '{}'.", program))?;
+
+        // Feed and drain the pipes on their own threads so that a large
+        // image or a chatty recognizer cannot block the timeout below.
+        let mut stdin = child.stdin.take().context("OCR stdin is not piped.")?;
+        let input = input.to_vec();
+        let writer = std::thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+        let mut stdout = child.stdout.take().context("OCR stdout is not piped.")?;
+        let reader = std::thread::spawn(move || {
+            let mut output = Vec::new();
+            stdout.read_to_end(&mut output).map(|_| output)
+        });
+
+        let deadline = Instant::now() + Duration::from_millis(self.timeout_ms);
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!("OCR timed out after {} ms.", self.timeout_ms);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        };
+        let _ = writer.join();
+        let output = reader
+            .join()
+            .map_err(|_| anyhow::anyhow!("OCR output reader panicked."))?
+            .context("Failed to read the OCR output.")?;
+        if !status.success() {
+            let mut stderr = String::new();
+            if let Some(mut pipe) = child.stderr.take() {
+                let _ = pipe.read_to_string(&mut stderr);
+            }
+            bail!("OCR command failed ({}). {}", status, stderr.trim());
+        }
+        Ok(output)
+    }
+}
@@ -0,0 +1,1142 @@
+// image-compressor-rust-service/src/options.rs
+
+use crate::animation::FrameSelection;
+use crate::jpeg::CustomTables;
+use crate::metadata::{CustomMetadata, MetadataPolicy, COPYRIGHT};
+use crate::placeholder;
+use crate::transform::MaxDimensions;
+use axum::http::HeaderMap;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// Quality used when the caller does not send a valid `X-Compression-Quality`.
+pub const DEFAULT_QUALITY: u8 = 80;
+
+/// What to do with animated inputs (currently APNG).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnimationMode {
+    /// Keep only the first frame and encode it as a still image.
+    #[default]
+    FirstFrame,
+    /// Keep every frame and encode them as an animated WebP.
+    Animate,
+}
+
+impl AnimationMode {
+    /// Parses the value of the `X-Animation` header.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "first-frame" | "first" | "still" => Some(Self::FirstFrame),
+            "animate" | "animated" | "webp" => Some(Self::Animate),
+            _ => None,
+        }
+    }
+}
+
+/// How the image is fitted into the requested width and height when both are given.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Scale to fit inside the box, preserving the aspect ratio.
+    #[default]
+    Contain,
+    /// Scale to cover the box, preserving the aspect ratio, and crop the overflow.
+    Cover,
+    /// Stretch to exactly the requested box.
+    Fill,
+    /// Scale to fit inside the box like `contain`, then pad to exactly the
+    /// box with the [`background`](CompressionOptions::background).
+    Pad,
+}
+
+impl Fit {
+    /// Parses the value of the `X-Fit` header.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "contain" | "inside" => Some(Self::Contain),
+            "cover" | "crop" => Some(Self::Cover),
+            "fill" | "stretch" => Some(Self::Fill),
+            "pad" | "letterbox" => Some(Self::Pad),
+            _ => None,
+        }
+    }
+}
+
+/// How much CPU the encoders spend on making the output smaller.
+///
+/// It sets the libwebp method of animated WebP output and the zlib level of
+/// palette PNGs. JPEG output is the same at every effort: its encoder has
+/// no speed settings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Effort {
+    /// For interactive requests: the quickest settings, larger files.
+    Fast,
+    #[default]
+    Balanced,
+    /// For batch jobs: the densest settings, several times slower.
+    Max,
+}
+
+impl Effort {
+    /// Parses the value of the `X-Effort` header.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "fast" | "speed" => Some(Self::Fast),
+            "balanced" | "default" => Some(Self::Balanced),
+            "max" | "best" | "density" => Some(Self::Max),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Fast => "fast",
+            Self::Balanced => "balanced",
+            Self::Max => "max",
+        }
+    }
+}
+
+/// The colour space of the output (see [`crate::color`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorSpace {
+    Srgb,
+    /// The wide gamut of Apple displays, with the sRGB transfer curve.
+    DisplayP3,
+    /// sRGB primaries with a linear transfer curve. Dark tones band at 8
+    /// bits; meant for compositing pipelines, not for display.
+    LinearSrgb,
+}
+
+impl ColorSpace {
+    /// Parses the value of the `X-Color-Space` header.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "srgb" => Some(Self::Srgb),
+            "display-p3" | "p3" => Some(Self::DisplayP3),
+            "linear" | "linear-srgb" => Some(Self::LinearSrgb),
+            _ => None,
+        }
+    }
+}
+
+/// How colours missing from a palette are approximated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dither {
+    /// The nearest palette colour; flat, with visible banding on gradients.
+    None,
+    /// Error diffusion; the smoothest gradients.
+    #[default]
+    FloydSteinberg,
+    /// An 8x8 Bayer pattern; a regular texture that compresses better than
+    /// error diffusion.
+    Ordered,
+}
+
+impl Dither {
+    /// Parses the value of the `X-Dither` header.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "none" | "off" => Some(Self::None),
+            "floyd-steinberg" | "fs" | "diffusion" => Some(Self::FloydSteinberg),
+            "ordered" | "bayer" => Some(Self::Ordered),
+            _ => None,
+        }
+    }
+}
+
+/// Palette output: an indexed PNG of at most `colors` colours (see
+/// [`crate::palette`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Palette {
+    /// From 2 to 256.
+    pub colors: u16,
+    pub dither: Dither,
+}
+
+impl Palette {
+    pub const MAX_COLORS: u16 = 256;
+
+    /// A palette of `colors` colours, clamped to 2..=256.
+    pub fn new(colors: u16, dither: Dither) -> Self {
+        Self {
+            colors: colors.clamp(2, Self::MAX_COLORS),
+            dither,
+        }
+    }
+}
+
+/// A ceiling on the output size, met by lowering the quality and, if that
+/// is not enough, the dimensions (see [`crate::budget`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ByteBudget {
+    pub max_bytes: u64,
+    /// The lowest quality the output may be encoded at.
+    pub min_quality: u8,
+    /// The output is not scaled down below this width.
+    pub min_width: Option<u32>,
+    /// The output is not scaled down below this height.
+    pub min_height: Option<u32>,
+}
+
+/// A padding or border colour, as straight RGBA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String")]
+pub struct Color(pub [u8; 4]);
+
+impl Color {
+    pub const TRANSPARENT: Self = Self([0, 0, 0, 0]);
+    pub const WHITE: Self = Self([255, 255, 255, 255]);
+    pub const BLACK: Self = Self([0, 0, 0, 255]);
+
+    /// Parses `transparent`, `white`, `black` or a hex colour as `rrggbb`
+    /// or `rrggbbaa`, with or without a leading `#`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().to_ascii_lowercase();
+        match value.as_str() {
+            "transparent" | "none" => return Some(Self::TRANSPARENT),
+            "white" => return Some(Self::WHITE),
+            "black" => return Some(Self::BLACK),
+            _ => {}
+        }
+        let hex = value.strip_prefix('#').unwrap_or(&value);
+        if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+            return None;
+        }
+        let mut rgba = [255; 4];
+        for (channel, i) in rgba.iter_mut().zip((0..hex.len()).step_by(2)) {
+            *channel = u8::from_str_radix(&hex[i..i + 2], 16).ok()?;
+        }
+        Some(Self(rgba))
+    }
+
+    pub fn is_opaque(self) -> bool {
+        self.0[3] == 255
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value).ok_or_else(|| {
+            format!(
+                "invalid colour '{}', expected transparent or a hex colour such as #ffffff",
+                value
+            )
+        })
+    }
+}
+
+/// A border drawn along the inside edge of the output, following rounded
+/// corners.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Border {
+    /// Width in pixels.
+    pub width: u32,
+    pub color: Color,
+}
+
+/// How round the output's corners are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String")]
+pub enum CornerRadius {
+    Pixels(u32),
+    /// A percentage of the shorter side; `50%` turns a square into a circle.
+    Percent(u8),
+}
+
+impl CornerRadius {
+    /// Parses a radius in pixels, e.g. `12`, or a percentage up to 50, e.g.
+    /// `50%`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        match value.strip_suffix('%') {
+            Some(percent) => percent
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|p| (1..=50).contains(p))
+                .map(Self::Percent),
+            None => value
+                .parse::<u32>()
+                .ok()
+                .filter(|&px| px > 0)
+                .map(Self::Pixels),
+        }
+    }
+
+    /// The radius in pixels for an image of `width` by `height`, at most
+    /// half the shorter side.
+    pub fn pixels(self, width: u32, height: u32) -> f64 {
+        let short = f64::from(width.min(height));
+        let radius = match self {
+            Self::Pixels(px) => f64::from(px),
+            Self::Percent(percent) => short * f64::from(percent) / 100.0,
+        };
+        radius.min(short / 2.0)
+    }
+}
+
+impl TryFrom<String> for CornerRadius {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value).ok_or_else(|| {
+            format!(
+                "invalid corner radius '{}', expected pixels such as 12 or a percentage up to 50%",
+                value
+            )
+        })
+    }
+}
+
+/// A colour filter (see [`crate::filter`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String")]
+pub enum Filter {
+    /// Maps luma onto a gradient from `shadow` to `highlight`.
+    Duotone { shadow: Color, highlight: Color },
+    /// Multiplies by a colour; its alpha is the strength.
+    Tint(Color),
+    /// Sepia toning, `amount` percent of the way.
+    Sepia { amount: u8 },
+}
+
+impl Filter {
+    /// Parses `sepia`, `sepia:<amount>` with the amount between 0 and 1,
+    /// `tint:<colour>` or `duotone:<shadow>,<highlight>`, with colours as
+    /// [`Color::parse`] takes them.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().to_ascii_lowercase();
+        let (name, args) = value.split_once(':').unwrap_or((value.as_str(), ""));
+        match (name.trim(), args.trim()) {
+            ("sepia", "") => Some(Self::Sepia { amount: 100 }),
+            ("sepia", amount) => amount
+                .parse::<f64>()
+                .ok()
+                .filter(|a| (0.0..=1.0).contains(a))
+                .map(|a| Self::Sepia {
+                    amount: (a * 100.0).round() as u8,
+                }),
+            ("tint", color) => Color::parse(color).map(Self::Tint),
+            ("duotone", colors) => {
+                let (shadow, highlight) = colors.split_once(',')?;
+                Some(Self::Duotone {
+                    shadow: Color::parse(shadow)?,
+                    highlight: Color::parse(highlight)?,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<String> for Filter {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value).ok_or_else(|| {
+            format!(
+                "invalid filter '{}', expected sepia, tint:#rrggbb or duotone:#rrggbb,#rrggbb",
+                value
+            )
+        })
+    }
+}
+
+/// Which part of the image survives a `cover` or aspect-ratio crop.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String")]
+pub enum Gravity {
+    #[default]
+    Center,
+    North,
+    South,
+    East,
+    West,
+    NorthEast,
+    NorthWest,
+    SouthEast,
+    SouthWest,
+    /// Keep this point, in ten-thousandths of the width and height from
+    /// the top left corner, as close to the centre as the crop allows.
+    Focal {
+        x: u16,
+        y: u16,
+    },
+}
+
+impl Gravity {
+    /// Parses a compass direction (`north`, `south-east`, `center`, ...) or
+    /// a focal point as `x,y` with both between 0 and 1, e.g. `0.3,0.25`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().to_ascii_lowercase();
+        if let Some((x, y)) = value.split_once(',') {
+            let coordinate = |v: &str| {
+                v.trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|v| (0.0..=1.0).contains(v))
+                    .map(|v| (v * 10_000.0).round() as u16)
+            };
+            return Some(Self::Focal {
+                x: coordinate(x)?,
+                y: coordinate(y)?,
+            });
+        }
+        match value.replace(['-', '_', ' '], "").as_str() {
+            "center" | "centre" => Some(Self::Center),
+            "north" | "n" | "top" => Some(Self::North),
+            "south" | "s" | "bottom" => Some(Self::South),
+            "east" | "e" | "right" => Some(Self::East),
+            "west" | "w" | "left" => Some(Self::West),
+            "northeast" | "ne" | "topright" => Some(Self::NorthEast),
+            "northwest" | "nw" | "topleft" => Some(Self::NorthWest),
+            "southeast" | "se" | "bottomright" => Some(Self::SouthEast),
+            "southwest" | "sw" | "bottomleft" => Some(Self::SouthWest),
+            _ => None,
+        }
+    }
+
+    /// The point kept, as fractions of the width and height.
+    pub fn anchor(self) -> (f64, f64) {
+        match self {
+            Self::Center => (0.5, 0.5),
+            Self::North => (0.5, 0.0),
+            Self::South => (0.5, 1.0),
+            Self::East => (1.0, 0.5),
+            Self::West => (0.0, 0.5),
+            Self::NorthEast => (1.0, 0.0),
+            Self::NorthWest => (0.0, 0.0),
+            Self::SouthEast => (1.0, 1.0),
+            Self::SouthWest => (0.0, 1.0),
+            Self::Focal { x, y } => (f64::from(x) / 10_000.0, f64::from(y) / 10_000.0),
+        }
+    }
+}
+
+impl TryFrom<String> for Gravity {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value).ok_or_else(|| {
+            format!(
+                "invalid gravity '{}', expected a direction such as north-east or a focal point such as 0.3,0.25",
+                value
+            )
+        })
+    }
+}
+
+/// An exact output aspect ratio, e.g. `16:9`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String")]
+pub struct AspectRatio {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AspectRatio {
+    /// Parses `W:H` (or `W/H`) with positive whole numbers.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (width, height) = value.trim().split_once([':', '/'])?;
+        let ratio = Self {
+            width: width.trim().parse().ok()?,
+            height: height.trim().parse().ok()?,
+        };
+        (ratio.width > 0 && ratio.height > 0).then_some(ratio)
+    }
+}
+
+impl TryFrom<String> for AspectRatio {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+            .ok_or_else(|| format!("invalid aspect ratio '{}', expected e.g. 16:9", value))
+    }
+}
+
+/// A clockwise rotation by a multiple of 90°.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "i32")]
+pub enum Rotation {
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+impl Rotation {
+    /// The rotation by `degrees` clockwise, `90`, `180` or `270`, or
+    /// counterclockwise from `-90` to `-270`.
+    pub fn from_degrees(degrees: i32) -> Option<Self> {
+        match degrees {
+            90 | -270 => Some(Self::Quarter),
+            180 | -180 => Some(Self::Half),
+            270 | -90 => Some(Self::ThreeQuarters),
+            _ => None,
+        }
+    }
+
+    /// Parses the value of the `X-Rotate` header.
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::from_degrees(value.trim().parse().ok()?)
+    }
+
+    /// Degrees clockwise.
+    pub fn degrees(self) -> u16 {
+        match self {
+            Self::Quarter => 90,
+            Self::Half => 180,
+            Self::ThreeQuarters => 270,
+        }
+    }
+
+    /// The dimensions of a `(width, height)` image once rotated.
+    pub fn dimensions(self, (width, height): (u32, u32)) -> (u32, u32) {
+        match self {
+            Self::Half => (width, height),
+            Self::Quarter | Self::ThreeQuarters => (height, width),
+        }
+    }
+}
+
+impl TryFrom<i32> for Rotation {
+    type Error = String;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        Self::from_degrees(value)
+            .ok_or_else(|| format!("invalid rotation {}, expected 90, 180 or 270", value))
+    }
+}
+
+/// How the requested quality is interpreted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityScale {
+    /// A format-independent scale: the same value gives visually comparable
+    /// results whichever encoder runs (see [`crate::quality`]).
+    #[default]
+    Perceptual,
+    /// Passed to the encoder unchanged.
+    Native,
+}
+
+impl QualityScale {
+    /// Parses the value of the `X-Quality-Scale` header.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "perceptual" => Some(Self::Perceptual),
+            "native" | "raw" => Some(Self::Native),
+            _ => None,
+        }
+    }
+}
+
+/// Options controlling a single compression request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CompressionOptions {
+    /// Encoder quality, from 1 to 100.
+    pub quality: u8,
+    /// Whether `quality` was chosen by the caller. When it was not, the
+    /// configured per-format default applies instead.
+    pub quality_explicit: bool,
+    /// Whether `quality` is perceptual or encoder-native.
+    pub quality_scale: QualityScale,
+    /// How animated inputs are handled.
+    pub animation: AnimationMode,
+    /// Which frames of an animated input are kept, or the one encoded as a
+    /// still poster.
+    pub frames: FrameSelection,
+    /// Rotate the image clockwise before resizing it, so `width` and
+    /// `height` apply to the rotated image. JPEG inputs that are only
+    /// rotated or cropped are transformed without loss where they can be
+    /// (see [`crate::lossless`]).
+    pub rotate: Option<Rotation>,
+    /// Requested output width in pixels. With only one of `width` and
+    /// `height` set, the other follows the source aspect ratio.
+    pub width: Option<u32>,
+    /// Requested output height in pixels.
+    pub height: Option<u32>,
+    /// How to fit the image when both `width` and `height` are set.
+    pub fit: Fit,
+    /// Which part survives crops, and where `pad` places the image.
+    pub gravity: Gravity,
+    /// What `pad` fills the box with. Without it, formats with alpha are
+    /// padded with transparency and JPEG with white.
+    pub background: Option<Color>,
+    /// A colour filter, applied after resizing and before the border.
+    pub filter: Option<Filter>,
+    /// Encode stills as a palette PNG instead of JPEG.
+    pub palette: Option<Palette>,
+    /// Convert the output to this colour space and tag it with its ICC
+    /// profile. Without it, pixels are passed through untagged.
+    pub color_space: Option<ColorSpace>,
+    /// Encode stills with a single grey channel (see
+    /// [`crate::color::to_grayscale`]). Without it, stills whose pixels
+    /// are all grey are, unless they are converted to a colour space.
+    pub grayscale: Option<bool>,
+    /// Also return a blurred preview this many pixels wide (see
+    /// [`crate::placeholder`]).
+    pub placeholder: Option<u32>,
+    /// Also return the text of the input (see [`crate::ocr`]). Set by
+    /// presets only.
+    pub ocr: bool,
+    /// A border drawn inside the output's edges.
+    pub border: Option<Border>,
+    /// Rounds the output's corners. Outside them, formats with alpha are
+    /// transparent and JPEG shows the background (white by default).
+    pub corner_radius: Option<CornerRadius>,
+    /// Crop to this aspect ratio when at most one of `width` and `height`
+    /// is set; the missing side follows the ratio instead of the source.
+    pub aspect: Option<AspectRatio>,
+    /// Whether the image may come out larger than the source. Without it,
+    /// requested dimensions beyond the source are not reached (see
+    /// [`crate::transform::output_dimensions`]).
+    pub allow_upscale: bool,
+    /// Compress what can be recovered of a truncated or corrupt input
+    /// instead of failing (see [`crate::decode::salvage_image`]).
+    pub salvage: bool,
+    /// Overrides the configured metadata policy. Not read from the request;
+    /// the server sets it from the caller's API key.
+    pub metadata: Option<MetadataPolicy>,
+    /// Metadata to write into the output.
+    pub custom_metadata: CustomMetadata,
+    /// Guarantee byte-identical output for identical input and options
+    /// (see [`crate::DETERMINISTIC_OUTPUT_VERSION`]).
+    pub deterministic: bool,
+    /// Hide this payload in the output as an invisible watermark (see
+    /// [`crate::watermark`]). Stills encoded as JPEG only.
+    pub watermark: Option<u32>,
+    /// Keep the output within this many bytes. Stills encoded as JPEG only.
+    pub budget: Option<ByteBudget>,
+    /// Overrides the configured encoder effort.
+    pub effort: Option<Effort>,
+    /// Quantization tables of JPEG outputs, in place of the configured
+    /// ones. Set by presets only.
+    pub quant_tables: Option<CustomTables>,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        Self {
+            quality: DEFAULT_QUALITY,
+            quality_explicit: false,
+            quality_scale: QualityScale::default(),
+            animation: AnimationMode::default(),
+            frames: FrameSelection::default(),
+            rotate: None,
+            width: None,
+            height: None,
+            fit: Fit::default(),
+            gravity: Gravity::default(),
+            background: None,
+            filter: None,
+            palette: None,
+            color_space: None,
+            grayscale: None,
+            placeholder: None,
+            ocr: false,
+            border: None,
+            corner_radius: None,
+            aspect: None,
+            allow_upscale: false,
+            salvage: false,
+            metadata: None,
+            custom_metadata: CustomMetadata::default(),
+            deterministic: false,
+            watermark: None,
+            budget: None,
+            effort: None,
+            quant_tables: None,
+        }
+    }
+}
+
+impl CompressionOptions {
+    /// Sets an explicit quality, overriding the configured per-format default.
+    pub fn with_quality(mut self, quality: u8) -> Self {
+        self.quality = quality;
+        self.quality_explicit = true;
+        self
+    }
+
+    /// Builds the options from request headers, falling back to defaults
+    /// for any header that is missing or invalid (see
+    /// [`read_headers`](Self::read_headers)).
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut options = Self::default();
+        options.read_headers(headers);
+        options
+    }
+
+    /// Sets the options given by request headers, leaving the others as
+    /// they are, and returns an error for every header whose value cannot
+    /// be used, which is left as it is too, whose number is out of range,
+    /// which is clamped, or which modifies an option that is not set.
+    ///
+    /// * `X-Compression-Quality` - quality from 1 to 100; numeric values
+    ///   outside that range are clamped to it. Without it, the configured
+    ///   per-format default applies.
+    /// * `X-Quality-Scale` - `perceptual` (default) or `native`.
+    /// * `X-Animation` - `first-frame` (default) or `animate`.
+    /// * `X-Max-Frames` / `X-Frame-Step` / `X-Max-Duration` - animate at
+    ///   most this many frames, every k-th frame, and for at most this many
+    ///   milliseconds, within the server's own caps.
+    /// * `X-Poster-Frame` - the frame of an animated input, from 0, to
+    ///   encode as a still instead.
+    /// * `X-Rotate` - `90`, `180` or `270` degrees clockwise (`-90` for
+    ///   counterclockwise), applied before resizing.
+    /// * `X-Width` / `X-Height` - target dimensions in pixels (positive integers).
+    /// * `X-Fit` - `contain` (default), `cover`, `fill` or `pad`.
+    /// * `X-Aspect` - an exact aspect ratio such as `16:9`, cropped to.
+    /// * `X-Gravity` - which part survives crops: `center` (default), a
+    ///   direction such as `north` or `south-east`, or a focal point `x,y`
+    ///   normalized from 0 to 1.
+    /// * `X-Background` - the `pad` colour: `transparent` or a hex colour
+    ///   such as `#ffffff` or `#00000080`. For JPEG, an opaque colour also
+    ///   fills the area outside rounded corners.
+    /// * `X-Filter` - `sepia` or `sepia:<0-1>`, `tint:<colour>` (the
+    ///   colour's alpha is the strength) or `duotone:<shadow>,<highlight>`.
+    /// * `X-Palette-Colors` / `X-Dither` - palette output of up to this many
+    ///   colours, and how it is dithered.
+    /// * `X-Color-Space` - `srgb`, `display-p3` or `linear-srgb`.
+    /// * `X-Grayscale` - `true` to encode stills with a single grey
+    ///   channel, `false` to keep the colour channels of grey ones.
+    /// * `X-Placeholder` - `true` or a width up to 64 pixels, to also
+    ///   return a tiny blurred preview.
+    /// * `X-Border-Width` / `X-Border-Color` - a border in pixels, drawn
+    ///   inside the edges, and its colour (black by default).
+    /// * `X-Corner-Radius` - rounded corners, in pixels or as a percentage
+    ///   of the shorter side up to `50%`.
+    /// * `X-Allow-Upscale` - `true` to let the output grow past the source.
+    /// * `X-Salvage` - `true` to compress what can be recovered of a
+    ///   truncated or corrupt JPEG or PNG instead of failing.
+    /// * `X-Metadata-Copyright` - EXIF copyright notice to write.
+    /// * `X-Metadata-Exif` - EXIF text fields as `Name=value` pairs separated
+    ///   by `;`, e.g. `Artist=Jane Doe;ImageDescription=Harbour at dusk`.
+    /// * `X-Metadata-Xmp` - base64-encoded XMP packet to embed.
+    /// * `X-Deterministic` - `true` for reproducible, byte-identical output.
+    /// * `X-Watermark` - a number from 0 to 4294967295 to hide in the output
+    ///   as an invisible watermark.
+    /// * `X-Max-Bytes` - the largest output, in bytes, reached by lowering
+    ///   the quality down to `X-Min-Quality` (1 by default) and scaling the
+    ///   image down to `X-Min-Width` and `X-Min-Height`.
+    /// * `X-Effort` - `fast`, `balanced` or `max`, trading encoding time for
+    ///   density. Without it, the configured effort applies.
+    ///
+    /// Errors name the options as query parameters and presets do, e.g.
+    /// `quality` for `X-Compression-Quality`.
+    pub fn read_headers(&mut self, headers: &HeaderMap) -> Vec<FieldError> {
+        let mut reader = HeaderReader {
+            headers,
+            errors: Vec::new(),
+            names: Vec::new(),
+        };
+        self.read_from(&mut reader);
+        reader.errors
+    }
+
+    /// The headers [`read_headers`](Self::read_headers) reads.
+    pub fn header_names() -> Vec<&'static str> {
+        let mut reader = HeaderReader {
+            headers: &HeaderMap::new(),
+            errors: Vec::new(),
+            names: Vec::new(),
+        };
+        Self::default().read_from(&mut reader);
+        reader.names
+    }
+
+    fn read_from(&mut self, reader: &mut HeaderReader) {
+        let number = |s: &str| s.parse::<i64>().ok();
+        let dimension = |s: &str| s.parse::<u32>().ok().filter(|&d| d > 0);
+        let flag = |s: &str| match s.to_ascii_lowercase().as_str() {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        };
+        const DIMENSION: &str = "a positive whole number of pixels";
+        const FLAG: &str = "true or false";
+        const COLOR: &str = "transparent or a hex colour such as #ffffff";
+
+        if let Some(quality) = reader.read(
+            "X-Compression-Quality",
+            "quality",
+            "a whole number from 1 to 100",
+            number,
+        ) {
+            self.quality = reader.clamp("quality", quality, 1, 100) as u8;
+            self.quality_explicit = true;
+        }
+        if let Some(scale) = reader.read(
+            "X-Quality-Scale",
+            "quality_scale",
+            "perceptual or native",
+            QualityScale::parse,
+        ) {
+            self.quality_scale = scale;
+        }
+        if let Some(animation) = reader.read(
+            "X-Animation",
+            "animation",
+            "first-frame or animate",
+            AnimationMode::parse,
+        ) {
+            self.animation = animation;
+        }
+        const COUNT: &str = "a positive whole number";
+        let positive = |s: &str| s.parse::<u32>().ok().filter(|&n| n > 0);
+        if let Some(max) = reader.read("X-Max-Frames", "max_frames", COUNT, positive) {
+            self.frames.max_frames = Some(max);
+        }
+        if let Some(step) = reader.read("X-Frame-Step", "frame_step", COUNT, positive) {
+            self.frames.step = Some(step);
+        }
+        if let Some(max) = reader.read(
+            "X-Max-Duration",
+            "max_duration",
+            "a positive number of milliseconds",
+            |s| s.parse::<u64>().ok().filter(|&ms| ms > 0),
+        ) {
+            self.frames.max_duration_ms = Some(max);
+        }
+        if let Some(index) = reader.read(
+            "X-Poster-Frame",
+            "poster_frame",
+            "a frame number from 0",
+            |s| s.parse().ok(),
+        ) {
+            self.frames.poster = Some(index);
+        }
+        if let Some(rotation) = reader.read(
+            "X-Rotate",
+            "rotate",
+            "90, 180 or 270 degrees",
+            Rotation::parse,
+        ) {
+            self.rotate = Some(rotation);
+        }
+        if let Some(width) = reader.read("X-Width", "width", DIMENSION, dimension) {
+            self.width = Some(width);
+        }
+        if let Some(height) = reader.read("X-Height", "height", DIMENSION, dimension) {
+            self.height = Some(height);
+        }
+        if let Some(fit) = reader.read("X-Fit", "fit", "contain, cover, fill or pad", Fit::parse) {
+            self.fit = fit;
+        }
+        if let Some(aspect) = reader.read(
+            "X-Aspect",
+            "aspect",
+            "an aspect ratio such as 16:9",
+            AspectRatio::parse,
+        ) {
+            self.aspect = Some(aspect);
+        }
+        if let Some(gravity) = reader.read(
+            "X-Gravity",
+            "gravity",
+            "a direction such as north-east or a focal point such as 0.3,0.25",
+            Gravity::parse,
+        ) {
+            self.gravity = gravity;
+        }
+        if let Some(background) = reader.read("X-Background", "background", COLOR, Color::parse) {
+            self.background = Some(background);
+        }
+        if let Some(filter) = reader.read(
+            "X-Filter",
+            "filter",
+            "sepia, tint:#rrggbb or duotone:#rrggbb,#rrggbb",
+            Filter::parse,
+        ) {
+            self.filter = Some(filter);
+        }
+        let colors = reader
+            .read(
+                "X-Palette-Colors",
+                "palette_colors",
+                "a number of colours from 2 to 256",
+                |s| s.parse::<u16>().ok(),
+            )
+            .map(|colors| {
+                let max = Palette::MAX_COLORS.into();
+                reader.clamp("palette_colors", colors.into(), 2, max) as u16
+            });
+        let dither = reader.read(
+            "X-Dither",
+            "dither",
+            "floyd-steinberg, ordered or none",
+            Dither::parse,
+        );
+        if let Some(colors) = colors {
+            let dither = dither
+                .or(self.palette.map(|palette| palette.dither))
+                .unwrap_or_default();
+            self.palette = Some(Palette::new(colors, dither));
+        } else if let Some(dither) = dither {
+            match &mut self.palette {
+                Some(palette) => palette.dither = dither,
+                None => reader.needs("dither", "palette_colors"),
+            }
+        }
+        if let Some(color_space) = reader.read(
+            "X-Color-Space",
+            "color_space",
+            "srgb, display-p3 or linear-srgb",
+            ColorSpace::parse,
+        ) {
+            self.color_space = Some(color_space);
+        }
+        if let Some(grayscale) = reader.read("X-Grayscale", "grayscale", FLAG, flag) {
+            self.grayscale = Some(grayscale);
+        }
+        if let Some(width) = reader.read(
+            "X-Placeholder",
+            "placeholder",
+            "true or a width in pixels",
+            |s| {
+                if s.eq_ignore_ascii_case("true") {
+                    Some(placeholder::DEFAULT_WIDTH)
+                } else {
+                    dimension(s)
+                }
+            },
+        ) {
+            let max = placeholder::MAX_WIDTH.into();
+            self.placeholder = Some(reader.clamp("placeholder", width.into(), 1, max) as u32);
+        }
+        let border_width = reader.read("X-Border-Width", "border_width", DIMENSION, dimension);
+        let border_color = reader.read("X-Border-Color", "border_color", COLOR, Color::parse);
+        if let Some(width) = border_width {
+            let color = border_color
+                .or(self.border.map(|border| border.color))
+                .unwrap_or(Color::BLACK);
+            self.border = Some(Border { width, color });
+        } else if let Some(color) = border_color {
+            match &mut self.border {
+                Some(border) => border.color = color,
+                None => reader.needs("border_color", "border_width"),
+            }
+        }
+        if let Some(radius) = reader.read(
+            "X-Corner-Radius",
+            "corner_radius",
+            "pixels such as 12 or a percentage up to 50%",
+            CornerRadius::parse,
+        ) {
+            self.corner_radius = Some(radius);
+        }
+        if let Some(allow_upscale) = reader.read("X-Allow-Upscale", "allow_upscale", FLAG, flag) {
+            self.allow_upscale = allow_upscale;
+        }
+        if let Some(salvage) = reader.read("X-Salvage", "salvage", FLAG, flag) {
+            self.salvage = salvage;
+        }
+        if let Some(exif) = reader.read("X-Metadata-Exif", "metadata_exif", "", |s| {
+            Some(CustomMetadata::parse_exif(s))
+        }) {
+            self.custom_metadata.exif = exif;
+        }
+        if let Some(xmp) = reader.read("X-Metadata-Xmp", "metadata_xmp", "base64", |s| {
+            base64::engine::general_purpose::STANDARD.decode(s).ok()
+        }) {
+            self.custom_metadata.xmp = Some(xmp);
+        }
+        if let Some(copyright) = reader.read(
+            "X-Metadata-Copyright",
+            "metadata_copyright",
+            "non-empty ASCII text",
+            |s| (!s.is_empty() && s.is_ascii()).then(|| s.to_string()),
+        ) {
+            self.custom_metadata.exif.push((COPYRIGHT, copyright));
+        }
+        if let Some(deterministic) = reader.read("X-Deterministic", "deterministic", FLAG, flag) {
+            self.deterministic = deterministic;
+        }
+        if let Some(watermark) = reader.read(
+            "X-Watermark",
+            "watermark",
+            "a number from 0 to 4294967295",
+            |s| s.parse().ok(),
+        ) {
+            self.watermark = Some(watermark);
+        }
+        let max_bytes = reader.read(
+            "X-Max-Bytes",
+            "max_bytes",
+            "a positive number of bytes",
+            |s| s.parse::<u64>().ok().filter(|&bytes| bytes > 0),
+        );
+        let min_quality = reader
+            .read(
+                "X-Min-Quality",
+                "min_quality",
+                "a whole number from 1 to 100",
+                number,
+            )
+            .map(|q| reader.clamp("min_quality", q, 1, 100) as u8);
+        let min_width = reader.read("X-Min-Width", "min_width", DIMENSION, dimension);
+        let min_height = reader.read("X-Min-Height", "min_height", DIMENSION, dimension);
+        if let Some(max_bytes) = max_bytes {
+            self.budget = Some(ByteBudget {
+                max_bytes,
+                min_quality: min_quality.unwrap_or(1),
+                min_width,
+                min_height,
+            });
+        } else {
+            for (field, given) in [
+                ("min_quality", min_quality.is_some()),
+                ("min_width", min_width.is_some()),
+                ("min_height", min_height.is_some()),
+            ] {
+                if given {
+                    reader.needs(field, "max_bytes");
+                }
+            }
+        }
+        if let Some(effort) =
+            reader.read("X-Effort", "effort", "fast, balanced or max", Effort::parse)
+        {
+            self.effort = Some(effort);
+        }
+    }
+
+    /// The options that contradict each other or go past `max`, the
+    /// largest output allowed.
+    pub fn conflicts(&self, max: MaxDimensions) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        for (field, requested, max) in [
+            ("width", self.width, max.width),
+            ("height", self.height, max.height),
+        ] {
+            if requested.is_some_and(|requested| requested > max) {
+                errors.push(FieldError::new(
+                    field,
+                    format!("exceeds the maximum of {} pixels", max),
+                ));
+            }
+        }
+        if self.frames.poster.is_some() && self.animation == AnimationMode::Animate {
+            errors.push(FieldError::new(
+                "poster_frame",
+                "cannot be combined with animation=animate",
+            ));
+        }
+        if self.grayscale == Some(true) && self.color_space.is_some() {
+            errors.push(FieldError::new(
+                "grayscale",
+                "cannot be combined with color_space",
+            ));
+        }
+        if self.aspect.is_some() && self.width.is_some() && self.height.is_some() {
+            errors.push(FieldError::new(
+                "aspect",
+                "cannot be combined with both width and height",
+            ));
+        }
+        if let Some(border) = self.border {
+            let short = self.width.into_iter().chain(self.height).min();
+            if short.is_some_and(|short| border.width.saturating_mul(2) >= short) {
+                errors.push(FieldError::new(
+                    "border_width",
+                    "leaves nothing of the image at the requested size",
+                ));
+            }
+        }
+        if let Some(budget) = self.budget {
+            if self.quality_explicit && budget.min_quality > self.quality {
+                errors.push(FieldError::new("min_quality", "is above quality"));
+            }
+            for (field, min, side, requested) in [
+                ("min_width", budget.min_width, "width", self.width),
+                ("min_height", budget.min_height, "height", self.height),
+            ] {
+                if min
+                    .zip(requested)
+                    .is_some_and(|(min, requested)| min > requested)
+                {
+                    errors.push(FieldError::new(field, format!("is larger than {}", side)));
+                }
+            }
+        }
+        errors
+    }
+}
+
+/// An option that was not understood, named as in query parameters and
+/// presets.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Reads option headers, collecting the values that cannot be used.
+struct HeaderReader<'a> {
+    headers: &'a HeaderMap,
+    errors: Vec<FieldError>,
+    /// Every header asked for, present or not.
+    names: Vec<&'static str>,
+}
+
+impl HeaderReader<'_> {
+    /// The value of `header` parsed with `parse`, or `None` when it is
+    /// missing or, recording an error for `field`, not what was `expected`.
+    fn read<T>(
+        &mut self,
+        header: &'static str,
+        field: &str,
+        expected: &str,
+        parse: impl FnOnce(&str) -> Option<T>,
+    ) -> Option<T> {
+        self.names.push(header);
+        let value = self.headers.get(header)?;
+        let parsed = value.to_str().ok().and_then(|s| parse(s.trim()));
+        if parsed.is_none() {
+            self.errors.push(FieldError::new(
+                field,
+                format!(
+                    "expected {}, got '{}'",
+                    expected,
+                    String::from_utf8_lossy(value.as_bytes())
+                ),
+            ));
+        }
+        parsed
+    }
+
+    /// `value` clamped to `min..=max`, recording an error for `field` when
+    /// it is out of that range.
+    fn clamp(&mut self, field: &str, value: i64, min: i64, max: i64) -> i64 {
+        if !(min..=max).contains(&value) {
+            self.errors.push(FieldError::new(
+                field,
+                format!("must be between {} and {}, got {}", min, max, value),
+            ));
+        }
+        value.clamp(min, max)
+    }
+
+    /// Records that `field` was given without `other`, which it modifies.
+    fn needs(&mut self, field: &str, other: &str) {
+        self.errors
+            .push(FieldError::new(field, format!("needs {}", other)));
+    }
+}
@@ -0,0 +1,255 @@
+// image-compressor-rust-service/src/palette.rs
+
+//! Palette quantization for PNG8 output.
+//!
+//! Images with no more colours than the palette allows keep their exact
+//! colours. Others get a palette trained with NeuQuant, a neural-network
+//! quantizer that weighs colours by how often they occur, which is then
+//! refined with a few k-means passes, as libimagequant does. Each pixel is
+//! mapped to it with the requested [`Dither`]. Colours are compared
+//! premultiplied by their alpha, so faint anti-aliased edges do not spend
+//! entries on RGB values nobody can see. Error diffusion and the
+//! ordered pattern only touch the colour channels, so edges keep their
+//! alpha.
+
+use crate::options::{Dither, Palette};
+use color_quant::NeuQuant;
+use image::RgbaImage;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+/// NeuQuant trains on every n-th pixel. Small images need all of theirs to
+/// settle the network; large ones are sampled down to about this many.
+const TRAINING_PIXELS: usize = 1 << 18;
+
+/// NeuQuant's own limit on how sparsely it samples.
+const MAX_SAMPLE_FACTOR: usize = 30;
+
+/// k-means passes over the trained palette. Most of the improvement comes
+/// from the first; later ones mostly settle rarely used entries.
+const REFINE_PASSES: usize = 3;
+
+/// The 8x8 Bayer threshold matrix, with values 0..64.
+const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// An image as palette indices.
+#[derive(Debug, Clone)]
+pub struct Indexed {
+    pub width: u32,
+    pub height: u32,
+    /// Straight RGBA entries, at most [`Palette::MAX_COLORS`].
+    pub palette: Vec<[u8; 4]>,
+    /// One index into `palette` per pixel, row by row.
+    pub indices: Vec<u8>,
+}
+
+/// Reduces `image` to at most `palette.colors` colours.
+pub fn quantize(image: &RgbaImage, palette: Palette) -> Indexed {
+    let (width, height) = image.dimensions();
+    // Fully transparent pixels all look the same; give them one entry.
+    let pixels: Vec<[u8; 4]> = image
+        .pixels()
+        .map(|p| if p.0[3] == 0 { [0; 4] } else { p.0 })
+        .collect();
+    let max = usize::from(palette.colors);
+
+    if let Some(exact) = exact_palette(&pixels, max) {
+        let lookup: HashMap<[u8; 4], u8> = exact
+            .iter()
+            .enumerate()
+            .map(|(index, &color)| (color, index as u8))
+            .collect();
+        let indices = pixels.iter().map(|p| lookup[p]).collect();
+        return Indexed {
+            width,
+            height,
+            palette: exact,
+            indices,
+        };
+    }
+
+    let flat: Vec<u8> = pixels.iter().flatten().copied().collect();
+    let sample_factor = (pixels.len() / TRAINING_PIXELS).clamp(1, MAX_SAMPLE_FACTOR);
+    let quantizer = NeuQuant::new(sample_factor as i32, max, &flat);
+    let mut entries: Vec<[u8; 4]> = quantizer
+        .color_map_rgba()
+        .chunks_exact(4)
+        .map(|c| [c[0], c[1], c[2], c[3]])
+        .collect();
+    refine(&pixels, &mut entries, sample_factor);
+    // Entries no pixel picked may be off-grey; grey images keep a grey
+    // palette, which can be written as greyscale.
+    if pixels.iter().all(|&[r, g, b, _]| r == g && g == b) {
+        for entry in &mut entries {
+            let sum: u16 = entry[..3].iter().map(|&c| u16::from(c)).sum();
+            entry[..3].fill((sum / 3) as u8);
+        }
+    }
+    // NeuQuant's own lookup walks its network approximately and can land
+    // a whole step away, so search the finished palette instead.
+    let cache = RefCell::new(HashMap::new());
+    let nearest = |color: [u8; 4]| {
+        *cache
+            .borrow_mut()
+            .entry(color)
+            .or_insert_with(|| closest(&entries, color))
+    };
+
+    let indices = match palette.dither {
+        Dither::None => pixels.iter().map(|&p| nearest(p)).collect(),
+        Dither::Ordered => {
+            let spread = palette_step(&entries);
+            pixels
+                .iter()
+                .enumerate()
+                .map(|(i, &p)| {
+                    let (x, y) = (i % width as usize, i / width as usize);
+                    let threshold = (f32::from(BAYER[y % 8][x % 8]) + 0.5) / 64.0 - 0.5;
+                    let offset = threshold * spread;
+                    let shift = |c: u8| (f32::from(c) + offset).round().clamp(0.0, 255.0) as u8;
+                    nearest([shift(p[0]), shift(p[1]), shift(p[2]), p[3]])
+                })
+                .collect()
+        }
+        Dither::FloydSteinberg => floyd_steinberg(&pixels, width as usize, &entries, nearest),
+    };
+    Indexed {
+        width,
+        height,
+        palette: entries,
+        indices,
+    }
+}
+
+/// The distinct colours of `pixels`, if there are at most `max`.
+fn exact_palette(pixels: &[[u8; 4]], max: usize) -> Option<Vec<[u8; 4]>> {
+    let mut seen = HashSet::new();
+    let mut colors = Vec::new();
+    for &pixel in pixels {
+        if seen.insert(pixel) {
+            if colors.len() == max {
+                return None;
+            }
+            colors.push(pixel);
+        }
+    }
+    Some(colors)
+}
+
+/// Moves each entry to the mean of the pixels nearest to it, sampling every
+/// `step`-th pixel. Entries no pixel picks are left where they are.
+fn refine(pixels: &[[u8; 4]], entries: &mut [[u8; 4]], step: usize) {
+    for _ in 0..REFINE_PASSES {
+        let mut nearest = HashMap::new();
+        let mut sums = vec![([0u64; 4], 0u64); entries.len()];
+        for &pixel in pixels.iter().step_by(step) {
+            let index = *nearest
+                .entry(pixel)
+                .or_insert_with(|| closest(entries, pixel));
+            let (sum, count) = &mut sums[usize::from(index)];
+            for c in 0..4 {
+                sum[c] += u64::from(pixel[c]);
+            }
+            *count += 1;
+        }
+        for (entry, (sum, count)) in entries.iter_mut().zip(sums) {
+            if count > 0 {
+                *entry = sum.map(|s| ((s + count / 2) / count) as u8);
+            }
+        }
+    }
+}
+
+/// The index of the entry nearest to `color`, comparing premultiplied
+/// colours plus the difference in alpha.
+fn closest(entries: &[[u8; 4]], color: [u8; 4]) -> u8 {
+    let premultiply = |rgba: &[u8; 4]| {
+        let alpha = i32::from(rgba[3]);
+        [0, 1, 2].map(|c| i32::from(rgba[c]) * alpha / 255)
+    };
+    let target = premultiply(&color);
+    let distance = |entry: &[u8; 4]| {
+        let rgb = premultiply(entry);
+        let alpha = i32::from(entry[3]) - i32::from(color[3]);
+        (0..3).map(|c| (rgb[c] - target[c]).pow(2)).sum::<i32>() + alpha * alpha
+    };
+    (0..entries.len())
+        .min_by_key(|&i| distance(&entries[i]))
+        .unwrap_or(0) as u8
+}
+
+/// The mean distance from each entry to its nearest neighbour: how far the
+/// ordered pattern has to push a colour to reach the next one.
+fn palette_step(entries: &[[u8; 4]]) -> f32 {
+    // The shift is applied to every channel at once, so measure the
+    // largest per-channel difference.
+    let distance = |a: &[u8; 4], b: &[u8; 4]| {
+        (0..3)
+            .map(|c| f32::from(a[c].abs_diff(b[c])))
+            .fold(0.0, f32::max)
+    };
+    let nearest: Vec<f32> = entries
+        .iter()
+        .enumerate()
+        .filter_map(|(i, a)| {
+            entries
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(_, b)| distance(a, b))
+                .reduce(f32::min)
+        })
+        .collect();
+    if nearest.is_empty() {
+        return 0.0;
+    }
+    nearest.iter().sum::<f32>() / nearest.len() as f32
+}
+
+/// Maps each pixel to the nearest entry after adding the error of its
+/// neighbours (7/16 right, 3/16, 5/16 and 1/16 on the row below).
+fn floyd_steinberg(
+    pixels: &[[u8; 4]],
+    width: usize,
+    entries: &[[u8; 4]],
+    nearest: impl Fn([u8; 4]) -> u8,
+) -> Vec<u8> {
+    let mut indices = Vec::with_capacity(pixels.len());
+    // Errors carried into the current and the next row, per colour channel,
+    // with one cell of padding on each side.
+    let mut current = vec![[0f32; 3]; width + 2];
+    let mut next = vec![[0f32; 3]; width + 2];
+    for row in pixels.chunks_exact(width) {
+        for (x, pixel) in row.iter().enumerate() {
+            let error = current[x + 1];
+            let mut wanted = [0f32; 3];
+            let mut target = *pixel;
+            for c in 0..3 {
+                wanted[c] = (f32::from(pixel[c]) + error[c]).clamp(0.0, 255.0);
+                target[c] = wanted[c].round() as u8;
+            }
+            let index = nearest(target);
+            indices.push(index);
+            let chosen = entries[usize::from(index)];
+            for c in 0..3 {
+                let e = wanted[c] - f32::from(chosen[c]);
+                current[x + 2][c] += e * 7.0 / 16.0;
+                next[x][c] += e * 3.0 / 16.0;
+                next[x + 1][c] += e * 5.0 / 16.0;
+                next[x + 2][c] += e / 16.0;
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.iter_mut().for_each(|e| *e = [0.0; 3]);
+    }
+    indices
+}
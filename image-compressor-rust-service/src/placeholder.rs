@@ -0,0 +1,38 @@
+// image-compressor-rust-service/src/placeholder.rs
+
+//! Low-quality image placeholders (LQIP): a tiny, blurred JPEG of the
+//! output, inlined as a `data:` URI for pages to show while the real image
+//! loads.
+
+use crate::encode;
+use anyhow::Result;
+use base64::Engine;
+use image::DynamicImage;
+
+/// Width used when the caller asks for a placeholder without a size.
+pub const DEFAULT_WIDTH: u32 = 24;
+
+/// The largest placeholder width a caller may ask for.
+pub const MAX_WIDTH: u32 = 64;
+
+/// JPEG quality of placeholders; the blur hides the artefacts.
+const QUALITY: u8 = 40;
+
+/// Scales `image` down to `width` pixels wide (never up), blurs it and
+/// returns it as a `data:image/jpeg;base64,...` URI.
+///
+/// `image` is the already transformed output, so this costs a thumbnail
+/// pass over it and a tiny encode.
+pub fn generate(image: &DynamicImage, width: u32) -> Result<String> {
+    let width = width.clamp(1, MAX_WIDTH).min(image.width());
+    let height = (u64::from(image.height()) * u64::from(width) / u64::from(image.width().max(1)))
+        .max(1) as u32;
+    let tiny = image.thumbnail_exact(width, height);
+    let sigma = (width as f32 / 12.0).max(1.0);
+    let blurred = DynamicImage::ImageRgba8(image::imageops::blur(&tiny, sigma));
+    let jpeg = encode::encode_jpeg(&DynamicImage::ImageRgb8(blurred.into_rgb8()), QUALITY)?;
+    Ok(format!(
+        "data:image/jpeg;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(jpeg)
+    ))
+}
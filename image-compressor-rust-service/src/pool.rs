@@ -0,0 +1,205 @@
+// image-compressor-rust-service/src/pool.rs
+
+//! Reuse of large byte buffers across requests.
+//!
+//! Decoded pixels and intermediate encodes are the largest scratch
+//! allocations of a compression, and under load the allocator hands back
+//! fresh pages for them on every request. The pool keeps buffers that are
+//! done with for the next compression instead, on whichever worker thread
+//! it runs. Final outputs are not taken from the pool: they leave with the
+//! response, and may be kept by the idempotency cache, so they are
+//! allocated at the size they need.
+//!
+//! Buffers are kept in size classes by capacity, powers of two from
+//! `min_buffer_bytes` to `max_buffer_bytes`. [`take`] hands out an empty
+//! buffer from the class that fits the capacity asked for, or allocates
+//! one of that class's size; smaller and larger buffers are allocated as
+//! asked. [`give`] keeps a buffer unless its class holds `max_per_class`
+//! already or the pool holds `max_bytes`, in which case it is freed as it
+//! would have been. Buffers outside the classes are never pooled.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// Settings for the buffer pool.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolConfig {
+    /// Reuse buffers across requests.
+    pub enabled: bool,
+    /// Most bytes of capacity kept in the pool, across all classes.
+    pub max_bytes: usize,
+    /// Most buffers kept in each size class.
+    pub max_per_class: usize,
+    /// Size of the smallest class. Smaller buffers are left to the
+    /// allocator.
+    pub min_buffer_bytes: usize,
+    /// Size of the largest class. Larger buffers are never pooled.
+    pub max_buffer_bytes: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_bytes: 256 * 1024 * 1024,
+            max_per_class: 8,
+            min_buffer_bytes: 64 * 1024,
+            max_buffer_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl PoolConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !self.min_buffer_bytes.is_power_of_two() || !self.max_buffer_bytes.is_power_of_two() {
+            bail!("min_buffer_bytes and max_buffer_bytes must be powers of two");
+        }
+        if self.min_buffer_bytes > self.max_buffer_bytes {
+            bail!(
+                "min_buffer_bytes ({}) is above max_buffer_bytes ({})",
+                self.min_buffer_bytes,
+                self.max_buffer_bytes
+            );
+        }
+        if self.enabled && self.max_buffer_bytes > self.max_bytes {
+            bail!(
+                "max_buffer_bytes ({}) is above max_bytes ({}), so the largest class could never be pooled",
+                self.max_buffer_bytes,
+                self.max_bytes
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Buffers kept for reuse, by size class.
+pub struct BufferPool {
+    config: PoolConfig,
+    /// Class `n` holds buffers of capacity `min_buffer_bytes << n` up to
+    /// twice that.
+    classes: Vec<Mutex<Vec<Vec<u8>>>>,
+    /// Capacity of the buffers held, in bytes.
+    pooled: AtomicUsize,
+}
+
+impl BufferPool {
+    /// A pool for `config`, which must have been validated.
+    pub fn new(config: &PoolConfig) -> Self {
+        let count = if config.enabled {
+            (config.max_buffer_bytes / config.min_buffer_bytes).trailing_zeros() as usize + 1
+        } else {
+            0
+        };
+        Self {
+            config: config.clone(),
+            classes: (0..count).map(|_| Mutex::new(Vec::new())).collect(),
+            pooled: AtomicUsize::new(0),
+        }
+    }
+
+    /// An empty buffer with room for at least `capacity` bytes.
+    pub fn take(&self, capacity: usize) -> Vec<u8> {
+        let Some(class) = self.class_fitting(capacity) else {
+            return Vec::with_capacity(capacity);
+        };
+        let buffer = self.classes[class]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop();
+        match buffer {
+            Some(buffer) => {
+                metrics::increment_counter!("buffer_pool_hits_total");
+                self.release(buffer.capacity());
+                buffer
+            }
+            None => {
+                metrics::increment_counter!("buffer_pool_misses_total");
+                Vec::with_capacity(self.config.min_buffer_bytes << class)
+            }
+        }
+    }
+
+    /// Keeps `buffer` for a later [`take`](Self::take), or frees it.
+    pub fn give(&self, mut buffer: Vec<u8>) {
+        let capacity = buffer.capacity();
+        let Some(class) = self.class_holding(capacity) else {
+            return;
+        };
+        let mut held = self.classes[class]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if held.len() >= self.config.max_per_class || !self.reserve(capacity) {
+            return;
+        }
+        buffer.clear();
+        held.push(buffer);
+    }
+
+    /// Capacity of the buffers held, in bytes.
+    pub fn pooled_bytes(&self) -> usize {
+        self.pooled.load(Ordering::Relaxed)
+    }
+
+    /// The smallest class whose buffers all have room for `capacity`.
+    fn class_fitting(&self, capacity: usize) -> Option<usize> {
+        if self.classes.is_empty()
+            || !(self.config.min_buffer_bytes..=self.config.max_buffer_bytes).contains(&capacity)
+        {
+            return None;
+        }
+        let size = capacity.next_power_of_two();
+        Some((size / self.config.min_buffer_bytes).trailing_zeros() as usize)
+    }
+
+    /// The class a buffer of `capacity` bytes is kept in.
+    fn class_holding(&self, capacity: usize) -> Option<usize> {
+        if capacity < self.config.min_buffer_bytes {
+            return None;
+        }
+        let class = (capacity / self.config.min_buffer_bytes).ilog2() as usize;
+        (class < self.classes.len()).then_some(class)
+    }
+
+    fn reserve(&self, capacity: usize) -> bool {
+        let reserved = self
+            .pooled
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |pooled| {
+                (pooled + capacity <= self.config.max_bytes).then_some(pooled + capacity)
+            });
+        if reserved.is_ok() {
+            metrics::increment_gauge!("buffer_pool_bytes", capacity as f64);
+        }
+        reserved.is_ok()
+    }
+
+    fn release(&self, capacity: usize) {
+        self.pooled.fetch_sub(capacity, Ordering::Relaxed);
+        metrics::decrement_gauge!("buffer_pool_bytes", capacity as f64);
+    }
+}
+
+static POOL: OnceLock<BufferPool> = OnceLock::new();
+
+/// Sets up the pool the compression pipeline uses. Only the first call
+/// counts; without one, the pool has the default settings.
+pub fn init(config: &PoolConfig) {
+    let _ = POOL.set(BufferPool::new(config));
+}
+
+/// The pool the compression pipeline uses.
+pub fn global() -> &'static BufferPool {
+    POOL.get_or_init(|| BufferPool::new(&PoolConfig::default()))
+}
+
+/// An empty buffer from the [`global`] pool with room for `capacity` bytes.
+pub fn take(capacity: usize) -> Vec<u8> {
+    global().take(capacity)
+}
+
+/// Returns `buffer` to the [`global`] pool.
+pub fn give(buffer: Vec<u8>) {
+    global().give(buffer)
+}
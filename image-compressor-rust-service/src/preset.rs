@@ -0,0 +1,247 @@
+// image-compressor-rust-service/src/preset.rs
+
+//! Named sets of compression options, e.g. `web` or `thumbnail`.
+//!
+//! The built-in presets can be overridden, and more added, under
+//! `[presets.<name>]` in the config file.
+
+use crate::jpeg::CustomTables;
+use crate::options::{
+    AnimationMode, AspectRatio, Border, Color, ColorSpace, CompressionOptions, CornerRadius,
+    Dither, Effort, FieldError, Filter, Fit, Gravity, Palette, QualityScale, Rotation,
+};
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// Names of the built-in presets.
+pub const BUILTIN: [&str; 2] = ["web", "thumbnail"];
+
+/// Options set by a preset. Anything left out keeps its default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Preset {
+    /// Quality from 1 to 100. Without it the configured per-format default
+    /// applies.
+    pub quality: Option<u8>,
+    pub quality_scale: Option<QualityScale>,
+    pub animation: Option<AnimationMode>,
+    /// Animate at most this many frames.
+    pub max_frames: Option<u32>,
+    /// Animate every k-th frame.
+    pub frame_step: Option<u32>,
+    /// Animate for at most this many milliseconds.
+    pub max_duration: Option<u64>,
+    /// Encode this frame of an animated input, from 0, as a still.
+    pub poster_frame: Option<u32>,
+    /// Degrees clockwise: `90`, `180` or `270`.
+    pub rotate: Option<Rotation>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Option<Fit>,
+    /// A direction such as `"north"` or a focal point such as `"0.3,0.25"`.
+    pub gravity: Option<Gravity>,
+    /// The `pad` colour, `"transparent"` or a hex colour such as `"#ffffff"`.
+    pub background: Option<Color>,
+    /// E.g. `"sepia"`, `"tint:#ff880080"` or `"duotone:#1a1a40,#ffd000"`.
+    pub filter: Option<Filter>,
+    /// Encode stills as a palette PNG of at most this many colours.
+    pub palette_colors: Option<u16>,
+    /// `"none"`, `"floyd-steinberg"` (the default) or `"ordered"`.
+    pub dither: Option<Dither>,
+    /// `"srgb"`, `"display-p3"` or `"linear-srgb"`.
+    pub color_space: Option<ColorSpace>,
+    /// Encode stills with a single grey channel, or `false` to keep the
+    /// colour channels of grey ones.
+    pub grayscale: Option<bool>,
+    /// Width of a blurred placeholder to return as well.
+    pub placeholder: Option<u32>,
+    /// Return the text of the input as well. Needs `[ocr]` to be enabled.
+    pub ocr: Option<bool>,
+    pub border_width: Option<u32>,
+    /// The border colour, black by default.
+    pub border_color: Option<Color>,
+    /// Pixels such as `"12"` or a percentage such as `"50%"`.
+    pub corner_radius: Option<CornerRadius>,
+    /// An exact aspect ratio such as `"16:9"`.
+    pub aspect: Option<AspectRatio>,
+    pub allow_upscale: Option<bool>,
+    /// Compress what can be recovered of truncated or corrupt inputs.
+    pub salvage: Option<bool>,
+    /// `"fast"`, `"balanced"` or `"max"`.
+    pub effort: Option<Effort>,
+    /// JPEG quantization tables, as `{ luma = [...], chroma = [...] }`
+    /// with 64 entries each, applying at quality 50.
+    pub quant_tables: Option<CustomTables>,
+}
+
+impl Preset {
+    /// The built-in preset `name`, if there is one.
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            // Fits screens up to 2K, at the default quality.
+            "web" => Some(Self {
+                width: Some(2048),
+                height: Some(2048),
+                fit: Some(Fit::Contain),
+                ..Self::default()
+            }),
+            "thumbnail" => Some(Self {
+                quality: Some(70),
+                width: Some(320),
+                height: Some(320),
+                fit: Some(Fit::Cover),
+                ..Self::default()
+            }),
+            _ => None,
+        }
+    }
+
+    /// Builds a preset from the fields of a JSON object that hold valid
+    /// values, and returns an error for each of the others.
+    pub fn from_json(fields: &Map<String, Value>) -> (Self, Vec<FieldError>) {
+        let mut valid = Map::new();
+        let mut errors = Vec::new();
+        for (name, value) in fields {
+            let field = Map::from_iter([(name.clone(), value.clone())]);
+            match serde_json::from_value::<Self>(Value::Object(field.clone())) {
+                Ok(preset) => match preset.check().into_iter().next() {
+                    Some(error) => errors.push(error),
+                    None => valid.extend(field),
+                },
+                Err(e) => errors.push(FieldError::new(name, e.to_string())),
+            }
+        }
+        // Every field deserializes on its own, so they do together.
+        let preset = serde_json::from_value(Value::Object(valid)).unwrap_or_default();
+        (preset, errors)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self.check().into_iter().next() {
+            Some(error) => bail!("{} {}", error.field, error.message),
+            None => Ok(()),
+        }
+    }
+
+    /// The fields holding values out of range.
+    fn check(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if let Some(quality) = self.quality.filter(|q| !(1..=100).contains(q)) {
+            errors.push(FieldError::new(
+                "quality",
+                format!("must be between 1 and 100, got {}", quality),
+            ));
+        }
+        for (field, value) in [
+            ("width", self.width),
+            ("height", self.height),
+            ("max_frames", self.max_frames),
+            ("frame_step", self.frame_step),
+        ] {
+            if value == Some(0) {
+                errors.push(FieldError::new(field, "must be positive"));
+            }
+        }
+        if self.max_duration == Some(0) {
+            errors.push(FieldError::new("max_duration", "must be positive"));
+        }
+        if let Some(problem) = self.quant_tables.as_ref().and_then(CustomTables::problem) {
+            errors.push(FieldError::new("quant_tables", problem));
+        }
+        errors
+    }
+
+    /// Sets the options this preset defines.
+    pub fn apply(&self, options: &mut CompressionOptions) {
+        if let Some(quality) = self.quality {
+            options.quality = quality;
+            options.quality_explicit = true;
+        }
+        if let Some(scale) = self.quality_scale {
+            options.quality_scale = scale;
+        }
+        if let Some(animation) = self.animation {
+            options.animation = animation;
+        }
+        if let Some(max) = self.max_frames {
+            options.frames.max_frames = Some(max);
+        }
+        if let Some(step) = self.frame_step {
+            options.frames.step = Some(step);
+        }
+        if let Some(max) = self.max_duration {
+            options.frames.max_duration_ms = Some(max);
+        }
+        if let Some(index) = self.poster_frame {
+            options.frames.poster = Some(index);
+        }
+        if let Some(rotation) = self.rotate {
+            options.rotate = Some(rotation);
+        }
+        if let Some(width) = self.width {
+            options.width = Some(width);
+        }
+        if let Some(height) = self.height {
+            options.height = Some(height);
+        }
+        if let Some(fit) = self.fit {
+            options.fit = fit;
+        }
+        if let Some(gravity) = self.gravity {
+            options.gravity = gravity;
+        }
+        if let Some(background) = self.background {
+            options.background = Some(background);
+        }
+        if let Some(filter) = self.filter {
+            options.filter = Some(filter);
+        }
+        if let Some(colors) = self.palette_colors {
+            options.palette = Some(Palette::new(colors, self.dither.unwrap_or_default()));
+        }
+        if let Some(color_space) = self.color_space {
+            options.color_space = Some(color_space);
+        }
+        if let Some(grayscale) = self.grayscale {
+            options.grayscale = Some(grayscale);
+        }
+        if let Some(width) = self.placeholder {
+            options.placeholder = Some(width.clamp(1, crate::placeholder::MAX_WIDTH));
+        }
+        if let Some(ocr) = self.ocr {
+            options.ocr = ocr;
+        }
+        if let Some(width) = self.border_width.filter(|&w| w > 0) {
+            options.border = Some(Border {
+                width,
+                color: self.border_color.unwrap_or(Color::BLACK),
+            });
+        }
+        if let Some(radius) = self.corner_radius {
+            options.corner_radius = Some(radius);
+        }
+        if let Some(aspect) = self.aspect {
+            options.aspect = Some(aspect);
+        }
+        if let Some(allow_upscale) = self.allow_upscale {
+            options.allow_upscale = allow_upscale;
+        }
+        if let Some(salvage) = self.salvage {
+            options.salvage = salvage;
+        }
+        if let Some(effort) = self.effort {
+            options.effort = Some(effort);
+        }
+        if let Some(tables) = &self.quant_tables {
+            options.quant_tables = Some(tables.clone());
+        }
+    }
+
+    /// The options of a request using only this preset.
+    pub fn options(&self) -> CompressionOptions {
+        let mut options = CompressionOptions::default();
+        self.apply(&mut options);
+        options
+    }
+}
@@ -0,0 +1,221 @@
+// image-compressor-rust-service/src/provenance.rs
+
+//! C2PA content credentials for outputs.
+//!
+//! When enabled, every output carries a signed C2PA manifest that names the
+//! input as its parent ingredient and records what the service did to it
+//! (transcoding, resizing). Consumers can verify with any C2PA validator
+//! that the derivative came from this service and was not altered since.
+//!
+//! Signing needs the `c2pa` cargo feature, which pulls in the C2PA SDK.
+
+use crate::formats::{InputFormat, OutputFormat};
+use crate::options::Rotation;
+use anyhow::Result;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Settings for signing outputs.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProvenanceConfig {
+    /// Embed a signed C2PA manifest in every output.
+    pub enabled: bool,
+    /// PEM file with the signing certificate followed by its chain.
+    pub certificate_chain: PathBuf,
+    /// PEM file with the private key of the signing certificate.
+    pub private_key: PathBuf,
+    /// Signature algorithm matching the key: `es256`, `es384`, `es512`,
+    /// `ps256`, `ps384`, `ps512` or `ed25519`.
+    pub algorithm: String,
+    /// RFC 3161 time-stamp authority countersigning the manifest.
+    pub tsa_url: Option<String>,
+}
+
+impl Default for ProvenanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            certificate_chain: PathBuf::from("c2pa/certs.pem"),
+            private_key: PathBuf::from("c2pa/private.key"),
+            algorithm: "es256".to_string(),
+            tsa_url: None,
+        }
+    }
+}
+
+/// What the pipeline did to produce an output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transformation {
+    pub input_format: Option<InputFormat>,
+    pub output_format: OutputFormat,
+    /// Encoder-native quality.
+    pub quality: u8,
+    pub source_dimensions: (u32, u32),
+    pub rotation: Option<Rotation>,
+    pub output_dimensions: (u32, u32),
+}
+
+impl ProvenanceConfig {
+    /// Checks that the key material can be loaded, so a bad setup fails at
+    /// startup instead of on every request.
+    pub fn validate(&self) -> Result<()> {
+        if self.enabled {
+            imp::signer(self).map(drop)?;
+        }
+        Ok(())
+    }
+
+    /// Embeds a signed manifest describing `transformation` in the encoded
+    /// `output`. `input` is recorded as the parent ingredient, so a manifest
+    /// it already carries stays part of the history.
+    ///
+    /// The key files are read on every call, so rotated keys take effect
+    /// without a restart.
+    pub fn sign(
+        &self,
+        input: &[u8],
+        output: Vec<u8>,
+        transformation: &Transformation,
+    ) -> Result<Vec<u8>> {
+        if !self.enabled {
+            return Ok(output);
+        }
+        imp::sign(self, input, output, transformation)
+    }
+}
+
+#[cfg(feature = "c2pa")]
+mod imp {
+    use super::{ProvenanceConfig, Transformation};
+    use crate::build_info;
+    use anyhow::{Context, Result};
+    use c2pa::{create_signer, BoxedSigner, Builder, SigningAlg};
+    use serde_json::json;
+    use std::io::Cursor;
+    use std::time::Instant;
+
+    /// Links the `c2pa.opened` action to the input ingredient.
+    const INGREDIENT_ID: &str = "input";
+
+    pub fn signer(config: &ProvenanceConfig) -> Result<BoxedSigner> {
+        let read = |path: &std::path::Path| {
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+        };
+        let algorithm: SigningAlg = config
+            .algorithm
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Unknown C2PA algorithm '{}'", config.algorithm))?;
+        create_signer::from_keys(
+            &read(&config.certificate_chain)?,
+            &read(&config.private_key)?,
+            algorithm,
+            config.tsa_url.clone(),
+        )
+        .context("Failed to load the C2PA signing key")
+    }
+
+    pub fn sign(
+        config: &ProvenanceConfig,
+        input: &[u8],
+        output: Vec<u8>,
+        transformation: &Transformation,
+    ) -> Result<Vec<u8>> {
+        let start = Instant::now();
+        let signer = signer(config)?;
+        let output_mime = transformation.output_format.mime_type();
+
+        // The first action must open the input ingredient (or create the
+        // asset, when the input format is unknown).
+        let mut actions = vec![match transformation.input_format {
+            Some(_) => json!({
+                "action": "c2pa.opened",
+                "parameters": { "ingredientIds": [INGREDIENT_ID] },
+            }),
+            None => json!({ "action": "c2pa.created" }),
+        }];
+        let mut source_dimensions = transformation.source_dimensions;
+        if let Some(rotation) = transformation.rotation {
+            source_dimensions = rotation.dimensions(source_dimensions);
+            actions.push(json!({
+                "action": "c2pa.orientation",
+                "parameters": { "degrees": rotation.degrees() },
+            }));
+        }
+        if source_dimensions != transformation.output_dimensions {
+            let (width, height) = transformation.output_dimensions;
+            actions.push(json!({
+                "action": "c2pa.resized",
+                "parameters": { "width": width, "height": height },
+            }));
+        }
+        actions.push(json!({
+            "action": "c2pa.transcoded",
+            "parameters": {
+                "format": output_mime,
+                "quality": transformation.quality,
+            },
+        }));
+        let manifest = json!({
+            "claim_generator_info": [{
+                "name": env!("CARGO_PKG_NAME"),
+                "version": build_info::VERSION,
+            }],
+            "format": output_mime,
+            "assertions": [{
+                "label": "c2pa.actions",
+                "data": { "actions": actions },
+            }],
+        });
+
+        let mut builder =
+            Builder::from_context(c2pa::Context::new()).with_definition(manifest.to_string())?;
+        if let Some(format) = transformation.input_format {
+            builder
+                .add_ingredient_from_stream(
+                    json!({
+                        "title": "input",
+                        "relationship": "parentOf",
+                        "label": INGREDIENT_ID,
+                    })
+                    .to_string(),
+                    format.mime_type(),
+                    &mut Cursor::new(input),
+                )
+                .context("Failed to record the input as a C2PA ingredient")?;
+        }
+        let mut signed = Cursor::new(Vec::with_capacity(output.len() + 16 * 1024));
+        builder
+            .sign(
+                signer.as_ref(),
+                output_mime,
+                &mut Cursor::new(output),
+                &mut signed,
+            )
+            .context("Failed to sign the C2PA manifest")?;
+
+        metrics::histogram!("c2pa_sign_duration_seconds", start.elapsed().as_secs_f64());
+        Ok(signed.into_inner())
+    }
+}
+
+#[cfg(not(feature = "c2pa"))]
+mod imp {
+    use super::{ProvenanceConfig, Transformation};
+    use anyhow::{bail, Result};
+
+    const MISSING_FEATURE: &str = "provenance.enabled requires a build with the `c2pa` feature";
+
+    pub fn signer(_config: &ProvenanceConfig) -> Result<()> {
+        bail!(MISSING_FEATURE)
+    }
+
+    pub fn sign(
+        _config: &ProvenanceConfig,
+        _input: &[u8],
+        _output: Vec<u8>,
+        _transformation: &Transformation,
+    ) -> Result<Vec<u8>> {
+        bail!(MISSING_FEATURE)
+    }
+}
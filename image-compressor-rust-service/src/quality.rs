@@ -0,0 +1,300 @@
+// image-compressor-rust-service/src/quality.rs
+
+//! Quality defaults, clamping and the perceptual quality scale.
+//!
+//! Requests use a perceptual scale by default: JPEG quality is the reference,
+//! and other encoders are driven at the native quality that reached the same
+//! median luma SSIM on a calibration set of photographs (landscape, portrait,
+//! architecture and fur textures, up to 800 px wide). `X-Quality-Scale: native`
+//! bypasses the mapping.
+//!
+//! The quality a JPEG input was saved at is estimated from its quantization
+//! tables (see [`estimate_jpeg_quality`]), so requests that would re-encode
+//! it at a higher quality, which only makes the file larger, can be warned
+//! about or refused (see [`AboveSource`]).
+
+use crate::formats::OutputFormat;
+use crate::options::{CompressionOptions, QualityScale, DEFAULT_QUALITY};
+use anyhow::{ensure, Result};
+use serde::Deserialize;
+
+/// Perceptual quality to native libwebp quality, as `(perceptual, native)`
+/// points between which the mapping is linear. On photographs libwebp needs a
+/// higher setting than a libjpeg-style encoder to reach the same SSIM through
+/// the low and mid range; near the top the two scales meet.
+const WEBP_CURVE: &[(u8, u8)] = &[
+    (1, 1),
+    (20, 24),
+    (30, 39),
+    (40, 50),
+    (50, 64),
+    (60, 70),
+    (70, 79),
+    (75, 82),
+    (80, 85),
+    (85, 92),
+    (90, 95),
+    (95, 98),
+    (100, 100),
+];
+
+/// Converts a perceptual quality (1-100) to the encoder-native value.
+pub fn perceptual_to_native(format: OutputFormat, quality: u8) -> u8 {
+    match format {
+        OutputFormat::Jpeg | OutputFormat::Png => quality,
+        OutputFormat::Webp => interpolate(WEBP_CURVE, quality),
+    }
+}
+
+fn interpolate(curve: &[(u8, u8)], x: u8) -> u8 {
+    let x = x.clamp(curve[0].0, curve[curve.len() - 1].0);
+    let upper = curve.iter().position(|&(px, _)| px >= x).unwrap_or(0);
+    if upper == 0 {
+        return curve[0].1;
+    }
+    let ((x0, y0), (x1, y1)) = (curve[upper - 1], curve[upper]);
+    let t = f32::from(x - x0) / f32::from(x1 - x0);
+    (f32::from(y0) + t * (f32::from(y1) - f32::from(y0))).round() as u8
+}
+
+/// Default quality and allowed range for one output format, on the scale the
+/// request uses (perceptual unless it asks for native values).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FormatQuality {
+    /// Used when the request does not send `X-Compression-Quality`.
+    pub default: u8,
+    /// Lowest quality a request may ask for.
+    pub min: u8,
+    /// Highest quality a request may ask for.
+    pub max: u8,
+}
+
+impl Default for FormatQuality {
+    fn default() -> Self {
+        Self {
+            default: DEFAULT_QUALITY,
+            min: 1,
+            max: 100,
+        }
+    }
+}
+
+impl FormatQuality {
+    fn validate(&self, format: OutputFormat) -> Result<()> {
+        ensure!(
+            1 <= self.min && self.min <= self.max && self.max <= 100,
+            "quality.{}: min and max must satisfy 1 <= min <= max <= 100",
+            format
+        );
+        ensure!(
+            (self.min..=self.max).contains(&self.default),
+            "quality.{}: default {} is outside {}..={}",
+            format,
+            self.default,
+            self.min,
+            self.max
+        );
+        Ok(())
+    }
+}
+
+/// Palette PNGs have no quality setting; their loss is set by the palette
+/// size instead.
+static PALETTE_QUALITY: FormatQuality = FormatQuality {
+    default: 100,
+    min: 100,
+    max: 100,
+};
+
+/// What to do with requests for a JPEG above the estimated quality of a
+/// JPEG input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AboveSource {
+    /// Encode as asked.
+    Allow,
+    /// Encode as asked, with an `X-Quality-Warning` response header.
+    #[default]
+    Warn,
+    /// Reject the request.
+    Refuse,
+}
+
+/// Per-output-format quality settings.
+///
+/// The same number means different things to different encoders, so each
+/// format has its own default and clamp range.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QualityConfig {
+    pub jpeg: FormatQuality,
+    pub webp: FormatQuality,
+    /// Requests with an explicit quality above that of their JPEG input.
+    pub above_source: AboveSource,
+}
+
+impl QualityConfig {
+    pub fn for_format(&self, format: OutputFormat) -> &FormatQuality {
+        match format {
+            OutputFormat::Jpeg => &self.jpeg,
+            OutputFormat::Webp => &self.webp,
+            OutputFormat::Png => &PALETTE_QUALITY,
+        }
+    }
+
+    /// The encoder quality for a request: the caller's value clamped to the
+    /// format's range, or the format default when the caller sent none,
+    /// then mapped to the encoder's native scale unless it already is.
+    pub fn resolve(&self, format: OutputFormat, options: &CompressionOptions) -> u8 {
+        let settings = self.for_format(format);
+        let quality = if options.quality_explicit {
+            options.quality.clamp(settings.min, settings.max)
+        } else {
+            settings.default
+        };
+        match options.quality_scale {
+            QualityScale::Perceptual => perceptual_to_native(format, quality),
+            QualityScale::Native => quality,
+        }
+    }
+
+    /// The JPEG quality a request resolves to, if it explicitly asks for
+    /// one above `source`, the estimated quality of its JPEG input.
+    pub fn exceeds_source(&self, source: u8, options: &CompressionOptions) -> Option<u8> {
+        if !options.quality_explicit || options.palette.is_some() {
+            return None;
+        }
+        let requested = self.resolve(OutputFormat::Jpeg, options);
+        (requested > source).then_some(requested)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for format in OutputFormat::ALL {
+            self.for_format(format).validate(format)?;
+        }
+        Ok(())
+    }
+}
+
+/// The IJG reference luma table (JPEG Annex K.1), in natural order.
+#[rustfmt::skip]
+pub(crate) const LUMA_TABLE: [u16; 64] = [
+    16, 11, 10, 16,  24,  40,  51,  61,
+    12, 12, 14, 19,  26,  58,  60,  55,
+    14, 13, 16, 24,  40,  57,  69,  56,
+    14, 17, 22, 29,  51,  87,  80,  62,
+    18, 22, 37, 56,  68, 109, 103,  77,
+    24, 35, 55, 64,  81, 104, 113,  92,
+    49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103,  99,
+];
+
+/// The IJG reference chroma table (JPEG Annex K.2), in natural order.
+#[rustfmt::skip]
+pub(crate) const CHROMA_TABLE: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99,
+    18, 21, 26, 66, 99, 99, 99, 99,
+    24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// The natural-order position of each table entry as stored, in zigzag order.
+#[rustfmt::skip]
+pub(crate) const ZIGZAG: [usize; 64] = [
+     0,  1,  8, 16,  9,  2,  3, 10, 17, 24, 32, 25, 18, 11,  4,  5,
+    12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13,  6,  7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Estimates the quality (1-100, on the IJG scale) a JPEG was saved at, by
+/// finding the quality whose scaled reference tables come closest to the
+/// image's quantization tables. Exact for libjpeg-style encoders, this one
+/// included; approximate for encoders with tables of their own. `None` for
+/// other formats and JPEGs without quantization tables.
+pub fn estimate_jpeg_quality(data: &[u8]) -> Option<u8> {
+    let tables = quantization_tables(data)?;
+    let references = [&LUMA_TABLE, &CHROMA_TABLE];
+    let error = |quality: u8| -> u64 {
+        tables
+            .iter()
+            .filter_map(|(id, table)| Some((references.get(usize::from(*id))?, table)))
+            .flat_map(|(reference, table)| reference.iter().zip(table))
+            .map(|(&base, &actual)| u64::from(scale_entry(base, quality).abs_diff(actual)))
+            .sum()
+    };
+    if !tables
+        .iter()
+        .any(|(id, _)| usize::from(*id) < references.len())
+    {
+        return None;
+    }
+    // Several top qualities scale to the same all-ones tables; the highest
+    // of those is reported.
+    (1..=100).rev().min_by_key(|&quality| error(quality))
+}
+
+/// A reference table entry scaled to `quality` as libjpeg does it.
+pub(crate) fn scale_entry(base: u16, quality: u8) -> u16 {
+    let quality = u32::from(quality);
+    let scale = if quality < 50 {
+        5000 / quality
+    } else {
+        200 - quality * 2
+    };
+    ((u32::from(base) * scale + 50) / 100).clamp(1, 255) as u16
+}
+
+/// The quantization tables of a JPEG, by table id and in natural order. Only
+/// the segments before the first scan are read.
+fn quantization_tables(data: &[u8]) -> Option<Vec<(u8, [u16; 64])>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut tables = Vec::new();
+    let mut at = 2;
+    loop {
+        // Markers may be padded with any number of 0xFF bytes.
+        while data.get(at) == Some(&0xFF) && data.get(at + 1) == Some(&0xFF) {
+            at += 1;
+        }
+        if *data.get(at)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(at + 1)?;
+        // Standalone markers carry no length.
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            at += 2;
+            continue;
+        }
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let length = usize::from(u16::from_be_bytes([*data.get(at + 2)?, *data.get(at + 3)?]));
+        let segment = data.get(at + 4..at + 2 + length)?;
+        if marker == 0xDB {
+            let mut rest = segment;
+            while let Some((&spec, tail)) = rest.split_first() {
+                let (precision, id) = (spec >> 4, spec & 0x0F);
+                let width = if precision == 0 { 1 } else { 2 };
+                let values = tail.get(..64 * width)?;
+                let mut table = [0u16; 64];
+                for (i, &natural) in ZIGZAG.iter().enumerate() {
+                    table[natural] = match width {
+                        1 => u16::from(values[i]),
+                        _ => u16::from_be_bytes([values[2 * i], values[2 * i + 1]]),
+                    };
+                }
+                tables.push((id, table));
+                rest = &tail[64 * width..];
+            }
+        }
+        at += 2 + length;
+    }
+    (!tables.is_empty()).then_some(tables)
+}
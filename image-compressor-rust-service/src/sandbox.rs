@@ -0,0 +1,507 @@
+// image-compressor-rust-service/src/sandbox.rs
+
+//! Out-of-process decoding for untrusted inputs.
+//!
+//! When enabled, the service re-executes its own binary with the
+//! [`WORKER_ARG`] subcommand and streams the encoded input over stdin. The
+//! worker reads the whole input, drops its privileges (rlimits and a seccomp
+//! filter on Linux), decodes, and writes the raw pixels back over stdout. A
+//! decoder crash or exploit is therefore confined to a short-lived process
+//! that can only read, write and allocate memory.
+//!
+//! Wire format (all integers little-endian):
+//!
+//! * request: `mode: u8` (0 = still image, 1 = animation frames, 2 = salvaged
+//!   still image), then for frames `max_frames: u64, max_duration_ms: u64`
+//!   (`u64::MAX` for no limit), then the input bytes.
+//! * response: `status: u8` (0 = ok, 1 = error). Errors are followed by
+//!   `kind: u8` (0 = unclassified, otherwise one more than the index of the
+//!   [`DecodeError`] in [`DecodeError::ALL`]) and a UTF-8 message. A still
+//!   image is `color: u8, width: u32, height: u32, pixels`, preceded by
+//!   `salvaged: u8` when salvaged. Frames are `present: u8`, then `count: u32` and for every frame
+//!   `delay_numer: u32, delay_denom: u32, left: u32, top: u32, width: u32,
+//!   height: u32, rgba pixels`.
+
+use crate::animation::FrameLimit;
+use crate::decode::{decode_frames, decode_image, salvage_image, DecodeError};
+use anyhow::{anyhow, bail, Context, Result};
+use image::{
+    Delay, DynamicImage, Frame, GrayAlphaImage, GrayImage, ImageBuffer, Luma, LumaA, Rgb, RgbImage,
+    Rgba, RgbaImage,
+};
+use serde::Deserialize;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// First command-line argument that switches the binary into worker mode.
+pub const WORKER_ARG: &str = "decode-worker";
+
+const MODE_STILL: u8 = 0;
+const MODE_FRAMES: u8 = 1;
+const MODE_SALVAGE: u8 = 2;
+const STATUS_OK: u8 = 0;
+const STATUS_ERR: u8 = 1;
+
+/// Configuration of the decoding sandbox (`[sandbox]` in the config file).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SandboxConfig {
+    /// Decode every input in a separate, resource-limited subprocess.
+    pub enabled: bool,
+    /// Address-space limit of the worker process, in bytes.
+    pub memory_limit_bytes: u64,
+    /// CPU time limit of the worker process, in seconds.
+    pub cpu_time_limit_secs: u64,
+    /// Binary to execute as the worker. Defaults to the running executable.
+    pub worker_path: Option<PathBuf>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            memory_limit_bytes: 1024 * 1024 * 1024, // 1 GiB
+            cpu_time_limit_secs: 10,
+            worker_path: None,
+        }
+    }
+}
+
+/// Decodes a still image in a sandboxed worker process.
+///
+/// Behaves like [`decode_image`], but any crash, resource-limit violation or
+/// forbidden syscall in the decoder surfaces as an error instead of affecting
+/// the calling process.
+pub fn decode_image_sandboxed(input_bytes: &[u8], config: &SandboxConfig) -> Result<DynamicImage> {
+    let response = run_in_worker(&[MODE_STILL], input_bytes, config)?;
+    let mut reader = response.as_slice();
+    read_image(&mut reader)
+}
+
+/// Salvages a still image in a sandboxed worker process.
+///
+/// Behaves like [`salvage_image`]; see [`decode_image_sandboxed`].
+pub fn salvage_image_sandboxed(
+    input_bytes: &[u8],
+    config: &SandboxConfig,
+) -> Result<(DynamicImage, bool)> {
+    let response = run_in_worker(&[MODE_SALVAGE], input_bytes, config)?;
+    let mut reader = response.as_slice();
+    let salvaged = read_u8(&mut reader)? != 0;
+    Ok((read_image(&mut reader)?, salvaged))
+}
+
+/// Decodes animation frames in a sandboxed worker process.
+///
+/// Behaves like [`decode_frames`]; see [`decode_image_sandboxed`].
+pub fn decode_frames_sandboxed(
+    input_bytes: &[u8],
+    limit: FrameLimit,
+    config: &SandboxConfig,
+) -> Result<Option<Vec<Frame>>> {
+    let mut request = vec![MODE_FRAMES];
+    let max_duration = limit.duration.map(|max| max.as_millis() as u64);
+    for value in [limit.frames as u64, max_duration.unwrap_or(u64::MAX)] {
+        request.extend_from_slice(&value.to_le_bytes());
+    }
+    let response = run_in_worker(&request, input_bytes, config)?;
+    let mut reader = response.as_slice();
+    if read_u8(&mut reader)? == 0 {
+        return Ok(None);
+    }
+    let count = read_u32(&mut reader)?;
+    let mut frames = Vec::new();
+    for _ in 0..count {
+        let numer = read_u32(&mut reader)?;
+        let denom = read_u32(&mut reader)?.max(1);
+        let left = read_u32(&mut reader)?;
+        let top = read_u32(&mut reader)?;
+        let (width, height) = (read_u32(&mut reader)?, read_u32(&mut reader)?);
+        let pixels = read_pixels(&mut reader, width, height, 4)?;
+        let buffer = RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow!("Sandboxed decoder returned a malformed frame."))?;
+        let delay = Delay::from_numer_denom_ms(numer, denom);
+        frames.push(Frame::from_parts(buffer, left, top, delay));
+    }
+    Ok(Some(frames))
+}
+
+/// Spawns the worker, sends it the request (`header`, the mode and its
+/// parameters, followed by the input) and returns the payload of a
+/// successful response.
+fn run_in_worker(header: &[u8], input_bytes: &[u8], config: &SandboxConfig) -> Result<Vec<u8>> {
+    let program = match &config.worker_path {
+        Some(path) => path.clone(),
+        None => std::env::current_exe().context("Failed to locate the decoder worker binary.")?,
+    };
+
+    let mut child = Command::new(program)
+        .arg(WORKER_ARG)
+        .arg(config.memory_limit_bytes.to_string())
+        .arg(config.cpu_time_limit_secs.to_string())
+        .env_clear()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to spawn the sandboxed decoder.")?;
+
+    // A worker that dies early closes its stdin; the exit status reported
+    // below is more useful than the resulting broken-pipe error.
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin
+            .write_all(header)
+            .and_then(|_| stdin.write_all(input_bytes));
+    }
+
+    let output = child
+        .wait_with_output()
+        .context("Failed to wait for the sandboxed decoder.")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "Sandboxed decoder terminated abnormally ({}). {}",
+            output.status,
+            stderr.trim()
+        );
+    }
+
+    let (&status, payload) = output
+        .stdout
+        .split_first()
+        .ok_or_else(|| anyhow!("Sandboxed decoder produced no output."))?;
+    if status == STATUS_OK {
+        return Ok(payload.to_vec());
+    }
+    let (&kind, message) = payload.split_first().unwrap_or((&0, &[]));
+    let error = anyhow!("{}", String::from_utf8_lossy(message));
+    match DecodeError::ALL.get(usize::from(kind).wrapping_sub(1)) {
+        Some(&kind) => Err(error.context(kind)),
+        None => Err(error),
+    }
+}
+
+/// Entry point of the worker process. Never returns.
+///
+/// `args` are the command-line arguments following [`WORKER_ARG`]: the
+/// memory limit in bytes and the CPU time limit in seconds.
+pub fn run_worker(args: &[String]) -> ! {
+    let mut response = Vec::new();
+    match worker_main(args, &mut response) {
+        Ok(()) => {}
+        Err(e) => {
+            response.clear();
+            response.push(STATUS_ERR);
+            // The parent restores the kind as the context of the message.
+            let (kind, message) = match e.downcast_ref::<DecodeError>() {
+                Some(kind) => {
+                    let index = DecodeError::ALL.iter().position(|k| k == kind);
+                    let causes: Vec<_> = e
+                        .chain()
+                        .map(|c| c.to_string())
+                        .filter(|c| *c != kind.to_string())
+                        .collect();
+                    (index.map_or(0, |i| i as u8 + 1), causes.join(": "))
+                }
+                None => (0, format!("{:#}", e)),
+            };
+            response.push(kind);
+            response.extend_from_slice(message.as_bytes());
+        }
+    }
+
+    let mut stdout = std::io::stdout().lock();
+    let written = stdout.write_all(&response).and_then(|_| stdout.flush());
+    std::process::exit(if written.is_ok() { 0 } else { 1 })
+}
+
+fn worker_main(args: &[String], response: &mut Vec<u8>) -> Result<()> {
+    let memory_limit: u64 = args
+        .first()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| anyhow!("Missing memory limit argument."))?;
+    let cpu_limit: u64 = args
+        .get(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| anyhow!("Missing CPU time limit argument."))?;
+
+    let mut request = Vec::new();
+    std::io::stdin()
+        .lock()
+        .read_to_end(&mut request)
+        .context("Failed to read the decode request.")?;
+
+    // From here on the process can no longer open files, sockets or processes.
+    restrict_process(memory_limit, cpu_limit)?;
+
+    let (&mode, input_bytes) = request
+        .split_first()
+        .ok_or_else(|| anyhow!("Empty decode request."))?;
+
+    response.push(STATUS_OK);
+    match mode {
+        MODE_STILL => write_image(response, &decode_image(input_bytes)?),
+        MODE_SALVAGE => {
+            let (image, salvaged) = salvage_image(input_bytes)?;
+            response.push(u8::from(salvaged));
+            write_image(response, &image);
+        }
+        MODE_FRAMES => {
+            let mut reader = input_bytes;
+            let max_frames = read_u64(&mut reader)?;
+            let max_duration = read_u64(&mut reader)?;
+            let limit = FrameLimit {
+                frames: usize::try_from(max_frames).unwrap_or(usize::MAX),
+                duration: Some(max_duration)
+                    .filter(|&ms| ms != u64::MAX)
+                    .map(Duration::from_millis),
+            };
+            match decode_frames(reader, limit)? {
+                None => response.push(0),
+                Some(frames) => {
+                    response.push(1);
+                    response.extend_from_slice(&(frames.len() as u32).to_le_bytes());
+                    for frame in &frames {
+                        let (numer, denom) = frame.delay().numer_denom_ms();
+                        let buffer = frame.buffer();
+                        for value in [
+                            numer,
+                            denom,
+                            frame.left(),
+                            frame.top(),
+                            buffer.width(),
+                            buffer.height(),
+                        ] {
+                            response.extend_from_slice(&value.to_le_bytes());
+                        }
+                        response.extend_from_slice(buffer.as_raw());
+                    }
+                }
+            }
+        }
+        other => bail!("Unknown decode mode {}.", other),
+    }
+    Ok(())
+}
+
+/// Applies rlimits and a seccomp allowlist to the current process.
+///
+/// Decoders may start threads (`jpeg-decoder` does for every JPEG wider
+/// than 128 pixels), but never processes: `clone` is only allowed with
+/// `CLONE_THREAD`, and `clone3`, whose flags a filter cannot see, fails
+/// with `ENOSYS` so that the C library falls back to `clone`. Any other
+/// syscall outside the allowlist kills the process with `SIGSYS`.
+#[cfg(target_os = "linux")]
+pub fn restrict_process(memory_limit: u64, cpu_limit: u64) -> Result<()> {
+    use seccompiler::{
+        apply_filter, BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition,
+        SeccompFilter, SeccompRule, TargetArch,
+    };
+    use std::collections::BTreeMap;
+
+    // `RLIMIT_NPROC` is left alone: it counts threads too, and the filter
+    // already keeps the worker from starting processes.
+    let limits = [
+        (libc::RLIMIT_AS, memory_limit),
+        (libc::RLIMIT_CPU, cpu_limit),
+        (libc::RLIMIT_FSIZE, 0),
+        (libc::RLIMIT_NOFILE, 0),
+        (libc::RLIMIT_CORE, 0),
+    ];
+    for (resource, value) in limits {
+        let limit = libc::rlimit {
+            rlim_cur: value,
+            rlim_max: value,
+        };
+        // SAFETY: `setrlimit` only reads the provided, fully initialized struct.
+        if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+            bail!("setrlimit failed: {}", std::io::Error::last_os_error());
+        }
+    }
+
+    // Everything a decoder needs once its input is in memory: memory
+    // management, threads, writing the result, and orderly (or aborting)
+    // exit.
+    let allowed = [
+        libc::SYS_read,
+        libc::SYS_write,
+        libc::SYS_close,
+        libc::SYS_mmap,
+        libc::SYS_munmap,
+        libc::SYS_mremap,
+        libc::SYS_mprotect,
+        libc::SYS_madvise,
+        libc::SYS_brk,
+        libc::SYS_futex,
+        libc::SYS_getrandom,
+        libc::SYS_sched_yield,
+        libc::SYS_sched_getaffinity,
+        libc::SYS_clock_gettime,
+        libc::SYS_sigaltstack,
+        libc::SYS_rt_sigaction,
+        libc::SYS_rt_sigprocmask,
+        libc::SYS_rt_sigreturn,
+        libc::SYS_set_robust_list,
+        libc::SYS_rseq,
+        libc::SYS_prctl,
+        libc::SYS_clone3,
+        libc::SYS_getpid,
+        libc::SYS_gettid,
+        libc::SYS_tgkill,
+        libc::SYS_exit,
+        libc::SYS_exit_group,
+    ];
+    let mut rules: BTreeMap<i64, Vec<SeccompRule>> =
+        allowed.into_iter().map(|nr| (nr, Vec::new())).collect();
+    let thread = libc::CLONE_THREAD as u64;
+    let clone_thread = SeccompCondition::new(
+        0,
+        SeccompCmpArgLen::Qword,
+        SeccompCmpOp::MaskedEq(thread),
+        thread,
+    )
+    .and_then(|condition| SeccompRule::new(vec![condition]))
+    .map_err(|e| anyhow!("Failed to build seccomp rule: {}", e))?;
+    rules.insert(libc::SYS_clone, vec![clone_thread]);
+
+    let arch = TargetArch::try_from(std::env::consts::ARCH)
+        .map_err(|e| anyhow!("Unsupported seccomp architecture: {}", e))?;
+    let compile = |rules, mismatch, matched| -> Result<BpfProgram> {
+        let filter = SeccompFilter::new(rules, mismatch, matched, arch)
+            .map_err(|e| anyhow!("Failed to build seccomp filter: {}", e))?;
+        filter
+            .try_into()
+            .map_err(|e| anyhow!("Failed to compile seccomp filter: {}", e))
+    };
+    let allowlist = compile(rules, SeccompAction::KillProcess, SeccompAction::Allow)?;
+    // Filters stack, and the stricter action wins: `clone3` passes the
+    // allowlist and fails here. The allowlist goes last, since it forbids
+    // installing filters.
+    let no_clone3 = compile(
+        BTreeMap::from([(libc::SYS_clone3, Vec::new())]),
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::ENOSYS as u32),
+    )?;
+    for program in [no_clone3, allowlist] {
+        apply_filter(&program).map_err(|e| anyhow!("Failed to install seccomp filter: {}", e))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn restrict_process(_memory_limit: u64, _cpu_limit: u64) -> Result<()> {
+    bail!("Sandboxed decoding is only supported on Linux.")
+}
+
+fn write_image(out: &mut Vec<u8>, image: &DynamicImage) {
+    let tag = match image {
+        DynamicImage::ImageLuma8(_) => 0,
+        DynamicImage::ImageLumaA8(_) => 1,
+        DynamicImage::ImageRgb8(_) => 2,
+        DynamicImage::ImageRgba8(_) => 3,
+        DynamicImage::ImageLuma16(_) => 4,
+        DynamicImage::ImageLumaA16(_) => 5,
+        DynamicImage::ImageRgb16(_) => 6,
+        DynamicImage::ImageRgba16(_) => 7,
+        // Floating-point and future variants are narrowed to RGBA8.
+        _ => return write_image(out, &DynamicImage::ImageRgba8(image.to_rgba8())),
+    };
+    out.push(tag);
+    out.extend_from_slice(&image.width().to_le_bytes());
+    out.extend_from_slice(&image.height().to_le_bytes());
+    out.extend_from_slice(image.as_bytes());
+}
+
+fn read_image(reader: &mut &[u8]) -> Result<DynamicImage> {
+    let tag = read_u8(reader)?;
+    let (width, height) = (read_u32(reader)?, read_u32(reader)?);
+    let malformed = || anyhow!("Sandboxed decoder returned a malformed image.");
+
+    let image = match tag {
+        0 => GrayImage::from_raw(width, height, read_pixels(reader, width, height, 1)?)
+            .map(DynamicImage::ImageLuma8),
+        1 => GrayAlphaImage::from_raw(width, height, read_pixels(reader, width, height, 2)?)
+            .map(DynamicImage::ImageLumaA8),
+        2 => RgbImage::from_raw(width, height, read_pixels(reader, width, height, 3)?)
+            .map(DynamicImage::ImageRgb8),
+        3 => RgbaImage::from_raw(width, height, read_pixels(reader, width, height, 4)?)
+            .map(DynamicImage::ImageRgba8),
+        4 => ImageBuffer::<Luma<u16>, _>::from_raw(
+            width,
+            height,
+            read_u16_pixels(reader, width, height, 1)?,
+        )
+        .map(DynamicImage::ImageLuma16),
+        5 => ImageBuffer::<LumaA<u16>, _>::from_raw(
+            width,
+            height,
+            read_u16_pixels(reader, width, height, 2)?,
+        )
+        .map(DynamicImage::ImageLumaA16),
+        6 => ImageBuffer::<Rgb<u16>, _>::from_raw(
+            width,
+            height,
+            read_u16_pixels(reader, width, height, 3)?,
+        )
+        .map(DynamicImage::ImageRgb16),
+        7 => ImageBuffer::<Rgba<u16>, _>::from_raw(
+            width,
+            height,
+            read_u16_pixels(reader, width, height, 4)?,
+        )
+        .map(DynamicImage::ImageRgba16),
+        _ => None,
+    };
+    image.ok_or_else(malformed)
+}
+
+fn read_u8(reader: &mut &[u8]) -> Result<u8> {
+    let (&value, rest) = reader
+        .split_first()
+        .ok_or_else(|| anyhow!("Sandboxed decoder returned a truncated response."))?;
+    *reader = rest;
+    Ok(value)
+}
+
+fn read_u32(reader: &mut &[u8]) -> Result<u32> {
+    let bytes = take(reader, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u64(reader: &mut &[u8]) -> Result<u64> {
+    let bytes = take(reader, 8)?;
+    Ok(u64::from_le_bytes(bytes.try_into()?))
+}
+
+fn read_pixels(reader: &mut &[u8], width: u32, height: u32, channels: usize) -> Result<Vec<u8>> {
+    let len = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|n| n.checked_mul(channels))
+        .ok_or_else(|| anyhow!("Sandboxed decoder returned oversized dimensions."))?;
+    Ok(take(reader, len)?.to_vec())
+}
+
+fn read_u16_pixels(
+    reader: &mut &[u8],
+    width: u32,
+    height: u32,
+    channels: usize,
+) -> Result<Vec<u16>> {
+    // Worker and parent run on the same machine, so native byte order is shared.
+    let bytes = read_pixels(reader, width, height, channels * 2)?;
+    Ok(bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_ne_bytes([pair[0], pair[1]]))
+        .collect())
+}
+
+fn take<'a>(reader: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if reader.len() < len {
+        bail!("Sandboxed decoder returned a truncated response.");
+    }
+    let (head, rest) = reader.split_at(len);
+    *reader = rest;
+    Ok(head)
+}
@@ -0,0 +1,165 @@
+// image-compressor-rust-service/src/server/admin.rs
+
+//! Administrative endpoints, behind the `[admin]` bearer token.
+//!
+//! `POST /admin/validate-config` checks a candidate configuration before
+//! it is rolled out: the body is a TOML config file, or with
+//! `?kind=presets` the contents of a `[presets]` table, which is checked
+//! against the running configuration. Nothing is applied. The answer is a
+//! report of errors, which would stop the service from starting, and
+//! warnings, for settings that are valid but probably not what was meant
+//! (see [`Config::warnings`]); `200` when there are no errors, `422`
+//! otherwise. Environment overrides are not applied to the candidate.
+
+use super::selftest::authorized;
+use super::{request_id, ApiError, AppState};
+use crate::config::Config;
+use crate::preset::Preset;
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::{info, warn};
+
+/// Settings for the `/admin` endpoints.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Bearer token required to call `/admin` endpoints. They answer `404`
+    /// while no token is configured.
+    pub token: Option<String>,
+}
+
+/// What the body of a validation request holds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CandidateKind {
+    /// A whole config file.
+    #[default]
+    Config,
+    /// Named presets, replacing the running ones.
+    Presets,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ValidateQuery {
+    #[serde(default)]
+    kind: CandidateKind,
+}
+
+/// The outcome of checking a candidate.
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub valid: bool,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Checks a candidate config or preset file without applying it.
+///
+/// Requires `Authorization: Bearer <admin.token>`.
+pub async fn validate_config_handler(
+    State(state): State<AppState>,
+    Query(query): Query<ValidateQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let request_id = request_id(&headers).to_owned();
+    let Some(token) = state.config.admin.token.as_deref() else {
+        return Err(
+            ApiError::new(StatusCode::NOT_FOUND, "not_found", "Not found.")
+                .with_request_id(request_id),
+        );
+    };
+    if !authorized(&headers, token) {
+        warn!(
+            request_id,
+            "Rejected /admin call with a missing or wrong token."
+        );
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "A valid bearer token is required.",
+        )
+        .with_request_id(request_id));
+    }
+    let Ok(text) = std::str::from_utf8(&body) else {
+        return Err(
+            ApiError::bad_request("The candidate must be UTF-8 TOML.").with_request_id(request_id)
+        );
+    };
+
+    let report = match query.kind {
+        CandidateKind::Config => check_config(text),
+        CandidateKind::Presets => check_presets(text, &state.config),
+    };
+    info!(
+        request_id,
+        kind = ?query.kind,
+        errors = report.errors.len(),
+        warnings = report.warnings.len(),
+        "Validated a candidate configuration."
+    );
+    let status = if report.valid {
+        StatusCode::OK
+    } else {
+        StatusCode::UNPROCESSABLE_ENTITY
+    };
+    Ok((status, Json(report)).into_response())
+}
+
+/// Checks a whole config file, as [`Config::load`] would.
+pub fn check_config(text: &str) -> Report {
+    match toml::from_str::<Config>(text) {
+        Ok(candidate) => report(&candidate, candidate.warnings()),
+        Err(e) => invalid(format!("Failed to parse the config: {}", e)),
+    }
+}
+
+/// Checks the contents of a `[presets]` table against the running config,
+/// as if they replaced its presets.
+pub fn check_presets(text: &str, running: &Config) -> Report {
+    match toml::from_str::<BTreeMap<String, Preset>>(text) {
+        Ok(presets) => {
+            let candidate = Config {
+                presets,
+                ..running.clone()
+            };
+            let warnings = candidate
+                .presets
+                .iter()
+                .flat_map(|(name, preset)| candidate.preset_warnings(name, preset))
+                .collect();
+            report(&candidate, warnings)
+        }
+        Err(e) => invalid(format!("Failed to parse the presets: {}", e)),
+    }
+}
+
+fn report(candidate: &Config, warnings: Vec<String>) -> Report {
+    match candidate.validate() {
+        Ok(()) => Report {
+            valid: true,
+            errors: Vec::new(),
+            warnings,
+        },
+        Err(e) => Report {
+            valid: false,
+            errors: vec![format!("{:#}", e)],
+            warnings,
+        },
+    }
+}
+
+fn invalid(error: String) -> Report {
+    Report {
+        valid: false,
+        errors: vec![error],
+        warnings: Vec::new(),
+    }
+}
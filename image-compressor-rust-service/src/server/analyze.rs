@@ -0,0 +1,78 @@
+// image-compressor-rust-service/src/server/analyze.rs
+
+use super::compress::check_input_format;
+use super::{decode_still, request_id, ApiError, AppState};
+use crate::analyze;
+use crate::cpu::Priority;
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+/// Returns per-channel histograms, mean luminance and clipping of the image
+/// in the body as JSON (see [`analyze::exposure`]).
+///
+/// The image is checked and decoded like a `/compress` input.
+pub async fn histogram_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let request_id = request_id(&headers).to_owned();
+    if body.is_empty() {
+        return Err(
+            ApiError::bad_request("Request body cannot be empty.").with_request_id(request_id)
+        );
+    }
+    check_input_format(&body, &state.config.allowed_input_formats)
+        .map_err(|e| e.with_request_id(&request_id))?;
+
+    metrics::increment_counter!("analyze_requests_total");
+    let config = state.config.clone();
+    let threads = state.cpu.acquire(Priority::default()).await;
+    let stats = tokio::task::spawn_blocking(move || {
+        let _threads = threads;
+        decode_still(&body, &config).map(|image| analyze::exposure(&image))
+    })
+    .await
+    .map_err(|e| {
+        ApiError::internal(format!("Analysis task failed: {}", e)).with_request_id(&request_id)
+    })?
+    .map_err(|e| e.with_request_id(&request_id))?;
+
+    Ok((StatusCode::OK, Json(stats)).into_response())
+}
+
+/// Returns the format, dimensions, size and, for JPEGs, the estimated
+/// quality of the image in the body as JSON (see [`analyze::inspect`]),
+/// with whether its `Content-Type` matched the detected format.
+///
+/// Only the image header and JPEG tables are read, so this does not wait
+/// for the CPU budget.
+pub async fn inspect_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let request_id = request_id(&headers).to_owned();
+    if body.is_empty() {
+        return Err(
+            ApiError::bad_request("Request body cannot be empty.").with_request_id(request_id)
+        );
+    }
+    let format = check_input_format(&body, &state.config.allowed_input_formats)
+        .map_err(|e| e.with_request_id(&request_id))?;
+
+    metrics::increment_counter!("inspect_requests_total");
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    let inspection = analyze::inspect(&body, format, content_type).map_err(|e| {
+        ApiError::unprocessable(format!("Failed to inspect image: {:#}", e))
+            .with_request_id(&request_id)
+    })?;
+    Ok((StatusCode::OK, Json(inspection)).into_response())
+}
@@ -0,0 +1,299 @@
+// image-compressor-rust-service/src/server/audit.rs
+
+//! Audit log of `/compress` requests.
+//!
+//! Every request, including rejected ones, produces one [`AuditRecord`]:
+//! who sent it, with which options, SHA-256 hashes and sizes of the input
+//! and output, and how it ended. Records are appended as JSON lines to a
+//! file, POSTed as JSON to a webhook, or both. Image contents are never
+//! recorded.
+
+use super::idempotency::StoredResponse;
+use crate::cpu::Priority;
+use crate::filename;
+use crate::formats::InputFormat;
+use crate::CompressionOptions;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, warn};
+
+/// Where audit records go. Auditing is off unless `path` or `webhook_url`
+/// is set.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// Append one JSON line per request to this file. It is reopened for
+    /// every record, so it can be rotated by renaming.
+    pub path: Option<PathBuf>,
+    /// POST each record as JSON to this URL.
+    pub webhook_url: Option<String>,
+    /// Timeout of a webhook call, in milliseconds.
+    pub webhook_timeout_ms: u64,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            webhook_url: None,
+            webhook_timeout_ms: 5_000,
+        }
+    }
+}
+
+impl AuditConfig {
+    pub fn enabled(&self) -> bool {
+        self.path.is_some() || self.webhook_url.is_some()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(url) = &self.webhook_url {
+            reqwest::Url::parse(url).context("Invalid webhook_url")?;
+        }
+        Ok(())
+    }
+}
+
+/// What the handler learned about a request while serving it.
+#[derive(Debug, Default)]
+pub struct AuditDraft {
+    pub input_format: Option<InputFormat>,
+    pub options: Option<CompressionOptions>,
+    pub priority: Option<Priority>,
+    pub output: Option<StoredResponse>,
+    /// The output was replayed for an `Idempotency-Key`.
+    pub replayed: bool,
+}
+
+/// How a request ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    /// Compressed.
+    Success,
+    /// Served from the idempotency cache.
+    Replayed,
+    /// Refused before compressing, e.g. an unsupported format.
+    Rejected,
+    /// The image could not be compressed.
+    Failed,
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    /// UTC time the request finished, `YYYY-MM-DDTHH:MM:SS.mmmZ`.
+    pub timestamp: String,
+    pub request_id: String,
+    /// The caller's `X-Api-Key`.
+    pub tenant: Option<String>,
+    /// The sanitized `X-Filename` of the input.
+    pub filename: Option<String>,
+    pub outcome: Outcome,
+    pub status: u16,
+    /// The `code` of the error response, if any.
+    pub error_code: Option<&'static str>,
+    pub input_format: Option<&'static str>,
+    pub input_bytes: usize,
+    pub input_sha256: String,
+    pub output_content_type: Option<&'static str>,
+    pub output_bytes: Option<usize>,
+    pub output_sha256: Option<String>,
+    /// The options applied, once they were known.
+    pub options: Option<AuditOptions>,
+    pub duration_ms: u64,
+}
+
+/// The compression options of a request, as recorded.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditOptions {
+    pub quality: u8,
+    pub quality_explicit: bool,
+    pub quality_scale: String,
+    pub animation: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: String,
+    pub keep_exif: Option<bool>,
+    pub deterministic: bool,
+    pub priority: Option<&'static str>,
+}
+
+impl AuditOptions {
+    pub(super) fn new(options: &CompressionOptions, priority: Option<Priority>) -> Self {
+        Self {
+            quality: options.quality,
+            quality_explicit: options.quality_explicit,
+            quality_scale: format!("{:?}", options.quality_scale).to_lowercase(),
+            animation: format!("{:?}", options.animation).to_lowercase(),
+            width: options.width,
+            height: options.height,
+            fit: format!("{:?}", options.fit).to_lowercase(),
+            keep_exif: options.metadata.map(|policy| policy.keep_exif),
+            deterministic: options.deterministic,
+            priority: priority.map(Priority::name),
+        }
+    }
+}
+
+/// A finished request, ready to be recorded.
+pub struct Finished<'a> {
+    pub request_id: &'a str,
+    pub tenant: Option<&'a str>,
+    /// The caller's `X-Filename`, unsanitized.
+    pub filename: Option<String>,
+    pub input: &'a [u8],
+    pub draft: AuditDraft,
+    pub status: u16,
+    pub error_code: Option<&'static str>,
+    pub duration: Duration,
+}
+
+/// Writes [`AuditRecord`]s to the configured sinks.
+pub struct AuditLog {
+    path: Option<PathBuf>,
+    /// Serializes appends so lines never interleave.
+    file: Mutex<()>,
+    webhook: Option<(reqwest::Client, String)>,
+}
+
+impl AuditLog {
+    pub fn new(config: &AuditConfig) -> Self {
+        let webhook = config.webhook_url.clone().map(|url| {
+            let client = reqwest::Client::builder()
+                .timeout(Duration::from_millis(config.webhook_timeout_ms))
+                .build()
+                .expect("Failed to build the audit webhook client");
+            (client, url)
+        });
+        Self {
+            path: config.path.clone(),
+            file: Mutex::new(()),
+            webhook,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.path.is_some() || self.webhook.is_some()
+    }
+
+    /// Records a finished request, built with [`build_record`]. Webhook
+    /// calls are made in the background; failures of either sink are logged
+    /// and counted, not returned.
+    pub fn record(&self, record: &AuditRecord) {
+        if !self.enabled() {
+            return;
+        }
+
+        if let Some(path) = &self.path {
+            let _guard = self.file.lock().unwrap_or_else(|e| e.into_inner());
+            let written = serde_json::to_vec(&record)
+                .map_err(anyhow::Error::from)
+                .and_then(|mut line| {
+                    line.push(b'\n');
+                    OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)?
+                        .write_all(&line)?;
+                    Ok(())
+                });
+            if let Err(e) = written {
+                metrics::increment_counter!("audit_write_failures_total", "sink" => "file");
+                error!(
+                    request_id = record.request_id,
+                    "Failed to append to the audit log {}: {:#}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+
+        if let Some((client, url)) = &self.webhook {
+            let request = client.post(url).json(record);
+            let request_id = record.request_id.clone();
+            tokio::spawn(async move {
+                let result = request.send().await.and_then(|r| r.error_for_status());
+                if let Err(e) = result {
+                    metrics::increment_counter!("audit_write_failures_total", "sink" => "webhook");
+                    let e = crate::logging::redaction().request_error(e);
+                    warn!(request_id, "Failed to send the audit record: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// The audit record of a finished request.
+pub(super) fn build_record(finished: Finished<'_>) -> AuditRecord {
+    let draft = finished.draft;
+    let outcome = match finished.status {
+        200..=399 if draft.replayed => Outcome::Replayed,
+        200..=399 => Outcome::Success,
+        500.. => Outcome::Failed,
+        _ if finished.error_code == Some("unprocessable_image") => Outcome::Failed,
+        _ => Outcome::Rejected,
+    };
+    AuditRecord {
+        timestamp: timestamp(SystemTime::now()),
+        request_id: finished.request_id.to_string(),
+        tenant: finished.tenant.map(str::to_string),
+        filename: finished.filename.as_deref().and_then(filename::sanitize),
+        outcome,
+        status: finished.status,
+        error_code: finished.error_code,
+        input_format: draft.input_format.map(InputFormat::name),
+        input_bytes: finished.input.len(),
+        input_sha256: sha256(finished.input),
+        output_content_type: draft.output.as_ref().map(|o| o.content_type),
+        output_bytes: draft.output.as_ref().map(|o| o.data.len()),
+        output_sha256: draft.output.as_ref().map(|o| sha256(&o.data)),
+        options: draft
+            .options
+            .as_ref()
+            .map(|options| AuditOptions::new(options, draft.priority)),
+        duration_ms: finished.duration.as_millis() as u64,
+    }
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Formats `time` as `YYYY-MM-DDTHH:MM:SS.mmmZ`.
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let epoch = since_epoch.as_secs();
+    let (days, secs) = (epoch / 86_400, epoch % 86_400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Year, month and day of the date `days` after the Unix epoch, after
+/// Howard Hinnant's date algorithms.
+pub(crate) fn civil_from_days(days: u64) -> (i64, i64, i64) {
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
@@ -0,0 +1,106 @@
+// image-compressor-rust-service/src/server/coalesce.rs
+
+//! Coalescing of identical concurrent `/compress` requests.
+//!
+//! Behind a CDN, a cache miss on a popular image sends many identical
+//! requests at once. The first to arrive becomes the leader of a flight and
+//! encodes; the others, keyed by the same fingerprint of body and options,
+//! wait for it without taking a thread of the CPU budget and get a copy of
+//! its result, errors included.
+//!
+//! A leader that goes away before landing (its client disconnected) hands
+//! nothing over: its followers start over, and one of them leads the next
+//! flight.
+
+use super::ApiError;
+use crate::CompressedImage;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// The result a flight lands with, shared with every follower.
+pub type Landed = Result<CompressedImage, ApiError>;
+
+/// Outcome of [`Coalescer::join`].
+pub enum Join {
+    /// No identical request is in flight: encode, then call
+    /// [`Flight::land`].
+    Leader(Flight),
+    /// An identical request is already encoding; wait for it with
+    /// [`Landing::wait`].
+    Follower(Landing),
+}
+
+/// The flights in progress, keyed by request fingerprint.
+#[derive(Default)]
+pub struct Coalescer {
+    flights: Mutex<HashMap<[u8; 32], watch::Receiver<Option<Landed>>>>,
+}
+
+impl Coalescer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Joins the flight for `fingerprint`, starting it if there is none.
+    pub fn join(self: &Arc<Self>, fingerprint: [u8; 32]) -> Join {
+        let mut flights = self.flights.lock().unwrap();
+        if let Some(receiver) = flights.get(&fingerprint) {
+            return Join::Follower(Landing(receiver.clone()));
+        }
+        let (sender, receiver) = watch::channel(None);
+        flights.insert(fingerprint, receiver);
+        Join::Leader(Flight {
+            coalescer: Arc::clone(self),
+            fingerprint,
+            sender: Some(sender),
+        })
+    }
+
+    fn remove(&self, fingerprint: &[u8; 32]) {
+        if let Ok(mut flights) = self.flights.lock() {
+            flights.remove(fingerprint);
+        }
+    }
+}
+
+/// A flight led by the caller. Dropping it without calling
+/// [`land`](Self::land) sends its followers back to [`Coalescer::join`].
+pub struct Flight {
+    coalescer: Arc<Coalescer>,
+    fingerprint: [u8; 32],
+    sender: Option<watch::Sender<Option<Landed>>>,
+}
+
+impl Flight {
+    /// Hands `landed` to the followers. Requests arriving from now on start
+    /// a new flight.
+    pub fn land(mut self, landed: &Landed) {
+        self.coalescer.remove(&self.fingerprint);
+        if let Some(sender) = self.sender.take() {
+            // Nobody listening is fine: there were no followers.
+            let _ = sender.send(Some(landed.clone()));
+        }
+    }
+}
+
+impl Drop for Flight {
+    fn drop(&mut self) {
+        if self.sender.is_some() {
+            self.coalescer.remove(&self.fingerprint);
+        }
+    }
+}
+
+/// A flight the caller follows.
+pub struct Landing(watch::Receiver<Option<Landed>>);
+
+impl Landing {
+    /// The leader's result, or `None` if the leader went away without one.
+    pub async fn wait(mut self) -> Option<Landed> {
+        match self.0.wait_for(Option::is_some).await {
+            Ok(landed) => landed.clone(),
+            Err(_) => None,
+        }
+    }
+}
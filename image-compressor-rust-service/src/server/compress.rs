@@ -0,0 +1,730 @@
+// image-compressor-rust-service/src/server/compress.rs
+
+use super::audit::{build_record, AuditDraft, AuditOptions, Finished};
+use super::coalesce::Join;
+use super::error_reporting::{self, ErrorContext};
+use super::hooks::BeforeRequest;
+use super::idempotency::{
+    Begin, IdempotencyCache, StoredResponse, IDEMPOTENCY_KEY_HEADER, MAX_KEY_LEN, REPLAYED_HEADER,
+};
+use super::limits::api_key;
+use super::options::{Options, OptionsRejection};
+use super::peers::Load;
+use super::scan::Verdict;
+use super::skip::{SkipPolicy, SkipReason};
+use super::slow_log::RequestSummary;
+use super::{request_id, ApiError, AppState};
+use crate::cpu::Priority;
+use crate::filename::{self, FilenameVars};
+use crate::formats::{InputFormat, SniffedFormat};
+use crate::logging::redaction;
+use crate::quality::{self, AboveSource};
+use crate::timing::Timings;
+use crate::transform::{self, Scaling};
+use crate::{compress_image_on, AnimationMode, CompressedImage, CompressionOptions};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+use std::time::Instant;
+use tokio::task::JoinError;
+use tracing::{error, info, warn, Span};
+
+/// Request header choosing the scheduling class: `high`, `normal` or `low`.
+pub const PRIORITY_HEADER: &str = "X-Priority";
+
+/// Request header with the caller's original filename, percent-encoded if
+/// it is not ASCII.
+pub const FILENAME_HEADER: &str = "X-Filename";
+
+/// Request header asking for `Content-Disposition: attachment`.
+pub const DOWNLOAD_HEADER: &str = "X-Download";
+
+/// Request header with the output filename template, e.g.
+/// `{name}-{width}x{height}.{ext}`.
+pub const FILENAME_TEMPLATE_HEADER: &str = "X-Filename-Template";
+
+/// Response header carrying per-stage durations.
+pub const SERVER_TIMING: &str = "server-timing";
+
+/// Response header carrying [`crate::DETERMINISTIC_OUTPUT_VERSION`].
+pub const OUTPUT_VERSION_HEADER: &str = "X-Output-Version";
+
+/// Response header saying whether the image was resized: `none`, `down`
+/// or `up`.
+pub const SCALING_HEADER: &str = "X-Scaling";
+
+/// Response header set when requested dimensions were not reached because
+/// upscaling was not allowed.
+pub const UPSCALE_PREVENTED_HEADER: &str = "X-Upscale-Prevented";
+
+/// Response header carrying the placeholder requested with `X-Placeholder`,
+/// as a `data:` URI.
+pub const PLACEHOLDER_DATA_HEADER: &str = "X-Placeholder-Data";
+
+/// Response header carrying the estimated quality of a JPEG input.
+pub const SOURCE_QUALITY_HEADER: &str = "X-Source-Quality";
+
+/// Response header set when the requested quality is above that of the
+/// JPEG input.
+pub const QUALITY_WARNING_HEADER: &str = "X-Quality-Warning";
+
+/// Response header set when only what could be recovered of a truncated or
+/// corrupt input was compressed, with `X-Salvage: true`.
+pub const SALVAGED_HEADER: &str = "X-Salvaged";
+
+/// Response header with the format detected in the input and whether its
+/// `Content-Type` named it, e.g. `png; matches-content-type=false` (see
+/// [`SniffedFormat::header_value`]). Sent on every response to a request
+/// whose image was read, errors included. Requests rejected for their
+/// options never get this far: they are rejected before the body is read
+/// (see [`super::options`]).
+pub const INPUT_FORMAT_HEADER: &str = "X-Input-Format";
+
+/// Handles image compression requests.
+///
+/// The body is the image and its options are headers or query parameters
+/// (see [`super::options`]), unless it is a JSON document carrying both
+/// (see [`super::json_body`]). A request goes through, in order:
+///
+/// 1. format detection against the allowlist, then the virus scan
+///    ([`super::scan`]);
+/// 2. the `before` hook ([`super::hooks`]) and the check of the quality
+///    against that of a JPEG input ([`crate::quality`]);
+/// 3. the skip conditions ([`super::skip`]);
+/// 4. the `Idempotency-Key` lookup ([`super::idempotency`]), then
+///    forwarding to a less loaded peer ([`super::peers`]);
+/// 5. a thread of the CPU budget, by `X-Priority` ([`crate::cpu`]),
+///    unless an identical request is already encoding ([`super::coalesce`]);
+/// 6. decoding, transforming and encoding on the blocking thread pool
+///    ([`crate::compress_image_on`]), where a panic is a `500`;
+/// 7. the response, then its audit record and `after` hook
+///    ([`super::audit`]), slow request warnings ([`super::slow_log`]) and
+///    error reports ([`super::error_reporting`]).
+///
+/// Every stage is timed (see [`crate::timing`]).
+pub async fn compress_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    options: Result<Options, OptionsRejection>,
+) -> Response {
+    let request_id = request_id(&headers).to_owned();
+    let start_time = Instant::now();
+    let mut draft = AuditDraft::default();
+    let mut sniffed = None;
+    let (headers, input, result) = match options {
+        Ok(Options {
+            headers,
+            options,
+            input,
+            json,
+            timings,
+        }) => {
+            let result = compress(
+                state.clone(),
+                &headers,
+                input.clone(),
+                options,
+                timings,
+                &request_id,
+                &mut draft,
+            )
+            .await;
+            let result = match (result, &json) {
+                (Ok(response), Some(json)) => Ok(json.respond(response).await),
+                (result, _) => result,
+            };
+            if !input.is_empty() {
+                let content_type = headers
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok());
+                sniffed = Some(SniffedFormat::of(&input, content_type));
+            }
+            (headers, input, result)
+        }
+        Err(rejection) => (headers, rejection.body, Err(rejection.error)),
+    };
+
+    let (status, error_code) = match &result {
+        Ok(response) => (response.status(), None),
+        Err(e) => (e.status, Some(e.code)),
+    };
+    if state.audit.enabled() || state.hooks.after_enabled() {
+        let record = build_record(Finished {
+            request_id: &request_id,
+            tenant: api_key(&headers),
+            filename: headers
+                .get(FILENAME_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .map(filename::percent_decode),
+            input: &input,
+            draft,
+            status: status.as_u16(),
+            error_code,
+            duration: start_time.elapsed(),
+        });
+        state.audit.record(&record);
+        state.hooks.after(&record);
+    }
+    let mut response = result.unwrap_or_else(|e| e.with_request_id(request_id).into_response());
+    if let Some(value) = sniffed.and_then(|s| HeaderValue::from_str(&s.header_value()).ok()) {
+        response.headers_mut().insert(INPUT_FORMAT_HEADER, value);
+    }
+    response
+}
+
+async fn compress(
+    state: AppState,
+    headers: &HeaderMap,
+    body: Bytes,
+    mut options: CompressionOptions,
+    mut timings: Timings,
+    request_id: &str,
+    draft: &mut AuditDraft,
+) -> Result<Response, ApiError> {
+    let start_time = Instant::now();
+    info!(
+        "Received compression request. Body size: {} bytes",
+        body.len()
+    );
+
+    if body.is_empty() {
+        warn!("Request body is empty.");
+        return Err(ApiError::bad_request("Request body cannot be empty."));
+    }
+
+    let input_format = check_input_format(&body, &state.config.allowed_input_formats)?;
+    draft.input_format = Some(input_format);
+    scan_input(&state, &body, &mut timings).await?;
+
+    options.metadata = Some(*state.config.metadata.policy_for(api_key(headers)));
+    options.deterministic |= state.config.deterministic;
+    let before = BeforeRequest {
+        request_id,
+        tenant: api_key(headers),
+        input_format: input_format.name(),
+        input_bytes: body.len(),
+        options: AuditOptions::new(&options, None),
+    };
+    state
+        .hooks
+        .before(before, &mut options, &state.config, &mut timings)
+        .await?;
+    let quality_headers = quality_headers(&body, &options, &state)?;
+    draft.options = Some(options.clone());
+    let deterministic = options.deterministic;
+
+    info!(
+        "Using compression quality: {}, animation mode: {:?}, input format: {}, resize: {:?}x{:?} ({:?})",
+        options.quality, options.animation, input_format, options.width, options.height, options.fit
+    );
+
+    let skip = SkipPolicy::from_headers(headers);
+    if skip.is_small_enough(&body, &options, state.config.max_dimensions()) {
+        info!("Input is already small enough; skipping compression.");
+        return Ok(skip.response(SkipReason::SmallEnough, body, input_format));
+    }
+
+    let key = idempotency_key(headers, &state)?;
+    let fingerprint = (key.is_some() || state.config.coalesce).then(|| {
+        let options = json!({ "options": options, "skip": skip });
+        IdempotencyCache::fingerprint(&body, &options)
+    });
+    let tenant = api_key(headers);
+    let reservation = match key.zip(fingerprint) {
+        Some((key, fingerprint)) => match state.idempotency.begin(tenant, key, fingerprint) {
+            Begin::New(reservation) => Some(reservation),
+            Begin::Replay(stored) => {
+                info!("Replaying stored result for idempotency key.");
+                metrics::increment_counter!("compress_idempotent_replays_total");
+                draft.output = Some(stored.clone());
+                draft.replayed = true;
+                let disposition = content_disposition(headers, &stored, &state);
+                let scaling = scaling_headers(&stored, &options, &state);
+                let mut response = compressed_response(stored, true, deterministic);
+                response.headers_mut().extend(scaling);
+                response.headers_mut().extend(quality_headers);
+                insert_disposition(&mut response, disposition);
+                return Ok(with_server_timing(response, &timings, &state));
+            }
+            Begin::Mismatch => {
+                return Err(ApiError::new(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "idempotency_key_reused",
+                    "Idempotency-Key was already used for a different request.",
+                ))
+            }
+            Begin::InProgress => {
+                return Err(ApiError::new(
+                    StatusCode::CONFLICT,
+                    "idempotency_key_in_progress",
+                    "A request with this Idempotency-Key is still being processed.",
+                ))
+            }
+        },
+        None => None,
+    };
+
+    // After the lookup, so that a retry is replayed or rejected here
+    // whichever replica would take it. A peer's result is not stored:
+    // dropping the reservation releases the key once it has answered.
+    if let Some(response) = forward_to_peer(&state, headers, &body).await {
+        return Ok(response);
+    }
+
+    let input_len = body.len();
+    let error_context = ErrorContext {
+        request_id,
+        input_format,
+        input_bytes: input_len,
+    };
+    let priority = state.config.cpu.priorities.priority_for(
+        api_key(headers),
+        headers
+            .get(PRIORITY_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(Priority::parse),
+    );
+    draft.priority = Some(priority);
+    let input = body.clone();
+    let work = Encode {
+        body,
+        options: &options,
+        priority,
+        error_context: &error_context,
+    };
+    let mut compressed = match fingerprint.filter(|_| state.config.coalesce) {
+        Some(fingerprint) => coalesced(&state, fingerprint, work, &mut timings).await?,
+        None => encode(&state, work, &mut timings).await?,
+    };
+
+    let duration = start_time.elapsed();
+    timings.extend(std::mem::take(&mut compressed.timings));
+    info!(
+        stages = %timings,
+        "Compression successful in {:.2?}. Original size: {}, Compressed size: {}",
+        duration,
+        input_len,
+        compressed.data.len()
+    );
+
+    if skip.returns_input(&input, &compressed, &options, &state.config) {
+        // Dropping the reservation releases the key: the input is not a
+        // result to replay.
+        info!("Compressed output is not smaller than the input; returning the input.");
+        let mut response = skip.response(SkipReason::NotSmaller, input, input_format);
+        response.headers_mut().extend(quality_headers);
+        return Ok(with_server_timing(response, &timings, &state));
+    }
+
+    let stored = StoredResponse {
+        data: compressed.data.into(),
+        content_type: compressed.content_type,
+        format: compressed.format,
+        dimensions: compressed.dimensions,
+        source_dimensions: compressed.source_dimensions,
+        placeholder: compressed.placeholder,
+        salvaged: compressed.salvaged,
+    };
+    draft.output = Some(stored.clone());
+    if let Some(reservation) = reservation {
+        reservation.complete(stored.clone());
+    }
+    state.config.slow_log.check(&RequestSummary {
+        request_id,
+        options: &options,
+        priority,
+        input_format,
+        input_bytes: input_len,
+        output_bytes: stored.data.len(),
+        decoded_pixels: compressed.decoded_pixels,
+        duration,
+        timings: &timings,
+    });
+    let disposition = content_disposition(headers, &stored, &state);
+    let scaling = scaling_headers(&stored, &options, &state);
+    let mut response = compressed_response(stored, false, deterministic);
+    response.headers_mut().extend(scaling);
+    response.headers_mut().extend(quality_headers);
+    insert_disposition(&mut response, disposition);
+    Ok(with_server_timing(response, &timings, &state))
+}
+
+/// The compression a request asks for.
+struct Encode<'a> {
+    body: Bytes,
+    options: &'a CompressionOptions,
+    priority: Priority,
+    error_context: &'a ErrorContext<'a>,
+}
+
+/// Hands the request to a less loaded replica, when sharing work with
+/// peers says so (see [`super::peers`]). A failed forward is logged and the
+/// request compressed here.
+async fn forward_to_peer(state: &AppState, headers: &HeaderMap, body: &Bytes) -> Option<Response> {
+    let peer = state.peers.pick(headers, body, Load::of(&state.cpu))?;
+    match state.peers.forward(&peer, headers, body.clone()).await {
+        Ok(response) => {
+            info!(
+                peer = %redaction().url(&peer),
+                "Forwarded the request to a less loaded peer."
+            );
+            metrics::increment_counter!("compress_forwarded_total");
+            Some(response)
+        }
+        Err(e) => {
+            warn!(
+                peer = %redaction().url(&peer),
+                "Forwarding failed; compressing locally: {:#}",
+                e
+            );
+            metrics::increment_counter!("compress_forward_failures_total");
+            None
+        }
+    }
+}
+
+/// Waits for the CPU budget, and for a slot of the animated conversion
+/// limit when converting an animation, and compresses on the blocking
+/// thread pool, logging and reporting failures.
+async fn encode(
+    state: &AppState,
+    work: Encode<'_>,
+    timings: &mut Timings,
+) -> Result<CompressedImage, ApiError> {
+    let Encode {
+        body,
+        options,
+        priority,
+        error_context,
+    } = work;
+    let config = state.config.clone();
+    let queued = Instant::now();
+    let _slot = match state.concurrency.animated() {
+        Some(limiter) if options.animation == AnimationMode::Animate => Some(
+            limiter
+                .enter()
+                .await
+                .map_err(|e| e.with_request_id(error_context.request_id))?,
+        ),
+        _ => None,
+    };
+    let mut threads = state.cpu.acquire(priority).await;
+    timings.push("queue", queued.elapsed());
+    metrics::histogram!(
+        "compress_queue_seconds",
+        queued.elapsed().as_secs_f64(),
+        "priority" => priority.name()
+    );
+    // The stage spans of the pipeline nest under the request span.
+    let span = Span::current();
+    let task_options = options.clone();
+    let task = tokio::task::spawn_blocking(move || {
+        span.in_scope(|| compress_image_on(&body, &task_options, &config, &mut threads))
+    });
+
+    let result = match task.await {
+        Ok(result) => result,
+        Err(join_error) => return Err(task_failure(join_error, error_context)),
+    };
+    result.map_err(|e| {
+        error!("Image compression failed: {:?}", e);
+        error_reporting::report(
+            "compression_failed",
+            &format!("Image compression failed: {:#}", e),
+            error_context,
+        );
+        ApiError::unprocessable_input(format!("Failed to compress image: {}", e), &e)
+    })
+}
+
+/// [`encode`], unless an identical request is already encoding, in which
+/// case its result is shared (see [`super::coalesce`]).
+async fn coalesced(
+    state: &AppState,
+    fingerprint: [u8; 32],
+    work: Encode<'_>,
+    timings: &mut Timings,
+) -> Result<CompressedImage, ApiError> {
+    loop {
+        match state.coalescer.join(fingerprint) {
+            Join::Leader(flight) => {
+                let landed = encode(state, work, timings).await;
+                flight.land(&landed);
+                return landed;
+            }
+            Join::Follower(landing) => {
+                let waited = Instant::now();
+                let landed = landing.wait().await;
+                timings.push("coalesce", waited.elapsed());
+                if let Some(landed) = landed {
+                    info!("Shared the result of an identical request in flight.");
+                    metrics::increment_counter!("compress_coalesced_total");
+                    return landed;
+                }
+                // The leader went away; lead the next flight or follow it.
+            }
+        }
+    }
+}
+
+fn compressed_response(stored: StoredResponse, replayed: bool, deterministic: bool) -> Response {
+    let mut response = (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, stored.content_type)],
+        stored.data,
+    )
+        .into_response();
+    if let Some(value) = stored
+        .placeholder
+        .as_deref()
+        .and_then(|p| HeaderValue::from_str(p).ok())
+    {
+        response
+            .headers_mut()
+            .insert(PLACEHOLDER_DATA_HEADER, value);
+    }
+    if replayed {
+        response
+            .headers_mut()
+            .insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+    }
+    if stored.salvaged {
+        response
+            .headers_mut()
+            .insert(SALVAGED_HEADER, HeaderValue::from_static("true"));
+    }
+    if deterministic {
+        response.headers_mut().insert(
+            OUTPUT_VERSION_HEADER,
+            HeaderValue::from(crate::DETERMINISTIC_OUTPUT_VERSION),
+        );
+    }
+    response
+}
+
+/// The `X-Scaling` and `X-Upscale-Prevented` headers of a result.
+fn scaling_headers(
+    stored: &StoredResponse,
+    options: &CompressionOptions,
+    state: &AppState,
+) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    // Sizes apply to the rotated image.
+    let source = transform::rotated_dimensions(stored.source_dimensions, options);
+    let scaling = Scaling::between(source, stored.dimensions);
+    headers.insert(SCALING_HEADER, HeaderValue::from_static(scaling.name()));
+    if transform::upscale_prevented(source, options, state.config.max_dimensions()) {
+        headers.insert(UPSCALE_PREVENTED_HEADER, HeaderValue::from_static("true"));
+    }
+    headers
+}
+
+/// The `X-Source-Quality` and `X-Quality-Warning` headers of a request,
+/// or its rejection when it asks for more quality than its JPEG input has
+/// and the policy refuses that.
+fn quality_headers(
+    body: &[u8],
+    options: &CompressionOptions,
+    state: &AppState,
+) -> Result<HeaderMap, ApiError> {
+    let mut headers = HeaderMap::new();
+    let Some(source) = quality::estimate_jpeg_quality(body) else {
+        return Ok(headers);
+    };
+    headers.insert(SOURCE_QUALITY_HEADER, HeaderValue::from(u16::from(source)));
+    let config = &state.config.quality;
+    let Some(requested) = config.exceeds_source(source, options) else {
+        return Ok(headers);
+    };
+    let message = format!(
+        "Quality {} is above the input's estimated quality of {}; re-encoding above it only makes the file larger.",
+        requested, source
+    );
+    match config.above_source {
+        AboveSource::Allow => {}
+        AboveSource::Warn => {
+            warn!("{}", message);
+            metrics::increment_counter!("compress_above_source_quality_total");
+            if let Ok(value) = HeaderValue::from_str(&message) {
+                headers.insert(QUALITY_WARNING_HEADER, value);
+            }
+        }
+        AboveSource::Refuse => {
+            metrics::increment_counter!("compress_above_source_quality_total");
+            return Err(ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "quality_above_source",
+                message,
+            ));
+        }
+    }
+    Ok(headers)
+}
+
+/// The `Content-Disposition` requested by the caller, if any.
+///
+/// `X-Download: true` makes the response an attachment and an
+/// `X-Filename-Template` names it; either sends the header, with the
+/// filename rendered from the template (or the configured one), the
+/// original `X-Filename` and the output dimensions and format.
+fn content_disposition(
+    headers: &HeaderMap,
+    stored: &StoredResponse,
+    state: &AppState,
+) -> Option<String> {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    let attachment = header(DOWNLOAD_HEADER)
+        .and_then(|v| v.trim().parse::<bool>().ok())
+        .unwrap_or(false);
+    let template = header(FILENAME_TEMPLATE_HEADER).map(filename::percent_decode);
+    if !attachment && template.is_none() {
+        return None;
+    }
+    let original = header(FILENAME_HEADER).map(filename::percent_decode);
+    let (width, height) = stored.dimensions;
+    let name = state.config.filename.render(
+        template.as_deref(),
+        &FilenameVars {
+            original: original.as_deref(),
+            width,
+            height,
+            format: stored.format,
+        },
+    );
+    Some(filename::content_disposition(attachment, &name))
+}
+
+fn insert_disposition(response: &mut Response, disposition: Option<String>) {
+    if let Some(value) = disposition.and_then(|d| HeaderValue::from_str(&d).ok()) {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_DISPOSITION, value);
+    }
+}
+
+/// Adds a `Server-Timing` header, if enabled.
+fn with_server_timing(mut response: Response, timings: &Timings, state: &AppState) -> Response {
+    if state.config.server_timing {
+        if let Ok(value) = HeaderValue::from_str(&timings.server_timing()) {
+            response.headers_mut().insert(SERVER_TIMING, value);
+        }
+    }
+    response
+}
+
+/// Returns the request's `Idempotency-Key`, if present and enabled.
+fn idempotency_key<'a>(
+    headers: &'a HeaderMap,
+    state: &AppState,
+) -> Result<Option<&'a str>, ApiError> {
+    if !state.config.idempotency.enabled {
+        return Ok(None);
+    }
+    let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => Ok(Some(key)),
+        _ => Err(ApiError::bad_request(format!(
+            "Idempotency-Key must be 1 to {} visible ASCII characters.",
+            MAX_KEY_LEN
+        ))),
+    }
+}
+
+/// Converts a failed blocking task (normally a panic in a decoder or
+/// encoder) into a structured `500`.
+fn task_failure(join_error: JoinError, context: &ErrorContext<'_>) -> ApiError {
+    let request_id = context.request_id;
+    if join_error.is_panic() {
+        let payload = join_error.into_panic();
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic payload".to_string());
+        metrics::increment_counter!("compress_panics_total");
+        error!(request_id, "Compression task panicked: {}", message);
+        error_reporting::report(
+            "panic",
+            &format!("Compression task panicked: {}", message),
+            context,
+        );
+    } else {
+        error!(request_id, "Compression task was cancelled: {}", join_error);
+    }
+
+    ApiError::internal("An internal error occurred while compressing the image.")
+}
+
+/// Runs the virus scan, if enabled, as the `scan` stage.
+async fn scan_input(state: &AppState, body: &[u8], timings: &mut Timings) -> Result<(), ApiError> {
+    let config = &state.config.virus_scan;
+    if !config.enabled {
+        return Ok(());
+    }
+    let start = Instant::now();
+    let verdict = config.scan(body).await;
+    timings.push("scan", start.elapsed());
+    match verdict {
+        Ok(Verdict::Clean) => Ok(()),
+        Ok(Verdict::Infected(signature)) => {
+            metrics::increment_counter!("input_scan_flagged_total");
+            warn!(signature, "Rejected input flagged by the virus scanner.");
+            Err(ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "input_flagged",
+                "The input was flagged by the virus scanner.",
+            ))
+        }
+        Err(e) if config.fail_open => {
+            warn!("Virus scan failed, continuing unscanned: {:#}", e);
+            Ok(())
+        }
+        Err(e) => {
+            error!("Virus scan failed: {:#}", e);
+            Err(ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "scan_unavailable",
+                "The input could not be scanned. Try again later.",
+            ))
+        }
+    }
+}
+
+/// Sniffs the input format and rejects anything outside the allowlist.
+pub(super) fn check_input_format(
+    body: &[u8],
+    allowed: &[InputFormat],
+) -> Result<InputFormat, ApiError> {
+    let allowed_list = || {
+        allowed
+            .iter()
+            .map(|f| f.name())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    match InputFormat::sniff(body) {
+        Some(format) if allowed.contains(&format) => Ok(format),
+        Some(format) => {
+            warn!("Rejected input format not in allowlist: {}", format);
+            Err(ApiError::unsupported_media_type(format!(
+                "Input format '{}' is not accepted. Allowed formats: {}.",
+                format,
+                allowed_list()
+            )))
+        }
+        None => {
+            warn!("Rejected input with unrecognized magic bytes.");
+            Err(ApiError::unsupported_media_type(format!(
+                "Input format could not be recognized. Allowed formats: {}.",
+                allowed_list()
+            )))
+        }
+    }
+}
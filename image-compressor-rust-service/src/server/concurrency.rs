@@ -0,0 +1,245 @@
+// image-compressor-rust-service/src/server/concurrency.rs
+
+//! Per-endpoint concurrency limits.
+//!
+//! The CPU budget (see [`crate::cpu`]) is shared by every endpoint, so a
+//! burst of heavy requests, say contact sheets or animated conversions,
+//! can hold all of it while cheap compressions queue behind them. A route
+//! given a limit here runs at most `max_in_flight` requests at once, lets
+//! up to `max_queued` more wait for a slot, and turns any further ones away
+//! with a `503` before they take anything from the budget.
+//!
+//! Each limited endpoint reports its saturation in the `endpoint_in_flight`
+//! and `endpoint_queued` gauges, the `endpoint_queue_seconds` histogram and
+//! the `endpoint_rejected_total` counter, all labelled with `endpoint`.
+
+use super::{request_id, ApiError, AppState};
+use anyhow::{bail, Result};
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+/// Name of the [`ConcurrencyConfig::animated`] limit in metrics and errors.
+pub const ANIMATED_ENDPOINT: &str = "/compress (animated)";
+
+/// Concurrency limits of individual endpoints, within the CPU budget.
+/// Endpoints without one are only bounded by the budget.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConcurrencyConfig {
+    /// Limits by route as it is registered, e.g. `"/contact-sheet"` or
+    /// `"/icons/:set"`.
+    pub routes: HashMap<String, EndpointLimit>,
+    /// Limit of `/compress` requests converting animations
+    /// (`X-Animation: animate`), on top of any for `/compress`.
+    pub animated: Option<EndpointLimit>,
+}
+
+/// How many requests an endpoint runs and holds at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EndpointLimit {
+    /// Requests handled at once.
+    pub max_in_flight: usize,
+    /// Requests waiting for one of those; any more are rejected.
+    #[serde(default)]
+    pub max_queued: usize,
+}
+
+impl ConcurrencyConfig {
+    /// Checks that every limit lets requests through.
+    pub fn validate(&self) -> Result<()> {
+        for (route, limit) in &self.routes {
+            if !route.starts_with('/') {
+                bail!("route '{}' must start with '/'", route);
+            }
+            if limit.max_in_flight == 0 {
+                bail!("max_in_flight of '{}' must be at least 1", route);
+            }
+        }
+        if self.animated.is_some_and(|limit| limit.max_in_flight == 0) {
+            bail!("max_in_flight of animated must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// The configured limits, shared by all requests.
+#[derive(Debug, Default)]
+pub struct Limiters {
+    routes: HashMap<String, Limiter>,
+    animated: Option<Limiter>,
+}
+
+impl Limiters {
+    pub fn new(config: &ConcurrencyConfig) -> Self {
+        Self {
+            routes: config
+                .routes
+                .iter()
+                .map(|(route, &limit)| (route.clone(), Limiter::new(route, limit)))
+                .collect(),
+            animated: config
+                .animated
+                .map(|limit| Limiter::new(ANIMATED_ENDPOINT, limit)),
+        }
+    }
+
+    /// The limit of the route registered as `route`, if it has one.
+    pub fn route(&self, route: &str) -> Option<&Limiter> {
+        self.routes.get(route)
+    }
+
+    /// The limit of animated conversions, if there is one.
+    pub fn animated(&self) -> Option<&Limiter> {
+        self.animated.as_ref()
+    }
+}
+
+/// The slots and queue of one endpoint.
+#[derive(Debug)]
+pub struct Limiter {
+    endpoint: String,
+    limit: EndpointLimit,
+    slots: Arc<Semaphore>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl Limiter {
+    pub fn new(endpoint: &str, limit: EndpointLimit) -> Self {
+        Self {
+            endpoint: endpoint.to_owned(),
+            limit,
+            slots: Arc::new(Semaphore::new(limit.max_in_flight)),
+            queued: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Takes a slot, waiting for one if the queue has room. A full queue is
+    /// a `503` with the limit in its details.
+    pub async fn enter(&self) -> Result<Slot, ApiError> {
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            return Ok(self.slot(permit));
+        }
+        if self.queued.fetch_add(1, Ordering::AcqRel) >= self.limit.max_queued {
+            self.queued.fetch_sub(1, Ordering::AcqRel);
+            return Err(self.rejection());
+        }
+        metrics::increment_gauge!("endpoint_queued", 1.0, "endpoint" => self.endpoint.clone());
+        let waiting = Waiting {
+            endpoint: &self.endpoint,
+            queued: &self.queued,
+        };
+        let started = Instant::now();
+        let permit = self
+            .slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        drop(waiting);
+        metrics::histogram!(
+            "endpoint_queue_seconds",
+            started.elapsed().as_secs_f64(),
+            "endpoint" => self.endpoint.clone()
+        );
+        Ok(self.slot(permit))
+    }
+
+    /// Requests holding a slot right now.
+    pub fn in_flight(&self) -> usize {
+        self.limit.max_in_flight - self.slots.available_permits()
+    }
+
+    /// Requests waiting for a slot right now.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Acquire)
+    }
+
+    fn slot(&self, permit: OwnedSemaphorePermit) -> Slot {
+        metrics::increment_gauge!("endpoint_in_flight", 1.0, "endpoint" => self.endpoint.clone());
+        Slot {
+            endpoint: self.endpoint.clone(),
+            _permit: permit,
+        }
+    }
+
+    fn rejection(&self) -> ApiError {
+        metrics::increment_counter!("endpoint_rejected_total", "endpoint" => self.endpoint.clone());
+        warn!(
+            endpoint = %self.endpoint,
+            max_in_flight = self.limit.max_in_flight,
+            max_queued = self.limit.max_queued,
+            "Endpoint saturated, rejecting the request."
+        );
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "endpoint_saturated",
+            format!(
+                "{} is handling as many requests as it can. Try again later.",
+                self.endpoint
+            ),
+        )
+        .with_details(json!({
+            "endpoint": self.endpoint,
+            "max_in_flight": self.limit.max_in_flight,
+            "max_queued": self.limit.max_queued,
+        }))
+    }
+}
+
+/// A request's place in the queue, given up when it gets a slot or is
+/// dropped while waiting.
+struct Waiting<'a> {
+    endpoint: &'a str,
+    queued: &'a AtomicUsize,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.queued.fetch_sub(1, Ordering::AcqRel);
+        metrics::decrement_gauge!("endpoint_queued", 1.0, "endpoint" => self.endpoint.to_owned());
+    }
+}
+
+/// A request's slot on a limited endpoint, freed when dropped.
+#[derive(Debug)]
+pub struct Slot {
+    endpoint: String,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        metrics::decrement_gauge!("endpoint_in_flight", 1.0, "endpoint" => self.endpoint.clone());
+    }
+}
+
+/// Holds a slot of the matched route's limit, if it has one, while the
+/// request is handled.
+pub async fn enforce_concurrency(
+    State(state): State<AppState>,
+    route: MatchedPath,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let Some(limiter) = state.concurrency.route(route.as_str()) else {
+        return Ok(next.run(request).await);
+    };
+    let _slot = limiter
+        .enter()
+        .await
+        .map_err(|e| e.with_request_id(request_id(request.headers())))?;
+    Ok(next.run(request).await)
+}
@@ -0,0 +1,164 @@
+// image-compressor-rust-service/src/server/contact_sheet.rs
+
+use super::compress::check_input_format;
+use super::{decode_still, request_id, ApiError, AppState};
+use crate::archive::Archive;
+use crate::contact_sheet::{Layout, Tile};
+use crate::cpu::Priority;
+use crate::encode::encode_jpeg_with;
+use crate::formats::OutputFormat;
+use crate::text::Font;
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use image::DynamicImage;
+
+/// Request headers overriding the default [`Layout`], and picking the page.
+pub const COLUMNS_HEADER: &str = "X-Columns";
+pub const ROWS_HEADER: &str = "X-Rows";
+pub const THUMB_SIZE_HEADER: &str = "X-Thumb-Size";
+pub const PAGE_HEADER: &str = "X-Page";
+
+/// Response headers with the number of pages and of images.
+pub const PAGE_COUNT_HEADER: &str = "x-page-count";
+pub const IMAGE_COUNT_HEADER: &str = "x-image-count";
+
+/// Renders one page of a contact sheet for the images in a ZIP body and
+/// returns it as a JPEG.
+///
+/// Images are sorted by their path in the archive; hidden files and macOS
+/// resource forks are skipped. `X-Page` (from 1) picks the page and
+/// `X-Columns`, `X-Rows` and `X-Thumb-Size` override the default
+/// [`Layout`]. The response says how many pages there are in
+/// `X-Page-Count`, and how many images in `X-Image-Count`. Entries are
+/// checked and decoded like `/compress` inputs, and each may be as large
+/// as a `/compress` body; ones that fail are drawn as empty red cells.
+/// Only routed when `[social] font` is set, which also sets the labels.
+pub async fn contact_sheet_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let request_id = request_id(&headers).to_owned();
+    if body.is_empty() {
+        return Err(
+            ApiError::bad_request("Request body cannot be empty.").with_request_id(request_id)
+        );
+    }
+    let number = |name: &str, default: u32| {
+        headers
+            .get(name)
+            .map(|value| {
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|v| v.trim().parse::<u32>().ok())
+                    .ok_or_else(|| {
+                        ApiError::bad_request(format!("{} must be a positive integer.", name))
+                            .with_request_id(&request_id)
+                    })
+            })
+            .unwrap_or(Ok(default))
+    };
+    let defaults = Layout::default();
+    let layout = Layout {
+        columns: number(COLUMNS_HEADER, defaults.columns)?,
+        rows: number(ROWS_HEADER, defaults.rows)?,
+        thumb_size: number(THUMB_SIZE_HEADER, defaults.thumb_size)?,
+    };
+    layout.validate().map_err(|e| {
+        ApiError::bad_request(format!("Invalid contact sheet layout: {}", e))
+            .with_request_id(&request_id)
+    })?;
+    let page = number(PAGE_HEADER, 1)?;
+
+    let archive = Archive::parse(&body)
+        .map_err(|e| ApiError::bad_request(format!("{:#}", e)).with_request_id(&request_id))?;
+    let mut entries: Vec<_> = archive
+        .entries()
+        .iter()
+        .filter(|entry| {
+            let hidden = entry
+                .name
+                .split('/')
+                .any(|part| part.starts_with('.') || part == "__MACOSX");
+            !hidden
+        })
+        .collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    let pages = layout.pages(entries.len());
+    if page == 0 || page as usize > pages {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "no_such_page",
+            format!("Page {} is out of range; the sheet has {}.", page, pages),
+        )
+        .with_request_id(&request_id));
+    }
+    let start = (page as usize - 1) * layout.per_page();
+    let selected: Vec<_> = entries
+        .iter()
+        .skip(start)
+        .take(layout.per_page())
+        .map(|&entry| entry.clone())
+        .collect();
+    let count = entries.len();
+
+    metrics::increment_counter!("contact_sheet_requests_total");
+    let config = state.config.clone();
+    let threads = state.cpu.acquire(Priority::default()).await;
+    let sheet = tokio::task::spawn_blocking(move || {
+        let _threads = threads;
+        let archive = Archive::parse(&body).expect("parsed above");
+        let tiles: Vec<Tile> = selected
+            .into_iter()
+            .map(|entry| {
+                let image = archive
+                    .read(&entry, config.max_body_bytes as u64)
+                    .ok()
+                    .filter(|data| check_input_format(data, &config.allowed_input_formats).is_ok())
+                    .and_then(|data| decode_still(&data, &config).ok());
+                Tile {
+                    name: entry.name,
+                    image,
+                }
+            })
+            .collect();
+        let path = config
+            .social
+            .font
+            .as_deref()
+            .expect("routed only with a font");
+        let font = Font::load(path).map_err(|e| ApiError::internal(format!("{:#}", e)))?;
+        let page = DynamicImage::ImageRgba8(layout.render(&font, &tiles));
+        encode_jpeg_with(
+            &DynamicImage::ImageRgb8(page.into_rgb8()),
+            config.quality.for_format(OutputFormat::Jpeg).default,
+            &config.encoders.jpeg,
+            config.encoders.jpeg.backend,
+            None,
+        )
+        .map_err(|e| ApiError::internal(format!("{:#}", e)))
+    })
+    .await
+    .map_err(|e| {
+        ApiError::internal(format!("Contact sheet task failed: {}", e)).with_request_id(&request_id)
+    })?
+    .map_err(|e| e.with_request_id(&request_id))?;
+
+    let mut response = (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, OutputFormat::Jpeg.mime_type())],
+        sheet,
+    )
+        .into_response();
+    for (name, value) in [(PAGE_COUNT_HEADER, pages), (IMAGE_COUNT_HEADER, count)] {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static(name), HeaderValue::from(value));
+    }
+    Ok(response)
+}
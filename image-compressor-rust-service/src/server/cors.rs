@@ -0,0 +1,92 @@
+// image-compressor-rust-service/src/server/cors.rs
+
+use super::headers;
+use anyhow::{Context, Result};
+use axum::http::{HeaderName, HeaderValue, Method};
+use serde::Deserialize;
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Cross-origin access for browser clients.
+///
+/// CORS is off until at least one origin is allowed. `"*"` allows any origin.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Origins allowed to call the API, e.g. `https://app.example.com`.
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed in cross-origin requests.
+    pub allowed_methods: Vec<String>,
+    /// Request headers browsers may send. By default, every header the API
+    /// reads (see [`headers`]).
+    pub allowed_headers: Vec<String>,
+    /// Response headers exposed to browser scripts. By default, every
+    /// header the API sends for clients.
+    pub exposed_headers: Vec<String>,
+    /// How long browsers may cache a preflight response, in seconds.
+    pub max_age_secs: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "POST", "HEAD", "PATCH", "DELETE"]
+                .map(str::to_string)
+                .into(),
+            allowed_headers: headers::request_headers(),
+            exposed_headers: headers::response_headers(),
+            max_age_secs: 600,
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Builds the middleware, rejecting malformed origins, methods or headers.
+    /// Without allowed origins there is none: an idle `CorsLayer` would
+    /// still answer every `OPTIONS` request itself.
+    pub fn layer(&self) -> Result<Option<CorsLayer>> {
+        if self.allowed_origins.is_empty() {
+            return Ok(None);
+        }
+
+        let origin = if self.allowed_origins.iter().any(|o| o == "*") {
+            AllowOrigin::any()
+        } else {
+            let origins = self
+                .allowed_origins
+                .iter()
+                .map(|o| {
+                    HeaderValue::from_str(o).with_context(|| format!("Invalid CORS origin '{}'", o))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            AllowOrigin::list(origins)
+        };
+        let methods = self
+            .allowed_methods
+            .iter()
+            .map(|m| {
+                Method::from_bytes(m.to_ascii_uppercase().as_bytes())
+                    .with_context(|| format!("Invalid CORS method '{}'", m))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Some(
+            CorsLayer::new()
+                .allow_origin(origin)
+                .allow_methods(methods)
+                .allow_headers(header_names(&self.allowed_headers)?)
+                .expose_headers(header_names(&self.exposed_headers)?)
+                .max_age(Duration::from_secs(self.max_age_secs)),
+        ))
+    }
+}
+
+fn header_names(names: &[String]) -> Result<Vec<HeaderName>> {
+    names
+        .iter()
+        .map(|n| {
+            HeaderName::try_from(n.as_str()).with_context(|| format!("Invalid CORS header '{}'", n))
+        })
+        .collect()
+}
@@ -0,0 +1,121 @@
+// image-compressor-rust-service/src/server/diff.rs
+
+use super::compress::check_input_format;
+use super::{decode_still, request_id, ApiError, AppState};
+use crate::cpu::Priority;
+use crate::diff::{self, DiffReport};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::Engine;
+use image::{DynamicImage, ImageOutputFormat};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::io::Cursor;
+use tracing::info;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DiffRequest {
+    /// Base64-encoded reference image.
+    baseline: String,
+    /// Base64-encoded image compared with it.
+    candidate: String,
+    /// Largest channel delta (0-255) still counted as equal.
+    #[serde(default)]
+    threshold: u8,
+}
+
+#[derive(Debug, Serialize)]
+struct DiffResponse {
+    #[serde(flatten)]
+    report: DiffReport,
+    /// The heatmap as a `data:image/png;base64,...` URI.
+    diff: String,
+}
+
+/// Compares two images and returns difference metrics with a heatmap.
+///
+/// The body is JSON: `{"baseline": "<base64>", "candidate": "<base64>",
+/// "threshold": 0}`. Both images must be of an allowed input format and the
+/// same size; they are decoded like `/compress` inputs, in the sandbox if
+/// it is enabled. The response holds the [`DiffReport`] fields and the
+/// heatmap as a PNG `data:` URI in `diff` (see [`crate::diff::compare`]).
+pub async fn diff_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let request_id = request_id(&headers).to_owned();
+    let request: DiffRequest = serde_json::from_slice(&body).map_err(|e| {
+        ApiError::bad_request(format!("Invalid diff request: {}", e)).with_request_id(&request_id)
+    })?;
+    let decode_base64 = |name: &str, value: &str| {
+        base64::engine::general_purpose::STANDARD
+            .decode(value.trim())
+            .map_err(|e| {
+                ApiError::bad_request(format!("'{}' is not valid base64: {}", name, e))
+                    .with_request_id(&request_id)
+            })
+    };
+    let baseline = decode_base64("baseline", &request.baseline)?;
+    let candidate = decode_base64("candidate", &request.candidate)?;
+    for input in [&baseline, &candidate] {
+        check_input_format(input, &state.config.allowed_input_formats)
+            .map_err(|e| e.with_request_id(&request_id))?;
+    }
+
+    metrics::increment_counter!("diff_requests_total");
+    let config = state.config.clone();
+    let threads = state.cpu.acquire(Priority::default()).await;
+    let threshold = request.threshold;
+    let result = tokio::task::spawn_blocking(move || {
+        let _threads = threads;
+        let baseline = decode_still(&baseline, &config)?;
+        let candidate = decode_still(&candidate, &config)?;
+        if (baseline.width(), baseline.height()) != (candidate.width(), candidate.height()) {
+            return Err(ApiError::unprocessable(format!(
+                "Images differ in size: {}x{} and {}x{}.",
+                baseline.width(),
+                baseline.height(),
+                candidate.width(),
+                candidate.height()
+            ))
+            .with_details(json!({
+                "baseline": [baseline.width(), baseline.height()],
+                "candidate": [candidate.width(), candidate.height()],
+            })));
+        }
+        let (report, heatmap) = diff::compare(&baseline, &candidate, threshold)
+            .map_err(|e| ApiError::unprocessable(format!("{:#}", e)))?;
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba8(heatmap)
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+            .map_err(|e| ApiError::internal(format!("Failed to encode the diff image: {}", e)))?;
+        Ok(DiffResponse {
+            report,
+            diff: format!(
+                "data:image/png;base64,{}",
+                base64::engine::general_purpose::STANDARD.encode(png)
+            ),
+        })
+    })
+    .await
+    .map_err(|e| {
+        ApiError::internal(format!("Diff task failed: {}", e)).with_request_id(&request_id)
+    })?
+    .map_err(|e| e.with_request_id(&request_id))?;
+
+    info!(
+        request_id,
+        differing_pixels = result.report.differing_pixels,
+        "Compared two {}x{} images.",
+        result.report.width,
+        result.report.height
+    );
+    Ok((StatusCode::OK, Json(result)).into_response())
+}
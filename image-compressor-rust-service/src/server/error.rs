@@ -0,0 +1,100 @@
+// image-compressor-rust-service/src/server/error.rs
+
+use crate::decode::DecodeError;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Value};
+
+/// A structured API error, rendered as
+/// `{"error": {"code": "...", "message": "...", "request_id": "...", "details": {...}}}`
+/// with the given status. `request_id` and `details` are only present when known.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub status: StatusCode,
+    /// Stable, machine-readable error code.
+    pub code: &'static str,
+    /// Human-readable description of the failure.
+    pub message: String,
+    /// The `X-Request-Id` of the failed request, for correlation with logs.
+    pub request_id: Option<String>,
+    /// Machine-readable context for the failure, e.g. the limit that was exceeded.
+    /// Boxed to keep `Result<_, ApiError>` small.
+    pub details: Option<Box<Value>>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+            request_id: None,
+            details: None,
+        }
+    }
+
+    /// Attaches the request ID to the error body.
+    pub fn with_request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = Some(request_id.into());
+        self
+    }
+
+    /// Attaches machine-readable details to the error body.
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(Box::new(details));
+        self
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "bad_request", message)
+    }
+
+    pub fn unsupported_media_type(message: impl Into<String>) -> Self {
+        Self::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+            message,
+        )
+    }
+
+    pub fn unprocessable(message: impl Into<String>) -> Self {
+        Self::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "unprocessable_image",
+            message,
+        )
+    }
+
+    /// A `422` for an input that could not be processed. When it failed to
+    /// decode, `details.decode_error` says why: `truncated`,
+    /// `unsupported_container`, `unsupported_feature`, `corrupt` or
+    /// `too_large` (see [`DecodeError`]).
+    pub fn unprocessable_input(message: impl Into<String>, error: &anyhow::Error) -> Self {
+        let api_error = Self::unprocessable(message);
+        match error.downcast_ref::<DecodeError>() {
+            Some(kind) => api_error.with_details(json!({ "decode_error": kind.name() })),
+            None => api_error,
+        }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut error = json!({ "code": self.code, "message": self.message });
+        if let Some(request_id) = self.request_id {
+            error["request_id"] = json!(request_id);
+        }
+        if let Some(details) = self.details {
+            error["details"] = *details;
+        }
+        let body = json!({ "error": error });
+        (self.status, Json(body)).into_response()
+    }
+}
@@ -0,0 +1,189 @@
+// image-compressor-rust-service/src/server/error_reporting.rs
+
+//! Reporting of failed requests to Sentry.
+//!
+//! Failed compressions and panics in the pipeline are sent as events tagged
+//! with the request id, input format and input size. Events never carry the
+//! request: its body, headers and anything else attached to it are dropped
+//! before sending, so no image contents leave the service.
+//!
+//! Reporting needs the `sentry` cargo feature.
+
+use crate::formats::InputFormat;
+use anyhow::Result;
+use serde::Deserialize;
+
+/// Settings for error reporting.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ErrorReportingConfig {
+    /// Sentry DSN. Reporting is off without one.
+    pub sentry_dsn: Option<String>,
+    /// Environment events are filed under, e.g. `production`.
+    pub environment: Option<String>,
+    /// Share of events sent, from `0.0` to `1.0`.
+    pub sample_rate: f32,
+}
+
+impl Default for ErrorReportingConfig {
+    fn default() -> Self {
+        Self {
+            sentry_dsn: None,
+            environment: None,
+            sample_rate: 1.0,
+        }
+    }
+}
+
+/// Keeps reporting alive; pending events are flushed when it is dropped.
+#[must_use]
+pub struct Reporter {
+    _guard: imp::Guard,
+}
+
+/// What is known about the request an error happened in.
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorContext<'a> {
+    pub request_id: &'a str,
+    pub input_format: InputFormat,
+    pub input_bytes: usize,
+}
+
+impl ErrorReportingConfig {
+    /// Checks the DSN, so a bad setup fails at startup.
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.sample_rate) {
+            anyhow::bail!("sample_rate must be between 0.0 and 1.0");
+        }
+        if self.sentry_dsn.is_some() {
+            imp::check(self)?;
+        }
+        Ok(())
+    }
+
+    /// Starts reporting, if a DSN is configured. Hold on to the returned
+    /// [`Reporter`] for as long as the service runs.
+    pub fn init(&self) -> Result<Reporter> {
+        if self.sentry_dsn.is_none() {
+            return Ok(Reporter {
+                _guard: imp::Guard::default(),
+            });
+        }
+        imp::init(self).map(|_guard| Reporter { _guard })
+    }
+}
+
+/// Reports an error of `kind` (e.g. `panic`) that failed a request. A no-op
+/// unless reporting was started.
+pub fn report(kind: &'static str, message: &str, context: &ErrorContext<'_>) {
+    imp::report(kind, message, context);
+}
+
+#[cfg(feature = "sentry")]
+pub use imp::{client_options, scrub};
+
+#[cfg(feature = "sentry")]
+mod imp {
+    use super::{ErrorContext, ErrorReportingConfig};
+    use anyhow::{Context, Result};
+    use sentry::protocol::{Event, Value};
+    use sentry::{ClientInitGuard, ClientOptions, Level};
+    use std::sync::Arc;
+
+    /// Longest message or extra value kept in an event.
+    const MAX_VALUE_LEN: usize = 1024;
+
+    pub type Guard = Option<ClientInitGuard>;
+
+    pub fn check(config: &ErrorReportingConfig) -> Result<()> {
+        client_options(config).map(drop)
+    }
+
+    pub fn init(config: &ErrorReportingConfig) -> Result<Guard> {
+        Ok(Some(sentry::init(client_options(config)?)))
+    }
+
+    /// The options [`ErrorReportingConfig::init`] starts the client with.
+    pub fn client_options(config: &ErrorReportingConfig) -> Result<ClientOptions> {
+        let dsn = config
+            .sentry_dsn
+            .as_deref()
+            .unwrap_or_default()
+            .parse()
+            .context("Invalid sentry_dsn")?;
+        Ok(ClientOptions {
+            dsn: Some(dsn),
+            environment: config.environment.clone().map(Into::into),
+            release: sentry::release_name!(),
+            sample_rate: config.sample_rate,
+            send_default_pii: false,
+            before_send: Some(Arc::new(scrub)),
+            ..ClientOptions::default()
+        })
+    }
+
+    /// Strips everything from `event` that could carry request data: the
+    /// request itself, the user, breadcrumb data, and overlong values.
+    pub fn scrub(mut event: Event<'static>) -> Option<Event<'static>> {
+        event.request = None;
+        event.user = None;
+        for breadcrumb in &mut event.breadcrumbs.values {
+            breadcrumb.data.clear();
+            breadcrumb.message = breadcrumb.message.take().map(truncate);
+        }
+        event.message = event.message.take().map(truncate);
+        for exception in &mut event.exception.values {
+            exception.value = exception.value.take().map(truncate);
+        }
+        for value in event.extra.values_mut() {
+            if let Value::String(s) = value {
+                *s = truncate(std::mem::take(s));
+            }
+        }
+        Some(event)
+    }
+
+    fn truncate(mut s: String) -> String {
+        if s.len() > MAX_VALUE_LEN {
+            let mut end = MAX_VALUE_LEN;
+            while !s.is_char_boundary(end) {
+                end -= 1;
+            }
+            s.truncate(end);
+        }
+        s
+    }
+
+    pub fn report(kind: &'static str, message: &str, context: &ErrorContext<'_>) {
+        sentry::with_scope(
+            |scope| {
+                scope.set_tag("kind", kind);
+                scope.set_tag("request_id", context.request_id);
+                scope.set_tag("input_format", context.input_format.name());
+                scope.set_extra("input_bytes", context.input_bytes.into());
+            },
+            || sentry::capture_message(message, Level::Error),
+        );
+    }
+}
+
+#[cfg(not(feature = "sentry"))]
+mod imp {
+    use super::{ErrorContext, ErrorReportingConfig};
+    use anyhow::{bail, Result};
+
+    const MISSING_FEATURE: &str =
+        "error_reporting.sentry_dsn requires a build with the `sentry` feature";
+
+    pub type Guard = ();
+
+    pub fn check(_config: &ErrorReportingConfig) -> Result<()> {
+        bail!(MISSING_FEATURE)
+    }
+
+    pub fn init(_config: &ErrorReportingConfig) -> Result<Guard> {
+        bail!(MISSING_FEATURE)
+    }
+
+    pub fn report(_kind: &'static str, _message: &str, _context: &ErrorContext<'_>) {}
+}
@@ -0,0 +1,92 @@
+// image-compressor-rust-service/src/server/favicon.rs
+
+use super::compress::check_input_format;
+use super::{decode_still, request_id, ApiError, AppState};
+use crate::cpu::Priority;
+use crate::favicon::{self, Manifest};
+use crate::options::Color;
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+/// Request header naming the app in `site.webmanifest`.
+pub const MANIFEST_NAME_HEADER: &str = "X-Manifest-Name";
+/// Request header with the manifest's theme colour.
+pub const THEME_COLOR_HEADER: &str = "X-Theme-Color";
+
+/// Returns the `favicon` icon set as a ZIP; see [`icons_handler`].
+pub async fn favicon_handler(
+    state: State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    icons_handler(state, Path("favicon".to_string()), headers, body).await
+}
+
+/// Returns a ZIP of the icons in a configured or built-in icon set and,
+/// when `X-Manifest-Name` is given, a `site.webmanifest` naming the app
+/// (see [`favicon::bundle`]). `X-Theme-Color` sets its theme colour.
+///
+/// The image is checked and decoded like a `/compress` input.
+pub async fn icons_handler(
+    State(state): State<AppState>,
+    Path(set): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let request_id = request_id(&headers).to_owned();
+    let icon_set = state.config.icon_set(&set).ok_or_else(|| {
+        ApiError::new(
+            StatusCode::NOT_FOUND,
+            "unknown_icon_set",
+            format!("No icon set named '{}'.", set),
+        )
+        .with_request_id(&request_id)
+    })?;
+    if body.is_empty() {
+        return Err(
+            ApiError::bad_request("Request body cannot be empty.").with_request_id(request_id)
+        );
+    }
+    check_input_format(&body, &state.config.allowed_input_formats)
+        .map_err(|e| e.with_request_id(&request_id))?;
+    let header_str = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    let manifest = header_str(MANIFEST_NAME_HEADER)
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| Manifest {
+            name: name.to_string(),
+            theme_color: header_str(THEME_COLOR_HEADER).and_then(Color::parse),
+        });
+
+    metrics::increment_counter!("favicon_requests_total");
+    let config = state.config.clone();
+    let threads = state.cpu.acquire(Priority::default()).await;
+    let archive = tokio::task::spawn_blocking(move || {
+        let _threads = threads;
+        let image = decode_still(&body, &config)?;
+        favicon::bundle(&image, &icon_set, manifest.as_ref())
+            .map_err(|e| ApiError::internal(format!("{:#}", e)))
+    })
+    .await
+    .map_err(|e| {
+        ApiError::internal(format!("Icon task failed: {}", e)).with_request_id(&request_id)
+    })?
+    .map_err(|e| e.with_request_id(&request_id))?;
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/zip"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"icons.zip\"",
+            ),
+        ],
+        archive,
+    )
+        .into_response())
+}
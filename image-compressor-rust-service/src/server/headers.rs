@@ -0,0 +1,90 @@
+// image-compressor-rust-service/src/server/headers.rs
+
+//! The headers clients send and read, in one place, so that the CORS
+//! defaults allow and expose every one of them.
+//!
+//! Option headers come from [`QUERY_OPTIONS`], which every option header
+//! needs an entry in anyway; the others are listed here. A header the API
+//! starts reading or sending belongs in one of these lists, or browsers
+//! will not be allowed to send or see it.
+
+use super::compress::{
+    INPUT_FORMAT_HEADER, OUTPUT_VERSION_HEADER, PLACEHOLDER_DATA_HEADER, QUALITY_WARNING_HEADER,
+    SALVAGED_HEADER, SCALING_HEADER, SERVER_TIMING, SOURCE_QUALITY_HEADER,
+    UPSCALE_PREVENTED_HEADER,
+};
+use super::contact_sheet::{
+    COLUMNS_HEADER, IMAGE_COUNT_HEADER, PAGE_COUNT_HEADER, PAGE_HEADER, ROWS_HEADER,
+    THUMB_SIZE_HEADER,
+};
+use super::favicon::{MANIFEST_NAME_HEADER, THEME_COLOR_HEADER};
+use super::idempotency::{IDEMPOTENCY_KEY_HEADER, REPLAYED_HEADER};
+use super::limits::API_KEY_HEADER;
+use super::options::QUERY_OPTIONS;
+use super::peers::PEER_HEADER;
+use super::routing::WORKER_HEADER;
+use super::skip::SKIPPED_HEADER;
+use super::tune::QUALITY_STEP_HEADER;
+use super::{tus, REQUEST_ID_HEADER};
+
+/// Request headers other than the options of [`QUERY_OPTIONS`] and the
+/// upload protocol's.
+const REQUEST_HEADERS: [&str; 13] = [
+    "content-type",
+    "range",
+    "if-range",
+    API_KEY_HEADER,
+    REQUEST_ID_HEADER,
+    IDEMPOTENCY_KEY_HEADER,
+    QUALITY_STEP_HEADER,
+    COLUMNS_HEADER,
+    ROWS_HEADER,
+    THUMB_SIZE_HEADER,
+    PAGE_HEADER,
+    MANIFEST_NAME_HEADER,
+    THEME_COLOR_HEADER,
+];
+
+/// Response headers other than the upload protocol's.
+const RESPONSE_HEADERS: [&str; 21] = [
+    REQUEST_ID_HEADER,
+    SERVER_TIMING,
+    "content-disposition",
+    "content-range",
+    "accept-ranges",
+    "etag",
+    "location",
+    REPLAYED_HEADER,
+    OUTPUT_VERSION_HEADER,
+    INPUT_FORMAT_HEADER,
+    SCALING_HEADER,
+    UPSCALE_PREVENTED_HEADER,
+    PLACEHOLDER_DATA_HEADER,
+    SOURCE_QUALITY_HEADER,
+    QUALITY_WARNING_HEADER,
+    SALVAGED_HEADER,
+    SKIPPED_HEADER,
+    WORKER_HEADER,
+    PEER_HEADER,
+    PAGE_COUNT_HEADER,
+    IMAGE_COUNT_HEADER,
+];
+
+/// Every header clients may send, in lower case.
+pub fn request_headers() -> Vec<String> {
+    REQUEST_HEADERS
+        .into_iter()
+        .chain(QUERY_OPTIONS.iter().map(|(_, header)| *header))
+        .map(str::to_ascii_lowercase)
+        .chain(tus::REQUEST_HEADERS.iter().map(|h| h.to_string()))
+        .collect()
+}
+
+/// Every header responses carry for clients to read, in lower case.
+pub fn response_headers() -> Vec<String> {
+    RESPONSE_HEADERS
+        .into_iter()
+        .map(str::to_ascii_lowercase)
+        .chain(tus::RESPONSE_HEADERS.iter().map(|h| h.to_string()))
+        .collect()
+}
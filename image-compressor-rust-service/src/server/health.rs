@@ -0,0 +1,24 @@
+// image-compressor-rust-service/src/server/health.rs
+
+use super::AppState;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde_json::json;
+use tracing::info;
+
+/// Provides a simple health check endpoint.
+pub async fn health_handler() -> impl IntoResponse {
+    info!("Health check requested.");
+    (StatusCode::OK, Json(json!({ "status": "ok" })))
+}
+
+/// Readiness probe: `503` until the startup warm-up has finished.
+pub async fn ready_handler(State(state): State<AppState>) -> impl IntoResponse {
+    if state.is_ready() {
+        (StatusCode::OK, Json(json!({ "status": "ready" })))
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "warming_up" })),
+        )
+    }
+}
@@ -0,0 +1,259 @@
+// image-compressor-rust-service/src/server/hooks.rs
+
+//! Webhooks called around each `/compress` request.
+//!
+//! The `before` hook is POSTed a [`BeforeRequest`] once the input has
+//! passed the format check and the virus scan, and answers with a
+//! [`Decision`]: `{"allow": false, "reason": "..."}` rejects the request
+//! with `403 rejected_by_hook`, and `{"options": {"quality": 60}}` changes
+//! its options, spelled as in a JSON body. A hook that times out, fails, or
+//! answers something else fails the request with `503 hook_unavailable`,
+//! or with `fail_open` lets it through with its own options.
+//!
+//! The `after` hook is POSTed the request's [`AuditRecord`] once it has
+//! been answered, in the background; its failures are logged and counted
+//! and never change the response.
+
+use super::audit::{AuditOptions, AuditRecord};
+use super::options::check;
+use super::ApiError;
+use crate::config::Config;
+use crate::logging::redaction;
+use crate::preset::Preset;
+use crate::timing::Timings;
+use crate::CompressionOptions;
+use anyhow::{bail, Context, Result};
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+/// The hooks called around `/compress`. Both are off by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HooksConfig {
+    /// Called before compressing; may reject the request or change its
+    /// options.
+    pub before: Option<HookConfig>,
+    /// Told about every request once it has been answered.
+    pub after: Option<HookConfig>,
+}
+
+/// Where a hook is and how long it may take.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HookConfig {
+    /// URL the hook is POSTed to.
+    pub url: String,
+    /// Timeout of a call, in milliseconds.
+    pub timeout_ms: u64,
+    /// Go on with the request's own options when the `before` hook cannot
+    /// be reached or answers something unusable, instead of failing it.
+    /// The `after` hook never fails a request.
+    pub fail_open: bool,
+}
+
+impl Default for HookConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            timeout_ms: 1_000,
+            fail_open: false,
+        }
+    }
+}
+
+impl HooksConfig {
+    pub fn validate(&self) -> Result<()> {
+        for (name, hook) in [("before", &self.before), ("after", &self.after)] {
+            if let Some(hook) = hook {
+                hook.validate()
+                    .with_context(|| format!("Invalid {} hook", name))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl HookConfig {
+    fn validate(&self) -> Result<()> {
+        reqwest::Url::parse(&self.url).context("Invalid url")?;
+        if self.timeout_ms == 0 {
+            bail!("timeout_ms must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// What the `before` hook is told about a request.
+#[derive(Debug, Serialize)]
+pub struct BeforeRequest<'a> {
+    pub request_id: &'a str,
+    /// The caller's `X-Api-Key`.
+    pub tenant: Option<&'a str>,
+    pub input_format: &'static str,
+    pub input_bytes: usize,
+    /// The options the request would be compressed with.
+    pub options: AuditOptions,
+}
+
+/// The `before` hook's answer.
+#[derive(Debug, Deserialize)]
+pub struct Decision {
+    /// Whether the request may go ahead.
+    #[serde(default = "allow")]
+    pub allow: bool,
+    /// Why it may not, passed on to the caller.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Options replacing the request's own, named as in a JSON body.
+    #[serde(default)]
+    pub options: Map<String, Value>,
+}
+
+fn allow() -> bool {
+    true
+}
+
+/// Calls the configured hooks.
+pub struct Hooks {
+    before: Option<(reqwest::Client, HookConfig)>,
+    after: Option<(reqwest::Client, HookConfig)>,
+}
+
+impl Hooks {
+    pub fn new(config: &HooksConfig) -> Self {
+        let client = |hook: &HookConfig| {
+            let client = reqwest::Client::builder()
+                .timeout(Duration::from_millis(hook.timeout_ms))
+                .build()
+                .expect("Failed to build the hook client");
+            (client, hook.clone())
+        };
+        Self {
+            before: config.before.as_ref().map(client),
+            after: config.after.as_ref().map(client),
+        }
+    }
+
+    /// Asks the `before` hook, if any, whether the request may go ahead,
+    /// as the `hook` stage, and applies the options it answers with.
+    pub async fn before(
+        &self,
+        request: BeforeRequest<'_>,
+        options: &mut CompressionOptions,
+        config: &Config,
+        timings: &mut Timings,
+    ) -> Result<(), ApiError> {
+        let Some((client, hook)) = &self.before else {
+            return Ok(());
+        };
+        let start = Instant::now();
+        let decision = call(client, &hook.url, &request).await;
+        timings.push("hook", start.elapsed());
+        metrics::histogram!("hook_seconds", start.elapsed().as_secs_f64(), "hook" => "before");
+
+        let failure = match decision {
+            Ok(decision) if !decision.allow => {
+                metrics::increment_counter!("hook_rejections_total");
+                info!(
+                    reason = decision.reason,
+                    "Request rejected by the before hook."
+                );
+                let message = match &decision.reason {
+                    Some(reason) => format!("The request was rejected: {}", reason),
+                    None => "The request was rejected.".to_owned(),
+                };
+                return Err(
+                    ApiError::new(StatusCode::FORBIDDEN, "rejected_by_hook", message)
+                        .with_details(json!({ "reason": decision.reason })),
+                );
+            }
+            Ok(decision) => match changed(options, &decision.options, config) {
+                Ok(changed) => {
+                    *options = changed;
+                    return Ok(());
+                }
+                Err(e) => e,
+            },
+            Err(e) => e,
+        };
+
+        metrics::increment_counter!("hook_failures_total", "hook" => "before");
+        if hook.fail_open {
+            warn!("Before hook failed, continuing without it: {:#}", failure);
+            Ok(())
+        } else {
+            error!("Before hook failed: {:#}", failure);
+            Err(ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "hook_unavailable",
+                "The request could not be checked. Try again later.",
+            ))
+        }
+    }
+
+    /// Sends `record` to the `after` hook, if any, in the background.
+    pub fn after(&self, record: &AuditRecord) {
+        let Some((client, hook)) = &self.after else {
+            return;
+        };
+        let request = client.post(&hook.url).json(record);
+        let request_id = record.request_id.clone();
+        tokio::spawn(async move {
+            let start = Instant::now();
+            let result = request.send().await.and_then(|r| r.error_for_status());
+            metrics::histogram!("hook_seconds", start.elapsed().as_secs_f64(), "hook" => "after");
+            if let Err(e) = result {
+                metrics::increment_counter!("hook_failures_total", "hook" => "after");
+                let e = redaction().request_error(e);
+                warn!(request_id, "Failed to call the after hook: {}", e);
+            }
+        });
+    }
+
+    /// Whether there is an `after` hook to send records to.
+    pub fn after_enabled(&self) -> bool {
+        self.after.is_some()
+    }
+}
+
+async fn call(
+    client: &reqwest::Client,
+    url: &str,
+    request: &BeforeRequest<'_>,
+) -> Result<Decision> {
+    let response = client
+        .post(url)
+        .json(request)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| redaction().request_error(e))
+        .context("Failed to call the hook")?;
+    response
+        .json()
+        .await
+        .context("Invalid answer from the hook")
+}
+
+/// `options` with the fields of `fields` applied, if they are valid.
+fn changed(
+    options: &CompressionOptions,
+    fields: &Map<String, Value>,
+    config: &Config,
+) -> Result<CompressionOptions> {
+    let (preset, errors) = Preset::from_json(fields);
+    let mut changed = options.clone();
+    preset.apply(&mut changed);
+    if let Err(e) = check(config, &changed, errors) {
+        let details = e.details.map(|d| d.to_string()).unwrap_or_default();
+        bail!(
+            "The hook answered invalid options: {} {}",
+            e.message,
+            details
+        );
+    }
+    Ok(changed)
+}
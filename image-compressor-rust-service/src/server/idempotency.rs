@@ -0,0 +1,267 @@
+// image-compressor-rust-service/src/server/idempotency.rs
+
+//! Replay of `/compress` results by `Idempotency-Key`.
+//!
+//! A request carrying a key that an earlier successful request of the same
+//! tenant used, with the same body and options, gets the stored result
+//! back, marked with `Idempotent-Replayed: true`, without re-encoding.
+//! Reusing a key for a different request is a `422`, and retrying while
+//! the first request is still running a `409`. Failed requests release
+//! their key.
+
+use crate::formats::OutputFormat;
+use bytes::Bytes;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Header carrying the client-chosen idempotency key.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+/// Response header set to `true` when a stored result is replayed.
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+/// Longest accepted idempotency key.
+pub const MAX_KEY_LEN: usize = 255;
+
+/// Settings for `Idempotency-Key` handling on `/compress`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdempotencyConfig {
+    /// Honour the `Idempotency-Key` header. When disabled the header is ignored.
+    pub enabled: bool,
+    /// How long a successful result is kept for replay, in seconds.
+    pub ttl_secs: u64,
+    /// Maximum number of stored results. The oldest are evicted first.
+    pub max_entries: usize,
+    /// Maximum total size of the stored results, in bytes. The oldest are
+    /// evicted first; a result larger than this is not stored at all.
+    pub max_bytes: usize,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ttl_secs: 24 * 60 * 60,
+            max_entries: 1024,
+            max_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// A stored `/compress` result.
+#[derive(Debug, Clone)]
+pub struct StoredResponse {
+    pub data: Bytes,
+    pub content_type: &'static str,
+    pub format: OutputFormat,
+    pub dimensions: (u32, u32),
+    pub source_dimensions: (u32, u32),
+    /// The `data:` URI of the placeholder, if one was asked for.
+    pub placeholder: Option<String>,
+    /// Whether only what could be recovered of the input was compressed.
+    pub salvaged: bool,
+}
+
+impl StoredResponse {
+    /// Bytes the result takes up in the cache.
+    fn size(&self) -> usize {
+        self.data.len() + self.placeholder.as_ref().map_or(0, String::len)
+    }
+}
+
+/// Outcome of [`IdempotencyCache::begin`].
+pub enum Begin {
+    /// First request with this key: do the work and call [`Reservation::complete`].
+    New(Reservation),
+    /// A request with the same key and fingerprint already succeeded.
+    Replay(StoredResponse),
+    /// The key was used before for a different request.
+    Mismatch,
+    /// A request with this key is still being processed.
+    InProgress,
+}
+
+enum State {
+    InFlight,
+    Done(StoredResponse),
+}
+
+struct Entry {
+    fingerprint: [u8; 32],
+    created: Instant,
+    state: State,
+}
+
+impl Entry {
+    fn size(&self) -> usize {
+        match &self.state {
+            State::InFlight => 0,
+            State::Done(response) => response.size(),
+        }
+    }
+}
+
+/// An `Idempotency-Key` within the tenant (`X-Api-Key`) that sent it, so
+/// tenants choosing the same key do not see each other's results.
+type Key = (Option<String>, String);
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<Key, Entry>,
+    /// Total size of the stored results.
+    bytes: usize,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.map.remove(key) {
+            self.bytes -= entry.size();
+        }
+    }
+
+    /// Evicts the oldest stored result, if there is one.
+    fn evict_oldest(&mut self) -> bool {
+        let oldest = self
+            .map
+            .iter()
+            .filter(|(_, entry)| matches!(entry.state, State::Done(_)))
+            .min_by_key(|(_, entry)| entry.created)
+            .map(|(key, _)| key.clone());
+        match oldest {
+            Some(oldest) => {
+                self.remove(&oldest);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// In-memory store of recent results keyed by tenant and `Idempotency-Key`.
+///
+/// Only successful results are stored; a failed or panicked request releases
+/// its key so the client can retry it.
+pub struct IdempotencyCache {
+    ttl: Duration,
+    max_entries: usize,
+    max_bytes: usize,
+    entries: Mutex<Entries>,
+}
+
+impl IdempotencyCache {
+    pub fn new(config: &IdempotencyConfig) -> Self {
+        Self {
+            ttl: Duration::from_secs(config.ttl_secs),
+            max_entries: config.max_entries.max(1),
+            max_bytes: config.max_bytes,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Hashes everything that defines a request's result: the body and the
+    /// options applied to it, as JSON. Objects of a [`Value`] keep their keys
+    /// sorted, so equal options always hash alike.
+    pub fn fingerprint(body: &[u8], options: &Value) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(body);
+        hasher.update([0]);
+        hasher.update(options.to_string().as_bytes());
+        hasher.finalize().into()
+    }
+
+    /// Looks up `key` of `tenant`, reserving it for the caller if it is
+    /// unknown.
+    pub fn begin(
+        self: &Arc<Self>,
+        tenant: Option<&str>,
+        key: &str,
+        fingerprint: [u8; 32],
+    ) -> Begin {
+        let key: Key = (tenant.map(str::to_owned), key.to_owned());
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        let expired: Vec<Key> = entries
+            .map
+            .iter()
+            .filter(|(_, entry)| now.duration_since(entry.created) >= self.ttl)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for expired in &expired {
+            entries.remove(expired);
+        }
+
+        if let Some(entry) = entries.map.get(&key) {
+            return if entry.fingerprint != fingerprint {
+                Begin::Mismatch
+            } else {
+                match &entry.state {
+                    State::InFlight => Begin::InProgress,
+                    State::Done(response) => Begin::Replay(response.clone()),
+                }
+            };
+        }
+
+        if entries.map.len() >= self.max_entries {
+            entries.evict_oldest();
+        }
+
+        entries.map.insert(
+            key.clone(),
+            Entry {
+                fingerprint,
+                created: now,
+                state: State::InFlight,
+            },
+        );
+        Begin::New(Reservation {
+            cache: Arc::clone(self),
+            key: Some(key),
+        })
+    }
+
+    /// Total size of the stored results, in bytes.
+    pub fn stored_bytes(&self) -> usize {
+        self.entries.lock().unwrap().bytes
+    }
+}
+
+/// A reserved key. Dropping it without calling [`complete`](Self::complete)
+/// releases the key.
+pub struct Reservation {
+    cache: Arc<IdempotencyCache>,
+    key: Option<Key>,
+}
+
+impl Reservation {
+    /// Stores the result for replay, evicting the oldest results to stay
+    /// within `max_bytes`. A result that cannot fit releases the key.
+    pub fn complete(mut self, response: StoredResponse) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        let size = response.size();
+        let mut entries = self.cache.entries.lock().unwrap();
+        if size > self.cache.max_bytes {
+            entries.remove(&key);
+            return;
+        }
+        while entries.bytes + size > self.cache.max_bytes && entries.evict_oldest() {}
+        if let Some(entry) = entries.map.get_mut(&key) {
+            entry.state = State::Done(response);
+            entries.bytes += size;
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            if let Ok(mut entries) = self.cache.entries.lock() {
+                entries.remove(&key);
+            }
+        }
+    }
+}
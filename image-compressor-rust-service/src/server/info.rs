@@ -0,0 +1,66 @@
+// image-compressor-rust-service/src/server/info.rs
+
+use super::AppState;
+use crate::build_info;
+use crate::formats::{InputFormat, OutputFormat};
+use crate::simd::{self, CpuFeatures};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde_json::json;
+
+/// Reports the build: version, commit, build time, features and formats,
+/// and the vector extensions of the CPU it runs on.
+pub async fn version_handler() -> impl IntoResponse {
+    let features = CpuFeatures::detect();
+    (
+        StatusCode::OK,
+        Json(json!({
+            "version": build_info::VERSION,
+            "git_commit": build_info::GIT_COMMIT,
+            "build_timestamp": build_info::BUILD_TIMESTAMP,
+            "features": build_info::features(),
+            "codecs": build_info::CODECS,
+            "input_formats": InputFormat::ALL.map(InputFormat::name),
+            "output_formats": OutputFormat::ALL.map(OutputFormat::name),
+            "deterministic_output_version": crate::DETERMINISTIC_OUTPUT_VERSION,
+            "cpu": {
+                "arch": features.arch,
+                "features": features.names(),
+                "simd": simd::path().name(),
+            },
+        })),
+    )
+}
+
+/// Lists the input and output formats with their capabilities.
+///
+/// `enabled` reflects the runtime configuration: an input format compiled
+/// in but missing from `allowed_input_formats` is rejected by `/compress`.
+pub async fn formats_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let input: Vec<_> = InputFormat::ALL
+        .iter()
+        .map(|&format| {
+            json!({
+                "format": format.name(),
+                "mime_type": format.mime_type(),
+                "enabled": state.config.allowed_input_formats.contains(&format),
+                "capabilities": format.capabilities(),
+            })
+        })
+        .collect();
+    let output: Vec<_> = OutputFormat::ALL
+        .iter()
+        .map(|&format| {
+            json!({
+                "format": format.name(),
+                "mime_type": format.mime_type(),
+                "enabled": true,
+                "capabilities": format.capabilities(),
+            })
+        })
+        .collect();
+
+    (
+        StatusCode::OK,
+        Json(json!({ "input": input, "output": output })),
+    )
+}
@@ -0,0 +1,143 @@
+// image-compressor-rust-service/src/server/json_body.rs
+
+//! The JSON mode of `/compress`, for callers that can only exchange JSON.
+//!
+//! A request with `Content-Type: application/json` carries the image in
+//! base64, or as a `data:` URI, and its options under the names presets
+//! use:
+//!
+//! ```json
+//! {"image_base64": "...", "preset": "web", "options": {"quality": 70}, "data_uri": false}
+//! ```
+//!
+//! The options override any sent as headers or query parameters, and
+//! `preset` replaces `X-Preset` (see [`super::options`]). A successful
+//! response is JSON too, with the output in `image_base64` (and `data_uri`
+//! when asked for), its `content_type` and size, and the usual response
+//! headers. Errors are JSON either way.
+//!
+//! The body limit is raised by the base64 overhead (see
+//! [`super::limits::json_limit`]); the decoded image is held to the limit
+//! of a binary body.
+
+use super::compress::PLACEHOLDER_DATA_HEADER;
+use super::ApiError;
+use axum::{
+    body::{to_bytes, Bytes},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonRequest {
+    /// The image in base64, or as a `data:` URI.
+    image_base64: String,
+    /// A preset the options apply on top of, instead of `X-Preset`.
+    preset: Option<String>,
+    #[serde(default)]
+    options: Map<String, Value>,
+    /// Also return the output as a `data:` URI.
+    #[serde(default)]
+    data_uri: bool,
+}
+
+/// A decoded JSON request.
+#[derive(Debug)]
+pub struct JsonInput {
+    pub image: Bytes,
+    pub preset: Option<String>,
+    /// Options overriding those sent as headers, as sent (see
+    /// [`crate::preset::Preset::from_json`]).
+    pub options: Map<String, Value>,
+    pub data_uri: bool,
+}
+
+impl JsonInput {
+    /// Decodes a JSON request body, holding its image to `limit` bytes.
+    pub fn parse(body: &[u8], limit: usize) -> Result<Self, ApiError> {
+        let request: JsonRequest = serde_json::from_slice(body)
+            .map_err(|e| ApiError::bad_request(format!("Invalid JSON request: {}", e)))?;
+        let encoded = request.image_base64.trim();
+        // `data:image/png;base64,...` is accepted as well as bare base64.
+        let encoded = match encoded.strip_prefix("data:") {
+            Some(uri) => match uri.split_once(',') {
+                Some((media_type, data)) if media_type.ends_with(";base64") => data,
+                _ => {
+                    return Err(ApiError::bad_request(
+                        "'image_base64' data URIs must be base64-encoded.",
+                    ))
+                }
+            },
+            None => encoded,
+        };
+        let image = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|e| {
+                ApiError::bad_request(format!("'image_base64' is not valid base64: {}", e))
+            })?;
+        if image.len() > limit {
+            return Err(ApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload_too_large",
+                format!("The decoded image exceeds the limit of {} bytes.", limit),
+            )
+            .with_details(json!({ "limit_bytes": limit })));
+        }
+        Ok(Self {
+            image: image.into(),
+            preset: request.preset,
+            options: request.options,
+            data_uri: request.data_uri,
+        })
+    }
+
+    /// Turns a successful binary `/compress` response into its JSON form,
+    /// keeping the headers other than the body's own.
+    pub async fn respond(&self, response: Response) -> Response {
+        if !response.status().is_success() {
+            return response;
+        }
+        let (mut parts, body) = response.into_parts();
+        let data = match to_bytes(body, usize::MAX).await {
+            Ok(data) => data,
+            Err(e) => {
+                return ApiError::internal(format!("Failed to read the output: {}", e))
+                    .into_response()
+            }
+        };
+        let content_type = parts
+            .headers
+            .remove(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok().map(str::to_owned))
+            .unwrap_or_else(|| "application/octet-stream".to_owned());
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.remove(header::CONTENT_DISPOSITION);
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&data);
+        let mut body = json!({
+            "content_type": content_type,
+            "bytes": data.len(),
+            "image_base64": encoded,
+        });
+        if self.data_uri {
+            body["data_uri"] = json!(format!("data:{};base64,{}", content_type, encoded));
+        }
+        if let Some(placeholder) = parts
+            .headers
+            .get(PLACEHOLDER_DATA_HEADER)
+            .and_then(|v| v.to_str().ok())
+        {
+            body["placeholder"] = json!(placeholder);
+        }
+        let mut response = Json(body).into_response();
+        let status = parts.status;
+        parts.headers.extend(std::mem::take(response.headers_mut()));
+        *response.headers_mut() = parts.headers;
+        *response.status_mut() = status;
+        response
+    }
+}
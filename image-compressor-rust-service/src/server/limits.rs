@@ -0,0 +1,208 @@
+// image-compressor-rust-service/src/server/limits.rs
+
+use super::{request_id, ApiError, AppState};
+use crate::timing::Timings;
+use axum::{
+    async_trait,
+    body::{to_bytes, Body, Bytes},
+    extract::{FromRequest, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use http_body_util::LengthLimitError;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::error::Error as _;
+use std::time::Instant;
+use tracing::warn;
+
+/// Header identifying the calling tenant for per-key limits.
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// The caller's `X-Api-Key`, if any.
+pub fn api_key(headers: &HeaderMap) -> Option<&str> {
+    headers.get(API_KEY_HEADER).and_then(|v| v.to_str().ok())
+}
+
+/// Room for the other fields of a JSON body next to its base64 images.
+pub const JSON_OVERHEAD_BYTES: usize = 64 * 1024;
+
+/// Whether the request body is JSON.
+pub fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// The body limit of a JSON request carrying images of up to `limit` bytes
+/// in base64: four bytes for every three, plus room for the other fields.
+pub fn json_limit(limit: usize) -> usize {
+    limit
+        .div_ceil(3)
+        .saturating_mul(4)
+        .saturating_add(JSON_OVERHEAD_BYTES)
+}
+
+/// Request body size overrides on top of the global `max_body_bytes`.
+///
+/// A limit for the caller's API key wins over a limit for the route, which
+/// wins over the global one.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BodyLimitConfig {
+    /// Limits by exact request path, e.g. `"/compress" = 20971520`.
+    pub routes: HashMap<String, usize>,
+    /// Limits by `X-Api-Key` value.
+    pub api_keys: HashMap<String, usize>,
+}
+
+/// Where the limit applied to a request came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitScope {
+    Global,
+    Route,
+    ApiKey,
+}
+
+impl LimitScope {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Global => "global",
+            Self::Route => "route",
+            Self::ApiKey => "api_key",
+        }
+    }
+}
+
+impl BodyLimitConfig {
+    /// The limit for a request to `path` with the given headers.
+    pub fn limit_for(&self, global: usize, path: &str, headers: &HeaderMap) -> (usize, LimitScope) {
+        let api_key = api_key(headers).and_then(|key| self.api_keys.get(key));
+        if let Some(&limit) = api_key {
+            return (limit, LimitScope::ApiKey);
+        }
+        if let Some(&limit) = self.routes.get(path) {
+            return (limit, LimitScope::Route);
+        }
+        (global, LimitScope::Global)
+    }
+}
+
+/// Paths whose handlers check what they can of a request before reading
+/// its body, which [`enforce_body_limit`] leaves to them (see [`BodyLimit`]).
+pub const STREAMED_PATHS: [&str; 1] = ["/compress"];
+
+/// The limit applied to a request body, for handlers that read it
+/// themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyLimit {
+    pub limit: usize,
+    pub scope: LimitScope,
+}
+
+impl BodyLimit {
+    /// Reads `body`, failing as soon as more than the limit has arrived
+    /// rather than once all of it has.
+    pub async fn read(self, body: Body, request_id: &str) -> Result<Bytes, ApiError> {
+        match to_bytes(body, self.limit).await {
+            Ok(bytes) => Ok(bytes),
+            Err(e) if e.source().is_some_and(|s| s.is::<LengthLimitError>()) => {
+                Err(self.rejection(request_id))
+            }
+            Err(e) => Err(
+                ApiError::bad_request(format!("Failed to read request body: {}", e))
+                    .with_request_id(request_id),
+            ),
+        }
+    }
+
+    /// The structured `413` of a body over the limit.
+    fn rejection(self, request_id: &str) -> ApiError {
+        warn!(
+            request_id,
+            limit = self.limit,
+            scope = self.scope.as_str(),
+            "Request body too large."
+        );
+        ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            format!("Request body exceeds the limit of {} bytes.", self.limit),
+        )
+        .with_details(json!({ "limit_bytes": self.limit, "scope": self.scope.as_str() }))
+        .with_request_id(request_id)
+    }
+}
+
+/// The request body, read within its [`BodyLimit`] when
+/// [`enforce_body_limit`] left that to the handler.
+pub struct LimitedBytes(pub Bytes);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequest<S> for LimitedBytes {
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request, _state: &S) -> Result<Self, Self::Rejection> {
+        let limit = request.extensions().get::<BodyLimit>().copied();
+        let request_id = request_id(request.headers()).to_owned();
+        let limit = limit.unwrap_or(BodyLimit {
+            limit: usize::MAX,
+            scope: LimitScope::Global,
+        });
+        limit.read(request.into_body(), &request_id).await.map(Self)
+    }
+}
+
+/// Enforces the applicable body limit. Requests announcing a larger
+/// `Content-Length` are rejected before any of the body is read. The body
+/// is then buffered, failing as soon as it exceeds the limit, and the time
+/// spent reading it recorded as the `read` stage in the request's
+/// [`Timings`] extension; for [`STREAMED_PATHS`], it is left to the handler
+/// with a [`BodyLimit`] extension instead. JSON bodies, which carry images
+/// in base64, get the [`json_limit`] of the applicable limit.
+pub async fn enforce_body_limit(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let (limit, scope) = state.config.body_limits.limit_for(
+        state.config.max_body_bytes,
+        request.uri().path(),
+        request.headers(),
+    );
+    let limit = if is_json(request.headers()) {
+        json_limit(limit)
+    } else {
+        limit
+    };
+    let limit = BodyLimit { limit, scope };
+
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if content_length.is_some_and(|len| len > limit.limit as u64) {
+        return Err(limit.rejection(request_id(request.headers())));
+    }
+
+    let (mut parts, body) = request.into_parts();
+    if STREAMED_PATHS.contains(&parts.uri.path()) {
+        parts.extensions.insert(limit);
+        return Ok(next.run(Request::from_parts(parts, body)).await);
+    }
+    let read_start = Instant::now();
+    let bytes = limit.read(body, request_id(&parts.headers)).await?;
+
+    let mut timings = Timings::new();
+    timings.push("read", read_start.elapsed());
+    parts.extensions.insert(timings);
+
+    Ok(next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await)
+}
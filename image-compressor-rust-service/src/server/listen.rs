@@ -0,0 +1,248 @@
+// image-compressor-rust-service/src/server/listen.rs
+
+use crate::config::Config;
+use anyhow::{Context, Result};
+use axum::Router;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use serde::Deserialize;
+use std::fmt;
+use std::io;
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, info, warn};
+
+/// First file descriptor passed by systemd socket activation (`SD_LISTEN_FDS_START`).
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Connection-level HTTP settings.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Accept HTTP/2 (prior knowledge / h2c) in addition to HTTP/1.1.
+    pub http2: bool,
+    /// Maximum concurrent streams per HTTP/2 connection.
+    pub http2_max_concurrent_streams: u32,
+    /// Interval between HTTP/2 keep-alive pings. `0` disables pings.
+    pub http2_keep_alive_interval_secs: u64,
+    /// Close an HTTP/2 connection if a ping is not acknowledged in time.
+    pub http2_keep_alive_timeout_secs: u64,
+    /// Reuse HTTP/1.1 connections for further requests.
+    pub keep_alive: bool,
+    /// Close an HTTP/1.1 connection whose request headers take longer than
+    /// this to arrive, in seconds. `0` disables the timeout.
+    pub header_read_timeout_secs: u64,
+    /// Maximum number of open connections. Further connections wait in the
+    /// listen backlog. `0` means unlimited.
+    pub max_connections: usize,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            http2: true,
+            http2_max_concurrent_streams: 200,
+            http2_keep_alive_interval_secs: 0,
+            http2_keep_alive_timeout_secs: 20,
+            keep_alive: true,
+            header_read_timeout_secs: 30,
+            max_connections: 0,
+        }
+    }
+}
+
+impl HttpConfig {
+    fn builder(&self) -> auto::Builder<TokioExecutor> {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .keep_alive(self.keep_alive)
+            .header_read_timeout(seconds(self.header_read_timeout_secs));
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .max_concurrent_streams(self.http2_max_concurrent_streams)
+            .keep_alive_interval(seconds(self.http2_keep_alive_interval_secs))
+            .keep_alive_timeout(Duration::from_secs(self.http2_keep_alive_timeout_secs));
+        if self.http2 {
+            builder
+        } else {
+            builder.http1_only()
+        }
+    }
+}
+
+/// `0` means "disabled".
+fn seconds(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// A bound listening socket.
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+    /// A socket inherited from systemd; the path is unknown for Unix sockets.
+    InheritedUnix(UnixListener),
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "tcp://{}", addr),
+                Err(_) => write!(f, "tcp://<unknown>"),
+            },
+            Self::Unix(_, path) => write!(f, "unix://{}", path.display()),
+            Self::InheritedUnix(_) => write!(f, "unix://<systemd socket>"),
+        }
+    }
+}
+
+impl Listener {
+    /// Opens the socket described by the configuration.
+    ///
+    /// In order of preference: a socket passed by systemd (`LISTEN_FDS`), the
+    /// Unix socket at `unix_socket`, or TCP on `bind_addr`.
+    pub async fn bind(config: &Config) -> Result<Self> {
+        if let Some(listener) = Self::from_systemd()? {
+            return Ok(listener);
+        }
+        if let Some(path) = &config.unix_socket {
+            return Self::bind_unix(path);
+        }
+        let listener = TcpListener::bind(config.bind_addr)
+            .await
+            .with_context(|| format!("Failed to bind {}", config.bind_addr))?;
+        Ok(Self::Tcp(listener))
+    }
+
+    fn bind_unix(path: &Path) -> Result<Self> {
+        // A socket file left behind by a previous run would make bind fail.
+        match std::fs::remove_file(path) {
+            Ok(()) => debug!("Removed stale socket {}", path.display()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+            }
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to bind Unix socket {}", path.display()))?;
+        Ok(Self::Unix(listener, path.to_owned()))
+    }
+
+    /// Takes over the first socket passed by systemd, if this process was
+    /// socket-activated.
+    fn from_systemd() -> Result<Option<Self>> {
+        let pid_matches = std::env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            .is_some_and(|pid| pid == std::process::id());
+        let fds = std::env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|n| n.parse::<u32>().ok())
+            .unwrap_or(0);
+        if !pid_matches || fds == 0 {
+            return Ok(None);
+        }
+        if fds > 1 {
+            warn!("systemd passed {} sockets; only the first is used", fds);
+        }
+        // Keep the variables from leaking into child processes.
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+
+        // SAFETY: systemd guarantees that `SD_LISTEN_FDS_START` is an open
+        // listening socket owned by this process, and nothing else uses it.
+        let tcp = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+        if tcp.local_addr().is_ok() {
+            tcp.set_nonblocking(true)?;
+            return Ok(Some(Self::Tcp(TcpListener::from_std(tcp)?)));
+        }
+
+        // Not an inet socket: hand the descriptor over to a Unix listener.
+        // SAFETY: the descriptor was just released by `into_raw_fd`.
+        let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+        unix.local_addr()
+            .context("Inherited systemd socket is neither TCP nor a Unix socket")?;
+        unix.set_nonblocking(true)?;
+        Ok(Some(Self::InheritedUnix(UnixListener::from_std(unix)?)))
+    }
+}
+
+/// Serves `app` on `listener` with the given connection settings until the
+/// process exits.
+pub async fn serve(listener: Listener, app: Router, http: &HttpConfig) -> Result<()> {
+    info!(
+        "Server listening on {} (http2: {}, max connections: {})",
+        listener,
+        http.http2,
+        match http.max_connections {
+            0 => "unlimited".to_string(),
+            n => n.to_string(),
+        }
+    );
+    let builder = Arc::new(http.builder());
+    let connections =
+        (http.max_connections > 0).then(|| Arc::new(Semaphore::new(http.max_connections)));
+
+    loop {
+        // Wait for a free slot before accepting, so excess connections queue
+        // in the kernel backlog instead of being accepted and starved.
+        let permit = match &connections {
+            Some(semaphore) => Some(semaphore.clone().acquire_owned().await?),
+            None => None,
+        };
+        match &listener {
+            Listener::Tcp(listener) => match listener.accept().await {
+                Ok((stream, _)) => {
+                    let _ = stream.set_nodelay(true);
+                    spawn_connection(stream, app.clone(), builder.clone(), permit);
+                }
+                Err(e) => accept_error(e).await,
+            },
+            Listener::Unix(listener, _) | Listener::InheritedUnix(listener) => {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        spawn_connection(stream, app.clone(), builder.clone(), permit)
+                    }
+                    Err(e) => accept_error(e).await,
+                }
+            }
+        }
+    }
+}
+
+fn spawn_connection<S>(
+    stream: S,
+    app: Router,
+    builder: Arc<auto::Builder<TokioExecutor>>,
+    permit: Option<OwnedSemaphorePermit>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let service = TowerToHyperService::new(app);
+        if let Err(e) = builder
+            .serve_connection(TokioIo::new(stream), service)
+            .await
+        {
+            debug!("Connection closed with error: {}", e);
+        }
+        drop(permit);
+    });
+}
+
+/// Accept errors are usually transient (e.g. out of file descriptors); back
+/// off briefly instead of spinning.
+async fn accept_error(e: io::Error) {
+    warn!("Failed to accept connection: {}", e);
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+}
@@ -0,0 +1,176 @@
+// image-compressor-rust-service/src/server/metrics_endpoint.rs
+
+//! The Prometheus `/metrics` endpoint.
+//!
+//! By default it is served on the main listener. With `metrics.bind_addr`
+//! set it moves to a listener of its own, typically on an internal
+//! interface, and disappears from the public one. Either way it can require
+//! HTTP basic auth.
+//!
+//! The recorder behind it is set up from the same config: a name prefix,
+//! global labels and histogram buckets. With the `statsd` backend, metrics
+//! are pushed to a StatsD agent instead and there is no `/metrics`.
+
+use super::statsd::{StatsdConfig, StatsdRecorder};
+use super::{constant_time_eq, request_id, ApiError, AppState};
+use anyhow::{Context, Result};
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use base64::Engine;
+use metrics::Recorder;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use metrics_util::layers::{Layer, PrefixLayer};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use tracing::warn;
+
+/// Where metrics go.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsBackend {
+    /// Scraped from `/metrics`.
+    #[default]
+    Prometheus,
+    /// Pushed to a StatsD or DogStatsD agent.
+    Statsd,
+}
+
+/// Settings for metrics and `/metrics`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub backend: MetricsBackend,
+    /// The agent used by the `statsd` backend.
+    pub statsd: StatsdConfig,
+    /// Serve `/metrics` only on this address instead of the main listener.
+    pub bind_addr: Option<SocketAddr>,
+    /// Credentials required to read the metrics.
+    pub basic_auth: Option<BasicAuth>,
+    /// Prepended to every metric name, e.g. `imgsvc` turns
+    /// `compress_requests_total` into `imgsvc_compress_requests_total`.
+    pub prefix: Option<String>,
+    /// Labels added to every metric, e.g. `service`, `region`, `instance`.
+    pub global_labels: BTreeMap<String, String>,
+    /// Upper bounds of the buckets of every histogram. Without them,
+    /// histograms are rendered as summaries with quantiles.
+    pub buckets: Vec<f64>,
+    /// Buckets for metrics whose name ends with the key, e.g.
+    /// `compress_queue_seconds = [0.001, 0.01, 0.1, 1.0]`. These win over
+    /// `buckets`.
+    pub metric_buckets: BTreeMap<String, Vec<f64>>,
+}
+
+impl MetricsConfig {
+    /// Whether `/metrics` is served.
+    pub fn serves_endpoint(&self) -> bool {
+        self.backend == MetricsBackend::Prometheus
+    }
+
+    /// Builds the recorder of the configured backend, wrapped to apply the
+    /// prefix, and the handle that renders `/metrics`. With the `statsd`
+    /// backend the handle renders nothing.
+    pub fn recorder(&self) -> Result<(Box<dyn Recorder>, PrometheusHandle)> {
+        if self.backend == MetricsBackend::Statsd {
+            let recorder = StatsdRecorder::new(&self.statsd, &self.global_labels)?;
+            let unused = PrometheusBuilder::new().build_recorder().handle();
+            return Ok((self.prefixed(recorder), unused));
+        }
+
+        let mut builder = PrometheusBuilder::new();
+        for (key, value) in &self.global_labels {
+            builder = builder.add_global_label(key, value);
+        }
+        if !self.buckets.is_empty() {
+            builder = builder
+                .set_buckets(&self.buckets)
+                .context("Invalid metrics.buckets")?;
+        }
+        for (name, buckets) in &self.metric_buckets {
+            builder = builder
+                .set_buckets_for_metric(Matcher::Suffix(name.clone()), buckets)
+                .with_context(|| format!("Invalid metrics.metric_buckets.{}", name))?;
+        }
+        let recorder = builder.build_recorder();
+        let handle = recorder.handle();
+        Ok((self.prefixed(recorder), handle))
+    }
+
+    fn prefixed(&self, recorder: impl Recorder + 'static) -> Box<dyn Recorder> {
+        match self.prefix.as_deref().filter(|p| !p.is_empty()) {
+            Some(prefix) => Box::new(PrefixLayer::new(prefix).layer(recorder)),
+            None => Box::new(recorder),
+        }
+    }
+
+    /// Installs the recorder globally and returns its handle.
+    pub fn install(&self) -> Result<PrometheusHandle> {
+        let (recorder, handle) = self.recorder()?;
+        metrics::set_boxed_recorder(recorder).context("A metrics recorder is already installed")?;
+        Ok(handle)
+    }
+}
+
+/// HTTP basic auth credentials.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: String,
+}
+
+/// A router serving only `/metrics`, for the separate metrics listener.
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(state)
+}
+
+/// Renders the Prometheus metrics collected by the recorder.
+pub async fn metrics_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    if let Some(credentials) = &state.config.metrics.basic_auth {
+        if !authorized(&headers, credentials) {
+            let request_id = request_id(&headers).to_owned();
+            warn!(
+                request_id,
+                "Rejected /metrics call with missing or wrong credentials."
+            );
+            let mut response = ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+                "Valid metrics credentials are required.",
+            )
+            .with_request_id(request_id)
+            .into_response();
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Basic realm=\"metrics\""),
+            );
+            return Ok(response);
+        }
+    }
+
+    let body = state.metrics.render();
+    Ok((StatusCode::OK, [(header::CONTENT_TYPE, "text/plain")], body).into_response())
+}
+
+fn authorized(headers: &HeaderMap, credentials: &BasicAuth) -> bool {
+    let Some(given) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Basic "))
+        .and_then(|v| base64::engine::general_purpose::STANDARD.decode(v).ok())
+    else {
+        return false;
+    };
+    let expected = format!("{}:{}", credentials.username, credentials.password);
+    constant_time_eq(&given, expected.as_bytes())
+}
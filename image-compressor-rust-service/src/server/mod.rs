@@ -0,0 +1,295 @@
+// image-compressor-rust-service/src/server/mod.rs
+
+pub mod admin;
+mod analyze;
+pub mod audit;
+pub mod coalesce;
+mod compress;
+pub mod concurrency;
+mod contact_sheet;
+pub mod cors;
+mod diff;
+pub mod error;
+pub mod error_reporting;
+mod favicon;
+pub mod headers;
+mod health;
+pub mod hooks;
+pub mod idempotency;
+mod info;
+pub mod json_body;
+pub mod limits;
+pub mod listen;
+pub mod metrics_endpoint;
+pub mod options;
+pub mod peers;
+pub mod range;
+mod redis;
+pub mod routing;
+pub mod scan;
+pub mod selftest;
+pub mod skip;
+pub mod slow_log;
+mod social;
+pub mod statsd;
+mod tune;
+pub mod tus;
+mod ui;
+mod watermark;
+
+use crate::config::Config;
+use crate::cpu::{BackgroundPool, CpuBudget};
+use audit::AuditLog;
+use axum::{
+    extract::{DefaultBodyLimit, Request},
+    http::HeaderMap,
+    middleware,
+    routing::{get, post},
+    Router,
+};
+use coalesce::Coalescer;
+use concurrency::Limiters;
+use hooks::Hooks;
+use idempotency::IdempotencyCache;
+use image::DynamicImage;
+use metrics_exporter_prometheus::PrometheusHandle;
+use peers::Peers;
+use routing::Workers;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tower::util::option_layer;
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::TraceLayer;
+use tus::Uploads;
+
+pub use error::ApiError;
+
+/// State shared by all request handlers.
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub metrics: Arc<PrometheusHandle>,
+    pub idempotency: Arc<IdempotencyCache>,
+    /// Identical `/compress` requests in flight.
+    pub coalescer: Arc<Coalescer>,
+    /// The other replicas work is shared with.
+    pub peers: Arc<Peers>,
+    /// The workers `/compress` is routed to in router mode.
+    pub workers: Arc<Workers>,
+    pub audit: Arc<AuditLog>,
+    /// Webhooks called before and after each `/compress` request.
+    pub hooks: Arc<Hooks>,
+    /// Resumable uploads on `/uploads`.
+    pub uploads: Arc<Uploads>,
+    /// Threads available to compressions.
+    pub cpu: CpuBudget,
+    /// Slots of the endpoints with a concurrency limit of their own.
+    pub concurrency: Arc<Limiters>,
+    /// Low-priority threads for background jobs.
+    pub background: BackgroundPool,
+    /// Set once the startup warm-up has finished; reported by `/ready`.
+    pub ready: Arc<AtomicBool>,
+}
+
+impl AppState {
+    pub fn new(config: Config, metrics: PrometheusHandle) -> Self {
+        Self {
+            idempotency: Arc::new(IdempotencyCache::new(&config.idempotency)),
+            coalescer: Arc::new(Coalescer::new()),
+            peers: Arc::new(Peers::new(&config.peers)),
+            workers: Arc::new(Workers::new(&config.routing)),
+            audit: Arc::new(AuditLog::new(&config.audit)),
+            hooks: Arc::new(Hooks::new(&config.hooks)),
+            uploads: Arc::new(Uploads::new(&config.uploads)),
+            cpu: CpuBudget::new(&config.cpu),
+            concurrency: Arc::new(Limiters::new(&config.concurrency)),
+            background: BackgroundPool::new(&config.cpu.background),
+            config: Arc::new(config),
+            metrics: Arc::new(metrics),
+            ready: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Marks the service as ready to take traffic.
+    pub fn mark_ready(&self) {
+        self.ready.store(true, Ordering::Release);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+}
+
+/// Every route the router may serve, as registered.
+pub const ROUTES: [&str; 21] = [
+    "/compress",
+    "/diff",
+    "/analyze/histogram",
+    "/inspect",
+    "/favicon",
+    "/icons/:set",
+    "/presets/:name/tune",
+    "/health",
+    "/ready",
+    "/load",
+    "/metrics",
+    "/uploads",
+    "/uploads/:id",
+    "/ui",
+    "/watermark/detect",
+    "/social-card",
+    "/contact-sheet",
+    "/selftest",
+    "/version",
+    "/formats",
+    "/admin/validate-config",
+];
+
+/// Whether a request to `path` would reach one of the [`ROUTES`].
+pub fn is_route(path: &str) -> bool {
+    ROUTES.iter().any(|route| {
+        let mut segments = path.split('/');
+        route.split('/').all(|expected| {
+            segments
+                .next()
+                .is_some_and(|segment| expected.starts_with(':') || segment == expected)
+        }) && segments.next().is_none()
+    })
+}
+
+/// Header carrying the request ID, set by the request-id middleware when
+/// the client sends none and echoed in every response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Returns the request ID assigned by the request-id middleware.
+pub fn request_id(headers: &HeaderMap) -> &str {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("unknown")
+}
+
+/// Decodes a still image like `/compress` does, in the sandbox if it is
+/// enabled. Failures are `422`s saying why (see [`ApiError::unprocessable_input`]).
+pub(crate) fn decode_still(input: &[u8], config: &Config) -> Result<DynamicImage, ApiError> {
+    let decoded = match Some(&config.sandbox).filter(|s| s.enabled) {
+        Some(sandbox) => crate::sandbox::decode_image_sandboxed(input, sandbox),
+        None => crate::decode_image(input),
+    };
+    decoded
+        .map_err(|e| ApiError::unprocessable_input(format!("Failed to decode image: {:#}", e), &e))
+}
+
+/// Compares two secrets in time independent of where they differ.
+pub(crate) fn constant_time_eq(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected)
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Builds the application router with all routes and middleware.
+///
+/// `/metrics` is left out when it has a listener of its own or metrics go to
+/// StatsD (see [`metrics_endpoint`]), the tus `/uploads` routes unless
+/// `uploads` is enabled (see [`tus`]), and `/social-card` and
+/// `/contact-sheet` unless `[social]` has a font, and `/watermark/detect`
+/// without a `[watermark]` secret.
+pub fn router(state: AppState) -> Router {
+    let compress_responses = state.config.compress_responses;
+    let logging = state.config.logging.clone();
+    let cors = state
+        .config
+        .cors
+        .layer()
+        .expect("CORS settings are checked by Config::validate");
+
+    // gzip/br for JSON and metrics responses, negotiated via `Accept-Encoding`.
+    // Image bodies are already compressed, so they are always sent as-is.
+    let compression = CompressionLayer::new()
+        .gzip(compress_responses)
+        .br(compress_responses)
+        .compress_when(
+            SizeAbove::new(32)
+                .and(NotForContentType::IMAGES)
+                .and(NotForContentType::const_new("application/octet-stream"))
+                .and(NotForContentType::const_new("application/zip")),
+        );
+
+    let compress = if state.config.routing.enabled() {
+        post(routing::route_handler)
+    } else {
+        post(compress::compress_handler)
+    };
+    let mut router = Router::new()
+        .route("/compress", compress)
+        .route("/diff", post(diff::diff_handler))
+        .route("/analyze/histogram", post(analyze::histogram_handler))
+        .route("/inspect", post(analyze::inspect_handler))
+        .route("/favicon", post(favicon::favicon_handler))
+        .route("/icons/:set", post(favicon::icons_handler))
+        .route("/presets/:name/tune", post(tune::tune_handler))
+        .route("/health", get(health::health_handler))
+        .route("/ready", get(health::ready_handler))
+        .route("/load", get(peers::load_handler));
+    if state.config.metrics.serves_endpoint() && state.config.metrics.bind_addr.is_none() {
+        router = router.route("/metrics", get(metrics_endpoint::metrics_handler));
+    }
+    if state.config.uploads.enabled {
+        router = router.merge(tus::routes());
+    }
+    if state.config.ui {
+        router = router.route("/ui", get(ui::ui_handler));
+    }
+    if state.config.watermark.secret.is_some() {
+        router = router.route("/watermark/detect", post(watermark::detect_handler));
+    }
+    if state.config.social.font.is_some() {
+        router = router
+            .route("/social-card", post(social::social_card_handler))
+            .route("/contact-sheet", post(contact_sheet::contact_sheet_handler));
+    }
+    router
+        .route("/selftest", get(selftest::selftest_handler))
+        .route(
+            "/admin/validate-config",
+            post(admin::validate_config_handler),
+        )
+        .route("/version", get(info::version_handler))
+        .route("/formats", get(info::formats_handler))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            concurrency::enforce_concurrency,
+        ))
+        .layer(compression)
+        .layer(
+            TraceLayer::new_for_http().make_span_with(move |request: &Request| {
+                let redact = crate::logging::redaction();
+                let request_id = request_id(request.headers());
+                tracing::info_span!(
+                    "request",
+                    method = %request.method(),
+                    uri = %redact.uri(request.uri()),
+                    request_id = %request_id,
+                    tenant = limits::api_key(request.headers()).map(|key| redact.tenant(key).into_owned()),
+                    sampled = logging.sampled(request_id),
+                )
+            }),
+        )
+        // Limits are enforced (per route and API key) by `enforce_body_limit`.
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            limits::enforce_body_limit,
+        ))
+        .layer(DefaultBodyLimit::disable())
+        .layer(option_layer(cors))
+        // Outermost: accept the caller's `X-Request-Id` or generate one, and
+        // echo it back on the response.
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .with_state(state)
+}
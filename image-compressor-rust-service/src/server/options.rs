@@ -0,0 +1,364 @@
+// image-compressor-rust-service/src/server/options.rs
+
+//! The options of a `/compress` request, from every place they can be sent.
+//!
+//! Every option header can also be sent as a query parameter named like
+//! the preset field, e.g. `?quality=60&width=800&fit=cover` for
+//! `X-Compression-Quality`, `X-Width` and `X-Fit`, for gateways that drop
+//! or rename custom headers. The precedence, lowest first:
+//!
+//! 1. the preset named by `X-Preset` (or `preset`), configured or built in;
+//! 2. headers;
+//! 3. query parameters;
+//! 4. the `options` of a JSON body (see [`super::json_body`]).
+//!
+//! The [`Options`] extractor merges them into one [`CompressionOptions`].
+//! Unknown parameters and presets, values that cannot be used or are out of
+//! range, options that modify one that is not set (`dither` without
+//! `palette_colors`) and options that contradict each other (`aspect` with
+//! both `width` and `height`, a `min_width` above `width`) are a `400` with
+//! code `invalid_options`, listing every offending field in
+//! `details.fields`, e.g.
+//! `[{"field": "fit", "message": "expected contain, cover, fill or pad, got 'sideways'"}]`.
+//! Endpoints taking compression options should extract them with it.
+//!
+//! With `lenient_options` set, those options are ignored instead, or
+//! clamped when they are numbers, as before strict validation.
+//!
+//! The query parameters are also merged into the request's headers, so
+//! that requests forwarded to peers or workers keep them
+//! ([`OptionHeaders`] does only that).
+
+use super::compress::{
+    DOWNLOAD_HEADER, FILENAME_HEADER, FILENAME_TEMPLATE_HEADER, PRIORITY_HEADER,
+};
+use super::json_body::JsonInput;
+use super::limits::{is_json, BodyLimit};
+use super::skip::{
+    FORCE_ENCODE_HEADER, ONLY_IF_LARGER_HEADER, SKIP_IF_SMALLER_THAN_HEADER, SKIP_RESPONSE_HEADER,
+};
+use super::{request_id, ApiError, AppState};
+use crate::config::Config;
+use crate::options::{CompressionOptions, FieldError};
+use crate::preset::Preset;
+use crate::timing::Timings;
+use axum::{
+    async_trait,
+    body::{to_bytes, Bytes},
+    extract::{FromRequest, FromRequestParts, Query, Request},
+    http::{request::Parts, HeaderMap, HeaderValue, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+use std::time::Instant;
+use tracing::debug;
+
+/// Request header naming the preset the other options apply on top of.
+pub const PRESET_HEADER: &str = "X-Preset";
+
+/// Query parameters and the headers they stand for.
+pub const QUERY_OPTIONS: [(&str, &str); 44] = [
+    ("preset", PRESET_HEADER),
+    ("quality", "X-Compression-Quality"),
+    ("quality_scale", "X-Quality-Scale"),
+    ("animation", "X-Animation"),
+    ("max_frames", "X-Max-Frames"),
+    ("frame_step", "X-Frame-Step"),
+    ("max_duration", "X-Max-Duration"),
+    ("poster_frame", "X-Poster-Frame"),
+    ("rotate", "X-Rotate"),
+    ("width", "X-Width"),
+    ("height", "X-Height"),
+    ("fit", "X-Fit"),
+    ("aspect", "X-Aspect"),
+    ("gravity", "X-Gravity"),
+    ("background", "X-Background"),
+    ("filter", "X-Filter"),
+    ("palette_colors", "X-Palette-Colors"),
+    ("dither", "X-Dither"),
+    ("color_space", "X-Color-Space"),
+    ("grayscale", "X-Grayscale"),
+    ("placeholder", "X-Placeholder"),
+    ("border_width", "X-Border-Width"),
+    ("border_color", "X-Border-Color"),
+    ("corner_radius", "X-Corner-Radius"),
+    ("allow_upscale", "X-Allow-Upscale"),
+    ("salvage", "X-Salvage"),
+    ("metadata_copyright", "X-Metadata-Copyright"),
+    ("metadata_exif", "X-Metadata-Exif"),
+    ("metadata_xmp", "X-Metadata-Xmp"),
+    ("deterministic", "X-Deterministic"),
+    ("watermark", "X-Watermark"),
+    ("max_bytes", "X-Max-Bytes"),
+    ("min_quality", "X-Min-Quality"),
+    ("min_width", "X-Min-Width"),
+    ("min_height", "X-Min-Height"),
+    ("effort", "X-Effort"),
+    ("skip_if_smaller_than", SKIP_IF_SMALLER_THAN_HEADER),
+    ("only_if_larger", ONLY_IF_LARGER_HEADER),
+    ("force_encode", FORCE_ENCODE_HEADER),
+    ("skip_response", SKIP_RESPONSE_HEADER),
+    ("priority", PRIORITY_HEADER),
+    ("filename", FILENAME_HEADER),
+    ("download", DOWNLOAD_HEADER),
+    ("filename_template", FILENAME_TEMPLATE_HEADER),
+];
+
+/// A request's options, resolved from every source, and its image.
+#[derive(Debug)]
+pub struct Options {
+    /// The request's headers, with its query parameters merged in.
+    pub headers: HeaderMap,
+    pub options: CompressionOptions,
+    /// The image: the body, or the image of a JSON body.
+    pub input: Bytes,
+    /// Set for JSON requests, which get JSON responses.
+    pub json: Option<JsonInput>,
+    /// The time spent reading the body, with that of the middleware.
+    pub timings: Timings,
+}
+
+/// Why [`Options`] could not be extracted, with the body, which the audit
+/// log still records. It is empty when the request was rejected before the
+/// body was read.
+#[derive(Debug)]
+pub struct OptionsRejection {
+    pub error: ApiError,
+    pub body: Bytes,
+}
+
+impl IntoResponse for OptionsRejection {
+    fn into_response(self) -> Response {
+        self.error.into_response()
+    }
+}
+
+impl Options {
+    /// Resolves the options of a request to `uri` (whose query it reads)
+    /// with these headers and an already read body.
+    pub fn resolve(
+        config: &Config,
+        uri: &Uri,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Result<Self, OptionsRejection> {
+        let request_id = request_id(&headers).to_owned();
+        let (headers, options) =
+            Self::head(config, uri, headers).map_err(|e| OptionsRejection {
+                error: e.with_request_id(&request_id),
+                body: body.clone(),
+            })?;
+        Self::finish(config, uri, headers, options, body)
+    }
+
+    /// The headers, with the query merged in, and the options they set,
+    /// checked before the body is read. Only the options of a JSON body are
+    /// left to [`finish`](Self::finish).
+    fn head(
+        config: &Config,
+        uri: &Uri,
+        headers: HeaderMap,
+    ) -> Result<(HeaderMap, CompressionOptions), ApiError> {
+        let headers = merge_query(headers, uri, config.lenient_options)?;
+        let preset = headers
+            .get(PRESET_HEADER)
+            .map(|v| v.to_str().unwrap_or_default().trim());
+        let (options, errors) = base_options(config, preset, &headers);
+        if is_json(&headers) {
+            if !errors.is_empty() && !config.lenient_options {
+                return Err(invalid_options(errors));
+            }
+        } else {
+            check(config, &options, errors)?;
+        }
+        Ok((headers, options))
+    }
+
+    /// Adds the options of a JSON body to those of the [`head`](Self::head).
+    fn finish(
+        config: &Config,
+        uri: &Uri,
+        headers: HeaderMap,
+        mut options: CompressionOptions,
+        body: Bytes,
+    ) -> Result<Self, OptionsRejection> {
+        let reject = |error: ApiError, body: &Bytes| OptionsRejection {
+            error: error.with_request_id(request_id(&headers)),
+            body: body.clone(),
+        };
+        let json = is_json(&headers)
+            .then(|| {
+                let (limit, _) =
+                    config
+                        .body_limits
+                        .limit_for(config.max_body_bytes, uri.path(), &headers);
+                JsonInput::parse(&body, limit)
+            })
+            .transpose()
+            .map_err(|e| reject(e, &body))?;
+        if let Some(json) = &json {
+            let mut errors = Vec::new();
+            if let Some(preset) = &json.preset {
+                (options, errors) = base_options(config, Some(preset), &headers);
+            }
+            let (preset, fields) = Preset::from_json(&json.options);
+            preset.apply(&mut options);
+            errors.extend(fields);
+            check(config, &options, errors).map_err(|e| reject(e, &body))?;
+        }
+
+        let input = match &json {
+            Some(json) => json.image.clone(),
+            None => body,
+        };
+        Ok(Self {
+            headers,
+            options,
+            input,
+            json,
+            timings: Timings::new(),
+        })
+    }
+}
+
+/// The options of the preset named `preset` with those of the headers on
+/// top, and the errors found in either.
+fn base_options(
+    config: &Config,
+    preset: Option<&str>,
+    headers: &HeaderMap,
+) -> (CompressionOptions, Vec<FieldError>) {
+    let mut errors = Vec::new();
+    let mut options = match preset.map(|name| (name, config.preset(name))) {
+        None => CompressionOptions::default(),
+        Some((_, Some(preset))) => preset.options(),
+        Some((name, None)) => {
+            errors.push(FieldError::new(
+                "preset",
+                format!("no preset named '{}'", name),
+            ));
+            CompressionOptions::default()
+        }
+    };
+    errors.extend(options.read_headers(headers));
+    (options, errors)
+}
+
+/// Fails with the `errors` found so far and those of the final `options`.
+pub(super) fn check(
+    config: &Config,
+    options: &CompressionOptions,
+    mut errors: Vec<FieldError>,
+) -> Result<(), ApiError> {
+    errors.extend(options.conflicts(config.max_dimensions()));
+    if config.lenient_options && !errors.is_empty() {
+        debug!(?errors, "Ignoring invalid options.");
+        errors.clear();
+    }
+    if options.ocr && !config.ocr.enabled {
+        errors.push(FieldError::new("ocr", "needs [ocr] to be enabled"));
+    }
+    if options.watermark.is_some() && config.watermark.secret.is_none() {
+        errors.push(FieldError::new(
+            "watermark",
+            "needs a [watermark] secret to be configured",
+        ));
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(invalid_options(errors))
+    }
+}
+
+/// Checks the headers and query, then reads the body within the limit set
+/// by [`super::limits::enforce_body_limit`], so that a request with invalid
+/// options is rejected without reading any of it.
+#[async_trait]
+impl FromRequest<AppState> for Options {
+    type Rejection = OptionsRejection;
+
+    async fn from_request(request: Request, state: &AppState) -> Result<Self, Self::Rejection> {
+        let (mut parts, body) = request.into_parts();
+        let request_id = request_id(&parts.headers).to_owned();
+        let reject = |error: ApiError| OptionsRejection {
+            error: error.with_request_id(&request_id),
+            body: Bytes::new(),
+        };
+        let mut timings = parts.extensions.remove::<Timings>().unwrap_or_default();
+        let (headers, options) =
+            Self::head(&state.config, &parts.uri, parts.headers).map_err(reject)?;
+        let body = match parts.extensions.get::<BodyLimit>() {
+            Some(limit) => {
+                let read_start = Instant::now();
+                let body = limit.read(body, &request_id).await.map_err(reject)?;
+                timings.push("read", read_start.elapsed());
+                body
+            }
+            None => to_bytes(body, usize::MAX).await.map_err(|e| {
+                reject(ApiError::bad_request(format!(
+                    "Failed to read request body: {}",
+                    e
+                )))
+            })?,
+        };
+        let options = Self::finish(&state.config, &parts.uri, headers, options, body)?;
+        Ok(Self { timings, ..options })
+    }
+}
+
+/// The request's headers, with the options of its query string merged in,
+/// for requests passed on as they are.
+#[derive(Debug, Clone, Default)]
+pub struct OptionHeaders(pub HeaderMap);
+
+#[async_trait]
+impl FromRequestParts<AppState> for OptionHeaders {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let lenient = state.config.lenient_options;
+        merge_query(parts.headers.clone(), &parts.uri, lenient)
+            .map(Self)
+            .map_err(|e| e.with_request_id(request_id(&parts.headers)))
+    }
+}
+
+/// Sets the header of every option in the query of `uri`, replacing the
+/// one sent. Parameters given more than once take their last value.
+/// Unknown parameters are an error unless `lenient`.
+fn merge_query(mut headers: HeaderMap, uri: &Uri, lenient: bool) -> Result<HeaderMap, ApiError> {
+    let Query(query) = Query::<Vec<(String, String)>>::try_from_uri(uri)
+        .map_err(|e| ApiError::bad_request(format!("Invalid query string: {}", e.body_text())))?;
+    let mut errors = Vec::new();
+    for (name, value) in query {
+        let Some(&(_, header)) = QUERY_OPTIONS.iter().find(|(option, _)| *option == name) else {
+            errors.push(FieldError::new(name, "unknown option"));
+            continue;
+        };
+        match HeaderValue::from_str(&value) {
+            Ok(value) => {
+                headers.insert(header, value);
+            }
+            Err(_) => errors.push(FieldError::new(name, "invalid characters")),
+        }
+    }
+    if errors.is_empty() || lenient {
+        Ok(headers)
+    } else {
+        Err(invalid_options(errors))
+    }
+}
+
+/// The `400` listing the fields in `errors`.
+fn invalid_options(errors: Vec<FieldError>) -> ApiError {
+    ApiError::new(
+        StatusCode::BAD_REQUEST,
+        "invalid_options",
+        format!("{} invalid option(s).", errors.len()),
+    )
+    .with_details(json!({ "fields": errors }))
+}
@@ -0,0 +1,251 @@
+// image-compressor-rust-service/src/server/peers.rs
+
+//! Work sharing between replicas.
+//!
+//! With `[peers] urls` set, every replica polls the `/load` of the others
+//! and forwards the `/compress` requests that would wait for a thread, or
+//! whose input has at least `forward_min_pixels`, to the least loaded peer,
+//! provided that peer is less loaded than itself. A burst of panoramas on
+//! one pod is spread over the idle ones instead of queueing behind itself.
+//!
+//! Forwarded requests carry `X-Peer-Forwarded` and are never forwarded
+//! again, so a request makes at most one hop. A peer that does not answer a
+//! poll is left out until it does, and a request whose forward fails is
+//! compressed locally.
+
+use super::AppState;
+use crate::cpu::CpuBudget;
+use crate::logging::redaction;
+use anyhow::{Context, Result};
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, HeaderMap, HeaderValue},
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Request header marking a request forwarded by a peer.
+pub const FORWARDED_HEADER: &str = "X-Peer-Forwarded";
+
+/// Response header naming the peer that served a forwarded request.
+pub const PEER_HEADER: &str = "X-Peer";
+
+/// Headers that describe one connection and are not passed on.
+const HOP_BY_HOP: [header::HeaderName; 5] = [
+    header::CONNECTION,
+    header::CONTENT_LENGTH,
+    header::HOST,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+/// The other replicas, and when to send them work. Sharing is off unless
+/// `urls` is set.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeersConfig {
+    /// Base URLs of the other replicas, e.g. `http://compressor-1:8000`.
+    pub urls: Vec<String>,
+    /// How often their load is polled, in milliseconds. A load is ignored
+    /// once it is three intervals old.
+    pub poll_interval_ms: u64,
+    /// Forward inputs with at least this many pixels whenever a peer is
+    /// less loaded, `0` to forward by load only.
+    pub forward_min_pixels: u64,
+    /// Timeout of a forwarded request, in milliseconds.
+    pub timeout_ms: u64,
+}
+
+impl Default for PeersConfig {
+    fn default() -> Self {
+        Self {
+            urls: Vec::new(),
+            poll_interval_ms: 1_000,
+            forward_min_pixels: 24_000_000,
+            timeout_ms: 60_000,
+        }
+    }
+}
+
+impl PeersConfig {
+    pub fn validate(&self) -> Result<()> {
+        for url in &self.urls {
+            reqwest::Url::parse(url).with_context(|| format!("Invalid peer URL '{}'", url))?;
+        }
+        Ok(())
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms.max(1))
+    }
+}
+
+/// How busy a replica is, as served on `/load`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Load {
+    /// Requests waiting for a thread.
+    pub queued: usize,
+    /// Threads free right now.
+    pub available: usize,
+}
+
+impl Load {
+    pub fn of(cpu: &CpuBudget) -> Self {
+        Self {
+            queued: cpu.queued(),
+            available: cpu.available(),
+        }
+    }
+
+    /// Lower is less loaded: queued requests count against a replica and
+    /// free threads for it.
+    fn score(self) -> i64 {
+        self.queued as i64 - self.available as i64
+    }
+}
+
+/// The last known load of every peer.
+pub struct Peers {
+    config: PeersConfig,
+    client: reqwest::Client,
+    loads: Mutex<HashMap<String, (Instant, Load)>>,
+}
+
+impl Peers {
+    pub fn new(config: &PeersConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+            .expect("Failed to build the peer client");
+        Self {
+            config: config.clone(),
+            client,
+            loads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.config.urls.is_empty()
+    }
+
+    /// Fetches the load of every peer. Peers that do not answer are
+    /// forgotten until they do.
+    pub async fn poll(&self) {
+        let mut polls = tokio::task::JoinSet::new();
+        for url in &self.config.urls {
+            let request = self
+                .client
+                .get(format!("{}/load", url.trim_end_matches('/')))
+                .timeout(self.config.poll_interval());
+            let url = url.clone();
+            polls.spawn(async move {
+                let load = match request.send().await.and_then(|r| r.error_for_status()) {
+                    Ok(response) => response.json::<Load>().await,
+                    Err(e) => Err(e),
+                };
+                (url, load)
+            });
+        }
+        while let Some(Ok((url, load))) = polls.join_next().await {
+            let mut loads = self.loads.lock().unwrap();
+            match load {
+                Ok(load) => {
+                    loads.insert(url, (Instant::now(), load));
+                }
+                Err(e) => {
+                    if loads.remove(&url).is_some() {
+                        let redact = redaction();
+                        warn!(
+                            peer = %redact.url(&url),
+                            "Peer stopped answering load polls: {}",
+                            redact.request_error(e)
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// The peer a request should be forwarded to, if any.
+    pub fn pick(&self, headers: &HeaderMap, body: &[u8], own: Load) -> Option<String> {
+        if !self.enabled() || headers.contains_key(FORWARDED_HEADER) {
+            return None;
+        }
+        let oversized = self.config.forward_min_pixels > 0
+            && crate::decode::probe_dimensions(body).is_ok_and(|(width, height)| {
+                u64::from(width) * u64::from(height) >= self.config.forward_min_pixels
+            });
+        if own.available > 0 && !oversized {
+            return None;
+        }
+        let stale = self.config.poll_interval() * 3;
+        let loads = self.loads.lock().unwrap();
+        loads
+            .iter()
+            .filter(|(_, (polled, load))| polled.elapsed() < stale && load.score() < own.score())
+            .min_by_key(|(url, (_, load))| (load.score(), url.as_str()))
+            .map(|(url, _)| url.clone())
+    }
+
+    /// Sends the request to `peer`'s `/compress` and returns its response,
+    /// marked with [`PEER_HEADER`].
+    pub async fn forward(&self, peer: &str, headers: &HeaderMap, body: Bytes) -> Result<Response> {
+        let mut forwarded = headers.clone();
+        forwarded.insert(FORWARDED_HEADER, HeaderValue::from_static("true"));
+        let mut response = proxy(&self.client, peer, forwarded, body).await?;
+        if let Ok(value) = HeaderValue::from_str(peer) {
+            response.headers_mut().insert(PEER_HEADER, value);
+        }
+        Ok(response)
+    }
+}
+
+/// POSTs a `/compress` request to the replica at `base_url` and returns its
+/// response, leaving out the headers of either connection.
+pub(super) async fn proxy(
+    client: &reqwest::Client,
+    base_url: &str,
+    mut headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response> {
+    for name in &HOP_BY_HOP {
+        headers.remove(name);
+    }
+    let response = client
+        .post(format!("{}/compress", base_url.trim_end_matches('/')))
+        .headers(headers)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| redaction().request_error(e))
+        .with_context(|| format!("Failed to forward to {}", redaction().url(base_url)))?;
+
+    let mut builder = Response::builder().status(response.status());
+    for (name, value) in response.headers() {
+        if !HOP_BY_HOP.contains(name) {
+            builder = builder.header(name, value);
+        }
+    }
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| redaction().request_error(e))
+        .with_context(|| {
+            format!(
+                "Failed to read the response of {}",
+                redaction().url(base_url)
+            )
+        })?;
+    Ok(builder.body(Body::from(body))?)
+}
+
+/// Reports this replica's [`Load`] to its peers.
+pub async fn load_handler(State(state): State<AppState>) -> Json<Load> {
+    Json(Load::of(&state.cpu))
+}
@@ -0,0 +1,116 @@
+// image-compressor-rust-service/src/server/range.rs
+
+//! Byte range requests for stored results, so download managers can resume
+//! large outputs.
+//!
+//! A single `Range: bytes=...` range is served as `206 Partial Content`.
+//! Several ranges, malformed ranges and an `If-Range` that does not match
+//! the result's `ETag` get the whole result, as RFC 9110 allows.
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+
+/// A strong `ETag` for `data`.
+pub fn etag(data: &[u8]) -> String {
+    let digest = format!("{:x}", Sha256::digest(data));
+    format!("\"{}\"", &digest[..32])
+}
+
+/// Answers a `GET` for `data`, honouring a `Range` header. `etag` is the
+/// result's strong entity tag, compared against `If-Range`.
+pub fn serve(headers: &HeaderMap, data: Bytes, etag: Option<&str>) -> Response {
+    let len = data.len() as u64;
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|_| if_range_matches(headers, etag))
+        .and_then(|range| parse(range, len));
+
+    let mut response = match range {
+        None => Body::from(data).into_response(),
+        Some(Err(Unsatisfiable)) => {
+            let mut response = StatusCode::RANGE_NOT_SATISFIABLE.into_response();
+            insert(
+                &mut response,
+                header::CONTENT_RANGE,
+                format!("bytes */{}", len),
+            );
+            response
+        }
+        Some(Ok((start, end))) => {
+            let part = data.slice(start as usize..=end as usize);
+            let mut response = (StatusCode::PARTIAL_CONTENT, Body::from(part)).into_response();
+            insert(
+                &mut response,
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, len),
+            );
+            response
+        }
+    };
+    response
+        .headers_mut()
+        .insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Some(etag) = etag {
+        insert(&mut response, header::ETAG, etag.to_string());
+    }
+    response
+}
+
+/// The range cannot be served from a result of this length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unsatisfiable;
+
+/// Parses a single `bytes=` range into inclusive offsets. Returns `None`
+/// when the header should be ignored.
+pub fn parse(range: &str, len: u64) -> Option<Result<(u64, u64), Unsatisfiable>> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    let range = if first.is_empty() {
+        // The last `last` bytes.
+        let suffix: u64 = last.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(Err(Unsatisfiable));
+        }
+        (len.saturating_sub(suffix), len - 1)
+    } else {
+        let start: u64 = first.parse().ok()?;
+        let end = match last {
+            "" => u64::MAX,
+            last => last.parse().ok()?,
+        };
+        if end < start {
+            return None;
+        }
+        if start >= len {
+            return Some(Err(Unsatisfiable));
+        }
+        (start, end.min(len - 1))
+    };
+    Some(Ok(range))
+}
+
+/// Whether a `Range` may be honoured given the request's `If-Range`. Only
+/// entity tags are compared; a date never matches, as results carry no
+/// `Last-Modified`.
+fn if_range_matches(headers: &HeaderMap, etag: Option<&str>) -> bool {
+    match headers.get(header::IF_RANGE).and_then(|v| v.to_str().ok()) {
+        None => true,
+        Some(condition) => etag.is_some_and(|etag| condition.trim() == etag),
+    }
+}
+
+fn insert(response: &mut Response, name: header::HeaderName, value: String) {
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        response.headers_mut().insert(name, value);
+    }
+}
@@ -0,0 +1,177 @@
+// image-compressor-rust-service/src/server/redis.rs
+
+//! A minimal Redis client: one lazily opened connection speaking RESP2,
+//! enough for the handful of commands the service sends.
+
+use anyhow::{bail, Context, Result};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+/// How long connecting or a single command may take.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A reply to a command. Array replies are not used and not supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Nil,
+    Status(String),
+    Int(i64),
+    Bulk(Vec<u8>),
+}
+
+/// Where to connect, from a `redis://[[user]:password@]host[:port][/db]` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedisUrl {
+    pub addr: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub db: u32,
+}
+
+impl RedisUrl {
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix("redis://")
+            .context("Redis URLs start with redis://")?;
+        let (credentials, rest) = match rest.rsplit_once('@') {
+            Some((credentials, rest)) => (Some(credentials), rest),
+            None => (None, rest),
+        };
+        let (host, db) = match rest.split_once('/') {
+            Some((host, "")) => (host, 0),
+            Some((host, db)) => (host, db.parse().context("Invalid Redis database")?),
+            None => (rest, 0),
+        };
+        if host.is_empty() {
+            bail!("Missing Redis host");
+        }
+        let addr = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:6379", host)
+        };
+        let (username, password) = match credentials.map(|c| c.split_once(':')) {
+            Some(Some((user, password))) => (
+                Some(user.to_string()).filter(|u| !u.is_empty()),
+                Some(password.to_string()),
+            ),
+            Some(None) => (None, credentials.map(str::to_string)),
+            None => (None, None),
+        };
+        Ok(Self {
+            addr,
+            username,
+            password,
+            db,
+        })
+    }
+}
+
+pub struct RedisClient {
+    url: RedisUrl,
+    connection: Mutex<Option<BufStream<TcpStream>>>,
+}
+
+impl RedisClient {
+    pub fn new(url: RedisUrl) -> Self {
+        Self {
+            url,
+            connection: Mutex::new(None),
+        }
+    }
+
+    /// Sends one command, connecting first if needed. A failed command drops
+    /// the connection, so the next one starts on a fresh one.
+    pub async fn command(&self, args: &[&[u8]]) -> Result<Reply> {
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() {
+            *connection = Some(self.connect().await?);
+        }
+        let stream = connection.as_mut().expect("connected above");
+        let result = tokio::time::timeout(TIMEOUT, roundtrip(stream, args))
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("Redis command timed out")));
+        if result.is_err() {
+            *connection = None;
+        }
+        match result? {
+            Ok(reply) => Ok(reply),
+            Err(message) => bail!("Redis error: {}", message),
+        }
+    }
+
+    async fn connect(&self) -> Result<BufStream<TcpStream>> {
+        let stream = tokio::time::timeout(TIMEOUT, TcpStream::connect(&self.url.addr))
+            .await
+            .with_context(|| format!("Timed out connecting to Redis at {}", self.url.addr))?
+            .with_context(|| format!("Failed to connect to Redis at {}", self.url.addr))?;
+        let mut stream = BufStream::new(stream);
+        if let Some(password) = &self.url.password {
+            let reply = match &self.url.username {
+                Some(user) => {
+                    roundtrip(
+                        &mut stream,
+                        &[b"AUTH", user.as_bytes(), password.as_bytes()],
+                    )
+                    .await?
+                }
+                None => roundtrip(&mut stream, &[b"AUTH", password.as_bytes()]).await?,
+            };
+            reply
+                .map_err(anyhow::Error::msg)
+                .context("Redis AUTH failed")?;
+        }
+        if self.url.db != 0 {
+            let db = self.url.db.to_string();
+            roundtrip(&mut stream, &[b"SELECT", db.as_bytes()])
+                .await?
+                .map_err(anyhow::Error::msg)
+                .context("Redis SELECT failed")?;
+        }
+        Ok(stream)
+    }
+}
+
+/// Writes a command and reads its reply. An error reply is the inner `Err`.
+async fn roundtrip(
+    stream: &mut BufStream<TcpStream>,
+    args: &[&[u8]],
+) -> Result<Result<Reply, String>> {
+    let mut request = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        request.extend_from_slice(arg);
+        request.extend_from_slice(b"\r\n");
+    }
+    stream.write_all(&request).await?;
+    stream.flush().await?;
+
+    let mut line = String::new();
+    if stream.read_line(&mut line).await? == 0 {
+        bail!("Redis closed the connection");
+    }
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (kind, value) = (
+        line.get(..1).unwrap_or_default(),
+        line.get(1..).unwrap_or_default(),
+    );
+    Ok(Ok(match kind {
+        "+" => Reply::Status(value.to_string()),
+        "-" => return Ok(Err(value.to_string())),
+        ":" => Reply::Int(value.parse().context("Invalid Redis integer reply")?),
+        "$" => {
+            let len: i64 = value.parse().context("Invalid Redis bulk reply")?;
+            if len < 0 {
+                Reply::Nil
+            } else {
+                let mut data = vec![0; len as usize + 2];
+                stream.read_exact(&mut data).await?;
+                data.truncate(len as usize);
+                Reply::Bulk(data)
+            }
+        }
+        _ => bail!("Unsupported Redis reply '{}'", line),
+    }))
+}
@@ -0,0 +1,179 @@
+// image-compressor-rust-service/src/server/routing.rs
+
+//! Router mode: spreading `/compress` over worker instances by content.
+//!
+//! With `[routing] workers` set, this instance compresses nothing itself
+//! and proxies every `/compress` request to a worker picked on a consistent
+//! hash ring by the SHA-256 of the body. Every request for the same image,
+//! whatever its options, lands on the same worker, so its coalescing and
+//! idempotency caches see them all; adding or removing a worker only moves
+//! the images that hashed to it.
+//!
+//! A worker that cannot be reached is skipped for the next one on the ring.
+//! Responses, errors included, are passed back as the worker sent them,
+//! with the worker's URL in `X-Worker`. Options sent as query parameters
+//! are passed on as headers (see [`super::options`]).
+
+use super::limits::LimitedBytes;
+use super::options::OptionHeaders;
+use super::peers::proxy;
+use super::{ApiError, AppState};
+use crate::logging::redaction;
+use anyhow::{Context, Result};
+use axum::{
+    extract::State,
+    http::{HeaderValue, StatusCode},
+    response::Response,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Response header naming the worker that served a routed request.
+pub const WORKER_HEADER: &str = "X-Worker";
+
+/// The workers `/compress` is routed to. Router mode is off unless
+/// `workers` is set.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoutingConfig {
+    /// Base URLs of the workers, e.g. `http://compressor-1:8000`.
+    pub workers: Vec<String>,
+    /// Points per worker on the ring. More spread the images more evenly.
+    pub virtual_nodes: usize,
+    /// Timeout of a routed request, in milliseconds.
+    pub timeout_ms: u64,
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            workers: Vec::new(),
+            virtual_nodes: 160,
+            timeout_ms: 60_000,
+        }
+    }
+}
+
+impl RoutingConfig {
+    pub fn enabled(&self) -> bool {
+        !self.workers.is_empty()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for url in &self.workers {
+            reqwest::Url::parse(url).with_context(|| format!("Invalid worker URL '{}'", url))?;
+        }
+        Ok(())
+    }
+}
+
+/// A consistent hash ring over worker URLs.
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    workers: Vec<String>,
+    /// Ring position to index into `workers`.
+    points: BTreeMap<u64, usize>,
+}
+
+impl HashRing {
+    pub fn new(workers: &[String], virtual_nodes: usize) -> Self {
+        let mut points = BTreeMap::new();
+        for (index, worker) in workers.iter().enumerate() {
+            for node in 0..virtual_nodes.max(1) {
+                points.insert(position(format!("{}#{}", worker, node).as_bytes()), index);
+            }
+        }
+        Self {
+            workers: workers.to_vec(),
+            points,
+        }
+    }
+
+    /// The workers to try for `key`, in order: the owner of the first
+    /// point clockwise of its hash, then the owners of the following
+    /// points, each once.
+    pub fn candidates(&self, key: &[u8]) -> Vec<&str> {
+        let start = position(key);
+        let mut seen = vec![false; self.workers.len()];
+        let mut candidates = Vec::with_capacity(self.workers.len());
+        let clockwise = self.points.range(start..).chain(self.points.range(..start));
+        for (_, &index) in clockwise {
+            if !std::mem::replace(&mut seen[index], true) {
+                candidates.push(self.workers[index].as_str());
+                if candidates.len() == self.workers.len() {
+                    break;
+                }
+            }
+        }
+        candidates
+    }
+}
+
+/// Where `data` falls on the ring.
+fn position(data: &[u8]) -> u64 {
+    let digest = Sha256::digest(data);
+    u64::from_be_bytes(digest[..8].try_into().expect("SHA-256 is 32 bytes"))
+}
+
+/// The ring and the client requests are proxied with.
+pub struct Workers {
+    ring: HashRing,
+    client: reqwest::Client,
+}
+
+impl Workers {
+    pub fn new(config: &RoutingConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+            .expect("Failed to build the routing client");
+        Self {
+            ring: HashRing::new(&config.workers, config.virtual_nodes),
+            client,
+        }
+    }
+}
+
+/// Handles `/compress` in router mode.
+pub async fn route_handler(
+    State(state): State<AppState>,
+    OptionHeaders(headers): OptionHeaders,
+    LimitedBytes(body): LimitedBytes,
+) -> Result<Response, ApiError> {
+    let request_id = super::request_id(&headers).to_owned();
+    if body.is_empty() {
+        return Err(
+            ApiError::bad_request("Request body cannot be empty.").with_request_id(request_id)
+        );
+    }
+    let workers = &state.workers;
+    for worker in workers.ring.candidates(&body) {
+        match proxy(&workers.client, worker, headers.clone(), body.clone()).await {
+            Ok(mut response) => {
+                info!(worker = %redaction().url(worker), "Routed the request.");
+                metrics::increment_counter!("route_requests_total", "worker" => worker.to_string());
+                if let Ok(value) = HeaderValue::from_str(worker) {
+                    response.headers_mut().insert(WORKER_HEADER, value);
+                }
+                return Ok(response);
+            }
+            Err(e) => {
+                warn!(
+                    worker = %redaction().url(worker),
+                    "Worker unreachable; trying the next: {:#}",
+                    e
+                );
+                metrics::increment_counter!("route_failures_total", "worker" => worker.to_string());
+            }
+        }
+    }
+    Err(ApiError::new(
+        StatusCode::BAD_GATEWAY,
+        "workers_unavailable",
+        "No worker could be reached.",
+    )
+    .with_request_id(request_id))
+}
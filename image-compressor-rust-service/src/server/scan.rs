@@ -0,0 +1,211 @@
+// image-compressor-rust-service/src/server/scan.rs
+
+//! Virus scanning of inputs before they are decoded.
+//!
+//! The input is streamed to a ClamAV daemon (`clamd`, `INSTREAM` command)
+//! or to an ICAP server (`RESPMOD`). A flagged input is rejected with `422`
+//! and never reaches a decoder. If the scanner cannot be reached the
+//! request fails with `503`, unless `fail_open` is set.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// Input is sent to the scanner in chunks of this size.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Longest scanner reply read, in bytes.
+const MAX_REPLY_LEN: u64 = 64 * 1024;
+
+/// The protocol spoken by the scanner.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanProtocol {
+    /// The `clamd` TCP protocol.
+    #[default]
+    Clamav,
+    /// ICAP (RFC 3507), e.g. c-icap or a commercial AV gateway.
+    Icap,
+}
+
+/// Settings for input scanning.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScanConfig {
+    /// Scan every `/compress` input before decoding it.
+    pub enabled: bool,
+    pub protocol: ScanProtocol,
+    /// `host:port` of the scanner.
+    pub addr: String,
+    /// ICAP service name, the path of the `icap://` URI.
+    pub icap_service: String,
+    /// Time allowed for a whole scan, in milliseconds.
+    pub timeout_ms: u64,
+    /// Let inputs through unscanned when the scanner fails, instead of
+    /// answering `503`.
+    pub fail_open: bool,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            protocol: ScanProtocol::Clamav,
+            addr: "127.0.0.1:3310".to_string(),
+            icap_service: "avscan".to_string(),
+            timeout_ms: 10_000,
+            fail_open: false,
+        }
+    }
+}
+
+/// What the scanner found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Clean,
+    /// Flagged, with the signature name when the scanner reports one.
+    Infected(String),
+}
+
+impl ScanConfig {
+    /// Scans `input`, recording the latency in `input_scan_duration_seconds`.
+    pub async fn scan(&self, input: &[u8]) -> Result<Verdict> {
+        let start = std::time::Instant::now();
+        let scan = async {
+            match self.protocol {
+                ScanProtocol::Clamav => scan_clamav(&self.addr, input).await,
+                ScanProtocol::Icap => scan_icap(&self.addr, &self.icap_service, input).await,
+            }
+        };
+        let result = tokio::time::timeout(Duration::from_millis(self.timeout_ms), scan)
+            .await
+            .unwrap_or_else(|_| bail!("Scan timed out after {} ms", self.timeout_ms));
+
+        let outcome = match &result {
+            Ok(Verdict::Clean) => "clean",
+            Ok(Verdict::Infected(_)) => "infected",
+            Err(_) => "error",
+        };
+        metrics::histogram!(
+            "input_scan_duration_seconds",
+            start.elapsed().as_secs_f64(),
+            "result" => outcome
+        );
+        result
+    }
+}
+
+async fn connect(addr: &str) -> Result<TcpStream> {
+    TcpStream::connect(addr)
+        .await
+        .with_context(|| format!("Failed to connect to the scanner at {}", addr))
+}
+
+/// Reads the reply up to and including `terminator`.
+async fn read_reply(stream: &mut TcpStream, terminator: u8) -> Result<String> {
+    let mut reader = BufReader::new(stream.take(MAX_REPLY_LEN));
+    let mut reply = Vec::new();
+    reader
+        .read_until(terminator, &mut reply)
+        .await
+        .context("Failed to read the scanner reply")?;
+    Ok(String::from_utf8_lossy(&reply).into_owned())
+}
+
+/// Reads the ICAP status line and headers, up to the first empty line.
+async fn read_icap_headers(stream: &mut TcpStream) -> Result<Vec<String>> {
+    let mut reader = BufReader::new(stream.take(MAX_REPLY_LEN));
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        let read = reader
+            .read_line(&mut line)
+            .await
+            .context("Failed to read the scanner reply")?;
+        let line = line.trim_end();
+        if read == 0 || line.is_empty() {
+            return Ok(lines);
+        }
+        lines.push(line.to_string());
+    }
+}
+
+/// `zINSTREAM`: length-prefixed chunks ended by a zero length. The reply is
+/// `stream: OK` or `stream: <signature> FOUND`.
+async fn scan_clamav(addr: &str, input: &[u8]) -> Result<Verdict> {
+    let mut stream = connect(addr).await?;
+    stream.write_all(b"zINSTREAM\0").await?;
+    for chunk in input.chunks(CHUNK_SIZE) {
+        stream
+            .write_all(&(chunk.len() as u32).to_be_bytes())
+            .await?;
+        stream.write_all(chunk).await?;
+    }
+    stream.write_all(&0u32.to_be_bytes()).await?;
+    stream.flush().await?;
+
+    let reply = read_reply(&mut stream, b'\0').await?;
+    let reply = reply.trim_end_matches(['\0', '\n']);
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+    if result == "OK" {
+        Ok(Verdict::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(Verdict::Infected(signature.to_string()))
+    } else {
+        bail!("Unexpected clamd reply: {}", reply)
+    }
+}
+
+/// `RESPMOD` with the input as the body of an encapsulated HTTP response.
+/// `204` means clean; a `200` means the server replaced the content and is
+/// taken as flagged.
+async fn scan_icap(addr: &str, service: &str, input: &[u8]) -> Result<Verdict> {
+    let http_headers = "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\n\r\n";
+    let request = format!(
+        "RESPMOD icap://{addr}/{service} ICAP/1.0\r\n\
+         Host: {addr}\r\n\
+         Allow: 204\r\n\
+         Connection: close\r\n\
+         Encapsulated: res-hdr=0, res-body={}\r\n\r\n\
+         {http_headers}",
+        http_headers.len(),
+    );
+
+    let mut stream = connect(addr).await?;
+    stream.write_all(request.as_bytes()).await?;
+    for chunk in input.chunks(CHUNK_SIZE) {
+        stream
+            .write_all(format!("{:x}\r\n", chunk.len()).as_bytes())
+            .await?;
+        stream.write_all(chunk).await?;
+        stream.write_all(b"\r\n").await?;
+    }
+    stream.write_all(b"0\r\n\r\n").await?;
+    stream.flush().await?;
+
+    let reply = read_icap_headers(&mut stream).await?;
+    let mut lines = reply.iter();
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .context("Empty ICAP reply")?;
+    match status {
+        "204" => Ok(Verdict::Clean),
+        "200" => {
+            // The name of the threat, if the server says.
+            let signature = lines
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| {
+                    name.eq_ignore_ascii_case("X-Infection-Found")
+                        || name.eq_ignore_ascii_case("X-Violations-Found")
+                        || name.eq_ignore_ascii_case("X-Virus-ID")
+                })
+                .map(|(_, value)| value.trim().to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            Ok(Verdict::Infected(signature))
+        }
+        other => bail!("Unexpected ICAP status {}", other),
+    }
+}
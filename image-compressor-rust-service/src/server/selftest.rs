@@ -0,0 +1,142 @@
+// image-compressor-rust-service/src/server/selftest.rs
+
+use super::{constant_time_eq, request_id, ApiError, AppState};
+use crate::config::Config;
+use crate::warmup::{sample_image, sample_input};
+use crate::{compress_image_with, CompressionOptions};
+use anyhow::{ensure, Result};
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tracing::warn;
+
+/// Settings for the `/selftest` endpoint.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SelftestConfig {
+    /// Bearer token required to call `/selftest`. The endpoint answers `404`
+    /// while no token is configured.
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Check {
+    name: String,
+    passed: bool,
+    duration_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    passed: bool,
+    checks: Vec<Check>,
+}
+
+/// Runs an end-to-end check of the pipeline, and pings Redis when uploads
+/// are kept there, and returns a JSON report: `200` when every check
+/// passed, `503` otherwise.
+///
+/// Requires `Authorization: Bearer <selftest.token>`.
+pub async fn selftest_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let request_id = request_id(&headers).to_owned();
+    let Some(token) = state.config.selftest.token.as_deref() else {
+        return Err(
+            ApiError::new(StatusCode::NOT_FOUND, "not_found", "Not found.")
+                .with_request_id(request_id),
+        );
+    };
+    if !authorized(&headers, token) {
+        warn!(
+            request_id,
+            "Rejected /selftest call with a missing or wrong token."
+        );
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "A valid bearer token is required.",
+        )
+        .with_request_id(request_id));
+    }
+
+    let config = state.config.clone();
+    let mut checks = tokio::task::spawn_blocking(move || run_checks(&config))
+        .await
+        .map_err(|e| {
+            ApiError::internal(format!("Self-test task failed: {}", e)).with_request_id(request_id)
+        })?;
+    if state.config.uploads.enabled {
+        let start = Instant::now();
+        if let Some(result) = state.uploads.ping().await {
+            checks.push(finish_check("uploads:redis".to_string(), start, result));
+        }
+    }
+    let report = Report {
+        passed: checks.iter().all(|c| c.passed),
+        checks,
+    };
+
+    let status = if report.passed {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok((status, Json(report)).into_response())
+}
+
+/// One decode/encode round trip of the built-in sample per allowed input
+/// format, through the same pipeline (and sandbox, if enabled) as `/compress`.
+/// The handler adds a ping of Redis when uploads are kept there.
+fn run_checks(config: &Config) -> Vec<Check> {
+    config
+        .allowed_input_formats
+        .iter()
+        .map(|&format| {
+            run_check(format!("compress:{}", format), || {
+                let input = sample_input(format)?;
+                let output = compress_image_with(&input, &CompressionOptions::default(), config)?;
+                let decoded = image::load_from_memory(&output.data)?;
+                let expected = sample_image();
+                ensure!(
+                    (decoded.width(), decoded.height()) == (expected.width(), expected.height()),
+                    "round trip changed the dimensions to {}x{}",
+                    decoded.width(),
+                    decoded.height()
+                );
+                Ok(())
+            })
+        })
+        .collect()
+}
+
+fn run_check(name: String, check: impl FnOnce() -> Result<()>) -> Check {
+    let start = Instant::now();
+    finish_check(name, start, check())
+}
+
+fn finish_check(name: String, start: Instant, result: Result<()>) -> Check {
+    Check {
+        name,
+        passed: result.is_ok(),
+        duration_ms: start.elapsed().as_secs_f64() * 1000.0,
+        error: result.err().map(|e| format!("{:#}", e)),
+    }
+}
+
+/// Compares the bearer token in constant time.
+pub(super) fn authorized(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+}
@@ -0,0 +1,166 @@
+// image-compressor-rust-service/src/server/skip.rs
+
+//! Conditional processing: leave inputs that are already small enough alone.
+//!
+//! * `X-Skip-If-Smaller-Than: <bytes>` - inputs below this size that need no
+//!   resizing are not compressed at all, saving the CPU.
+//! * `X-Only-If-Larger: true` - when the compressed output is not smaller
+//!   than the input, the input is returned instead.
+//! * By default (`pass_through_larger`), the same happens without the
+//!   header when the input can stand in for the output: it is already in
+//!   the output format, the request does not resize or otherwise change
+//!   the image, and the input carries no EXIF or XMP the output would have
+//!   filtered. `X-Force-Encode: true` always returns the encoded output.
+//! * `X-Skip-Response` - `pass-through` (default) answers a skipped request
+//!   with the input, untouched and with its own `Content-Type`;
+//!   `no-content` answers `204 No Content`.
+//!
+//! Skipped responses carry `X-Compression-Skipped` with the reason. Passed
+//! through inputs keep all of their metadata, whatever the metadata policy.
+
+use crate::config::Config;
+use crate::formats::InputFormat;
+use crate::options::CompressionOptions;
+use crate::transform::{self, MaxDimensions};
+use crate::{metadata, CompressedImage};
+use axum::{
+    body::Bytes,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+pub const SKIP_IF_SMALLER_THAN_HEADER: &str = "X-Skip-If-Smaller-Than";
+pub const ONLY_IF_LARGER_HEADER: &str = "X-Only-If-Larger";
+pub const SKIP_RESPONSE_HEADER: &str = "X-Skip-Response";
+pub const FORCE_ENCODE_HEADER: &str = "X-Force-Encode";
+
+/// Response header naming why the input was returned uncompressed.
+pub const SKIPPED_HEADER: &str = "X-Compression-Skipped";
+
+/// Why a request was not compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The input was under `X-Skip-If-Smaller-Than` and needed no resizing.
+    SmallEnough,
+    /// Compressing did not make it smaller.
+    NotSmaller,
+}
+
+impl SkipReason {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::SmallEnough => "small-enough",
+            Self::NotSmaller => "not-smaller",
+        }
+    }
+}
+
+/// The conditions a request set, from its headers. Invalid values are
+/// ignored, like other options.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SkipPolicy {
+    pub smaller_than: Option<u64>,
+    pub only_if_larger: bool,
+    pub force_encode: bool,
+    pub no_content: bool,
+}
+
+impl SkipPolicy {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+        Self {
+            smaller_than: header(SKIP_IF_SMALLER_THAN_HEADER)
+                .and_then(|v| v.trim().parse().ok())
+                .filter(|&bytes| bytes > 0),
+            only_if_larger: header(ONLY_IF_LARGER_HEADER)
+                .is_some_and(|v| v.trim().eq_ignore_ascii_case("true")),
+            force_encode: header(FORCE_ENCODE_HEADER)
+                .is_some_and(|v| v.trim().eq_ignore_ascii_case("true")),
+            no_content: header(SKIP_RESPONSE_HEADER)
+                .is_some_and(|v| v.trim().eq_ignore_ascii_case("no-content")),
+        }
+    }
+
+    /// Whether `input` can skip compression before any decoding: it is under
+    /// the size threshold, is not rotated and already has the dimensions the
+    /// request would produce. Only the image header is read.
+    pub fn is_small_enough(
+        &self,
+        input: &[u8],
+        options: &CompressionOptions,
+        max: MaxDimensions,
+    ) -> bool {
+        let Some(threshold) = self.smaller_than else {
+            return false;
+        };
+        if input.len() as u64 >= threshold || options.rotate.is_some() {
+            return false;
+        }
+        crate::decode::probe_dimensions(input)
+            .is_ok_and(|source| transform::output_dimensions(source, options, max) == source)
+    }
+
+    /// Whether `compressed` is thrown away for the input because it is not
+    /// smaller: always with `X-Only-If-Larger`, and by default when the
+    /// input can stand in for it. A salvaged output is never replaced by
+    /// its broken input.
+    pub fn returns_input(
+        &self,
+        input: &[u8],
+        compressed: &CompressedImage,
+        options: &CompressionOptions,
+        config: &Config,
+    ) -> bool {
+        if self.force_encode || compressed.salvaged || compressed.data.len() < input.len() {
+            return false;
+        }
+        self.only_if_larger
+            || (config.pass_through_larger && stands_in(input, compressed, options, config))
+    }
+
+    /// The response to a skipped request.
+    pub fn response(&self, reason: SkipReason, input: Bytes, format: InputFormat) -> Response {
+        metrics::increment_counter!("compress_skipped_total", "reason" => reason.name());
+        let mut response = if self.no_content {
+            StatusCode::NO_CONTENT.into_response()
+        } else {
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, format.mime_type())],
+                input,
+            )
+                .into_response()
+        };
+        response
+            .headers_mut()
+            .insert(SKIPPED_HEADER, HeaderValue::from_static(reason.name()));
+        response
+    }
+}
+
+/// Whether `input` answers the request as well as `compressed` does, apart
+/// from its size: same format and dimensions, nothing rotated, drawn,
+/// converted or embedded, and no metadata the output would have filtered or signed.
+fn stands_in(
+    input: &[u8],
+    compressed: &CompressedImage,
+    options: &CompressionOptions,
+    config: &Config,
+) -> bool {
+    InputFormat::sniff(input).is_some_and(|f| f.name() == compressed.format.name())
+        && compressed.dimensions == compressed.source_dimensions
+        && options.rotate.is_none()
+        && options.filter.is_none()
+        && options.palette.is_none()
+        && options.color_space.is_none()
+        && options.border.is_none()
+        && options.corner_radius.is_none()
+        && options.watermark.is_none()
+        && options.placeholder.is_none()
+        && !options.ocr
+        && options.custom_metadata.is_empty()
+        && !config.provenance.enabled
+        && metadata::find_exif(input).is_none()
+        && metadata::find_xmp(input).is_none()
+}
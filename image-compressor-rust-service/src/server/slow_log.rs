@@ -0,0 +1,88 @@
+// image-compressor-rust-service/src/server/slow_log.rs
+
+//! Dedicated warnings for slow requests and large images.
+//!
+//! A request crossing a threshold logs one `WARN` event carrying its
+//! parameters and stage timings, so alerts can match on the `event` field
+//! instead of computing durations from other log lines.
+
+use crate::cpu::Priority;
+use crate::formats::InputFormat;
+use crate::timing::Timings;
+use crate::CompressionOptions;
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::warn;
+
+/// Thresholds for the slow request and large image warnings. `0` disables
+/// a threshold.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SlowLogConfig {
+    /// Warn about `/compress` requests taking longer, in milliseconds.
+    pub slow_request_ms: u64,
+    /// Warn about inputs decoding to more pixels, all frames together.
+    pub large_image_pixels: u64,
+}
+
+impl Default for SlowLogConfig {
+    fn default() -> Self {
+        Self {
+            slow_request_ms: 10_000,
+            large_image_pixels: 40_000_000,
+        }
+    }
+}
+
+/// What is known about a finished request.
+#[derive(Debug)]
+pub struct RequestSummary<'a> {
+    pub request_id: &'a str,
+    pub options: &'a CompressionOptions,
+    pub priority: Priority,
+    pub input_format: InputFormat,
+    pub input_bytes: usize,
+    pub output_bytes: usize,
+    pub decoded_pixels: u64,
+    pub duration: Duration,
+    pub timings: &'a Timings,
+}
+
+impl SlowLogConfig {
+    /// Logs a warning for every threshold `summary` crosses.
+    pub fn check(&self, summary: &RequestSummary<'_>) {
+        let duration_ms = summary.duration.as_millis() as u64;
+        if self.slow_request_ms > 0 && duration_ms > self.slow_request_ms {
+            metrics::increment_counter!("compress_slow_requests_total");
+            report("slow_request", summary, "Slow request.");
+        }
+        if self.large_image_pixels > 0 && summary.decoded_pixels > self.large_image_pixels {
+            metrics::increment_counter!("compress_large_images_total");
+            report("large_image", summary, "Large image.");
+        }
+    }
+}
+
+fn report(event: &'static str, summary: &RequestSummary<'_>, message: &str) {
+    let options = summary.options;
+    warn!(
+        event,
+        request_id = summary.request_id,
+        duration_ms = summary.duration.as_millis() as u64,
+        decoded_pixels = summary.decoded_pixels,
+        input_format = summary.input_format.name(),
+        input_bytes = summary.input_bytes,
+        output_bytes = summary.output_bytes,
+        quality = options.quality,
+        quality_scale = ?options.quality_scale,
+        animation = ?options.animation,
+        width = ?options.width,
+        height = ?options.height,
+        fit = ?options.fit,
+        deterministic = options.deterministic,
+        priority = summary.priority.name(),
+        stages = %summary.timings,
+        "{}",
+        message
+    );
+}
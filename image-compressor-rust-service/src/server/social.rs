@@ -0,0 +1,152 @@
+// image-compressor-rust-service/src/server/social.rs
+
+use super::compress::check_input_format;
+use super::{decode_still, request_id, ApiError, AppState};
+use crate::cpu::Priority;
+use crate::formats::OutputFormat;
+use crate::social::Card;
+use crate::text::Font;
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use image::{DynamicImage, ImageOutputFormat};
+use serde::Deserialize;
+use std::io::Cursor;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CardRequest {
+    #[serde(default = "default_template")]
+    template: String,
+    title: String,
+    #[serde(default)]
+    subtitle: Option<String>,
+    /// Base64-encoded background image.
+    #[serde(default)]
+    background: Option<String>,
+    /// Base64-encoded logo, ideally with transparency.
+    #[serde(default)]
+    logo: Option<String>,
+    /// `jpeg` (the default) or `png`.
+    #[serde(default)]
+    format: Option<String>,
+}
+
+fn default_template() -> String {
+    "default".to_string()
+}
+
+/// Renders a social card from a template and returns it as a JPEG or PNG.
+///
+/// The body is JSON: `{"template": "default", "title": "...", "subtitle":
+/// "...", "background": "<base64>", "logo": "<base64>", "format": "jpeg"}`;
+/// everything but the title is optional. Images must be of an allowed
+/// input format and are decoded like `/compress` inputs (see
+/// [`crate::social`]). Only routed when `[social] font` is set.
+pub async fn social_card_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let request_id = request_id(&headers).to_owned();
+    let request: CardRequest = serde_json::from_slice(&body).map_err(|e| {
+        ApiError::bad_request(format!("Invalid social card request: {}", e))
+            .with_request_id(&request_id)
+    })?;
+    let template = state
+        .config
+        .social
+        .template(&request.template)
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                "unknown_template",
+                format!("No social card template named '{}'.", request.template),
+            )
+            .with_request_id(&request_id)
+        })?;
+    let format = match request
+        .format
+        .as_deref()
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+    {
+        None | Some("jpeg") | Some("jpg") => OutputFormat::Jpeg,
+        Some("png") => OutputFormat::Png,
+        Some(other) => {
+            return Err(ApiError::bad_request(format!(
+                "Unsupported card format '{}'; use jpeg or png.",
+                other
+            ))
+            .with_request_id(&request_id))
+        }
+    };
+    let decode_base64 = |name: &str, value: &Option<String>| {
+        value
+            .as_deref()
+            .map(|value| {
+                let data = base64::engine::general_purpose::STANDARD
+                    .decode(value.trim())
+                    .map_err(|e| {
+                        ApiError::bad_request(format!("'{}' is not valid base64: {}", name, e))
+                    })?;
+                check_input_format(&data, &state.config.allowed_input_formats)?;
+                Ok(data)
+            })
+            .transpose()
+            .map_err(|e: ApiError| e.with_request_id(&request_id))
+    };
+    let background = decode_base64("background", &request.background)?;
+    let logo = decode_base64("logo", &request.logo)?;
+
+    metrics::increment_counter!("social_card_requests_total");
+    let config = state.config.clone();
+    let threads = state.cpu.acquire(Priority::default()).await;
+    let (title, subtitle) = (request.title, request.subtitle);
+    let result = tokio::task::spawn_blocking(move || {
+        let _threads = threads;
+        let decode = |data: Option<Vec<u8>>| data.map(|d| decode_still(&d, &config)).transpose();
+        let card = Card {
+            title,
+            subtitle,
+            background: decode(background)?,
+            logo: decode(logo)?,
+        };
+        let path = config
+            .social
+            .font
+            .as_deref()
+            .expect("routed only with a font");
+        let font = Font::load(path).map_err(|e| ApiError::internal(format!("{:#}", e)))?;
+        let image = DynamicImage::ImageRgba8(template.render(&font, &card));
+        let mut data = Vec::new();
+        let output = match format {
+            OutputFormat::Png => ImageOutputFormat::Png,
+            _ => ImageOutputFormat::Jpeg(config.quality.for_format(OutputFormat::Jpeg).default),
+        };
+        let image = match format {
+            OutputFormat::Png => image,
+            _ => DynamicImage::ImageRgb8(image.into_rgb8()),
+        };
+        image
+            .write_to(&mut Cursor::new(&mut data), output)
+            .map_err(|e| ApiError::internal(format!("Failed to encode the card: {}", e)))?;
+        Ok::<_, ApiError>(data)
+    })
+    .await
+    .map_err(|e| {
+        ApiError::internal(format!("Social card task failed: {}", e)).with_request_id(&request_id)
+    })?
+    .map_err(|e| e.with_request_id(&request_id))?;
+
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, format.mime_type())],
+        result,
+    )
+        .into_response())
+}
@@ -0,0 +1,158 @@
+// image-compressor-rust-service/src/server/statsd.rs
+
+//! A StatsD/DogStatsD backend for the `metrics` facade.
+//!
+//! Every update is sent as one UDP datagram, e.g.
+//! `compress_requests_total:1|c|#region:eu-west-1`. Sending never blocks and
+//! failures are dropped, as is usual for StatsD.
+
+use anyhow::{Context, Result};
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Recorder,
+    SharedString, Unit,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+
+/// Settings for the StatsD backend.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatsdConfig {
+    /// `host:port` of the StatsD or Datadog agent.
+    pub addr: String,
+    /// Send labels as DogStatsD tags. Plain StatsD has no tags, so labels
+    /// are dropped without this.
+    pub tags: bool,
+}
+
+impl Default for StatsdConfig {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1:8125".to_string(),
+            tags: true,
+        }
+    }
+}
+
+/// Sends metrics to a StatsD agent.
+#[derive(Debug)]
+pub struct StatsdRecorder {
+    socket: Arc<UdpSocket>,
+    /// Labels added to every metric.
+    global_labels: BTreeMap<String, String>,
+    tags: bool,
+}
+
+impl StatsdRecorder {
+    pub fn new(config: &StatsdConfig, global_labels: &BTreeMap<String, String>) -> Result<Self> {
+        let addr = config
+            .addr
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .with_context(|| format!("Cannot resolve StatsD address '{}'", config.addr))?;
+        let local = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local).context("Failed to open the StatsD socket")?;
+        socket
+            .connect(addr)
+            .with_context(|| format!("Failed to connect to StatsD at {}", addr))?;
+        socket
+            .set_nonblocking(true)
+            .context("Failed to open the StatsD socket")?;
+        Ok(Self {
+            socket: Arc::new(socket),
+            global_labels: global_labels.clone(),
+            tags: config.tags,
+        })
+    }
+
+    fn metric(&self, key: &Key) -> Arc<Metric> {
+        let mut suffix = String::new();
+        if self.tags {
+            let tags: Vec<String> = self
+                .global_labels
+                .iter()
+                .map(|(k, v)| format!("{}:{}", k, v))
+                .chain(key.labels().map(|l| format!("{}:{}", l.key(), l.value())))
+                .collect();
+            if !tags.is_empty() {
+                suffix = format!("|#{}", tags.join(","));
+            }
+        }
+        Arc::new(Metric {
+            socket: self.socket.clone(),
+            name: key.name().to_string(),
+            suffix,
+        })
+    }
+}
+
+impl Recorder for StatsdRecorder {
+    fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+    fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+    fn describe_histogram(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn register_counter(&self, key: &Key) -> Counter {
+        Counter::from_arc(self.metric(key))
+    }
+
+    fn register_gauge(&self, key: &Key) -> Gauge {
+        Gauge::from_arc(self.metric(key))
+    }
+
+    fn register_histogram(&self, key: &Key) -> Histogram {
+        Histogram::from_arc(self.metric(key))
+    }
+}
+
+/// One registered metric: its name and rendered tags.
+#[derive(Debug)]
+struct Metric {
+    socket: Arc<UdpSocket>,
+    name: String,
+    /// `|#tag:value,...`, or empty.
+    suffix: String,
+}
+
+impl Metric {
+    fn send(&self, value: impl std::fmt::Display, kind: &str) {
+        let line = format!("{}:{}|{}{}", self.name, value, kind, self.suffix);
+        let _ = self.socket.send(line.as_bytes());
+    }
+}
+
+impl CounterFn for Metric {
+    fn increment(&self, value: u64) {
+        self.send(value, "c");
+    }
+
+    /// StatsD counters only count up from each flush; absolute values have
+    /// no equivalent.
+    fn absolute(&self, _value: u64) {}
+}
+
+impl GaugeFn for Metric {
+    fn increment(&self, value: f64) {
+        self.send(format_args!("+{}", value), "g");
+    }
+
+    fn decrement(&self, value: f64) {
+        self.send(format_args!("-{}", value), "g");
+    }
+
+    fn set(&self, value: f64) {
+        self.send(value, "g");
+    }
+}
+
+impl HistogramFn for Metric {
+    fn record(&self, value: f64) {
+        self.send(value, "h");
+    }
+}
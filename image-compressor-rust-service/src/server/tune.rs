@@ -0,0 +1,158 @@
+// image-compressor-rust-service/src/server/tune.rs
+
+use super::compress::check_input_format;
+use super::{request_id, ApiError, AppState};
+use crate::cpu::Priority;
+use crate::tune::{self, Candidate};
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::Engine;
+use serde::Serialize;
+use tracing::info;
+
+/// Request header with the distance between the qualities tried.
+pub const QUALITY_STEP_HEADER: &str = "X-Quality-Step";
+
+/// Quality step used without `X-Quality-Step`.
+const DEFAULT_STEP: u8 = 5;
+
+#[derive(Debug, Serialize)]
+struct TuneResponse {
+    preset: String,
+    original: Original,
+    /// Ordered by quality.
+    outputs: Vec<Output>,
+}
+
+#[derive(Debug, Serialize)]
+struct Original {
+    bytes: usize,
+    width: u32,
+    height: u32,
+    data: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Output {
+    quality: u8,
+    /// Whether this is the preset's own quality.
+    preset: bool,
+    bytes: usize,
+    width: u32,
+    height: u32,
+    ssim: f64,
+    data: String,
+}
+
+impl From<Candidate> for Output {
+    fn from(candidate: Candidate) -> Self {
+        Self {
+            quality: candidate.quality,
+            preset: candidate.preset,
+            bytes: candidate.data.len(),
+            width: candidate.dimensions.0,
+            height: candidate.dimensions.1,
+            ssim: candidate.ssim,
+            data: data_uri(candidate.content_type, &candidate.data),
+        }
+    }
+}
+
+/// Compresses the body with a configured or built-in preset at its quality
+/// and two `X-Quality-Step`s (5 by default) above and below it, for tuning
+/// the preset. The JSON response holds the original and every output as
+/// `data:` URIs, with their sizes and, for the outputs, their SSIM (see
+/// [`crate::tune`]).
+///
+/// The image is checked and decoded like a `/compress` input.
+pub async fn tune_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let request_id = request_id(&headers).to_owned();
+    let preset = state.config.preset(&name).ok_or_else(|| {
+        ApiError::new(
+            StatusCode::NOT_FOUND,
+            "unknown_preset",
+            format!("No preset named '{}'.", name),
+        )
+        .with_request_id(&request_id)
+    })?;
+    if body.is_empty() {
+        return Err(
+            ApiError::bad_request("Request body cannot be empty.").with_request_id(request_id)
+        );
+    }
+    let input_format = check_input_format(&body, &state.config.allowed_input_formats)
+        .map_err(|e| e.with_request_id(&request_id))?;
+    let step = match headers.get(QUALITY_STEP_HEADER) {
+        None => DEFAULT_STEP,
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|v| v.trim().parse::<u8>().ok())
+            .filter(|step| (1..=50).contains(step))
+            .ok_or_else(|| {
+                ApiError::bad_request("X-Quality-Step must be between 1 and 50.")
+                    .with_request_id(&request_id)
+            })?,
+    };
+
+    metrics::increment_counter!("tune_requests_total");
+    let config = state.config.clone();
+    let threads = state.cpu.acquire(Priority::default()).await;
+    let input = body.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let _threads = threads;
+        let (width, height) = crate::decode::probe_dimensions(&input)?;
+        let candidates = tune::tune(&input, &preset, step, &config)?;
+        anyhow::Ok((width, height, candidates))
+    })
+    .await
+    .map_err(|e| {
+        ApiError::internal(format!("Tuning task failed: {}", e)).with_request_id(&request_id)
+    })?
+    .map_err(|e| {
+        ApiError::unprocessable_input(format!("Failed to compress image: {:#}", e), &e)
+            .with_request_id(&request_id)
+    })?;
+    let (width, height, candidates) = result;
+
+    info!(
+        request_id,
+        preset = name,
+        "Compressed a {}x{} image at {} qualities for tuning.",
+        width,
+        height,
+        candidates.len()
+    );
+    Ok((
+        StatusCode::OK,
+        Json(TuneResponse {
+            preset: name,
+            original: Original {
+                bytes: body.len(),
+                width,
+                height,
+                data: data_uri(input_format.mime_type(), &body),
+            },
+            outputs: candidates.into_iter().map(Output::from).collect(),
+        }),
+    )
+        .into_response())
+}
+
+fn data_uri(content_type: &str, data: &[u8]) -> String {
+    format!(
+        "data:{};base64,{}",
+        content_type,
+        base64::engine::general_purpose::STANDARD.encode(data)
+    )
+}
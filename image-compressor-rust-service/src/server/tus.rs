@@ -0,0 +1,964 @@
+// image-compressor-rust-service/src/server/tus.rs
+
+//! Resumable uploads over the [tus](https://tus.io) protocol, version 1.0.0
+//! with the `creation`, `creation-with-upload`, `termination` and
+//! `expiration` extensions.
+//!
+//! `POST /uploads` with an `Upload-Length` creates an upload. The client
+//! then `PATCH`es chunks at the `Upload-Offset` reported by `HEAD`, resuming
+//! where a dropped connection left off. The `PATCH` completing an upload
+//! runs it through `/compress`, with that request's headers as options and
+//! the `filename` from `Upload-Metadata` as `X-Filename`; the compressed
+//! image is then served by `GET /uploads/{id}`, which honours `Range`. A
+//! failed compression answers the final `PATCH` with the `/compress` error,
+//! and an empty `PATCH` at the final offset tries again.
+//!
+//! Upload state lives on disk or in Redis, so uploads survive restarts.
+//! Uploads created with an `X-Api-Key` are only visible with the same key.
+
+use super::compress::compress_handler;
+use super::limits::api_key;
+use super::options::Options;
+use super::range;
+use super::redis::{RedisClient, RedisUrl, Reply};
+use super::{ApiError, AppState};
+use crate::filename;
+use crate::timing::Timings;
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    body::to_bytes,
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode, Uri},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{head, post},
+    Extension, Router,
+};
+use base64::Engine;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hasher};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+/// The protocol version spoken.
+pub const TUS_VERSION: &str = "1.0.0";
+/// Extensions announced by `OPTIONS /uploads`.
+pub const TUS_EXTENSIONS: &str = "creation,creation-with-upload,termination,expiration";
+/// Content type of `PATCH` bodies.
+pub const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+const TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+const TUS_MAX_SIZE: HeaderName = HeaderName::from_static("tus-max-size");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+const UPLOAD_EXPIRES: HeaderName = HeaderName::from_static("upload-expires");
+const UPLOAD_DEFER_LENGTH: HeaderName = HeaderName::from_static("upload-defer-length");
+
+/// The protocol's request headers.
+pub const REQUEST_HEADERS: [HeaderName; 5] = [
+    TUS_RESUMABLE,
+    UPLOAD_LENGTH,
+    UPLOAD_OFFSET,
+    UPLOAD_METADATA,
+    UPLOAD_DEFER_LENGTH,
+];
+/// The protocol's response headers.
+pub const RESPONSE_HEADERS: [HeaderName; 8] = [
+    TUS_RESUMABLE,
+    TUS_VERSION_HEADER,
+    TUS_EXTENSION,
+    TUS_MAX_SIZE,
+    UPLOAD_LENGTH,
+    UPLOAD_OFFSET,
+    UPLOAD_METADATA,
+    UPLOAD_EXPIRES,
+];
+
+/// Where upload state is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadStore {
+    /// Files under `dir`.
+    Disk,
+    /// Keys under `redis_prefix`, expiring with the upload.
+    Redis,
+}
+
+/// Settings for resumable uploads on `/uploads`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TusConfig {
+    pub enabled: bool,
+    pub store: UploadStore,
+    /// Directory of the `disk` store.
+    pub dir: PathBuf,
+    /// `redis://[[user]:password@]host[:port][/db]` of the `redis` store.
+    pub redis_url: String,
+    /// Prefix of the keys of the `redis` store.
+    pub redis_prefix: String,
+    /// How long after creation an upload and its result are kept, in seconds.
+    pub expire_secs: u64,
+    /// Most bytes the `disk` store holds: the full length of every upload
+    /// plus its result. When a new upload would not fit, the oldest are
+    /// evicted; `0` for no cap. The `redis` store is bounded by expiry and
+    /// Redis's own `maxmemory` policy.
+    pub max_storage_bytes: u64,
+    /// How often expired uploads are removed from the `disk` store and the
+    /// cap enforced, in seconds.
+    pub reap_interval_secs: u64,
+}
+
+impl Default for TusConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            store: UploadStore::Disk,
+            dir: std::env::temp_dir().join("image-compressor-uploads"),
+            redis_url: "redis://127.0.0.1:6379".to_string(),
+            redis_prefix: "tus:".to_string(),
+            expire_secs: 24 * 60 * 60,
+            max_storage_bytes: 10 * 1024 * 1024 * 1024, // 10 GiB
+            reap_interval_secs: 5 * 60,
+        }
+    }
+}
+
+impl TusConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.enabled && self.store == UploadStore::Redis {
+            RedisUrl::parse(&self.redis_url).context("Invalid redis_url")?;
+        }
+        if self.expire_secs == 0 || self.reap_interval_secs == 0 {
+            bail!("expire_secs and reap_interval_secs must be positive");
+        }
+        Ok(())
+    }
+}
+
+/// What is known about an upload besides its data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UploadInfo {
+    length: u64,
+    /// The `Upload-Metadata` header it was created with.
+    metadata: Option<String>,
+    /// The `X-Api-Key` it was created with.
+    tenant: Option<String>,
+    /// Unix time after which it is gone.
+    expires: u64,
+    /// Headers of the `/compress` response, once compressed.
+    result: Option<Vec<(String, String)>>,
+}
+
+/// What [`Uploads::reap`] did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Reaped {
+    pub expired: usize,
+    pub evicted: usize,
+    /// Bytes the remaining uploads hold or have reserved.
+    pub bytes: u64,
+}
+
+/// The state of all uploads.
+pub struct Uploads {
+    store: Store,
+    expire_secs: u64,
+    max_storage_bytes: u64,
+    /// Uploads a request is writing to, so concurrent `PATCH`es of one
+    /// upload cannot interleave.
+    busy: Mutex<HashSet<String>>,
+}
+
+enum Store {
+    Disk(PathBuf),
+    Redis {
+        client: Box<RedisClient>,
+        prefix: String,
+    },
+}
+
+/// Outcome of [`Uploads::append`].
+enum Append {
+    Written(u64),
+    /// The upload is at this offset instead.
+    Conflict(u64),
+}
+
+/// Appends `ARGV[2]` to `KEYS[1]` if it is `ARGV[1]` bytes long, then
+/// sets it to expire in `ARGV[3]` seconds. Returns the new length, or
+/// `-1 - length` if the length did not match.
+const APPEND_SCRIPT: &str = "local len = redis.call('STRLEN', KEYS[1]) \
+     if len ~= tonumber(ARGV[1]) then return -1 - len end \
+     local n = redis.call('APPEND', KEYS[1], ARGV[2]) \
+     redis.call('EXPIRE', KEYS[1], ARGV[3]) \
+     return n";
+
+impl Uploads {
+    pub fn new(config: &TusConfig) -> Self {
+        let store = match config.store {
+            UploadStore::Disk => Store::Disk(config.dir.clone()),
+            UploadStore::Redis => Store::Redis {
+                client: Box::new(RedisClient::new(
+                    RedisUrl::parse(&config.redis_url)
+                        .expect("uploads settings are checked by Config::validate"),
+                )),
+                prefix: config.redis_prefix.clone(),
+            },
+        };
+        Self {
+            store,
+            expire_secs: config.expire_secs,
+            max_storage_bytes: config.max_storage_bytes,
+            busy: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Checks that Redis answers, for the `redis` store. `None` for the
+    /// disk store, which has nothing to check.
+    pub async fn ping(&self) -> Option<Result<()>> {
+        let Store::Redis { client, .. } = &self.store else {
+            return None;
+        };
+        Some(match client.command(&[b"PING"]).await {
+            Ok(Reply::Status(status)) if status == "PONG" => Ok(()),
+            Ok(reply) => Err(anyhow!("Unexpected reply to PING: {:?}", reply)),
+            Err(e) => Err(e),
+        })
+    }
+
+    async fn create(&self, id: &str, info: &UploadInfo) -> Result<()> {
+        match &self.store {
+            Store::Disk(dir) => {
+                tokio::fs::create_dir_all(dir)
+                    .await
+                    .with_context(|| format!("Failed to create {}", dir.display()))?;
+                tokio::fs::write(dir.join(format!("{}.part", id)), b"").await?;
+                self.put_info(id, info).await
+            }
+            Store::Redis { .. } => {
+                self.redis_set(&format!("{}:data", id), b"", info.expires)
+                    .await?;
+                self.put_info(id, info).await
+            }
+        }
+    }
+
+    /// The upload's info, unless it does not exist or has expired.
+    async fn info(&self, id: &str) -> Result<Option<UploadInfo>> {
+        let data = match &self.store {
+            Store::Disk(dir) => match tokio::fs::read(dir.join(format!("{}.json", id))).await {
+                Ok(data) => data,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e.into()),
+            },
+            Store::Redis { client, prefix } => {
+                match client
+                    .command(&[b"GET", format!("{}{}", prefix, id).as_bytes()])
+                    .await?
+                {
+                    Reply::Bulk(data) => data,
+                    _ => return Ok(None),
+                }
+            }
+        };
+        let info: UploadInfo = serde_json::from_slice(&data).context("Corrupt upload info")?;
+        if info.expires <= unix_now() {
+            self.delete(id).await?;
+            return Ok(None);
+        }
+        Ok(Some(info))
+    }
+
+    async fn put_info(&self, id: &str, info: &UploadInfo) -> Result<()> {
+        let data = serde_json::to_vec(info)?;
+        match &self.store {
+            Store::Disk(dir) => {
+                // Written aside and renamed, so readers never see half of it.
+                let path = dir.join(format!("{}.json", id));
+                let temporary = dir.join(format!("{}.json.tmp", id));
+                tokio::fs::write(&temporary, data).await?;
+                tokio::fs::rename(&temporary, &path).await?;
+                Ok(())
+            }
+            Store::Redis { .. } => self.redis_set(id, &data, info.expires).await,
+        }
+    }
+
+    async fn offset(&self, id: &str) -> Result<u64> {
+        match &self.store {
+            Store::Disk(dir) => Ok(tokio::fs::metadata(dir.join(format!("{}.part", id)))
+                .await?
+                .len()),
+            Store::Redis { client, prefix } => {
+                match client
+                    .command(&[b"STRLEN", format!("{}{}:data", prefix, id).as_bytes()])
+                    .await?
+                {
+                    Reply::Int(len) => Ok(len as u64),
+                    other => bail!("Unexpected STRLEN reply {:?}", other),
+                }
+            }
+        }
+    }
+
+    /// Appends `chunk` if the upload is at `offset`.
+    async fn append(&self, id: &str, offset: u64, chunk: &[u8], expires: u64) -> Result<Append> {
+        match &self.store {
+            Store::Disk(dir) => {
+                let current = self.offset(id).await?;
+                if current != offset {
+                    return Ok(Append::Conflict(current));
+                }
+                let mut file = tokio::fs::OpenOptions::new()
+                    .append(true)
+                    .open(dir.join(format!("{}.part", id)))
+                    .await?;
+                file.write_all(chunk).await?;
+                file.flush().await?;
+                Ok(Append::Written(offset + chunk.len() as u64))
+            }
+            Store::Redis { client, prefix } => {
+                let key = format!("{}{}:data", prefix, id);
+                let offset = offset.to_string();
+                let ttl = ttl_secs(expires).to_string();
+                let reply = client
+                    .command(&[
+                        b"EVAL",
+                        APPEND_SCRIPT.as_bytes(),
+                        b"1",
+                        key.as_bytes(),
+                        offset.as_bytes(),
+                        chunk,
+                        ttl.as_bytes(),
+                    ])
+                    .await?;
+                match reply {
+                    Reply::Int(len) if len >= 0 => Ok(Append::Written(len as u64)),
+                    Reply::Int(conflict) => Ok(Append::Conflict((-1 - conflict) as u64)),
+                    other => bail!("Unexpected EVAL reply {:?}", other),
+                }
+            }
+        }
+    }
+
+    async fn data(&self, id: &str) -> Result<Bytes> {
+        self.read_blob(id, "part", "data").await
+    }
+
+    async fn result(&self, id: &str) -> Result<Bytes> {
+        self.read_blob(id, "out", "result").await
+    }
+
+    async fn save_result(&self, id: &str, info: &UploadInfo, data: &[u8]) -> Result<()> {
+        match &self.store {
+            Store::Disk(dir) => tokio::fs::write(dir.join(format!("{}.out", id)), data).await?,
+            Store::Redis { .. } => {
+                self.redis_set(&format!("{}:result", id), data, info.expires)
+                    .await?
+            }
+        }
+        self.put_info(id, info).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        match &self.store {
+            Store::Disk(dir) => {
+                for extension in ["json", "part", "out"] {
+                    match tokio::fs::remove_file(dir.join(format!("{}.{}", id, extension))).await {
+                        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                        _ => {}
+                    }
+                }
+                Ok(())
+            }
+            Store::Redis { client, prefix } => {
+                let keys = [
+                    format!("{}{}", prefix, id),
+                    format!("{}{}:data", prefix, id),
+                    format!("{}{}:result", prefix, id),
+                ];
+                let mut command: Vec<&[u8]> = vec![b"DEL"];
+                command.extend(keys.iter().map(|k| k.as_bytes()));
+                client.command(&command).await.map(drop)
+            }
+        }
+    }
+
+    async fn read_blob(&self, id: &str, extension: &str, suffix: &str) -> Result<Bytes> {
+        match &self.store {
+            Store::Disk(dir) => Ok(tokio::fs::read(dir.join(format!("{}.{}", id, extension)))
+                .await?
+                .into()),
+            Store::Redis { client, prefix } => {
+                match client
+                    .command(&[b"GET", format!("{}{}:{}", prefix, id, suffix).as_bytes()])
+                    .await?
+                {
+                    Reply::Bulk(data) => Ok(data.into()),
+                    other => bail!("Unexpected GET reply {:?}", other),
+                }
+            }
+        }
+    }
+
+    /// Sets `<prefix><key>` to expire along with its upload.
+    async fn redis_set(&self, key: &str, value: &[u8], expires: u64) -> Result<()> {
+        let Store::Redis { client, prefix } = &self.store else {
+            unreachable!("only called for the redis store");
+        };
+        let key = format!("{}{}", prefix, key);
+        let ttl = ttl_secs(expires).to_string();
+        client
+            .command(&[b"SET", key.as_bytes(), value, b"EX", ttl.as_bytes()])
+            .await
+            .map(drop)
+    }
+
+    /// Deletes the expired uploads of the `disk` store, then the oldest
+    /// ones not being written to until the rest is within
+    /// `max_storage_bytes`, keeping `reserve` bytes free. The `redis` store
+    /// expires uploads by itself.
+    pub async fn reap(&self, reserve: u64) -> Result<Reaped> {
+        let Store::Disk(dir) = &self.store else {
+            return Ok(Reaped::default());
+        };
+        let mut reaped = Reaped::default();
+        let mut live = Vec::new();
+        let mut entries = match tokio::fs::read_dir(dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(reaped),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some(id) = name.strip_suffix(".json") else {
+                continue;
+            };
+            // Reading the info deletes the upload once it expired.
+            match self.info(id).await {
+                Ok(Some(info)) => {
+                    let result = tokio::fs::metadata(dir.join(format!("{}.out", id)))
+                        .await
+                        .map_or(0, |m| m.len());
+                    live.push((info.expires, id.to_string(), info.length + result));
+                }
+                Ok(None) => reaped.expired += 1,
+                Err(e) => warn!("Failed to check upload {} for expiry: {:#}", id, e),
+            }
+        }
+        reaped.bytes = live.iter().map(|(_, _, bytes)| bytes).sum();
+
+        if self.max_storage_bytes > 0 {
+            live.sort();
+            for (_, id, bytes) in live {
+                if reaped.bytes + reserve <= self.max_storage_bytes {
+                    break;
+                }
+                if self
+                    .busy
+                    .lock()
+                    .expect("upload lock poisoned")
+                    .contains(&id)
+                {
+                    continue;
+                }
+                self.delete(&id).await?;
+                reaped.evicted += 1;
+                reaped.bytes -= bytes;
+            }
+        }
+        if reaped.expired > 0 {
+            metrics::counter!("tus_uploads_reaped_total", reaped.expired as u64, "reason" => "expired");
+        }
+        if reaped.evicted > 0 {
+            metrics::counter!("tus_uploads_reaped_total", reaped.evicted as u64, "reason" => "evicted");
+        }
+        Ok(reaped)
+    }
+
+    /// Whether `length` more bytes fit under `max_storage_bytes`, after
+    /// making room.
+    async fn has_room(&self, length: u64) -> Result<bool> {
+        if self.max_storage_bytes == 0 || !matches!(self.store, Store::Disk(_)) {
+            return Ok(true);
+        }
+        // Evicting uploads cannot make room for one larger than the cap.
+        if length > self.max_storage_bytes {
+            return Ok(false);
+        }
+        let reaped = self.reap(length).await?;
+        Ok(reaped.bytes + length <= self.max_storage_bytes)
+    }
+
+    /// Marks `id` as being written to, unless it already is.
+    fn lock(self: &Arc<Self>, id: &str) -> Option<BusyGuard> {
+        let mut busy = self.busy.lock().expect("upload lock poisoned");
+        busy.insert(id.to_string()).then(|| BusyGuard {
+            uploads: self.clone(),
+            id: id.to_string(),
+        })
+    }
+}
+
+struct BusyGuard {
+    uploads: Arc<Uploads>,
+    id: String,
+}
+
+impl Drop for BusyGuard {
+    fn drop(&mut self) {
+        if let Ok(mut busy) = self.uploads.busy.lock() {
+            busy.remove(&self.id);
+        }
+    }
+}
+
+/// The `/uploads` routes. Every response carries `Tus-Resumable`.
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/uploads", post(create_upload).options(describe))
+        .route(
+            "/uploads/:id",
+            head(upload_status)
+                .patch(append_upload)
+                .get(upload_result)
+                .delete(terminate_upload),
+        )
+        .layer(middleware::map_response(add_tus_headers))
+}
+
+async fn add_tus_headers(mut response: Response) -> Response {
+    let headers = response.headers_mut();
+    headers.insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+    if response.status() == StatusCode::PRECONDITION_FAILED {
+        response
+            .headers_mut()
+            .insert(TUS_VERSION_HEADER, HeaderValue::from_static(TUS_VERSION));
+    }
+    response
+}
+
+/// `OPTIONS /uploads`: what this server supports.
+async fn describe(State(state): State<AppState>) -> Response {
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    let headers = response.headers_mut();
+    headers.insert(TUS_VERSION_HEADER, HeaderValue::from_static(TUS_VERSION));
+    headers.insert(TUS_EXTENSION, HeaderValue::from_static(TUS_EXTENSIONS));
+    headers.insert(TUS_MAX_SIZE, state.config.max_body_bytes.into());
+    response
+}
+
+/// `POST /uploads`: creates an upload, optionally with its first chunk.
+async fn create_upload(
+    State(state): State<AppState>,
+    timings: Option<Extension<Timings>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    check_version(&headers)?;
+    if headers.contains_key(UPLOAD_DEFER_LENGTH) {
+        return Err(ApiError::bad_request(
+            "Upload-Defer-Length is not supported; send Upload-Length.",
+        ));
+    }
+    let length: u64 = header_str(&headers, &UPLOAD_LENGTH)
+        .ok_or_else(|| ApiError::bad_request("Missing Upload-Length header."))?
+        .parse()
+        .map_err(|_| ApiError::bad_request("Invalid Upload-Length header."))?;
+    if length == 0 {
+        return Err(ApiError::bad_request("Upload-Length must be positive."));
+    }
+    let (limit, _) =
+        state
+            .config
+            .body_limits
+            .limit_for(state.config.max_body_bytes, "/compress", &headers);
+    if length > limit as u64 {
+        return Err(ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            format!("Upload-Length exceeds the limit of {} bytes.", limit),
+        )
+        .with_details(json!({ "limit_bytes": limit })));
+    }
+    let metadata = header_str(&headers, &UPLOAD_METADATA).map(str::to_string);
+    if let Some(metadata) = &metadata {
+        parse_metadata(metadata)
+            .ok_or_else(|| ApiError::bad_request("Invalid Upload-Metadata header."))?;
+    }
+
+    if !state.uploads.has_room(length).await.map_err(store_error)? {
+        return Err(ApiError::new(
+            StatusCode::INSUFFICIENT_STORAGE,
+            "upload_storage_full",
+            "There is no room for an upload of this length right now.",
+        ));
+    }
+
+    let id = new_id();
+    let expires = unix_now() + state.uploads.expire_secs;
+    let info = UploadInfo {
+        length,
+        metadata,
+        tenant: api_key(&headers).map(str::to_string),
+        expires,
+        result: None,
+    };
+    state
+        .uploads
+        .create(&id, &info)
+        .await
+        .map_err(store_error)?;
+    metrics::increment_counter!("tus_uploads_created_total");
+    info!(upload = %id, length, "Created upload.");
+
+    let mut response = if body.is_empty() {
+        StatusCode::CREATED.into_response()
+    } else {
+        check_content_type(&headers)?;
+        let mut response = write_chunk(&state, timings, &headers, &id, info, 0, body).await?;
+        *response.status_mut() = StatusCode::CREATED;
+        response
+    };
+    let location = format!("/uploads/{}", id);
+    response.headers_mut().insert(
+        header::LOCATION,
+        HeaderValue::from_str(&location).expect("upload IDs are hex"),
+    );
+    insert_expires(&mut response, expires);
+    Ok(response)
+}
+
+/// `HEAD /uploads/{id}`: how much of the upload has arrived.
+async fn upload_status(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    check_version(&headers)?;
+    let info = find(&state, &id, &headers).await?;
+    let offset = state.uploads.offset(&id).await.map_err(store_error)?;
+
+    let mut response = StatusCode::OK.into_response();
+    let response_headers = response.headers_mut();
+    response_headers.insert(UPLOAD_OFFSET, offset.into());
+    response_headers.insert(UPLOAD_LENGTH, info.length.into());
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    if let Some(value) = info.metadata.and_then(|m| HeaderValue::from_str(&m).ok()) {
+        response_headers.insert(UPLOAD_METADATA, value);
+    }
+    insert_expires(&mut response, info.expires);
+    Ok(response)
+}
+
+/// `PATCH /uploads/{id}`: appends a chunk, compressing the upload once
+/// complete.
+async fn append_upload(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    timings: Option<Extension<Timings>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    check_version(&headers)?;
+    check_content_type(&headers)?;
+    let offset: u64 = header_str(&headers, &UPLOAD_OFFSET)
+        .ok_or_else(|| ApiError::bad_request("Missing Upload-Offset header."))?
+        .parse()
+        .map_err(|_| ApiError::bad_request("Invalid Upload-Offset header."))?;
+    let info = find(&state, &id, &headers).await?;
+    write_chunk(&state, timings, &headers, &id, info, offset, body).await
+}
+
+async fn write_chunk(
+    state: &AppState,
+    timings: Option<Extension<Timings>>,
+    headers: &HeaderMap,
+    id: &str,
+    mut info: UploadInfo,
+    offset: u64,
+    chunk: Bytes,
+) -> Result<Response, ApiError> {
+    let Some(_guard) = state.uploads.lock(id) else {
+        return Err(ApiError::new(
+            StatusCode::LOCKED,
+            "upload_locked",
+            "Another request is writing to this upload.",
+        ));
+    };
+    if offset + chunk.len() as u64 > info.length {
+        return Err(ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "upload_length_exceeded",
+            format!("The chunk would go past Upload-Length {}.", info.length),
+        ));
+    }
+    let offset = match state
+        .uploads
+        .append(id, offset, &chunk, info.expires)
+        .await
+        .map_err(store_error)?
+    {
+        Append::Written(offset) => offset,
+        Append::Conflict(current) => {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "offset_mismatch",
+                format!("The upload is at offset {}.", current),
+            )
+            .with_details(json!({ "offset": current })))
+        }
+    };
+
+    let mut response = if offset == info.length && (!chunk.is_empty() || info.result.is_none()) {
+        metrics::increment_counter!("tus_uploads_completed_total");
+        let input = state.uploads.data(id).await.map_err(store_error)?;
+        let headers = compress_headers(headers, &info);
+        let options = Options::resolve(
+            &state.config,
+            &Uri::from_static("/compress"),
+            headers.clone(),
+            input,
+        )
+        .map(|options| Options {
+            timings: timings
+                .map(|Extension(timings)| timings)
+                .unwrap_or_default(),
+            ..options
+        });
+        let compressed = compress_handler(State(state.clone()), headers, options).await;
+        match compressed {
+            response if response.status().is_success() => {
+                let (parts, body) = response.into_parts();
+                let data = to_bytes(body, usize::MAX)
+                    .await
+                    .map_err(|e| ApiError::internal(format!("Failed to read result: {}", e)))?;
+                let mut result_headers: Vec<_> = parts
+                    .headers
+                    .iter()
+                    .filter(|(name, _)| *name != header::CONTENT_LENGTH)
+                    .filter_map(|(name, value)| {
+                        Some((name.to_string(), value.to_str().ok()?.to_string()))
+                    })
+                    .collect();
+                result_headers.push((header::ETAG.to_string(), range::etag(&data)));
+                info.result = Some(result_headers);
+                state
+                    .uploads
+                    .save_result(id, &info, &data)
+                    .await
+                    .map_err(store_error)?;
+                info!(upload = %id, "Compressed completed upload.");
+                StatusCode::NO_CONTENT.into_response()
+            }
+            response => response,
+        }
+    } else {
+        StatusCode::NO_CONTENT.into_response()
+    };
+    response.headers_mut().insert(UPLOAD_OFFSET, offset.into());
+    insert_expires(&mut response, info.expires);
+    Ok(response)
+}
+
+/// `GET /uploads/{id}`: the compressed image of a completed upload, or
+/// the part of it asked for in a `Range` header.
+async fn upload_result(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let info = find(&state, &id, &headers).await?;
+    let Some(result_headers) = info.result else {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "upload_not_compressed",
+            "The upload is incomplete or failed to compress.",
+        ));
+    };
+    let data = state.uploads.result(&id).await.map_err(store_error)?;
+    let etag = result_headers
+        .iter()
+        .find(|(name, _)| name == header::ETAG.as_str())
+        .map(|(_, value)| value.as_str());
+    let mut response = range::serve(&headers, data, etag);
+    if !response.status().is_success() {
+        return Ok(response);
+    }
+    for (name, value) in result_headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+    Ok(response)
+}
+
+/// `DELETE /uploads/{id}`: discards an upload and its result.
+async fn terminate_upload(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    check_version(&headers)?;
+    find(&state, &id, &headers).await?;
+    state.uploads.delete(&id).await.map_err(store_error)?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// The upload `id`, if it exists and belongs to the caller.
+async fn find(state: &AppState, id: &str, headers: &HeaderMap) -> Result<UploadInfo, ApiError> {
+    let not_found = || ApiError::new(StatusCode::NOT_FOUND, "upload_not_found", "No such upload.");
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(not_found());
+    }
+    match state.uploads.info(id).await.map_err(store_error)? {
+        Some(info) if info.tenant.as_deref() == api_key(headers) => Ok(info),
+        _ => Err(not_found()),
+    }
+}
+
+/// The headers the completing request passes to `/compress`.
+fn compress_headers(headers: &HeaderMap, info: &UploadInfo) -> HeaderMap {
+    let mut headers = headers.clone();
+    headers.remove(header::CONTENT_TYPE);
+    headers.remove(header::CONTENT_LENGTH);
+    let filename = info
+        .metadata
+        .as_deref()
+        .and_then(parse_metadata)
+        .and_then(|pairs| pairs.into_iter().find(|(key, _)| key == "filename"))
+        .and_then(|(_, value)| value);
+    if let Some(filename) = filename {
+        if !headers.contains_key(super::compress::FILENAME_HEADER) {
+            if let Ok(value) = HeaderValue::from_str(&filename::percent_encode(&filename)) {
+                headers.insert(super::compress::FILENAME_HEADER, value);
+            }
+        }
+    }
+    headers
+}
+
+fn check_version(headers: &HeaderMap) -> Result<(), ApiError> {
+    match header_str(headers, &TUS_RESUMABLE) {
+        Some(TUS_VERSION) => Ok(()),
+        _ => Err(ApiError::new(
+            StatusCode::PRECONDITION_FAILED,
+            "tus_version_unsupported",
+            format!("Send Tus-Resumable: {}.", TUS_VERSION),
+        )),
+    }
+}
+
+fn check_content_type(headers: &HeaderMap) -> Result<(), ApiError> {
+    match header_str(headers, &header::CONTENT_TYPE) {
+        Some(OFFSET_CONTENT_TYPE) => Ok(()),
+        _ => Err(ApiError::unsupported_media_type(format!(
+            "Chunks must be sent as {}.",
+            OFFSET_CONTENT_TYPE
+        ))),
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &HeaderName) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+}
+
+/// Parses `key base64value,key2,...`. Returns `None` if it is malformed.
+fn parse_metadata(metadata: &str) -> Option<Vec<(String, Option<String>)>> {
+    metadata
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut parts = pair.split(' ').filter(|p| !p.is_empty());
+            let key = parts.next()?.to_string();
+            let value = match parts.next() {
+                Some(encoded) => {
+                    let decoded = base64::engine::general_purpose::STANDARD
+                        .decode(encoded)
+                        .ok()?;
+                    Some(String::from_utf8(decoded).ok()?)
+                }
+                None => None,
+            };
+            parts.next().is_none().then_some((key, value))
+        })
+        .collect()
+}
+
+fn insert_expires(response: &mut Response, expires: u64) {
+    if let Ok(value) = HeaderValue::from_str(&http_date(expires)) {
+        response.headers_mut().insert(UPLOAD_EXPIRES, value);
+    }
+}
+
+/// Formats a Unix time as an HTTP date, e.g. `Wed, 25 Jun 2014 16:00:00 GMT`.
+fn http_date(unix: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (days, secs) = (unix / 86_400, unix % 86_400);
+    let (year, month, day) = super::audit::civil_from_days(days);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+/// An unguessable upload ID: whoever knows it can read the result.
+fn new_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut digest = Sha256::new();
+    // `RandomState` keys are seeded from the OS's random source.
+    for _ in 0..2 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        digest.update(hasher.finish().to_le_bytes());
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    digest.update(now.as_nanos().to_le_bytes());
+    format!("{:x}", digest.finalize())[..32].to_string()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn ttl_secs(expires: u64) -> u64 {
+    expires.saturating_sub(unix_now()).max(1)
+}
+
+fn store_error(e: anyhow::Error) -> ApiError {
+    warn!("Upload store failed: {:#}", e);
+    ApiError::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "upload_store_unavailable",
+        "The upload store is unavailable.",
+    )
+}
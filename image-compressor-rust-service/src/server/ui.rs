@@ -0,0 +1,13 @@
+// image-compressor-rust-service/src/server/ui.rs
+
+use axum::response::{Html, IntoResponse};
+
+/// The page served on `/ui`: drag-and-drop upload, the common options,
+/// and the input and output side by side with their sizes. It only calls
+/// `/compress` from the browser, with the options as headers.
+const PAGE: &str = include_str!("ui.html");
+
+/// Serves the manual testing page.
+pub async fn ui_handler() -> impl IntoResponse {
+    Html(PAGE)
+}
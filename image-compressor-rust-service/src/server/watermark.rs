@@ -0,0 +1,59 @@
+// image-compressor-rust-service/src/server/watermark.rs
+
+use super::compress::check_input_format;
+use super::{decode_still, request_id, ApiError, AppState};
+use crate::cpu::Priority;
+use axum::{body::Bytes, extract::State, http::HeaderMap, Json};
+use serde_json::{json, Value};
+
+/// Reads the invisible watermark of an image, answering with
+/// `{"found": bool, "payload": number|null, "confidence": number}` (see
+/// [`crate::watermark`]). Only routed when `[watermark] secret` is set.
+///
+/// The image must be the same size as when it was marked; it is checked
+/// and decoded like a `/compress` input.
+pub async fn detect_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Value>, ApiError> {
+    let request_id = request_id(&headers).to_owned();
+    if body.is_empty() {
+        return Err(
+            ApiError::bad_request("Request body cannot be empty.").with_request_id(request_id)
+        );
+    }
+    check_input_format(&body, &state.config.allowed_input_formats)
+        .map_err(|e| e.with_request_id(&request_id))?;
+
+    metrics::increment_counter!("watermark_detect_requests_total");
+    let config = state.config.clone();
+    let threads = state.cpu.acquire(Priority::default()).await;
+    let detection = tokio::task::spawn_blocking(move || {
+        let _threads = threads;
+        let image = decode_still(&body, &config)?;
+        config
+            .watermark
+            .detect(&image)
+            .map_err(|e| ApiError::internal(format!("{:#}", e)))
+    })
+    .await
+    .map_err(|e| {
+        ApiError::internal(format!("Watermark task failed: {}", e)).with_request_id(&request_id)
+    })?
+    .map_err(|e| e.with_request_id(&request_id))?;
+
+    if let Some(payload) = detection.payload {
+        metrics::increment_counter!("watermark_detections_total");
+        tracing::info!(
+            payload,
+            confidence = detection.confidence,
+            "Watermark found"
+        );
+    }
+    Ok(Json(json!({
+        "found": detection.payload.is_some(),
+        "payload": detection.payload,
+        "confidence": detection.confidence,
+    })))
+}
@@ -0,0 +1,267 @@
+// image-compressor-rust-service/src/simd.rs
+
+//! Detection of CPU vector extensions and dispatch of the pixel loops that
+//! benefit from them.
+//!
+//! The binary is built for the baseline of its target, so x86-64 builds
+//! cannot assume AVX2. Three in-tree loops are compiled a second time with
+//! AVX2 enabled and picked at runtime when the CPU has it:
+//!
+//! * [`is_gray`], the grey check every still output goes through;
+//! * [`rgb_to_ycbcr`], the colour conversion of the in-tree JPEG encoder,
+//!   used for custom quantization tables and `encoders.jpeg.backend = "builtin"`;
+//! * [`map_rgb`], the `X-Filter` pass.
+//!
+//! NEON is part of the aarch64 baseline, so there the baseline loops are
+//! already the NEON ones. Every path computes exactly the same values; Rust
+//! never fuses multiplies and adds behind the code's back, so outputs do
+//! not depend on the node that produced them.
+//!
+//! Decoding, resampling, ICC conversion and the default encoders are done
+//! by dependencies, built for the baseline, and are not dispatched.
+//!
+//! [`SimdMode::Scalar`] turns dispatch off, to tell whether a difference
+//! between nodes comes from it. The detected features and the path in use
+//! are logged at startup and reported by `/version`.
+
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+/// Whether vector code paths may be used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SimdMode {
+    /// The fastest path the CPU supports.
+    #[default]
+    Auto,
+    /// The baseline path everywhere.
+    Scalar,
+}
+
+/// A set of code paths, compiled for one level of vector support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Path {
+    /// Built for the target's baseline.
+    Scalar,
+    /// x86-64 with AVX2.
+    Avx2,
+    /// aarch64, whose baseline includes NEON.
+    Neon,
+}
+
+impl Path {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Scalar => "scalar",
+            Self::Avx2 => "avx2",
+            Self::Neon => "neon",
+        }
+    }
+
+    /// The fastest path this CPU can run.
+    pub fn detected() -> Self {
+        let features = CpuFeatures::detect();
+        if features.avx2 {
+            Self::Avx2
+        } else if features.neon {
+            Self::Neon
+        } else {
+            Self::Scalar
+        }
+    }
+
+    /// `self` if this CPU can run it, the scalar path otherwise.
+    fn supported(self) -> Self {
+        match self {
+            Self::Avx2 if !CpuFeatures::detect().avx2 => Self::Scalar,
+            Self::Neon if !CpuFeatures::detect().neon => Self::Scalar,
+            path => path,
+        }
+    }
+}
+
+/// The vector extensions of the CPU the service runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CpuFeatures {
+    /// `x86_64`, `aarch64`, ...
+    pub arch: &'static str,
+    pub sse4_1: bool,
+    pub avx2: bool,
+    pub fma: bool,
+    pub avx512f: bool,
+    pub neon: bool,
+}
+
+impl CpuFeatures {
+    /// Queries the CPU; the answer is cached.
+    pub fn detect() -> Self {
+        static FEATURES: OnceLock<CpuFeatures> = OnceLock::new();
+        *FEATURES.get_or_init(|| {
+            #[allow(unused_mut)]
+            let mut features = CpuFeatures {
+                arch: std::env::consts::ARCH,
+                sse4_1: false,
+                avx2: false,
+                fma: false,
+                avx512f: false,
+                neon: false,
+            };
+            #[cfg(target_arch = "x86_64")]
+            {
+                features.sse4_1 = std::arch::is_x86_feature_detected!("sse4.1");
+                features.avx2 = std::arch::is_x86_feature_detected!("avx2");
+                features.fma = std::arch::is_x86_feature_detected!("fma");
+                features.avx512f = std::arch::is_x86_feature_detected!("avx512f");
+            }
+            #[cfg(target_arch = "aarch64")]
+            {
+                features.neon = std::arch::is_aarch64_feature_detected!("neon");
+            }
+            features
+        })
+    }
+
+    /// The names of the extensions present.
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            ("sse4.1", self.sse4_1),
+            ("avx2", self.avx2),
+            ("fma", self.fma),
+            ("avx512f", self.avx512f),
+            ("neon", self.neon),
+        ]
+        .into_iter()
+        .filter_map(|(name, present)| present.then_some(name))
+        .collect()
+    }
+}
+
+static MODE: OnceLock<SimdMode> = OnceLock::new();
+
+/// Sets whether vector paths may be used. Only the first call counts;
+/// without one, the mode is [`SimdMode::Auto`].
+pub fn init(mode: SimdMode) {
+    let _ = MODE.set(mode);
+}
+
+/// The path the dispatched loops take.
+pub fn path() -> Path {
+    match MODE.get().copied().unwrap_or_default() {
+        SimdMode::Auto => Path::detected(),
+        SimdMode::Scalar => Path::Scalar,
+    }
+}
+
+/// Whether the first three channels of every `channels`-byte pixel are at
+/// most `tolerance` apart. With four channels, pixels whose fourth (alpha)
+/// is 0 are not looked at.
+pub fn is_gray(pixels: &[u8], channels: usize, tolerance: u8) -> bool {
+    is_gray_on(path(), pixels, channels, tolerance)
+}
+
+/// [`is_gray`] on a given path, or the scalar one if this CPU cannot run
+/// it.
+pub fn is_gray_on(path: Path, pixels: &[u8], channels: usize, tolerance: u8) -> bool {
+    match path.supported() {
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: `supported` only keeps AVX2 when the CPU has it.
+        Path::Avx2 => unsafe { avx2::is_gray(pixels, channels, tolerance) },
+        _ => is_gray_scalar(pixels, channels, tolerance),
+    }
+}
+
+/// Splits packed 8-bit RGB into the Y, Cb and Cr planes of a JFIF JPEG.
+pub fn rgb_to_ycbcr(rgb: &[u8]) -> [Vec<f32>; 3] {
+    rgb_to_ycbcr_on(path(), rgb)
+}
+
+/// [`rgb_to_ycbcr`] on a given path, or the scalar one if this CPU cannot
+/// run it.
+pub fn rgb_to_ycbcr_on(path: Path, rgb: &[u8]) -> [Vec<f32>; 3] {
+    match path.supported() {
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: `supported` only keeps AVX2 when the CPU has it.
+        Path::Avx2 => unsafe { avx2::rgb_to_ycbcr(rgb) },
+        _ => rgb_to_ycbcr_scalar(rgb),
+    }
+}
+
+/// Replaces the first three channels of every `channels`-byte pixel with
+/// `map` of them; other channels are kept.
+pub fn map_rgb(pixels: &mut [u8], channels: usize, map: impl Fn([u8; 3]) -> [u8; 3]) {
+    map_rgb_on(path(), pixels, channels, map)
+}
+
+/// [`map_rgb`] on a given path, or the scalar one if this CPU cannot run
+/// it.
+pub fn map_rgb_on(
+    path: Path,
+    pixels: &mut [u8],
+    channels: usize,
+    map: impl Fn([u8; 3]) -> [u8; 3],
+) {
+    match path.supported() {
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: `supported` only keeps AVX2 when the CPU has it.
+        Path::Avx2 => unsafe { avx2::map_rgb(pixels, channels, map) },
+        _ => map_rgb_scalar(pixels, channels, map),
+    }
+}
+
+/// Pixels checked per block; blocks are not cut short so that the loop
+/// over one can be vectorized.
+const GRAY_BLOCK: usize = 64;
+
+#[inline(always)]
+fn is_gray_scalar(pixels: &[u8], channels: usize, tolerance: u8) -> bool {
+    let transparent = |pixel: &[u8]| channels == 4 && pixel[3] == 0;
+    pixels.chunks(channels * GRAY_BLOCK).all(|block| {
+        block.chunks_exact(channels).fold(true, |gray, pixel| {
+            let (r, g, b) = (pixel[0], pixel[1], pixel[2]);
+            let spread = r.max(g).max(b) - r.min(g).min(b);
+            gray & (spread <= tolerance || transparent(pixel))
+        })
+    })
+}
+
+#[inline(always)]
+fn rgb_to_ycbcr_scalar(rgb: &[u8]) -> [Vec<f32>; 3] {
+    let len = rgb.len() / 3;
+    let [mut y, mut cb, mut cr] = [(); 3].map(|_| vec![0f32; len]);
+    let planes = y.iter_mut().zip(cb.iter_mut()).zip(cr.iter_mut());
+    for (pixel, ((y, cb), cr)) in rgb.chunks_exact(3).zip(planes) {
+        let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(f32::from);
+        *y = 0.299 * r + 0.587 * g + 0.114 * b;
+        *cb = -0.168_736 * r - 0.331_264 * g + 0.5 * b + 128.0;
+        *cr = 0.5 * r - 0.418_688 * g - 0.081_312 * b + 128.0;
+    }
+    [y, cb, cr]
+}
+
+#[inline(always)]
+fn map_rgb_scalar(pixels: &mut [u8], channels: usize, map: impl Fn([u8; 3]) -> [u8; 3]) {
+    for pixel in pixels.chunks_exact_mut(channels) {
+        let mapped = map([pixel[0], pixel[1], pixel[2]]);
+        pixel[..3].copy_from_slice(&mapped);
+    }
+}
+
+/// The scalar loops, compiled with AVX2 enabled.
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn is_gray(pixels: &[u8], channels: usize, tolerance: u8) -> bool {
+        super::is_gray_scalar(pixels, channels, tolerance)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn rgb_to_ycbcr(rgb: &[u8]) -> [Vec<f32>; 3] {
+        super::rgb_to_ycbcr_scalar(rgb)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn map_rgb(pixels: &mut [u8], channels: usize, map: impl Fn([u8; 3]) -> [u8; 3]) {
+        super::map_rgb_scalar(pixels, channels, map)
+    }
+}
@@ -0,0 +1,198 @@
+// image-compressor-rust-service/src/social.rs
+
+//! Social cards (Open Graph and Twitter images): a background photo with a
+//! title, subtitle and logo composited over it, laid out by a server-side
+//! [`CardTemplate`].
+//!
+//! The background is scaled to cover the card, and darkened with the
+//! template's scrim so white text stays readable on any photo. The logo
+//! sits in the top-left corner; the title and subtitle are wrapped to the
+//! card's width and stacked along its bottom edge.
+
+use crate::options::{Color, Gravity};
+use crate::text::Font;
+use crate::transform;
+use anyhow::{bail, Context, Result};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Largest card a template may ask for.
+pub const MAX_CARD_SIDE: u32 = 4096;
+
+/// Settings for `/social-card`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocialConfig {
+    /// TrueType font for card text, also used to label `/contact-sheet`
+    /// thumbnails. Both endpoints are left out without one.
+    pub font: Option<PathBuf>,
+    /// Named templates, on top of (or replacing) the built-in `default`.
+    pub templates: BTreeMap<String, CardTemplate>,
+}
+
+impl SocialConfig {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(path) = &self.font {
+            Font::load(path)?;
+        }
+        for (name, template) in &self.templates {
+            template
+                .validate()
+                .with_context(|| format!("Invalid template '{}'", name))?;
+        }
+        Ok(())
+    }
+
+    /// The template `name`, from the config or built in.
+    pub fn template(&self, name: &str) -> Option<CardTemplate> {
+        self.templates
+            .get(name)
+            .cloned()
+            .or_else(|| (name == "default").then(CardTemplate::default))
+    }
+}
+
+/// Where things go on a card. The default is a 1200x630 Open Graph image.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CardTemplate {
+    pub width: u32,
+    pub height: u32,
+    /// Fills the card when the request has no background image.
+    pub background: Color,
+    /// Laid over the background image.
+    pub scrim: Color,
+    /// Space kept clear along every edge, in pixels.
+    pub margin: u32,
+    /// Height of the logo; it is never wider than half the card.
+    pub logo_height: u32,
+    pub title: TextStyle,
+    pub subtitle: TextStyle,
+}
+
+/// How a line of card text is set.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextStyle {
+    /// Font size in pixels.
+    pub size: u32,
+    pub color: Color,
+    /// Text that needs more lines is cut off with an ellipsis.
+    pub max_lines: usize,
+}
+
+impl Default for CardTemplate {
+    fn default() -> Self {
+        Self {
+            width: 1200,
+            height: 630,
+            background: Color([26, 26, 64, 255]),
+            scrim: Color([0, 0, 0, 112]),
+            margin: 72,
+            logo_height: 80,
+            title: TextStyle {
+                size: 64,
+                color: Color::WHITE,
+                max_lines: 3,
+            },
+            subtitle: TextStyle {
+                size: 32,
+                color: Color([255, 255, 255, 204]),
+                max_lines: 2,
+            },
+        }
+    }
+}
+
+impl CardTemplate {
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=MAX_CARD_SIDE).contains(&self.width) || !(1..=MAX_CARD_SIDE).contains(&self.height)
+        {
+            bail!(
+                "width and height must be between 1 and {}, got {}x{}",
+                MAX_CARD_SIDE,
+                self.width,
+                self.height
+            );
+        }
+        if self.margin * 2 >= self.width.min(self.height) {
+            bail!("margin leaves no room for content");
+        }
+        for (name, style) in [("title", &self.title), ("subtitle", &self.subtitle)] {
+            if style.size == 0 || style.max_lines == 0 {
+                bail!("{} size and max_lines must be positive", name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Composites `card` onto a new image of the template's size.
+    pub fn render(&self, font: &Font, card: &Card) -> RgbaImage {
+        let (width, height) = (self.width, self.height);
+        let mut canvas = match &card.background {
+            Some(background) => {
+                let mut canvas =
+                    transform::cover(background.clone(), (width, height), Gravity::default())
+                        .into_rgba8();
+                for pixel in canvas.pixels_mut() {
+                    crate::text::blend(pixel, self.scrim, 1.0);
+                }
+                canvas
+            }
+            None => RgbaImage::from_pixel(width, height, Rgba(self.background.0)),
+        };
+
+        let margin = self.margin as f32;
+        if let Some(logo) = &card.logo {
+            let scale = (f64::from(self.logo_height) / f64::from(logo.height()))
+                .min(f64::from(width / 2) / f64::from(logo.width()));
+            let size = (
+                ((f64::from(logo.width()) * scale).round() as u32).max(1),
+                ((f64::from(logo.height()) * scale).round() as u32).max(1),
+            );
+            let logo = imageops::resize(&logo.to_rgba8(), size.0, size.1, FilterType::Lanczos3);
+            let at = i64::from(self.margin);
+            imageops::overlay(&mut canvas, &logo, at, at);
+        }
+
+        // Text is stacked upwards from the bottom margin.
+        let text_width = (width - 2 * self.margin) as f32;
+        let set = |text: &str, style: &TextStyle| {
+            let size = style.size as f32;
+            let lines = font.wrap(text, size, text_width, style.max_lines);
+            (lines, size, style.color)
+        };
+        let mut blocks = vec![set(&card.title, &self.title)];
+        if let Some(subtitle) = card.subtitle.as_deref().filter(|s| !s.trim().is_empty()) {
+            blocks.push(set(subtitle, &self.subtitle));
+        }
+        let gap = self.subtitle.size as f32 / 2.0;
+        let block_height = |(lines, size, _): &(Vec<String>, f32, Color)| {
+            lines.len() as f32 * font.line_height(*size)
+        };
+        let total = blocks.iter().map(block_height).sum::<f32>()
+            + gap * (blocks.len().saturating_sub(1)) as f32;
+        let mut top = height as f32 - margin - total;
+        for block in &blocks {
+            let (lines, size, color) = block;
+            for (i, line) in lines.iter().enumerate() {
+                let baseline = top + font.ascent(*size) + i as f32 * font.line_height(*size);
+                font.draw(&mut canvas, line, (margin, baseline), *size, *color);
+            }
+            top += block_height(block) + gap;
+        }
+        canvas
+    }
+}
+
+/// What a card says and shows.
+#[derive(Debug, Clone)]
+pub struct Card {
+    pub title: String,
+    pub subtitle: Option<String>,
+    pub background: Option<DynamicImage>,
+    pub logo: Option<DynamicImage>,
+}
@@ -0,0 +1,620 @@
+// image-compressor-rust-service/src/text.rs
+
+//! Text rendering with TrueType fonts.
+//!
+//! Only what drawing a few lines of text needs is read from the font: the
+//! character map (formats 4 and 12), horizontal metrics and `glyf`
+//! outlines, including composite glyphs. There is no kerning, shaping or
+//! hinting, so scripts that need shaping render as separate glyphs, and
+//! CFF-flavoured OpenType fonts are refused. Outlines are filled with the
+//! non-zero winding rule and antialiased with exact horizontal coverage
+//! over a few sample rows per pixel.
+
+use crate::options::Color;
+use anyhow::{bail, Context, Result};
+use image::RgbaImage;
+use std::path::Path;
+
+/// Sample rows per pixel row.
+const SUBSAMPLES: usize = 5;
+
+/// Line segments each quadratic curve is flattened into.
+const CURVE_STEPS: usize = 8;
+
+/// Composite glyphs nested deeper than this are left out.
+const MAX_COMPONENT_DEPTH: u8 = 8;
+
+/// A parsed TrueType font.
+#[derive(Debug, Clone)]
+pub struct Font {
+    data: Vec<u8>,
+    units_per_em: f32,
+    ascender: f32,
+    descender: f32,
+    line_gap: f32,
+    long_offsets: bool,
+    num_glyphs: u16,
+    num_h_metrics: u16,
+    loca: usize,
+    glyf: usize,
+    hmtx: usize,
+    /// The character map subtable and its format.
+    cmap: (usize, u16),
+}
+
+/// One point of a glyph outline, in font units.
+#[derive(Debug, Clone, Copy)]
+struct Point {
+    x: f32,
+    y: f32,
+    on_curve: bool,
+}
+
+impl Font {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(data).with_context(|| format!("Invalid font {}", path.display()))
+    }
+
+    pub fn parse(data: Vec<u8>) -> Result<Self> {
+        let reader = Reader(&data);
+        match reader.u32(0) {
+            Some(0x0001_0000) | Some(0x7472_7565) => {}
+            Some(0x4f54_544f) => bail!("CFF (OTTO) fonts are not supported"),
+            _ => bail!("not a TrueType font"),
+        }
+        let num_tables = usize::from(reader.u16(4).context("truncated table directory")?);
+        let table = |tag: &[u8; 4]| {
+            (0..num_tables)
+                .map(|i| 12 + 16 * i)
+                .find(|&record| data.get(record..record + 4) == Some(tag))
+                .and_then(|record| reader.u32(record + 8))
+                .map(|offset| offset as usize)
+                .with_context(|| format!("missing '{}' table", String::from_utf8_lossy(tag)))
+        };
+        let (head, hhea, maxp) = (table(b"head")?, table(b"hhea")?, table(b"maxp")?);
+        let truncated = || anyhow::anyhow!("truncated font tables");
+
+        let cmap = table(b"cmap")?;
+        let subtables = usize::from(reader.u16(cmap + 2).ok_or_else(truncated)?);
+        let mut best: Option<(u8, usize, u16)> = None;
+        for i in 0..subtables {
+            let record = cmap + 4 + 8 * i;
+            let (platform, encoding) = (reader.u16(record), reader.u16(record + 2));
+            let offset = cmap + reader.u32(record + 4).ok_or_else(truncated)? as usize;
+            let format = reader.u16(offset).ok_or_else(truncated)?;
+            // Full Unicode tables first, then the Basic Multilingual Plane.
+            let rank = match (platform, encoding, format) {
+                (Some(3), Some(10), 12) | (Some(0), _, 12) => 2,
+                (Some(3), Some(1), 4) | (Some(0), _, 4) => 1,
+                _ => continue,
+            };
+            if best.is_none_or(|(r, _, _)| rank > r) {
+                best = Some((rank, offset, format));
+            }
+        }
+        let (_, cmap_offset, cmap_format) = best.context("no Unicode character map")?;
+
+        Ok(Self {
+            units_per_em: f32::from(reader.u16(head + 18).ok_or_else(truncated)?.max(1)),
+            long_offsets: reader.i16(head + 50).ok_or_else(truncated)? == 1,
+            ascender: f32::from(reader.i16(hhea + 4).ok_or_else(truncated)?),
+            descender: f32::from(reader.i16(hhea + 6).ok_or_else(truncated)?),
+            line_gap: f32::from(reader.i16(hhea + 8).ok_or_else(truncated)?),
+            num_h_metrics: reader.u16(hhea + 34).ok_or_else(truncated)?.max(1),
+            num_glyphs: reader.u16(maxp + 4).ok_or_else(truncated)?,
+            loca: table(b"loca")?,
+            glyf: table(b"glyf")?,
+            hmtx: table(b"hmtx")?,
+            cmap: (cmap_offset, cmap_format),
+            data,
+        })
+    }
+
+    /// Distance from the baseline to the top of the tallest glyphs, in
+    /// pixels at `size`.
+    pub fn ascent(&self, size: f32) -> f32 {
+        self.ascender * size / self.units_per_em
+    }
+
+    /// Baseline-to-baseline distance at `size`.
+    pub fn line_height(&self, size: f32) -> f32 {
+        (self.ascender - self.descender + self.line_gap) * size / self.units_per_em
+    }
+
+    /// Width of `text` in pixels at `size`.
+    pub fn measure(&self, text: &str, size: f32) -> f32 {
+        let scale = size / self.units_per_em;
+        text.chars()
+            .map(|c| f32::from(self.advance(self.glyph(c))) * scale)
+            .sum()
+    }
+
+    /// Breaks `text` into lines at most `max_width` wide, at most
+    /// `max_lines` of them. Text that does not fit ends in an ellipsis.
+    /// Words wider than a line are cut.
+    pub fn wrap(&self, text: &str, size: f32, max_width: f32, max_lines: usize) -> Vec<String> {
+        let fits = |line: &str| self.measure(line, size) <= max_width;
+        let mut lines = Vec::new();
+        let mut current = String::new();
+        for word in text.split_whitespace() {
+            let candidate = if current.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", current, word)
+            };
+            if fits(&candidate) {
+                current = candidate;
+                continue;
+            }
+            if !current.is_empty() {
+                lines.push(std::mem::take(&mut current));
+            }
+            let mut word = word.to_string();
+            while !fits(&word) && word.chars().count() > 1 {
+                // Keep the longest prefix that fits, at least one character.
+                let cut = word
+                    .char_indices()
+                    .skip(1)
+                    .map(|(i, _)| i)
+                    .take_while(|&i| fits(&word[..i]))
+                    .last()
+                    .unwrap_or_else(|| word.chars().next().map_or(0, char::len_utf8));
+                let rest = word.split_off(cut);
+                lines.push(std::mem::replace(&mut word, rest));
+            }
+            current = word;
+        }
+        if !current.is_empty() {
+            lines.push(current);
+        }
+        if lines.len() > max_lines {
+            lines.truncate(max_lines);
+            if let Some(last) = lines.pop() {
+                lines.push(self.ellipsize(&last, size, max_width));
+            }
+        }
+        lines
+    }
+
+    /// `line` with characters dropped from its end until it fits with an
+    /// ellipsis after it.
+    fn ellipsize(&self, line: &str, size: f32, max_width: f32) -> String {
+        let mut line = line.to_string();
+        loop {
+            let candidate = format!("{}…", line.trim_end());
+            if self.measure(&candidate, size) <= max_width || line.is_empty() {
+                return candidate;
+            }
+            line.pop();
+        }
+    }
+
+    /// Draws `text` in `color` with its baseline at `baseline`, starting at
+    /// `x`, blending it over `canvas`.
+    pub fn draw(
+        &self,
+        canvas: &mut RgbaImage,
+        text: &str,
+        (x, baseline): (f32, f32),
+        size: f32,
+        color: Color,
+    ) {
+        let scale = size / self.units_per_em;
+        let mut pen = x;
+        for c in text.chars() {
+            let glyph = self.glyph(c);
+            let edges: Vec<_> = self
+                .contours(glyph, 0)
+                .iter()
+                .flat_map(|contour| flatten(contour))
+                .map(|((x0, y0), (x1, y1))| {
+                    (
+                        (pen + x0 * scale, baseline - y0 * scale),
+                        (pen + x1 * scale, baseline - y1 * scale),
+                    )
+                })
+                .collect();
+            fill(canvas, &edges, color);
+            pen += f32::from(self.advance(glyph)) * scale;
+        }
+    }
+
+    /// The glyph for `c`, or 0 (the "missing" glyph).
+    fn glyph(&self, c: char) -> u16 {
+        let reader = Reader(&self.data);
+        let (table, format) = self.cmap;
+        let c = u32::from(c);
+        let glyph = match format {
+            4 => (|| {
+                let code = u16::try_from(c).ok()?;
+                let segments = usize::from(reader.u16(table + 6)? / 2);
+                let ends = table + 14;
+                let starts = ends + 2 * segments + 2;
+                let deltas = starts + 2 * segments;
+                let ranges = deltas + 2 * segments;
+                let segment = (0..segments).find(|&i| reader.u16(ends + 2 * i) >= Some(code))?;
+                let start = reader.u16(starts + 2 * segment)?;
+                if code < start {
+                    return None;
+                }
+                let delta = reader.u16(deltas + 2 * segment)?;
+                let range_at = ranges + 2 * segment;
+                let range = usize::from(reader.u16(range_at)?);
+                if range == 0 {
+                    return Some(code.wrapping_add(delta));
+                }
+                let at = range_at + range + 2 * usize::from(code - start);
+                let glyph = reader.u16(at)?;
+                (glyph != 0).then(|| glyph.wrapping_add(delta))
+            })(),
+            12 => (|| {
+                let groups = reader.u32(table + 12)? as usize;
+                (0..groups).find_map(|i| {
+                    let group = table + 16 + 12 * i;
+                    let (start, end) = (reader.u32(group)?, reader.u32(group + 4)?);
+                    (start..=end).contains(&c).then(|| {
+                        let first = reader.u32(group + 8)?;
+                        u16::try_from(first + (c - start)).ok()
+                    })?
+                })
+            })(),
+            _ => None,
+        };
+        glyph.filter(|&g| g < self.num_glyphs).unwrap_or(0)
+    }
+
+    fn advance(&self, glyph: u16) -> u16 {
+        let metric = glyph.min(self.num_h_metrics - 1);
+        Reader(&self.data)
+            .u16(self.hmtx + 4 * usize::from(metric))
+            .unwrap_or(0)
+    }
+
+    /// The outline of `glyph` as closed contours. Broken glyph data gives
+    /// an empty outline rather than an error, so one bad glyph does not
+    /// lose the whole text.
+    fn contours(&self, glyph: u16, depth: u8) -> Vec<Vec<Point>> {
+        self.read_contours(glyph, depth).unwrap_or_default()
+    }
+
+    fn read_contours(&self, glyph: u16, depth: u8) -> Option<Vec<Vec<Point>>> {
+        let reader = Reader(&self.data);
+        let index = usize::from(glyph);
+        let (start, end) = if self.long_offsets {
+            (
+                reader.u32(self.loca + 4 * index)? as usize,
+                reader.u32(self.loca + 4 * index + 4)? as usize,
+            )
+        } else {
+            (
+                2 * usize::from(reader.u16(self.loca + 2 * index)?),
+                2 * usize::from(reader.u16(self.loca + 2 * index + 2)?),
+            )
+        };
+        if end <= start {
+            return Some(Vec::new());
+        }
+        let at = self.glyf + start;
+        let count = reader.i16(at)?;
+        if count < 0 {
+            return self.composite(at + 10, depth);
+        }
+
+        let count = count as usize;
+        let ends: Vec<usize> = (0..count)
+            .map(|i| reader.u16(at + 10 + 2 * i).map(usize::from))
+            .collect::<Option<_>>()?;
+        let points = ends.last().map_or(0, |&last| last + 1);
+        let instructions = usize::from(reader.u16(at + 10 + 2 * count)?);
+        let mut offset = at + 12 + 2 * count + instructions;
+
+        let mut flags = Vec::with_capacity(points);
+        while flags.len() < points {
+            let flag = reader.u8(offset)?;
+            offset += 1;
+            let repeat = if flag & 8 != 0 {
+                offset += 1;
+                usize::from(reader.u8(offset - 1)?)
+            } else {
+                0
+            };
+            flags.extend(std::iter::repeat_n(flag, repeat + 1));
+        }
+        flags.truncate(points);
+
+        let mut read_axis = |short: u8, same_or_positive: u8| -> Option<Vec<f32>> {
+            let mut value = 0i32;
+            let mut values = Vec::with_capacity(points);
+            for &flag in &flags {
+                if flag & short != 0 {
+                    let delta = i32::from(reader.u8(offset)?);
+                    offset += 1;
+                    value += if flag & same_or_positive != 0 {
+                        delta
+                    } else {
+                        -delta
+                    };
+                } else if flag & same_or_positive == 0 {
+                    value += i32::from(reader.i16(offset)?);
+                    offset += 2;
+                }
+                values.push(value as f32);
+            }
+            Some(values)
+        };
+        let xs = read_axis(2, 16)?;
+        let ys = read_axis(4, 32)?;
+
+        let mut contours = Vec::with_capacity(count);
+        let mut first = 0;
+        for &last in &ends {
+            if last < first || last >= points {
+                return None;
+            }
+            contours.push(
+                (first..=last)
+                    .map(|i| Point {
+                        x: xs[i],
+                        y: ys[i],
+                        on_curve: flags[i] & 1 != 0,
+                    })
+                    .collect(),
+            );
+            first = last + 1;
+        }
+        Some(contours)
+    }
+
+    /// The components of a composite glyph, moved and scaled into place.
+    fn composite(&self, mut at: usize, depth: u8) -> Option<Vec<Vec<Point>>> {
+        if depth >= MAX_COMPONENT_DEPTH {
+            return Some(Vec::new());
+        }
+        let reader = Reader(&self.data);
+        let f2dot14 = |at: usize| reader.i16(at).map(|v| f32::from(v) / 16384.0);
+        let mut contours = Vec::new();
+        loop {
+            let flags = reader.u16(at)?;
+            let component = reader.u16(at + 2)?;
+            at += 4;
+            let (dx, dy) = if flags & 1 != 0 {
+                at += 4;
+                (reader.i16(at - 4)?, reader.i16(at - 2)?)
+            } else {
+                at += 2;
+                (
+                    i16::from(reader.u8(at - 2)? as i8),
+                    i16::from(reader.u8(at - 1)? as i8),
+                )
+            };
+            // Points matched by index instead of offsets are rare; placing
+            // the component unmoved is close enough.
+            let (dx, dy) = if flags & 2 != 0 {
+                (f32::from(dx), f32::from(dy))
+            } else {
+                (0.0, 0.0)
+            };
+            let (mut a, mut b, mut c, mut d) = (1.0, 0.0, 0.0, 1.0);
+            if flags & 8 != 0 {
+                a = f2dot14(at)?;
+                d = a;
+                at += 2;
+            } else if flags & 0x40 != 0 {
+                a = f2dot14(at)?;
+                d = f2dot14(at + 2)?;
+                at += 4;
+            } else if flags & 0x80 != 0 {
+                (a, b, c, d) = (
+                    f2dot14(at)?,
+                    f2dot14(at + 2)?,
+                    f2dot14(at + 4)?,
+                    f2dot14(at + 6)?,
+                );
+                at += 8;
+            }
+            for contour in self.contours(component, depth + 1) {
+                contours.push(
+                    contour
+                        .into_iter()
+                        .map(|p| Point {
+                            x: a * p.x + c * p.y + dx,
+                            y: b * p.x + d * p.y + dy,
+                            ..p
+                        })
+                        .collect(),
+                );
+            }
+            if flags & 0x20 == 0 {
+                return Some(contours);
+            }
+        }
+    }
+}
+
+/// Bounds-checked big-endian reads.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn u8(&self, at: usize) -> Option<u8> {
+        self.0.get(at).copied()
+    }
+
+    fn u16(&self, at: usize) -> Option<u16> {
+        Some(u16::from_be_bytes(self.0.get(at..at + 2)?.try_into().ok()?))
+    }
+
+    fn i16(&self, at: usize) -> Option<i16> {
+        self.u16(at).map(|v| v as i16)
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        Some(u32::from_be_bytes(self.0.get(at..at + 4)?.try_into().ok()?))
+    }
+}
+
+type Edge = ((f32, f32), (f32, f32));
+
+/// A closed contour as line segments. Consecutive off-curve points imply an
+/// on-curve point half way between them.
+fn flatten(contour: &[Point]) -> Vec<Edge> {
+    let n = contour.len();
+    if n < 2 {
+        return Vec::new();
+    }
+    let mid = |a: Point, b: Point| ((a.x + b.x) / 2.0, (a.y + b.y) / 2.0);
+    let start = match contour.iter().position(|p| p.on_curve) {
+        Some(i) => i,
+        None => {
+            // All control points: start between the first two.
+            let mut edges = Vec::new();
+            let mut from = mid(contour[0], contour[1]);
+            for i in 1..=n {
+                let control = contour[i % n];
+                let to = mid(control, contour[(i + 1) % n]);
+                quadratic(&mut edges, from, (control.x, control.y), to);
+                from = to;
+            }
+            return edges;
+        }
+    };
+
+    let mut edges = Vec::new();
+    let mut from = (contour[start].x, contour[start].y);
+    let mut control: Option<Point> = None;
+    for step in 1..=n {
+        let point = contour[(start + step) % n];
+        match (point.on_curve, control) {
+            (true, None) => {
+                edges.push((from, (point.x, point.y)));
+                from = (point.x, point.y);
+            }
+            (true, Some(c)) => {
+                quadratic(&mut edges, from, (c.x, c.y), (point.x, point.y));
+                from = (point.x, point.y);
+                control = None;
+            }
+            (false, None) => control = Some(point),
+            (false, Some(c)) => {
+                let to = mid(c, point);
+                quadratic(&mut edges, from, (c.x, c.y), to);
+                from = to;
+                control = Some(point);
+            }
+        }
+    }
+    edges
+}
+
+fn quadratic(edges: &mut Vec<Edge>, from: (f32, f32), control: (f32, f32), to: (f32, f32)) {
+    let mut previous = from;
+    for step in 1..=CURVE_STEPS {
+        let t = step as f32 / CURVE_STEPS as f32;
+        let u = 1.0 - t;
+        let point = (
+            u * u * from.0 + 2.0 * u * t * control.0 + t * t * to.0,
+            u * u * from.1 + 2.0 * u * t * control.1 + t * t * to.1,
+        );
+        edges.push((previous, point));
+        previous = point;
+    }
+}
+
+/// Fills the outline made of `edges` (in pixels) with `color`, using the
+/// non-zero winding rule.
+fn fill(canvas: &mut RgbaImage, edges: &[Edge], color: Color) {
+    let (width, height) = canvas.dimensions();
+    let bounds = edges.iter().fold(
+        (f32::MAX, f32::MIN, f32::MAX, f32::MIN),
+        |(x0, x1, y0, y1), &((ax, ay), (bx, by))| {
+            (
+                x0.min(ax).min(bx),
+                x1.max(ax).max(bx),
+                y0.min(ay).min(by),
+                y1.max(ay).max(by),
+            )
+        },
+    );
+    let (min_x, max_x, min_y, max_y) = bounds;
+    if edges.is_empty() || max_x < 0.0 || max_y < 0.0 {
+        return;
+    }
+    let left = min_x.floor().max(0.0) as u32;
+    let right = (max_x.ceil() as u32).min(width);
+    let top = min_y.floor().max(0.0) as u32;
+    let bottom = (max_y.ceil() as u32).min(height);
+    if left >= right || top >= bottom {
+        return;
+    }
+
+    let span = (right - left) as usize;
+    let mut coverage = vec![0f32; span];
+    let mut crossings: Vec<(f32, i32)> = Vec::new();
+    for y in top..bottom {
+        coverage.iter_mut().for_each(|c| *c = 0.0);
+        for sample in 0..SUBSAMPLES {
+            let sy = y as f32 + (sample as f32 + 0.5) / SUBSAMPLES as f32;
+            crossings.clear();
+            for &((x0, y0), (x1, y1)) in edges {
+                if (y0 <= sy) != (y1 <= sy) {
+                    let x = x0 + (sy - y0) * (x1 - x0) / (y1 - y0);
+                    crossings.push((x, if y1 > y0 { 1 } else { -1 }));
+                }
+            }
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let mut winding = 0;
+            let mut span_start = 0.0;
+            for &(x, direction) in &crossings {
+                let was_inside = winding != 0;
+                winding += direction;
+                match (was_inside, winding != 0) {
+                    (false, true) => span_start = x,
+                    (true, false) => {
+                        add_span(&mut coverage, span_start - left as f32, x - left as f32)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for (i, &c) in coverage.iter().enumerate() {
+            let alpha = (c / SUBSAMPLES as f32).min(1.0);
+            if alpha > 0.0 {
+                blend(canvas.get_pixel_mut(left + i as u32, y), color, alpha);
+            }
+        }
+    }
+}
+
+/// Adds the horizontal coverage of `[from, to)` to each pixel it touches.
+fn add_span(coverage: &mut [f32], from: f32, to: f32) {
+    let from = from.max(0.0);
+    let to = to.min(coverage.len() as f32);
+    if to <= from {
+        return;
+    }
+    let (first, last) = (
+        from.floor() as usize,
+        (to.ceil() as usize).min(coverage.len()),
+    );
+    for (i, cell) in coverage.iter_mut().enumerate().take(last).skip(first) {
+        let (start, end) = (i as f32, i as f32 + 1.0);
+        *cell += end.min(to) - start.max(from);
+    }
+}
+
+/// Blends `color` at `coverage` over `pixel`, source over destination.
+pub fn blend(pixel: &mut image::Rgba<u8>, color: Color, coverage: f32) {
+    let source = f32::from(color.0[3]) / 255.0 * coverage;
+    let destination = f32::from(pixel.0[3]) / 255.0;
+    let alpha = source + destination * (1.0 - source);
+    if alpha <= 0.0 {
+        return;
+    }
+    for c in 0..3 {
+        let over =
+            f32::from(color.0[c]) * source + f32::from(pixel.0[c]) * destination * (1.0 - source);
+        pixel.0[c] = (over / alpha).round() as u8;
+    }
+    pixel.0[3] = (alpha * 255.0).round() as u8;
+}
@@ -0,0 +1,78 @@
+// image-compressor-rust-service/src/timing.rs
+
+//! Per-stage timings of a request.
+//!
+//! Each stage runs inside a `stage` tracing span and its duration is kept in
+//! [`Timings`], which the server logs with the request and, when
+//! `server_timing` is enabled, reports in a `Server-Timing` header.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Durations of the stages a request went through, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timings {
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `f` as the stage `name` and records how long it took.
+    pub fn record<T>(&mut self, name: &'static str, f: impl FnOnce() -> T) -> T {
+        let span = tracing::info_span!("stage", stage = name);
+        let _entered = span.enter();
+        let start = Instant::now();
+        let result = f();
+        self.push(name, start.elapsed());
+        result
+    }
+
+    /// Records a stage measured elsewhere.
+    pub fn push(&mut self, name: &'static str, duration: Duration) {
+        self.stages.push((name, duration));
+    }
+
+    /// Appends the stages of `other`.
+    pub fn extend(&mut self, other: Timings) {
+        self.stages.extend(other.stages);
+    }
+
+    /// Total time spent in the stage `name`.
+    pub fn get(&self, name: &str) -> Option<Duration> {
+        self.stages
+            .iter()
+            .filter(|(stage, _)| *stage == name)
+            .map(|(_, duration)| *duration)
+            .reduce(|a, b| a + b)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, Duration)> + '_ {
+        self.stages.iter().copied()
+    }
+
+    /// The value of a `Server-Timing` header, e.g.
+    /// `read;dur=0.8, decode;dur=12.1, encode;dur=30.4`.
+    pub fn server_timing(&self) -> String {
+        self.stages
+            .iter()
+            .map(|(name, duration)| format!("{};dur={:.1}", name, duration.as_secs_f64() * 1e3))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// `read=0.8ms decode=12.1ms ...`, for logs.
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, duration)) in self.stages.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{}={:.1}ms", name, duration.as_secs_f64() * 1e3)?;
+        }
+        Ok(())
+    }
+}
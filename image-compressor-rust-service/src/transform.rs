@@ -0,0 +1,406 @@
+// image-compressor-rust-service/src/transform.rs
+
+use crate::options::{Color, CompressionOptions, Fit, Gravity, Rotation};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, Frame, Rgba, RgbaImage};
+
+/// Upper bound on output dimensions, enforced regardless of what the caller asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxDimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// How the output size compares to the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scaling {
+    None,
+    Down,
+    /// At least one side grew.
+    Up,
+}
+
+impl Scaling {
+    pub fn between(source: (u32, u32), output: (u32, u32)) -> Self {
+        if output == source {
+            Self::None
+        } else if output.0 > source.0 || output.1 > source.1 {
+            Self::Up
+        } else {
+            Self::Down
+        }
+    }
+
+    /// The value of the `X-Scaling` response header.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Down => "down",
+            Self::Up => "up",
+        }
+    }
+}
+
+/// The box the image is fitted into, before any cropping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TargetBox {
+    width: u32,
+    height: u32,
+    fit: Fit,
+}
+
+/// Whether the no-enlarge guard changed the output dimensions of a request.
+pub fn upscale_prevented(
+    source: (u32, u32),
+    options: &CompressionOptions,
+    max: MaxDimensions,
+) -> bool {
+    if options.allow_upscale {
+        return false;
+    }
+    let upscaled = CompressionOptions {
+        allow_upscale: true,
+        width: options.width,
+        height: options.height,
+        fit: options.fit,
+        aspect: options.aspect,
+        ..CompressionOptions::default()
+    };
+    output_dimensions(source, &upscaled, max) != output_dimensions(source, options, max)
+}
+
+/// Computes the final output dimensions for a source of `source` size.
+///
+/// * No requested size: the source size.
+/// * Only one side requested: the other side follows the source aspect ratio.
+/// * Both sides requested: `contain` fits inside the box, `cover`, `fill`
+///   and `pad` produce exactly the box.
+/// * An [`aspect`](CompressionOptions::aspect) with at most one side
+///   requested: the missing side follows the ratio and the image is cropped
+///   as with `cover`. Without either side, the largest region of the source
+///   with that ratio is cropped out.
+///
+/// Unless [`CompressionOptions::allow_upscale`] is set, the image is never
+/// enlarged: `contain` keeps a source that already fits the box, `fill`
+/// clamps each side to the source, `cover` crops the largest region with
+/// the box's aspect ratio out of the source instead of scaling it up, and
+/// `pad` surrounds the unscaled source with more padding.
+///
+/// The result never exceeds `max`; oversized results are scaled down,
+/// preserving their aspect ratio. Both sides are always at least 1.
+pub fn output_dimensions(
+    source: (u32, u32),
+    options: &CompressionOptions,
+    max: MaxDimensions,
+) -> (u32, u32) {
+    let target = target_box(source, options, max);
+    match target.fit {
+        Fit::Contain => contain(source, (target.width, target.height)),
+        Fit::Cover | Fit::Fill | Fit::Pad => (target.width, target.height),
+    }
+}
+
+/// Resizes a still image according to the options and the dimension cap.
+///
+/// Returns the input unchanged when no resizing is needed.
+pub fn resize(
+    image: DynamicImage,
+    options: &CompressionOptions,
+    max: MaxDimensions,
+) -> DynamicImage {
+    let source = (image.width(), image.height());
+    let target = target_box(source, options, max);
+    let (width, height) = output_dimensions(source, options, max);
+    if (width, height) == source {
+        return image;
+    }
+
+    match target.fit {
+        Fit::Contain | Fit::Fill => image.resize_exact(width, height, FilterType::Lanczos3),
+        // Scales to cover the box, then crops the centre.
+        Fit::Cover if options.gravity == Gravity::Center => {
+            image.resize_to_fill(width, height, FilterType::Lanczos3)
+        }
+        Fit::Cover => cover(image, (width, height), options.gravity),
+        Fit::Pad => pad(image, (width, height), options),
+    }
+}
+
+/// Fits `image` inside `size` like `contain` and places it on a canvas of
+/// exactly `size`, centred or towards the gravity's anchor. The canvas is
+/// RGBA and transparent unless a background is set.
+fn pad(image: DynamicImage, size: (u32, u32), options: &CompressionOptions) -> DynamicImage {
+    let source = (image.width(), image.height());
+    let fits = source.0 <= size.0 && source.1 <= size.1;
+    let inner = if fits && !options.allow_upscale {
+        source
+    } else {
+        contain(source, size)
+    };
+    let image = if inner == source {
+        image
+    } else {
+        image.resize_exact(inner.0, inner.1, FilterType::Lanczos3)
+    };
+
+    let background = options.background.unwrap_or(Color::TRANSPARENT);
+    let mut canvas = RgbaImage::from_pixel(size.0, size.1, Rgba(background.0));
+    let (ax, ay) = options.gravity.anchor();
+    let offset =
+        |size: u32, inner: u32, anchor: f64| (anchor * f64::from(size - inner)).round() as i64;
+    imageops::overlay(
+        &mut canvas,
+        &image.into_rgba8(),
+        offset(size.0, inner.0, ax),
+        offset(size.1, inner.1, ay),
+    );
+    DynamicImage::ImageRgba8(canvas)
+}
+
+/// Draws the border and rounds the corners, if the options ask for either.
+///
+/// Edges are antialiased, and the border follows the rounded outline.
+/// Rounded corners are transparent, so the result is RGBA.
+pub fn decorate(image: DynamicImage, options: &CompressionOptions) -> DynamicImage {
+    if options.border.is_none() && options.corner_radius.is_none() {
+        return image;
+    }
+    let (width, height) = (image.width(), image.height());
+    let radius = options
+        .corner_radius
+        .map_or(0.0, |r| r.pixels(width, height));
+    let (border_width, border_color) = options
+        .border
+        .map_or((0.0, Color::TRANSPARENT), |b| (f64::from(b.width), b.color));
+    let coverage = |distance: f64| (0.5 - distance).clamp(0.0, 1.0);
+
+    let (half_w, half_h) = (f64::from(width) / 2.0, f64::from(height) / 2.0);
+    let mut image = image.into_rgba8();
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        // Signed distance from the pixel centre to the rounded outline,
+        // negative inside.
+        let qx = (f64::from(x) + 0.5 - half_w).abs() - (half_w - radius);
+        let qy = (f64::from(y) + 0.5 - half_h).abs() - (half_h - radius);
+        let distance = qx.max(0.0).hypot(qy.max(0.0)) + qx.max(qy).min(0.0) - radius;
+        if distance + border_width <= -0.5 {
+            continue;
+        }
+
+        // The border colour over the pixel, then the outline's coverage.
+        let border =
+            (1.0 - coverage(distance + border_width)) * f64::from(border_color.0[3]) / 255.0;
+        let alpha = f64::from(pixel.0[3]) / 255.0;
+        let blended = border + alpha * (1.0 - border);
+        for (channel, &bc) in pixel.0[..3].iter_mut().zip(&border_color.0[..3]) {
+            let over = f64::from(bc) * border + f64::from(*channel) * alpha * (1.0 - border);
+            *channel = if blended > 0.0 {
+                (over / blended).round() as u8
+            } else {
+                *channel
+            };
+        }
+        pixel.0[3] = (blended * coverage(distance) * 255.0).round() as u8;
+    }
+    DynamicImage::ImageRgba8(image)
+}
+
+/// Whether [`resize`] or [`decorate`] can leave transparent pixels behind:
+/// padding without a background, or rounded corners.
+pub fn adds_transparency(options: &CompressionOptions) -> bool {
+    options.fit == Fit::Pad || options.corner_radius.is_some()
+}
+
+/// Composites an image with transparency onto an opaque `matte`, for
+/// formats without an alpha channel. Opaque images are returned unchanged.
+pub fn flatten(image: DynamicImage, matte: Color) -> DynamicImage {
+    if !image.color().has_alpha() {
+        return image;
+    }
+    let (width, height) = (image.width(), image.height());
+    let [r, g, b, _] = matte.0;
+    let mut canvas = RgbaImage::from_pixel(width, height, Rgba([r, g, b, 255]));
+    imageops::overlay(&mut canvas, &image.into_rgba8(), 0, 0);
+    DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(canvas).into_rgb8())
+}
+
+/// Scales `image` to cover `size` like [`DynamicImage::resize_to_fill`],
+/// then crops around the gravity's anchor instead of the centre.
+pub fn cover(image: DynamicImage, size: (u32, u32), gravity: Gravity) -> DynamicImage {
+    let scaled = cover_scale((image.width(), image.height()), size);
+    let image = image.resize_exact(scaled.0, scaled.1, FilterType::Lanczos3);
+    let (x, y) = cover_offset(scaled, size, gravity);
+    image.crop_imm(x, y, size.0, size.1)
+}
+
+/// The size [`cover`] scales a `source`-sized image to before cropping.
+fn cover_scale(source: (u32, u32), size: (u32, u32)) -> (u32, u32) {
+    let (sw, sh) = (f64::from(source.0), f64::from(source.1));
+    let ratio = (f64::from(size.0) / sw).max(f64::from(size.1) / sh);
+    (
+        ((sw * ratio).round() as u32).max(size.0),
+        ((sh * ratio).round() as u32).max(size.1),
+    )
+}
+
+/// Where [`cover`] crops `size` out of the `scaled` image.
+fn cover_offset(scaled: (u32, u32), size: (u32, u32), gravity: Gravity) -> (u32, u32) {
+    let (ax, ay) = gravity.anchor();
+    let offset = |scaled: u32, size: u32, anchor: f64| {
+        let centred = anchor * f64::from(scaled) - f64::from(size) / 2.0;
+        centred.round().clamp(0.0, f64::from(scaled - size)) as u32
+    };
+    (offset(scaled.0, size.0, ax), offset(scaled.1, size.1, ay))
+}
+
+/// The region [`resize`] cuts out of a `source`-sized image when it only
+/// crops it, as `(x, y, width, height)`: a `cover` box or aspect ratio
+/// that the source covers at its own size. `None` when the image is
+/// scaled, or left as it is.
+pub fn crop_region(
+    source: (u32, u32),
+    options: &CompressionOptions,
+    max: MaxDimensions,
+) -> Option<(u32, u32, u32, u32)> {
+    let size = output_dimensions(source, options, max);
+    let fit = target_box(source, options, max).fit;
+    if size == source || fit != Fit::Cover || cover_scale(source, size) != source {
+        return None;
+    }
+    let (x, y) = if options.gravity == Gravity::Center {
+        // As `resize_to_fill` centres it, rounding down.
+        ((source.0 - size.0) / 2, (source.1 - size.1) / 2)
+    } else {
+        cover_offset(source, size, options.gravity)
+    };
+    Some((x, y, size.0, size.1))
+}
+
+/// Rotates `image` clockwise as the options ask, if they do.
+pub fn rotate(image: DynamicImage, rotation: Option<Rotation>) -> DynamicImage {
+    match rotation {
+        None => image,
+        Some(Rotation::Quarter) => image.rotate90(),
+        Some(Rotation::Half) => image.rotate180(),
+        Some(Rotation::ThreeQuarters) => image.rotate270(),
+    }
+}
+
+/// The dimensions of a `source`-sized image once rotated as the options
+/// ask, which is what the requested size applies to.
+pub fn rotated_dimensions(source: (u32, u32), options: &CompressionOptions) -> (u32, u32) {
+    options
+        .rotate
+        .map_or(source, |rotation| rotation.dimensions(source))
+}
+
+/// Rotates, resizes, [filters](crate::filter) and [decorates](decorate)
+/// every frame of an animation like a still image, spreading the frames over up to
+/// `threads` threads.
+pub fn resize_frames(
+    frames: Vec<Frame>,
+    options: &CompressionOptions,
+    max: MaxDimensions,
+    threads: usize,
+) -> Vec<Frame> {
+    let resize_frame = |frame: Frame| {
+        let (left, top, delay) = (frame.left(), frame.top(), frame.delay());
+        let image = rotate(
+            DynamicImage::ImageRgba8(frame.into_buffer()),
+            options.rotate,
+        );
+        let mut image = resize(image, options, max);
+        if let Some(filter) = options.filter {
+            image = crate::filter::apply(image, filter);
+        }
+        let resized = decorate(image, options).into_rgba8();
+        Frame::from_parts(resized, left, top, delay)
+    };
+    if threads <= 1 || frames.len() <= 1 {
+        return frames.into_iter().map(resize_frame).collect();
+    }
+
+    // Contiguous chunks, joined in order, so the result matches the
+    // single-threaded one.
+    let chunk_len = frames.len().div_ceil(threads);
+    let mut frames = frames.into_iter();
+    let chunks: Vec<Vec<Frame>> = std::iter::from_fn(|| {
+        let chunk: Vec<Frame> = frames.by_ref().take(chunk_len).collect();
+        (!chunk.is_empty()).then_some(chunk)
+    })
+    .collect();
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| scope.spawn(|| chunk.into_iter().map(resize_frame).collect::<Vec<_>>()))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("frame resize panicked"))
+            .collect()
+    })
+}
+
+fn target_box(source: (u32, u32), options: &CompressionOptions, max: MaxDimensions) -> TargetBox {
+    let (sw, sh) = (source.0.max(1), source.1.max(1));
+    let (width, height, fit) = match (options.width, options.height, options.aspect) {
+        (Some(w), Some(h), _) => (w, h, options.fit),
+        (Some(w), None, Some(aspect)) => (w, scale(w, aspect.height, aspect.width), Fit::Cover),
+        (None, Some(h), Some(aspect)) => (scale(h, aspect.width, aspect.height), h, Fit::Cover),
+        (None, None, Some(aspect)) => {
+            let (w, h) = contain((aspect.width, aspect.height), (sw, sh));
+            (w, h, Fit::Cover)
+        }
+        (Some(w), None, None) => (w, scale(sh, w, sw), Fit::Fill),
+        (None, Some(h), None) => (scale(sw, h, sh), h, Fit::Fill),
+        (None, None, None) => (sw, sh, Fit::Fill),
+    };
+
+    let (width, height) = if options.allow_upscale {
+        (width, height)
+    } else {
+        match fit {
+            Fit::Contain if width >= sw && height >= sh => (sw, sh),
+            Fit::Contain => (width, height),
+            Fit::Cover if width > sw || height > sh => contain((width, height), (sw, sh)),
+            Fit::Cover | Fit::Pad => (width, height),
+            Fit::Fill => (width.min(sw), height.min(sh)),
+        }
+    };
+
+    // Cap the box, keeping its own aspect ratio.
+    let (width, height) = if width > max.width || height > max.height {
+        contain((width, height), (max.width, max.height))
+    } else {
+        (width, height)
+    };
+    TargetBox {
+        width: width.max(1),
+        height: height.max(1),
+        fit,
+    }
+}
+
+/// The largest size with the aspect ratio of `source` that fits in `bounds`.
+fn contain(source: (u32, u32), bounds: (u32, u32)) -> (u32, u32) {
+    let (sw, sh) = (u64::from(source.0.max(1)), u64::from(source.1.max(1)));
+    let (bw, bh) = (u64::from(bounds.0.max(1)), u64::from(bounds.1.max(1)));
+    // Compare bw/sw with bh/sh without floating point.
+    if bw * sh <= bh * sw {
+        (
+            bw as u32,
+            scale(sh as u32, bw as u32, sw as u32).min(bh as u32),
+        )
+    } else {
+        (
+            scale(sw as u32, bh as u32, sh as u32).min(bw as u32),
+            bh as u32,
+        )
+    }
+}
+
+/// Computes `value * numer / denom`, rounded to nearest and at least 1.
+fn scale(value: u32, numer: u32, denom: u32) -> u32 {
+    let denom = u64::from(denom.max(1));
+    let scaled = (u64::from(value) * u64::from(numer) + denom / 2) / denom;
+    scaled.clamp(1, u64::from(u32::MAX)) as u32
+}
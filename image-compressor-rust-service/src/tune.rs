@@ -0,0 +1,83 @@
+// image-compressor-rust-service/src/tune.rs
+
+//! Side-by-side outputs for tuning a preset's quality.
+//!
+//! [`tune`] compresses an image with a preset at its quality and at two
+//! steps either side of it, and scores each output by its SSIM against the
+//! input resized and filtered like the output, before encoding, so the
+//! trade between size and fidelity around the preset can be seen at a
+//! glance.
+
+use crate::config::Config;
+use crate::formats::OutputFormat;
+use crate::options::CompressionOptions;
+use crate::preset::Preset;
+use crate::{compress_image_with, decode_image, diff, filter, transform};
+use anyhow::Result;
+use image::imageops::FilterType;
+use std::borrow::Cow;
+
+/// Quality steps tried either side of the preset's quality.
+const STEPS: i16 = 2;
+
+/// One output of [`tune`].
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub quality: u8,
+    /// Whether this is the preset's own quality.
+    pub preset: bool,
+    pub data: Vec<u8>,
+    pub content_type: &'static str,
+    pub dimensions: (u32, u32),
+    /// Structural similarity with the input, resized and filtered like the
+    /// output.
+    pub ssim: f64,
+}
+
+/// Compresses `input` with `preset` at its quality (or the configured JPEG
+/// default) and at up to two `step`s above and below it, within 1 to 100.
+/// The candidates are ordered by quality.
+pub fn tune(input: &[u8], preset: &Preset, step: u8, config: &Config) -> Result<Vec<Candidate>> {
+    let mut options = CompressionOptions::default();
+    preset.apply(&mut options);
+    let base = if options.quality_explicit {
+        options.quality
+    } else {
+        config.quality.for_format(OutputFormat::Jpeg).default
+    };
+    let mut qualities: Vec<u8> = (-STEPS..=STEPS)
+        .map(|offset| (i16::from(base) + offset * i16::from(step.max(1))).clamp(1, 100) as u8)
+        .collect();
+    qualities.dedup();
+
+    // Only the quality differs between candidates, so they share the
+    // image they are compared with.
+    let resized = transform::resize(decode_image(input)?, &options, config.max_dimensions());
+    let reference = match options.filter {
+        Some(filter) => filter::apply(resized, filter),
+        None => resized,
+    };
+    qualities
+        .into_iter()
+        .map(|quality| {
+            let options = options.clone().with_quality(quality);
+            let compressed = compress_image_with(input, &options, config)?;
+            let output = decode_image(&compressed.data)?;
+            let reference = if (reference.width(), reference.height()) == compressed.dimensions {
+                Cow::Borrowed(&reference)
+            } else {
+                // Borders and the like change the size.
+                let (width, height) = compressed.dimensions;
+                Cow::Owned(reference.resize_exact(width, height, FilterType::Lanczos3))
+            };
+            Ok(Candidate {
+                quality,
+                preset: quality == base,
+                ssim: diff::ssim(&reference, &output)?,
+                content_type: compressed.content_type,
+                dimensions: compressed.dimensions,
+                data: compressed.data,
+            })
+        })
+        .collect()
+}
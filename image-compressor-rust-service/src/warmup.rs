@@ -0,0 +1,87 @@
+// image-compressor-rust-service/src/warmup.rs
+
+use crate::config::Config;
+use crate::formats::InputFormat;
+use crate::{compress_image_with, encode, CompressionOptions};
+use anyhow::{Context, Result};
+use image::{Delay, DynamicImage, Frame, ImageOutputFormat, Rgb, RgbImage};
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Side length of the built-in test image.
+const SAMPLE_SIZE: u32 = 64;
+
+/// A small gradient with enough detail to exercise every encoder stage.
+pub fn sample_image() -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(SAMPLE_SIZE, SAMPLE_SIZE, |x, y| {
+        Rgb([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8])
+    }))
+}
+
+/// Encodes [`sample_image`] in the given input format.
+pub fn sample_input(format: InputFormat) -> Result<Vec<u8>> {
+    let image = sample_image();
+    match format {
+        InputFormat::Jpeg | InputFormat::Png => {
+            let output = match format {
+                InputFormat::Jpeg => ImageOutputFormat::Jpeg(90),
+                _ => ImageOutputFormat::Png,
+            };
+            let mut buffer = Vec::new();
+            image
+                .write_to(&mut Cursor::new(&mut buffer), output)
+                .with_context(|| format!("Failed to encode the {} sample", format))?;
+            Ok(buffer)
+        }
+        InputFormat::Webp => {
+            let rgb = image.to_rgb8();
+            Ok(
+                webp::Encoder::from_rgb(rgb.as_raw(), rgb.width(), rgb.height())
+                    .encode(90.0)
+                    .to_vec(),
+            )
+        }
+    }
+}
+
+/// Runs one full decode and encode per allowed input format, and one
+/// animated WebP encode, so that codec tables and allocator pools are set up
+/// before real traffic arrives.
+///
+/// Returns the time each step took; they are also exported as the
+/// `warmup_duration_seconds` gauge, labelled by `step`.
+pub fn warm_up(config: &Config) -> Result<Vec<(&'static str, Duration)>> {
+    let mut timings = Vec::new();
+
+    for &format in &config.allowed_input_formats {
+        let input = sample_input(format)?;
+        let start = Instant::now();
+        compress_image_with(&input, &CompressionOptions::default(), config)
+            .with_context(|| format!("Warm-up compression of the {} sample failed", format))?;
+        timings.push((format.name(), start.elapsed()));
+    }
+
+    let start = Instant::now();
+    let frame = Frame::from_parts(
+        sample_image().to_rgba8(),
+        0,
+        0,
+        Delay::from_numer_denom_ms(100, 1),
+    );
+    encode::encode_animated_webp(
+        &[frame.clone(), frame],
+        CompressionOptions::default().quality,
+        1,
+        config.effort,
+        &config.encoders.webp,
+    )
+    .context("Warm-up animated WebP encode failed")?;
+    timings.push(("animated-webp", start.elapsed()));
+
+    for (step, duration) in &timings {
+        info!("Warm-up {} took {:.2?}", step, duration);
+        metrics::gauge!("warmup_duration_seconds", duration.as_secs_f64(), "step" => *step);
+    }
+    Ok(timings)
+}
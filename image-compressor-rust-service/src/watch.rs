@@ -0,0 +1,412 @@
+// image-compressor-rust-service/src/watch.rs
+
+//! Watch mode: compress every image dropped into a folder.
+//!
+//! `image-compressor-rust-service watch <in> --out <out> [--preset web]`
+//! polls `<in>` recursively. A file is picked up once its size and
+//! modification time have stayed the same for the settle time, so files
+//! still being copied are left alone. A file that fails to decode is
+//! retried with backoff, in case it was still incomplete. Outputs keep the
+//! relative path of their input, with the extension of the output format,
+//! unless `--template` lays them out by capture date or camera (see
+//! [`filename::render_path`]), and are written through a temporary file so readers of `<out>` never see
+//! half-written images. Originals can be kept, deleted or moved away.
+//! With a preset that asks for OCR, the text of each input is written to a
+//! `.txt` file next to its output, before the output itself.
+
+use crate::config::Config;
+use crate::filename::{self, PathVars};
+use crate::formats::InputFormat;
+use crate::metadata::Exif;
+use crate::{compress_image_with, CompressionOptions};
+use anyhow::{bail, Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{error, info, warn};
+
+/// The first argument selecting watch mode.
+pub const COMMAND: &str = "watch";
+
+/// What happens to an original once it has been compressed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Originals {
+    /// Leave it in place; it is not compressed again unless it changes.
+    Keep,
+    Delete,
+    /// Move it into this directory, keeping its relative path.
+    Archive(PathBuf),
+}
+
+/// Settings of a watch run, from the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchOptions {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub preset: Option<String>,
+    /// Output path template, relative to `output`, e.g.
+    /// `{yyyy}/{mm}/{name}.{ext}`.
+    pub template: Option<String>,
+    pub originals: Originals,
+    /// Time between scans of the input directory.
+    pub interval: Duration,
+    /// How long a file must stay unchanged before it is compressed.
+    pub settle: Duration,
+    /// Attempts after the first before a file is given up on.
+    pub retries: u32,
+}
+
+impl WatchOptions {
+    /// Usage, printed for bad arguments.
+    pub const USAGE: &'static str =
+        "usage: image-compressor-rust-service watch <input-dir> --out <output-dir> \
+         [--preset <name>] [--template <path>] [--delete | --archive <dir>] [--interval-ms <n>] \
+         [--settle-ms <n>] [--retries <n>]";
+
+    /// Parses the arguments following `watch`.
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let mut input = None;
+        let mut output = None;
+        let mut preset = None;
+        let mut template = None;
+        let mut originals = Originals::Keep;
+        let mut interval = Duration::from_secs(1);
+        let mut settle = Duration::from_secs(2);
+        let mut retries = 3;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("{} needs a value", arg))
+            };
+            let millis = |value: &String| -> Result<Duration> {
+                Ok(Duration::from_millis(
+                    value.parse().with_context(|| format!("Invalid {}", arg))?,
+                ))
+            };
+            match arg.as_str() {
+                "--out" => output = Some(PathBuf::from(value()?)),
+                "--preset" => preset = Some(value()?.clone()),
+                "--template" => template = Some(value()?.clone()),
+                "--delete" => originals = Originals::Delete,
+                "--archive" => originals = Originals::Archive(PathBuf::from(value()?)),
+                "--interval-ms" => interval = millis(value()?)?,
+                "--settle-ms" => settle = millis(value()?)?,
+                "--retries" => retries = value()?.parse().context("Invalid --retries")?,
+                flag if flag.starts_with("--") => bail!("Unknown option {}", flag),
+                path if input.is_none() => input = Some(PathBuf::from(path)),
+                extra => bail!("Unexpected argument {}", extra),
+            }
+        }
+
+        if let Some(template) = &template {
+            let sample = PathVars {
+                name: "image",
+                width: 1,
+                height: 1,
+                format: crate::formats::OutputFormat::Jpeg,
+                date: None,
+                camera: None,
+            };
+            if filename::render_path(template, &sample).is_none() {
+                bail!("--template must end with a filename");
+            }
+        }
+
+        Ok(Self {
+            input: input.context("Missing the input directory")?,
+            output: output.context("Missing --out")?,
+            preset,
+            template,
+            originals,
+            interval,
+            settle,
+            retries,
+        })
+    }
+}
+
+/// What a scan did with one file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Compressed into `output`.
+    Compressed { input: PathBuf, output: PathBuf },
+    /// Failed and will be tried again.
+    Retrying { input: PathBuf, attempt: u32 },
+    /// Failed for good, or not an image this service accepts. It is not
+    /// tried again unless it changes.
+    Failed { input: PathBuf },
+}
+
+/// The state of a file seen but not finished with.
+#[derive(Debug)]
+struct Pending {
+    stamp: Stamp,
+    /// When `stamp` was first seen.
+    since: Instant,
+    failures: u32,
+    next_attempt: Instant,
+}
+
+/// Size and modification time, to tell whether a file changed.
+type Stamp = (u64, Option<SystemTime>);
+
+/// Compresses the files of a watched directory.
+pub struct Watcher {
+    options: WatchOptions,
+    config: Config,
+    compression: CompressionOptions,
+    pending: HashMap<PathBuf, Pending>,
+    /// Files already handled, and their stamp at the time.
+    done: HashMap<PathBuf, Stamp>,
+}
+
+impl Watcher {
+    pub fn new(options: WatchOptions, config: Config) -> Result<Self> {
+        let compression = match &options.preset {
+            Some(name) => config
+                .preset(name)
+                .with_context(|| format!("Unknown preset '{}'", name))?
+                .options(),
+            None => CompressionOptions::default(),
+        };
+        let input = fs::canonicalize(&options.input)
+            .with_context(|| format!("Cannot watch {}", options.input.display()))?;
+        fs::create_dir_all(&options.output)
+            .with_context(|| format!("Cannot create {}", options.output.display()))?;
+        let mut outputs = vec![&options.output];
+        if let Originals::Archive(archive) = &options.originals {
+            fs::create_dir_all(archive)
+                .with_context(|| format!("Cannot create {}", archive.display()))?;
+            outputs.push(archive);
+        }
+        for dir in outputs {
+            if fs::canonicalize(dir)?.starts_with(&input) {
+                bail!(
+                    "{} is inside the watched directory {}",
+                    dir.display(),
+                    options.input.display()
+                );
+            }
+        }
+
+        Ok(Self {
+            options,
+            config,
+            compression,
+            pending: HashMap::new(),
+            done: HashMap::new(),
+        })
+    }
+
+    /// Scans forever.
+    pub fn run(mut self) -> ! {
+        info!(
+            "Watching {} for images, writing to {}",
+            self.options.input.display(),
+            self.options.output.display()
+        );
+        loop {
+            self.scan(Instant::now());
+            std::thread::sleep(self.options.interval);
+        }
+    }
+
+    /// Scans the input directory once and compresses every file that has
+    /// settled, as of `now`.
+    pub fn scan(&mut self, now: Instant) -> Vec<Event> {
+        let mut seen = Vec::new();
+        collect_files(&self.options.input, &mut seen);
+        let present: HashSet<&PathBuf> = seen.iter().collect();
+        self.pending.retain(|path, _| present.contains(path));
+        self.done.retain(|path, _| present.contains(path));
+
+        let mut events = Vec::new();
+        for path in seen {
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            let stamp = (metadata.len(), metadata.modified().ok());
+            if self.done.get(&path) == Some(&stamp) {
+                continue;
+            }
+            self.done.remove(&path);
+
+            let pending = self.pending.entry(path.clone()).or_insert(Pending {
+                stamp,
+                since: now,
+                failures: 0,
+                next_attempt: now,
+            });
+            if pending.stamp != stamp {
+                // Still being written.
+                *pending = Pending {
+                    stamp,
+                    since: now,
+                    failures: 0,
+                    next_attempt: now,
+                };
+            }
+            if now.duration_since(pending.since) < self.options.settle || now < pending.next_attempt
+            {
+                continue;
+            }
+
+            match self.process(&path) {
+                Ok(output) => {
+                    self.pending.remove(&path);
+                    if self.options.originals == Originals::Keep {
+                        self.done.insert(path.clone(), stamp);
+                    }
+                    events.push(Event::Compressed {
+                        input: path,
+                        output,
+                    });
+                }
+                Err(Failure::Unsupported) => {
+                    self.pending.remove(&path);
+                    self.done.insert(path.clone(), stamp);
+                    events.push(Event::Failed { input: path });
+                }
+                Err(Failure::Error(e)) => {
+                    let pending = self.pending.get_mut(&path).expect("pending entry");
+                    pending.failures += 1;
+                    if pending.failures > self.options.retries {
+                        error!(
+                            "Giving up on {} after {} attempts: {:#}",
+                            path.display(),
+                            pending.failures,
+                            e
+                        );
+                        self.pending.remove(&path);
+                        self.done.insert(path.clone(), stamp);
+                        events.push(Event::Failed { input: path });
+                    } else {
+                        let backoff = self.options.settle.max(Duration::from_millis(100))
+                            * 2u32.pow(pending.failures - 1);
+                        warn!(
+                            "Failed to compress {}, retrying in {:?}: {:#}",
+                            path.display(),
+                            backoff,
+                            e
+                        );
+                        pending.next_attempt = now + backoff;
+                        events.push(Event::Retrying {
+                            input: path,
+                            attempt: pending.failures,
+                        });
+                    }
+                }
+            }
+        }
+        events
+    }
+
+    fn process(&self, path: &Path) -> Result<PathBuf, Failure> {
+        let input = fs::read(path).map_err(|e| Failure::Error(e.into()))?;
+        match InputFormat::sniff(&input) {
+            Some(format) if self.config.allowed_input_formats.contains(&format) => {}
+            _ => {
+                info!("Skipping {}: not an accepted image format", path.display());
+                return Err(Failure::Unsupported);
+            }
+        }
+        let compressed =
+            compress_image_with(&input, &self.compression, &self.config).map_err(Failure::Error)?;
+
+        let relative = path
+            .strip_prefix(&self.options.input)
+            .expect("scanned paths are inside the input directory");
+        let output = match &self.options.template {
+            Some(template) => {
+                let exif = Exif::from_image(&input);
+                let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                let vars = PathVars {
+                    name: &name,
+                    width: compressed.dimensions.0,
+                    height: compressed.dimensions.1,
+                    format: compressed.format,
+                    date: exif.as_ref().and_then(Exif::capture_date),
+                    camera: exif.as_ref().and_then(Exif::camera_model),
+                };
+                let rendered = filename::render_path(template, &vars)
+                    .with_context(|| format!("Template '{}' renders no filename", template))
+                    .map_err(Failure::Error)?;
+                self.options.output.join(rendered)
+            }
+            None => self
+                .options
+                .output
+                .join(relative)
+                .with_extension(compressed.format.extension()),
+        };
+        if let Some(text) = &compressed.text {
+            write_atomically(&output.with_extension("txt"), text.as_bytes())
+                .map_err(Failure::Error)?;
+        }
+        write_atomically(&output, &compressed.data).map_err(Failure::Error)?;
+        info!(
+            "Compressed {} to {} ({} -> {} bytes)",
+            path.display(),
+            output.display(),
+            input.len(),
+            compressed.data.len()
+        );
+
+        match &self.options.originals {
+            Originals::Keep => {}
+            Originals::Delete => {
+                fs::remove_file(path)
+                    .with_context(|| format!("Failed to delete {}", path.display()))
+                    .map_err(Failure::Error)?;
+            }
+            Originals::Archive(archive) => {
+                let target = archive.join(relative);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).map_err(|e| Failure::Error(e.into()))?;
+                }
+                fs::rename(path, &target)
+                    .with_context(|| format!("Failed to archive {}", path.display()))
+                    .map_err(Failure::Error)?;
+            }
+        }
+        Ok(output)
+    }
+}
+
+enum Failure {
+    /// Not an image this service accepts.
+    Unsupported,
+    Error(anyhow::Error),
+}
+
+/// Regular files under `dir`, skipping hidden files and directories, which
+/// is where copy tools usually keep partial uploads.
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() => collect_files(&entry.path(), files),
+            Ok(kind) if kind.is_file() => files.push(entry.path()),
+            _ => {}
+        }
+    }
+}
+
+/// Writes `data` to a hidden temporary file next to `path`, then renames it.
+fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let dir = path.parent().context("Output path has no parent")?;
+    fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    let name = path.file_name().context("Output path has no file name")?;
+    let temporary = dir.join(format!(".{}.tmp", name.to_string_lossy()));
+    fs::write(&temporary, data)
+        .with_context(|| format!("Failed to write {}", temporary.display()))?;
+    fs::rename(&temporary, path).with_context(|| format!("Failed to write {}", path.display()))
+}
@@ -0,0 +1,294 @@
+// image-compressor-rust-service/src/watermark.rs
+
+//! Invisible watermarks: a 32-bit payload hidden in an image's luma, so a
+//! leaked copy can be traced back to whoever it was sent to.
+//!
+//! Every 8x8 block carries one bit, repeated across the image, as the sign
+//! of the difference between two mid-frequency DCT coefficients that JPEG
+//! quantizes about equally. Which bit a block carries, and with which
+//! polarity, is drawn from the configured secret, so marks can neither be
+//! read nor forged without it. A 16-bit checksum travels with the payload
+//! so that unmarked images are told apart from marked ones.
+//!
+//! Marks survive JPEG re-encoding at ordinary qualities and mild colour
+//! edits. They do not survive resizing, rotation or crops that move the
+//! block grid, nor palette quantization.
+
+use anyhow::{bail, Result};
+use image::{DynamicImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Bits of caller payload in a mark.
+pub const PAYLOAD_BITS: usize = 32;
+
+/// Checksum bits embedded after the payload.
+const CHECK_BITS: usize = 16;
+
+const BITS: usize = PAYLOAD_BITS + CHECK_BITS;
+
+const BLOCK: usize = 8;
+
+/// The two coefficients compared, as (vertical, horizontal) frequencies.
+/// JPEG's standard luma table quantizes them with steps 14 and 13.
+const PAIR: [(usize, usize); 2] = [(1, 2), (2, 1)];
+
+/// Settings for embedding and detecting watermarks.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatermarkConfig {
+    /// Key that places and reads marks. Watermarking is unavailable
+    /// without one, and marks written under one secret are invisible
+    /// under another.
+    pub secret: Option<String>,
+    /// The gap forced between the two coefficients, in DCT units. Higher
+    /// survives harsher re-encoding but starts to show on flat areas.
+    pub strength: f32,
+}
+
+impl Default for WatermarkConfig {
+    fn default() -> Self {
+        Self {
+            secret: None,
+            strength: 20.0,
+        }
+    }
+}
+
+/// What [`WatermarkConfig::detect`] found.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Detection {
+    /// The embedded payload, if its checksum matched.
+    pub payload: Option<u32>,
+    /// The share of blocks agreeing with the bits read: about 0.5 for
+    /// unmarked images, close to 1 for intact marks.
+    pub confidence: f32,
+}
+
+impl WatermarkConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.secret.as_deref().is_some_and(|s| s.is_empty()) {
+            bail!("secret must not be empty");
+        }
+        if !(self.strength > 0.0 && self.strength <= 100.0) {
+            bail!("strength must be above 0 and at most 100");
+        }
+        Ok(())
+    }
+
+    /// Hides `payload` in `image`. Alpha is kept as it is.
+    pub fn embed(&self, image: DynamicImage, payload: u32) -> Result<DynamicImage> {
+        let secret = self.secret()?;
+        let has_alpha = image.color().has_alpha();
+        let mut rgba = image.into_rgba8();
+        let blocks = Blocks::new(&rgba, secret)?;
+        let bits = encode(payload);
+        let basis = basis();
+        for (index, (x, y)) in blocks.origins().enumerate() {
+            let (bit, polarity) = blocks.assignment[index];
+            let sign = if bits[bit] { polarity } else { -polarity };
+            let luma = block_luma(&rgba, x, y);
+            let difference =
+                coefficient(&luma, &basis, PAIR[0]) - coefficient(&luma, &basis, PAIR[1]);
+            let shortfall = self.strength - sign * difference;
+            if shortfall <= 0.0 {
+                continue;
+            }
+            // Move both coefficients half the way, in opposite directions,
+            // and add the change to every channel so chroma is untouched.
+            let step = sign * shortfall / 2.0;
+            for dy in 0..BLOCK {
+                for dx in 0..BLOCK {
+                    let delta = step
+                        * (basis[PAIR[0].0][dy] * basis[PAIR[0].1][dx]
+                            - basis[PAIR[1].0][dy] * basis[PAIR[1].1][dx]);
+                    let pixel = rgba.get_pixel_mut((x + dx) as u32, (y + dy) as u32);
+                    for channel in &mut pixel.0[..3] {
+                        *channel = (f32::from(*channel) + delta).round().clamp(0.0, 255.0) as u8;
+                    }
+                }
+            }
+        }
+        Ok(if has_alpha {
+            DynamicImage::ImageRgba8(rgba)
+        } else {
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).into_rgb8())
+        })
+    }
+
+    /// Reads the mark in `image`, if there is one under this secret.
+    /// Images too small to hold a mark have none.
+    pub fn detect(&self, image: &DynamicImage) -> Result<Detection> {
+        let secret = self.secret()?;
+        let rgba = match image {
+            DynamicImage::ImageRgba8(rgba) => rgba.clone(),
+            other => other.to_rgba8(),
+        };
+        let Ok(blocks) = Blocks::new(&rgba, secret) else {
+            return Ok(Detection {
+                payload: None,
+                confidence: 0.0,
+            });
+        };
+        let basis = basis();
+        let mut votes = [0f32; BITS];
+        let mut signs = Vec::with_capacity(blocks.assignment.len());
+        for (index, (x, y)) in blocks.origins().enumerate() {
+            let (bit, polarity) = blocks.assignment[index];
+            let luma = block_luma(&rgba, x, y);
+            let vote = polarity
+                * (coefficient(&luma, &basis, PAIR[0]) - coefficient(&luma, &basis, PAIR[1]));
+            votes[bit] += vote;
+            signs.push((bit, vote > 0.0));
+        }
+        let bits = votes.map(|v| v > 0.0);
+        let agreeing = signs.iter().filter(|&&(bit, one)| bits[bit] == one).count();
+        Ok(Detection {
+            payload: decode(&bits),
+            confidence: agreeing as f32 / signs.len() as f32,
+        })
+    }
+
+    fn secret(&self) -> Result<&str> {
+        match self.secret.as_deref() {
+            Some(secret) => Ok(secret),
+            None => bail!("Watermarking needs a [watermark] secret."),
+        }
+    }
+}
+
+/// The payload followed by its checksum, most significant bit first.
+fn encode(payload: u32) -> [bool; BITS] {
+    let check = checksum(payload);
+    let mut bits = [false; BITS];
+    for (i, bit) in bits.iter_mut().enumerate() {
+        *bit = if i < PAYLOAD_BITS {
+            payload >> (PAYLOAD_BITS - 1 - i) & 1 == 1
+        } else {
+            check >> (BITS - 1 - i) & 1 == 1
+        };
+    }
+    bits
+}
+
+fn decode(bits: &[bool; BITS]) -> Option<u32> {
+    let value = |range: std::ops::Range<usize>| {
+        bits[range]
+            .iter()
+            .fold(0u32, |acc, &bit| (acc << 1) | u32::from(bit))
+    };
+    let payload = value(0..PAYLOAD_BITS);
+    (value(PAYLOAD_BITS..BITS) == u32::from(checksum(payload))).then_some(payload)
+}
+
+fn checksum(payload: u32) -> u16 {
+    crc32fast::hash(&payload.to_be_bytes()) as u16
+}
+
+/// The whole 8x8 blocks of an image, and the bit and polarity the secret
+/// gives each of them.
+struct Blocks {
+    columns: usize,
+    assignment: Vec<(usize, f32)>,
+}
+
+impl Blocks {
+    fn new(image: &RgbaImage, secret: &str) -> Result<Self> {
+        let columns = image.width() as usize / BLOCK;
+        let count = columns * (image.height() as usize / BLOCK);
+        if count < BITS {
+            bail!(
+                "Images need at least {} 8x8 blocks to hold a watermark.",
+                BITS
+            );
+        }
+        // Every bit gets an equal share of blocks, shuffled by the secret.
+        let mut random = SplitMix64::new(secret, image.width(), image.height());
+        let mut bits: Vec<usize> = (0..count).map(|i| i % BITS).collect();
+        for i in (1..count).rev() {
+            bits.swap(i, (random.next() % (i as u64 + 1)) as usize);
+        }
+        let assignment = bits
+            .into_iter()
+            .map(|bit| (bit, if random.next() & 1 == 1 { 1.0 } else { -1.0 }))
+            .collect();
+        Ok(Self {
+            columns,
+            assignment,
+        })
+    }
+
+    /// Top-left corners of the blocks, row by row.
+    fn origins(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..self.assignment.len()).map(|i| ((i % self.columns) * BLOCK, (i / self.columns) * BLOCK))
+    }
+}
+
+/// A small, fast generator; the secret only has to be unguessable, not the
+/// sequence unpredictable from its outputs.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn new(secret: &str, width: u32, height: u32) -> Self {
+        let mut hash = Sha256::new();
+        hash.update(secret.as_bytes());
+        hash.update(width.to_be_bytes());
+        hash.update(height.to_be_bytes());
+        let digest = hash.finalize();
+        Self(u64::from_be_bytes(digest[..8].try_into().expect("8 bytes")))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// `basis[frequency][position]` of the orthonormal 8-point DCT-II.
+fn basis() -> [[f32; BLOCK]; BLOCK] {
+    let mut basis = [[0f32; BLOCK]; BLOCK];
+    for (frequency, row) in basis.iter_mut().enumerate() {
+        let scale = if frequency == 0 {
+            (1.0 / BLOCK as f32).sqrt()
+        } else {
+            (2.0 / BLOCK as f32).sqrt()
+        };
+        for (position, value) in row.iter_mut().enumerate() {
+            *value = scale
+                * ((2 * position + 1) as f32 * frequency as f32 * std::f32::consts::PI
+                    / (2 * BLOCK) as f32)
+                    .cos();
+        }
+    }
+    basis
+}
+
+/// Rec. 601 luma of the block at (`x`, `y`), as JPEG computes it.
+fn block_luma(image: &RgbaImage, x: usize, y: usize) -> [[f32; BLOCK]; BLOCK] {
+    let mut luma = [[0f32; BLOCK]; BLOCK];
+    for (dy, row) in luma.iter_mut().enumerate() {
+        for (dx, value) in row.iter_mut().enumerate() {
+            let [r, g, b, _] = image.get_pixel((x + dx) as u32, (y + dy) as u32).0;
+            *value = 0.299 * f32::from(r) + 0.587 * f32::from(g) + 0.114 * f32::from(b);
+        }
+    }
+    luma
+}
+
+/// One DCT coefficient of a block.
+fn coefficient(
+    block: &[[f32; BLOCK]; BLOCK],
+    basis: &[[f32; BLOCK]; BLOCK],
+    (v, u): (usize, usize),
+) -> f32 {
+    let mut sum = 0.0;
+    for (y, row) in block.iter().enumerate() {
+        for (x, &value) in row.iter().enumerate() {
+            sum += value * basis[v][y] * basis[u][x];
+        }
+    }
+    sum
+}
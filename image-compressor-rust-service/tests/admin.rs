@@ -0,0 +1,95 @@
+// image-compressor-rust-service/tests/admin.rs
+
+//! Dry-run validation of candidate configs on `/admin/validate-config`.
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use common::{app, json_body, send};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::server;
+
+fn validate(query: &str, token: Option<&str>, body: &str) -> Request<Body> {
+    let mut request = Request::post(format!("/admin/validate-config{}", query));
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    request.body(Body::from(body.to_owned())).unwrap()
+}
+
+#[tokio::test]
+async fn candidates_are_checked_without_being_applied() {
+    let disabled = send(&app(Config::default()), validate("", None, "")).await;
+    assert_eq!(disabled.status(), StatusCode::NOT_FOUND);
+
+    let mut config = Config::default();
+    config.admin.token = Some("s3cret".to_owned());
+    let app = app(config);
+    let wrong = send(&app, validate("", Some("guess"), "")).await;
+    assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+
+    let candidate = "max_body_bytes = 1048576\n[body_limits.routes]\n\"/compres\" = 1";
+    let response = send(&app, validate("", Some("s3cret"), candidate)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let checked = json_body(response).await;
+    assert_eq!(checked["valid"], true);
+    assert_eq!(
+        checked["warnings"][0],
+        "body_limits: no route serves '/compres'"
+    );
+
+    let unknown_format = "allowed_input_formats = [\"jpeg\", \"bmp\"]";
+    let response = send(&app, validate("", Some("s3cret"), unknown_format)).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let checked = json_body(response).await;
+    assert_eq!(checked["valid"], false);
+    assert!(
+        checked["errors"][0].as_str().unwrap().contains("bmp"),
+        "{}",
+        checked
+    );
+
+    // Presets are checked against the running config.
+    let presets = "[hero]\nwidth = 10000\n[scan]\nocr = true";
+    let response = send(&app, validate("?kind=presets", Some("s3cret"), presets)).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let checked = json_body(response).await;
+    assert!(
+        checked["errors"][0]
+            .as_str()
+            .unwrap()
+            .contains("ocr needs [ocr] to be enabled"),
+        "{}",
+        checked
+    );
+    assert_eq!(
+        checked["warnings"][0],
+        "preset 'hero': width exceeds the maximum of 8192 pixels"
+    );
+}
+
+#[test]
+fn conflicting_limits_are_warned_about() {
+    let config: Config = toml::from_str(
+        "[cpu]\nbudget = 2\nmax_threads_per_image = 4\n\
+         [concurrency.routes.\"/contact-sheet\"]\nmax_in_flight = 3\n\
+         [concurrency.routes.\"/icons\"]\nmax_in_flight = 1\n\
+         [quality.jpeg]\nmax = 85\n\
+         [presets.thumbnail]\nquality = 90",
+    )
+    .unwrap();
+    config.validate().unwrap();
+    assert_eq!(
+        config.warnings(),
+        [
+            "concurrency: max_in_flight of '/contact-sheet' is above the CPU budget of 2 threads",
+            "concurrency: there is no route '/icons'",
+            "cpu: max_threads_per_image is above the budget of 2 threads",
+            "preset 'thumbnail' replaces the built-in one",
+            "preset 'thumbnail': quality 90 is clamped to 1-85 for jpeg",
+        ]
+    );
+    assert!(server::is_route("/icons/pwa"));
+    assert!(!server::is_route("/icons/pwa/extra"));
+}
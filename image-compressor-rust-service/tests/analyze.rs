@@ -0,0 +1,56 @@
+// image-compressor-rust-service/tests/analyze.rs
+
+//! Histograms and exposure statistics from `/analyze/histogram`.
+
+mod common;
+
+use axum::http::StatusCode;
+use common::{app, fixture, json_body, post, send};
+use image::{DynamicImage, Rgba, RgbaImage};
+use image_compressor_rust_service::analyze::exposure;
+use image_compressor_rust_service::config::Config;
+use serde_json::Value;
+
+async fn analyze(body: Vec<u8>) -> (StatusCode, Value) {
+    let request = post("/analyze/histogram", &[], body);
+    let response = send(&app(Config::default()), request).await;
+    (response.status(), json_body(response).await)
+}
+
+#[test]
+fn exposure_counts_clipped_and_transparent_pixels() {
+    // Black and white columns, and a transparent bottom row.
+    let image = RgbaImage::from_fn(4, 3, |x, y| match (x, y) {
+        (_, 2) => Rgba([255, 255, 255, 0]),
+        (0..=1, _) => Rgba([0, 0, 0, 255]),
+        _ => Rgba([255, 255, 255, 255]),
+    });
+    let stats = exposure(&DynamicImage::ImageRgba8(image));
+    assert_eq!(stats.pixels, 8);
+    assert_eq!(stats.histogram.luminance[0], 4);
+    assert_eq!(stats.histogram.luminance[255], 4);
+    assert_eq!(stats.histogram.red.iter().sum::<u64>(), 8);
+    assert!((stats.mean_luminance - 0.5).abs() < 1e-9);
+    assert_eq!(stats.clipping.shadows_percent, 50.0);
+    assert_eq!(stats.clipping.highlights_percent, 50.0);
+}
+
+#[tokio::test]
+async fn histograms_are_returned_as_json() {
+    let (status, body) = analyze(fixture("landscape.jpg")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["pixels"], 160 * 96);
+    for channel in ["red", "green", "blue", "luminance"] {
+        let bins = body["histogram"][channel].as_array().unwrap();
+        assert_eq!(bins.len(), 256);
+        let total: u64 = bins.iter().map(|b| b.as_u64().unwrap()).sum();
+        assert_eq!(total, 160 * 96, "{}", channel);
+    }
+    let mean = body["mean_luminance"].as_f64().unwrap();
+    assert!((0.0..=1.0).contains(&mean));
+
+    let (status, _) = analyze(Vec::new()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = analyze(b"plain text".to_vec()).await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
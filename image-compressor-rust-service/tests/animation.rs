@@ -0,0 +1,174 @@
+// image-compressor-rust-service/tests/animation.rs
+
+//! Keeping fewer frames of animated inputs, and poster frames.
+
+mod common;
+
+use common::fixture;
+use image::{Delay, Frame, Rgba, RgbaImage};
+use image_compressor_rust_service::animation::{self, AnimationConfig, FrameLimit, FrameSelection};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::transform::MaxDimensions;
+use image_compressor_rust_service::{
+    compress_image_with, decode_frames, AnimationMode, CompressionOptions,
+};
+
+const UNCAPPED: AnimationConfig = AnimationConfig {
+    max_frames: 0,
+    max_duration_ms: 0,
+};
+
+/// Ten 100 ms frames, the n-th filled with the grey level n.
+fn frames() -> Vec<Frame> {
+    (0..10)
+        .map(|n| {
+            let buffer = RgbaImage::from_pixel(4, 4, Rgba([n, n, n, 255]));
+            Frame::from_parts(buffer, 0, 0, Delay::from_numer_denom_ms(100, 1))
+        })
+        .collect()
+}
+
+/// The grey level and delay in milliseconds of each frame.
+fn summary(frames: &[Frame]) -> Vec<(u8, u32)> {
+    frames
+        .iter()
+        .map(|frame| {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            (frame.buffer().get_pixel(0, 0)[0], numer / denom)
+        })
+        .collect()
+}
+
+/// An APNG announcing ten 100 ms frames, of which only the first two are
+/// there: decoding any further fails.
+fn cut_short_animation() -> Vec<u8> {
+    let mut apng = Vec::new();
+    let mut encoder = png::Encoder::new(&mut apng, 4, 4);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_animated(10, 0).unwrap();
+    encoder.set_frame_delay(1, 10).unwrap();
+    let mut writer = encoder.write_header().unwrap();
+    for n in 0..2 {
+        writer.write_image_data(&[n; 4 * 4 * 4]).unwrap();
+    }
+    drop(writer);
+    apng
+}
+
+fn select(selection: FrameSelection, config: &AnimationConfig) -> Vec<(u8, u32)> {
+    summary(&animation::select(frames(), &selection, config))
+}
+
+#[test]
+fn frames_are_sampled_and_capped_keeping_their_pace() {
+    let every_third = FrameSelection {
+        step: Some(3),
+        ..FrameSelection::default()
+    };
+    assert_eq!(
+        select(every_third, &UNCAPPED),
+        [(0, 300), (3, 300), (6, 300), (9, 100)]
+    );
+
+    let first_two = FrameSelection {
+        max_frames: Some(2),
+        ..every_third
+    };
+    assert_eq!(select(first_two, &UNCAPPED), [(0, 300), (3, 300)]);
+
+    let short = FrameSelection {
+        max_duration_ms: Some(450),
+        ..every_third
+    };
+    assert_eq!(select(short, &UNCAPPED), [(0, 300), (3, 150)]);
+
+    // The server caps apply whatever the request asks.
+    let config = AnimationConfig {
+        max_frames: 3,
+        max_duration_ms: 250,
+    };
+    let greedy = FrameSelection {
+        max_frames: Some(100),
+        ..FrameSelection::default()
+    };
+    assert_eq!(select(greedy, &config), [(0, 100), (1, 100), (2, 50)]);
+}
+
+#[test]
+fn poster_frames_are_encoded_as_stills() {
+    let input = fixture("animated.png");
+    let poster = |index| {
+        let options = CompressionOptions {
+            frames: FrameSelection {
+                poster: Some(index),
+                ..FrameSelection::default()
+            },
+            ..CompressionOptions::default()
+        };
+        compress_image_with(&input, &options, &Config::default()).unwrap()
+    };
+    let first = poster(0);
+    assert_eq!(first.content_type, "image/jpeg");
+    assert_eq!(first.dimensions, (64, 64));
+    assert_ne!(poster(1).data, first.data);
+    // Past the last frame, the last one.
+    assert_eq!(poster(99).data, poster(2).data);
+
+    let contradictory = CompressionOptions {
+        animation: AnimationMode::Animate,
+        frames: FrameSelection {
+            poster: Some(0),
+            ..FrameSelection::default()
+        },
+        ..CompressionOptions::default()
+    };
+    let max = MaxDimensions {
+        width: 8192,
+        height: 8192,
+    };
+    assert_eq!(contradictory.conflicts(max)[0].field, "poster_frame");
+}
+
+#[test]
+fn decoding_stops_once_the_caps_are_reached() {
+    let input = cut_short_animation();
+    assert!(decode_frames(&input, FrameLimit::NONE).is_err());
+
+    let capped = AnimationConfig {
+        max_frames: 2,
+        max_duration_ms: 0,
+    };
+    let limits = [
+        FrameSelection {
+            max_frames: Some(2),
+            ..FrameSelection::default()
+        }
+        .limit(&UNCAPPED),
+        FrameSelection {
+            max_duration_ms: Some(150),
+            ..FrameSelection::default()
+        }
+        .limit(&UNCAPPED),
+        FrameSelection {
+            poster: Some(1),
+            ..FrameSelection::default()
+        }
+        .limit(&UNCAPPED),
+        FrameSelection::default().limit(&capped),
+    ];
+    for limit in limits {
+        let frames = decode_frames(&input, limit).unwrap().unwrap();
+        assert_eq!(frames.len(), 2, "{:?}", limit);
+    }
+
+    let options = CompressionOptions {
+        animation: AnimationMode::Animate,
+        frames: FrameSelection {
+            max_frames: Some(2),
+            ..FrameSelection::default()
+        },
+        ..CompressionOptions::default()
+    };
+    let compressed = compress_image_with(&input, &options, &Config::default()).unwrap();
+    assert_eq!(compressed.content_type, "image/webp");
+}
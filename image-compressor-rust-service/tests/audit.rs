@@ -0,0 +1,155 @@
+// image-compressor-rust-service/tests/audit.rs
+
+//! The audit log of `/compress` requests, exercised through the router.
+
+mod common;
+
+use axum::body::to_bytes;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::{Json, Router};
+use common::{fixture, send};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::server::audit::AuditConfig;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+fn app(audit: AuditConfig) -> Router {
+    common::app(Config {
+        audit,
+        ..Config::default()
+    })
+}
+
+/// A fresh audit log path for one test.
+fn log_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("audit-{}-{}.jsonl", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn read_records(path: &PathBuf) -> Vec<Value> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+#[tokio::test]
+async fn successful_requests_are_recorded_with_hashes() {
+    let path = log_path("success");
+    let app = app(AuditConfig {
+        path: Some(path.clone()),
+        ..AuditConfig::default()
+    });
+    let input = fixture("landscape.jpg");
+
+    let headers = [
+        ("X-Api-Key", "tenant-a"),
+        ("X-Filename", "../uploads/ch%C3%A2teau.png"),
+        ("X-Compression-Quality", "60"),
+        ("X-Width", "80"),
+    ];
+    let response = send(&app, common::post("/compress", &headers, input.clone())).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let output = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+    let records = read_records(&path);
+    assert_eq!(records.len(), 1);
+    let record = &records[0];
+    assert_eq!(record["tenant"], "tenant-a");
+    assert_eq!(record["filename"], "château.png");
+    assert_eq!(record["outcome"], "success");
+    assert_eq!(record["status"], 200);
+    assert_eq!(record["input_format"], "jpeg");
+    assert_eq!(record["input_bytes"], input.len());
+    assert_eq!(record["input_sha256"], sha256(&input));
+    assert_eq!(record["output_content_type"], "image/jpeg");
+    assert_eq!(record["output_bytes"], output.len());
+    assert_eq!(record["output_sha256"], sha256(&output));
+    assert_eq!(record["options"]["quality"], 60);
+    assert_eq!(record["options"]["width"], 80);
+    assert_eq!(record["options"]["priority"], "normal");
+    assert!(record["request_id"]
+        .as_str()
+        .is_some_and(|id| !id.is_empty()));
+    assert!(record["timestamp"].as_str().unwrap().ends_with('Z'));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn rejected_requests_are_recorded_too() {
+    let path = log_path("rejected");
+    let app = app(AuditConfig {
+        path: Some(path.clone()),
+        ..AuditConfig::default()
+    });
+
+    let body = "definitely not an image";
+    let response = send(&app, common::post("/compress", &[], body)).await;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let records = read_records(&path);
+    assert_eq!(records.len(), 1);
+    let record = &records[0];
+    assert_eq!(record["outcome"], "rejected");
+    assert_eq!(record["status"], 415);
+    assert_eq!(record["error_code"], "unsupported_media_type");
+    assert_eq!(record["input_sha256"], sha256(b"definitely not an image"));
+    assert!(record["tenant"].is_null());
+    assert!(record["output_sha256"].is_null());
+    assert!(record["options"].is_null());
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Webhook receiver passing records on to the test.
+async fn collect(State(sender): State<mpsc::UnboundedSender<Value>>, Json(record): Json<Value>) {
+    sender.send(record).unwrap();
+}
+
+#[tokio::test]
+async fn records_are_posted_to_the_webhook() {
+    let (sender, mut received) = mpsc::unbounded_channel::<Value>();
+    let webhook = Router::new()
+        .route("/audit", post(collect))
+        .with_state(sender);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, webhook).await });
+
+    let app = app(AuditConfig {
+        webhook_url: Some(format!("http://{}/audit", addr)),
+        ..AuditConfig::default()
+    });
+    let input = fixture("landscape.jpg");
+    let response = send(&app, common::post("/compress", &[], input.clone())).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let record = tokio::time::timeout(Duration::from_secs(10), received.recv())
+        .await
+        .expect("the webhook is called")
+        .unwrap();
+    assert_eq!(record["outcome"], "success");
+    assert_eq!(record["input_sha256"], sha256(&input));
+}
+
+#[test]
+fn invalid_webhook_urls_are_rejected() {
+    let config = Config {
+        audit: AuditConfig {
+            webhook_url: Some("not a url".to_string()),
+            ..AuditConfig::default()
+        },
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+}
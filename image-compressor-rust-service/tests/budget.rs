@@ -0,0 +1,127 @@
+// image-compressor-rust-service/tests/budget.rs
+
+//! Byte budgets requested with `X-Max-Bytes`.
+
+mod common;
+
+use axum::body::to_bytes;
+use axum::http::StatusCode;
+use axum::response::Response;
+use common::{app, fixture, post, send};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::cpu::{CpuBudget, CpuConfig, Priority};
+use image_compressor_rust_service::{
+    compress_image, compress_image_on, ByteBudget, CompressionOptions, Dither, Palette,
+};
+
+fn budget(max_bytes: u64) -> ByteBudget {
+    ByteBudget {
+        max_bytes,
+        min_quality: 1,
+        min_width: None,
+        min_height: None,
+    }
+}
+
+fn size_at(quality: u8) -> u64 {
+    let options = CompressionOptions::default().with_quality(quality);
+    compress_image(&fixture("landscape.jpg"), &options)
+        .unwrap()
+        .data
+        .len() as u64
+}
+
+#[test]
+fn budgets_within_reach_of_the_quality_keep_the_size() {
+    let max_bytes = size_at(50);
+    let options = CompressionOptions {
+        budget: Some(budget(max_bytes)),
+        ..CompressionOptions::default()
+    };
+    let output = compress_image(&fixture("landscape.jpg"), &options).unwrap();
+    assert!(output.data.len() as u64 <= max_bytes);
+    assert!(output.data.len() as u64 > size_at(40));
+    assert_eq!(output.dimensions, (160, 96));
+
+    // A budget the requested quality already meets changes nothing.
+    let options = CompressionOptions {
+        budget: Some(budget(1 << 20)),
+        ..CompressionOptions::default()
+    };
+    let output = compress_image(&fixture("landscape.jpg"), &options).unwrap();
+    assert_eq!(output.data.len() as u64, size_at(80));
+}
+
+#[test]
+fn budgets_out_of_reach_of_the_quality_scale_the_image_down() {
+    let max_bytes = size_at(1) * 3 / 4;
+    let options = CompressionOptions {
+        budget: Some(budget(max_bytes)),
+        ..CompressionOptions::default()
+    };
+    let output = compress_image(&fixture("landscape.jpg"), &options).unwrap();
+    assert!(output.data.len() as u64 <= max_bytes);
+    assert!(output.dimensions.0 < 160 && output.dimensions.1 < 96);
+
+    let floors = CompressionOptions {
+        budget: Some(ByteBudget {
+            min_width: Some(150),
+            ..budget(max_bytes)
+        }),
+        ..CompressionOptions::default()
+    };
+    let error = compress_image(&fixture("landscape.jpg"), &floors).unwrap_err();
+    assert!(error.to_string().contains("does not fit"), "{}", error);
+
+    let palette = CompressionOptions {
+        budget: Some(budget(max_bytes)),
+        palette: Some(Palette::new(16, Dither::None)),
+        ..CompressionOptions::default()
+    };
+    assert!(compress_image(&fixture("landscape.jpg"), &palette).is_err());
+}
+
+#[tokio::test]
+async fn threaded_trials_match_single_threaded_output() {
+    let options = CompressionOptions {
+        budget: Some(budget(size_at(1) * 3 / 4)),
+        ..CompressionOptions::default()
+    };
+    let input = fixture("landscape.jpg");
+    let cpu = CpuBudget::new(&CpuConfig {
+        budget: 4,
+        ..CpuConfig::default()
+    });
+    let mut threads = cpu.acquire(Priority::Normal).await;
+
+    let threaded = compress_image_on(&input, &options, &Config::default(), &mut threads).unwrap();
+    assert_eq!(threads.count(), 4, "trials borrow free threads");
+    let single = compress_image(&input, &options).unwrap();
+    assert!(
+        threaded.data == single.data,
+        "thread count changed the output"
+    );
+}
+
+async fn compress(headers: &[(&str, &str)]) -> Response {
+    let request = post("/compress", headers, fixture("landscape.jpg"));
+    send(&app(Config::default()), request).await
+}
+
+#[tokio::test]
+async fn budgets_are_read_from_headers() {
+    let max_bytes = (size_at(1) * 3 / 4).to_string();
+    let response = compress(&[("X-Max-Bytes", &max_bytes)]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-scaling"], "down");
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert!(body.len() <= max_bytes.parse().unwrap());
+
+    let response = compress(&[
+        ("X-Max-Bytes", &max_bytes),
+        ("X-Min-Width", "160"),
+        ("X-Min-Quality", "40"),
+    ])
+    .await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
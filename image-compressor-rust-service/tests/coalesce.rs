@@ -0,0 +1,85 @@
+// image-compressor-rust-service/tests/coalesce.rs
+
+//! Coalescing of identical concurrent `/compress` requests.
+
+mod common;
+
+use axum::body::to_bytes;
+use axum::http::StatusCode;
+use axum::Router;
+use common::{fixture, post, send};
+use futures_util::future::join_all;
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::server::coalesce::{Coalescer, Join};
+use image_compressor_rust_service::server::ApiError;
+use std::sync::Arc;
+
+fn app(coalesce: bool) -> Router {
+    common::app(Config {
+        coalesce,
+        server_timing: true,
+        ..Config::default()
+    })
+}
+
+/// Sends the requests at once; returns whether each was coalesced, and its
+/// body.
+async fn compress_concurrently(app: &Router, qualities: &[&str]) -> Vec<(bool, Vec<u8>)> {
+    let requests = qualities.iter().map(|quality| {
+        let headers = [("X-Compression-Quality", *quality)];
+        let request = post("/compress", &headers, fixture("landscape.jpg"));
+        async move {
+            let response = send(app, request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let timing = response.headers()["server-timing"].to_str().unwrap();
+            let coalesced = timing.contains("coalesce");
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (coalesced, body.to_vec())
+        }
+    });
+    join_all(requests).await
+}
+
+#[tokio::test]
+async fn identical_requests_in_flight_share_one_encode() {
+    let app = app(true);
+    let responses = compress_concurrently(&app, &["60", "60", "60", "30"]).await;
+    let coalesced: Vec<bool> = responses.iter().map(|(coalesced, _)| *coalesced).collect();
+    assert_eq!(coalesced, [false, true, true, false]);
+    assert_eq!(responses[0].1, responses[1].1);
+    assert_eq!(responses[0].1, responses[2].1);
+    assert_ne!(responses[0].1, responses[3].1);
+
+    // Once the flight has landed, the next request encodes again.
+    let responses = compress_concurrently(&app, &["60"]).await;
+    assert!(!responses[0].0);
+
+    let responses = compress_concurrently(&self::app(false), &["60", "60"]).await;
+    assert!(responses.iter().all(|(coalesced, _)| !coalesced));
+}
+
+#[tokio::test]
+async fn followers_share_errors_and_outlive_their_leader() {
+    let coalescer = Arc::new(Coalescer::new());
+    let Join::Leader(flight) = coalescer.join([1; 32]) else {
+        panic!("the first request leads");
+    };
+    let Join::Follower(landing) = coalescer.join([1; 32]) else {
+        panic!("identical requests follow");
+    };
+    assert!(matches!(coalescer.join([2; 32]), Join::Leader(_)));
+    flight.land(&Err(ApiError::unprocessable("broken")));
+    let landed = landing.wait().await.unwrap();
+    assert_eq!(landed.unwrap_err().message, "broken");
+
+    // A leader dropped without landing sends its followers back to lead.
+    let Join::Leader(flight) = coalescer.join([1; 32]) else {
+        panic!("landed flights are over");
+    };
+    let Join::Follower(landing) = coalescer.join([1; 32]) else {
+        panic!("identical requests follow");
+    };
+    drop(flight);
+    assert!(landing.wait().await.is_none());
+    assert!(matches!(coalescer.join([1; 32]), Join::Leader(_)));
+}
@@ -0,0 +1,157 @@
+// image-compressor-rust-service/tests/color.rs
+
+//! Output colour spaces and their ICC tags, and greyscale outputs.
+
+mod common;
+
+use common::fixture;
+use image::{ColorType, DynamicImage, ImageOutputFormat, Rgb, RgbImage, Rgba, RgbaImage};
+use image_compressor_rust_service::color::{self, convert};
+use image_compressor_rust_service::metadata::find_icc;
+use image_compressor_rust_service::transform::MaxDimensions;
+use image_compressor_rust_service::{
+    compress_image, decode_image, ColorSpace, CompressionOptions, Palette,
+};
+use std::io::Cursor;
+
+fn solid(rgb: [u8; 3]) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 4, Rgb(rgb)))
+}
+
+fn first_pixel(image: &DynamicImage) -> [u8; 3] {
+    image.to_rgb8().get_pixel(0, 0).0
+}
+
+fn close(actual: [u8; 3], expected: [u8; 3]) -> bool {
+    actual
+        .iter()
+        .zip(expected)
+        .all(|(&a, e)| a.abs_diff(e) <= 2)
+}
+
+#[test]
+fn srgb_is_converted_with_gamma_and_gamut() {
+    // Pure sRGB red sits well inside the P3 gamut.
+    let p3 = first_pixel(&convert(solid([255, 0, 0]), None, ColorSpace::DisplayP3).unwrap());
+    assert!(close(p3, [234, 51, 35]), "P3 red is {:?}", p3);
+
+    // Mid grey is 21.6% of linear light.
+    let linear =
+        first_pixel(&convert(solid([128, 128, 128]), None, ColorSpace::LinearSrgb).unwrap());
+    assert!(close(linear, [55, 55, 55]), "linear grey is {:?}", linear);
+
+    // Untagged input is assumed to be sRGB already.
+    let srgb = first_pixel(&convert(solid([10, 200, 30]), None, ColorSpace::Srgb).unwrap());
+    assert_eq!(srgb, [10, 200, 30]);
+}
+
+#[test]
+fn embedded_profiles_are_the_source() {
+    let p3 = color::icc(ColorSpace::DisplayP3).unwrap();
+    let back = convert(solid([234, 51, 35]), Some(&p3), ColorSpace::Srgb).unwrap();
+    assert!(
+        close(first_pixel(&back), [255, 0, 0]),
+        "{:?}",
+        first_pixel(&back)
+    );
+
+    // Unusable profiles are ignored.
+    let garbage = convert(solid([1, 2, 3]), Some(b"not a profile"), ColorSpace::Srgb).unwrap();
+    assert_eq!(first_pixel(&garbage), [1, 2, 3]);
+}
+
+#[test]
+fn alpha_is_kept() {
+    let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 77])));
+    let converted = convert(image, None, ColorSpace::DisplayP3)
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(converted.get_pixel(1, 1).0[3], 77);
+}
+
+#[test]
+fn outputs_are_tagged_with_the_target_profile() {
+    let options = CompressionOptions {
+        color_space: Some(ColorSpace::DisplayP3),
+        ..CompressionOptions::default()
+    };
+    let input = fixture("srgb-icc.jpg");
+    let output = compress_image(&input, &options).unwrap();
+    assert_eq!(
+        find_icc(&output.data),
+        Some(color::icc(ColorSpace::DisplayP3).unwrap())
+    );
+    // The pixels still decode.
+    assert_eq!(image::load_from_memory(&output.data).unwrap().width(), 160);
+
+    // The profile carries no creation time, so repeated requests match.
+    let again = compress_image(&input, &options).unwrap();
+    assert_eq!(output.data, again.data);
+
+    let untagged = compress_image(&input, &CompressionOptions::default()).unwrap();
+    assert_eq!(find_icc(&untagged.data), None);
+}
+
+#[test]
+fn color_spaces_parse() {
+    assert_eq!(ColorSpace::parse("Display-P3"), Some(ColorSpace::DisplayP3));
+    assert_eq!(ColorSpace::parse("p3"), Some(ColorSpace::DisplayP3));
+    assert_eq!(ColorSpace::parse("linear"), Some(ColorSpace::LinearSrgb));
+    assert_eq!(ColorSpace::parse("sRGB"), Some(ColorSpace::Srgb));
+    assert_eq!(ColorSpace::parse("adobe-rgb"), None);
+}
+
+#[test]
+fn grey_stills_are_encoded_with_one_channel() {
+    // A scan saved as RGB.
+    let scan = decode_image(&fixture("landscape.jpg")).unwrap();
+    let scan = DynamicImage::ImageRgb8(DynamicImage::ImageLuma8(scan.to_luma8()).to_rgb8());
+    let mut png = Vec::new();
+    scan.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+        .unwrap();
+    let encode = |input: &[u8], options: &CompressionOptions| {
+        let data = compress_image(input, options).unwrap().data;
+        (decode_image(&data).unwrap().color(), data.len())
+    };
+
+    let (color, detected) = encode(&png, &CompressionOptions::default());
+    assert_eq!(color, ColorType::L8);
+    let keep = CompressionOptions {
+        grayscale: Some(false),
+        ..CompressionOptions::default()
+    };
+    let (color, kept) = encode(&png, &keep);
+    assert_eq!(color, ColorType::Rgb8);
+    assert!(detected < kept);
+
+    // Colour inputs keep their colour unless asked.
+    let photo = fixture("landscape.jpg");
+    assert_eq!(
+        encode(&photo, &CompressionOptions::default()).0,
+        ColorType::Rgb8
+    );
+    let forced = CompressionOptions {
+        grayscale: Some(true),
+        ..CompressionOptions::default()
+    };
+    assert_eq!(encode(&photo, &forced).0, ColorType::L8);
+
+    // Grey palettes are written without one.
+    let palette = CompressionOptions {
+        palette: Some(Palette::new(64, Default::default())),
+        ..CompressionOptions::default()
+    };
+    assert_eq!(encode(&png, &palette).0, ColorType::L8);
+
+    let tagged = CompressionOptions {
+        color_space: Some(ColorSpace::DisplayP3),
+        ..forced
+    };
+    let max = MaxDimensions {
+        width: 8192,
+        height: 8192,
+    };
+    assert_eq!(tagged.conflicts(max)[0].field, "grayscale");
+    assert!(color::is_grayscale(&scan));
+    assert!(!color::is_grayscale(&solid([200, 100, 50])));
+}
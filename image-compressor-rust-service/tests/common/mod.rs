@@ -0,0 +1,106 @@
+// image-compressor-rust-service/tests/common/mod.rs
+
+//! Helpers shared by the integration tests.
+
+#![allow(dead_code)]
+
+use axum::body::{to_bytes, Body};
+use axum::http::Request;
+use axum::response::Response;
+use axum::Router;
+use image::{DynamicImage, GrayImage};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::server::{self, AppState};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use serde_json::Value;
+use std::path::PathBuf;
+use tower::ServiceExt;
+
+/// Reads a file from `tests/fixtures`.
+pub fn fixture(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    std::fs::read(&path).unwrap_or_else(|e| panic!("missing fixture {}: {}", path.display(), e))
+}
+
+/// The service's state for `config`, with a metrics recorder of its own.
+pub fn state(config: Config) -> AppState {
+    AppState::new(config, PrometheusBuilder::new().build_recorder().handle())
+}
+
+/// The service's router for `config`.
+pub fn app(config: Config) -> Router {
+    server::router(state(config))
+}
+
+/// A `POST` of `body` to `path` with these headers.
+pub fn post(path: &str, headers: &[(&str, &str)], body: impl Into<Body>) -> Request<Body> {
+    let mut request = Request::post(path);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    request.body(body.into()).unwrap()
+}
+
+/// A `GET` of `path`.
+pub fn get(path: &str) -> Request<Body> {
+    Request::get(path).body(Body::empty()).unwrap()
+}
+
+/// Sends `request` through `app`.
+pub async fn send(app: &Router, request: Request<Body>) -> Response {
+    app.clone().oneshot(request).await.unwrap()
+}
+
+/// The body of `response`, parsed as JSON.
+pub async fn json_body(response: Response) -> Value {
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// Mean structural similarity of the luma channels of two equally sized
+/// images, computed over non-overlapping 8x8 windows. 1.0 means identical.
+pub fn ssim(a: &DynamicImage, b: &DynamicImage) -> f64 {
+    assert_eq!(
+        (a.width(), a.height()),
+        (b.width(), b.height()),
+        "SSIM requires equally sized images"
+    );
+    let (a, b) = (a.to_luma8(), b.to_luma8());
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    const WINDOW: u32 = 8;
+
+    let mut total = 0.0;
+    let mut windows = 0u32;
+    for wy in (0..a.height()).step_by(WINDOW as usize) {
+        for wx in (0..a.width()).step_by(WINDOW as usize) {
+            let (pa, pb) = (window(&a, wx, wy, WINDOW), window(&b, wx, wy, WINDOW));
+            let n = pa.len() as f64;
+            let mean_a = pa.iter().sum::<f64>() / n;
+            let mean_b = pb.iter().sum::<f64>() / n;
+            let (mut var_a, mut var_b, mut cov) = (0.0, 0.0, 0.0);
+            for (x, y) in pa.iter().zip(&pb) {
+                var_a += (x - mean_a) * (x - mean_a);
+                var_b += (y - mean_b) * (y - mean_b);
+                cov += (x - mean_a) * (y - mean_b);
+            }
+            let (var_a, var_b, cov) = (var_a / n, var_b / n, cov / n);
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * cov + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    total / f64::from(windows)
+}
+
+fn window(image: &GrayImage, x0: u32, y0: u32, size: u32) -> Vec<f64> {
+    let mut samples = Vec::new();
+    for y in y0..(y0 + size).min(image.height()) {
+        for x in x0..(x0 + size).min(image.width()) {
+            samples.push(f64::from(image.get_pixel(x, y).0[0]));
+        }
+    }
+    samples
+}
@@ -0,0 +1,80 @@
+// image-compressor-rust-service/tests/concurrency.rs
+
+//! Per-endpoint concurrency limits.
+
+mod common;
+
+use axum::body::to_bytes;
+use axum::http::StatusCode;
+use common::{fixture, post, send};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::server;
+use image_compressor_rust_service::server::concurrency::{EndpointLimit, Limiter};
+use std::time::Duration;
+
+#[tokio::test]
+async fn requests_past_the_queue_are_turned_away() {
+    let limiter = Limiter::new(
+        "/contact-sheet",
+        EndpointLimit {
+            max_in_flight: 1,
+            max_queued: 1,
+        },
+    );
+    let first = limiter.enter().await.unwrap();
+    let waiting = limiter.enter();
+    tokio::pin!(waiting);
+    // The second request queues behind the first...
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), &mut waiting)
+            .await
+            .is_err()
+    );
+    assert_eq!((limiter.in_flight(), limiter.queued()), (1, 1));
+
+    // ...and a third finds the queue full.
+    let rejected = limiter.enter().await.unwrap_err();
+    assert_eq!(rejected.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(rejected.code, "endpoint_saturated");
+    let details = rejected.details.unwrap();
+    assert_eq!(details["endpoint"], "/contact-sheet");
+    assert_eq!(details["max_queued"], 1);
+
+    drop(first);
+    let slot = waiting.await.unwrap();
+    assert_eq!((limiter.in_flight(), limiter.queued()), (1, 0));
+    drop(slot);
+    assert_eq!(limiter.in_flight(), 0);
+}
+
+#[tokio::test]
+async fn limits_apply_to_their_route_and_are_validated() {
+    let config: Config = toml::from_str(
+        "[concurrency.routes.\"/icons/:set\"]\nmax_in_flight = 2\n\
+         [concurrency.animated]\nmax_in_flight = 1\nmax_queued = 4",
+    )
+    .unwrap();
+    config.validate().unwrap();
+    let state = common::state(config);
+    assert!(state.concurrency.route("/icons/:set").is_some());
+    assert!(state.concurrency.route("/compress").is_none());
+
+    // Requests through a limited route take and give back a slot.
+    let request = post("/icons/favicon", &[], fixture("landscape.jpg"));
+    let response = send(&server::router(state.clone()), request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(
+        state.concurrency.route("/icons/:set").unwrap().in_flight(),
+        0
+    );
+
+    let closed: Config =
+        toml::from_str("[concurrency.routes.\"/diff\"]\nmax_in_flight = 0").unwrap();
+    let error = format!("{:#}", closed.validate().unwrap_err());
+    assert!(
+        error.contains("max_in_flight of '/diff' must be at least 1"),
+        "{}",
+        error
+    );
+}
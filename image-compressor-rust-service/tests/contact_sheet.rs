@@ -0,0 +1,179 @@
+// image-compressor-rust-service/tests/contact_sheet.rs
+
+//! ZIP inputs and contact sheets from `/contact-sheet`.
+
+mod common;
+
+use axum::body::to_bytes;
+use axum::http::{header, StatusCode};
+use common::{fixture, send};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use image::{DynamicImage, Rgba, RgbaImage};
+use image_compressor_rust_service::archive::{write_zip, Archive};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::contact_sheet::{Layout, Tile};
+use image_compressor_rust_service::text::Font;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// A one-entry archive holding `data` deflated, as most ZIP tools write.
+fn deflated_zip(name: &str, data: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    let compressed = encoder.finish().unwrap();
+    // Stored layout first, then the compressed data and method patched in.
+    let mut archive = write_zip(&[(name.to_string(), compressed.clone())]);
+    let fix = |archive: &mut Vec<u8>, at: usize| {
+        archive[at..at + 2].copy_from_slice(&8u16.to_le_bytes());
+        archive[at + 6..at + 10].copy_from_slice(&crc32fast::hash(data).to_le_bytes());
+        archive[at + 14..at + 18].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    };
+    fix(&mut archive, 8);
+    let directory = 30 + name.len() + compressed.len();
+    fix(&mut archive, directory + 10);
+    archive
+}
+
+#[test]
+fn archives_round_trip_stored_and_deflated_entries() {
+    let files = vec![
+        ("a.txt".to_string(), b"hello".to_vec()),
+        ("dir/b.bin".to_string(), vec![7; 1000]),
+    ];
+    let data = write_zip(&files);
+    let archive = Archive::parse(&data).unwrap();
+    let read: Vec<_> = archive
+        .entries()
+        .iter()
+        .map(|e| (e.name.clone(), archive.read(e, 1 << 20).unwrap()))
+        .collect();
+    assert_eq!(read, files);
+
+    let text = b"contact sheet ".repeat(100);
+    let data = deflated_zip("notes.txt", &text);
+    let archive = Archive::parse(&data).unwrap();
+    let entry = &archive.entries()[0];
+    assert_eq!(entry.size, text.len() as u64);
+    assert_eq!(archive.read(entry, 1 << 20).unwrap(), text);
+    // Sizes are checked before anything is inflated.
+    assert!(archive.read(entry, 100).is_err());
+}
+
+#[test]
+fn damaged_archives_are_refused() {
+    assert!(Archive::parse(b"not a zip").is_err());
+    assert!(Archive::parse(&fixture("landscape.jpg")).is_err());
+
+    let mut data = write_zip(&[("a.txt".to_string(), b"hello".to_vec())]);
+    data[30 + 5] = b'j';
+    let archive = Archive::parse(&data).unwrap();
+    assert!(archive.read(&archive.entries()[0], 1 << 20).is_err());
+}
+
+fn font() -> Font {
+    Font::parse(fixture("font.ttf")).unwrap()
+}
+
+#[test]
+fn layouts_paginate_and_shrink_the_last_page() {
+    let layout = Layout {
+        columns: 3,
+        rows: 2,
+        thumb_size: 64,
+    };
+    layout.validate().unwrap();
+    assert_eq!(layout.per_page(), 6);
+    assert_eq!(layout.pages(0), 1);
+    assert_eq!(layout.pages(6), 1);
+    assert_eq!(layout.pages(7), 2);
+    assert!(Layout {
+        columns: 0,
+        ..layout
+    }
+    .validate()
+    .is_err());
+    assert!(Layout {
+        thumb_size: 4096,
+        ..layout
+    }
+    .validate()
+    .is_err());
+
+    let tile = |image: Option<DynamicImage>| Tile {
+        name: "IO".to_string(),
+        image,
+    };
+    let red = DynamicImage::ImageRgba8(RgbaImage::from_pixel(32, 16, Rgba([255, 0, 0, 255])));
+    let full = layout.render(&font(), &vec![tile(Some(red.clone())); 6]);
+    let short = layout.render(&font(), &[tile(Some(red)), tile(None)]);
+    assert_eq!(full.width(), short.width());
+    assert!(short.height() < full.height());
+
+    // Small images are centred in their cell, not enlarged.
+    assert_eq!(short.get_pixel(16 + 32, 16 + 32).0, [255, 0, 0, 255]);
+    assert_ne!(short.get_pixel(16 + 8, 16 + 32).0, [255, 0, 0, 255]);
+    // Unreadable files get a tinted cell.
+    let broken = short.get_pixel(16 + 64 + 16 + 32, 16 + 32).0;
+    assert!(broken[0] > broken[1], "{:?}", broken);
+    // The name is written under the thumbnail.
+    let label = (16..16 + 24).any(|x| short.get_pixel(x, 16 + 64 + 10).0[0] < 128);
+    assert!(label);
+}
+
+fn router() -> axum::Router {
+    let mut config = Config::default();
+    config.social.font =
+        Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/font.ttf"));
+    common::app(config)
+}
+
+async fn post(archive: Vec<u8>, headers: &[(&str, &str)]) -> axum::response::Response {
+    let headers = [&[("Content-Type", "application/zip")], headers].concat();
+    send(&router(), common::post("/contact-sheet", &headers, archive)).await
+}
+
+#[tokio::test]
+async fn contact_sheets_are_paginated() {
+    let mut files: Vec<_> = (0..5)
+        .map(|i| (format!("batch/{}.jpg", i), fixture("landscape.jpg")))
+        .collect();
+    files.push(("batch/.DS_Store".to_string(), vec![0; 16]));
+    files.push(("__MACOSX/batch/._0.jpg".to_string(), vec![0; 16]));
+    files.push(("batch/broken.png".to_string(), b"not an image".to_vec()));
+    let archive = write_zip(&files);
+    let layout = [("X-Columns", "2"), ("X-Rows", "2"), ("X-Thumb-Size", "64")];
+
+    let response = post(archive.clone(), &layout).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/jpeg");
+    assert_eq!(response.headers()["x-page-count"], "2");
+    assert_eq!(response.headers()["x-image-count"], "6");
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let first = image::load_from_memory(&body).unwrap();
+
+    let response = post(
+        archive.clone(),
+        &[layout.as_slice(), &[("X-Page", "2")]].concat(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let last = image::load_from_memory(&body).unwrap();
+    assert_eq!(first.width(), last.width());
+    assert!(last.height() < first.height());
+
+    let response = post(archive, &[layout.as_slice(), &[("X-Page", "3")]].concat()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn contact_sheet_rejects_bad_requests() {
+    let response = post(fixture("landscape.jpg"), &[]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let archive = write_zip(&[("a.jpg".to_string(), fixture("landscape.jpg"))]);
+    let response = post(archive.clone(), &[("X-Columns", "100")]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = post(archive, &[("X-Page", "first")]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
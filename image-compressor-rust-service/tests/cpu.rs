@@ -0,0 +1,212 @@
+// image-compressor-rust-service/tests/cpu.rs
+
+mod common;
+
+use common::fixture;
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::cpu::{
+    BackgroundConfig, BackgroundPool, CpuBudget, CpuConfig, Priority, PriorityConfig,
+};
+use image_compressor_rust_service::{
+    compress_image_on, compress_image_with, AnimationMode, CompressionOptions,
+};
+
+fn budget(threads: usize) -> CpuBudget {
+    CpuBudget::new(&CpuConfig {
+        budget: threads,
+        max_threads_per_image: 4,
+        parallel_min_pixels: 1_000,
+        ..CpuConfig::default()
+    })
+}
+
+#[tokio::test]
+async fn large_images_borrow_only_free_threads() {
+    let budget = budget(3);
+    let mut other = budget.acquire(Priority::Normal).await;
+    let mut large = budget.acquire(Priority::Normal).await;
+
+    assert_eq!(large.reserve_for(1_000_000), 2, "only one thread is free");
+    assert_eq!(budget.available(), 0);
+    assert_eq!(other.reserve_for(1_000_000), 1, "the budget is exhausted");
+
+    drop(large);
+    assert_eq!(budget.available(), 2);
+}
+
+#[tokio::test]
+async fn small_images_stay_on_one_thread() {
+    let budget = budget(4);
+    let mut threads = budget.acquire(Priority::Normal).await;
+
+    assert_eq!(threads.reserve_for(999), 1);
+    assert_eq!(budget.available(), 3);
+}
+
+#[tokio::test]
+async fn threaded_animations_match_single_threaded_output() {
+    let config = Config {
+        cpu: CpuConfig {
+            parallel_min_pixels: 0,
+            ..CpuConfig::default()
+        },
+        ..Config::default()
+    };
+    let options = CompressionOptions {
+        animation: AnimationMode::Animate,
+        width: Some(40),
+        ..CompressionOptions::default()
+    };
+    let input = fixture("animated.png");
+    let mut threads = budget(4).acquire(Priority::Normal).await;
+    threads.reserve_for(u64::MAX);
+    assert_eq!(threads.count(), 4);
+
+    let threaded = compress_image_on(&input, &options, &config, &mut threads).unwrap();
+    let single = compress_image_with(&input, &options, &config).unwrap();
+    assert!(
+        threaded.data == single.data,
+        "thread count changed the output"
+    );
+}
+
+#[tokio::test]
+async fn background_jobs_run_on_their_own_threads() {
+    let budget = budget(1);
+    let _interactive = budget.acquire(Priority::Normal).await;
+    let pool = BackgroundPool::new(&BackgroundConfig {
+        threads: 2,
+        nice: 5,
+    });
+
+    // The interactive budget is exhausted, yet background jobs still run.
+    let name = pool
+        .run(|| std::thread::current().name().map(str::to_owned))
+        .await
+        .unwrap();
+    assert!(name.unwrap().starts_with("background-"));
+    assert_eq!(budget.available(), 0);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn background_threads_run_at_the_configured_nice_level() {
+    let pool = BackgroundPool::new(&BackgroundConfig {
+        threads: 1,
+        nice: 7,
+    });
+    // Field 19 of the thread's stat line; the command name before it is
+    // parenthesised and may contain spaces.
+    let nice = pool
+        .run(|| {
+            let stat = std::fs::read_to_string("/proc/thread-self/stat").unwrap();
+            let fields = stat.rsplit_once(')').unwrap().1;
+            fields
+                .split_whitespace()
+                .nth(16)
+                .unwrap()
+                .parse::<i32>()
+                .unwrap()
+        })
+        .await
+        .unwrap();
+    assert_eq!(nice, 7);
+}
+
+#[tokio::test]
+async fn a_panicking_background_job_is_reported() {
+    let pool = BackgroundPool::new(&BackgroundConfig::default());
+    let result = pool.run(|| -> u8 { panic!("boom") }).await;
+    assert!(result.is_err());
+    assert_eq!(pool.run(|| 1).await.unwrap(), 1, "the worker survives");
+}
+
+/// Queues `count` requests of `priority` behind the single busy thread of
+/// `budget`; each records its priority in `order` once it gets the thread.
+fn queue(
+    budget: &CpuBudget,
+    priority: Priority,
+    count: usize,
+    order: &std::sync::Arc<std::sync::Mutex<Vec<Priority>>>,
+) -> Vec<tokio::task::JoinHandle<()>> {
+    (0..count)
+        .map(|_| {
+            let (budget, order) = (budget.clone(), order.clone());
+            tokio::spawn(async move {
+                let _threads = budget.acquire(priority).await;
+                order.lock().unwrap().push(priority);
+            })
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn queued_requests_are_dispatched_by_weight() {
+    let budget = budget(1);
+    let busy = budget.acquire(Priority::Normal).await;
+    let order = Default::default();
+    let mut tasks = queue(&budget, Priority::Low, 10, &order);
+    tasks.extend(queue(&budget, Priority::High, 10, &order));
+    tasks.extend(queue(&budget, Priority::Normal, 10, &order));
+    // On the single-threaded test runtime, one yield lets every task reach
+    // the queue.
+    tokio::task::yield_now().await;
+    assert_eq!(budget.available(), 0);
+
+    drop(busy);
+    for task in tasks {
+        task.await.unwrap();
+    }
+    let order = order.lock().unwrap();
+    let first_ten = &order[..10];
+    let count = |p| first_ten.iter().filter(|&&q| q == p).count();
+    assert_eq!(
+        (
+            count(Priority::High),
+            count(Priority::Normal),
+            count(Priority::Low)
+        ),
+        (6, 3, 1),
+        "{:?}",
+        first_ten
+    );
+    assert_eq!(order.len(), 30);
+}
+
+#[tokio::test]
+async fn abandoned_waiters_do_not_leak_threads() {
+    let budget = budget(1);
+    let busy = budget.acquire(Priority::Normal).await;
+    let waiter = tokio::spawn({
+        let budget = budget.clone();
+        async move { budget.acquire(Priority::High).await.count() }
+    });
+    tokio::task::yield_now().await;
+    waiter.abort();
+    let _ = waiter.await;
+
+    drop(busy);
+    assert_eq!(budget.available(), 1);
+}
+
+#[test]
+fn api_keys_cap_and_default_the_priority() {
+    let config = PriorityConfig {
+        api_keys: [("premium".to_string(), Priority::High)].into(),
+        ..PriorityConfig::default()
+    };
+    assert_eq!(config.priority_for(None, None), Priority::Normal);
+    assert_eq!(
+        config.priority_for(None, Some(Priority::High)),
+        Priority::Normal
+    );
+    assert_eq!(
+        config.priority_for(None, Some(Priority::Low)),
+        Priority::Low
+    );
+    assert_eq!(config.priority_for(Some("premium"), None), Priority::High);
+    assert_eq!(
+        config.priority_for(Some("premium"), Some(Priority::Low)),
+        Priority::Low
+    );
+}
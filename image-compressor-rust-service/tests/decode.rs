@@ -0,0 +1,154 @@
+// image-compressor-rust-service/tests/decode.rs
+
+//! Telling apart why an input failed to decode, and salvaging what is left
+//! of it.
+
+mod common;
+
+use axum::body::to_bytes;
+use axum::http::StatusCode;
+use common::{app, fixture, json_body, post, send};
+use image_compressor_rust_service::animation::FrameLimit;
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::decode::salvage_image;
+use image_compressor_rust_service::{decode_frames, decode_image, DecodeError};
+
+fn decode_error(data: &[u8]) -> DecodeError {
+    let error = decode_image(data).unwrap_err();
+    *error.downcast_ref::<DecodeError>().unwrap()
+}
+
+/// The landscape fixture with its baseline frame header renamed to `marker`.
+fn landscape_with_frame(marker: u8) -> Vec<u8> {
+    let mut data = fixture("landscape.jpg");
+    let sof = data.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap();
+    data[sof + 1] = marker;
+    data
+}
+
+#[test]
+fn decode_failures_are_classified() {
+    let jpeg = fixture("landscape.jpg");
+    let png = fixture("portrait-alpha.png");
+    assert_eq!(
+        decode_error(&jpeg[..jpeg.len() / 2]),
+        DecodeError::Truncated
+    );
+    assert_eq!(decode_error(&png[..png.len() / 2]), DecodeError::Truncated);
+
+    // Arithmetic entropy coding.
+    let arithmetic = landscape_with_frame(0xC9);
+    assert_eq!(decode_error(&arithmetic), DecodeError::UnsupportedFeature);
+    assert_eq!(
+        decode_error(b"GIF89a\x01\x00\x01\x00\x00\x00\x00;"),
+        DecodeError::UnsupportedContainer
+    );
+
+    let mut corrupt = jpeg.clone();
+    for i in (jpeg.len() / 3..jpeg.len() / 2).step_by(7) {
+        corrupt[i] ^= 0x5A;
+    }
+    assert_eq!(decode_error(&corrupt), DecodeError::Corrupt);
+}
+
+#[tokio::test]
+async fn compress_errors_say_why_the_input_did_not_decode() {
+    let app = app(Config::default());
+    let jpeg = fixture("landscape.jpg");
+    let cases = [
+        (jpeg[..jpeg.len() / 2].to_vec(), "truncated"),
+        (landscape_with_frame(0xC9), "unsupported_feature"),
+        (fixture("oversized.jpg"), "too_large"),
+    ];
+    for (input, expected) in cases {
+        let response = send(&app, post("/compress", &[], input)).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let error = json_body(response).await;
+        assert_eq!(error["error"]["code"], "unprocessable_image");
+        assert_eq!(error["error"]["details"]["decode_error"], expected);
+    }
+}
+
+#[tokio::test]
+async fn oversized_jpegs_are_rejected_from_their_header() {
+    // 136 bytes declaring 60000x60000 pixels: decoding would allocate
+    // 3.6 GB, and a failed allocation aborts the process.
+    let oversized = fixture("oversized.jpg");
+    assert_eq!(decode_error(&oversized), DecodeError::TooLarge);
+    assert!(salvage_image(&oversized).is_err());
+
+    let app = app(Config::default());
+    for headers in [&[][..], &[("X-Rotate", "90")]] {
+        let request = post("/compress", headers, oversized.clone());
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
+
+#[test]
+fn oversized_animations_are_rejected_before_any_frame_is_decoded() {
+    // 200 frames of 1024x1024 RGBA canvas take 800 MiB, more than the
+    // allocation limit, although each frame alone is within it. Only the
+    // first frame is written.
+    let mut apng = Vec::new();
+    let mut encoder = png::Encoder::new(&mut apng, 1024, 1024);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_animated(200, 0).unwrap();
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&vec![0; 1024 * 1024 * 4]).unwrap();
+    drop(writer);
+
+    // Decoding the frames would fail on the missing ones instead.
+    let error = decode_frames(&apng, FrameLimit::NONE).err().unwrap();
+    assert_eq!(
+        error.downcast_ref::<DecodeError>(),
+        Some(&DecodeError::TooLarge)
+    );
+}
+
+#[test]
+fn truncated_and_corrupt_inputs_are_salvaged() {
+    let jpeg = fixture("landscape.jpg");
+    let png = fixture("portrait-alpha.png");
+    let mut corrupt = jpeg.clone();
+    for i in (jpeg.len() / 2..jpeg.len() * 2 / 3).step_by(7) {
+        corrupt[i] ^= 0x5A;
+    }
+    for input in [&jpeg[..jpeg.len() / 2], &corrupt, &png[..png.len() / 2]] {
+        let whole = decode_image(if input.starts_with(b"\x89PNG") {
+            &png
+        } else {
+            &jpeg
+        })
+        .unwrap();
+        let (image, salvaged) = salvage_image(input).unwrap();
+        assert!(salvaged);
+        assert_eq!(image.width(), whole.width());
+        assert_eq!(image.height(), whole.height());
+    }
+
+    let (_, salvaged) = salvage_image(&jpeg).unwrap();
+    assert!(!salvaged);
+    // Nothing is left of an input cut inside its header.
+    let error = salvage_image(&jpeg[..20]).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<DecodeError>(),
+        Some(&DecodeError::Truncated)
+    );
+}
+
+#[tokio::test]
+async fn compress_salvages_truncated_inputs_on_request() {
+    let jpeg = fixture("landscape.jpg");
+    let request = post(
+        "/compress?salvage=true",
+        &[],
+        jpeg[..jpeg.len() / 2].to_vec(),
+    );
+    let response = send(&app(Config::default()), request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-salvaged"], "true");
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let output = decode_image(&body).unwrap();
+    assert_eq!((output.width(), output.height()), (160, 96));
+}
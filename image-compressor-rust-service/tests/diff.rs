@@ -0,0 +1,104 @@
+// image-compressor-rust-service/tests/diff.rs
+
+//! Visual diffs with `/diff`.
+
+mod common;
+
+use axum::http::StatusCode;
+use base64::Engine;
+use common::{app, fixture, json_body, post, send};
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::diff::compare;
+use serde_json::{json, Value};
+use std::io::Cursor;
+
+fn gray(width: u32, height: u32) -> RgbImage {
+    RgbImage::from_pixel(width, height, Rgb([128, 128, 128]))
+}
+
+fn png(image: RgbImage) -> String {
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(image)
+        .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+        .unwrap();
+    base64::engine::general_purpose::STANDARD.encode(png)
+}
+
+async fn diff(body: Value) -> (StatusCode, Value) {
+    let headers = [("Content-Type", "application/json")];
+    let request = post("/diff", &headers, body.to_string());
+    let response = send(&app(Config::default()), request).await;
+    (response.status(), json_body(response).await)
+}
+
+#[test]
+fn differences_are_measured_and_drawn() {
+    let baseline = DynamicImage::ImageRgb8(gray(10, 10));
+    let (report, _) = compare(&baseline, &baseline, 0).unwrap();
+    assert_eq!(report.differing_pixels, 0);
+    assert_eq!(report.psnr, None);
+
+    let mut changed = gray(10, 10);
+    changed.put_pixel(3, 4, Rgb([255, 128, 128]));
+    changed.put_pixel(5, 5, Rgb([130, 128, 128]));
+    let changed = DynamicImage::ImageRgb8(changed);
+    let (report, heatmap) = compare(&baseline, &changed, 0).unwrap();
+    assert_eq!(report.differing_pixels, 2);
+    assert_eq!(report.max_delta, 127);
+    assert!((report.mismatch_ratio - 0.02).abs() < 1e-9);
+    assert!(report.psnr.unwrap() > 20.0);
+    assert_eq!(heatmap.get_pixel(3, 4).0, [255, 128, 0, 255]);
+    assert_eq!(heatmap.get_pixel(0, 0).0[0], heatmap.get_pixel(0, 0).0[2]);
+
+    // Small deltas fall under the threshold.
+    let (report, _) = compare(&baseline, &changed, 2).unwrap();
+    assert_eq!(report.differing_pixels, 1);
+
+    assert!(compare(&baseline, &DynamicImage::ImageRgb8(gray(10, 9)), 0).is_err());
+}
+
+#[tokio::test]
+async fn diff_returns_metrics_and_a_heatmap() {
+    let mut changed = gray(8, 8);
+    changed.put_pixel(0, 0, Rgb([0, 0, 0]));
+    let (status, body) = diff(json!({
+        "baseline": png(gray(8, 8)),
+        "candidate": png(changed),
+    }))
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["differing_pixels"], 1);
+    assert_eq!(body["width"], 8);
+
+    let heatmap = body["diff"]
+        .as_str()
+        .unwrap()
+        .strip_prefix("data:image/png;base64,")
+        .unwrap();
+    let heatmap = base64::engine::general_purpose::STANDARD
+        .decode(heatmap)
+        .unwrap();
+    let heatmap = image::load_from_memory(&heatmap).unwrap().to_rgba8();
+    assert_eq!(heatmap.dimensions(), (8, 8));
+    assert_eq!(heatmap.get_pixel(0, 0).0, [255, 127, 0, 255]);
+}
+
+#[tokio::test]
+async fn diff_rejects_bad_inputs() {
+    let (status, body) = diff(json!({
+        "baseline": png(gray(8, 8)),
+        "candidate": png(gray(8, 4)),
+    }))
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["details"]["candidate"], json!([8, 4]));
+
+    let (status, _) = diff(json!({ "baseline": "not base64!", "candidate": "" })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let text = base64::engine::general_purpose::STANDARD.encode("plain text");
+    let jpeg = base64::engine::general_purpose::STANDARD.encode(fixture("landscape.jpg"));
+    let (status, _) = diff(json!({ "baseline": jpeg, "candidate": text })).await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
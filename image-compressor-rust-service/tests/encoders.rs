@@ -0,0 +1,150 @@
+// image-compressor-rust-service/tests/encoders.rs
+
+//! Per-format encoder settings and the in-tree JPEG encoder.
+
+mod common;
+
+use common::{fixture, ssim};
+use image::{ColorType, DynamicImage, GrayImage, Luma};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::encode::{
+    encode_jpeg, encode_jpeg_with, Canary, JpegBackend, JpegTuning,
+};
+use image_compressor_rust_service::jpeg::{self, QuantTables};
+use image_compressor_rust_service::quality::estimate_jpeg_quality;
+use image_compressor_rust_service::{compress_image_with, decode_image, CompressionOptions};
+
+#[test]
+fn jpeg_outputs_use_the_configured_quantization_tables() {
+    let image = decode_image(&fixture("landscape.jpg")).unwrap();
+
+    // With the standard tables the in-tree encoder matches the image crate's.
+    let ours = jpeg::encode(&image, &QuantTables::Standard.scaled(75)).unwrap();
+    let theirs = encode_jpeg(&image, 75).unwrap();
+    assert_eq!(estimate_jpeg_quality(&ours), Some(75));
+    let (ours, theirs) = (decode_image(&ours).unwrap(), decode_image(&theirs).unwrap());
+    assert!(ssim(&ours, &theirs) > 0.99);
+
+    // Flat tables keep the sharp edges of line art, for more bytes.
+    let lines = DynamicImage::ImageLuma8(GrayImage::from_fn(64, 64, |x, y| {
+        Luma([if (x + 2 * y) % 7 == 0 { 0 } else { 255 }])
+    }));
+    let flat = JpegTuning {
+        tables: QuantTables::Flat,
+        ..JpegTuning::default()
+    };
+    let image = JpegBackend::Image;
+    let standard = encode_jpeg_with(&lines, 75, &JpegTuning::default(), image, None).unwrap();
+    let flat = encode_jpeg_with(&lines, 75, &flat, image, None).unwrap();
+    assert!(flat.len() > standard.len());
+    let score = |data: &[u8]| ssim(&lines, &decode_image(data).unwrap());
+    assert!(score(&flat) > score(&standard));
+
+    // Greyscale images get a single component.
+    let gray = decode_image(&fixture("gray.jpg")).unwrap();
+    let encoded = jpeg::encode(&gray, &QuantTables::Flat.scaled(90)).unwrap();
+    let decoded = decode_image(&encoded).unwrap();
+    assert_eq!(decoded.color(), ColorType::L8);
+    assert!(ssim(&gray, &decoded) > 0.95);
+}
+
+#[test]
+fn encoder_settings_are_read_and_validated() {
+    let config: Config = toml::from_str(
+        "[encoders.jpeg]\ntables = \"flat\"\n[encoders.webp]\nmethod = 6\nsegments = 2",
+    )
+    .unwrap();
+    config.validate().unwrap();
+    assert_eq!(config.encoders.jpeg.tables, QuantTables::Flat);
+    assert_eq!(config.encoders.webp.segments, Some(2));
+
+    let out_of_range: Config = toml::from_str("[encoders.webp]\nsegments = 5").unwrap();
+    let error = format!("{:#}", out_of_range.validate().unwrap_err());
+    assert!(error.contains("segments must be 1-4"), "{}", error);
+    assert!("mozjpeg".parse::<QuantTables>().is_err());
+}
+
+/// The entries of each DQT table of a JPEG, in the order they are stored.
+fn stored_tables(jpeg: &[u8]) -> Vec<Vec<u8>> {
+    jpeg.windows(2)
+        .enumerate()
+        .filter(|(_, marker)| marker == &[0xFF, 0xDB])
+        .map(|(at, _)| jpeg[at + 5..at + 5 + 64].to_vec())
+        .collect()
+}
+
+#[test]
+fn presets_can_bring_their_own_quantization_tables() {
+    let luma = format!("[{}]", ["3"; 64].join(", "));
+    let chroma = format!("[{}]", ["40"; 64].join(", "));
+    let config: Config = toml::from_str(&format!(
+        "[presets.scan]\nquality = 50\nquant_tables = {{ luma = {}, chroma = {} }}",
+        luma, chroma
+    ))
+    .unwrap();
+    config.validate().unwrap();
+
+    let options = config.preset("scan").unwrap().options();
+    let input = fixture("landscape.jpg");
+    let compressed = compress_image_with(&input, &options, &config).unwrap();
+    assert_eq!(stored_tables(&compressed.data), [vec![3; 64], vec![40; 64]]);
+    decode_image(&compressed.data).unwrap();
+
+    // Other qualities scale them.
+    let mut options = options;
+    options.quality = 75;
+    let compressed = compress_image_with(&input, &options, &config).unwrap();
+    assert_eq!(stored_tables(&compressed.data), [vec![2; 64], vec![20; 64]]);
+
+    let short: Config =
+        toml::from_str("[presets.scan]\nquant_tables = { luma = [1, 2, 3] }").unwrap();
+    let error = format!("{:#}", short.validate().unwrap_err());
+    assert!(error.contains("luma needs 64 entries, got 3"), "{}", error);
+}
+
+#[test]
+fn a_canary_backend_encodes_its_share_of_the_inputs() {
+    let config: Config =
+        toml::from_str("[encoders.jpeg.canary]\nbackend = \"builtin\"\npercent = 30").unwrap();
+    config.validate().unwrap();
+    let tuning = &config.encoders.jpeg;
+    let inputs: Vec<Vec<u8>> = (0..1000u32).map(|n| n.to_be_bytes().to_vec()).collect();
+    let canaries = inputs
+        .iter()
+        .filter(|input| tuning.backend_for(input, false) == JpegBackend::Builtin)
+        .count();
+    assert!((250..350).contains(&canaries), "{}", canaries);
+    // The same input always takes the same backend; deterministic outputs
+    // never take the canary.
+    assert!(inputs
+        .iter()
+        .all(|input| tuning.backend_for(input, false) == tuning.backend_for(input, false)));
+    assert!(inputs
+        .iter()
+        .all(|input| tuning.backend_for(input, true) == JpegBackend::Image));
+
+    // Canaried outputs come from the in-tree encoder.
+    let everything = Config {
+        encoders: toml::from_str("[jpeg.canary]\nbackend = \"builtin\"\npercent = 100").unwrap(),
+        ..Config::default()
+    };
+    let input = fixture("landscape.jpg");
+    let options = CompressionOptions::default();
+    let canaried = compress_image_with(&input, &options, &everything).unwrap();
+    let primary = compress_image_with(&input, &options, &Config::default()).unwrap();
+    assert_ne!(canaried.data, primary.data);
+    let decoded = decode_image(&canaried.data).unwrap();
+    assert!(ssim(&decoded, &decode_image(&primary.data).unwrap()) > 0.99);
+
+    let same: Config =
+        toml::from_str("[encoders.jpeg.canary]\nbackend = \"image\"\npercent = 5").unwrap();
+    let error = format!("{:#}", same.validate().unwrap_err());
+    assert!(error.contains("already the backend"), "{}", error);
+    assert_eq!(
+        "builtin:5".parse::<Canary>().unwrap(),
+        Canary {
+            backend: JpegBackend::Builtin,
+            percent: 5
+        }
+    );
+}
@@ -0,0 +1,136 @@
+// image-compressor-rust-service/tests/error_reporting.rs
+
+//! Sentry error reporting. The reporting tests need `--features sentry`.
+
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::server::error_reporting::ErrorReportingConfig;
+
+const TEST_DSN: &str = "https://public@sentry.example.com/1";
+
+fn with_dsn() -> ErrorReportingConfig {
+    ErrorReportingConfig {
+        sentry_dsn: Some(TEST_DSN.to_string()),
+        ..ErrorReportingConfig::default()
+    }
+}
+
+#[test]
+fn reporting_is_off_by_default() {
+    let config = Config::default();
+    config.validate().unwrap();
+    let _reporter = config.error_reporting.init().unwrap();
+}
+
+#[test]
+fn out_of_range_sample_rates_are_rejected() {
+    let config = Config {
+        error_reporting: ErrorReportingConfig {
+            sample_rate: 1.5,
+            ..with_dsn()
+        },
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[cfg(not(feature = "sentry"))]
+#[test]
+fn a_dsn_needs_the_sentry_feature() {
+    let config = Config {
+        error_reporting: with_dsn(),
+        ..Config::default()
+    };
+    let error = config.validate().unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("`sentry` feature"),
+        "{:#}",
+        error
+    );
+}
+
+#[cfg(feature = "sentry")]
+mod reporting {
+    use super::*;
+    use image_compressor_rust_service::formats::InputFormat;
+    use image_compressor_rust_service::server::error_reporting::{
+        client_options, report, scrub, ErrorContext,
+    };
+    use sentry::protocol::{Event, Request};
+    use sentry::{Client, Envelope, Hub, Scope, Transport};
+    use std::sync::{Arc, Mutex};
+
+    /// Keeps sent events instead of sending them.
+    #[derive(Default)]
+    struct Captured(Mutex<Vec<Event<'static>>>);
+
+    impl Transport for Captured {
+        fn send_envelope(&self, envelope: Envelope) {
+            if let Some(event) = envelope.event() {
+                self.0.lock().unwrap().push(event.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_dsns_are_rejected() {
+        let config = Config {
+            error_reporting: ErrorReportingConfig {
+                sentry_dsn: Some("not a dsn".to_string()),
+                ..ErrorReportingConfig::default()
+            },
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn reports_carry_the_request_context() {
+        let transport = Arc::new(Captured::default());
+        let mut options = client_options(&with_dsn()).unwrap();
+        options.transport = Some(Arc::new(transport.clone()));
+        let hub = Arc::new(Hub::new(
+            Some(Arc::new(Client::from(options))),
+            Arc::new(Scope::default()),
+        ));
+
+        Hub::run(hub, || {
+            report(
+                "panic",
+                "Compression task panicked: boom",
+                &ErrorContext {
+                    request_id: "req-1",
+                    input_format: InputFormat::Png,
+                    input_bytes: 1234,
+                },
+            )
+        });
+
+        let events = transport.0.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(
+            event.message.as_deref(),
+            Some("Compression task panicked: boom")
+        );
+        assert_eq!(event.tags["kind"], "panic");
+        assert_eq!(event.tags["request_id"], "req-1");
+        assert_eq!(event.tags["input_format"], "png");
+        assert_eq!(event.extra["input_bytes"], 1234);
+        assert!(event.request.is_none());
+    }
+
+    #[test]
+    fn scrubbing_drops_request_data() {
+        let event = Event {
+            message: Some("x".repeat(100_000)),
+            request: Some(Request {
+                data: Some("\u{89}PNG...".to_string()),
+                ..Request::default()
+            }),
+            ..Event::default()
+        };
+        let event = scrub(event).unwrap();
+        assert!(event.request.is_none());
+        assert!(event.message.unwrap().len() <= 1024);
+    }
+}
@@ -0,0 +1,217 @@
+// image-compressor-rust-service/tests/favicon.rs
+
+//! Favicon and app icon bundles from `/favicon` and `/icons`.
+
+mod common;
+
+use axum::body::to_bytes;
+use axum::http::{header, StatusCode};
+use common::{app, fixture, post, send};
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::favicon::{bundle, Icon, IconSet, Manifest, Purpose};
+use image_compressor_rust_service::options::Color;
+use std::collections::BTreeMap;
+
+/// The entries of a stored ZIP, read from its local headers.
+fn unzip(archive: &[u8]) -> BTreeMap<String, Vec<u8>> {
+    let u16_at = |at: usize| usize::from(u16::from_le_bytes([archive[at], archive[at + 1]]));
+    let u32_at = |at: usize| u32::from_le_bytes(archive[at..at + 4].try_into().unwrap());
+    let mut entries = BTreeMap::new();
+    let mut at = 0;
+    while archive[at..].starts_with(b"PK\x03\x04") {
+        assert_eq!(u16_at(at + 8), 0, "entries are stored");
+        let size = u32_at(at + 18) as usize;
+        let (name_len, extra_len) = (u16_at(at + 26), u16_at(at + 28));
+        let name = String::from_utf8(archive[at + 30..at + 30 + name_len].to_vec()).unwrap();
+        let start = at + 30 + name_len + extra_len;
+        let data = archive[start..start + size].to_vec();
+        assert_eq!(crc32fast::hash(&data), u32_at(at + 14), "{}", name);
+        entries.insert(name, data);
+        at = start + size;
+    }
+    assert!(archive[at..].starts_with(b"PK\x01\x02"));
+    assert!(archive.ends_with(&[0, 0]));
+    entries
+}
+
+#[test]
+fn bundles_hold_every_icon_size() {
+    let source = image::load_from_memory(&fixture("landscape.jpg")).unwrap();
+    let set = IconSet::builtin("favicon").unwrap();
+    let files = unzip(&bundle(&source, &set, None).unwrap());
+    assert_eq!(files.len(), 6);
+    assert!(!files.contains_key("site.webmanifest"));
+
+    for (name, size) in [
+        ("favicon-16x16.png", 16),
+        ("favicon-32x32.png", 32),
+        ("apple-touch-icon.png", 180),
+        ("android-chrome-192x192.png", 192),
+        ("android-chrome-512x512.png", 512),
+    ] {
+        let icon = image::load_from_memory_with_format(&files[name], ImageFormat::Png).unwrap();
+        assert_eq!(icon.dimensions(), (size, size), "{}", name);
+    }
+
+    // The ICO directory lists 16, 32 and 48 pixel PNG frames.
+    let ico = &files["favicon.ico"];
+    assert_eq!(&ico[..6], &[0, 0, 1, 0, 3, 0]);
+    let sizes: Vec<u8> = (0..3).map(|i| ico[6 + 16 * i]).collect();
+    assert_eq!(sizes, [16, 32, 48]);
+    let largest = image::load_from_memory_with_format(ico, ImageFormat::Ico).unwrap();
+    assert_eq!(largest.dimensions(), (48, 48));
+
+    // The wide source is letterboxed, not cropped.
+    let icon = image::load_from_memory(&files["favicon-32x32.png"])
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(icon.get_pixel(16, 0).0[3], 0);
+    assert_eq!(icon.get_pixel(16, 16).0[3], 255);
+}
+
+#[test]
+fn manifests_list_the_app_icons() {
+    let source = DynamicImage::new_rgba8(64, 64);
+    let manifest = Manifest {
+        name: "Storage".to_string(),
+        theme_color: Color::parse("#1a1a40"),
+    };
+    let set = IconSet::builtin("favicon").unwrap();
+    let archive = bundle(&source, &set, Some(&manifest)).unwrap();
+    assert_eq!(archive, bundle(&source, &set, Some(&manifest)).unwrap());
+
+    let files = unzip(&archive);
+    let manifest: serde_json::Value = serde_json::from_slice(&files["site.webmanifest"]).unwrap();
+    assert_eq!(manifest["name"], "Storage");
+    assert_eq!(manifest["theme_color"], "#1a1a40");
+    let icons = manifest["icons"].as_array().unwrap();
+    assert_eq!(icons.len(), 2);
+    assert_eq!(icons[1]["src"], "/android-chrome-512x512.png");
+    assert_eq!(icons[1]["sizes"], "512x512");
+    assert_eq!(icons[1]["purpose"], "any");
+}
+
+#[test]
+fn pwa_sets_pad_maskable_icons_into_the_safe_zone() {
+    // An opaque square filling the whole source.
+    let source = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+        100,
+        100,
+        image::Rgba([200, 0, 0, 255]),
+    ));
+    let manifest = Manifest {
+        name: "Storage".to_string(),
+        theme_color: None,
+    };
+    let set = IconSet::builtin("pwa").unwrap();
+    set.validate().unwrap();
+    let files = unzip(&bundle(&source, &set, Some(&manifest)).unwrap());
+    for size in [48, 72, 96, 128, 144, 152, 192, 384, 512] {
+        assert!(files.contains_key(&format!("icon-{0}x{0}.png", size)));
+    }
+
+    let maskable = image::load_from_memory(&files["maskable-icon-512x512.png"])
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(maskable.dimensions(), (512, 512));
+    // 10% of white on each side, then the image.
+    assert_eq!(maskable.get_pixel(40, 256).0, [255, 255, 255, 255]);
+    assert_eq!(maskable.get_pixel(56, 256).0, [200, 0, 0, 255]);
+    let plain = image::load_from_memory(&files["icon-512x512.png"])
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(plain.get_pixel(2, 256).0, [200, 0, 0, 255]);
+
+    let manifest: serde_json::Value = serde_json::from_slice(&files["site.webmanifest"]).unwrap();
+    let purposes: Vec<_> = manifest["icons"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|icon| icon["purpose"].as_str().unwrap())
+        .collect();
+    assert_eq!(purposes.len(), 11);
+    assert_eq!(purposes.iter().filter(|&&p| p == "maskable").count(), 2);
+}
+
+#[test]
+fn apple_icons_have_no_transparency() {
+    let source = DynamicImage::new_rgba8(10, 10);
+    let set = IconSet::builtin("apple").unwrap();
+    let files = unzip(&bundle(&source, &set, None).unwrap());
+    assert!(!files.contains_key("favicon.ico"));
+    let icon = image::load_from_memory(&files["apple-touch-icon-167x167.png"])
+        .unwrap()
+        .to_rgba8();
+    assert!(icon.pixels().all(|p| p.0 == [255, 255, 255, 255]));
+}
+
+#[test]
+fn configured_sets_are_validated() {
+    let icon = |name: &str, sizes: &[u32]| Icon {
+        name: name.to_string(),
+        sizes: sizes.to_vec(),
+        padding: 0,
+        background: None,
+        purpose: Some(Purpose::Any),
+    };
+    let set = |icons| IconSet {
+        icons,
+        ico_sizes: Vec::new(),
+    };
+    set(vec![icon("logo-{size}.png", &[64, 128])])
+        .validate()
+        .unwrap();
+    // Two sizes with the same file name.
+    assert!(set(vec![icon("logo.png", &[64, 128])]).validate().is_err());
+    assert!(set(vec![icon("../logo-{size}.png", &[64])])
+        .validate()
+        .is_err());
+    assert!(set(vec![icon("logo-{size}.png", &[4096])])
+        .validate()
+        .is_err());
+    assert!(set(Vec::new()).validate().is_err());
+
+    let config: Config = toml::from_str(
+        r#"
+        [icon_sets.store]
+        ico_sizes = [16]
+        [[icon_sets.store.icons]]
+        name = "store-{size}.png"
+        sizes = [64]
+        padding = 20
+        background = "black"
+        purpose = "maskable"
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    assert_eq!(config.icon_set("store").unwrap().icons[0].padding, 20);
+    assert!(config.icon_set("pwa").is_some());
+}
+
+#[tokio::test]
+async fn the_endpoints_return_zips() {
+    let app = app(Config::default());
+    let headers = [("X-Manifest-Name", "Storage"), ("Accept-Encoding", "gzip")];
+    let request = post("/favicon", &headers, fixture("portrait-alpha.png"));
+    let response = send(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/zip");
+    assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert!(unzip(&body).contains_key("site.webmanifest"));
+
+    let request = post("/icons/apple", &[], fixture("landscape.jpg"));
+    let response = send(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(unzip(&body).len(), 5);
+
+    let request = post("/icons/nope", &[], fixture("landscape.jpg"));
+    let response = send(&app, request).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = send(&app, post("/favicon", &[], "plain text")).await;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
@@ -0,0 +1,128 @@
+// image-compressor-rust-service/tests/filename.rs
+
+//! Output filename and path templates and `Content-Disposition` values.
+
+use image_compressor_rust_service::filename::{
+    content_disposition, percent_decode, render_path, sanitize, FilenameConfig, FilenameVars,
+    PathVars, MAX_LEN,
+};
+use image_compressor_rust_service::formats::OutputFormat;
+use image_compressor_rust_service::metadata::CaptureDate;
+use std::path::PathBuf;
+
+fn vars(original: Option<&str>) -> FilenameVars<'_> {
+    FilenameVars {
+        original,
+        width: 800,
+        height: 600,
+        format: OutputFormat::Jpeg,
+    }
+}
+
+#[test]
+fn templates_are_filled_in() {
+    let config = FilenameConfig::default();
+    assert_eq!(
+        config.render(None, &vars(Some("holiday.png"))),
+        "holiday.jpg"
+    );
+    assert_eq!(
+        config.render(
+            Some("{name}-{width}x{height}.{format}"),
+            &vars(Some("holiday.png"))
+        ),
+        "holiday-800x600.jpeg"
+    );
+    assert_eq!(config.render(None, &vars(None)), "image.jpg");
+}
+
+#[test]
+fn placeholders_in_the_original_name_stay_literal() {
+    let config = FilenameConfig::default();
+    assert_eq!(
+        config.render(None, &vars(Some("{width}.png"))),
+        "{width}.jpg"
+    );
+}
+
+#[test]
+fn caller_names_cannot_carry_paths_or_header_syntax() {
+    assert_eq!(sanitize("../../etc/passwd").as_deref(), Some("passwd"));
+    assert_eq!(
+        sanitize("C:\\Users\\me\\cat.png").as_deref(),
+        Some("cat.png")
+    );
+    assert_eq!(sanitize("a\"b\r\nc.png").as_deref(), Some("abc.png"));
+    assert_eq!(sanitize("..").as_deref(), None);
+    assert_eq!(sanitize(" .hidden ").as_deref(), Some("hidden"));
+    assert!(sanitize(&"é".repeat(MAX_LEN)).unwrap().len() <= MAX_LEN);
+
+    let config = FilenameConfig::default();
+    assert_eq!(
+        config.render(Some("../{name}.{ext}"), &vars(Some("/tmp/x.png"))),
+        "x.jpg"
+    );
+}
+
+#[test]
+fn non_ascii_names_get_an_encoded_form() {
+    assert_eq!(
+        content_disposition(true, "cat.jpg"),
+        "attachment; filename=\"cat.jpg\""
+    );
+    assert_eq!(
+        content_disposition(false, "château.jpg"),
+        "inline; filename=\"ch_teau.jpg\"; filename*=UTF-8''ch%C3%A2teau.jpg"
+    );
+    assert_eq!(percent_decode("ch%C3%A2teau.png"), "château.png");
+    assert_eq!(percent_decode("100%"), "100%");
+}
+
+fn photo<'a>(date: Option<CaptureDate>, camera: Option<&'a str>) -> PathVars<'a> {
+    PathVars {
+        name: "IMG_0042",
+        width: 800,
+        height: 600,
+        format: OutputFormat::Jpeg,
+        date,
+        camera,
+    }
+}
+
+#[test]
+fn path_templates_use_the_capture_date_and_camera() {
+    let date = CaptureDate::parse("2024:05:01 12:00:00");
+    assert_eq!(
+        date,
+        Some(CaptureDate {
+            year: 2024,
+            month: 5,
+            day: 1
+        })
+    );
+    let vars = photo(date, Some("EOS R5/Mark II"));
+    assert_eq!(
+        render_path("{yyyy}/{mm}/{dd}/{camera}/{name}.{ext}", &vars),
+        Some(PathBuf::from("2024/05/01/EOS R5-Mark II/IMG_0042.jpg"))
+    );
+
+    let undated = photo(None, None);
+    assert_eq!(
+        render_path("{yyyy}/{camera}-{name}.jpg", &undated),
+        Some(PathBuf::from("unknown/unknown-IMG_0042.jpg"))
+    );
+    // Cameras without a clock write zeros.
+    assert_eq!(CaptureDate::parse("0000:00:00 00:00:00"), None);
+    assert_eq!(CaptureDate::parse("    :  :     :  :  "), None);
+}
+
+#[test]
+fn path_templates_stay_inside_the_output_directory() {
+    let vars = photo(None, Some(".."));
+    assert_eq!(
+        render_path("/../{camera}/./{name}.{ext}", &vars),
+        Some(PathBuf::from("IMG_0042.jpg"))
+    );
+    assert_eq!(render_path("{yyyy}/", &vars), None);
+    assert_eq!(render_path("..", &vars), None);
+}
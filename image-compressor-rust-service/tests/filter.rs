@@ -0,0 +1,73 @@
+// image-compressor-rust-service/tests/filter.rs
+
+//! Colour filters: duotone, tint and sepia.
+
+use image::{DynamicImage, GrayImage, Luma, Rgba, RgbaImage};
+use image_compressor_rust_service::filter::apply;
+use image_compressor_rust_service::{Color, Filter};
+
+fn pixel(image: DynamicImage, x: u32) -> [u8; 4] {
+    image.to_rgba8().get_pixel(x, 0).0
+}
+
+#[test]
+fn duotone_maps_black_and_white_onto_its_colours() {
+    let ramp = DynamicImage::ImageLuma8(GrayImage::from_fn(2, 1, |x, _| Luma([(x * 255) as u8])));
+    let filter = Filter::parse("duotone:#1a1a40,#ffd000").unwrap();
+    let duotone = apply(ramp, filter);
+    assert!(!duotone.color().has_alpha());
+    assert_eq!(pixel(duotone.clone(), 0), [0x1a, 0x1a, 0x40, 255]);
+    assert_eq!(pixel(duotone, 1), [0xff, 0xd0, 0x00, 255]);
+}
+
+#[test]
+fn tint_strength_comes_from_the_colour_alpha() {
+    let gray = || DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([200, 200, 200, 90])));
+    assert_eq!(
+        pixel(apply(gray(), Filter::parse("tint:#ff000000").unwrap()), 0),
+        [200, 200, 200, 90]
+    );
+    // Full strength multiplies; alpha is left alone.
+    assert_eq!(
+        pixel(apply(gray(), Filter::parse("tint:#ff8000").unwrap()), 0),
+        [200, 100, 0, 90]
+    );
+    assert_eq!(
+        pixel(apply(gray(), Filter::parse("tint:#ff800080").unwrap()), 0),
+        [200, 150, 100, 90]
+    );
+}
+
+#[test]
+fn sepia_warms_grays() {
+    let gray = || DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([100, 100, 100, 255])));
+    let [r, g, b, _] = pixel(apply(gray(), Filter::Sepia { amount: 100 }), 0);
+    assert!(r > g && g > b, "{:?}", [r, g, b]);
+    assert_eq!(
+        pixel(apply(gray(), Filter::Sepia { amount: 0 }), 0),
+        [100, 100, 100, 255]
+    );
+}
+
+#[test]
+fn filters_parse() {
+    assert_eq!(Filter::parse("Sepia"), Some(Filter::Sepia { amount: 100 }));
+    assert_eq!(
+        Filter::parse("sepia:0.25"),
+        Some(Filter::Sepia { amount: 25 })
+    );
+    assert_eq!(
+        Filter::parse("tint:#00ff00"),
+        Some(Filter::Tint(Color([0, 255, 0, 255])))
+    );
+    assert_eq!(
+        Filter::parse("duotone: black, white"),
+        Some(Filter::Duotone {
+            shadow: Color::BLACK,
+            highlight: Color::WHITE,
+        })
+    );
+    assert_eq!(Filter::parse("sepia:2"), None);
+    assert_eq!(Filter::parse("duotone:#000000"), None);
+    assert_eq!(Filter::parse("vintage"), None);
+}
@@ -0,0 +1,37 @@
+// image-compressor-rust-service/tests/fuzz_regressions.rs
+
+//! Replays the inputs in `fuzz/regressions/compress` that once crashed the
+//! `compress` fuzz target. Each must keep failing with an error.
+
+use image_compressor_rust_service::{compress_image, AnimationMode, CompressionOptions};
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn fuzz_regressions_fail_cleanly() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/regressions/compress");
+    let mut replayed = 0;
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let data = fs::read(&path).unwrap();
+        // As in the fuzz target, the first byte picks the options.
+        let (&selector, input) = data.split_first().unwrap();
+        let options = CompressionOptions {
+            quality: selector % 100 + 1,
+            quality_explicit: true,
+            animation: if selector & 0x80 == 0 {
+                AnimationMode::FirstFrame
+            } else {
+                AnimationMode::Animate
+            },
+            ..CompressionOptions::default()
+        };
+        assert!(
+            compress_image(input, &options).is_err(),
+            "{}",
+            path.display()
+        );
+        replayed += 1;
+    }
+    assert!(replayed > 0);
+}
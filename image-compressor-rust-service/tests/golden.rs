@@ -0,0 +1,308 @@
+// image-compressor-rust-service/tests/golden.rs
+
+//! Golden-image tests: every fixture in `tests/fixtures` is run through the
+//! pipeline and the output characteristics are checked against the
+//! expectations below. A codec or pipeline change that alters output format,
+//! dimensions, size or visual fidelity beyond these bounds fails here.
+
+mod common;
+
+use common::{fixture, ssim};
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, ImageFormat};
+use image_compressor_rust_service::encode;
+use image_compressor_rust_service::formats::OutputFormat;
+use image_compressor_rust_service::quality::perceptual_to_native;
+use image_compressor_rust_service::{
+    compress_image, decode_image, AnimationMode, CompressionOptions, Filter,
+};
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+
+struct Golden {
+    fixture: &'static str,
+    options: CompressionOptions,
+    /// Lossless image the output is compared with; defaults to the decoded fixture.
+    reference: Option<&'static str>,
+    content_type: &'static str,
+    dimensions: (u32, u32),
+    max_bytes: usize,
+    min_ssim: f64,
+    /// Number of frames for animated outputs.
+    frames: Option<usize>,
+}
+
+impl Golden {
+    fn still(
+        fixture: &'static str,
+        dimensions: (u32, u32),
+        max_bytes: usize,
+        min_ssim: f64,
+    ) -> Self {
+        Self {
+            fixture,
+            options: CompressionOptions::default(),
+            reference: None,
+            content_type: "image/jpeg",
+            dimensions,
+            max_bytes,
+            min_ssim,
+            frames: None,
+        }
+    }
+
+    fn reference(mut self, reference: &'static str) -> Self {
+        self.reference = Some(reference);
+        self
+    }
+
+    /// Runs the fixture through `filter`. The SSIM floor still compares
+    /// with the unfiltered source, so it checks that the luma structure
+    /// survives.
+    fn filter(mut self, filter: &str) -> Self {
+        self.options.filter = Some(Filter::parse(filter).expect("valid filter"));
+        self
+    }
+}
+
+fn goldens() -> Vec<Golden> {
+    vec![
+        Golden::still("landscape.jpg", (160, 96), 3_200, 0.97),
+        Golden::still("portrait-alpha.png", (72, 120), 2_400, 0.97),
+        Golden::still("gray.jpg", (160, 96), 1_500, 0.97),
+        Golden::still("lossy.webp", (160, 96), 3_300, 0.97),
+        Golden::still("srgb-icc.jpg", (160, 96), 3_200, 0.97),
+        Golden::still("exif.jpg", (160, 96), 3_400, 0.97),
+        Golden::still("interlaced.png", (45, 37), 1_300, 0.97)
+            .reference("interlaced-reference.png"),
+        Golden::still("cmyk-adobe.jpg", (160, 96), 3_100, 0.97).reference("cmyk-reference.png"),
+        Golden::still("cmyk-plain.jpg", (160, 96), 3_100, 0.97).reference("cmyk-reference.png"),
+        Golden::still("animated.png", (64, 64), 1_700, 0.97),
+        Golden::still("landscape.jpg", (160, 96), 3_200, 0.93).filter("sepia"),
+        Golden::still("landscape.jpg", (160, 96), 3_200, 0.93).filter("tint:#ff880080"),
+        Golden::still("landscape.jpg", (160, 96), 3_200, 0.93).filter("duotone:#1a1a40,#ffd000"),
+        Golden::still("gray.jpg", (160, 96), 3_200, 0.93).filter("duotone:#1a1a40,#ffd000"),
+        Golden::still("portrait-alpha.png", (72, 120), 2_400, 0.96).filter("sepia:0.5"),
+        Golden {
+            fixture: "animated.png",
+            options: CompressionOptions {
+                animation: AnimationMode::Animate,
+                ..CompressionOptions::default()
+            },
+            reference: None,
+            content_type: "image/webp",
+            dimensions: (64, 64),
+            max_bytes: 1_300,
+            min_ssim: 0.96,
+            frames: Some(3),
+        },
+    ]
+}
+
+fn check(golden: &Golden) -> Result<(), String> {
+    let input = fixture(golden.fixture);
+    let output = compress_image(&input, &golden.options).map_err(|e| format!("{:#}", e))?;
+
+    if output.content_type != golden.content_type {
+        return Err(format!(
+            "content type {} != {}",
+            output.content_type, golden.content_type
+        ));
+    }
+    if output.data.len() > golden.max_bytes {
+        return Err(format!(
+            "{} bytes > max {}",
+            output.data.len(),
+            golden.max_bytes
+        ));
+    }
+
+    if let Some(expected_frames) = golden.frames {
+        let frames = WebPDecoder::new(Cursor::new(&output.data))
+            .and_then(|d| d.into_frames().collect_frames())
+            .map_err(|e| format!("output is not an animated WebP: {}", e))?;
+        if frames.len() != expected_frames {
+            return Err(format!("{} frames != {}", frames.len(), expected_frames));
+        }
+    }
+
+    let decoded =
+        image::load_from_memory(&output.data).map_err(|e| format!("undecodable output: {}", e))?;
+    let expected_format =
+        ImageFormat::from_mime_type(golden.content_type).expect("known MIME type");
+    if image::guess_format(&output.data).ok() != Some(expected_format) {
+        return Err(format!("output bytes are not {:?}", expected_format));
+    }
+    if (decoded.width(), decoded.height()) != golden.dimensions {
+        return Err(format!(
+            "dimensions {}x{} != {}x{}",
+            decoded.width(),
+            decoded.height(),
+            golden.dimensions.0,
+            golden.dimensions.1
+        ));
+    }
+
+    let reference = match golden.reference {
+        Some(name) => image::load_from_memory(&fixture(name)).expect("reference decodes"),
+        None => decode_image(&input).expect("fixture decodes"),
+    };
+    let score = ssim(&reference, &decoded);
+    if score < golden.min_ssim {
+        return Err(format!("SSIM {:.4} < floor {:.4}", score, golden.min_ssim));
+    }
+    Ok(())
+}
+
+#[test]
+fn golden_outputs_match_expectations() {
+    let failures: Vec<String> = goldens()
+        .iter()
+        .filter_map(|golden| {
+            check(golden).err().map(|e| {
+                format!(
+                    "{} ({:?}, {:?}): {}",
+                    golden.fixture, golden.options.animation, golden.options.filter, e
+                )
+            })
+        })
+        .collect();
+
+    assert!(
+        failures.is_empty(),
+        "golden mismatches:\n{}",
+        failures.join("\n")
+    );
+}
+
+/// The same perceptual quality should look about the same whichever encoder
+/// runs; this guards the calibration curves in `quality.rs`.
+#[test]
+fn perceptual_quality_is_comparable_across_formats() {
+    let sources = ["landscape.jpg", "portrait-alpha.png", "srgb-icc.jpg"];
+    for quality in [50u8, 80, 95] {
+        let (mut jpeg_total, mut webp_total) = (0.0, 0.0);
+        for name in sources {
+            let source = decode_image(&fixture(name)).unwrap();
+            let rgb = source.to_rgb8();
+
+            let jpeg = encode::encode_jpeg(
+                &image::DynamicImage::ImageRgb8(rgb.clone()),
+                perceptual_to_native(OutputFormat::Jpeg, quality),
+            )
+            .unwrap();
+            let native = perceptual_to_native(OutputFormat::Webp, quality);
+            let webp = webp::Encoder::from_rgb(rgb.as_raw(), rgb.width(), rgb.height())
+                .encode(f32::from(native))
+                .to_vec();
+
+            let reference = image::DynamicImage::ImageRgb8(rgb);
+            jpeg_total += ssim(&reference, &image::load_from_memory(&jpeg).unwrap());
+            webp_total += ssim(&reference, &image::load_from_memory(&webp).unwrap());
+        }
+        let (jpeg, webp) = (
+            jpeg_total / sources.len() as f64,
+            webp_total / sources.len() as f64,
+        );
+        assert!(
+            (jpeg - webp).abs() < 0.02,
+            "quality {}: jpeg SSIM {:.4} vs webp SSIM {:.4}",
+            quality,
+            jpeg,
+            webp
+        );
+    }
+}
+
+/// SHA-256 of deterministic JPEG outputs. A change here alters the bytes
+/// caches have stored, so it must come with a bump of
+/// `DETERMINISTIC_OUTPUT_VERSION`.
+const DETERMINISTIC_JPEG_HASHES: &[(&str, &str)] = &[
+    (
+        "landscape.jpg",
+        "d7dcf7b8086197daddf2b10db26da6b5cd1da575f03120d039914cbae5600900",
+    ),
+    (
+        "portrait-alpha.png",
+        "91e035b479e9f381093dbab3118704a6e78375c62f8b0c77c87e1a0e81fb75ad",
+    ),
+    (
+        "exif.jpg",
+        "8c6710adb0d72ec84b546e5a5d660cfc640f66cd7df6779d7b7afa956209c9d6",
+    ),
+];
+
+/// SHA-256 of deterministic `landscape.jpg` outputs per filter, pinned
+/// like [`DETERMINISTIC_JPEG_HASHES`] so filter changes are deliberate.
+const DETERMINISTIC_FILTER_HASHES: &[(&str, &str)] = &[
+    (
+        "sepia",
+        "4b97f1664dcffecfcebcb30e9142a33cf8847cc9e1e3b3851726b48c100a7fd3",
+    ),
+    (
+        "tint:#ff880080",
+        "e6e27b025c57d76de1587ed5241b297e91f3d549e7b6be3889369b8f76cf82c8",
+    ),
+    (
+        "duotone:#1a1a40,#ffd000",
+        "fafe1ddaad98ba39ba99fa8208be44019fe9962e865acca31f107e6c12849994",
+    ),
+];
+
+fn deterministic(options: CompressionOptions) -> CompressionOptions {
+    CompressionOptions {
+        deterministic: true,
+        ..options
+    }
+}
+
+#[test]
+fn deterministic_jpeg_outputs_are_pinned() {
+    for (name, expected) in DETERMINISTIC_JPEG_HASHES {
+        let output = compress_image(
+            &fixture(name),
+            &deterministic(CompressionOptions::default()),
+        )
+        .unwrap();
+        let hash = format!("{:x}", Sha256::digest(&output.data));
+        assert_eq!(
+            &hash, expected,
+            "{}: deterministic output changed; bump DETERMINISTIC_OUTPUT_VERSION",
+            name
+        );
+    }
+}
+
+#[test]
+fn deterministic_filter_outputs_are_pinned() {
+    for (filter, expected) in DETERMINISTIC_FILTER_HASHES {
+        let options = CompressionOptions {
+            filter: Filter::parse(filter),
+            ..deterministic(CompressionOptions::default())
+        };
+        let output = compress_image(&fixture("landscape.jpg"), &options).unwrap();
+        let hash = format!("{:x}", Sha256::digest(&output.data));
+        assert_eq!(
+            &hash, expected,
+            "{}: deterministic output changed; bump DETERMINISTIC_OUTPUT_VERSION",
+            filter
+        );
+    }
+}
+
+#[test]
+fn deterministic_outputs_are_reproducible() {
+    let animate = CompressionOptions {
+        animation: AnimationMode::Animate,
+        ..CompressionOptions::default()
+    };
+    for (name, options) in [
+        ("exif.jpg", CompressionOptions::default()),
+        ("animated.png", animate),
+    ] {
+        let options = deterministic(options);
+        let first = compress_image(&fixture(name), &options).unwrap().data;
+        let second = compress_image(&fixture(name), &options).unwrap().data;
+        assert!(first == second, "{}: outputs differ between runs", name);
+    }
+}
@@ -0,0 +1,135 @@
+// image-compressor-rust-service/tests/hooks.rs
+
+//! Webhooks called before and after `/compress` requests.
+
+mod common;
+
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{Request, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use common::{fixture, json_body, send};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::server::hooks::{HookConfig, HooksConfig};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Rejects the `blocked` tenant and caps everyone else's quality at 40.
+async fn before(Json(request): Json<Value>) -> Json<Value> {
+    if request["tenant"] == "blocked" {
+        Json(json!({ "allow": false, "reason": "over quota" }))
+    } else {
+        Json(json!({ "options": { "quality": 40 } }))
+    }
+}
+
+async fn after(State(sender): State<mpsc::UnboundedSender<Value>>, Json(record): Json<Value>) {
+    sender.send(record).unwrap();
+}
+
+/// Serves the hooks, returning their base URL.
+async fn spawn_hooks(sender: mpsc::UnboundedSender<Value>) -> String {
+    let hooks = Router::new()
+        .route("/before", post(before))
+        .route("/after", post(after))
+        .with_state(sender);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, hooks).await });
+    format!("http://{}", addr)
+}
+
+fn app(hooks: HooksConfig) -> Router {
+    common::app(Config {
+        hooks,
+        ..Config::default()
+    })
+}
+
+fn compress(tenant: &str) -> Request<Body> {
+    common::post(
+        "/compress",
+        &[("X-Api-Key", tenant)],
+        fixture("landscape.jpg"),
+    )
+}
+
+fn hook(url: String) -> Option<HookConfig> {
+    Some(HookConfig {
+        url,
+        ..HookConfig::default()
+    })
+}
+
+#[tokio::test]
+async fn hooks_can_veto_and_change_requests() {
+    let (sender, mut received) = mpsc::unbounded_channel();
+    let base = spawn_hooks(sender).await;
+    let app = app(HooksConfig {
+        before: hook(format!("{}/before", base)),
+        after: hook(format!("{}/after", base)),
+    });
+
+    let response = send(&app, compress("blocked")).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let error = json_body(response).await;
+    assert_eq!(error["error"]["code"], "rejected_by_hook");
+    assert_eq!(error["error"]["details"]["reason"], "over quota");
+
+    let response = send(&app, compress("tenant-a")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut records = Vec::new();
+    for _ in 0..2 {
+        let record = tokio::time::timeout(Duration::from_secs(10), received.recv())
+            .await
+            .expect("the after hook is called")
+            .unwrap();
+        records.push(record);
+    }
+    records.sort_by_key(|record| record["status"].as_u64());
+    assert_eq!(records[0]["outcome"], "success");
+    assert_eq!(records[0]["options"]["quality"], 40);
+    assert_eq!(records[1]["error_code"], "rejected_by_hook");
+}
+
+#[tokio::test]
+async fn unreachable_hooks_follow_the_failure_policy() {
+    // Nothing listens on a port that was just released.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/before", listener.local_addr().unwrap());
+    drop(listener);
+
+    let closed = app(HooksConfig {
+        before: hook(url.clone()),
+        after: None,
+    });
+    let response = send(&closed, compress("tenant-a")).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let open = app(HooksConfig {
+        before: Some(HookConfig {
+            url,
+            fail_open: true,
+            ..HookConfig::default()
+        }),
+        after: None,
+    });
+    let response = send(&open, compress("tenant-a")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[test]
+fn hook_settings_are_validated() {
+    let config: Config = toml::from_str("[hooks.after]\nurl = \"not a url\"").unwrap();
+    let error = format!("{:#}", config.validate().unwrap_err());
+    assert!(error.contains("Invalid after hook"), "{}", error);
+
+    let config: Config =
+        toml::from_str("[hooks.before]\nurl = \"http://policy.internal/check\"\ntimeout_ms = 0")
+            .unwrap();
+    let error = format!("{:#}", config.validate().unwrap_err());
+    assert!(error.contains("timeout_ms must be at least 1"), "{}", error);
+}
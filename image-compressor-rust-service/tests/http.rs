@@ -0,0 +1,961 @@
+// image-compressor-rust-service/tests/http.rs
+
+//! Router-level behaviour: middleware and response headers.
+
+mod common;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Request, StatusCode};
+use common::{app, fixture, get, json_body, post, send, state};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::formats::InputFormat;
+use image_compressor_rust_service::options::CompressionOptions;
+use image_compressor_rust_service::server;
+use image_compressor_rust_service::server::cors::CorsConfig;
+use image_compressor_rust_service::server::metrics_endpoint::{BasicAuth, MetricsBackend};
+use image_compressor_rust_service::server::options::QUERY_OPTIONS;
+use image_compressor_rust_service::warmup;
+use metrics::{Key, Label};
+use tower::ServiceExt;
+
+fn compress_request(body: Vec<u8>) -> Request<Body> {
+    Request::post("/compress")
+        .header(header::ACCEPT_ENCODING, "br, gzip")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn json_errors_are_compressed_when_accepted() {
+    let response = send(
+        &app(Config::default()),
+        compress_request(b"definitely not an image".to_vec()),
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(response.headers()[header::CONTENT_ENCODING], "br");
+}
+
+#[tokio::test]
+async fn image_responses_are_never_recompressed() {
+    let response = send(
+        &app(Config::default()),
+        compress_request(fixture("landscape.jpg")),
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/jpeg");
+    assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+}
+
+#[tokio::test]
+async fn response_compression_can_be_disabled() {
+    let config = Config {
+        compress_responses: false,
+        ..Config::default()
+    };
+    let response = send(
+        &app(config),
+        compress_request(b"definitely not an image".to_vec()),
+    )
+    .await;
+
+    assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+}
+
+fn preflight(origin: &str) -> Request<Body> {
+    Request::options("/compress")
+        .header(header::ORIGIN, origin)
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .header(
+            header::ACCESS_CONTROL_REQUEST_HEADERS,
+            "x-compression-quality",
+        )
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn cors_preflight_is_answered_for_allowed_origins() {
+    let mut config = Config::default();
+    config.cors.allowed_origins = vec!["https://app.example.com".to_string()];
+    let app = app(config);
+
+    let allowed = send(&app, preflight("https://app.example.com")).await;
+    assert_eq!(allowed.status(), StatusCode::OK);
+    assert_eq!(
+        allowed.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "https://app.example.com"
+    );
+    assert_eq!(allowed.headers()[header::ACCESS_CONTROL_MAX_AGE], "600");
+    let allowed_headers = allowed.headers()[header::ACCESS_CONTROL_ALLOW_HEADERS]
+        .to_str()
+        .unwrap();
+    assert!(
+        allowed_headers.split(',').any(|h| h.trim() == "x-api-key"),
+        "{}",
+        allowed_headers
+    );
+
+    let other = send(&app, preflight("https://evil.example")).await;
+    assert!(other
+        .headers()
+        .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        .is_none());
+}
+
+#[test]
+fn cors_allows_every_option_header_by_default() {
+    let cors = CorsConfig::default();
+    let headers = CompressionOptions::header_names()
+        .into_iter()
+        .chain(QUERY_OPTIONS.iter().map(|(_, header)| *header));
+    for header in headers {
+        assert!(
+            cors.allowed_headers.contains(&header.to_ascii_lowercase()),
+            "{} is not allowed",
+            header
+        );
+    }
+    for header in [
+        "x-request-id",
+        "server-timing",
+        "x-compression-skipped",
+        "tus-version",
+    ] {
+        assert!(
+            cors.exposed_headers.iter().any(|h| h == header),
+            "{} is not exposed",
+            header
+        );
+    }
+}
+
+#[tokio::test]
+async fn oversized_bodies_get_a_structured_413_with_the_applied_limit() {
+    let mut config = Config {
+        max_body_bytes: 1024,
+        ..Config::default()
+    };
+    config
+        .body_limits
+        .routes
+        .insert("/compress".to_string(), 2048);
+    config
+        .body_limits
+        .api_keys
+        .insert("big-tenant".to_string(), 1 << 20);
+    let app = app(config);
+    let input = fixture("landscape.jpg");
+    assert!(input.len() > 2048);
+
+    let response = send(
+        &app,
+        Request::post("/compress")
+            .header(header::CONTENT_LENGTH, input.len())
+            .body(Body::from(input.clone()))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let body = json_body(response).await;
+    assert_eq!(body["error"]["code"], "payload_too_large");
+    assert_eq!(body["error"]["details"]["limit_bytes"], 2048);
+    assert_eq!(body["error"]["details"]["scope"], "route");
+
+    // Without a Content-Length the limit is enforced while reading.
+    let chunks: Vec<Result<Vec<u8>, std::io::Error>> =
+        input.chunks(512).map(|c| Ok(c.to_vec())).collect();
+    let streamed = send(
+        &app,
+        Request::post("/compress")
+            .body(Body::from_stream(futures_util::stream::iter(chunks)))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(streamed.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let tenant = send(
+        &app,
+        Request::post("/compress")
+            .header("X-Api-Key", "big-tenant")
+            .body(Body::from(input))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(tenant.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn compress_checks_options_before_reading_an_endless_body() {
+    let endless = || {
+        let chunk = || Ok::<_, std::io::Error>(vec![0u8; 64 * 1024]);
+        Body::from_stream(futures_util::stream::repeat_with(chunk))
+    };
+    let compress = |config, fit| async move {
+        let request = Request::post("/compress")
+            .header("X-Fit", fit)
+            .body(endless())
+            .unwrap();
+        let response = app(config).oneshot(request);
+        tokio::time::timeout(std::time::Duration::from_secs(10), response)
+            .await
+            .expect("the body is never read to its end")
+            .unwrap()
+    };
+
+    let response = compress(Config::default(), "sideways").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        json_body(response).await["error"]["code"],
+        "invalid_options"
+    );
+
+    let config = Config {
+        max_body_bytes: 1 << 20,
+        ..Config::default()
+    };
+    let response = compress(config, "cover").await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn ready_only_after_warm_up() {
+    let config = Config::default();
+    let state = state(config);
+    let app = server::router(state.clone());
+    let ready = || Request::get("/ready").body(Body::empty()).unwrap();
+
+    let before = send(&app, ready()).await;
+    assert_eq!(before.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let timings = warmup::warm_up(&state.config).unwrap();
+    assert_eq!(timings.len(), state.config.allowed_input_formats.len() + 1);
+    state.mark_ready();
+
+    let after = send(&app, ready()).await;
+    assert_eq!(after.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn selftest_requires_a_token_and_reports_every_format() {
+    let selftest = |token: Option<&str>| {
+        let mut request = Request::get("/selftest");
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        request.body(Body::empty()).unwrap()
+    };
+
+    let disabled = send(&app(Config::default()), selftest(None)).await;
+    assert_eq!(disabled.status(), StatusCode::NOT_FOUND);
+
+    let mut config = Config::default();
+    config.selftest.token = Some("s3cret".to_string());
+    let app = app(config);
+
+    let wrong = send(&app, selftest(Some("guess"))).await;
+    assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+
+    let response = send(&app, selftest(Some("s3cret"))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = json_body(response).await;
+    assert_eq!(report["passed"], true);
+    let names: Vec<_> = report["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["name"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(names, ["compress:jpeg", "compress:png", "compress:webp"]);
+}
+
+#[tokio::test]
+async fn version_reports_build_metadata() {
+    let response = send(
+        &app(Config::default()),
+        Request::get("/version").body(Body::empty()).unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let version = json_body(response).await;
+    assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+    assert!(!version["git_commit"].as_str().unwrap().is_empty());
+    assert!(version["build_timestamp"].as_str().unwrap().ends_with('Z'));
+    assert_eq!(
+        version["input_formats"],
+        serde_json::json!(["jpeg", "png", "webp"])
+    );
+    assert_eq!(
+        version["output_formats"],
+        serde_json::json!(["jpeg", "webp", "png"])
+    );
+    assert_eq!(version["cpu"]["arch"], std::env::consts::ARCH);
+    assert!(["scalar", "avx2", "neon"].contains(&version["cpu"]["simd"].as_str().unwrap()));
+}
+
+#[tokio::test]
+async fn formats_lists_capabilities_and_runtime_allowlist() {
+    let config = Config {
+        allowed_input_formats: vec![InputFormat::Jpeg, InputFormat::Png],
+        ..Config::default()
+    };
+    let response = send(
+        &app(config),
+        Request::get("/formats").body(Body::empty()).unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let formats = json_body(response).await;
+    let input = formats["input"].as_array().unwrap();
+    assert_eq!(input.len(), 3);
+    let png = input.iter().find(|f| f["format"] == "png").unwrap();
+    assert_eq!(png["enabled"], true);
+    assert_eq!(png["capabilities"]["animation"], true);
+    let webp = input.iter().find(|f| f["format"] == "webp").unwrap();
+    assert_eq!(webp["enabled"], false);
+
+    let output = formats["output"].as_array().unwrap();
+    let webp = output.iter().find(|f| f["format"] == "webp").unwrap();
+    assert_eq!(webp["mime_type"], "image/webp");
+    assert_eq!(webp["capabilities"]["animation"], true);
+}
+
+#[tokio::test]
+async fn the_detected_input_format_is_reported_against_the_content_type() {
+    let config = Config {
+        allowed_input_formats: vec![InputFormat::Jpeg],
+        ..Config::default()
+    };
+    let cases = [
+        ("landscape.jpg", None, StatusCode::OK, "jpeg"),
+        (
+            "landscape.jpg",
+            Some("image/jpeg"),
+            StatusCode::OK,
+            "jpeg; matches-content-type=true",
+        ),
+        (
+            "portrait-alpha.png",
+            Some("image/jpeg; charset=binary"),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "png; matches-content-type=false",
+        ),
+        (
+            "portrait-alpha.png",
+            Some("application/octet-stream"),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "png",
+        ),
+    ];
+    for (name, content_type, status, expected) in cases {
+        let mut request = Request::post("/compress");
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        let response = send(
+            &app(config.clone()),
+            request.body(Body::from(fixture(name))).unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), status, "{}", name);
+        assert_eq!(response.headers()["x-input-format"], expected, "{}", name);
+    }
+
+    let request = Request::post("/compress")
+        .header(header::CONTENT_TYPE, "image/webp")
+        .body(Body::from("definitely not an image"))
+        .unwrap();
+    let response = send(&app(config.clone()), request).await;
+    assert_eq!(
+        response.headers()["x-input-format"],
+        "unknown; matches-content-type=false"
+    );
+
+    // Invalid options are rejected before the body is read.
+    let request = Request::post("/compress")
+        .header(header::CONTENT_TYPE, "image/jpeg")
+        .header("X-Fit", "sideways")
+        .body(Body::from(fixture("landscape.jpg")))
+        .unwrap();
+    let response = send(&app(config), request).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.headers().get("x-input-format").is_none());
+}
+
+#[tokio::test]
+async fn deterministic_responses_carry_the_output_version() {
+    let config = Config {
+        deterministic: true,
+        ..Config::default()
+    };
+    let response = send(&app(config), compress_request(fixture("landscape.jpg"))).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["x-output-version"],
+        image_compressor_rust_service::DETERMINISTIC_OUTPUT_VERSION.to_string()
+    );
+}
+
+#[tokio::test]
+async fn downloads_are_named_from_the_template() {
+    let request = |headers: &[(&str, &str)]| post("/compress", headers, fixture("landscape.jpg"));
+
+    let response = send(
+        &app(Config::default()),
+        request(&[
+            ("X-Download", "true"),
+            ("X-Filename", "uploads/Holiday%20photo.png"),
+            ("X-Width", "40"),
+            ("X-Filename-Template", "{name}-{width}w.{ext}"),
+        ]),
+    )
+    .await;
+    assert_eq!(
+        response.headers()[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"Holiday photo-40w.jpg\""
+    );
+
+    let response = send(&app(Config::default()), request(&[])).await;
+    assert!(response
+        .headers()
+        .get(header::CONTENT_DISPOSITION)
+        .is_none());
+}
+
+#[tokio::test]
+async fn server_timing_reports_every_stage_when_enabled() {
+    let config = Config {
+        server_timing: true,
+        ..Config::default()
+    };
+    let response = send(&app(config), compress_request(fixture("landscape.jpg"))).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let value = response.headers()["server-timing"].to_str().unwrap();
+    let stages: Vec<&str> = value
+        .split(", ")
+        .map(|entry| entry.split_once(";dur=").expect("name;dur=ms").0)
+        .collect();
+    assert_eq!(
+        stages,
+        [
+            "read",
+            "queue",
+            "decode",
+            "transform",
+            "encode",
+            "metadata",
+            "sign"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn server_timing_is_off_by_default() {
+    let response = send(
+        &app(Config::default()),
+        compress_request(fixture("landscape.jpg")),
+    )
+    .await;
+
+    assert!(response.headers().get("server-timing").is_none());
+}
+
+/// Collects everything the JSON log formatter writes.
+#[derive(Clone, Default)]
+struct LogBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn large_images_log_a_dedicated_warning_with_the_request_parameters() {
+    let logs = LogBuffer::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let mut config = Config::default();
+    config.slow_log.large_image_pixels = 1_000;
+    let request = Request::post("/compress")
+        .header("x-width", "80")
+        .body(Body::from(fixture("landscape.jpg")))
+        .unwrap();
+    let response = send(&app(config), request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    let warnings: Vec<serde_json::Value> = logs
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .filter(|event: &serde_json::Value| event["fields"]["event"].is_string())
+        .collect();
+    assert_eq!(warnings.len(), 1, "{}", logs);
+    let fields = &warnings[0]["fields"];
+    assert_eq!(warnings[0]["level"], "WARN");
+    assert_eq!(fields["event"], "large_image");
+    assert_eq!(fields["decoded_pixels"], 160 * 96);
+    assert_eq!(fields["width"], "Some(80)");
+    assert!(fields["stages"].as_str().unwrap().contains("decode="));
+}
+
+#[tokio::test]
+async fn metrics_move_off_the_public_listener_when_they_have_their_own() {
+    let mut config = Config::default();
+    config.metrics.bind_addr = Some("127.0.0.1:9100".parse().unwrap());
+    let state = state(config);
+
+    let public = send(&server::router(state.clone()), get("/metrics")).await;
+    assert_eq!(public.status(), StatusCode::NOT_FOUND);
+    let internal = send(&server::metrics_endpoint::router(state), get("/metrics")).await;
+    assert_eq!(internal.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn metrics_require_basic_auth_when_configured() {
+    let mut config = Config::default();
+    config.metrics.basic_auth = Some(BasicAuth {
+        username: "prometheus".to_string(),
+        password: "s3cret".to_string(),
+    });
+    let app = app(config);
+
+    let anonymous = send(&app, get("/metrics")).await;
+    assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        anonymous.headers()[header::WWW_AUTHENTICATE],
+        "Basic realm=\"metrics\""
+    );
+
+    // base64 of `prometheus:s3cret` and `prometheus:wrong`.
+    for (credentials, status) in [
+        ("cHJvbWV0aGV1czpzM2NyZXQ=", StatusCode::OK),
+        ("cHJvbWV0aGV1czp3cm9uZw==", StatusCode::UNAUTHORIZED),
+    ] {
+        let request = Request::get("/metrics")
+            .header(header::AUTHORIZATION, format!("Basic {}", credentials))
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), status);
+    }
+}
+
+#[test]
+fn metrics_carry_the_configured_prefix_labels_and_buckets() {
+    let mut config = Config::default();
+    config.metrics.prefix = Some("imgsvc".to_string());
+    config.metrics.global_labels = [("region".to_string(), "eu-west-1".to_string())].into();
+    config.metrics.buckets = vec![1.0, 10.0];
+    config.metrics.metric_buckets = [("queue_seconds".to_string(), vec![0.01, 0.1])].into();
+    let (recorder, handle) = config.metrics.recorder().unwrap();
+
+    recorder
+        .register_histogram(&Key::from_static_name("compress_queue_seconds"))
+        .record(0.05);
+    recorder
+        .register_histogram(&Key::from_static_name("compress_duration_seconds"))
+        .record(5.0);
+    let rendered = handle.render();
+
+    for line in [
+        r#"imgsvc_compress_queue_seconds_bucket{region="eu-west-1",le="0.1"} 1"#,
+        r#"imgsvc_compress_queue_seconds_bucket{region="eu-west-1",le="0.01"} 0"#,
+        r#"imgsvc_compress_duration_seconds_bucket{region="eu-west-1",le="10"} 1"#,
+    ] {
+        assert!(
+            rendered.contains(line),
+            "{} missing in:\n{}",
+            line,
+            rendered
+        );
+    }
+}
+
+#[test]
+fn empty_bucket_lists_are_rejected() {
+    let mut config = Config::default();
+    config.metrics.metric_buckets = [("queue_seconds".to_string(), Vec::new())].into();
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn the_statsd_backend_sends_prefixed_tagged_datagrams() {
+    let agent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    agent
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    let mut config = Config::default();
+    config.metrics.backend = MetricsBackend::Statsd;
+    config.metrics.statsd.addr = agent.local_addr().unwrap().to_string();
+    config.metrics.prefix = Some("imgsvc".to_string());
+    config.metrics.global_labels = [("region".to_string(), "eu-west-1".to_string())].into();
+    let (recorder, _) = config.metrics.recorder().unwrap();
+
+    let labels = vec![Label::new("priority", "high")];
+    recorder
+        .register_counter(&Key::from_static_name("compress_requests_total"))
+        .increment(1);
+    recorder
+        .register_histogram(&Key::from_parts("compress_queue_seconds", labels))
+        .record(0.25);
+    recorder
+        .register_gauge(&Key::from_static_name("compress_queued"))
+        .decrement(1.0);
+
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 512];
+    for _ in 0..3 {
+        let len = agent.recv(&mut buffer).unwrap();
+        received.push(String::from_utf8_lossy(&buffer[..len]).into_owned());
+    }
+    assert_eq!(
+        received,
+        [
+            "imgsvc.compress_requests_total:1|c|#region:eu-west-1",
+            "imgsvc.compress_queue_seconds:0.25|h|#region:eu-west-1,priority:high",
+            "imgsvc.compress_queued:-1|g|#region:eu-west-1",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn the_statsd_backend_has_no_metrics_endpoint() {
+    let mut config = Config::default();
+    config.metrics.backend = MetricsBackend::Statsd;
+    let response = send(&app(config), get("/metrics")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn images_are_only_upscaled_on_request() {
+    let compress = |headers: &'static [(&'static str, &'static str)]| async move {
+        let request = post("/compress", headers, fixture("landscape.jpg"));
+        let response = send(&app(Config::default()), request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let scaling = response.headers()["x-scaling"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let prevented = response.headers().contains_key("x-upscale-prevented");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let image = image::load_from_memory(&body).unwrap();
+        ((image.width(), image.height()), scaling, prevented)
+    };
+
+    // The fixture is 160x96.
+    assert_eq!(
+        compress(&[("X-Width", "320")]).await,
+        ((160, 96), "none".to_string(), true)
+    );
+    assert_eq!(
+        compress(&[("X-Width", "320"), ("X-Allow-Upscale", "true")]).await,
+        ((320, 192), "up".to_string(), false)
+    );
+    assert_eq!(
+        compress(&[("X-Width", "40")]).await,
+        ((40, 24), "down".to_string(), false)
+    );
+    assert_eq!(
+        compress(&[("X-Width", "320"), ("X-Height", "320"), ("X-Fit", "cover")]).await,
+        ((96, 96), "down".to_string(), true)
+    );
+}
+
+#[tokio::test]
+async fn aspect_ratios_are_cropped_to() {
+    let compress = |headers: &'static [(&'static str, &'static str)]| async move {
+        let request = post("/compress", headers, fixture("landscape.jpg"));
+        let response = send(&app(Config::default()), request).await;
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let image = image::load_from_memory(&body).unwrap();
+        (image.width(), image.height())
+    };
+
+    // The fixture is 160x96.
+    assert_eq!(compress(&[("X-Aspect", "1:1")]).await, (96, 96));
+    assert_eq!(
+        compress(&[("X-Aspect", "16:9"), ("X-Width", "80")]).await,
+        (80, 45)
+    );
+    assert_eq!(
+        compress(&[("X-Aspect", "9:16"), ("X-Height", "64")]).await,
+        (36, 64)
+    );
+
+    let response = send(
+        &app(Config::default()),
+        Request::post("/compress")
+            .header("X-Aspect", "sideways")
+            .body(Body::from(fixture("landscape.jpg")))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn padded_jpegs_are_letterboxed_onto_the_background() {
+    let compress = |background: Option<&'static str>| async move {
+        let mut request = Request::post("/compress")
+            .header("X-Width", "160")
+            .header("X-Height", "160")
+            .header("X-Fit", "pad");
+        if let Some(background) = background {
+            request = request.header("X-Background", background);
+        }
+        let response = send(
+            &app(Config::default()),
+            request.body(Body::from(fixture("landscape.jpg"))).unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        image::load_from_memory(&body).unwrap().to_rgb8()
+    };
+
+    // The 160x96 fixture leaves 32 rows of padding above and below.
+    let white = compress(None).await;
+    assert_eq!(white.dimensions(), (160, 160));
+    assert!(white.get_pixel(80, 4).0.iter().all(|&c| c > 245));
+
+    let blue = compress(Some("#0000ff")).await;
+    let [r, g, b] = blue.get_pixel(80, 156).0;
+    assert!(r < 10 && g < 10 && b > 245, "padding is {:?}", [r, g, b]);
+}
+
+#[tokio::test]
+async fn rounded_jpeg_corners_show_the_background() {
+    let compress = |headers: &'static [(&'static str, &'static str)]| async move {
+        let headers = [&[("X-Corner-Radius", "50%")], headers].concat();
+        let request = post("/compress", &headers, fixture("landscape.jpg"));
+        let response = send(&app(Config::default()), request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        image::load_from_memory(&body).unwrap().to_rgb8()
+    };
+
+    let white = compress(&[]).await;
+    assert!(white.get_pixel(1, 1).0.iter().all(|&c| c > 245));
+
+    let black = compress(&[("X-Background", "black"), ("X-Border-Width", "3")]).await;
+    assert!(black.get_pixel(1, 1).0.iter().all(|&c| c < 10));
+    // The border runs along the straight edges too.
+    assert!(black.get_pixel(80, 1).0.iter().all(|&c| c < 20));
+}
+
+#[tokio::test]
+async fn palette_requests_return_png() {
+    let request = Request::post("/compress")
+        .header("X-Palette-Colors", "16")
+        .header("X-Dither", "ordered")
+        .body(Body::from(fixture("landscape.jpg")))
+        .unwrap();
+    let response = send(&app(Config::default()), request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let image = image::load_from_memory(&body).unwrap().to_rgba8();
+    let colors: std::collections::HashSet<_> = image.pixels().collect();
+    assert!(colors.len() <= 16, "{} colours", colors.len());
+}
+
+#[tokio::test]
+async fn ui_serves_the_testing_page_unless_disabled() {
+    let get_ui = || Request::get("/ui").body(Body::empty()).unwrap();
+    let response = send(&app(Config::default()), get_ui()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()[header::CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    let page = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let page = String::from_utf8(page.to_vec()).unwrap();
+    assert!(page.contains("fetch('/compress'"));
+
+    let config = Config {
+        ui: false,
+        ..Config::default()
+    };
+    let response = send(&app(config), get_ui()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn options_can_be_sent_as_query_parameters() {
+    let compress = |uri: &'static str, width: Option<&'static str>| async move {
+        let mut request = Request::post(uri);
+        if let Some(width) = width {
+            request = request.header("X-Width", width);
+        }
+        send(
+            &app(Config::default()),
+            request.body(Body::from(fixture("landscape.jpg"))).unwrap(),
+        )
+        .await
+    };
+    let dimensions = |response: axum::response::Response| async move {
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let image = image::load_from_memory(&body).unwrap();
+        (image.width(), image.height())
+    };
+
+    let response = compress("/compress?width=40&height=40&fit=cover", None).await;
+    assert_eq!(dimensions(response).await, (40, 40));
+    // Query parameters override headers.
+    let response = compress("/compress?width=40", Some("80")).await;
+    assert_eq!(dimensions(response).await, (40, 24));
+
+    let response = compress("/compress?widht=40", None).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error = json_body(response).await;
+    assert_eq!(error["error"]["code"], "invalid_options");
+    assert_eq!(
+        error["error"]["details"]["fields"],
+        serde_json::json!([{ "field": "widht", "message": "unknown option" }])
+    );
+}
+
+#[tokio::test]
+async fn options_merge_presets_headers_query_and_json_with_field_errors() {
+    let compress = |uri: &'static str, headers: &'static [(&'static str, &'static str)], body| async move {
+        send(&app(Config::default()), post(uri, headers, body)).await
+    };
+    let dimensions = |response: axum::response::Response| async move {
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let image = image::load_from_memory(&body).unwrap();
+        (image.width(), image.height())
+    };
+
+    // The thumbnail preset covers 320x320, which headers and the query
+    // narrow down.
+    let response = compress(
+        "/compress",
+        &[("X-Preset", "thumbnail")],
+        fixture("landscape.jpg"),
+    )
+    .await;
+    assert_eq!(dimensions(response).await, (96, 96));
+    let response = compress(
+        "/compress?height=30",
+        &[
+            ("X-Preset", "thumbnail"),
+            ("X-Width", "40"),
+            ("X-Height", "20"),
+        ],
+        fixture("landscape.jpg"),
+    )
+    .await;
+    assert_eq!(dimensions(response).await, (40, 30));
+
+    let response = compress(
+        "/compress?fit=sideways",
+        &[
+            ("X-Preset", "poster"),
+            ("X-Width", "-3"),
+            ("X-Compression-Quality", "70"),
+        ],
+        fixture("landscape.jpg"),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error = json_body(response).await;
+    assert_eq!(error["error"]["code"], "invalid_options");
+    let fields: Vec<&str> = error["error"]["details"]["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| field["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["preset", "width", "fit"]);
+
+    let body = serde_json::json!({
+        "image_base64": "aGVsbG8=",
+        "options": { "fit": "sideways", "widht": 3, "quality": 0 },
+    });
+    let response = compress(
+        "/compress",
+        &[("Content-Type", "application/json")],
+        body.to_string().into_bytes(),
+    )
+    .await;
+    let error = json_body(response).await;
+    let fields: Vec<&str> = error["error"]["details"]["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| field["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["fit", "quality", "widht"]);
+}
+
+#[tokio::test]
+async fn out_of_range_and_contradictory_options_are_rejected_unless_lenient() {
+    let compress = |lenient_options, headers: &'static [(&'static str, &'static str)]| async move {
+        let config = Config {
+            lenient_options,
+            ..Config::default()
+        };
+        send(
+            &app(config),
+            post("/compress", headers, fixture("landscape.jpg")),
+        )
+        .await
+    };
+
+    let response = compress(
+        false,
+        &[
+            ("X-Compression-Quality", "150"),
+            ("X-Width", "9000"),
+            ("X-Height", "40"),
+            ("X-Aspect", "16:9"),
+            ("X-Dither", "ordered"),
+            ("X-Max-Bytes", "4000"),
+            ("X-Min-Height", "60"),
+        ],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error = json_body(response).await;
+    assert_eq!(
+        error["error"]["details"]["fields"],
+        serde_json::json!([
+            { "field": "quality", "message": "must be between 1 and 100, got 150" },
+            { "field": "dither", "message": "needs palette_colors" },
+            { "field": "width", "message": "exceeds the maximum of 8192 pixels" },
+            { "field": "aspect", "message": "cannot be combined with both width and height" },
+            { "field": "min_height", "message": "is larger than height" },
+        ])
+    );
+
+    let response = compress(
+        true,
+        &[
+            ("X-Compression-Quality", "150"),
+            ("X-Dither", "ordered"),
+            ("X-Fit", "sideways"),
+        ],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
@@ -0,0 +1,170 @@
+// image-compressor-rust-service/tests/idempotency.rs
+
+//! `Idempotency-Key` handling on `/compress`, exercised through the router.
+
+mod common;
+
+use axum::body::to_bytes;
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::Router;
+use bytes::Bytes;
+use common::{fixture, post, send};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::formats::OutputFormat;
+use image_compressor_rust_service::server::idempotency::{
+    Begin, IdempotencyCache, IdempotencyConfig, StoredResponse,
+};
+use image_compressor_rust_service::CompressionOptions;
+use std::sync::Arc;
+
+fn app() -> Router {
+    common::app(Config::default())
+}
+
+async fn compress(app: &Router, key: &str, quality: &str, body: Vec<u8>) -> Response {
+    compress_as(app, "tenant-a", key, quality, body).await
+}
+
+async fn compress_as(
+    app: &Router,
+    tenant: &str,
+    key: &str,
+    quality: &str,
+    body: Vec<u8>,
+) -> Response {
+    let headers = [
+        ("X-Api-Key", tenant),
+        ("Idempotency-Key", key),
+        ("X-Compression-Quality", quality),
+    ];
+    send(app, post("/compress", &headers, body)).await
+}
+
+#[tokio::test]
+async fn retry_with_same_key_replays_the_stored_result() {
+    let app = app();
+    let input = fixture("landscape.jpg");
+
+    let first = compress(&app, "order-42", "60", input.clone()).await;
+    assert_eq!(first.status(), StatusCode::OK);
+    assert!(first.headers().get("Idempotent-Replayed").is_none());
+    let first = to_bytes(first.into_body(), usize::MAX).await.unwrap();
+
+    let retry = compress(&app, "order-42", "60", input).await;
+    assert_eq!(retry.status(), StatusCode::OK);
+    assert_eq!(retry.headers()["Idempotent-Replayed"], "true");
+    let retry = to_bytes(retry.into_body(), usize::MAX).await.unwrap();
+
+    assert_eq!(first, retry);
+}
+
+#[tokio::test]
+async fn reusing_a_key_for_a_different_request_is_rejected() {
+    let app = app();
+    let input = fixture("landscape.jpg");
+
+    let first = compress(&app, "order-43", "60", input.clone()).await;
+    assert_eq!(first.status(), StatusCode::OK);
+
+    let other_options = compress(&app, "order-43", "90", input).await;
+    assert_eq!(other_options.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let other_body = compress(&app, "order-43", "60", fixture("gray.jpg")).await;
+    assert_eq!(other_body.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn failed_requests_release_their_key() {
+    let app = app();
+
+    let failed = compress(&app, "order-44", "60", b"\xFF\xD8\xFFtruncated".to_vec()).await;
+    assert_eq!(failed.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let retry = compress(&app, "order-44", "60", b"\xFF\xD8\xFFtruncated".to_vec()).await;
+    assert_eq!(retry.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = to_bytes(retry.into_body(), usize::MAX).await.unwrap();
+    assert!(String::from_utf8_lossy(&body).contains("unprocessable_image"));
+}
+
+#[tokio::test]
+async fn keys_are_scoped_to_the_tenant() {
+    let app = app();
+    let input = fixture("landscape.jpg");
+
+    let first = compress_as(&app, "tenant-a", "order-45", "60", input.clone()).await;
+    assert_eq!(first.status(), StatusCode::OK);
+
+    let other_tenant = compress_as(&app, "tenant-b", "order-45", "90", input.clone()).await;
+    assert_eq!(other_tenant.status(), StatusCode::OK);
+    assert!(other_tenant.headers().get("Idempotent-Replayed").is_none());
+
+    let same_request = compress_as(&app, "tenant-c", "order-45", "60", input).await;
+    assert_eq!(same_request.status(), StatusCode::OK);
+    assert!(same_request.headers().get("Idempotent-Replayed").is_none());
+}
+
+#[test]
+fn stored_results_are_bounded_in_bytes() {
+    let cache = Arc::new(IdempotencyCache::new(&IdempotencyConfig {
+        max_bytes: 250,
+        ..IdempotencyConfig::default()
+    }));
+    let stored = |len: usize| StoredResponse {
+        data: Bytes::from(vec![0; len]),
+        content_type: "image/jpeg",
+        format: OutputFormat::Jpeg,
+        dimensions: (1, 1),
+        source_dimensions: (1, 1),
+        placeholder: None,
+        salvaged: false,
+    };
+    let store = |key: &str, len: usize| match cache.begin(None, key, [0; 32]) {
+        Begin::New(reservation) => reservation.complete(stored(len)),
+        _ => panic!("{} is already known", key),
+    };
+
+    store("first", 100);
+    store("second", 100);
+    assert_eq!(cache.stored_bytes(), 200);
+
+    // Storing a third evicts the oldest.
+    store("third", 100);
+    assert_eq!(cache.stored_bytes(), 200);
+    assert!(matches!(cache.begin(None, "first", [0; 32]), Begin::New(_)));
+    assert!(matches!(
+        cache.begin(None, "second", [0; 32]),
+        Begin::Replay(_)
+    ));
+
+    // A result that could never fit is not stored.
+    store("huge", 300);
+    assert_eq!(cache.stored_bytes(), 200);
+    assert!(matches!(cache.begin(None, "huge", [0; 32]), Begin::New(_)));
+}
+
+#[test]
+fn fingerprints_hash_options_canonically() {
+    let options = |quality| CompressionOptions {
+        quality,
+        quality_explicit: true,
+        ..CompressionOptions::default()
+    };
+    let fingerprint = |quality| {
+        let options = serde_json::to_value(options(quality)).unwrap();
+        IdempotencyCache::fingerprint(b"body", &options)
+    };
+    assert_eq!(fingerprint(60), fingerprint(60));
+    assert_ne!(fingerprint(60), fingerprint(61));
+
+    // Object keys are hashed sorted, however the object was built.
+    let mut forward = serde_json::Map::new();
+    forward.insert("a".into(), 1.into());
+    forward.insert("b".into(), 2.into());
+    let mut backward = serde_json::Map::new();
+    backward.insert("b".into(), 2.into());
+    backward.insert("a".into(), 1.into());
+    assert_eq!(
+        IdempotencyCache::fingerprint(b"body", &forward.into()),
+        IdempotencyCache::fingerprint(b"body", &backward.into())
+    );
+}
@@ -0,0 +1,91 @@
+// image-compressor-rust-service/tests/json_body.rs
+
+//! The JSON mode of `/compress`: base64 images in and out.
+
+mod common;
+
+use axum::http::{header, StatusCode};
+use axum::response::Response;
+use base64::Engine;
+use common::{app, fixture, json_body, post, send};
+use image_compressor_rust_service::config::Config;
+use serde_json::{json, Value};
+
+fn base64(data: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(data)
+}
+
+async fn compress_json(config: Config, headers: &[(&str, &str)], body: Value) -> Response {
+    let headers = [&[("Content-Type", "application/json")], headers].concat();
+    send(&app(config), post("/compress", &headers, body.to_string())).await
+}
+
+#[tokio::test]
+async fn json_requests_get_base64_outputs() {
+    let input = fixture("landscape.jpg");
+    let response = compress_json(
+        Config::default(),
+        &[("X-Width", "40"), ("X-Compression-Quality", "90")],
+        json!({ "image_base64": base64(&input), "options": { "width": 80, "quality": 50 } }),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+    assert_eq!(response.headers()["x-scaling"], "down");
+    let output = json_body(response).await;
+    assert_eq!(output["content_type"], "image/jpeg");
+    assert!(output.get("data_uri").is_none());
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(output["image_base64"].as_str().unwrap())
+        .unwrap();
+    assert_eq!(output["bytes"], data.len());
+    // The JSON options win over the headers.
+    assert_eq!(image::load_from_memory(&data).unwrap().width(), 80);
+
+    let response = compress_json(
+        Config::default(),
+        &[],
+        json!({
+            "image_base64": format!("data:image/jpeg;base64,{}", base64(&input)),
+            "data_uri": true,
+        }),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let output = json_body(response).await;
+    assert!(output["data_uri"]
+        .as_str()
+        .unwrap()
+        .starts_with("data:image/jpeg;base64,"));
+}
+
+#[tokio::test]
+async fn malformed_json_requests_are_rejected() {
+    for body in [
+        json!({ "image_base64": "not base64!" }),
+        json!({ "image_base64": "data:image/png,raw" }),
+        json!({ "image": base64(&fixture("landscape.jpg")) }),
+        json!({ "image_base64": base64(&fixture("landscape.jpg")), "options": { "quality": 0 } }),
+    ] {
+        let response = compress_json(Config::default(), &[], body.clone()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", body);
+    }
+}
+
+#[tokio::test]
+async fn the_body_limit_allows_for_base64_but_not_a_larger_image() {
+    let input = fixture("landscape.jpg");
+    let body = json!({ "image_base64": base64(&input) });
+    let config = |max_body_bytes| Config {
+        max_body_bytes,
+        ..Config::default()
+    };
+
+    let response = compress_json(config(input.len()), &[], body.clone()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = compress_json(config(input.len() - 1), &[], body).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let error = json_body(response).await;
+    assert_eq!(error["error"]["details"]["limit_bytes"], input.len() - 1);
+}
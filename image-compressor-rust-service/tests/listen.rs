@@ -0,0 +1,39 @@
+// image-compressor-rust-service/tests/listen.rs
+
+//! Serving over a Unix domain socket.
+
+mod common;
+
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::server::listen::{self, Listener};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+#[tokio::test]
+async fn serves_http_over_a_unix_socket() {
+    let path = std::env::temp_dir().join(format!("image-compressor-{}.sock", std::process::id()));
+    // A leftover file from an earlier run must not prevent binding.
+    std::fs::write(&path, b"stale").unwrap();
+
+    let config = Config {
+        unix_socket: Some(path.clone()),
+        ..Config::default()
+    };
+    let listener = Listener::bind(&config).await.unwrap();
+    assert_eq!(listener.to_string(), format!("unix://{}", path.display()));
+    let http = config.http.clone();
+    let app = common::app(config);
+    tokio::spawn(async move { listen::serve(listener, app, &http).await });
+
+    let mut stream = UnixStream::connect(&path).await.unwrap();
+    stream
+        .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.contains(r#"{"status":"ok"}"#));
+}
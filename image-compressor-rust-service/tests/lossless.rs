@@ -0,0 +1,100 @@
+// image-compressor-rust-service/tests/lossless.rs
+
+//! Rotating and cropping JPEGs without decoding them.
+
+mod common;
+
+use common::{fixture, ssim};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::{
+    compress_image_with, decode_image, AspectRatio, CompressedImage, CompressionOptions, Gravity,
+    Rotation,
+};
+
+fn compress(input: &[u8], options: &CompressionOptions) -> CompressedImage {
+    compress_image_with(input, options, &Config::default()).unwrap()
+}
+
+/// Whether the input was decoded to pixels.
+fn decoded(compressed: &CompressedImage) -> bool {
+    compressed.timings.get("decode").is_some()
+}
+
+#[test]
+fn jpegs_are_rotated_without_generation_loss() {
+    let input = fixture("landscape.jpg");
+    let original = decode_image(&input).unwrap();
+    let quarter = CompressionOptions {
+        rotate: Some(Rotation::Quarter),
+        ..CompressionOptions::default()
+    };
+    let turned = compress(&input, &quarter);
+    // Nothing was decoded to pixels, but the input's are still counted.
+    assert!(!decoded(&turned));
+    assert_eq!(turned.decoded_pixels, 160 * 96);
+    assert_eq!(turned.dimensions, (96, 160));
+    assert!(ssim(&original.rotate90(), &decode_image(&turned.data).unwrap()) > 0.99);
+
+    // Four quarter turns give back the very same pixels.
+    let mut data = input.clone();
+    for _ in 0..4 {
+        data = compress(&data, &quarter).data;
+    }
+    assert_eq!(decode_image(&data).unwrap(), original);
+
+    let half = CompressionOptions {
+        rotate: Some(Rotation::Half),
+        ..CompressionOptions::default()
+    };
+    let gray = fixture("gray.jpg");
+    let turned = compress(&gray, &half);
+    assert!(!decoded(&turned));
+    let twice = compress(&turned.data, &half);
+    assert_eq!(
+        decode_image(&twice.data).unwrap(),
+        decode_image(&gray).unwrap()
+    );
+
+    // An explicit quality re-encodes.
+    let requantized = compress(&input, &quarter.with_quality(80));
+    assert!(decoded(&requantized));
+    assert_eq!(requantized.dimensions, (96, 160));
+}
+
+#[test]
+fn jpegs_are_cropped_on_mcu_boundaries_without_generation_loss() {
+    let input = fixture("gray.jpg");
+    let original = decode_image(&input).unwrap();
+    let square = CompressionOptions {
+        aspect: Some(AspectRatio {
+            width: 1,
+            height: 1,
+        }),
+        ..CompressionOptions::default()
+    };
+    // The centred square starts 32 pixels in, on a block boundary.
+    let cropped = compress(&input, &square);
+    assert!(!decoded(&cropped));
+    assert_eq!(
+        decode_image(&cropped.data).unwrap(),
+        original.crop_imm(32, 0, 96, 96)
+    );
+
+    // After a quarter turn, the square is cut out of the 96x160 image.
+    let turned = CompressionOptions {
+        rotate: Some(Rotation::Quarter),
+        ..square.clone()
+    };
+    let compressed = compress(&input, &turned);
+    assert!(!decoded(&compressed));
+    assert_eq!(compressed.dimensions, (96, 96));
+
+    // Off the block grid, the pixels are cropped instead.
+    let off_grid = CompressionOptions {
+        gravity: Gravity::Focal { x: 3300, y: 5000 },
+        ..square
+    };
+    let compressed = compress(&input, &off_grid);
+    assert!(decoded(&compressed));
+    assert_eq!(compressed.dimensions, (96, 96));
+}
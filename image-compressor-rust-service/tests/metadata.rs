@@ -0,0 +1,156 @@
+// image-compressor-rust-service/tests/metadata.rs
+
+use axum::http::{HeaderMap, HeaderValue};
+use image::{DynamicImage, ImageOutputFormat, RgbImage};
+use image_compressor_rust_service::metadata::{
+    embed, embed_xmp, find_xmp, text_tag, Exif, MetadataPolicy, BODY_SERIAL_NUMBER,
+    CAMERA_OWNER_NAME, COPYRIGHT, EXIF_IFD, GPS_IFD, LENS_SERIAL_NUMBER, MAKER_NOTE, ORIENTATION,
+};
+use image_compressor_rust_service::{compress_image, AnimationMode, CompressionOptions};
+use std::io::Cursor;
+
+const XMP: &[u8] = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"></x:xmpmeta>";
+
+fn options_with(pairs: &[(&'static str, &str)]) -> CompressionOptions {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+        headers.insert(*name, HeaderValue::from_str(value).unwrap());
+    }
+    CompressionOptions::from_headers(&headers)
+}
+
+fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(format!(
+        "{}/tests/fixtures/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    ))
+    .expect("fixture exists")
+}
+
+fn output_exif(policy: MetadataPolicy) -> Option<Exif> {
+    let options = CompressionOptions {
+        metadata: Some(policy),
+        ..CompressionOptions::default()
+    };
+    let output = compress_image(&fixture("exif.jpg"), &options).unwrap();
+    image::load_from_memory(&output.data).expect("output still decodes");
+    Exif::from_image(&output.data)
+}
+
+#[test]
+fn default_policy_keeps_orientation_and_copyright_only() {
+    let exif = output_exif(MetadataPolicy::default()).expect("EXIF is carried over");
+
+    assert!(exif.has_tag(ORIENTATION));
+    assert!(exif.has_tag(COPYRIGHT));
+    assert!(exif.has_tag(EXIF_IFD));
+    assert!(!exif.has_tag(GPS_IFD));
+    for tag in [
+        BODY_SERIAL_NUMBER,
+        LENS_SERIAL_NUMBER,
+        CAMERA_OWNER_NAME,
+        MAKER_NOTE,
+    ] {
+        assert!(!exif.has_tag(tag), "tag {:#06x} was not stripped", tag);
+    }
+    assert!(!exif.has_thumbnail());
+}
+
+#[test]
+fn permissive_policy_keeps_gps_serials_and_thumbnail() {
+    let policy = MetadataPolicy {
+        strip_gps: false,
+        strip_serial_numbers: false,
+        strip_thumbnail: false,
+        ..MetadataPolicy::default()
+    };
+    let exif = output_exif(policy).expect("EXIF is carried over");
+
+    assert!(exif.has_tag(GPS_IFD));
+    assert!(exif.has_tag(BODY_SERIAL_NUMBER));
+    assert!(exif.has_thumbnail());
+    // Maker notes hold offsets that do not survive relocation.
+    assert!(!exif.has_tag(MAKER_NOTE));
+
+    let reparsed = Exif::parse(&exif.to_tiff()).expect("serialized EXIF parses");
+    assert!(reparsed.has_tag(GPS_IFD) && reparsed.has_thumbnail());
+}
+
+#[test]
+fn keep_exif_false_strips_everything() {
+    let policy = MetadataPolicy {
+        keep_exif: false,
+        ..MetadataPolicy::default()
+    };
+    assert!(output_exif(policy).is_none());
+}
+
+#[test]
+fn requested_metadata_is_written_over_the_carried_over_block() {
+    let options = options_with(&[
+        ("x-metadata-copyright", "(c) Example Agency"),
+        (
+            "x-metadata-exif",
+            "Artist=Jane Doe; 0x010E=Harbour at dusk; Bogus=1",
+        ),
+        // base64 of `XMP`.
+        (
+            "x-metadata-xmp",
+            "PHg6eG1wbWV0YSB4bWxuczp4PSJhZG9iZTpuczptZXRhLyI+PC94OnhtcG1ldGE+",
+        ),
+    ]);
+    let output = compress_image(&fixture("exif.jpg"), &options).unwrap();
+    image::load_from_memory(&output.data).expect("output still decodes");
+
+    let exif = Exif::from_image(&output.data).expect("EXIF is written");
+    assert_eq!(exif.text(COPYRIGHT), Some("(c) Example Agency"));
+    assert_eq!(exif.text(text_tag("Artist").unwrap()), Some("Jane Doe"));
+    assert_eq!(exif.text(0x010E), Some("Harbour at dusk"));
+    assert!(exif.has_tag(ORIENTATION), "carried-over fields are kept");
+    assert_eq!(find_xmp(&output.data), Some(XMP));
+}
+
+#[test]
+fn requested_metadata_is_written_even_when_exif_is_stripped() {
+    let mut options = options_with(&[("x-metadata-copyright", "(c) Example Agency")]);
+    options.metadata = Some(MetadataPolicy {
+        keep_exif: false,
+        ..MetadataPolicy::default()
+    });
+    let output = compress_image(&fixture("exif.jpg"), &options).unwrap();
+
+    let exif = Exif::from_image(&output.data).expect("EXIF is written");
+    assert_eq!(exif.text(COPYRIGHT), Some("(c) Example Agency"));
+    assert!(!exif.has_tag(ORIENTATION));
+}
+
+#[test]
+fn metadata_round_trips_through_png_and_webp_containers() {
+    let mut exif = Exif::new();
+    exif.set_text(COPYRIGHT, "(c) Example Agency");
+    let tiff = exif.to_tiff();
+
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(RgbImage::new(8, 8))
+        .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+        .unwrap();
+    let webp = compress_image(
+        &fixture("animated.png"),
+        &CompressionOptions {
+            animation: AnimationMode::Animate,
+            ..CompressionOptions::default()
+        },
+    )
+    .unwrap()
+    .data;
+
+    for container in [png, webp] {
+        let output = embed(&container, &tiff).unwrap();
+        let output = embed_xmp(&output, XMP).unwrap();
+        image::load_from_memory(&output).expect("output still decodes");
+        let written = Exif::from_image(&output).expect("EXIF is found again");
+        assert_eq!(written.text(COPYRIGHT), Some("(c) Example Agency"));
+        assert_eq!(find_xmp(&output), Some(XMP));
+    }
+}
@@ -0,0 +1,150 @@
+// image-compressor-rust-service/tests/ocr.rs
+
+//! The OCR stage, with a shell script standing in for the recognizer.
+
+mod common;
+
+use common::fixture;
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::ocr::OcrConfig;
+use image_compressor_rust_service::preset::Preset;
+use image_compressor_rust_service::watch::{Originals, WatchOptions, Watcher};
+use image_compressor_rust_service::{compress_image_with, CompressionOptions};
+use std::fs;
+use std::time::{Duration, Instant};
+
+/// A recognizer that checks it was given a PNG and prints `script`'s output.
+fn recognizer(script: &str) -> OcrConfig {
+    OcrConfig {
+        enabled: true,
+        command: vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            format!(
+                "head -c 4 | grep -q PNG || exit 3; cat > /dev/null; {}",
+                script
+            ),
+        ],
+        ..OcrConfig::default()
+    }
+}
+
+fn document_options() -> CompressionOptions {
+    Preset {
+        ocr: Some(true),
+        ..Preset::default()
+    }
+    .options()
+}
+
+#[test]
+fn text_is_extracted_when_enabled() {
+    let config = Config {
+        ocr: recognizer("printf '  INVOICE 42\\n\\n'"),
+        ..Config::default()
+    };
+    let input = fixture("landscape.jpg");
+    let compressed = compress_image_with(&input, &document_options(), &config).unwrap();
+    assert_eq!(compressed.text.as_deref(), Some("INVOICE 42"));
+    assert!(compressed.timings.get("ocr").is_some());
+
+    // Neither the options nor the config alone run the recognizer.
+    let plain = compress_image_with(&input, &CompressionOptions::default(), &config).unwrap();
+    assert_eq!(plain.text, None);
+    let disabled = compress_image_with(&input, &document_options(), &Config::default()).unwrap();
+    assert_eq!(disabled.text, None);
+}
+
+#[test]
+fn long_text_is_truncated_at_a_character_boundary() {
+    let config = OcrConfig {
+        max_text_bytes: 5,
+        ..recognizer("printf 'caf\\303\\251 au lait'")
+    };
+    let image = image::load_from_memory(&fixture("landscape.jpg")).unwrap();
+    assert_eq!(config.recognize(&image).unwrap(), "café");
+}
+
+#[test]
+fn failures_and_timeouts_fail_the_compression() {
+    let input = fixture("landscape.jpg");
+    let failing = Config {
+        ocr: recognizer("echo 'no language data' >&2; exit 1"),
+        ..Config::default()
+    };
+    let error = compress_image_with(&input, &document_options(), &failing).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("no language data"),
+        "{:#}",
+        error
+    );
+
+    let slow = Config {
+        ocr: OcrConfig {
+            timeout_ms: 100,
+            ..recognizer("sleep 5")
+        },
+        ..Config::default()
+    };
+    let start = Instant::now();
+    let error = compress_image_with(&input, &document_options(), &slow).unwrap_err();
+    assert!(error.to_string().contains("timed out"), "{:#}", error);
+    assert!(start.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn presets_need_ocr_to_be_enabled() {
+    let mut config = Config::default();
+    config.presets.insert(
+        "documents".to_string(),
+        Preset {
+            ocr: Some(true),
+            ..Preset::default()
+        },
+    );
+    assert!(config.validate().is_err());
+    config.ocr = recognizer("true");
+    config.validate().unwrap();
+}
+
+#[test]
+fn watch_mode_writes_the_text_next_to_the_output() {
+    let root = std::env::temp_dir().join(format!("ocr-watch-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let (input, output) = (root.join("in"), root.join("out"));
+    fs::create_dir_all(&input).unwrap();
+    fs::write(input.join("scan.jpg"), fixture("landscape.jpg")).unwrap();
+
+    let mut config = Config {
+        ocr: recognizer("echo 'ROUTE TO: ACCOUNTING'"),
+        ..Config::default()
+    };
+    config.presets.insert(
+        "documents".to_string(),
+        Preset {
+            ocr: Some(true),
+            ..Preset::default()
+        },
+    );
+    let mut watcher = Watcher::new(
+        WatchOptions {
+            input,
+            output: output.clone(),
+            preset: Some("documents".to_string()),
+            template: None,
+            originals: Originals::Keep,
+            interval: Duration::from_millis(10),
+            settle: Duration::ZERO,
+            retries: 0,
+        },
+        config,
+    )
+    .unwrap();
+    watcher.scan(Instant::now());
+
+    assert!(output.join("scan.jpg").is_file());
+    assert_eq!(
+        fs::read_to_string(output.join("scan.txt")).unwrap(),
+        "ROUTE TO: ACCOUNTING"
+    );
+}
@@ -0,0 +1,226 @@
+// image-compressor-rust-service/tests/palette.rs
+
+//! Palette (PNG8) output, quantization and dithering.
+
+mod common;
+
+use common::fixture;
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+use image_compressor_rust_service::encode::encode_png8;
+use image_compressor_rust_service::palette::quantize;
+use image_compressor_rust_service::{compress_image, CompressionOptions, Dither, Effort, Palette};
+use std::collections::HashSet;
+
+/// Decodes a PNG, checking its header for an indexed colour type and
+/// returning its bit depth.
+fn indexed_depth(png: &[u8]) -> u8 {
+    assert_eq!(&png[1..4], b"PNG");
+    // IHDR: bit depth at offset 24, colour type at offset 25.
+    assert_eq!(png[25], 3, "not an indexed PNG");
+    png[24]
+}
+
+#[test]
+fn few_colours_are_kept_exactly() {
+    let image = RgbaImage::from_fn(10, 10, |x, y| match (x < 5, y < 5) {
+        (true, true) => Rgba([255, 0, 0, 255]),
+        (true, false) => Rgba([0, 255, 0, 255]),
+        (false, true) => Rgba([0, 0, 255, 128]),
+        (false, false) => Rgba([0, 0, 0, 0]),
+    });
+    let indexed = quantize(&image, Palette::new(256, Dither::FloydSteinberg));
+    assert_eq!(indexed.palette.len(), 4);
+
+    let png = encode_png8(&indexed, Effort::default()).unwrap();
+    assert_eq!(indexed_depth(&png), 2);
+    let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+    assert_eq!(decoded, image);
+}
+
+/// A horizontal grey ramp.
+fn ramp() -> RgbaImage {
+    let gray = GrayImage::from_fn(256, 32, |x, _| Luma([x as u8]));
+    image::DynamicImage::ImageLuma8(gray).to_rgba8()
+}
+
+/// Mean absolute difference between the 8x8 block averages of two grey
+/// images: how far the dithered tones drift from the original ones.
+fn tone_error(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    let block = |image: &RgbaImage, bx: u32, by: u32| {
+        let mut sum = 0.0;
+        for y in by * 8..by * 8 + 8 {
+            for x in bx * 8..bx * 8 + 8 {
+                sum += f64::from(image.get_pixel(x, y).0[0]);
+            }
+        }
+        sum / 64.0
+    };
+    let (bw, bh) = (a.width() / 8, a.height() / 8);
+    let mut error = 0.0;
+    for by in 0..bh {
+        for bx in 0..bw {
+            error += (block(a, bx, by) - block(b, bx, by)).abs();
+        }
+    }
+    error / f64::from(bw * bh)
+}
+
+#[test]
+fn dithering_preserves_tones_a_small_palette_cannot_hold() {
+    let source = ramp();
+    let render = |dither| {
+        let png = encode_png8(
+            &quantize(&source, Palette::new(4, dither)),
+            Effort::default(),
+        )
+        .unwrap();
+        assert!(indexed_depth(&png) <= 2);
+        image::load_from_memory(&png).unwrap().to_rgba8()
+    };
+    let flat = render(Dither::None);
+    let diffused = render(Dither::FloydSteinberg);
+    let ordered = render(Dither::Ordered);
+
+    let distinct: HashSet<_> = flat.pixels().collect();
+    assert!(distinct.len() <= 4);
+    let (flat, diffused, ordered) = (
+        tone_error(&source, &flat),
+        tone_error(&source, &diffused),
+        tone_error(&source, &ordered),
+    );
+    assert!(
+        diffused < flat / 2.0,
+        "diffused {} vs flat {}",
+        diffused,
+        flat
+    );
+    assert!(ordered < flat / 2.0, "ordered {} vs flat {}", ordered, flat);
+}
+
+#[test]
+fn photos_become_palette_pngs() {
+    let options = CompressionOptions {
+        palette: Some(Palette::new(64, Dither::Ordered)),
+        ..CompressionOptions::default()
+    };
+    let output = compress_image(&fixture("portrait-alpha.png"), &options).unwrap();
+    assert_eq!(output.content_type, "image/png");
+    assert_eq!(indexed_depth(&output.data), 8);
+    let decoded = image::load_from_memory(&output.data).unwrap().to_rgba8();
+    let colors: HashSet<_> = decoded.pixels().collect();
+    assert!(colors.len() <= 64, "{} colours", colors.len());
+    // Transparency survives quantization.
+    assert!(decoded.pixels().any(|p| p.0[3] == 0));
+}
+
+#[test]
+fn palette_sizes_and_dither_modes_parse() {
+    assert_eq!(Palette::new(1000, Dither::None).colors, 256);
+    assert_eq!(Palette::new(0, Dither::None).colors, 2);
+    assert_eq!(
+        Dither::parse("Floyd-Steinberg"),
+        Some(Dither::FloydSteinberg)
+    );
+    assert_eq!(Dither::parse("bayer"), Some(Dither::Ordered));
+    assert_eq!(Dither::parse("none"), Some(Dither::None));
+    assert_eq!(Dither::parse("random"), None);
+}
+
+/// A flat UI button: a two-tone fill and a white dot, with anti-aliased
+/// edges and a soft shadow on a transparent background.
+fn button() -> RgbaImage {
+    let (w, h, r) = (240.0f32, 96.0f32, 24.0f32);
+    // Coverage of a rounded rectangle inset by `inset`, smoothed over `soft`
+    // pixels.
+    let coverage = |x: f32, y: f32, inset: f32, soft: f32| {
+        let qx = (x - w / 2.0).abs() - (w / 2.0 - inset - r);
+        let qy = (y - h / 2.0).abs() - (h / 2.0 - inset - r);
+        let outside = qx.max(0.0).hypot(qy.max(0.0)) + qx.max(qy).min(0.0) - r;
+        (0.5 - outside / soft).clamp(0.0, 1.0)
+    };
+    RgbaImage::from_fn(w as u32, h as u32, |x, y| {
+        let (x, y) = (x as f32 + 0.5, y as f32 + 0.5);
+        let body = coverage(x, y, 8.0, 1.0);
+        let shadow = coverage(x, y - 3.0, 6.0, 8.0) * 0.35;
+        // A white dot on a two-tone fill, with anti-aliased edges.
+        let dot = (0.5 - ((x - 48.0).hypot(y - 48.0) - 20.0)).clamp(0.0, 1.0);
+        let base = if y < h / 2.0 {
+            [52.0, 120.0, 246.0]
+        } else {
+            [37.0, 99.0, 235.0]
+        };
+        let fill = base.map(|c| c + (255.0 - c) * dot);
+        let alpha = body + shadow * (1.0 - body);
+        if alpha == 0.0 {
+            return Rgba([0; 4]);
+        }
+        let rgb = fill.map(|c| (c * body / alpha).round() as u8);
+        Rgba([rgb[0], rgb[1], rgb[2], (alpha * 255.0).round() as u8])
+    })
+}
+
+#[test]
+fn flat_graphics_shrink_well_below_truecolour_png() {
+    let source = button();
+    let distinct: HashSet<_> = source.pixels().collect();
+    assert!(distinct.len() > 64, "{} colours", distinct.len());
+
+    let png = encode_png8(
+        &quantize(&source, Palette::new(64, Dither::FloydSteinberg)),
+        Effort::default(),
+    )
+    .unwrap();
+    let mut truecolour = Vec::new();
+    image::DynamicImage::ImageRgba8(source.clone())
+        .write_to(
+            &mut std::io::Cursor::new(&mut truecolour),
+            image::ImageOutputFormat::Png,
+        )
+        .unwrap();
+    assert!(
+        png.len() * 2 < truecolour.len(),
+        "{} vs {} bytes",
+        png.len(),
+        truecolour.len()
+    );
+
+    // Visually close: premultiplied channels stay within a few levels on
+    // average, dithering aside.
+    let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+    let premultiplied = |p: &Rgba<u8>| {
+        let a = u32::from(p.0[3]);
+        [0, 1, 2].map(|c| (u32::from(p.0[c]) * a / 255) as i32)
+    };
+    let total: i32 = source
+        .pixels()
+        .zip(decoded.pixels())
+        .map(|(a, b)| {
+            let (a, b) = (premultiplied(a), premultiplied(b));
+            (0..3).map(|c| (a[c] - b[c]).abs()).sum::<i32>()
+        })
+        .sum();
+    let error = f64::from(total) / (3.0 * source.pixels().len() as f64);
+    assert!(error < 1.0, "mean channel error {}", error);
+}
+
+#[test]
+fn effort_trades_encode_time_for_size() {
+    let indexed = quantize(&button(), Palette::new(64, Dither::FloydSteinberg));
+    let fast = encode_png8(&indexed, Effort::Fast).unwrap();
+    let balanced = encode_png8(&indexed, Effort::Balanced).unwrap();
+    assert!(
+        fast.len() >= balanced.len(),
+        "{} vs {}",
+        fast.len(),
+        balanced.len()
+    );
+    assert_eq!(
+        image::load_from_memory(&fast).unwrap().to_rgba8(),
+        image::load_from_memory(&balanced).unwrap().to_rgba8()
+    );
+
+    assert_eq!(Effort::parse("Speed"), Some(Effort::Fast));
+    assert_eq!(Effort::parse("best"), Some(Effort::Max));
+    assert_eq!(Effort::parse("slow"), None);
+    assert_eq!(Effort::default(), Effort::Balanced);
+}
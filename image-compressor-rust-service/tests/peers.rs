@@ -0,0 +1,149 @@
+// image-compressor-rust-service/tests/peers.rs
+
+//! Sharing `/compress` work with less loaded replicas.
+
+mod common;
+
+use axum::body::to_bytes;
+use axum::http::StatusCode;
+use axum::response::Response;
+use common::{fixture, post, send};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::cpu::{CpuConfig, Priority};
+use image_compressor_rust_service::server::peers::{Load, PeersConfig};
+use image_compressor_rust_service::server::{self, AppState};
+
+fn state(cpu_budget: usize, peers: PeersConfig) -> AppState {
+    common::state(Config {
+        cpu: CpuConfig {
+            budget: cpu_budget,
+            ..CpuConfig::default()
+        },
+        peers,
+        ..Config::default()
+    })
+}
+
+/// Serves an idle replica with four threads; returns its base URL.
+async fn spawn_peer() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let app = server::router(state(4, PeersConfig::default()));
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    url
+}
+
+async fn compress(state: &AppState, headers: &[(&str, &str)]) -> Response {
+    send(
+        &server::router(state.clone()),
+        post("/compress", headers, fixture("landscape.jpg")),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn requests_that_would_queue_go_to_an_idle_peer() {
+    let peer = spawn_peer().await;
+    let local = state(
+        1,
+        PeersConfig {
+            urls: vec![peer.clone()],
+            ..PeersConfig::default()
+        },
+    );
+    local.peers.poll().await;
+
+    // With a thread free, the request stays here.
+    let response = compress(&local, &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("x-peer").is_none());
+
+    let busy = local.cpu.acquire(Priority::Normal).await;
+    assert_eq!(
+        Load::of(&local.cpu),
+        Load {
+            queued: 0,
+            available: 0
+        }
+    );
+    let response = compress(&local, &[("X-Compression-Quality", "50")]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-peer"], peer.as_str());
+    assert_eq!(response.headers()["content-type"], "image/jpeg");
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert!(!body.is_empty());
+    drop(busy);
+}
+
+#[tokio::test]
+async fn large_inputs_go_to_a_less_loaded_peer_once() {
+    let peer = spawn_peer().await;
+    let local = state(
+        1,
+        PeersConfig {
+            urls: vec![peer.clone()],
+            forward_min_pixels: 160 * 96,
+            ..PeersConfig::default()
+        },
+    );
+    local.peers.poll().await;
+
+    let response = compress(&local, &[]).await;
+    assert_eq!(response.headers()["x-peer"], peer.as_str());
+
+    // A forwarded request is never forwarded again.
+    let response = compress(&local, &[("X-Peer-Forwarded", "true")]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("x-peer").is_none());
+}
+
+#[tokio::test]
+async fn unreachable_peers_are_left_out() {
+    // Bound and dropped, so nothing listens there.
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+    let local = state(
+        1,
+        PeersConfig {
+            urls: vec![url],
+            forward_min_pixels: 1,
+            ..PeersConfig::default()
+        },
+    );
+    local.peers.poll().await;
+
+    let response = compress(&local, &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("x-peer").is_none());
+}
+
+#[tokio::test]
+async fn idempotency_keys_are_looked_up_before_forwarding() {
+    let peer = spawn_peer().await;
+    let local = state(
+        1,
+        PeersConfig {
+            urls: vec![peer],
+            ..PeersConfig::default()
+        },
+    );
+    local.peers.poll().await;
+
+    let key = [("Idempotency-Key", "order-7")];
+    let response = compress(&local, &key).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("x-peer").is_none());
+
+    // A retry while busy is replayed here rather than compressed again by
+    // the peer, and a reused key is rejected here.
+    let busy = local.cpu.acquire(Priority::Normal).await;
+    let retry = compress(&local, &key).await;
+    assert_eq!(retry.status(), StatusCode::OK);
+    assert_eq!(retry.headers()["idempotent-replayed"], "true");
+    assert!(retry.headers().get("x-peer").is_none());
+
+    let reused = compress(&local, &[key[0], ("X-Compression-Quality", "20")]).await;
+    assert_eq!(reused.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    drop(busy);
+}
@@ -0,0 +1,71 @@
+// image-compressor-rust-service/tests/placeholder.rs
+
+//! Low-quality image placeholders requested with `X-Placeholder`.
+
+mod common;
+
+use axum::http::StatusCode;
+use axum::response::Response;
+use base64::Engine;
+use common::{fixture, post, send};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::{compress_image, AnimationMode, CompressionOptions};
+
+fn decode(uri: &str) -> image::DynamicImage {
+    let data = uri
+        .strip_prefix("data:image/jpeg;base64,")
+        .expect("a JPEG data URI");
+    let jpeg = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .unwrap();
+    image::load_from_memory(&jpeg).unwrap()
+}
+
+async fn compress(headers: &[(&str, &str)]) -> Response {
+    send(
+        &common::app(Config::default()),
+        post("/compress", headers, fixture("landscape.jpg")),
+    )
+    .await
+}
+
+#[test]
+fn placeholders_are_tiny_previews_of_the_output() {
+    let options = CompressionOptions {
+        placeholder: Some(24),
+        width: Some(80),
+        ..CompressionOptions::default()
+    };
+    let output = compress_image(&fixture("landscape.jpg"), &options).unwrap();
+    let placeholder = output.placeholder.expect("a placeholder");
+    assert!(placeholder.len() < 1_500, "{} bytes", placeholder.len());
+    let preview = decode(&placeholder);
+    // The 80x48 output scaled to 24 pixels wide.
+    assert_eq!((preview.width(), preview.height()), (24, 14));
+
+    let animated = CompressionOptions {
+        placeholder: Some(16),
+        animation: AnimationMode::Animate,
+        ..CompressionOptions::default()
+    };
+    let output = compress_image(&fixture("animated.png"), &animated).unwrap();
+    assert_eq!(decode(&output.placeholder.unwrap()).width(), 16);
+
+    let none = compress_image(&fixture("landscape.jpg"), &CompressionOptions::default()).unwrap();
+    assert_eq!(none.placeholder, None);
+}
+
+#[tokio::test]
+async fn placeholders_are_returned_in_a_header() {
+    let response = compress(&[("X-Placeholder", "true")]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let uri = response.headers()["X-Placeholder-Data"].to_str().unwrap();
+    assert_eq!(decode(uri).width(), 24);
+
+    // Wider than 64 pixels is out of range.
+    let response = compress(&[("X-Placeholder", "500")]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = compress(&[]).await;
+    assert!(!response.headers().contains_key("X-Placeholder-Data"));
+}
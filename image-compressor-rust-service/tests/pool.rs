@@ -0,0 +1,96 @@
+// image-compressor-rust-service/tests/pool.rs
+
+//! Reuse of decode and output buffers across requests.
+
+mod common;
+
+use common::fixture;
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::decode_image;
+use image_compressor_rust_service::pool::{BufferPool, PoolConfig};
+
+fn small_pool() -> PoolConfig {
+    PoolConfig {
+        enabled: true,
+        max_bytes: 16 * 1024,
+        max_per_class: 2,
+        min_buffer_bytes: 1024,
+        max_buffer_bytes: 8 * 1024,
+    }
+}
+
+#[test]
+fn buffers_are_reused_by_size_class() {
+    let pool = BufferPool::new(&small_pool());
+    let mut buffer = pool.take(3000);
+    assert_eq!(buffer.capacity(), 4096);
+    buffer.extend_from_slice(&[7; 3000]);
+    let address = buffer.as_ptr();
+    pool.give(buffer);
+    assert_eq!(pool.pooled_bytes(), 4096);
+
+    // Any request the class covers gets it back, emptied.
+    let reused = pool.take(2500);
+    assert_eq!((reused.as_ptr(), reused.len()), (address, 0));
+    assert_eq!(pool.pooled_bytes(), 0);
+    // A smaller class does not hand out larger buffers.
+    pool.give(reused);
+    assert_eq!(pool.take(1500).capacity(), 2048);
+
+    // Buffers outside the classes are left to the allocator.
+    assert_eq!(pool.take(100).capacity(), 100);
+    pool.give(Vec::with_capacity(100));
+    pool.give(Vec::with_capacity(64 * 1024));
+    assert_eq!(pool.pooled_bytes(), 4096);
+}
+
+#[test]
+fn the_pool_is_bounded() {
+    let pool = BufferPool::new(&small_pool());
+    for _ in 0..3 {
+        pool.give(Vec::with_capacity(1024));
+    }
+    // Two per class.
+    assert_eq!(pool.pooled_bytes(), 2048);
+    for _ in 0..3 {
+        pool.give(Vec::with_capacity(8 * 1024));
+    }
+    // 16 KiB in all.
+    assert_eq!(pool.pooled_bytes(), 2048 + 8 * 1024);
+
+    let disabled = BufferPool::new(&PoolConfig {
+        enabled: false,
+        ..small_pool()
+    });
+    assert_eq!(disabled.take(3000).capacity(), 3000);
+    disabled.give(Vec::with_capacity(4096));
+    assert_eq!(disabled.pooled_bytes(), 0);
+}
+
+#[test]
+fn pooled_decodes_match_the_image_crate() {
+    for name in ["portrait-alpha.png", "interlaced.png", "lossy.webp"] {
+        let input = fixture(name);
+        let pooled = decode_image(&input).unwrap();
+        let reference = image::load_from_memory(&input).unwrap();
+        assert_eq!(pooled.color(), reference.color(), "{}", name);
+        assert_eq!(pooled.as_bytes(), reference.as_bytes(), "{}", name);
+    }
+}
+
+#[test]
+fn pool_settings_are_validated() {
+    for (settings, expected) in [
+        ("min_buffer_bytes = 1000", "must be powers of two"),
+        (
+            "min_buffer_bytes = 131072\nmax_buffer_bytes = 65536",
+            "min_buffer_bytes (131072) is above max_buffer_bytes (65536)",
+        ),
+        ("max_bytes = 1048576", "is above max_bytes (1048576)"),
+    ] {
+        let config: Config = toml::from_str(&format!("[buffer_pool]\n{}", settings)).unwrap();
+        let error = format!("{:#}", config.validate().unwrap_err());
+        assert!(error.contains(expected), "{}", error);
+    }
+    Config::default().validate().unwrap();
+}
@@ -0,0 +1,218 @@
+// image-compressor-rust-service/tests/properties.rs
+
+//! Property-based tests for option parsing and pipeline invariants.
+
+mod common;
+
+use axum::http::{HeaderMap, HeaderValue};
+use common::ssim;
+use image::{DynamicImage, ImageOutputFormat, RgbImage};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::formats::OutputFormat;
+use image_compressor_rust_service::metadata::Exif;
+use image_compressor_rust_service::quality::{perceptual_to_native, FormatQuality, QualityConfig};
+use image_compressor_rust_service::transform::{output_dimensions, MaxDimensions};
+use image_compressor_rust_service::{compress_image_with, AspectRatio, CompressionOptions, Fit};
+use proptest::prelude::*;
+use std::io::Cursor;
+
+fn fit() -> impl Strategy<Value = Fit> {
+    prop_oneof![Just(Fit::Contain), Just(Fit::Cover), Just(Fit::Fill)]
+}
+
+fn requested_side() -> impl Strategy<Value = Option<u32>> {
+    prop_oneof![Just(None), (1u32..5_000).prop_map(Some)]
+}
+
+fn png_fixture(width: u32, height: u32, seed: u8) -> Vec<u8> {
+    let image = RgbImage::from_fn(width, height, |x, y| {
+        image::Rgb([(x * 7) as u8 ^ seed, (y * 5) as u8, ((x + y) * 3) as u8])
+    });
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(image)
+        .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+        .unwrap();
+    png
+}
+
+fn options_from(pairs: &[(&'static str, &str)]) -> CompressionOptions {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            headers.insert(*name, value);
+        }
+    }
+    CompressionOptions::from_headers(&headers)
+}
+
+proptest! {
+    #[test]
+    fn parsed_quality_is_always_in_range(value in ".{0,12}") {
+        let options = options_from(&[("x-compression-quality", &value)]);
+        prop_assert!((1..=100).contains(&options.quality));
+    }
+
+    #[test]
+    fn numeric_quality_is_clamped(value in any::<i32>()) {
+        let options = options_from(&[("x-compression-quality", &value.to_string())]);
+        prop_assert_eq!(i64::from(options.quality), i64::from(value).clamp(1, 100));
+    }
+
+    #[test]
+    fn resolved_quality_respects_the_format_range(
+        quality in 1u8..=100,
+        explicit in any::<bool>(),
+        min in 1u8..=100,
+        span in 0u8..100,
+    ) {
+        let max = min.saturating_add(span).min(100);
+        let jpeg = FormatQuality { default: max, min, max };
+        let config = QualityConfig { jpeg, ..QualityConfig::default() };
+        let mut options = CompressionOptions::default().with_quality(quality);
+        options.quality_explicit = explicit;
+
+        let resolved = config.resolve(OutputFormat::Jpeg, &options);
+        prop_assert!((min..=max).contains(&resolved));
+        if !explicit {
+            prop_assert_eq!(resolved, max);
+        }
+    }
+
+    #[test]
+    fn perceptual_mapping_is_monotonic_and_in_range(a in 1u8..=100, b in 1u8..=100) {
+        let (low, high) = (a.min(b), a.max(b));
+        for format in OutputFormat::ALL {
+            let (low, high) = (perceptual_to_native(format, low), perceptual_to_native(format, high));
+            prop_assert!((1..=100).contains(&low) && (1..=100).contains(&high));
+            prop_assert!(low <= high);
+        }
+    }
+
+    #[test]
+    fn output_never_exceeds_max_dimensions(
+        source in (1u32..10_000, 1u32..10_000),
+        width in requested_side(),
+        height in requested_side(),
+        fit in fit(),
+        max in (1u32..4_096, 1u32..4_096),
+    ) {
+        let options = CompressionOptions { width, height, fit, ..CompressionOptions::default() };
+        let max = MaxDimensions { width: max.0, height: max.1 };
+        let (w, h) = output_dimensions(source, &options, max);
+        prop_assert!(w >= 1 && h >= 1);
+        prop_assert!(w <= max.width && h <= max.height, "{}x{} exceeds {:?}", w, h, max);
+    }
+
+    #[test]
+    fn contain_preserves_aspect_ratio(
+        source in (1u32..10_000, 1u32..10_000),
+        target in (1u32..5_000, 1u32..5_000),
+    ) {
+        let options = CompressionOptions {
+            width: Some(target.0),
+            height: Some(target.1),
+            fit: Fit::Contain,
+            allow_upscale: true,
+            ..CompressionOptions::default()
+        };
+        let max = MaxDimensions { width: u32::MAX, height: u32::MAX };
+        let (w, h) = output_dimensions(source, &options, max);
+        prop_assert!(w <= target.0 && h <= target.1);
+        prop_assert!(w == target.0 || h == target.1, "{}x{} does not touch the box", w, h);
+
+        // Rounding to whole pixels may move each side by half a pixel.
+        let (sw, sh) = (f64::from(source.0), f64::from(source.1));
+        let expected_h = f64::from(w) * sh / sw;
+        let expected_w = f64::from(h) * sw / sh;
+        prop_assert!(
+            (f64::from(h) - expected_h).abs() <= 1.0 || (f64::from(w) - expected_w).abs() <= 1.0,
+            "{}x{} does not keep the {}x{} aspect ratio", w, h, source.0, source.1
+        );
+    }
+
+    #[test]
+    fn aspect_ratios_are_hit_exactly(
+        source in (1u32..10_000, 1u32..10_000),
+        width in prop_oneof![Just(None), (1u32..5_000).prop_map(Some)],
+        aspect in (1u32..20, 1u32..20),
+    ) {
+        let aspect = AspectRatio { width: aspect.0, height: aspect.1 };
+        let options = CompressionOptions { width, aspect: Some(aspect), ..CompressionOptions::default() };
+        let max = MaxDimensions { width: u32::MAX, height: u32::MAX };
+        let (w, h) = output_dimensions(source, &options, max);
+        prop_assert!(w <= source.0 && h <= source.1);
+        // Rounding to whole pixels may move each side by half a pixel.
+        let (aw, ah) = (f64::from(aspect.width), f64::from(aspect.height));
+        let expected_h = f64::from(w) * ah / aw;
+        let expected_w = f64::from(h) * aw / ah;
+        prop_assert!(
+            (f64::from(h) - expected_h).abs() <= 1.0 || (f64::from(w) - expected_w).abs() <= 1.0,
+            "{}x{} is not {}:{}", w, h, aspect.width, aspect.height
+        );
+    }
+
+    #[test]
+    fn output_never_exceeds_the_source_without_upscale(
+        source in (1u32..10_000, 1u32..10_000),
+        width in requested_side(),
+        height in requested_side(),
+        fit in fit(),
+    ) {
+        let options = CompressionOptions { width, height, fit, ..CompressionOptions::default() };
+        let max = MaxDimensions { width: u32::MAX, height: u32::MAX };
+        let (w, h) = output_dimensions(source, &options, max);
+        prop_assert!(w <= source.0 && h <= source.1, "{}x{} enlarges {:?}", w, h, source);
+    }
+}
+
+proptest! {
+    // Each case runs two full encode cycles, so keep the case count modest.
+    #![proptest_config(ProptestConfig::with_cases(24))]
+
+    #[test]
+    fn pipeline_respects_configured_max_dimensions(
+        source in (1u32..96, 1u32..96),
+        width in prop_oneof![Just(None), (1u32..200).prop_map(Some)],
+        height in prop_oneof![Just(None), (1u32..200).prop_map(Some)],
+        fit in fit(),
+        max in (1u32..64, 1u32..64),
+        seed in any::<u8>(),
+    ) {
+        let config = Config { max_output_width: max.0, max_output_height: max.1, ..Config::default() };
+        let options = CompressionOptions { width, height, fit, ..CompressionOptions::default() };
+        let output = compress_image_with(&png_fixture(source.0, source.1, seed), &options, &config).unwrap();
+        let decoded = image::load_from_memory(&output.data).unwrap();
+        prop_assert!(decoded.width() <= max.0 && decoded.height() <= max.1);
+    }
+
+    #[test]
+    fn recompressing_is_idempotent_within_tolerance(
+        source in (16u32..96, 16u32..96),
+        quality in 40u8..=95,
+        seed in any::<u8>(),
+    ) {
+        let config = Config::default();
+        let options = CompressionOptions::default().with_quality(quality);
+        let first = compress_image_with(&png_fixture(source.0, source.1, seed), &options, &config).unwrap();
+        let second = compress_image_with(&first.data, &options, &config).unwrap();
+
+        let (a, b) = (
+            image::load_from_memory(&first.data).unwrap(),
+            image::load_from_memory(&second.data).unwrap(),
+        );
+        prop_assert_eq!((a.width(), a.height()), (b.width(), b.height()));
+        prop_assert!(ssim(&a, &b) > 0.97, "SSIM {} after re-compression", ssim(&a, &b));
+
+        let (n1, n2) = (first.data.len() as f64, second.data.len() as f64);
+        prop_assert!((n2 - n1).abs() / n1 <= 0.10, "size changed from {} to {}", n1, n2);
+    }
+
+    #[test]
+    fn exif_parsing_never_panics(tail in proptest::collection::vec(any::<u8>(), 0..512)) {
+        // A valid TIFF header, so the fuzzed bytes reach the IFD parser.
+        let tiff = [b"II*\0\x08\0\0\0".as_slice(), &tail].concat();
+        if let Some(exif) = Exif::parse(&tiff) {
+            prop_assert!(Exif::parse(&exif.to_tiff()).is_some());
+        }
+    }
+}
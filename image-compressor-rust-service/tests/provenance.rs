@@ -0,0 +1,120 @@
+// image-compressor-rust-service/tests/provenance.rs
+
+//! C2PA signing. The signing tests need `--features c2pa`.
+
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::provenance::ProvenanceConfig;
+use image_compressor_rust_service::{compress_image_with, CompressionOptions};
+use std::path::PathBuf;
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// The test signer in `tests/fixtures/c2pa`, issued by a throwaway root CA.
+fn test_provenance() -> ProvenanceConfig {
+    ProvenanceConfig {
+        enabled: true,
+        certificate_chain: fixture_path("c2pa/certs.pem"),
+        private_key: fixture_path("c2pa/private.key"),
+        algorithm: "ed25519".to_string(),
+        tsa_url: None,
+    }
+}
+
+#[test]
+fn enabled_provenance_needs_loadable_keys() {
+    let config = Config {
+        provenance: ProvenanceConfig {
+            private_key: fixture_path("c2pa/missing.key"),
+            ..test_provenance()
+        },
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn deterministic_output_is_refused_while_signing() {
+    let config = Config {
+        provenance: test_provenance(),
+        ..Config::default()
+    };
+    let options = CompressionOptions {
+        deterministic: true,
+        ..CompressionOptions::default()
+    };
+    let input = std::fs::read(fixture_path("landscape.jpg")).unwrap();
+    let error = compress_image_with(&input, &options, &config).unwrap_err();
+    assert!(error.to_string().contains("Deterministic"), "{:#}", error);
+}
+
+#[cfg(feature = "c2pa")]
+mod signing {
+    use super::*;
+    use c2pa::{Context, Reader, ValidationState};
+    use std::io::Cursor;
+
+    fn read_manifest(output: &[u8]) -> Reader {
+        let trust = format!(
+            "[trust]\ntrust_anchors = '''\n{}'''\n",
+            std::fs::read_to_string(fixture_path("c2pa/ca.pem")).unwrap()
+        );
+        let context = Context::new().with_settings(trust.as_str()).unwrap();
+        Reader::from_context(context)
+            .with_stream("image/jpeg", Cursor::new(output))
+            .expect("output carries a C2PA manifest")
+    }
+
+    #[test]
+    fn outputs_carry_a_valid_signed_manifest() {
+        let config = Config {
+            provenance: test_provenance(),
+            ..Config::default()
+        };
+        config.validate().unwrap();
+        let input = std::fs::read(fixture_path("landscape.jpg")).unwrap();
+        let options = CompressionOptions {
+            width: Some(80),
+            ..CompressionOptions::default()
+        };
+        let output = compress_image_with(&input, &options, &config).unwrap();
+        image::load_from_memory(&output.data).expect("output still decodes");
+
+        let reader = read_manifest(&output.data);
+        assert_eq!(
+            reader.validation_state(),
+            ValidationState::Trusted,
+            "{:?}",
+            reader.validation_status()
+        );
+        let manifest = reader.active_manifest().unwrap();
+        assert_eq!(manifest.ingredients().len(), 1);
+        let json = reader.json();
+        for action in ["c2pa.opened", "c2pa.resized", "c2pa.transcoded"] {
+            assert!(json.contains(action), "{} is not recorded", action);
+        }
+    }
+
+    #[test]
+    fn tampered_outputs_fail_validation() {
+        let config = Config {
+            provenance: test_provenance(),
+            ..Config::default()
+        };
+        let input = std::fs::read(fixture_path("landscape.jpg")).unwrap();
+        let mut output = compress_image_with(&input, &CompressionOptions::default(), &config)
+            .unwrap()
+            .data;
+        // Flip a byte in the entropy-coded data, ahead of the end marker.
+        let at = output.len() - 10;
+        output[at] ^= 0x55;
+
+        assert_eq!(
+            read_manifest(&output).validation_state(),
+            ValidationState::Invalid
+        );
+    }
+}
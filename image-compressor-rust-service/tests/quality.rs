@@ -0,0 +1,93 @@
+// image-compressor-rust-service/tests/quality.rs
+
+//! Estimated JPEG input quality, from `/inspect` and the `/compress` guard.
+
+mod common;
+
+use axum::http::StatusCode;
+use common::{fixture, json_body, post, send};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::decode_image;
+use image_compressor_rust_service::encode::encode_jpeg;
+use image_compressor_rust_service::quality::{estimate_jpeg_quality, AboveSource};
+
+/// The landscape fixture re-encoded at `quality`.
+fn landscape_at(quality: u8) -> Vec<u8> {
+    encode_jpeg(&decode_image(&fixture("landscape.jpg")).unwrap(), quality).unwrap()
+}
+
+#[test]
+fn estimates_match_the_encoding_quality() {
+    for quality in [10, 35, 50, 60, 75, 90, 97] {
+        assert_eq!(estimate_jpeg_quality(&landscape_at(quality)), Some(quality));
+    }
+    assert_eq!(estimate_jpeg_quality(&fixture("portrait-alpha.png")), None);
+    assert_eq!(estimate_jpeg_quality(&[0xFF, 0xD8, 0xFF, 0xDB, 0x00]), None);
+}
+
+fn router(above_source: AboveSource) -> axum::Router {
+    let mut config = Config::default();
+    config.quality.above_source = above_source;
+    common::app(config)
+}
+
+#[tokio::test]
+async fn inspect_reports_format_dimensions_and_quality() {
+    let request = post("/inspect", &[], landscape_at(60));
+    let response = send(&router(AboveSource::Warn), request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let inspection = json_body(response).await;
+    assert_eq!(inspection["format"], "jpeg");
+    assert_eq!(inspection["width"], 160);
+    assert_eq!(inspection["height"], 96);
+    assert_eq!(inspection["jpeg_quality"], 60);
+    assert!(inspection["content_type_matches"].is_null());
+
+    let request = post(
+        "/inspect",
+        &[("Content-Type", "image/jpeg")],
+        fixture("portrait-alpha.png"),
+    );
+    let response = send(&router(AboveSource::Warn), request).await;
+    let inspection = json_body(response).await;
+    assert_eq!(inspection["format"], "png");
+    assert!(inspection["jpeg_quality"].is_null());
+    assert_eq!(inspection["content_type_matches"], false);
+}
+
+async fn compress(above_source: AboveSource, quality: &str) -> axum::response::Response {
+    let request = post(
+        "/compress",
+        &[("X-Compression-Quality", quality)],
+        landscape_at(60),
+    );
+    send(&router(above_source), request).await
+}
+
+#[tokio::test]
+async fn qualities_above_the_source_are_warned_about_or_refused() {
+    let response = compress(AboveSource::Warn, "90").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-source-quality"], "60");
+    assert!(response.headers().contains_key("x-quality-warning"));
+
+    let response = compress(AboveSource::Warn, "50").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key("x-quality-warning"));
+
+    let response = compress(AboveSource::Allow, "90").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key("x-quality-warning"));
+
+    let response = compress(AboveSource::Refuse, "90").await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let error = json_body(response).await;
+    assert_eq!(error["error"]["code"], "quality_above_source");
+    assert_eq!(
+        compress(AboveSource::Refuse, "60").await.status(),
+        StatusCode::OK
+    );
+
+    let invalid: Result<Config, _> = toml::from_str("[quality]\nabove_source = \"sometimes\"");
+    assert!(invalid.is_err());
+}
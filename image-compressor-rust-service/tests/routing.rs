@@ -0,0 +1,103 @@
+// image-compressor-rust-service/tests/routing.rs
+
+//! Router mode: `/compress` proxied to workers by content hash.
+
+mod common;
+
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::Router;
+use common::{fixture, post, send};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::server::routing::{HashRing, RoutingConfig};
+
+fn app(config: Config) -> Router {
+    common::app(config)
+}
+
+/// Serves a worker; returns its base URL.
+async fn spawn_worker() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let app = app(Config::default());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    url
+}
+
+fn router(workers: Vec<String>) -> Router {
+    app(Config {
+        routing: RoutingConfig {
+            workers,
+            ..RoutingConfig::default()
+        },
+        ..Config::default()
+    })
+}
+
+async fn compress(router: &Router, body: Vec<u8>, quality: &str) -> Response {
+    let request = post("/compress", &[("X-Compression-Quality", quality)], body);
+    send(router, request).await
+}
+
+fn worker(response: &Response) -> &str {
+    response.headers()["x-worker"].to_str().unwrap()
+}
+
+#[test]
+fn adding_a_worker_only_moves_keys_to_it() {
+    let workers: Vec<String> = (0..4).map(|i| format!("http://worker-{}", i)).collect();
+    let before = HashRing::new(&workers[..3], 160);
+    let after = HashRing::new(&workers, 160);
+    let mut owned = [0; 4];
+    for key in 0..2000u32 {
+        let key = key.to_be_bytes();
+        let (old, new) = (before.candidates(&key)[0], after.candidates(&key)[0]);
+        assert!(new == old || new == workers[3], "{} moved to {}", old, new);
+        owned[workers.iter().position(|w| w == new).unwrap()] += 1;
+        // Every worker is a candidate once, the owner first.
+        let mut candidates = after.candidates(&key);
+        assert_eq!(candidates[0], new);
+        candidates.sort();
+        assert_eq!(candidates, workers);
+    }
+    assert!(owned.iter().all(|&n| n > 300), "{:?}", owned);
+}
+
+#[tokio::test]
+async fn the_same_image_always_goes_to_the_same_worker() {
+    let workers = vec![
+        spawn_worker().await,
+        spawn_worker().await,
+        spawn_worker().await,
+    ];
+    let router = router(workers.clone());
+
+    let first = compress(&router, fixture("landscape.jpg"), "80").await;
+    assert_eq!(first.status(), StatusCode::OK);
+    assert_eq!(first.headers()["content-type"], "image/jpeg");
+    let second = compress(&router, fixture("landscape.jpg"), "40").await;
+    assert_eq!(worker(&first), worker(&second));
+
+    // Errors come back as the worker sent them.
+    let response = compress(&router, b"not an image".to_vec(), "80").await;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(workers.iter().any(|w| w == worker(&response)));
+}
+
+#[tokio::test]
+async fn unreachable_workers_are_skipped() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let dead = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+    let live = spawn_worker().await;
+
+    let router = router(vec![dead.clone(), live.clone()]);
+    for quality in ["80", "60"] {
+        let response = compress(&router, fixture("landscape.jpg"), quality).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(worker(&response), live);
+    }
+
+    let response = compress(&self::router(vec![dead]), fixture("landscape.jpg"), "80").await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+}
@@ -0,0 +1,90 @@
+// image-compressor-rust-service/tests/sandbox.rs
+
+//! Decoding in the sandboxed worker process.
+
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::fixture;
+use image_compressor_rust_service::animation::FrameLimit;
+use image_compressor_rust_service::sandbox::{
+    self, decode_frames_sandboxed, decode_image_sandboxed, SandboxConfig,
+};
+use image_compressor_rust_service::{decode_frames, decode_image, DecodeError};
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Set when this test binary runs again as a restricted process.
+const PROBE: &str = "SANDBOX_PROBE";
+
+fn config() -> SandboxConfig {
+    SandboxConfig {
+        enabled: true,
+        worker_path: Some(PathBuf::from(env!(
+            "CARGO_BIN_EXE_image-compressor-rust-service"
+        ))),
+        ..SandboxConfig::default()
+    }
+}
+
+#[test]
+fn the_worker_decodes_every_input_format() {
+    // The JPEG is wide enough for `jpeg-decoder` to start threads.
+    for name in ["landscape.jpg", "portrait-alpha.png", "lossy.webp"] {
+        let input = fixture(name);
+        let sandboxed = decode_image_sandboxed(&input, &config())
+            .unwrap_or_else(|e| panic!("{}: {:#}", name, e));
+        assert_eq!(sandboxed, decode_image(&input).unwrap(), "{}", name);
+    }
+}
+
+#[test]
+fn the_worker_decodes_animations_up_to_the_limit() {
+    let input = fixture("animated.png");
+    for frames in [usize::MAX, 2] {
+        let limit = FrameLimit {
+            frames,
+            ..FrameLimit::NONE
+        };
+        let sandboxed = decode_frames_sandboxed(&input, limit, &config())
+            .unwrap()
+            .unwrap();
+        let direct = decode_frames(&input, limit).unwrap().unwrap();
+        // The fixture has three frames.
+        assert_eq!(direct.len(), frames.min(3));
+        assert_eq!(sandboxed.len(), direct.len());
+        for (sandboxed, direct) in sandboxed.iter().zip(&direct) {
+            assert_eq!(sandboxed.buffer(), direct.buffer());
+            assert_eq!(sandboxed.delay(), direct.delay());
+        }
+    }
+}
+
+#[test]
+fn decode_errors_keep_their_kind() {
+    let error = decode_image_sandboxed(&fixture("oversized.jpg"), &config()).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<DecodeError>(),
+        Some(&DecodeError::TooLarge)
+    );
+}
+
+#[test]
+fn forbidden_syscalls_kill_the_worker() {
+    if std::env::var_os(PROBE).is_some() {
+        sandbox::restrict_process(1 << 30, 10).unwrap();
+        // `openat` is not on the allowlist.
+        let _ = std::fs::File::open("/dev/null");
+        std::process::exit(0);
+    }
+    let status = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "forbidden_syscalls_kill_the_worker"])
+        .env(PROBE, "1")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert_eq!(status.signal(), Some(libc::SIGSYS));
+}
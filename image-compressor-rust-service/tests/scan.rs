@@ -0,0 +1,177 @@
+// image-compressor-rust-service/tests/scan.rs
+
+//! Virus scanning of `/compress` inputs, against fake clamd and ICAP servers.
+
+mod common;
+
+use axum::http::StatusCode;
+use axum::response::Response;
+use common::{fixture, json_body, post, send};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::server::scan::{ScanConfig, ScanProtocol};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Inputs containing this are reported as infected by the fake scanners.
+const MARKER: &[u8] = b"EICAR-TEST-MARKER";
+
+fn contains_marker(data: &[u8]) -> bool {
+    data.windows(MARKER.len()).any(|w| w == MARKER)
+}
+
+/// A fake clamd answering `zINSTREAM` requests.
+async fn fake_clamd() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut command = [0u8; 10];
+                stream.read_exact(&mut command).await.unwrap();
+                assert_eq!(&command, b"zINSTREAM\0");
+                let mut data = Vec::new();
+                loop {
+                    let len = stream.read_u32().await.unwrap() as usize;
+                    if len == 0 {
+                        break;
+                    }
+                    let mut chunk = vec![0; len];
+                    stream.read_exact(&mut chunk).await.unwrap();
+                    data.extend(chunk);
+                }
+                let reply: &[u8] = if contains_marker(&data) {
+                    b"stream: Eicar-Test-Signature FOUND\0"
+                } else {
+                    b"stream: OK\0"
+                };
+                stream.write_all(reply).await.unwrap();
+            });
+        }
+    });
+    addr
+}
+
+/// A fake ICAP server answering `RESPMOD` requests.
+async fn fake_icap() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(serve_icap(stream));
+        }
+    });
+    addr
+}
+
+async fn serve_icap(stream: TcpStream) {
+    let mut reader = BufReader::new(stream);
+    // ICAP headers, then the encapsulated HTTP headers.
+    for _ in 0..2 {
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            if line == "\r\n" {
+                break;
+            }
+        }
+    }
+    let mut body = Vec::new();
+    loop {
+        let mut size = String::new();
+        reader.read_line(&mut size).await.unwrap();
+        let size = usize::from_str_radix(size.trim(), 16).unwrap();
+        let mut chunk = vec![0; size + 2];
+        reader.read_exact(&mut chunk).await.unwrap();
+        if size == 0 {
+            break;
+        }
+        body.extend(&chunk[..size]);
+    }
+    let reply: &[u8] = if contains_marker(&body) {
+        b"ICAP/1.0 200 OK\r\nX-Infection-Found: Type=0; Resolution=2; Threat=Eicar;\r\nEncapsulated: null-body=0\r\n\r\n"
+    } else {
+        b"ICAP/1.0 204 No Content\r\nEncapsulated: null-body=0\r\n\r\n"
+    };
+    reader.get_mut().write_all(reply).await.unwrap();
+}
+
+async fn compress(scan: ScanConfig, body: Vec<u8>) -> Response {
+    let config = Config {
+        virus_scan: scan,
+        server_timing: true,
+        ..Config::default()
+    };
+    let app = common::app(config);
+    send(&app, post("/compress", &[], body)).await
+}
+
+/// A JPEG with the marker after its end, which decoders ignore.
+fn flagged_jpeg() -> Vec<u8> {
+    let mut input = fixture("landscape.jpg");
+    input.extend_from_slice(MARKER);
+    input
+}
+
+async fn error_code(response: Response) -> String {
+    let body = json_body(response).await;
+    body["error"]["code"].as_str().unwrap().to_string()
+}
+
+fn clamav(addr: String) -> ScanConfig {
+    ScanConfig {
+        enabled: true,
+        addr,
+        ..ScanConfig::default()
+    }
+}
+
+#[tokio::test]
+async fn clean_inputs_are_compressed_after_the_scan() {
+    let response = compress(clamav(fake_clamd().await), fixture("landscape.jpg")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let timing = response.headers()["server-timing"].to_str().unwrap();
+    assert!(timing.contains("scan;dur="), "{}", timing);
+}
+
+#[tokio::test]
+async fn flagged_inputs_are_rejected_before_decoding() {
+    let response = compress(clamav(fake_clamd().await), flagged_jpeg()).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(error_code(response).await, "input_flagged");
+}
+
+#[tokio::test]
+async fn icap_scanners_are_supported() {
+    let scan = ScanConfig {
+        protocol: ScanProtocol::Icap,
+        ..clamav(fake_icap().await)
+    };
+    let clean = compress(scan.clone(), fixture("landscape.jpg")).await;
+    assert_eq!(clean.status(), StatusCode::OK);
+    let flagged = compress(scan, flagged_jpeg()).await;
+    assert_eq!(flagged.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn an_unreachable_scanner_fails_closed_by_default() {
+    // Bound and dropped, so nothing listens there.
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+
+    let response = compress(clamav(addr.clone()), fixture("landscape.jpg")).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(error_code(response).await, "scan_unavailable");
+
+    let fail_open = ScanConfig {
+        fail_open: true,
+        ..clamav(addr)
+    };
+    let response = compress(fail_open, fixture("landscape.jpg")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
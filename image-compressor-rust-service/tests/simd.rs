@@ -0,0 +1,93 @@
+// image-compressor-rust-service/tests/simd.rs
+
+//! Runtime dispatch of the vectorized pixel loops.
+
+use image_compressor_rust_service::simd::{self, CpuFeatures, Path};
+
+/// Pseudo-random bytes, including lengths that do not fill a vector.
+fn pixels(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+#[test]
+fn every_path_computes_the_same_values() {
+    let detected = Path::detected();
+    for len in [0, 3, 93, 3 * 4099] {
+        let rgb = pixels(len);
+        assert_eq!(
+            simd::rgb_to_ycbcr_on(detected, &rgb),
+            simd::rgb_to_ycbcr_on(Path::Scalar, &rgb),
+            "{} bytes",
+            len
+        );
+
+        let invert = |[r, g, b]: [u8; 3]| [255 - r, g / 2, b.saturating_add(40)];
+        let rgba = pixels(len / 3 * 4);
+        let (mut vector, mut scalar) = (rgba.clone(), rgba.clone());
+        simd::map_rgb_on(detected, &mut vector, 4, invert);
+        simd::map_rgb_on(Path::Scalar, &mut scalar, 4, invert);
+        assert_eq!(vector, scalar, "{} bytes", len);
+        // Alpha is left alone.
+        assert!(vector
+            .iter()
+            .skip(3)
+            .step_by(4)
+            .eq(rgba.iter().skip(3).step_by(4)));
+    }
+}
+
+#[test]
+fn every_path_finds_the_same_grey_images() {
+    let detected = Path::detected();
+    for len in [0, 4, 92, 4 * 4099] {
+        let rgba = pixels(len);
+        // Grey within the tolerance, with a coloured pixel at the end that
+        // is transparent or not.
+        let mut gray: Vec<u8> = rgba
+            .chunks_exact(4)
+            .flat_map(|p| [p[0], p[0].saturating_add(p[1] % 3), p[0], p[3]])
+            .collect();
+        for (pixels, channels) in [(&rgba, 4), (&gray, 4), (&rgba, 3), (&gray, 3)] {
+            assert_eq!(
+                simd::is_gray_on(detected, pixels, channels, 2),
+                simd::is_gray_on(Path::Scalar, pixels, channels, 2),
+                "{} bytes",
+                len
+            );
+        }
+        assert!(simd::is_gray_on(detected, &gray, 4, 2));
+        if let Some(last) = gray.len().checked_sub(4) {
+            gray[last..].copy_from_slice(&[255, 0, 0, 0]);
+            assert!(simd::is_gray_on(detected, &gray, 4, 2));
+            gray[last + 3] = 255;
+            assert!(!simd::is_gray_on(detected, &gray, 4, 2));
+            assert!(!simd::is_gray_on(Path::Scalar, &gray, 4, 2));
+        }
+    }
+}
+
+#[test]
+fn detected_features_match_the_path() {
+    let features = CpuFeatures::detect();
+    assert_eq!(features.arch, std::env::consts::ARCH);
+    let names = features.names();
+    match Path::detected() {
+        Path::Avx2 => assert!(names.contains(&"avx2")),
+        Path::Neon => assert!(names.contains(&"neon")),
+        Path::Scalar => assert!(!names.contains(&"avx2") && !names.contains(&"neon")),
+    }
+    // Paths the CPU lacks fall back to the scalar loops.
+    let rgb = pixels(30);
+    assert_eq!(
+        simd::rgb_to_ycbcr_on(Path::Neon, &rgb),
+        simd::rgb_to_ycbcr_on(Path::Avx2, &rgb)
+    );
+}
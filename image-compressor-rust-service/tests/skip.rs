@@ -0,0 +1,145 @@
+// image-compressor-rust-service/tests/skip.rs
+
+//! Conditional processing with `X-Skip-If-Smaller-Than`,
+//! `X-Only-If-Larger` and the default pass-through of outputs that did not
+//! shrink.
+
+mod common;
+
+use axum::body::to_bytes;
+use axum::http::{header, StatusCode};
+use axum::response::Response;
+use common::{fixture, post, send};
+use image_compressor_rust_service::compress_image_bytes;
+use image_compressor_rust_service::config::Config;
+
+async fn compress(input: &[u8], headers: &[(&str, &str)]) -> Response {
+    compress_with(Config::default(), input, headers).await
+}
+
+async fn compress_with(config: Config, input: &[u8], headers: &[(&str, &str)]) -> Response {
+    send(
+        &common::app(config),
+        post("/compress", headers, input.to_vec()),
+    )
+    .await
+}
+
+async fn body(response: Response) -> Vec<u8> {
+    to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap()
+        .to_vec()
+}
+
+#[tokio::test]
+async fn small_inputs_pass_through_untouched() {
+    let input = fixture("landscape.jpg");
+    let response = compress(&input, &[("X-Skip-If-Smaller-Than", "100000")]).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-compression-skipped"], "small-enough");
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/jpeg");
+    assert_eq!(body(response).await, input);
+}
+
+#[tokio::test]
+async fn skipped_requests_can_answer_no_content() {
+    let input = fixture("portrait-alpha.png");
+    let response = compress(
+        &input,
+        &[
+            ("X-Skip-If-Smaller-Than", "100000"),
+            ("X-Skip-Response", "no-content"),
+        ],
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers()["x-compression-skipped"], "small-enough");
+    assert!(body(response).await.is_empty());
+}
+
+#[tokio::test]
+async fn inputs_needing_work_are_still_compressed() {
+    let input = fixture("landscape.jpg");
+
+    let resized = compress(
+        &input,
+        &[("X-Skip-If-Smaller-Than", "100000"), ("X-Width", "8")],
+    )
+    .await;
+    assert_eq!(resized.status(), StatusCode::OK);
+    assert!(resized.headers().get("x-compression-skipped").is_none());
+
+    let large = compress(&input, &[("X-Skip-If-Smaller-Than", "100")]).await;
+    assert!(large.headers().get("x-compression-skipped").is_none());
+    assert_ne!(body(large).await, input);
+}
+
+#[tokio::test]
+async fn outputs_larger_than_the_input_are_discarded() {
+    let input = fixture("gray.jpg");
+    assert!(
+        compress_image_bytes(&input, 100).unwrap().len() >= input.len(),
+        "the fixture should grow at quality 100"
+    );
+
+    let response = compress(
+        &input,
+        &[
+            ("X-Compression-Quality", "100"),
+            ("X-Only-If-Larger", "true"),
+        ],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-compression-skipped"], "not-smaller");
+    assert_eq!(body(response).await, input);
+
+    let response = compress(
+        &input,
+        &[
+            ("X-Compression-Quality", "10"),
+            ("X-Only-If-Larger", "true"),
+        ],
+    )
+    .await;
+    assert!(response.headers().get("x-compression-skipped").is_none());
+    assert!(body(response).await.len() < input.len());
+}
+
+#[tokio::test]
+async fn outputs_that_did_not_shrink_pass_through_by_default() {
+    let input = fixture("gray.jpg");
+    let quality = ("X-Compression-Quality", "100");
+
+    let response = compress(&input, &[quality]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-compression-skipped"], "not-smaller");
+    assert_eq!(body(response).await, input);
+
+    let response = compress(&input, &[quality, ("X-Force-Encode", "true")]).await;
+    assert!(response.headers().get("x-compression-skipped").is_none());
+    assert!(body(response).await.len() >= input.len());
+
+    let config = Config {
+        pass_through_larger: false,
+        ..Config::default()
+    };
+    let response = compress_with(config, &input, &[quality]).await;
+    assert!(response.headers().get("x-compression-skipped").is_none());
+}
+
+#[tokio::test]
+async fn changed_images_are_never_passed_through_by_default() {
+    let input = fixture("gray.jpg");
+    for option in [("X-Filter", "sepia"), ("X-Border-Width", "2")] {
+        let response = compress(&input, &[("X-Compression-Quality", "100"), option]).await;
+        assert!(
+            response.headers().get("x-compression-skipped").is_none(),
+            "{:?}",
+            option
+        );
+    }
+}
@@ -0,0 +1,248 @@
+// image-compressor-rust-service/tests/social.rs
+
+//! Text rendering and social cards from `/social-card`.
+
+mod common;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Request, StatusCode};
+use base64::Engine;
+use common::{fixture, send};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::options::Color;
+use image_compressor_rust_service::social::{Card, CardTemplate};
+use image_compressor_rust_service::text::Font;
+use std::path::PathBuf;
+
+fn font_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/font.ttf")
+}
+
+fn font() -> Font {
+    Font::parse(fixture("font.ttf")).unwrap()
+}
+
+fn alpha(image: &RgbaImage, x: u32, y: u32) -> u8 {
+    image.get_pixel(x, y).0[3]
+}
+
+#[test]
+fn fonts_measure_and_wrap_text() {
+    let font = font();
+    // The fixture's em is 1000 units, so at 100px a unit is a tenth of a pixel.
+    assert_eq!(font.ascent(100.0), 80.0);
+    assert_eq!(font.line_height(100.0), 100.0);
+    assert_eq!(font.measure("I O", 100.0), 60.0 + 30.0 + 60.0);
+    assert_eq!(font.measure("H", 100.0), 100.0);
+
+    assert_eq!(font.wrap("IO DO", 100.0, 130.0, 3), ["IO", "DO"]);
+    // The ellipsis is missing from the fixture and drawn 50 units wide.
+    assert_eq!(font.wrap("IO DO OI", 100.0, 130.0, 2), ["IO", "D…"]);
+    // A word wider than the line is cut rather than overflowing it.
+    assert_eq!(font.wrap("IIIIII", 100.0, 130.0, 3), ["II", "II", "II"]);
+    assert!(font.wrap("  ", 100.0, 130.0, 3).is_empty());
+
+    assert!(Font::parse(b"OTTO\0\0\0\0".to_vec()).is_err());
+    assert!(Font::parse(fixture("landscape.jpg")).is_err());
+}
+
+#[test]
+fn glyphs_fill_their_outlines() {
+    let font = font();
+    let mut canvas = RgbaImage::new(240, 100);
+    font.draw(&mut canvas, "IOH", (0.0, 90.0), 100.0, Color::WHITE);
+
+    // `I` covers x 10..50 from the baseline up to y 10.
+    assert_eq!(alpha(&canvas, 30, 50), 255);
+    assert_eq!(alpha(&canvas, 5, 50), 0);
+    assert_eq!(alpha(&canvas, 30, 5), 0);
+    // `O` is a box from x 60 with a hole at x 80..100, y 30..70.
+    assert_eq!(alpha(&canvas, 70, 50), 255);
+    assert_eq!(alpha(&canvas, 90, 50), 0);
+    assert_eq!(alpha(&canvas, 90, 20), 255);
+    // `H` is two `I`s, at x 130..170 and 170..210.
+    assert_eq!(alpha(&canvas, 150, 50), 255);
+    assert_eq!(alpha(&canvas, 200, 50), 255);
+    assert_eq!(alpha(&canvas, 215, 50), 0);
+    // Edges on half pixels are anti-aliased.
+    let mut canvas = RgbaImage::new(20, 20);
+    font.draw(&mut canvas, "I", (0.5, 10.0), 10.0, Color::WHITE);
+    assert!((100..=155).contains(&alpha(&canvas, 1, 5)));
+    assert_eq!(alpha(&canvas, 3, 5), 255);
+}
+
+#[test]
+fn curves_are_filled_inside_the_control_polygon() {
+    let font = font();
+    let mut canvas = RgbaImage::new(80, 100);
+    font.draw(&mut canvas, "D", (0.0, 90.0), 100.0, Color::WHITE);
+    assert_eq!(alpha(&canvas, 30, 50), 255);
+    // The bowl bulges to x 60 at mid-height but not into the corners.
+    assert!(alpha(&canvas, 57, 50) > 128);
+    assert_eq!(alpha(&canvas, 58, 12), 0);
+    assert_eq!(alpha(&canvas, 58, 88), 0);
+}
+
+#[test]
+fn cards_composite_background_logo_and_text() {
+    let font = font();
+    let template = CardTemplate {
+        width: 400,
+        height: 200,
+        margin: 20,
+        logo_height: 40,
+        ..CardTemplate::default()
+    };
+    template.validate().unwrap();
+    let card = Card {
+        title: "IO".to_string(),
+        subtitle: Some("DO".to_string()),
+        background: Some(DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            100,
+            100,
+            Rgba([200, 200, 200, 255]),
+        ))),
+        logo: Some(DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            20,
+            10,
+            Rgba([255, 0, 0, 255]),
+        ))),
+    };
+    let image = template.render(&font, &card);
+    assert_eq!(image.dimensions(), (400, 200));
+
+    // The scrim darkens the background.
+    let background = image.get_pixel(390, 10).0;
+    assert!(
+        background[0] < 200 && background[0] > 100,
+        "{:?}",
+        background
+    );
+    // The logo is scaled to 80x40 at the margin.
+    assert_eq!(image.get_pixel(25, 25).0, [255, 0, 0, 255]);
+    assert_eq!(image.get_pixel(95, 55).0, [255, 0, 0, 255]);
+    assert_ne!(image.get_pixel(105, 25).0, [255, 0, 0, 255]);
+    // The subtitle (32px, 80% white) sits on the bottom margin, the title
+    // above it.
+    let white = |x: u32, y: u32| image.get_pixel(x, y).0[0] > 200;
+    assert!(white(28, 170));
+    assert!(!white(28, 185));
+    assert!(white(30, 110));
+
+    let card = Card {
+        background: None,
+        logo: None,
+        ..card
+    };
+    let image = template.render(&font, &card);
+    assert_eq!(image.get_pixel(390, 10).0, template.background.0);
+}
+
+#[test]
+fn templates_are_validated() {
+    let too_big = CardTemplate {
+        width: 5000,
+        ..CardTemplate::default()
+    };
+    assert!(too_big.validate().is_err());
+    let no_room = CardTemplate {
+        margin: 400,
+        ..CardTemplate::default()
+    };
+    assert!(no_room.validate().is_err());
+
+    let config: Config = toml::from_str(&format!(
+        r##"
+        [social]
+        font = "{}"
+
+        [social.templates.square]
+        width = 1080
+        height = 1080
+        background = "#102030"
+        title = {{ size = 90, color = "white", max_lines = 4 }}
+        "##,
+        font_path().display()
+    ))
+    .unwrap();
+    config.validate().unwrap();
+    let square = config.social.template("square").unwrap();
+    assert_eq!((square.width, square.height), (1080, 1080));
+    assert_eq!(square.title.max_lines, 4);
+    assert_eq!(square.subtitle, CardTemplate::default().subtitle);
+    assert!(config.social.template("default").is_some());
+    assert!(config.social.template("story").is_none());
+
+    let missing: Config = toml::from_str("[social]\nfont = \"/nonexistent/font.ttf\"").unwrap();
+    assert!(missing.validate().is_err());
+}
+
+fn router(font: bool) -> axum::Router {
+    let mut config = Config::default();
+    if font {
+        config.social.font = Some(font_path());
+    }
+    common::app(config)
+}
+
+async fn post(router: axum::Router, body: serde_json::Value) -> axum::response::Response {
+    let request = Request::post("/social-card")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    send(&router, request).await
+}
+
+#[tokio::test]
+async fn social_card_requires_a_font() {
+    let response = post(router(false), serde_json::json!({ "title": "IO" })).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn social_card_renders_jpeg_and_png() {
+    let background = base64::engine::general_purpose::STANDARD.encode(fixture("landscape.jpg"));
+    let response = post(
+        router(true),
+        serde_json::json!({ "title": "IO DO", "subtitle": "HI", "background": background }),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/jpeg");
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let card = image::load_from_memory(&body).unwrap();
+    assert_eq!(card.dimensions(), (1200, 630));
+
+    let response = post(
+        router(true),
+        serde_json::json!({ "title": "IO", "format": "png" }),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+}
+
+#[tokio::test]
+async fn social_card_rejects_bad_requests() {
+    let response = post(
+        router(true),
+        serde_json::json!({ "title": "IO", "template": "story" }),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = post(
+        router(true),
+        serde_json::json!({ "title": "IO", "format": "gif" }),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = post(
+        router(true),
+        serde_json::json!({ "title": "IO", "logo": "not base64!" }),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = post(router(true), serde_json::json!({ "subtitle": "IO" })).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,236 @@
+// image-compressor-rust-service/tests/transform.rs
+
+//! Which part of the image survives rotations, crops, padding, borders and
+//! rounded corners.
+
+use image::{DynamicImage, Rgb, RgbImage, RgbaImage};
+use image_compressor_rust_service::transform::{decorate, resize, rotate, MaxDimensions};
+use image_compressor_rust_service::{
+    AspectRatio, Border, Color, CompressionOptions, CornerRadius, Fit, Gravity, Rotation,
+};
+
+const UNLIMITED: MaxDimensions = MaxDimensions {
+    width: u32::MAX,
+    height: u32::MAX,
+};
+
+/// A 200x100 image: white on the left, black on the right and a red pixel
+/// row along the top.
+fn halves() -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(200, 100, |x, y| match (x, y) {
+        (_, 0..=4) => Rgb([255, 0, 0]),
+        (0..=99, _) => Rgb([255, 255, 255]),
+        _ => Rgb([0, 0, 0]),
+    }))
+}
+
+fn mean_luma(image: &DynamicImage) -> f64 {
+    let luma = image.to_luma8();
+    luma.pixels().map(|p| f64::from(p.0[0])).sum::<f64>() / luma.len() as f64
+}
+
+fn square(gravity: Gravity) -> DynamicImage {
+    let options = CompressionOptions {
+        width: Some(50),
+        height: Some(50),
+        fit: Fit::Cover,
+        gravity,
+        ..CompressionOptions::default()
+    };
+    resize(halves(), &options, UNLIMITED)
+}
+
+#[test]
+fn gravity_picks_the_side_that_survives() {
+    let west = square(Gravity::West);
+    let east = square(Gravity::East);
+    assert_eq!((west.width(), west.height()), (50, 50));
+    assert!(mean_luma(&west) > 200.0, "west kept {}", mean_luma(&west));
+    assert!(mean_luma(&east) < 30.0, "east kept {}", mean_luma(&east));
+
+    // The centre straddles both halves.
+    let center = mean_luma(&square(Gravity::Center));
+    assert!((100.0..160.0).contains(&center), "center kept {}", center);
+
+    let focal = square(Gravity::parse("0.9,0.5").unwrap());
+    assert!(mean_luma(&focal) < 30.0);
+}
+
+#[test]
+fn gravity_applies_to_aspect_crops() {
+    let crop = |gravity| {
+        let options = CompressionOptions {
+            aspect: Some(AspectRatio {
+                width: 4,
+                height: 1,
+            }),
+            gravity,
+            ..CompressionOptions::default()
+        };
+        resize(halves(), &options, UNLIMITED).to_rgb8()
+    };
+
+    let north = crop(Gravity::North);
+    assert_eq!(north.dimensions(), (200, 50));
+    assert_eq!(north.get_pixel(10, 0).0, [255, 0, 0]);
+    let south = crop(Gravity::South);
+    assert_eq!(south.get_pixel(10, 0).0, [255, 255, 255]);
+}
+
+#[test]
+fn gravity_values_parse() {
+    assert_eq!(Gravity::parse("south-east"), Some(Gravity::SouthEast));
+    assert_eq!(Gravity::parse("NorthWest"), Some(Gravity::NorthWest));
+    assert_eq!(Gravity::parse("centre"), Some(Gravity::Center));
+    assert_eq!(
+        Gravity::parse("0.25, 1"),
+        Some(Gravity::Focal { x: 2500, y: 10_000 })
+    );
+    assert_eq!(Gravity::parse("1.5,0"), None);
+    assert_eq!(Gravity::parse("up"), None);
+}
+
+fn padded(source: DynamicImage, size: (u32, u32), options: CompressionOptions) -> RgbaImage {
+    let options = CompressionOptions {
+        width: Some(size.0),
+        height: Some(size.1),
+        fit: Fit::Pad,
+        ..options
+    };
+    resize(source, &options, UNLIMITED).to_rgba8()
+}
+
+#[test]
+fn pad_fits_the_image_into_an_exact_canvas() {
+    let canvas = padded(halves(), (100, 100), CompressionOptions::default());
+    assert_eq!(canvas.dimensions(), (100, 100));
+    // Scaled to 100x50 and centred, leaving 25 transparent rows on each side.
+    assert_eq!(canvas.get_pixel(50, 10).0[3], 0);
+    assert_eq!(canvas.get_pixel(50, 90).0[3], 0);
+    assert_eq!(canvas.get_pixel(10, 50).0, [255, 255, 255, 255]);
+
+    let north = padded(
+        halves(),
+        (100, 100),
+        CompressionOptions {
+            gravity: Gravity::North,
+            background: Color::parse("#00ff00"),
+            ..CompressionOptions::default()
+        },
+    );
+    let [r, g, _, a] = north.get_pixel(10, 0).0;
+    assert!(r > 245 && g < 10 && a == 255, "top row is {:?}", [r, g]);
+    assert_eq!(north.get_pixel(50, 90).0, [0, 255, 0, 255]);
+}
+
+#[test]
+fn pad_does_not_enlarge_the_image_unless_asked() {
+    let canvas = padded(halves(), (400, 400), CompressionOptions::default());
+    assert_eq!(canvas.dimensions(), (400, 400));
+    // The unscaled 200x100 image sits in the middle.
+    assert_eq!(canvas.get_pixel(99, 200).0[3], 0);
+    assert_eq!(canvas.get_pixel(100, 200).0, [255, 255, 255, 255]);
+    assert_eq!(canvas.get_pixel(100, 149).0[3], 0);
+
+    let upscaled = padded(
+        halves(),
+        (400, 400),
+        CompressionOptions {
+            allow_upscale: true,
+            ..CompressionOptions::default()
+        },
+    );
+    assert_eq!(upscaled.get_pixel(10, 200).0, [255, 255, 255, 255]);
+}
+
+#[test]
+fn background_values_parse() {
+    assert_eq!(Color::parse("#FFFFFF"), Some(Color::WHITE));
+    assert_eq!(Color::parse("00000080"), Some(Color([0, 0, 0, 128])));
+    assert_eq!(Color::parse("transparent"), Some(Color::TRANSPARENT));
+    assert_eq!(Color::parse("#fff"), None);
+    assert_eq!(Color::parse("#gggggg"), None);
+}
+
+fn white(size: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_pixel(size, size, Rgb([255, 255, 255])))
+}
+
+#[test]
+fn rounded_corners_are_transparent() {
+    let options = CompressionOptions {
+        corner_radius: CornerRadius::parse("50%"),
+        ..CompressionOptions::default()
+    };
+    let circle = decorate(white(100), &options).to_rgba8();
+    assert_eq!(circle.get_pixel(0, 0).0[3], 0);
+    assert_eq!(circle.get_pixel(10, 10).0[3], 0);
+    assert_eq!(circle.get_pixel(50, 50).0, [255, 255, 255, 255]);
+    assert_eq!(circle.get_pixel(50, 1).0[3], 255);
+    // The outline is antialiased.
+    let edge = circle.get_pixel(14, 14).0[3];
+    assert!(edge > 0 && edge < 255, "edge alpha is {}", edge);
+}
+
+#[test]
+fn borders_follow_the_outline() {
+    let border = Border {
+        width: 4,
+        color: Color::parse("#ff0000").unwrap(),
+    };
+    let square = decorate(
+        white(100),
+        &CompressionOptions {
+            border: Some(border),
+            ..CompressionOptions::default()
+        },
+    )
+    .to_rgba8();
+    assert_eq!(square.get_pixel(0, 0).0, [255, 0, 0, 255]);
+    assert_eq!(square.get_pixel(3, 50).0, [255, 0, 0, 255]);
+    assert_eq!(square.get_pixel(4, 50).0, [255, 255, 255, 255]);
+
+    let rounded = decorate(
+        white(100),
+        &CompressionOptions {
+            border: Some(border),
+            corner_radius: CornerRadius::parse("20"),
+            ..CompressionOptions::default()
+        },
+    )
+    .to_rgba8();
+    assert_eq!(rounded.get_pixel(0, 0).0[3], 0);
+    assert_eq!(rounded.get_pixel(1, 50).0, [255, 0, 0, 255]);
+    // Inside the corner arc, not along the straight edge.
+    assert_eq!(rounded.get_pixel(6, 6).0, [255, 0, 0, 255]);
+    assert_eq!(rounded.get_pixel(50, 50).0, [255, 255, 255, 255]);
+}
+
+#[test]
+fn corner_radii_parse() {
+    assert_eq!(CornerRadius::parse("12"), Some(CornerRadius::Pixels(12)));
+    assert_eq!(CornerRadius::parse("50%"), Some(CornerRadius::Percent(50)));
+    assert_eq!(CornerRadius::parse("51%"), None);
+    assert_eq!(CornerRadius::parse("0"), None);
+    assert_eq!(CornerRadius::Pixels(500).pixels(100, 40), 20.0);
+}
+
+#[test]
+fn rotations_apply_before_resizing() {
+    let options = CompressionOptions {
+        rotate: Some(Rotation::Quarter),
+        width: Some(50),
+        ..CompressionOptions::default()
+    };
+    let rotated = resize(rotate(halves(), options.rotate), &options, UNLIMITED).to_rgb8();
+    assert_eq!(rotated.dimensions(), (50, 100));
+    // The red top row is now the right column, the white left half the top.
+    let [red, green, _] = rotated.get_pixel(49, 50).0;
+    assert!(red > 240 && green < 10);
+    assert_eq!(rotated.get_pixel(20, 20).0, [255, 255, 255]);
+    assert_eq!(rotated.get_pixel(20, 80).0, [0, 0, 0]);
+
+    assert_eq!(Rotation::parse("-90"), Some(Rotation::ThreeQuarters));
+    assert_eq!(Rotation::parse("180"), Some(Rotation::Half));
+    assert_eq!(Rotation::parse("45"), None);
+}
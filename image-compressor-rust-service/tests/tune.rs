@@ -0,0 +1,91 @@
+// image-compressor-rust-service/tests/tune.rs
+
+//! Side-by-side outputs for tuning presets, and the SSIM scoring them.
+
+mod common;
+
+use axum::http::StatusCode;
+use axum::response::Response;
+use common::{fixture, json_body, post, send};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::preset::Preset;
+use image_compressor_rust_service::{decode_image, diff, tune};
+
+#[test]
+fn ssim_falls_with_quality() {
+    let source = decode_image(&fixture("landscape.jpg")).unwrap();
+    assert!((diff::ssim(&source, &source).unwrap() - 1.0).abs() < 1e-9);
+    let blurred = source.blur(2.0);
+    let blurry = diff::ssim(&source, &blurred).unwrap();
+    let more = diff::ssim(&source, &source.blur(6.0)).unwrap();
+    assert!(more < blurry && blurry < 1.0, "{} vs {}", more, blurry);
+    assert!(diff::ssim(&source, &source.thumbnail(10, 10)).is_err());
+}
+
+#[test]
+fn presets_are_tried_two_steps_either_side() {
+    let thumbnail = Preset::builtin("thumbnail").unwrap();
+    let candidates = tune::tune(
+        &fixture("landscape.jpg"),
+        &thumbnail,
+        10,
+        &Config::default(),
+    )
+    .unwrap();
+    let qualities: Vec<u8> = candidates.iter().map(|c| c.quality).collect();
+    assert_eq!(qualities, [50, 60, 70, 80, 90]);
+    assert!(candidates.iter().all(|c| c.preset == (c.quality == 70)));
+    for pair in candidates.windows(2) {
+        assert!(pair[0].data.len() < pair[1].data.len());
+        assert!(pair[0].ssim < pair[1].ssim);
+    }
+    assert!(candidates.iter().all(|c| c.ssim > 0.5 && c.ssim <= 1.0));
+
+    // Steps stop at 100.
+    let high = Preset {
+        quality: Some(98),
+        ..Preset::default()
+    };
+    let candidates = tune::tune(&fixture("landscape.jpg"), &high, 5, &Config::default()).unwrap();
+    let qualities: Vec<u8> = candidates.iter().map(|c| c.quality).collect();
+    assert_eq!(qualities, [88, 93, 98, 100]);
+}
+
+async fn tune_request(preset: &str, step: Option<&str>) -> Response {
+    let headers: Vec<_> = step
+        .map(|step| ("X-Quality-Step", step))
+        .into_iter()
+        .collect();
+    let request = post(
+        &format!("/presets/{}/tune", preset),
+        &headers,
+        fixture("landscape.jpg"),
+    );
+    send(&common::app(Config::default()), request).await
+}
+
+#[tokio::test]
+async fn tuning_endpoint_returns_every_output_with_its_scores() {
+    let response = tune_request("thumbnail", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let tuning = json_body(response).await;
+    assert_eq!(tuning["preset"], "thumbnail");
+    assert_eq!(tuning["original"]["width"], 160);
+    assert!(tuning["original"]["data"]
+        .as_str()
+        .unwrap()
+        .starts_with("data:image/jpeg;base64,"));
+    let outputs = tuning["outputs"].as_array().unwrap();
+    let qualities: Vec<u64> = outputs
+        .iter()
+        .map(|o| o["quality"].as_u64().unwrap())
+        .collect();
+    assert_eq!(qualities, [60, 65, 70, 75, 80]);
+    assert_eq!(outputs[2]["preset"], true);
+    assert!(outputs.iter().all(|o| o["ssim"].as_f64().unwrap() > 0.0));
+
+    let response = tune_request("missing", None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = tune_request("thumbnail", Some("0")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,520 @@
+// image-compressor-rust-service/tests/tus.rs
+
+//! Resumable tus uploads on `/uploads`, on disk and against a fake Redis.
+
+mod common;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{Method, Request, StatusCode};
+use axum::response::Response;
+use axum::Router;
+use base64::Engine;
+use common::{fixture, json_body, send};
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::server;
+use image_compressor_rust_service::server::tus::{TusConfig, UploadStore};
+use std::collections::HashMap;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+fn disk_config(name: &str) -> TusConfig {
+    let dir = std::env::temp_dir().join(format!("tus-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    TusConfig {
+        enabled: true,
+        dir,
+        ..TusConfig::default()
+    }
+}
+
+fn app(uploads: TusConfig) -> Router {
+    let config = Config {
+        uploads,
+        ..Config::default()
+    };
+    common::app(config)
+}
+
+fn create(length: usize) -> axum::http::request::Builder {
+    Request::post("/uploads")
+        .header("Tus-Resumable", "1.0.0")
+        .header("Upload-Length", length.to_string())
+}
+
+fn patch(location: &str, offset: usize) -> axum::http::request::Builder {
+    Request::patch(location)
+        .header("Tus-Resumable", "1.0.0")
+        .header("Content-Type", "application/offset+octet-stream")
+        .header("Upload-Offset", offset.to_string())
+}
+
+fn header<'a>(response: &'a Response, name: &str) -> &'a str {
+    response.headers()[name].to_str().unwrap()
+}
+
+/// Uploads `input` in two chunks, checking the offset before resuming.
+async fn upload_in_two_chunks(app: &Router, input: &[u8]) -> String {
+    let metadata = format!(
+        "filename {}",
+        base64::engine::general_purpose::STANDARD.encode("holiday photo.jpg")
+    );
+    let created = send(
+        app,
+        create(input.len())
+            .header("Upload-Metadata", metadata)
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(created.status(), StatusCode::CREATED);
+    assert_eq!(header(&created, "tus-resumable"), "1.0.0");
+    assert!(header(&created, "upload-expires").ends_with(" GMT"));
+    let location = header(&created, "location").to_string();
+
+    let half = input.len() / 2;
+    let first = send(
+        app,
+        patch(&location, 0)
+            .body(Body::from(input[..half].to_vec()))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(first.status(), StatusCode::NO_CONTENT);
+    assert_eq!(header(&first, "upload-offset"), half.to_string());
+
+    // A client that lost the reply asks where to resume.
+    let status = send(
+        app,
+        Request::head(&location)
+            .header("Tus-Resumable", "1.0.0")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status.status(), StatusCode::OK);
+    assert_eq!(header(&status, "upload-offset"), half.to_string());
+    assert_eq!(header(&status, "upload-length"), input.len().to_string());
+
+    let last = send(
+        app,
+        patch(&location, half)
+            .header("X-Compression-Quality", "60")
+            .body(Body::from(input[half..].to_vec()))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(last.status(), StatusCode::NO_CONTENT);
+    assert_eq!(header(&last, "upload-offset"), input.len().to_string());
+    location
+}
+
+async fn assert_compressed(app: &Router, location: &str) {
+    let result = send(app, Request::get(location).body(Body::empty()).unwrap()).await;
+    assert_eq!(result.status(), StatusCode::OK);
+    assert_eq!(header(&result, "content-type"), "image/jpeg");
+    let body = to_bytes(result.into_body(), usize::MAX).await.unwrap();
+    assert!(image::load_from_memory(&body).is_ok());
+}
+
+#[tokio::test]
+async fn chunked_uploads_are_compressed_once_complete() {
+    let app = app(disk_config("chunked"));
+    let location = upload_in_two_chunks(&app, &fixture("landscape.jpg")).await;
+    assert_compressed(&app, &location).await;
+}
+
+#[tokio::test]
+async fn the_server_describes_its_tus_support() {
+    let app = app(disk_config("options"));
+    let response = send(
+        &app,
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/uploads")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(header(&response, "tus-version"), "1.0.0");
+    assert!(header(&response, "tus-extension").contains("creation"));
+    assert_eq!(header(&response, "tus-max-size"), "10485760");
+}
+
+#[tokio::test]
+async fn offsets_and_lengths_are_enforced() {
+    let app = app(disk_config("offsets"));
+    let input = fixture("landscape.jpg");
+    let created = send(&app, create(input.len()).body(Body::empty()).unwrap()).await;
+    let location = header(&created, "location").to_string();
+
+    let wrong_offset = send(
+        &app,
+        patch(&location, 5)
+            .body(Body::from(input[..10].to_vec()))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(wrong_offset.status(), StatusCode::CONFLICT);
+
+    let mut too_long = input.clone();
+    too_long.push(0);
+    let overflow = send(
+        &app,
+        patch(&location, 0).body(Body::from(too_long)).unwrap(),
+    )
+    .await;
+    assert_eq!(overflow.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let wrong_type = send(
+        &app,
+        Request::patch(&location)
+            .header("Tus-Resumable", "1.0.0")
+            .header("Content-Type", "image/jpeg")
+            .header("Upload-Offset", "0")
+            .body(Body::from(input.clone()))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(wrong_type.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let too_large = send(&app, create(11 * 1024 * 1024).body(Body::empty()).unwrap()).await;
+    assert_eq!(too_large.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let unversioned = send(
+        &app,
+        Request::post("/uploads")
+            .header("Upload-Length", "10")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(unversioned.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(header(&unversioned, "tus-version"), "1.0.0");
+}
+
+#[tokio::test]
+async fn uploads_can_be_created_with_data_and_terminated() {
+    let app = app(disk_config("terminate"));
+    let input = fixture("landscape.jpg");
+    let created = send(
+        &app,
+        create(input.len())
+            .header("Content-Type", "application/offset+octet-stream")
+            .body(Body::from(input.clone()))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(created.status(), StatusCode::CREATED);
+    assert_eq!(header(&created, "upload-offset"), input.len().to_string());
+    let location = header(&created, "location").to_string();
+    assert_compressed(&app, &location).await;
+
+    let deleted = send(
+        &app,
+        Request::delete(&location)
+            .header("Tus-Resumable", "1.0.0")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(deleted.status(), StatusCode::NO_CONTENT);
+    let gone = send(&app, Request::get(&location).body(Body::empty()).unwrap()).await;
+    assert_eq!(gone.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn uploads_are_private_to_their_api_key() {
+    let app = app(disk_config("tenants"));
+    let created = send(
+        &app,
+        create(100)
+            .header("X-Api-Key", "tenant-a")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    let location = header(&created, "location").to_string();
+
+    let other = send(
+        &app,
+        Request::head(&location)
+            .header("Tus-Resumable", "1.0.0")
+            .header("X-Api-Key", "tenant-b")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(other.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn failed_compressions_can_be_retried_at_the_final_offset() {
+    let app = app(disk_config("retry"));
+    let input = fixture("landscape.jpg");
+    let created = send(&app, create(input.len()).body(Body::empty()).unwrap()).await;
+    let location = header(&created, "location").to_string();
+
+    let rejected = send(
+        &app,
+        patch(&location, 0)
+            .header("Idempotency-Key", "")
+            .body(Body::from(input.clone()))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(rejected.status(), StatusCode::BAD_REQUEST);
+    let pending = send(&app, Request::get(&location).body(Body::empty()).unwrap()).await;
+    assert_eq!(pending.status(), StatusCode::CONFLICT);
+
+    let retried = send(
+        &app,
+        patch(&location, input.len()).body(Body::empty()).unwrap(),
+    )
+    .await;
+    assert_eq!(retried.status(), StatusCode::NO_CONTENT);
+    assert_compressed(&app, &location).await;
+}
+
+/// A fake Redis knowing the commands the upload store sends. `EVAL` runs
+/// the store's append script.
+async fn fake_redis() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let data = std::sync::Arc::new(tokio::sync::Mutex::new(HashMap::<Vec<u8>, Vec<u8>>::new()));
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(serve_redis(stream, data.clone()));
+        }
+    });
+    addr
+}
+
+async fn serve_redis(
+    stream: TcpStream,
+    data: std::sync::Arc<tokio::sync::Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await.unwrap() == 0 {
+            return;
+        }
+        let count: usize = line.trim()[1..].parse().unwrap();
+        let mut args = Vec::new();
+        for _ in 0..count {
+            let mut len = String::new();
+            reader.read_line(&mut len).await.unwrap();
+            let len: usize = len.trim()[1..].parse().unwrap();
+            let mut arg = vec![0; len + 2];
+            reader.read_exact(&mut arg).await.unwrap();
+            arg.truncate(len);
+            args.push(arg);
+        }
+
+        let mut data = data.lock().await;
+        let bulk = |value: Option<&Vec<u8>>| match value {
+            Some(value) => [
+                format!("${}\r\n", value.len()).into_bytes(),
+                value.clone(),
+                b"\r\n".to_vec(),
+            ]
+            .concat(),
+            None => b"$-1\r\n".to_vec(),
+        };
+        let reply = match args[0].as_slice() {
+            b"SET" => {
+                data.insert(args[1].clone(), args[2].clone());
+                b"+OK\r\n".to_vec()
+            }
+            b"PING" => b"+PONG\r\n".to_vec(),
+            b"GET" => bulk(data.get(&args[1])),
+            b"STRLEN" => format!(":{}\r\n", data.get(&args[1]).map_or(0, Vec::len)).into_bytes(),
+            b"DEL" => {
+                let removed = args[1..]
+                    .iter()
+                    .filter(|k| data.remove(*k).is_some())
+                    .count();
+                format!(":{}\r\n", removed).into_bytes()
+            }
+            b"EVAL" => {
+                let value = data.entry(args[3].clone()).or_default();
+                let offset: usize = String::from_utf8_lossy(&args[4]).parse().unwrap();
+                if value.len() == offset {
+                    value.extend_from_slice(&args[5]);
+                    format!(":{}\r\n", value.len()).into_bytes()
+                } else {
+                    format!(":{}\r\n", -1 - value.len() as i64).into_bytes()
+                }
+            }
+            other => format!(
+                "-ERR unknown command {}\r\n",
+                String::from_utf8_lossy(other)
+            )
+            .into_bytes(),
+        };
+        reader.get_mut().write_all(&reply).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn uploads_can_be_kept_in_redis() {
+    let uploads = TusConfig {
+        enabled: true,
+        store: UploadStore::Redis,
+        redis_url: format!("redis://{}", fake_redis().await),
+        ..TusConfig::default()
+    };
+    let app = app(uploads);
+    let location = upload_in_two_chunks(&app, &fixture("landscape.jpg")).await;
+    assert_compressed(&app, &location).await;
+}
+
+#[tokio::test]
+async fn selftest_pings_the_redis_store() {
+    let selftest = |redis_url: String| async move {
+        let mut config = Config {
+            uploads: TusConfig {
+                enabled: true,
+                store: UploadStore::Redis,
+                redis_url,
+                ..TusConfig::default()
+            },
+            ..Config::default()
+        };
+        config.selftest.token = Some("s3cret".to_string());
+        let app = common::app(config);
+        let request = Request::get("/selftest")
+            .header("Authorization", "Bearer s3cret")
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&app, request).await;
+        let status = response.status();
+        let report = json_body(response).await;
+        let check = report["checks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["name"] == "uploads:redis")
+            .cloned()
+            .expect("a Redis check");
+        (status, check)
+    };
+
+    let (status, check) = selftest(format!("redis://{}", fake_redis().await)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(check["passed"], true);
+
+    // Nothing listens on a port that was just released.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    let (status, check) = selftest(format!("redis://{}", addr)).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(check["passed"], false);
+    assert!(
+        check["error"].as_str().unwrap().contains("Redis"),
+        "{}",
+        check
+    );
+}
+
+#[tokio::test]
+async fn results_can_be_downloaded_in_ranges() {
+    let app = app(disk_config("ranges"));
+    let location = upload_in_two_chunks(&app, &fixture("landscape.jpg")).await;
+    let full = send(&app, Request::get(&location).body(Body::empty()).unwrap()).await;
+    assert_eq!(header(&full, "accept-ranges"), "bytes");
+    let etag = header(&full, "etag").to_string();
+    let full = to_bytes(full.into_body(), usize::MAX).await.unwrap();
+
+    let get_range = |range: &str, if_range: Option<&str>| {
+        let mut request = Request::get(&location).header("Range", range);
+        if let Some(if_range) = if_range {
+            request = request.header("If-Range", if_range);
+        }
+        send(&app, request.body(Body::empty()).unwrap())
+    };
+
+    let resumed = get_range("bytes=100-", Some(&etag)).await;
+    assert_eq!(resumed.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        header(&resumed, "content-range"),
+        format!("bytes 100-{}/{}", full.len() - 1, full.len())
+    );
+    assert_eq!(header(&resumed, "content-type"), "image/jpeg");
+    let body = to_bytes(resumed.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(body, full.slice(100..));
+
+    let tail = get_range("bytes=-10", None).await;
+    let body = to_bytes(tail.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(body, full.slice(full.len() - 10..));
+
+    // The result changed since the client started: it gets all of it.
+    let stale = get_range("bytes=100-", Some("\"stale\"")).await;
+    assert_eq!(stale.status(), StatusCode::OK);
+
+    let beyond = get_range(&format!("bytes={}-", full.len()), None).await;
+    assert_eq!(beyond.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(
+        header(&beyond, "content-range"),
+        format!("bytes */{}", full.len())
+    );
+}
+
+#[tokio::test]
+async fn the_oldest_uploads_make_room_for_new_ones() {
+    let app = app(TusConfig {
+        max_storage_bytes: 100,
+        ..disk_config("cap")
+    });
+
+    let first = send(&app, create(60).body(Body::empty()).unwrap()).await;
+    assert_eq!(first.status(), StatusCode::CREATED);
+    let first = header(&first, "location").to_string();
+
+    let second = send(&app, create(60).body(Body::empty()).unwrap()).await;
+    assert_eq!(second.status(), StatusCode::CREATED);
+    let second = header(&second, "location").to_string();
+
+    let head = |location: &str| {
+        Request::head(location)
+            .header("Tus-Resumable", "1.0.0")
+            .body(Body::empty())
+            .unwrap()
+    };
+    assert_eq!(
+        send(&app, head(&first)).await.status(),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(send(&app, head(&second)).await.status(), StatusCode::OK);
+
+    let too_large = send(&app, create(101).body(Body::empty()).unwrap()).await;
+    assert_eq!(too_large.status(), StatusCode::INSUFFICIENT_STORAGE);
+    let body = to_bytes(too_large.into_body(), usize::MAX).await.unwrap();
+    assert!(String::from_utf8_lossy(&body).contains("upload_storage_full"));
+    assert_eq!(send(&app, head(&second)).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn the_reaper_removes_expired_uploads() {
+    let uploads = TusConfig {
+        expire_secs: 1,
+        ..disk_config("reap")
+    };
+    let config = Config {
+        uploads,
+        ..Config::default()
+    };
+    let state = common::state(config);
+    let app = server::router(state.clone());
+
+    let created = send(&app, create(10).body(Body::empty()).unwrap()).await;
+    assert_eq!(created.status(), StatusCode::CREATED);
+    assert_eq!(state.uploads.reap(0).await.unwrap().expired, 0);
+
+    tokio::time::sleep(std::time::Duration::from_millis(2100)).await;
+    let reaped = state.uploads.reap(0).await.unwrap();
+    assert_eq!((reaped.expired, reaped.evicted, reaped.bytes), (1, 0, 0));
+}
@@ -0,0 +1,232 @@
+// image-compressor-rust-service/tests/watch.rs
+
+//! Watch mode and presets.
+
+mod common;
+
+use common::fixture;
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::preset::Preset;
+use image_compressor_rust_service::watch::{Event, Originals, WatchOptions, Watcher};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Fresh `in`, `out` and `archive` directories for one test.
+fn dirs(name: &str) -> (PathBuf, PathBuf, PathBuf) {
+    let root = std::env::temp_dir().join(format!("watch-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&root);
+    let input = root.join("in");
+    fs::create_dir_all(input.join("nested")).unwrap();
+    (input, root.join("out"), root.join("archive"))
+}
+
+fn options(input: &Path, output: &Path) -> WatchOptions {
+    WatchOptions {
+        input: input.to_path_buf(),
+        output: output.to_path_buf(),
+        preset: None,
+        template: None,
+        originals: Originals::Keep,
+        interval: Duration::from_millis(10),
+        settle: Duration::ZERO,
+        retries: 2,
+    }
+}
+
+#[test]
+fn arguments_are_parsed() {
+    let args: Vec<String> = "/in --out /out --preset web --archive /done --settle-ms 500"
+        .split(' ')
+        .map(String::from)
+        .collect();
+    let options = WatchOptions::parse(&args).unwrap();
+    assert_eq!(options.input, PathBuf::from("/in"));
+    assert_eq!(options.output, PathBuf::from("/out"));
+    assert_eq!(options.preset.as_deref(), Some("web"));
+    assert_eq!(
+        options.originals,
+        Originals::Archive(PathBuf::from("/done"))
+    );
+    assert_eq!(options.settle, Duration::from_millis(500));
+
+    assert!(WatchOptions::parse(&["/in".to_string()]).is_err());
+    assert!(
+        WatchOptions::parse(&["/in".into(), "--out".into(), "/o".into(), "--bogus".into()])
+            .is_err()
+    );
+}
+
+#[test]
+fn settled_files_are_compressed_once_with_the_preset() {
+    let (input, output, _) = dirs("keep");
+    fs::write(
+        input.join("nested/photo.png"),
+        fixture("portrait-alpha.png"),
+    )
+    .unwrap();
+    let mut watcher = Watcher::new(
+        WatchOptions {
+            preset: Some("thumbnail".to_string()),
+            ..options(&input, &output)
+        },
+        Config::default(),
+    )
+    .unwrap();
+
+    let events = watcher.scan(Instant::now());
+    let expected = output.join("nested/photo.jpg");
+    assert_eq!(
+        events,
+        vec![Event::Compressed {
+            input: input.join("nested/photo.png"),
+            output: expected.clone(),
+        }]
+    );
+    let image = image::load_from_memory(&fs::read(&expected).unwrap()).unwrap();
+    // The thumbnail crops the small fixture square instead of enlarging it.
+    assert_eq!((image.width(), image.height()), (72, 72));
+    assert!(input.join("nested/photo.png").exists());
+
+    assert!(watcher.scan(Instant::now()).is_empty());
+}
+
+#[test]
+fn templates_lay_outputs_out_by_capture_date() {
+    let (input, output, _) = dirs("template");
+    fs::write(input.join("nested/photo.jpg"), fixture("exif.jpg")).unwrap();
+    fs::write(input.join("plain.jpg"), fixture("landscape.jpg")).unwrap();
+    let mut watcher = Watcher::new(
+        WatchOptions {
+            template: Some("{yyyy}/{mm}/{camera}/{name}.{ext}".to_string()),
+            ..options(&input, &output)
+        },
+        Config::default(),
+    )
+    .unwrap();
+
+    watcher.scan(Instant::now());
+    assert!(output.join("2024/05/Model 1/photo.jpg").is_file());
+    assert!(output.join("unknown/unknown/unknown/plain.jpg").is_file());
+
+    let bad = ["/in", "--out", "/out", "--template", "{yyyy}/"].map(String::from);
+    assert!(WatchOptions::parse(&bad).is_err());
+}
+
+#[test]
+fn files_still_changing_are_left_alone() {
+    let (input, output, _) = dirs("settle");
+    let mut watcher = Watcher::new(
+        WatchOptions {
+            settle: Duration::from_secs(5),
+            ..options(&input, &output)
+        },
+        Config::default(),
+    )
+    .unwrap();
+    fs::write(input.join("a.jpg"), fixture("landscape.jpg")).unwrap();
+
+    let start = Instant::now();
+    assert!(watcher.scan(start).is_empty());
+    assert!(watcher.scan(start + Duration::from_secs(1)).is_empty());
+    let events = watcher.scan(start + Duration::from_secs(6));
+    assert!(matches!(events.as_slice(), [Event::Compressed { .. }]));
+}
+
+#[test]
+fn partial_writes_are_retried() {
+    let (input, output, archive) = dirs("partial");
+    let mut watcher = Watcher::new(
+        WatchOptions {
+            originals: Originals::Archive(archive.clone()),
+            ..options(&input, &output)
+        },
+        Config::default(),
+    )
+    .unwrap();
+    let full = fixture("landscape.jpg");
+    let path = input.join("a.jpg");
+    fs::write(&path, &full[..full.len() / 3]).unwrap();
+
+    let start = Instant::now();
+    assert_eq!(
+        watcher.scan(start),
+        vec![Event::Retrying {
+            input: path.clone(),
+            attempt: 1
+        }]
+    );
+    // Not yet due again.
+    assert!(watcher.scan(start).is_empty());
+
+    fs::write(&path, &full).unwrap();
+    let events = watcher.scan(start + Duration::from_millis(1));
+    assert!(matches!(events.as_slice(), [Event::Compressed { .. }]));
+    assert!(!path.exists());
+    assert_eq!(fs::read(archive.join("a.jpg")).unwrap(), full);
+}
+
+#[test]
+fn broken_and_foreign_files_are_given_up_on() {
+    let (input, output, _) = dirs("broken");
+    let mut watcher = Watcher::new(
+        WatchOptions {
+            originals: Originals::Delete,
+            retries: 1,
+            ..options(&input, &output)
+        },
+        Config::default(),
+    )
+    .unwrap();
+    fs::write(input.join("notes.txt"), "not an image").unwrap();
+    let broken = fixture("landscape.jpg")[..200].to_vec();
+    fs::write(input.join("broken.jpg"), broken).unwrap();
+
+    let start = Instant::now();
+    let mut failed = Vec::new();
+    for step in 0..5 {
+        for event in watcher.scan(start + Duration::from_secs(step)) {
+            if let Event::Failed { input } = event {
+                failed.push(input);
+            }
+        }
+    }
+    failed.sort();
+    assert_eq!(
+        failed,
+        vec![input.join("broken.jpg"), input.join("notes.txt")]
+    );
+    assert!(input.join("notes.txt").exists());
+}
+
+#[test]
+fn outputs_inside_the_watched_directory_are_refused() {
+    let (input, _, _) = dirs("nested-out");
+    let result = Watcher::new(options(&input, &input.join("out")), Config::default());
+    assert!(result.is_err());
+}
+
+#[test]
+fn configured_presets_override_built_in_ones() {
+    let mut config = Config::default();
+    assert_eq!(config.preset("web"), Preset::builtin("web"));
+    assert!(config.preset("print").is_none());
+
+    config.presets.insert(
+        "web".to_string(),
+        Preset {
+            quality: Some(60),
+            ..Preset::default()
+        },
+    );
+    assert_eq!(config.preset("web").unwrap().options().quality, 60);
+
+    config.presets.insert(
+        "bad".to_string(),
+        Preset {
+            quality: Some(0),
+            ..Preset::default()
+        },
+    );
+    assert!(config.validate().is_err());
+}
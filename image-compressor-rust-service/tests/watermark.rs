@@ -0,0 +1,152 @@
+// image-compressor-rust-service/tests/watermark.rs
+
+//! Invisible watermarks from `X-Watermark`, read by `/watermark/detect`.
+
+mod common;
+
+use axum::body::to_bytes;
+use axum::http::StatusCode;
+use common::{fixture, post, send};
+use image::DynamicImage;
+use image_compressor_rust_service::config::Config;
+use image_compressor_rust_service::encode::encode_jpeg;
+use image_compressor_rust_service::options::{CompressionOptions, Dither, Palette};
+use image_compressor_rust_service::watermark::WatermarkConfig;
+use image_compressor_rust_service::{compress_image_with, decode_image};
+
+fn marker(secret: &str) -> WatermarkConfig {
+    WatermarkConfig {
+        secret: Some(secret.to_string()),
+        ..WatermarkConfig::default()
+    }
+}
+
+fn landscape() -> DynamicImage {
+    decode_image(&fixture("landscape.jpg")).unwrap()
+}
+
+/// Peak signal-to-noise ratio of `b` against `a`, in decibels.
+fn psnr(a: &DynamicImage, b: &DynamicImage) -> f64 {
+    let (a, b) = (a.to_rgb8(), b.to_rgb8());
+    let error = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(&x, &y)| (f64::from(x) - f64::from(y)).powi(2))
+        .sum::<f64>()
+        / a.as_raw().len() as f64;
+    10.0 * (255.0 * 255.0 / error).log10()
+}
+
+#[test]
+fn marks_survive_jpeg_and_stay_invisible() {
+    let config = marker("review-copies");
+    let original = landscape();
+    let marked = config.embed(original.clone(), 0xC0FFEE).unwrap();
+    assert!(
+        psnr(&original, &marked) > 38.0,
+        "{}",
+        psnr(&original, &marked)
+    );
+
+    for quality in [90, 75] {
+        let jpeg = encode_jpeg(&marked, quality).unwrap();
+        let detection = config.detect(&decode_image(&jpeg).unwrap()).unwrap();
+        assert_eq!(detection.payload, Some(0xC0FFEE), "quality {}", quality);
+        assert!(detection.confidence > 0.75, "{:?}", detection);
+    }
+}
+
+#[test]
+fn unmarked_images_and_other_secrets_find_nothing() {
+    let config = marker("review-copies");
+    let detection = config.detect(&landscape()).unwrap();
+    assert_eq!(detection.payload, None);
+    assert!(detection.confidence < 0.75, "{:?}", detection);
+
+    let marked = config.embed(landscape(), 42).unwrap();
+    assert_eq!(marker("another").detect(&marked).unwrap().payload, None);
+
+    // Too few blocks to hold a mark.
+    let tiny = DynamicImage::new_rgb8(40, 40);
+    assert!(config.embed(tiny.clone(), 1).is_err());
+    assert_eq!(config.detect(&tiny).unwrap().payload, None);
+    assert!(WatermarkConfig::default().embed(landscape(), 1).is_err());
+}
+
+#[test]
+fn compression_embeds_requested_marks() {
+    let config = Config {
+        watermark: marker("review-copies"),
+        ..Config::default()
+    };
+    let options = CompressionOptions {
+        watermark: Some(7),
+        ..CompressionOptions::default()
+    };
+    let compressed = compress_image_with(&fixture("landscape.jpg"), &options, &config).unwrap();
+    let output = decode_image(&compressed.data).unwrap();
+    assert_eq!(config.watermark.detect(&output).unwrap().payload, Some(7));
+
+    let palette = CompressionOptions {
+        palette: Some(Palette::new(64, Dither::None)),
+        ..options
+    };
+    assert!(compress_image_with(&fixture("landscape.jpg"), &palette, &config).is_err());
+
+    let invalid: Result<Config, _> = toml::from_str("[watermark]\nsecret = \"\"");
+    assert!(invalid.unwrap().validate().is_err());
+    let invalid: Result<Config, _> = toml::from_str("[watermark]\nstrength = 0.0");
+    assert!(invalid.unwrap().validate().is_err());
+}
+
+fn router(secret: Option<&str>) -> axum::Router {
+    let mut config = Config::default();
+    config.watermark.secret = secret.map(String::from);
+    common::app(config)
+}
+
+#[tokio::test]
+async fn marked_outputs_are_detected_over_http() {
+    let router = router(Some("review-copies"));
+    let request = post(
+        "/compress",
+        &[("X-Watermark", "123456")],
+        fixture("landscape.jpg"),
+    );
+    let response = send(&router, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let marked = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+    let detect = |body: Vec<u8>| {
+        let router = router.clone();
+        async move {
+            let request = post("/watermark/detect", &[], body);
+            let response = send(&router, request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    };
+    let found = detect(marked.to_vec()).await;
+    assert_eq!(found["found"], true);
+    assert_eq!(found["payload"], 123456);
+    let missing = detect(fixture("landscape.jpg")).await;
+    assert_eq!(missing["found"], false);
+    assert!(missing["payload"].is_null());
+}
+
+#[tokio::test]
+async fn watermarks_need_a_secret() {
+    let request = post("/watermark/detect", &[], fixture("landscape.jpg"));
+    let response = send(&router(None), request).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let request = post(
+        "/compress",
+        &[("X-Watermark", "1")],
+        fixture("landscape.jpg"),
+    );
+    let response = send(&router(None), request).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}